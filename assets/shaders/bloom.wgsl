@@ -0,0 +1,78 @@
+#import bevy_core_pipeline::fullscreen_vertex_shader::FullscreenVertexOutput
+
+@group(0) @binding(0) var input_texture: texture_2d<f32>;
+@group(0) @binding(1) var input_sampler: sampler;
+struct BloomUniforms {
+    // x: the brightness past which the scene blooms, y: how strongly the bloom is added back
+    params: vec4<f32>,
+    // xy: the fraction of the scene that the world was drawn to, from r_scale
+    scale: vec4<f32>,
+}
+@group(0) @binding(2) var<uniform> bloom_uniforms: BloomUniforms;
+// only bound for the composite
+@group(0) @binding(3) var bloom_texture: texture_2d<f32>;
+
+// a 9-tap gaussian blur, sampled between texels so that the filtering does half the work
+const BLUR_WEIGHTS = vec3<f32>(0.2270270270, 0.3162162162, 0.0702702703);
+const BLUR_OFFSETS = vec2<f32>(1.3846153846, 3.2307692308);
+
+// the part of `color` past the threshold, keeping its hue
+fn threshold(color: vec3<f32>) -> vec3<f32> {
+    let brightness = max(color.r, max(color.g, color.b));
+    let excess = max(brightness - bloom_uniforms.params.x, 0.0);
+    return color * (excess / max(brightness, 1e-4));
+}
+
+// sample the scene `offset` texels from `uv`. the bloom textures only cover the part of the scene
+// the world was drawn to, so `uv` is scaled to it, staying half a texel inside so that filtering
+// doesn't pick up the unused part
+fn sample_scene(uv: vec2<f32>, offset: vec2<f32>) -> vec3<f32> {
+    let size = vec2<f32>(textureDimensions(input_texture));
+    let scale = bloom_uniforms.scale.xy;
+    let scene_uv = clamp(uv * scale + offset / size, vec2<f32>(0.0), scale - 0.5 / size);
+    return textureSample(input_texture, input_sampler, scene_uv).rgb;
+}
+
+// downsample the scene, averaging four filtered samples so that small bright spots don't flicker
+// as they move
+@fragment
+fn prefilter(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
+    let color = sample_scene(in.uv, vec2<f32>(-1.0, -1.0))
+        + sample_scene(in.uv, vec2<f32>(1.0, -1.0))
+        + sample_scene(in.uv, vec2<f32>(-1.0, 1.0))
+        + sample_scene(in.uv, vec2<f32>(1.0, 1.0));
+
+    return vec4<f32>(threshold(color / 4.0), 1.0);
+}
+
+fn blur(uv: vec2<f32>, direction: vec2<f32>) -> vec4<f32> {
+    let step = direction / vec2<f32>(textureDimensions(input_texture));
+    var color = textureSample(input_texture, input_sampler, uv).rgb * BLUR_WEIGHTS.x;
+    color += textureSample(input_texture, input_sampler, uv + step * BLUR_OFFSETS.x).rgb * BLUR_WEIGHTS.y;
+    color += textureSample(input_texture, input_sampler, uv - step * BLUR_OFFSETS.x).rgb * BLUR_WEIGHTS.y;
+    color += textureSample(input_texture, input_sampler, uv + step * BLUR_OFFSETS.y).rgb * BLUR_WEIGHTS.z;
+    color += textureSample(input_texture, input_sampler, uv - step * BLUR_OFFSETS.y).rgb * BLUR_WEIGHTS.z;
+
+    return vec4<f32>(color, 1.0);
+}
+
+@fragment
+fn blur_horizontal(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
+    return blur(in.uv, vec2<f32>(1.0, 0.0));
+}
+
+@fragment
+fn blur_vertical(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
+    return blur(in.uv, vec2<f32>(0.0, 1.0));
+}
+
+// add the blurred bloom to the scene. outside of the world, the scene is left as it is
+@fragment
+fn composite(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
+    let scene = textureLoad(input_texture, vec2<i32>(in.position.xy), 0);
+    let scale = bloom_uniforms.scale.xy;
+    let bloom = textureSample(bloom_texture, input_sampler, in.uv / scale).rgb;
+    let in_world = all(in.uv <= scale);
+
+    return vec4<f32>(scene.rgb + select(vec3<f32>(0.0), bloom, in_world) * bloom_uniforms.params.y, scene.a);
+}
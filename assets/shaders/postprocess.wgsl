@@ -6,15 +6,130 @@
 @group(0) @binding(1) var texture_sampler: sampler;
 struct PostProcessUniforms {
     color_shift: array<vec4<f32>, 4>,
+    inv_projection: mat4x4<f32>,
+    // x: the distance in focus, y: the aperture, or 0 for no depth of field
+    depth_of_field: vec4<f32>,
+    // xy: the fraction of the inputs that the world was drawn to, from r_scale
+    scale: vec4<f32>,
+    // x: gamma, y: contrast, z: vignette strength, w: 1 to dither
+    effects: vec4<f32>,
+    // x: the client time in seconds, y: the strength of the underwater warp, or 0 for none
+    warp: vec4<f32>,
 }
 @group(0) @binding(2) var<uniform> postprocess_uniforms: PostProcessUniforms;
+@group(0) @binding(3) var depth_texture: texture_2d<f32>;
 
 const COLOR_SPACE: u32 = #{COLORSPACE};
 const BLEND_MODE: u32 = #{BLENDMODE};
 
+const DOF_TAPS: i32 = 24;
+// the largest blur radius, as a fraction of the screen height
+const DOF_MAX_RADIUS: f32 = 0.015;
+const GOLDEN_ANGLE: f32 = 2.39996;
+
+// how far the underwater warp moves the image at a strength of 1, as a fraction of the screen
+const WARP_AMPLITUDE: f32 = 0.004;
+// the number of waves across the screen
+const WARP_CYCLES: f32 = 3.0;
+
+// where `uv` on the screen is in the inputs, which only have the world in their top-left corner
+// when it's rendered at a lower resolution
+fn world_uv(uv: vec2<f32>) -> vec2<f32> {
+    let size = vec2<f32>(textureDimensions(screen_texture));
+    let scale = postprocess_uniforms.scale.xy;
+    // stay half a texel inside, so that filtering doesn't pick up the unused part
+    return clamp(uv * scale, vec2<f32>(0.0), scale - 0.5 / size);
+}
+
+fn view_distance(uv: vec2<f32>) -> f32 {
+    let size = vec2<f32>(textureDimensions(depth_texture));
+    let texel = clamp(vec2<i32>(world_uv(uv) * size), vec2<i32>(0), vec2<i32>(size) - 1);
+    let depth = textureLoad(depth_texture, texel, 0).x;
+    let ndc = vec4<f32>(uv.x * 2.0 - 1.0, (1.0 - uv.y) * 2.0 - 1.0, depth, 1.0);
+    let view = postprocess_uniforms.inv_projection * ndc;
+
+    // nothing was drawn here, so treat it as far away
+    if abs(view.w) < 1e-6 {
+        return 65536.0;
+    }
+    return min(length(view.xyz / view.w), 65536.0);
+}
+
+// the radius of the circle of confusion at `distance`
+fn blur_radius(distance: f32) -> f32 {
+    let focus = postprocess_uniforms.depth_of_field.x;
+    let aperture = postprocess_uniforms.depth_of_field.y;
+    return min(aperture * abs(distance - focus) / max(distance, 1.0), 1.0) * DOF_MAX_RADIUS;
+}
+
+// gather samples in a spiral around `uv`, skipping sharper samples so that things in focus
+// don't bleed into the blur around them
+fn depth_of_field(uv: vec2<f32>) -> vec4<f32> {
+    let size = vec2<f32>(textureDimensions(screen_texture));
+    let aspect = vec2<f32>(size.y / size.x, 1.0);
+    let radius = blur_radius(view_distance(uv));
+
+    var total = textureSample(screen_texture, texture_sampler, world_uv(uv));
+    var weight = 1.0;
+    for (var i = 1; i < DOF_TAPS; i++) {
+        let r = sqrt(f32(i) / f32(DOF_TAPS)) * radius;
+        let theta = f32(i) * GOLDEN_ANGLE;
+        let sample_uv = uv + vec2<f32>(cos(theta), sin(theta)) * r * aspect;
+
+        let sample_radius = blur_radius(view_distance(sample_uv));
+        let w = clamp(sample_radius / max(r, 1e-5), 0.0, 1.0);
+        total += textureSample(screen_texture, texture_sampler, world_uv(sample_uv)) * w;
+        weight += w;
+    }
+
+    return total / weight;
+}
+
+// push the color away from (or towards) grey, then apply gamma
+fn contrast_gamma(color: vec3<f32>) -> vec3<f32> {
+    let contrasted = max((color - 0.5) * postprocess_uniforms.effects.y + 0.5, vec3<f32>(0.0));
+    return pow(contrasted, vec3<f32>(1.0 / postprocess_uniforms.effects.x));
+}
+
+fn vignette(uv: vec2<f32>) -> f32 {
+    let from_center = (uv - 0.5) * 2.0;
+    return 1.0 - postprocess_uniforms.effects.z * smoothstep(0.5, 1.5, dot(from_center, from_center));
+}
+
+// triangular noise of about one 8-bit step, from the screen position
+fn dither(position: vec2<f32>) -> vec3<f32> {
+    let noise = fract(sin(dot(position, vec2<f32>(12.9898, 78.233))) * 43758.5453);
+    let noise2 = fract(sin(dot(position, vec2<f32>(39.3468, 11.135))) * 24634.6345);
+    return vec3<f32>(noise + noise2 - 1.0) / 255.0;
+}
+
+// ripple the screen with sine waves running across each axis, shrinking it a little first so
+// that the edges don't show
+fn underwater_warp(uv: vec2<f32>) -> vec2<f32> {
+    let strength = postprocess_uniforms.warp.y;
+    if strength <= 0.0 {
+        return uv;
+    }
+
+    let time = postprocess_uniforms.warp.x;
+    let amplitude = WARP_AMPLITUDE * strength;
+    let wave = vec2<f32>(
+        sin((uv.y * WARP_CYCLES + time * 0.5) * 6.28318),
+        sin((uv.x * WARP_CYCLES + time * 0.5) * 6.28318),
+    );
+    return (uv - 0.5) * (1.0 - 2.0 * amplitude) + 0.5 + wave * amplitude;
+}
+
 @fragment
 fn main(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
-    var in_color: vec4<f32> = textureSample(screen_texture, texture_sampler, in.uv);
+    let uv = underwater_warp(in.uv);
+
+    var in_color: vec4<f32>;
+    if postprocess_uniforms.depth_of_field.y > 0.0 {
+        in_color = depth_of_field(uv);
+    } else {
+        in_color = textureSample(screen_texture, texture_sampler, world_uv(uv));
+    }
 
     var color_shifted: vec3<f32> = toColorSpace(COLOR_SPACE, in_color.rgb);
     for (var i = 0; i < 4; i++) {
@@ -29,5 +144,11 @@ fn main(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
         );
     }
 
-    return vec4<f32>(fromColorSpace(COLOR_SPACE, color_shifted), in_color.a);
+    var out_color = contrast_gamma(fromColorSpace(COLOR_SPACE, color_shifted));
+    out_color *= vignette(in.uv);
+    if postprocess_uniforms.effects.w > 0.0 {
+        out_color = max(out_color + dither(in.position.xy), vec3<f32>(0.0));
+    }
+
+    return vec4<f32>(out_color, in_color.a);
 }
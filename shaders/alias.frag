@@ -2,6 +2,7 @@
 
 layout(location = 0) in vec3 f_normal;
 layout(location = 1) in vec2 f_diffuse;
+layout(location = 2) flat in float f_alpha;
 
 // set 1: per-entity
 layout(set = 1, binding = 1) uniform sampler u_diffuse_sampler;
@@ -12,7 +13,20 @@ layout(set = 2, binding = 0) uniform texture2D u_diffuse_texture;
 layout(location = 0) out vec4 diffuse_attachment;
 layout(location = 1) out vec4 normal_attachment;
 
+// 4x4 ordered dither thresholds, for drawing translucent models without blending
+const float DITHER[16] = float[](
+   0.0,  8.0,  2.0, 10.0,
+  12.0,  4.0, 14.0,  6.0,
+   3.0, 11.0,  1.0,  9.0,
+  15.0,  7.0, 13.0,  5.0
+);
+
 void main() {
+  ivec2 dither_pos = ivec2(gl_FragCoord.xy) % 4;
+  if (f_alpha < (DITHER[dither_pos.y * 4 + dither_pos.x] + 0.5) / 16.0) {
+    discard;
+  }
+
   // TODO: get ambient light from uniform
   diffuse_attachment = vec4(texture(
     sampler2D(u_diffuse_texture, u_diffuse_sampler),
@@ -1,17 +1,26 @@
 #version 450
 
+// current frame
 layout(location = 0) in vec3 a_position1;
-// layout(location = 1) in vec3 a_position2;
+// previous frame
+layout(location = 1) in vec3 a_position2;
 layout(location = 2) in vec3 a_normal;
 layout(location = 3) in vec2 a_diffuse;
 
-layout(push_constant) uniform PushConstants {
+// per instance
+layout(location = 4) in mat4 a_model;
+layout(location = 8) in float a_alpha;
+layout(location = 9) in float a_blend;
+
+layout(set = 1, binding = 0) uniform EntityUniforms {
   mat4 transform;
+  mat4 model;
   mat4 model_view;
-} push_constants;
+} entity_uniforms;
 
 layout(location = 0) out vec3 f_normal;
 layout(location = 1) out vec2 f_diffuse;
+layout(location = 2) flat out float f_alpha;
 
 float det(mat2 matrix) {
     return matrix[0].x * matrix[1].y - matrix[0].y * matrix[1].x;
@@ -49,7 +58,10 @@ vec3 convert(vec3 from) {
 }
 
 void main() {
-  f_normal = transpose(inv(mat3(push_constants.model_view))) * convert(a_normal);
+  mat4 model_view = entity_uniforms.model_view * a_model;
+  f_normal = transpose(inv(mat3(model_view))) * convert(a_normal);
   f_diffuse = a_diffuse;
-  gl_Position = push_constants.transform * vec4(convert(a_position1), 1.0);
+  f_alpha = a_alpha;
+  vec3 position = mix(a_position2, a_position1, a_blend);
+  gl_Position = entity_uniforms.transform * a_model * vec4(convert(position), 1.0);
 }
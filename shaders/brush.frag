@@ -1,18 +1,21 @@
 #version 450
-#define LIGHTMAP_ANIM_END (255)
 
 const uint TEXTURE_KIND_REGULAR = 0;
 const uint TEXTURE_KIND_WARP = 1;
 const uint TEXTURE_KIND_SKY = 2;
+const uint TEXTURE_KIND_SKYBOX = 3;
 
 const float WARP_AMPLITUDE = 0.15;
 const float WARP_FREQUENCY = 0.25;
 const float WARP_SCALE = 1.0;
 
+// the speed of the sky's back layer, in texels per second
+const float SKY_SPEED = 8.0;
+
 layout(location = 0) in vec3 f_normal;
 layout(location = 1) in vec2 f_diffuse; // also used for fullbright
 layout(location = 2) in vec2 f_lightmap;
-flat layout(location = 3) in uvec4 f_lightmap_anim;
+layout(location = 3) in vec3 f_sky_dir;
 
 layout(push_constant) uniform PushConstants {
   layout(offset = 128) uint texture_kind;
@@ -37,29 +40,55 @@ layout(set = 2, binding = 2) uniform TextureUniforms {
 } texture_uniforms;
 
 // set 3: per-face
-layout(set = 3, binding = 0) uniform texture2D u_lightmap_texture[4];
+layout(set = 3, binding = 0) uniform texture2D u_lightmap_texture;
 
 layout(location = 0) out vec4 diffuse_attachment;
 layout(location = 1) out vec4 normal_attachment;
 
-vec4 calc_light() {
-    vec4 light = vec4(0.0, 0.0, 0.0, 0.0);
-    for (int i = 0; i < 4; i++) {
-        if (f_lightmap_anim[i] == LIGHTMAP_ANIM_END)
-            break;
+// the lightmap is blended from the light styles and dynamic lights on the CPU, and stored at half
+// brightness so that it can go up to twice as bright. it's white unless the map has a .lit file.
+vec3 calc_light() {
+    return 2.0 * texture(
+        sampler2D(u_lightmap_texture, u_lightmap_sampler),
+        f_lightmap
+    ).rgb;
+}
 
-        float map = texture(
-            sampler2D(u_lightmap_texture[i], u_lightmap_sampler),
-            f_lightmap
-        ).r;
+// the texcoord of a sky layer scrolling at `speed` units per second, in the layer's own
+// [0, 1] space. the layers are projected onto a flattened sphere around the camera.
+vec2 sky_texcoord(vec3 dir, float speed) {
+    dir.z *= 3.0;
+
+    // the coefficients here are magic taken from the Quake source
+    vec2 st = dir.xy * (6.0 * 63.0 / length(dir));
+    return fract((mod(speed * frame_uniforms.time, 128.0) + st) / 128.0);
+}
 
-        // range [0, 4]
-        ivec2 idx = ivec2(floor(f_lightmap_anim[i] / 4), mod(f_lightmap_anim[i], 4));
-        float style = frame_uniforms.light_anim_frames[idx.x][idx.y];
-        light[i] = map * style;
+// find where `dir` points in the skybox atlas, which has the faces rt, bk, lf, ft, up and dn
+// three across and two down. the faces are oriented as in FitzQuake.
+vec2 skybox_texcoord(vec3 dir) {
+    vec3 a = abs(dir);
+    uint face;
+    vec2 st;
+    if (a.x >= a.y && a.x >= a.z) {
+        face = dir.x > 0.0 ? 0u : 2u;
+        st = dir.x > 0.0 ? vec2(-dir.y, dir.z) / a.x : vec2(dir.y, dir.z) / a.x;
+    } else if (a.y >= a.z) {
+        face = dir.y > 0.0 ? 1u : 3u;
+        st = dir.y > 0.0 ? vec2(dir.x, dir.z) / a.y : vec2(-dir.x, dir.z) / a.y;
+    } else {
+        face = dir.z > 0.0 ? 4u : 5u;
+        st = dir.z > 0.0 ? vec2(-dir.y, -dir.x) / a.z : vec2(-dir.y, dir.x) / a.z;
     }
 
-    return light;
+    vec2 uv = vec2(st.x + 1.0, 1.0 - st.y) * 0.5;
+
+    // stay half a texel inside the face, so that filtering doesn't pick up the next one
+    vec2 face_size = vec2(textureSize(sampler2D(u_diffuse_texture, u_diffuse_sampler), 0))
+        / vec2(3.0, 2.0);
+    uv = clamp(uv, 0.5 / face_size, 1.0 - 0.5 / face_size);
+
+    return (vec2(face % 3u, face / 3u) + uv) / vec2(3.0, 2.0);
 }
 
 void main() {
@@ -71,17 +100,25 @@ void main() {
             ).r;
 
 
+            vec3 diffuse = texture(
+                sampler2D(u_diffuse_texture, u_diffuse_sampler),
+                f_diffuse
+            ).rgb;
+
+            // the G-buffer only has room for the light level, so the color of the light tints the
+            // diffuse color instead
             float light;
             if (fullbright != 0.0) {
                 light = 0.25;
             } else {
-                light = dot(calc_light(), vec4(1.));
+                vec3 color = calc_light();
+                light = max(color.r, max(color.g, color.b));
+                if (light > 0.0) {
+                    diffuse *= color / light;
+                }
             }
 
-            diffuse_attachment = vec4(texture(
-                sampler2D(u_diffuse_texture, u_diffuse_sampler),
-                f_diffuse
-            ).rgb, light);
+            diffuse_attachment = vec4(diffuse, light);
 
             break;
 
@@ -101,34 +138,39 @@ void main() {
             break;
 
         case TEXTURE_KIND_SKY:
-            vec2 base = mod(f_diffuse + frame_uniforms.time, 1.0);
-            vec2 cloud_texcoord = vec2(base.s * 0.5, base.t);
-            vec2 sky_texcoord = vec2(base.s * 0.5 + 0.5, base.t);
+            // the right half of the texture is the solid back layer, and the left half is the
+            // cloud layer in front of it, which scrolls twice as fast
+            vec2 back = sky_texcoord(f_sky_dir, SKY_SPEED);
+            vec2 front = sky_texcoord(f_sky_dir, 2.0 * SKY_SPEED);
 
             vec4 sky_color = texture(
                 sampler2D(u_diffuse_texture, u_diffuse_sampler),
-                sky_texcoord
+                vec2(back.s * 0.5 + 0.5, back.t)
             );
             vec4 cloud_color = texture(
                 sampler2D(u_diffuse_texture, u_diffuse_sampler),
-                cloud_texcoord
+                vec2(front.s * 0.5, front.t)
             );
 
-            // 0.0 if black, 1.0 otherwise
-            float cloud_factor;
-            if (cloud_color.r + cloud_color.g + cloud_color.b == 0.0) {
-                cloud_factor = 0.0;
-            } else {
-                cloud_factor = 1.0;
-            }
+            // black is transparent in the cloud layer
+            float cloud_factor = step(0.0001, cloud_color.r + cloud_color.g + cloud_color.b);
             diffuse_attachment = vec4(mix(sky_color, cloud_color, cloud_factor).rgb, 0.25);
             break;
 
+        case TEXTURE_KIND_SKYBOX:
+            diffuse_attachment = vec4(texture(
+                sampler2D(u_diffuse_texture, u_diffuse_sampler),
+                skybox_texcoord(f_sky_dir)
+            ).rgb, 0.25);
+            break;
+
         // not possible
         default:
             break;
     }
 
-    // rescale normal to [0, 1]
-    normal_attachment = vec4(f_normal / 2.0 + 0.5, 1.0);
+    // rescale normal to [0, 1]. the alpha is 0 where dynamic lights have already been added to
+    // the lightmap, so the deferred pass doesn't light it again.
+    float dynamic_light = push_constants.texture_kind == TEXTURE_KIND_REGULAR ? 0.0 : 1.0;
+    normal_attachment = vec4(f_normal / 2.0 + 0.5, dynamic_light);
 }
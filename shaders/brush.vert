@@ -3,12 +3,12 @@
 const uint TEXTURE_KIND_NORMAL = 0;
 const uint TEXTURE_KIND_WARP = 1;
 const uint TEXTURE_KIND_SKY = 2;
+const uint TEXTURE_KIND_SKYBOX = 3;
 
 layout(location = 0) in vec3 a_position;
 layout(location = 1) in vec3 a_normal;
 layout(location = 2) in vec2 a_diffuse;
 layout(location = 3) in vec2 a_lightmap;
-layout(location = 4) in uvec4 a_lightmap_anim;
 
 layout(push_constant) uniform PushConstants {
   mat4 transform;
@@ -19,7 +19,7 @@ layout(push_constant) uniform PushConstants {
 layout(location = 0) out vec3 f_normal;
 layout(location = 1) out vec2 f_diffuse;
 layout(location = 2) out vec2 f_lightmap;
-layout(location = 3) out uvec4 f_lightmap_anim;
+layout(location = 3) out vec3 f_sky_dir;
 
 // set 0: per-frame
 layout(set = 0, binding = 0) uniform FrameUniforms {
@@ -64,21 +64,14 @@ mat3 inv(mat3 matrix) {
 }
 
 void main() {
-    if (push_constants.texture_kind == TEXTURE_KIND_SKY) {
-        vec3 dir = a_position - frame_uniforms.camera_pos.xyz;
-        dir.z *= 3.0;
+    f_diffuse = a_diffuse;
 
-        // the coefficients here are magic taken from the Quake source
-        float len = 6.0 * 63.0 / length(dir);
-        dir = vec3(dir.xy * len, dir.z);
-        f_diffuse = (mod(8.0 * frame_uniforms.time, 128.0) + dir.xy) / 128.0;
-    } else {
-        f_diffuse = a_diffuse;
-    }
+    // the direction from the camera, in Quake coordinates. the sky is projected per pixel, since
+    // the projection isn't linear across large faces.
+    f_sky_dir = a_position - frame_uniforms.camera_pos.xyz;
 
     f_normal = transpose(inv(mat3(push_constants.model_view))) * convert(a_normal);
     f_lightmap = a_lightmap;
-    f_lightmap_anim = a_lightmap_anim;
     gl_Position = push_constants.transform * vec4(convert(a_position), 1.0);
 
 }
@@ -10,24 +10,39 @@ layout(set = 0, binding = 1) uniform sampler u_nearestsampler;
 layout(set = 0, binding = 2) uniform texture2D u_diffuse;
 layout(set = 0, binding = 3) uniform texture2D u_normal;
 layout(set = 0, binding = 4) uniform texture2D u_depth;
+struct PointLight {
+  // xyz: origin in view space, w: radius
+  vec4 origin_radius;
+  // rgb: linear color
+  vec4 color;
+};
+
 layout(set = 0, binding = 5) uniform DeferredUniforms {
   mat4 inv_projection;
   uint light_count;
   float exposure;
-  uvec2 _pad2;
-  vec4 lights[MAX_LIGHTS];
+  // the fraction of the inputs that the world was drawn to
+  vec2 scale;
+  PointLight lights[MAX_LIGHTS];
 } u_deferred;
 
+layout(set = 0, binding = 6) uniform FogUniforms {
+  vec3 color;
+  // 0 for no fog
+  float density;
+} u_fog;
+
 layout(location = 0) out vec4 color_attachment;
 
 const float MIN_LIGHT = 0.01;
+const float LOG2E = 1.442695;
 
-vec3 dlight_origin(vec4 dlight) {
-  return dlight.xyz;
+vec3 dlight_origin(PointLight dlight) {
+  return dlight.origin_radius.xyz;
 }
 
-float dlight_radius(vec4 dlight) {
-  return dlight.w;
+float dlight_radius(PointLight dlight) {
+  return dlight.origin_radius.w;
 }
 
 vec3 reconstruct_position(float depth) {
@@ -39,31 +54,49 @@ vec3 reconstruct_position(float depth) {
 }
 
 void main() {
-  vec4 in_diffuse = texture(sampler2D(u_diffuse, u_sampler), a_texcoord);
+  vec2 uv = a_texcoord * u_deferred.scale;
+  vec4 in_diffuse = texture(sampler2D(u_diffuse, u_sampler), uv);
   vec4 in_color = vec4(in_diffuse.rgb, 1.);
 
   // scale from [0, 1] to [-1, 1]
-  vec3 in_normal = 2.0
-    * texture(sampler2D(u_normal, u_sampler), a_texcoord).xyz
-    - 1.0;
+  vec4 normal_sample = texture(sampler2D(u_normal, u_sampler), uv);
+  vec3 in_normal = 2.0 * normal_sample.xyz - 1.0;
 
-  float in_depth = texture(sampler2D(u_depth, u_nearestsampler), a_texcoord).x;
+  // lightmapped surfaces have dynamic lights added to their lightmaps instead
+  bool dynamic_light = normal_sample.a > 0.5;
+
+  float in_depth = texture(sampler2D(u_depth, u_nearestsampler), uv).x;
   vec3 position = reconstruct_position(in_depth);
 
   vec4 out_color = in_color;
 
-  float light = in_diffuse.a;
-  for (uint i = 0; i < u_deferred.light_count && i < MAX_LIGHTS; i++) {
-    vec4 dlight = u_deferred.lights[i];
+  vec3 light = vec3(in_diffuse.a);
+  for (uint i = 0; dynamic_light && i < u_deferred.light_count && i < MAX_LIGHTS; i++) {
+    PointLight dlight = u_deferred.lights[i];
     vec3 dir = normalize(position - dlight_origin(dlight));
     float dist = abs(distance(dlight_origin(dlight), position));
     float radius = dlight_radius(dlight);
 
     if (dist < radius && dot(dir, in_normal) < 0.0) {
       // linear attenuation
-      light += (radius - dist) / radius;
+      light += dlight.color.rgb * (radius - dist) / radius;
+    }
+  }
+
+  vec3 lit = max(vec3(MIN_LIGHT), light) * out_color.rgb;
+
+  // exponential squared fog like GL_EXP2, where nothing drawn is as far away as can be
+  if (u_fog.density > 0.0) {
+    float visibility = 0.0;
+    if (in_depth < 1.0) {
+      float fog_distance = u_fog.density * length(position);
+      visibility = clamp(exp2(-LOG2E * fog_distance * fog_distance), 0.0, 1.0);
     }
+
+    // the fog color is given in the same space as the textures
+    vec3 fog_color = pow(u_fog.color, vec3(2.2));
+    lit = mix(fog_color, lit, visibility);
   }
 
-  color_attachment = vec4(u_deferred.exposure * max(MIN_LIGHT, light) * out_color.rgb, 1.0);
+  color_attachment = vec4(u_deferred.exposure * lit, 1.0);
 }
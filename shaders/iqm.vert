@@ -0,0 +1,90 @@
+#version 450
+
+layout(location = 0) in vec3 a_position;
+layout(location = 1) in vec3 a_normal;
+layout(location = 2) in vec2 a_diffuse;
+layout(location = 3) in uvec4 a_joints;
+layout(location = 4) in vec4 a_weights;
+
+// per instance
+layout(location = 5) in mat4 a_model;
+layout(location = 9) in float a_alpha;
+layout(location = 10) in float a_blend;
+
+layout(push_constant) uniform PushConstants {
+  mat4 transform;
+  mat4 model_view;
+  // where the previous and current poses start in u_poses
+  uint prev_pose_offset;
+  uint pose_offset;
+} push_constants;
+
+// set 3: per-model
+layout(std430, set = 3, binding = 0) readonly buffer Poses {
+  mat4 u_poses[];
+};
+
+layout(location = 0) out vec3 f_normal;
+layout(location = 1) out vec2 f_diffuse;
+layout(location = 2) flat out float f_alpha;
+
+float det(mat2 matrix) {
+    return matrix[0].x * matrix[1].y - matrix[0].y * matrix[1].x;
+}
+
+mat3 inv(mat3 matrix) {
+    vec3 row0 = matrix[0];
+    vec3 row1 = matrix[1];
+    vec3 row2 = matrix[2];
+
+    vec3 minors0 = vec3(
+        det(mat2(row1.y, row1.z, row2.y, row2.z)),
+        det(mat2(row1.z, row1.x, row2.z, row2.x)),
+        det(mat2(row1.x, row1.y, row2.x, row2.y))
+    );
+    vec3 minors1 = vec3(
+        det(mat2(row2.y, row2.z, row0.y, row0.z)),
+        det(mat2(row2.z, row2.x, row0.z, row0.x)),
+        det(mat2(row2.x, row2.y, row0.x, row0.y))
+    );
+    vec3 minors2 = vec3(
+        det(mat2(row0.y, row0.z, row1.y, row1.z)),
+        det(mat2(row0.z, row0.x, row1.z, row1.x)),
+        det(mat2(row0.x, row0.y, row1.x, row1.y))
+    );
+
+    mat3 adj = transpose(mat3(minors0, minors1, minors2));
+
+    return (1.0 / dot(row0, minors0)) * adj;
+}
+
+// convert from Quake coordinates
+vec3 convert(vec3 from) {
+  return vec3(-from.y, from.z, -from.x);
+}
+
+// the joint's skinning matrix, blended from the previous pose to the current one
+mat4 joint_matrix(uint joint) {
+  return u_poses[push_constants.prev_pose_offset + joint] * (1.0 - a_blend)
+    + u_poses[push_constants.pose_offset + joint] * a_blend;
+}
+
+void main() {
+  float total_weight = dot(a_weights, vec4(1.0));
+  mat4 skin = mat4(1.0);
+  if (total_weight > 0.0) {
+    skin = (joint_matrix(a_joints.x) * a_weights.x
+      + joint_matrix(a_joints.y) * a_weights.y
+      + joint_matrix(a_joints.z) * a_weights.z
+      + joint_matrix(a_joints.w) * a_weights.w) / total_weight;
+  }
+
+  vec3 position = (skin * vec4(a_position, 1.0)).xyz;
+  vec3 normal = mat3(skin) * a_normal;
+
+  mat4 model_view = push_constants.model_view * a_model;
+  f_normal = transpose(inv(mat3(model_view))) * convert(normal);
+  f_diffuse = a_diffuse;
+  f_alpha = a_alpha;
+  gl_Position = push_constants.transform * a_model * vec4(convert(position), 1.0);
+}
@@ -1,15 +1,16 @@
 #version 450
 
+// vertex rate
 layout(location = 0) in vec2 a_position;
 layout(location = 1) in vec2 a_texcoord;
 
-layout(location = 0) out vec2 f_texcoord;
+// instance rate
+layout(location = 2) in vec2 a_instance_position;
+layout(location = 3) in vec2 a_instance_scale;
 
-layout(set = 2, binding = 0) uniform QuadUniforms {
-  mat4 transform;
-} quad_uniforms;
+layout(location = 0) out vec2 f_texcoord;
 
 void main() {
   f_texcoord = a_texcoord;
-  gl_Position = quad_uniforms.transform * vec4(a_position, 0.0, 1.0);
+  gl_Position = vec4(a_instance_scale * a_position + a_instance_position, 0.0, 1.0);
 }
@@ -0,0 +1,38 @@
+#version 450
+
+layout(location = 0) in vec2 f_texcoord;
+layout(location = 1) in vec3 f_color;
+layout(location = 2) in float f_distance;
+
+layout(set = 0, binding = 0) uniform sampler u_sampler;
+layout(set = 0, binding = 1) uniform sampler u_nearestsampler;
+layout(set = 0, binding = 2) uniform texture2D u_texture;
+layout(set = 0, binding = 3) uniform texture2D u_depth;
+
+layout(set = 0, binding = 4) uniform SoftParticleUniforms {
+  mat4 view_projection;
+  mat4 view;
+  mat4 inv_projection;
+  // x: brightness, y: the distance over which particles fade into surfaces, z: size
+  vec4 params;
+} u_particle;
+
+layout(location = 0) out vec4 color_attachment;
+
+void main() {
+  float alpha = texture(sampler2D(u_texture, u_sampler), f_texcoord).a;
+
+  // the world was drawn to the same pixels of the depth buffer as this pass draws to
+  float depth = texelFetch(sampler2D(u_depth, u_nearestsampler), ivec2(gl_FragCoord.xy), 0).x;
+  vec4 view = u_particle.inv_projection * vec4(0.0, 0.0, depth, 1.0);
+  float world_distance = -view.z / view.w;
+
+  // fade out toward the world instead of being cut off where the particle goes into it
+  float softness = max(u_particle.params.y, 0.001);
+  alpha *= clamp((world_distance - f_distance) / softness, 0.0, 1.0);
+  if (alpha <= 0.0) {
+    discard;
+  }
+
+  color_attachment = vec4(u_particle.params.x * f_color, alpha);
+}
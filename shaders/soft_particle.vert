@@ -0,0 +1,39 @@
+#version 450
+
+layout(location = 0) in vec3 a_position;
+layout(location = 1) in vec2 a_texcoord;
+layout(location = 2) in vec3 a_origin;
+layout(location = 3) in vec3 a_color;
+
+layout(set = 0, binding = 4) uniform SoftParticleUniforms {
+  mat4 view_projection;
+  mat4 view;
+  mat4 inv_projection;
+  // x: brightness, y: the distance over which particles fade into surfaces, z: size
+  vec4 params;
+} u_particle;
+
+layout(location = 0) out vec2 f_texcoord;
+layout(location = 1) out vec3 f_color;
+layout(location = 2) out float f_distance;
+
+void main() {
+  float distance = -(u_particle.view * vec4(a_origin, 1.0)).z;
+
+  // like in GLQuake, distant particles shrink more slowly than perspective would shrink them so
+  // that they stay visible
+  float size = u_particle.params.z;
+  if (distance >= 20.0) {
+    size *= 1.0 + distance * 0.004;
+  }
+
+  // face the camera, whose right and up vectors are the first two rows of the view matrix
+  vec3 right = vec3(u_particle.view[0][0], u_particle.view[1][0], u_particle.view[2][0]);
+  vec3 up = vec3(u_particle.view[0][1], u_particle.view[1][1], u_particle.view[2][1]);
+  vec3 position = a_origin + size * (a_position.x * right + a_position.y * up);
+
+  f_texcoord = a_texcoord;
+  f_color = a_color;
+  f_distance = distance;
+  gl_Position = u_particle.view_projection * vec4(position, 1.0);
+}
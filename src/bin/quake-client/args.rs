@@ -0,0 +1,224 @@
+//! Quake-style command-line arguments.
+//!
+//! Options start with a single dash, like `-game hipnotic` or `-window`, and everything from a
+//! `+` up to the next option is a console command, like `+map e1m1 +skill 3`. The commands are run
+//! by `stuffcmds` in `quake.rc`, after the config has been loaded.
+
+use std::path::PathBuf;
+
+pub const USAGE: &str = "\
+usage: quake-client [options] [+command [args]...]
+
+options:
+  -basedir <dir>     the directory containing id1 (default: the current directory)
+  -game <dir>        play the game in <dir>, which is loaded on top of id1
+  -hipnotic, -rogue  play a mission pack, the same as -game hipnotic or -game rogue
+  -dedicated [n]     run a dedicated server for up to n players
+  -window            start in a window
+  -fullscreen        start fullscreen
+  -width <pixels>    the width of the window
+  -height <pixels>   the height of the window
+  -help              show this message
+
+commands are run after quake.rc, e.g. +map e1m1 +record run1";
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Args {
+    pub base_dir: Option<PathBuf>,
+    pub game: Option<String>,
+
+    /// The number of players for a dedicated server, if one was asked for.
+    pub dedicated: Option<usize>,
+
+    /// Whether to start fullscreen, or the default if `None`.
+    pub fullscreen: Option<bool>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+
+    pub help: bool,
+
+    /// Options this engine doesn't know, with their values, to be warned about once logging has
+    /// started.
+    pub ignored: Vec<String>,
+
+    /// The `+` commands, in order, without the `+`.
+    pub commands: Vec<String>,
+}
+
+/// Whether `arg` starts an option. Negative numbers are arguments to the command before them.
+fn is_option(arg: &str) -> bool {
+    arg.strip_prefix('-')
+        .and_then(|rest| rest.chars().next())
+        .is_some_and(|c| !c.is_ascii_digit() && c != '.')
+}
+
+impl Args {
+    pub fn parse<I, S>(args: I) -> Result<Args, String>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let mut args = args.into_iter().map(Into::into).peekable();
+        let mut out = Args::default();
+
+        fn value<T: std::str::FromStr>(option: &str, value: Option<String>) -> Result<T, String> {
+            let value = value.ok_or_else(|| format!("{} needs a value", option))?;
+            value
+                .parse()
+                .map_err(|_| format!("invalid value for {}: \"{}\"", option, value))
+        }
+
+        while let Some(arg) = args.next() {
+            if let Some(cmd) = arg.strip_prefix('+') {
+                let mut cmd = cmd.to_owned();
+                while let Some(arg) = args.next_if(|a| !a.starts_with('+') && !is_option(a)) {
+                    cmd.push(' ');
+                    cmd.push_str(&arg);
+                }
+                out.commands.push(cmd);
+                continue;
+            }
+
+            if !is_option(&arg) {
+                return Err(format!(
+                    "expected an option or a +command, found \"{}\"",
+                    arg
+                ));
+            }
+
+            // accept `--base-dir` as well as `-basedir`
+            let option = arg
+                .trim_start_matches('-')
+                .replace('-', "")
+                .to_ascii_lowercase();
+            match &*option {
+                "basedir" => out.base_dir = Some(value(&arg, args.next())?),
+                "game" => out.game = Some(value(&arg, args.next())?),
+                "hipnotic" | "rogue" => out.game = Some(option.clone()),
+                "dedicated" => {
+                    // the number of players is optional
+                    let players = args.next_if(|a| a.parse::<usize>().is_ok());
+                    out.dedicated = Some(match players {
+                        Some(players) => value(&arg, Some(players))?,
+                        None => 8,
+                    });
+                }
+                "window" | "windowed" => out.fullscreen = Some(false),
+                "fullscreen" => out.fullscreen = Some(true),
+                "width" => out.width = Some(value(&arg, args.next())?),
+                "height" => out.height = Some(value(&arg, args.next())?),
+                "help" | "?" => out.help = true,
+
+                // other engines' options shouldn't stop a launcher from starting the game. Any
+                // value they take is skipped too, like the 65536 in `-heapsize 65536`
+                _ => {
+                    let mut ignored = arg.clone();
+                    if let Some(value) = args.next_if(|a| !a.starts_with('+') && !is_option(a)) {
+                        ignored.push(' ');
+                        ignored.push_str(&value);
+                    }
+                    out.ignored.push(ignored);
+                }
+            }
+        }
+
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_args() {
+        let args = Args::parse([
+            "-basedir",
+            "/games/quake",
+            "-game",
+            "ad",
+            "-window",
+            "-width",
+            "1280",
+            "+map",
+            "e1m1",
+            "+record",
+            "run1",
+            "-height",
+            "720",
+            "+setpos",
+            "-100",
+            "20.5",
+            "-.5",
+        ])
+        .unwrap();
+
+        assert_eq!(
+            args,
+            Args {
+                base_dir: Some("/games/quake".into()),
+                game: Some("ad".into()),
+                fullscreen: Some(false),
+                width: Some(1280),
+                height: Some(720),
+                commands: vec![
+                    "map e1m1".into(),
+                    "record run1".into(),
+                    "setpos -100 20.5 -.5".into(),
+                ],
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_args_options() {
+        let args = Args::parse(["--base-dir", "q", "-rogue", "-dedicated", "+map", "start"]);
+        assert_eq!(
+            args,
+            Ok(Args {
+                base_dir: Some("q".into()),
+                game: Some("rogue".into()),
+                dedicated: Some(8),
+                commands: vec!["map start".into()],
+                ..Default::default()
+            })
+        );
+
+        let args = Args::parse(["-dedicated", "16"]).unwrap();
+        assert_eq!(args.dedicated, Some(16));
+
+        assert!(Args::parse(["-game"]).is_err());
+        assert!(Args::parse(["-width", "wide"]).is_err());
+        assert!(Args::parse(["e1m1"]).is_err());
+        assert!(Args::parse(["-nosound"]).is_ok());
+    }
+
+    #[test]
+    fn test_parse_args_unknown() {
+        let args = Args::parse([
+            "-heapsize",
+            "65536",
+            "-nosound",
+            "-zone",
+            "2048",
+            "-window",
+            "+map",
+            "e1m1",
+        ])
+        .unwrap();
+        assert_eq!(
+            args,
+            Args {
+                fullscreen: Some(false),
+                ignored: vec![
+                    "-heapsize 65536".into(),
+                    "-nosound".into(),
+                    "-zone 2048".into()
+                ],
+                commands: vec!["map e1m1".into()],
+                ..Default::default()
+            }
+        );
+    }
+}
@@ -1,287 +1,272 @@
 use clap::Parser;
 use crossbeam_channel::{Receiver, Sender};
 use std::{
-    collections::BTreeMap,
-    path::PathBuf,
-    sync::{atomic::AtomicBool, Arc},
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
     time::Duration,
 };
 
-use bevy::{prelude::*, render::view::screenshot::ScreenshotManager, window::PrimaryWindow};
-use chrono::Utc;
-use image::RgbImage;
-use seismon::common::console::RegisterCmdExt as _;
+use bevy::{
+    prelude::*, render::view::screenshot::ScreenshotManager, time::TimeUpdateStrategy,
+    window::PrimaryWindow,
+};
+use seismon::{
+    client::{
+        demo,
+        photo::{PhotoCaptures, PhotoMode},
+        Connection,
+    },
+    common::{
+        console::{ConsoleOutput, ExecResult, RegisterCmdExt as _, RunCmd},
+        util,
+        vfs::Vfs,
+    },
+};
+
+/// Screenshots without a path are numbered from `seismon0000` upwards in this directory, under the
+/// game directory.
+const SCREENSHOT_DIR: &str = "screenshots";
+const SCREENSHOT_FORMATS: [&str; 2] = ["png", "tga"];
+
+/// The first of `seismon0000.<ext>` to `seismon9999.<ext>` in `dir` that doesn't exist yet.
+fn next_screenshot_path(dir: &Path, ext: &str) -> Option<PathBuf> {
+    (0..10000)
+        .map(|i| dir.join(format!("seismon{:04}.{}", i, ext)))
+        .find(|path| !path.exists())
+}
+
+/// Write a screenshot read back from the GPU, in the format given by the extension of `path`.
+fn save_screenshot(image: Image, path: &Path) -> Result<(), Box<dyn Error>> {
+    let format = image::ImageFormat::from_path(path)?;
+    let image = image.try_into_dynamic()?;
+    image.to_rgb8().save_with_format(path, format)?;
+    Ok(())
+}
+
+/// The messages for screenshots that have finished saving, which happens after the `screenshot`
+/// command has returned.
+#[derive(Resource)]
+struct ScreenshotResults {
+    send: Sender<String>,
+    recv: Receiver<String>,
+}
+
+impl Default for ScreenshotResults {
+    fn default() -> Self {
+        let (send, recv) = crossbeam_channel::unbounded();
+        ScreenshotResults { send, recv }
+    }
+}
+
+/// Frames captured by `capturedemo` go in a directory named after the demo, under this one.
+const CAPTURE_DIR: &str = "capture";
 
 pub struct CapturePlugin;
 
 impl Plugin for CapturePlugin {
     fn build(&self, app: &mut App) {
         #[derive(Parser)]
-        #[command(name = "screenshot", about = "Take a screenshot")]
+        #[command(
+            name = "screenshot",
+            about = "Take a screenshot, saved as screenshots/seismon<n>.png if no path is given"
+        )]
         struct Screenshot {
             path: Option<PathBuf>,
+            /// The image format to use when no path is given, png or tga
+            #[arg(long, default_value = "png")]
+            format: String,
         }
 
         #[derive(Parser)]
-        #[command(name = "startvideo", about = "Start recording a video")]
-        struct StartVideo {
-            path: Option<PathBuf>,
-            #[arg(long)]
-            width: Option<u32>,
-            #[arg(long)]
-            height: Option<u32>,
+        #[command(
+            name = "capturedemo",
+            about = "Play a demo at a fixed frame rate, saving every frame as a numbered PNG"
+        )]
+        struct CaptureDemo {
+            demo: String,
+            fps: f32,
         }
 
-        #[derive(Parser)]
-        #[command(name = "stopvideo", about = "Stop recording")]
-        struct StopVideo;
-
+        app.init_resource::<ScreenshotResults>();
         app.add_systems(
             Update,
             (
-                systems::video_frame.run_if(resource_exists::<VideoCtx>),
-                systems::recv_frame.run_if(resource_exists::<VideoCtxRecv>),
+                systems::demo_capture_frame.run_if(resource_exists::<DemoCapture>),
+                systems::print_screenshot_results,
             ),
         )
         .command(
-            |In(Screenshot { path }),
+            |In(Screenshot { path, format }),
+             vfs: Res<Vfs>,
+             results: Res<ScreenshotResults>,
              window: Query<Entity, With<PrimaryWindow>>,
-             mut screenshot_manager: ResMut<ScreenshotManager>| {
+             photo: Res<PhotoMode>,
+             mut captures: ResMut<PhotoCaptures>,
+             mut screenshot_manager: ResMut<ScreenshotManager>|
+             -> ExecResult {
                 let Ok(window) = window.get_single() else {
                     return "Can't find primary window".to_owned().into();
                 };
 
                 let path = match path {
-                    // TODO: make default path configurable
+                    Some(path) => path,
                     None => {
-                        PathBuf::from(format!("richter-{}.png", Utc::now().format("%FT%H-%M-%S")))
+                        let format = format.to_lowercase();
+                        if !SCREENSHOT_FORMATS.contains(&format.as_str()) {
+                            return format!("Unknown screenshot format \"{}\"", format).into();
+                        }
+
+                        let dir = match vfs.find_writable_filename(SCREENSHOT_DIR) {
+                            Ok(dir) => dir,
+                            Err(e) => return format!("Couldn't take screenshot: {}", e).into(),
+                        };
+                        if let Err(e) = fs::create_dir_all(&dir) {
+                            return format!("Couldn't create {}: {}", dir.display(), e).into();
+                        }
+
+                        match next_screenshot_path(&dir, &format) {
+                            Some(path) => path,
+                            None => {
+                                return format!("Too many screenshots in {}", dir.display()).into()
+                            }
+                        }
                     }
-                    Some(path) => path,
                 };
 
-                match screenshot_manager.save_screenshot_to_disk(window, path) {
+                // the image is read back from the GPU and written out over the next few frames, so
+                // whether it worked is only known once that's done
+                let send = results.send.clone();
+
+                // photo mode pictures are rendered offscreen, larger than the window
+                if photo.is_active() {
+                    captures.request(move |image| {
+                        let message = match image {
+                            Ok(image) => match save_screenshot(image, &path) {
+                                Ok(()) => format!("Wrote {}", path.display()),
+                                Err(e) => format!("Couldn't write {}: {}", path.display(), e),
+                            },
+                            Err(e) => format!("Couldn't capture {}: {}", path.display(), e),
+                        };
+                        let _ = send.send(message);
+                    });
+                    return default();
+                }
+
+                let taken = screenshot_manager.take_screenshot(window, move |image| {
+                    let message = match save_screenshot(image, &path) {
+                        Ok(()) => format!("Wrote {}", path.display()),
+                        Err(e) => format!("Couldn't write {}: {}", path.display(), e),
+                    };
+                    // the receiver lives as long as the app
+                    let _ = send.send(message);
+                });
+
+                match taken {
                     Ok(()) => default(),
                     Err(e) => format!("Couldn't take screenshot: {}", e).into(),
                 }
             },
         )
         .command(
-            |In(StartVideo {
-                 path,
-                 width,
-                 height,
-             }),
+            |In(CaptureDemo { demo, fps }),
              mut commands: Commands,
-             window: Query<&Window, With<PrimaryWindow>>,
-             ctx: Option<Res<VideoCtx>>| {
-                fn ceil_to(x: u32, to: u32) -> u32 {
-                    let x = x + (to - 1);
-                    x - (x % to)
+             vfs: Res<Vfs>,
+             capture: Option<Res<DemoCapture>>,
+             mut run_cmds: EventWriter<RunCmd<'static>>| {
+                if capture.is_some() {
+                    return "Already capturing a demo".into();
                 }
 
-                const LONGEST_SIDE: u32 = 800;
-                const FPS: f64 = 30.;
-
-                if ctx.is_some() {
-                    return "Already recording video".into();
+                if !(fps > 0.0 && fps.is_finite()) {
+                    return "The frame rate must be above 0".into();
                 }
 
-                let mut path = match path {
-                    // TODO: make default path configurable
-                    None => {
-                        PathBuf::from(format!("richter-{}.mp4", Utc::now().format("%FT%H-%M-%S")))
-                    }
-                    Some(path) => path,
-                };
-                if path.extension().is_none() {
-                    path.set_extension("mp4");
+                if let Err(e) = demo::open_demo(&vfs, &demo) {
+                    return format!("{}", e).into();
                 }
 
-                let aspect_ratio = window
-                    .get_single()
-                    .map(|w| w.width() / w.height())
-                    .unwrap_or(4. / 3.);
-                let size = match (width, height) {
-                    (Some(w), Some(h)) => [w, h],
-                    (Some(w), None) => [w, (w as f32 / aspect_ratio) as u32],
-                    (None, Some(h)) => [(h as f32 * aspect_ratio) as u32, h],
-                    (None, None) => {
-                        if aspect_ratio < 1. {
-                            [(LONGEST_SIDE as f32 * aspect_ratio) as u32, LONGEST_SIDE]
-                        } else {
-                            [LONGEST_SIDE, (LONGEST_SIDE as f32 / aspect_ratio) as u32]
-                        }
-                    }
+                let name = util::file_stem(&demo, ".dem");
+                let dir = match vfs.find_writable_filename(format!("{}/{}", CAPTURE_DIR, name)) {
+                    Ok(dir) => dir,
+                    Err(e) => return format!("Couldn't capture {}: {}", demo, e).into(),
                 };
-                let [w, h] = size.map(|x| ceil_to(x, 10));
-
-                let out = format!("Recording a video ({}x{}) to {}", w, h, path.display());
-
-                let (sender, receiver) = crossbeam_channel::unbounded::<VideoFrame>();
-                let frame_time = Duration::from_secs_f64(FPS.recip());
-
-                let encoder = video_rs::Encoder::new(
-                    &path.into(),
-                    video_rs::EncoderSettings::for_h264_yuv420p(w as _, h as _, true),
-                )
-                .unwrap();
-
-                commands.insert_resource(VideoCtx {
-                    send_frame: sender,
-                    size: (w, h),
-                    frame_time,
-                    last_time: None,
-                    cur_frame: 0,
-                    closed: Arc::new(false.into()),
-                });
+                if let Err(e) = fs::create_dir_all(&dir) {
+                    return format!("Couldn't create {}: {}", dir.display(), e).into();
+                }
 
-                commands.insert_resource(VideoCtxRecv {
-                    recv_frame: Some(receiver),
-                    frame_buf: default(),
-                    encoder,
-                    frame_time: video_rs::Time::from_nth_of_a_second(FPS as _),
+                // every update advances the game by exactly one frame, however long it takes
+                commands.insert_resource(TimeUpdateStrategy::ManualDuration(
+                    Duration::from_secs_f32(fps.recip()),
+                ));
+                commands.insert_resource(DemoCapture {
+                    dir: dir.clone(),
                     cur_frame: 0,
+                    started: false,
                 });
+                run_cmds.send(RunCmd("playdemo".into(), Box::new([demo])));
 
-                out.into()
-            },
-        )
-        .command(
-            |In(StopVideo), mut commands: Commands, ctx: Option<Res<VideoCtx>>| {
-                if ctx.is_some() {
-                    commands.remove_resource::<VideoCtx>();
-                    default()
-                } else {
-                    "Error: no video recording in progress".into()
-                }
+                format!("Capturing {} fps to {}", fps, dir.display()).into()
             },
         );
     }
 }
 
-struct VideoFrame {
-    image: RgbImage,
-    frame_id: usize,
-}
-
+/// A demo being played back by `capturedemo`.
 #[derive(Resource)]
-struct VideoCtx {
-    send_frame: Sender<VideoFrame>,
-    size: (u32, u32),
-    last_time: Option<Duration>,
-    frame_time: Duration,
+struct DemoCapture {
+    dir: PathBuf,
     cur_frame: usize,
-    closed: Arc<AtomicBool>,
-}
 
-#[derive(Resource)]
-struct VideoCtxRecv {
-    recv_frame: Option<Receiver<VideoFrame>>,
-    frame_buf: BTreeMap<usize, RgbImage>,
-    cur_frame: usize,
-    frame_time: video_rs::Time,
-    encoder: video_rs::Encoder,
+    /// Whether the demo has started playing, after which the capture ends along with it.
+    started: bool,
 }
 
 mod systems {
-    use crossbeam_channel::TryRecvError;
-    use std::sync::atomic::Ordering;
-
-    use image::imageops::FilterType;
-
     use super::*;
 
-    pub fn video_frame(
+    pub fn print_screenshot_results(
+        results: Res<ScreenshotResults>,
+        time: Res<Time<Real>>,
+        mut console: ResMut<ConsoleOutput>,
+    ) {
+        for message in results.recv.try_iter() {
+            console.println(message, chrono::Duration::from_std(time.elapsed()).unwrap());
+        }
+    }
+
+    pub fn demo_capture_frame(
         mut commands: Commands,
         mut screenshot: ResMut<ScreenshotManager>,
         window: Query<Entity, With<PrimaryWindow>>,
-        time: Res<Time>,
-        mut ctx: ResMut<VideoCtx>,
+        conn: Option<Res<Connection>>,
+        mut capture: ResMut<DemoCapture>,
     ) {
+        let playing = conn.is_some_and(|c| c.is_demo());
+        capture.started |= playing;
+
         let Ok(window) = window.get_single() else {
-            commands.remove_resource::<VideoCtx>();
             return;
         };
 
-        if ctx.closed.load(Ordering::SeqCst) {
-            commands.remove_resource::<VideoCtx>();
+        if capture.started && !playing {
+            info!(
+                "Captured {} frames to {}",
+                capture.cur_frame,
+                capture.dir.display()
+            );
+            commands.remove_resource::<DemoCapture>();
+            commands.insert_resource(TimeUpdateStrategy::Automatic);
             return;
         }
 
-        if ctx
-            .last_time
-            .map(|t| time.elapsed() >= (t + ctx.frame_time))
-            .unwrap_or(true)
-        {
-            let sender = ctx.send_frame.clone();
-            let frame_id = ctx.cur_frame;
-            let size = ctx.size;
-            let closed = ctx.closed.clone();
-
-            ctx.last_time = Some(time.elapsed());
-
-            if let Ok(_) = screenshot.take_screenshot(window, move |image| {
-                let image = image
-                    .try_into_dynamic()
-                    .unwrap()
-                    .resize_to_fill(size.0, size.1, FilterType::Nearest)
-                    .into_rgb8();
-
-                if let Err(_) = sender.send(VideoFrame { image, frame_id }) {
-                    closed.store(true, Ordering::SeqCst);
-                }
-            }) {
-                ctx.cur_frame += 1;
-            }
+        if !playing {
+            return;
         }
 
-        // Handle new frames
-    }
-
-    pub fn recv_frame(mut ctx: ResMut<VideoCtxRecv>, mut commands: Commands) {
-        loop {
-            let frame = match (ctx.frame_buf.first_key_value(), &ctx.recv_frame) {
-                (Some((frame, _)), _) if *frame == ctx.cur_frame => {
-                    let (_, frame) = ctx.frame_buf.pop_first().unwrap();
-                    frame
-                }
-                (Some(_), None) => {
-                    let (_, frame) = ctx.frame_buf.pop_first().unwrap();
-                    frame
-                }
-                (_, Some(recv)) => {
-                    match recv.try_recv() {
-                        Ok(next) => {
-                            ctx.frame_buf.insert(next.frame_id, next.image);
-                        }
-                        Err(TryRecvError::Empty) => break,
-                        Err(TryRecvError::Disconnected) => ctx.recv_frame = None,
-                    }
-
-                    continue;
-                }
-                (None, None) => {
-                    commands.remove_resource::<VideoCtxRecv>();
-                    break;
-                }
-            };
-
-            let frame = frame.into_flat_samples();
-            let frame_array = ndarray::Array3::<u8>::from_shape_vec(
-                (
-                    frame.layout.height as usize,
-                    frame.layout.width as usize,
-                    frame.layout.channels as usize,
-                ),
-                frame.samples,
-            )
-            .unwrap();
-            let time = video_rs::Time::new(
-                Some(ctx.cur_frame as _),
-                ctx.frame_time.clone().into_parts().1,
-            );
-            ctx.encoder.encode(&frame_array, &time).unwrap();
-            ctx.cur_frame += 1;
+        let path = capture.dir.join(format!("{:06}.png", capture.cur_frame));
+        if screenshot.save_screenshot_to_disk(window, path).is_ok() {
+            capture.cur_frame += 1;
         }
     }
 }
@@ -20,45 +20,39 @@
 
 #![recursion_limit = "256"]
 
+mod args;
 mod capture;
 mod menu;
+#[cfg(all(feature = "screenrecord", not(target_arch = "wasm32")))]
+mod video;
 
-use std::{path::PathBuf, process::ExitCode};
+use std::process::ExitCode;
 
+use args::Args;
 use bevy::{
     audio::AudioPlugin,
     core_pipeline::{
-        bloom::BloomSettings,
         prepass::{DepthPrepass, NormalPrepass},
         tonemapping::Tonemapping,
     },
     pbr::DefaultOpaqueRendererMethod,
     prelude::*,
     render::{camera::Exposure, view::ColorGrading},
-    window::{PresentMode, PrimaryWindow},
+    window::{PresentMode, PrimaryWindow, WindowMode},
 };
 #[cfg(feature = "auto-exposure")]
 use bevy_mod_auto_exposure::{AutoExposure, AutoExposurePlugin};
 use capture::CapturePlugin;
-use clap::Parser;
 use seismon::{
     client::SeismonClientPlugin,
-    common::console::{ConsoleInput, RegisterCmdExt as _, RunCmd},
+    common::{
+        console::{startup_commands, ConsoleInput, RegisterCmdExt as _, RunCmd},
+        vfs::Vfs,
+    },
     server::SeismonServerPlugin,
 };
 use serde_lexpr::Value;
 
-#[derive(Parser, Debug)]
-struct Opt {
-    #[arg(long)]
-    base_dir: Option<PathBuf>,
-
-    #[arg(long)]
-    game: Option<String>,
-
-    commands: Vec<String>,
-}
-
 fn cmd_exposure(In(val): In<Value>, mut exposures: Query<&mut Exposure>) {
     let new_exposure = match val.as_name() {
         Some("indoor") => Exposure::INDOOR,
@@ -107,20 +101,6 @@ fn cmd_postsaturation(In(saturation): In<Value>, mut gradings: Query<&mut ColorG
     }
 }
 
-fn cmd_gamma(In(gamma): In<Value>, mut gradings: Query<&mut ColorGrading>) {
-    let gamma: f32 = match serde_lexpr::from_value(&gamma) {
-        Ok(gamma) => gamma,
-        Err(_) => {
-            // TODO: Error handling
-            return;
-        }
-    };
-
-    for mut grading in &mut gradings {
-        grading.gamma = 1. / gamma;
-    }
-}
-
 #[cfg(feature = "auto-exposure")]
 fn cmd_autoexposure(
     In(autoexposure): In<Value>,
@@ -185,8 +165,10 @@ fn cmd_gametitle(In(new_name): In<Value>, mut window: Query<&mut Window, With<Pr
     }
 }
 
-fn startup(opt: Opt) -> impl FnMut(Commands, ResMut<ConsoleInput>, EventWriter<RunCmd<'static>>) {
-    move |mut commands, mut input: ResMut<ConsoleInput>, mut console_cmds| {
+fn startup(
+    commands: Vec<String>,
+) -> impl FnMut(Commands, Res<Vfs>, ResMut<ConsoleInput>, EventWriter<RunCmd<'static>>) {
+    move |mut commands, vfs: Res<Vfs>, mut input: ResMut<ConsoleInput>, mut console_cmds| {
         // main game camera
         commands.spawn((
             Camera3dBundle {
@@ -205,52 +187,112 @@ fn startup(opt: Opt) -> impl FnMut(Commands, ResMut<ConsoleInput>, EventWriter<R
                 },
                 ..default()
             },
-            BloomSettings::default(),
             DepthPrepass,
             NormalPrepass,
         ));
 
-        console_cmds.send(RunCmd::parse("exec quake.rc").unwrap());
-
-        let mut commands = opt.commands.iter();
-        let mut next = commands.next();
-        while let Some(cur) = next {
-            if let Some(rest) = cur.strip_prefix("+") {
-                let mut cmd = rest.to_string();
-                loop {
-                    next = commands.next();
-
-                    if let Some(arg) = next {
-                        if arg.starts_with("+") {
-                            break;
-                        }
-
-                        cmd.push(' ');
-                        cmd.push_str(arg);
-                    } else {
-                        break;
-                    }
-                }
-
-                let runcmd = match RunCmd::parse(&*cmd) {
-                    Ok(cmd) => cmd.into_owned(),
-                    Err(e) => {
-                        warn!("Couldn't parse cmd {:?}: {}", cmd, e);
-                        continue;
-                    }
-                };
-
-                input.stuffcmds.push(runcmd);
-            } else {
-                warn!("Arg without command: {}", cur);
-                next = commands.next();
-            }
+        console_cmds.send_batch(startup_commands(&vfs));
+        stuff_commands(&mut input, &commands);
+    }
+}
+
+/// Warn about the command-line options that were ignored, which can't be logged until the app
+/// has started.
+fn warn_ignored(ignored: Vec<String>) -> impl FnMut() {
+    move || {
+        for option in &ignored {
+            warn!("Ignoring unknown option {}", option);
+        }
+    }
+}
+
+/// Queue the `+` commands to be run by `stuffcmds`.
+fn stuff_commands(input: &mut ConsoleInput, commands: &[String]) {
+    for cmd in commands {
+        match RunCmd::parse(cmd) {
+            Ok(runcmd) => input.stuffcmds.push(runcmd.into_owned()),
+            Err(e) => warn!("Couldn't parse cmd {:?}: {}", cmd, e),
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn dedicated_startup(
+    max_players: usize,
+    commands: Vec<String>,
+) -> impl FnMut(Res<Vfs>, ResMut<ConsoleInput>, EventWriter<RunCmd<'static>>) {
+    move |vfs: Res<Vfs>, mut input: ResMut<ConsoleInput>, mut console_cmds| {
+        // remote players can only connect to a game for more than one player, and a dedicated
+        // server has no local player
+        if max_players < 2 {
+            warn!(
+                "-dedicated {} allows no remote players, so nobody can join this server",
+                max_players
+            );
         }
+
+        let maxplayers = format!("maxplayers {}", max_players);
+        console_cmds.send(RunCmd::parse(&maxplayers).unwrap().into_owned());
+        console_cmds.send_batch(startup_commands(&vfs));
+        stuff_commands(&mut input, &commands);
     }
 }
 
+/// Run a server with no window, taking commands from stdin.
+#[cfg(not(target_arch = "wasm32"))]
+fn run_dedicated(args: Args, max_players: usize) -> ExitCode {
+    use std::time::Duration;
+
+    use bevy::{app::ScheduleRunnerPlugin, log::LogPlugin};
+    use seismon::server::dedicated::SeismonDedicatedPlugin;
+
+    App::new()
+        .add_plugins((
+            MinimalPlugins.set(ScheduleRunnerPlugin::run_loop(Duration::from_secs_f64(
+                1. / 100.,
+            ))),
+            LogPlugin::default(),
+        ))
+        .add_plugins(SeismonDedicatedPlugin {
+            base_dir: args.base_dir,
+            game: args.game,
+        })
+        .add_plugins(SeismonServerPlugin)
+        .add_systems(
+            Startup,
+            (
+                warn_ignored(args.ignored),
+                dedicated_startup(max_players, args.commands),
+            ),
+        )
+        .run();
+
+    ExitCode::SUCCESS
+}
+
 fn main() -> ExitCode {
-    let opt = Opt::parse();
+    let args = match Args::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, args::USAGE);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if args.help {
+        println!("{}", args::USAGE);
+        return ExitCode::SUCCESS;
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(max_players) = args.dedicated {
+        return run_dedicated(args, max_players);
+    }
+
+    let mode = match args.fullscreen {
+        Some(true) => WindowMode::BorderlessFullscreen,
+        _ => WindowMode::Windowed,
+    };
 
     let mut app = App::new();
     let default_plugins = DefaultPlugins
@@ -258,7 +300,12 @@ fn main() -> ExitCode {
             primary_window: Some(bevy::window::Window {
                 title: "Seismon".into(),
                 name: Some("seismon-engine".into()),
-                resolution: (1366., 768.).into(),
+                resolution: (
+                    args.width.unwrap_or(1366) as f32,
+                    args.height.unwrap_or(768) as f32,
+                )
+                    .into(),
+                mode,
                 present_mode: PresentMode::AutoVsync,
                 // Tells wasm not to override default event handling, like F5, Ctrl+R etc.
                 prevent_default_event_handling: false,
@@ -276,8 +323,8 @@ fn main() -> ExitCode {
 
     app
     .add_plugins(SeismonClientPlugin{
-        base_dir: opt.base_dir.clone(),
-        game: opt.game.clone(),
+        base_dir: args.base_dir.clone(),
+        game: args.game.clone(),
         main_menu: menu::build_main_menu,
     })
     .add_plugins(SeismonServerPlugin)
@@ -294,12 +341,6 @@ fn main() -> ExitCode {
         cmd_exposure,
         "Set the physically-based exposure of the screen: indoor, sunlight, overcast, blender, or a specific ev100 value",
     )
-    .cvar_on_set(
-        "gamma",
-        "1",
-        cmd_gamma,
-        "Adjust the gamma of the screen",
-    )
     .cvar_on_set(
         "r_saturation",
         "1",
@@ -318,7 +359,10 @@ fn main() -> ExitCode {
         cmd_tonemapping,
         "Set the tonemapping type - Tony McMapFace (TMMF), ACES, Blender Filmic, Somewhat Boring Display Transform (SBBT), or none",
     ).insert_resource(DefaultOpaqueRendererMethod::deferred())
-        .add_systems(Startup, startup(opt));
+        .add_systems(Startup, (warn_ignored(args.ignored), startup(args.commands)));
+
+    #[cfg(all(feature = "screenrecord", not(target_arch = "wasm32")))]
+    app.add_plugins(video::VideoPlugin);
 
     #[cfg(feature = "auto-exposure")]
     app.add_plugins(AutoExposurePlugin).cvar_on_set(
@@ -22,13 +22,24 @@ use bevy::{
     app::AppExit,
     ecs::{
         event::{EventWriter, Events},
-        system::ResMut,
+        system::{Res, ResMut},
     },
     log::warn,
 };
 use seismon::{
-    client::menu::{Menu, MenuBodyView, MenuBuilder, MenuView},
-    common::console::{Registry, RunCmd},
+    client::{
+        menu::{
+            layout::{MENU_WIDTH, VALUE_X},
+            EnumItem, Menu, MenuBodyView, MenuBuilder, MenuView, SaveSlotKind,
+        },
+        sound,
+        video::VIDEO_MODES,
+        SeismonGameSettings,
+    },
+    common::{
+        console::{Registry, RunCmd},
+        vfs::{self, Vfs, BASE_GAME},
+    },
 };
 
 use failure::Error;
@@ -53,9 +64,9 @@ pub fn build_main_menu(builder: MenuBuilder) -> Result<Menu, Error> {
 
 fn build_menu_sp(builder: MenuBuilder) -> Result<Menu, Error> {
     Ok(builder
-        .add_action("New Game", || ())
-        .add_action("Load", || unimplemented!())
-        .add_action("Save", || unimplemented!())
+        .add_submenu("New Game", build_menu_new_game)?
+        .add_submenu("Load", build_menu_load)?
+        .add_submenu("Save", build_menu_save)?
         .build(MenuView {
             draw_plaque: true,
             title_path: "gfx/ttl_sgl.lmp".into(),
@@ -65,11 +76,81 @@ fn build_menu_sp(builder: MenuBuilder) -> Result<Menu, Error> {
         }))
 }
 
+/// The first map of each episode, along with the episode's name.
+const EPISODES: &[(&str, &str)] = &[
+    ("start", "Introduction"),
+    ("e1m1", "Dimension of the Doomed"),
+    ("e2m1", "Realm of Black Magic"),
+    ("e3m1", "The Netherworld"),
+    ("e4m1", "The Elder World"),
+    // Scourge of Armagon
+    ("hip1m1", "Fortress of the Dead"),
+    ("hip2m1", "Dominion of Darkness"),
+    ("hip3m1", "The Rift"),
+    // Dissolution of Eternity
+    ("r1m1", "Hell's Fortress"),
+    ("r2m1", "Corridors of Time"),
+];
+
+fn build_menu_new_game(mut builder: MenuBuilder) -> Result<Menu, Error> {
+    let skills = [
+        ("Easy", "0"),
+        ("Normal", "1"),
+        ("Hard", "2"),
+        ("Nightmare", "3"),
+    ]
+    .into_iter()
+    .map(|(name, value)| EnumItem::new(name, value))
+    .collect::<Result<Vec<_>, _>>()?;
+
+    // only offer the episodes whose maps are installed
+    let episodes = builder
+        .world()
+        .get_resource::<Vfs>()
+        .map(|vfs| {
+            EPISODES
+                .iter()
+                .filter(|(map, _)| vfs.open(format!("maps/{}.bsp", map)).is_ok())
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+
+    let mut builder = builder.add_enum("Skill", "skill", 1, |_| skills);
+
+    for &(map, name) in episodes {
+        builder = builder.add_action(name, move |mut commands: EventWriter<RunCmd<'static>>| {
+            commands.send(RunCmd("map".into(), Box::new([map.to_owned()])));
+        });
+    }
+
+    Ok(builder.build(MenuView {
+        draw_plaque: true,
+        title_path: "gfx/ttl_sgl.lmp".into(),
+        body: MenuBodyView::Dynamic,
+    }))
+}
+
+fn build_menu_load(builder: MenuBuilder) -> Result<Menu, Error> {
+    Ok(builder.add_save_slots(SaveSlotKind::Load).build(MenuView {
+        draw_plaque: false,
+        title_path: "gfx/p_load.lmp".into(),
+        body: MenuBodyView::Dynamic,
+    }))
+}
+
+fn build_menu_save(builder: MenuBuilder) -> Result<Menu, Error> {
+    Ok(builder.add_save_slots(SaveSlotKind::Save).build(MenuView {
+        draw_plaque: false,
+        title_path: "gfx/p_save.lmp".into(),
+        body: MenuBodyView::Dynamic,
+    }))
+}
+
 fn build_menu_mp(builder: MenuBuilder) -> Result<Menu, Error> {
     Ok(builder
         .add_submenu("Join a Game", build_menu_mp_join)?
         .add_action("New Game", || unimplemented!())
-        .add_action("Setup", || unimplemented!())
+        .add_submenu("Setup", build_menu_setup)?
         .build(MenuView {
             draw_plaque: true,
             title_path: "gfx/p_multi.lmp".into(),
@@ -79,34 +160,84 @@ fn build_menu_mp(builder: MenuBuilder) -> Result<Menu, Error> {
         }))
 }
 
-fn build_menu_mp_join(builder: MenuBuilder) -> Result<Menu, Error> {
+/// The names of the colors players can choose for their shirt and pants.
+const PLAYER_COLORS: &[&str] = &[
+    "White",
+    "Brown",
+    "Light blue",
+    "Green",
+    "Red",
+    "Olive",
+    "Orange",
+    "Peach",
+    "Purple",
+    "Magenta",
+    "Tan",
+    "Dark green",
+    "Yellow",
+    "Blue",
+];
+
+fn build_menu_setup(mut builder: MenuBuilder) -> Result<Menu, Error> {
+    let (name, top, bottom) = match builder.world().get_resource::<Registry>() {
+        Some(registry) => (
+            registry
+                .get_cvar("name")
+                .and_then(|cvar| cvar.value().as_name().map(str::to_owned)),
+            registry.read_cvar::<usize>("topcolor").unwrap_or(0),
+            registry.read_cvar::<usize>("bottomcolor").unwrap_or(0),
+        ),
+        None => (None, 0, 0),
+    };
+
+    let colors = || {
+        PLAYER_COLORS
+            .iter()
+            .enumerate()
+            .map(|(i, name)| EnumItem::new(*name, i.to_string()))
+            .collect::<Result<Vec<_>, _>>()
+    };
+    let (top_colors, bottom_colors) = (colors()?, colors()?);
+    let max_color = PLAYER_COLORS.len() - 1;
+
     Ok(builder
-        .add_submenu("TCP", build_menu_mp_join_tcp)?
-        // .add_textbox // description
+        .add_text_field("Your name", name, Some(15), "name")?
+        .add_enum("Shirt color", "topcolor", top.min(max_color), |_| {
+            top_colors
+        })
+        .add_enum("Pants color", "bottomcolor", bottom.min(max_color), |_| {
+            bottom_colors
+        })
+        .add_player_preview("topcolor", "bottomcolor")
         .build(MenuView {
             draw_plaque: true,
             title_path: "gfx/p_multi.lmp".into(),
-            body: MenuBodyView::Predefined {
-                path: "gfx/mp_menu.lmp".into(),
-            },
+            body: MenuBodyView::Dynamic,
         }))
 }
 
-fn build_menu_mp_join_tcp(builder: MenuBuilder) -> Result<Menu, Error> {
-    // Join Game - TCP/IP          // title
-    //
-    //  Address: 127.0.0.1         // label
-    //
-    //  Port     [26000]           // text field
-    //
-    //  Search for local games...  // menu
-    //
-    //  Join game at:              // label
-    //  [                        ] // text field
+fn build_menu_mp_join(builder: MenuBuilder) -> Result<Menu, Error> {
     Ok(builder
-        // .add
-        // TODO
-        .add_toggle("placeholder", false, "scratch1")
+        .add_text_field("Join game at", Some(""), Some(40), "cl_joinaddress")?
+        .add_action(
+            "Join",
+            |registry: Res<Registry>, mut commands: EventWriter<RunCmd<'static>>| {
+                let addr = registry.read_cvar::<String>("cl_joinaddress");
+                match addr {
+                    Ok(addr) if !addr.is_empty() => {
+                        commands.send(RunCmd("connect".into(), Box::new([addr])));
+                    }
+                    _ => warn!("No server address entered"),
+                }
+            },
+        )
+        .add_action(
+            "Search for games",
+            |mut commands: EventWriter<RunCmd<'static>>| {
+                commands.send("slist".into());
+            },
+        )
+        .add_server_list()
         .build(MenuView {
             draw_plaque: true,
             title_path: "gfx/p_multi.lmp".into(),
@@ -114,49 +245,183 @@ fn build_menu_mp_join_tcp(builder: MenuBuilder) -> Result<Menu, Error> {
         }))
 }
 
+/// Laid out like the original options menu, with the extra pages at the end.
 fn build_menu_options(builder: MenuBuilder) -> Result<Menu, Error> {
+    let status_bar = vec![
+        EnumItem::new("None", "0")?,
+        EnumItem::new("Transparent", "1")?,
+        EnumItem::new("Standard", "2")?,
+        EnumItem::new("Full", "3")?,
+    ];
+    let invert_mouse = vec![
+        EnumItem::new("Off", "0.022")?,
+        EnumItem::new("On", "-0.022")?,
+    ];
+
     Ok(builder
-        // .add_submenu("Customize controls", unimplemented!())
+        .add_submenu("Customize controls", build_menu_controls)?
         .add_action(
             "Go to console",
             |mut commands: EventWriter<RunCmd<'static>>| {
                 commands.send("toggleconsole".into());
             },
         )
-        // TODO
         .add_action("Reset to defaults", |mut cvars: ResMut<Registry>| {
             for cvar in [
-                "r_renderscale",
-                "r_screensize",
-                "r_gamma",
-                "cl_sensitivity",
-                "bgmvolume",
+                "cl_hud",
+                "gamma",
+                "sensitivity",
                 "volume",
+                "sfxvolume",
+                "bgmvolume",
+                "snd_device",
+                "snd_reverb",
+                "snd_waterfx",
                 "cl_alwaysrun",
-                "invertmouse",
-                "lookspring",
-                "lookstrafe",
+                "m_pitch",
+                "freelook",
             ] {
                 if let Err(e) = cvars.reset_cvar(cvar) {
                     warn!("{}", e);
                 }
             }
         })
-        .add_slider("Render scale", 0.25, 1.0, 2, 0, "r_renderscale")?
-        .add_slider("Screen Size", 0.0, 1.0, 10, 9, "r_screensize")?
-        .add_slider("Brightness", 0.5, 1.5, 10, 5, "r_gamma")?
-        .add_slider("Mouse Speed", 0.0, 1.0, 10, 9, "cl_sensitivity")?
-        .add_slider("CD music volume", 0.0, 1.0, 10, 9, "bgmvolume")?
-        .add_slider("Sound volume", 0.0, 1.0, 10, 9, "volume")?
-        // TODO
-        .add_toggle("Always run", true, "cl_alwaysrun")
-        .add_toggle("Invert mouse", false, "invertmouse")
-        .add_toggle("Lookspring", false, "lookspring")
-        .add_toggle("Lookstrafe", false, "lookstrafe")
-        // .add_submenu("Video options", unimplemented!())
+        .add_enum("Status bar", "cl_hud", 3, |_| status_bar)
+        .add_slider("Brightness", 0.5, 1.5, 11, 5, "gamma")?
+        .add_slider("Mouse Speed", 1.0, 11.0, 21, 4, "sensitivity")?
+        .add_slider("Music Volume", 0.0, 1.0, 11, 10, "bgmvolume")?
+        .add_slider("Sound Volume", 0.0, 1.0, 11, 7, "volume")?
+        .add_toggle("Always run", false, "cl_alwaysrun")
+        .add_enum("Invert mouse", "m_pitch", 0, |_| invert_mouse)
+        .add_toggle("Mouse look", true, "freelook")
+        .add_submenu("Sound options", build_menu_sound)?
+        .add_submenu("Video options", build_menu_video)?
+        .add_submenu("Mods", build_menu_mods)?
         .build(MenuView {
             draw_plaque: true,
             title_path: "gfx/p_option.lmp".into(),
             body: MenuBodyView::Dynamic,
         }))
 }
+
+fn build_menu_video(builder: MenuBuilder) -> Result<Menu, Error> {
+    let resolutions = std::iter::once(EnumItem::new("Custom", "-1"))
+        .chain(VIDEO_MODES.iter().enumerate().map(|(i, (width, height))| {
+            EnumItem::new(format!("{}x{}", width, height), i.to_string())
+        }))
+        .collect::<Result<Vec<_>, _>>()?;
+    let modes = vec![
+        EnumItem::new("Windowed", "0")?,
+        EnumItem::new("Fullscreen", "1")?,
+        EnumItem::new("Borderless", "2")?,
+    ];
+    let vsync = vec![EnumItem::new("Off", "0")?, EnumItem::new("On", "1")?];
+
+    Ok(builder
+        .add_enum("Resolution", "vid_mode", 0, |_| resolutions)
+        .add_enum("Display mode", "vid_fullscreen", 0, |_| modes)
+        .add_enum("Vertical sync", "vid_vsync", 1, |_| vsync)
+        .add_slider("Render scale", 0.25, 1.0, 4, 3, "r_scale")?
+        .add_action(
+            "Restart renderer",
+            |mut commands: EventWriter<RunCmd<'static>>| {
+                commands.send("vid_restart".into());
+            },
+        )
+        .build(MenuView {
+            draw_plaque: true,
+            title_path: "gfx/vidmodes.lmp".into(),
+            body: MenuBodyView::Dynamic,
+        }))
+}
+
+fn build_menu_sound(builder: MenuBuilder) -> Result<Menu, Error> {
+    let devices = sound::output_devices();
+    let current = builder
+        .world()
+        .get_resource::<Registry>()
+        .and_then(|registry| registry.read_cvar::<String>("snd_device").ok())
+        .and_then(|current| devices.iter().position(|device| *device == current))
+        .map_or(0, |i| i + 1);
+
+    // device names can be long, so cut them off at the edge of the screen
+    let max_len = ((MENU_WIDTH - VALUE_X) / 8) as usize;
+    let devices = std::iter::once(EnumItem::new("Default", "\"\""))
+        .chain(devices.into_iter().map(|device| {
+            let name = device.chars().take(max_len).collect::<String>();
+            EnumItem::new(name, serde_lexpr::to_string(&device)?)
+        }))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // the overall and music volumes are on the options page, as in the original menu
+    Ok(builder
+        .add_slider("Effects volume", 0.0, 1.0, 11, 10, "sfxvolume")?
+        .add_enum("Output device", "snd_device", current, |_| devices)
+        .add_toggle("Underwater filter", true, "snd_waterfx")
+        .add_toggle("Reverb", true, "snd_reverb")
+        .build(MenuView {
+            draw_plaque: true,
+            title_path: "gfx/p_option.lmp".into(),
+            body: MenuBodyView::Dynamic,
+        }))
+}
+
+fn build_menu_mods(mut builder: MenuBuilder) -> Result<Menu, Error> {
+    let (games, current) = match builder.world().get_resource::<SeismonGameSettings>() {
+        Some(settings) => (
+            vfs::find_games(&settings.base_dir),
+            settings
+                .game
+                .clone()
+                .unwrap_or_else(|| BASE_GAME.to_owned()),
+        ),
+        None => (Vec::new(), BASE_GAME.to_owned()),
+    };
+
+    for game in games {
+        let name = if game == current {
+            format!("{} (current)", game)
+        } else {
+            game.clone()
+        };
+
+        builder = builder.add_action(name, move |mut commands: EventWriter<RunCmd<'static>>| {
+            commands.send(RunCmd("game".into(), Box::new([game.clone()])));
+        });
+    }
+
+    Ok(builder.build(MenuView {
+        draw_plaque: true,
+        title_path: "gfx/p_option.lmp".into(),
+        body: MenuBodyView::Dynamic,
+    }))
+}
+
+fn build_menu_controls(builder: MenuBuilder) -> Result<Menu, Error> {
+    Ok(builder
+        .add_binding("Attack", "+attack")
+        .add_binding("Next weapon", "impulse 10")
+        .add_binding("Previous weapon", "impulse 12")
+        .add_binding("Jump/Swim up", "+jump")
+        .add_binding("Walk forward", "+forward")
+        .add_binding("Backpedal", "+back")
+        .add_binding("Turn left", "+left")
+        .add_binding("Turn right", "+right")
+        .add_binding("Run", "+speed")
+        .add_binding("Step left", "+moveleft")
+        .add_binding("Step right", "+moveright")
+        .add_binding("Sidestep", "+strafe")
+        .add_binding("Look up", "+lookup")
+        .add_binding("Look down", "+lookdown")
+        .add_binding("Mouse look", "+mlook")
+        .add_binding("Keyboard look", "+klook")
+        .add_binding("Swim up", "+moveup")
+        .add_binding("Swim down", "+movedown")
+        .add_binding("Zoom", "+zoom")
+        .add_binding("Show scores", "+showscores")
+        .build(MenuView {
+            draw_plaque: true,
+            title_path: "gfx/ttl_cstm.lmp".into(),
+            body: MenuBodyView::Dynamic,
+        }))
+}
@@ -0,0 +1,226 @@
+//! Diagnostic bundles for bug reports (`bugreport`).
+//!
+//! The bundle is a zip archive written to the game directory. It contains:
+//!
+//! - `console.log`, the text printed to the console this session
+//! - `config.cfg`, every cvar that has been set
+//! - `paks.txt`, the mounted pakfiles and directories, with the size and CRC32 of each pak
+//! - `system.txt`, the engine version, the operating system and the graphics adapter
+//! - the newest demo in the game directory, if there is one, so the problem can be replayed
+
+use std::{
+    fmt::Write as _,
+    fs::{self, File},
+    io::Write as _,
+    path::{Path, PathBuf},
+};
+
+use bevy::{
+    prelude::*,
+    render::renderer::{RenderAdapterInfo, RenderDevice},
+};
+use chrono::Utc;
+use clap::Parser;
+use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+use crate::common::{
+    console::{ExecResult, RegisterCmdExt, Registry, RenderConsoleOutput},
+    vfs::{Mount, Vfs},
+};
+
+use super::SeismonGameSettings;
+
+fn config(registry: &Registry) -> String {
+    let mut out = String::new();
+    for name in registry.cvar_names() {
+        let Some(value) = registry.get_cvar(name).and_then(|cvar| cvar.value.as_ref()) else {
+            continue;
+        };
+
+        match value.as_name() {
+            Some(text) => writeln!(out, "{} \"{}\"", name, text),
+            None => writeln!(out, "{} \"{}\"", name, value),
+        }
+        .unwrap();
+    }
+
+    out
+}
+
+fn pak_list(vfs: &Vfs) -> String {
+    let mut out = String::new();
+    for mount in vfs.mounts() {
+        match mount {
+            Mount::Pak(pak) => writeln!(
+                out,
+                "{} {} bytes, {} files, crc32 {:08x}",
+                pak.path().display(),
+                pak.data().len(),
+                pak.iter().count(),
+                crc32fast::hash(pak.data()),
+            ),
+            Mount::Directory(path) => writeln!(out, "{}/", path.display()),
+            #[cfg(target_arch = "wasm32")]
+            Mount::Storage => writeln!(out, "browser storage"),
+        }
+        .unwrap();
+    }
+
+    out
+}
+
+fn system_info(
+    settings: &SeismonGameSettings,
+    adapter: Option<&RenderAdapterInfo>,
+    device: Option<&RenderDevice>,
+) -> String {
+    let mut out = String::new();
+    writeln!(out, "seismon {}", env!("CARGO_PKG_VERSION")).unwrap();
+    writeln!(
+        out,
+        "os: {} {}",
+        std::env::consts::OS,
+        std::env::consts::ARCH
+    )
+    .unwrap();
+    writeln!(out, "base dir: {}", settings.base_dir.display()).unwrap();
+    writeln!(out, "game: {}", settings.game.as_deref().unwrap_or("id1")).unwrap();
+
+    match adapter {
+        Some(adapter) => {
+            writeln!(
+                out,
+                "adapter: {} ({:?}, {:?})",
+                adapter.name, adapter.device_type, adapter.backend
+            )
+            .unwrap();
+            writeln!(
+                out,
+                "vendor: {:#06x}, device: {:#06x}",
+                adapter.vendor, adapter.device
+            )
+            .unwrap();
+            writeln!(out, "driver: {} {}", adapter.driver, adapter.driver_info).unwrap();
+        }
+        None => writeln!(out, "adapter: unknown").unwrap(),
+    }
+
+    if let Some(device) = device {
+        writeln!(out, "features: {:?}", device.features()).unwrap();
+        writeln!(out, "limits: {:#?}", device.limits()).unwrap();
+    }
+
+    out
+}
+
+/// The most recently modified demo in `dir`.
+fn newest_demo(dir: &Path) -> Option<PathBuf> {
+    fs::read_dir(dir)
+        .ok()?
+        .filter_map(Result::ok)
+        .filter(|entry| {
+            entry
+                .path()
+                .extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("dem"))
+        })
+        .filter_map(|entry| Some((entry.metadata().ok()?.modified().ok()?, entry.path())))
+        .max_by_key(|(modified, _)| *modified)
+        .map(|(_, path)| path)
+}
+
+fn write_bundle(path: &Path, files: &[(&str, &[u8])]) -> zip::result::ZipResult<()> {
+    let mut zip = ZipWriter::new(File::create(path)?);
+    let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    for (name, data) in files {
+        zip.start_file(*name, options)?;
+        zip.write_all(data)?;
+    }
+
+    zip.finish()?;
+    Ok(())
+}
+
+pub fn register_commands(app: &mut App) {
+    #[derive(Parser)]
+    #[command(
+        name = "bugreport",
+        about = "Save the console log, config, pak list, system info and latest demo to a zip file"
+    )]
+    struct BugReport;
+
+    app.command(
+        |In(BugReport),
+         registry: Res<Registry>,
+         vfs: Res<Vfs>,
+         settings: Res<SeismonGameSettings>,
+         console: Res<RenderConsoleOutput>,
+         adapter: Option<Res<RenderAdapterInfo>>,
+         device: Option<Res<RenderDevice>>|
+         -> ExecResult {
+            let name = format!("bugreport-{}.zip", Utc::now().format("%FT%H-%M-%S"));
+            let path = match vfs.find_writable_filename(&name) {
+                Ok(path) => path,
+                Err(e) => return format!("Couldn't write bug report: {}", e).into(),
+            };
+
+            let console_log = console.log();
+            let config = config(&registry);
+            let paks = pak_list(&vfs);
+            let system = system_info(&settings, adapter.as_deref(), device.as_deref());
+            let mut files: Vec<(&str, &[u8])> = vec![
+                ("console.log", console_log.as_bytes()),
+                ("config.cfg", config.as_bytes()),
+                ("paks.txt", paks.as_bytes()),
+                ("system.txt", system.as_bytes()),
+            ];
+
+            let demo = path
+                .parent()
+                .and_then(newest_demo)
+                .and_then(|demo| Some((demo.file_name()?.to_str()?.to_owned(), demo)));
+            let demo_data = match &demo {
+                Some((_, demo_path)) => fs::read(demo_path).map(Some),
+                None => Ok(None),
+            };
+            match (&demo, &demo_data) {
+                (Some((demo_name, _)), Ok(Some(data))) => files.push((demo_name, data)),
+                (Some((demo_name, _)), Err(e)) => {
+                    warn!("Leaving {} out of the bug report: {}", demo_name, e)
+                }
+                _ => {}
+            }
+
+            match write_bundle(&path, &files) {
+                Ok(()) => format!("Wrote {}", path.display()).into(),
+                Err(e) => format!("Couldn't write bug report: {}", e).into(),
+            }
+        },
+    );
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Read as _;
+
+    use super::*;
+
+    #[test]
+    fn test_write_bundle() {
+        let path =
+            std::env::temp_dir().join(format!("seismon-bugreport-{}.zip", std::process::id()));
+        write_bundle(&path, &[("a.txt", b"hello"), ("b.txt", b"")]).unwrap();
+
+        let mut zip = zip::ZipArchive::new(File::open(&path).unwrap()).unwrap();
+        let mut text = String::new();
+        zip.by_name("a.txt")
+            .unwrap()
+            .read_to_string(&mut text)
+            .unwrap();
+        assert_eq!(text, "hello");
+        assert_eq!(zip.len(), 2);
+
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -1,25 +1,26 @@
-use std::{collections::VecDeque, io::Read as _};
+use std::collections::VecDeque;
 
 use beef::Cow;
 use bevy::prelude::*;
-use clap::Parser;
+use clap::{Parser, Subcommand};
 
 use crate::{
     common::{
-        console::{AliasInfo, ExecResult, RegisterCmdExt as _, Registry, RunCmd},
+        console::{startup_commands, ExecResult, RegisterCmdExt as _, Registry},
         net::{ColorShift, QSocket, SignOnStage},
-        vfs::Vfs,
+        vfs::{Vfs, BASE_GAME},
     },
     server::Session,
 };
 
 use super::{
     connect,
-    demo::DemoServer,
+    demo::{self, DemoServer},
     input::InputFocus,
-    sound::{MixerEvent, MusicSource},
+    sound::{MixerEvent, MusicPlayer, MusicSource, StartMusic},
     state::ClientState,
-    ColorShiftCode, Connection, ConnectionKind, ConnectionState, DemoQueue,
+    ColorShiftCode, Connection, ConnectionKind, ConnectionState, DemoQueue, GameChanged,
+    SeismonGameSettings,
 };
 
 pub fn register_commands(app: &mut App) {
@@ -118,7 +119,7 @@ pub fn register_commands(app: &mut App) {
     );
 
     #[derive(Parser)]
-    #[command(name = "reconnect", about = "Disconnect from the current server")]
+    #[command(name = "disconnect", about = "Disconnect from the current server")]
     struct Disconnect;
 
     app.command(
@@ -137,6 +138,63 @@ pub fn register_commands(app: &mut App) {
         },
     );
 
+    #[derive(Parser)]
+    #[command(
+        name = "game",
+        about = "Switch to the game in a different directory, or show the current one"
+    )]
+    struct Game {
+        game: Option<String>,
+    }
+
+    app.command(
+        |In(Game { game }),
+         mut commands: Commands,
+         mut settings: ResMut<SeismonGameSettings>,
+         mut conn_state: ResMut<ConnectionState>,
+         mut focus: ResMut<InputFocus>,
+         mut changed: EventWriter<GameChanged>|
+         -> ExecResult {
+            let Some(game) = game else {
+                return format!(
+                    "\"game\" is \"{}\"",
+                    settings.game.as_deref().unwrap_or(BASE_GAME)
+                )
+                .into();
+            };
+
+            // the base game is always loaded, so it's the same as having no game
+            let game = Some(game).filter(|g| !g.eq_ignore_ascii_case(BASE_GAME));
+            if game == settings.game {
+                return default();
+            }
+
+            let vfs = match Vfs::try_with_base_dir(settings.base_dir.clone(), game.as_deref()) {
+                Ok(vfs) => vfs,
+                Err(e) => return format!("Couldn't switch game: {}", e).into(),
+            };
+
+            // nothing loaded from the old game's files can be kept, so disconnect first
+            commands.remove_resource::<Session>();
+            commands.remove_resource::<Connection>();
+            commands.remove_resource::<QSocket>();
+            *conn_state = ConnectionState::SignOn(SignOnStage::Not);
+            if *focus == InputFocus::Game {
+                *focus = InputFocus::Console;
+            }
+            let startup = startup_commands(&vfs);
+            commands.insert_resource(vfs);
+            settings.game = game;
+            changed.send(GameChanged);
+
+            // start over with the new game's config and demos
+            ExecResult {
+                extra_commands: Box::new(startup.into_iter()),
+                ..default()
+            }
+        },
+    );
+
     #[derive(Parser)]
     #[command(name = "playdemo", about = "Play a specific demo")]
     struct PlayDemo {
@@ -150,7 +208,7 @@ pub fn register_commands(app: &mut App) {
          mut focus: ResMut<InputFocus>,
          mut conn_state: ResMut<ConnectionState>| {
             let (new_conn, new_state) = {
-                let mut demo_file = match vfs.open(format!("{}.dem", demo)) {
+                let mut demo_file = match demo::open_demo(&vfs, &demo) {
                     Ok(f) => f,
                     Err(e) => {
                         return format!("{}", e).into();
@@ -241,6 +299,78 @@ pub fn register_commands(app: &mut App) {
         },
     );
 
+    #[derive(Parser)]
+    #[command(
+        name = "demo_analyze",
+        about = "Read through a demo without playing it and print statistics for each level"
+    )]
+    struct DemoAnalyze {
+        demo: String,
+    }
+
+    app.command(|In(DemoAnalyze { demo }), vfs: Res<Vfs>| -> ExecResult {
+        let mut demo_file = match demo::open_demo(&vfs, &demo) {
+            Ok(f) => f,
+            Err(e) => return format!("{}", e).into(),
+        };
+
+        let levels = match DemoServer::new(&mut demo_file)
+            .map_err(|e| e.to_string())
+            .and_then(|mut d| demo::analyze(&mut d).map_err(|e| e.to_string()))
+        {
+            Ok(levels) => levels,
+            Err(e) => return format!("Couldn't analyze {}: {}", demo, e).into(),
+        };
+
+        if levels.is_empty() {
+            return format!("{} doesn't contain any levels", demo).into();
+        }
+
+        let total = levels
+            .iter()
+            .filter(|l| l.completed)
+            .fold(chrono::Duration::zero(), |total, l| total + l.time);
+
+        let mut out = levels
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join("\n");
+        if levels.len() > 1 {
+            out.push_str(&format!(
+                "\ntotal time {}",
+                super::runtimer::format_time(total)
+            ));
+        }
+
+        out.into()
+    });
+
+    #[derive(Parser)]
+    #[command(
+        name = "getpos",
+        about = "Print the player's position and view angles as a `setpos` command"
+    )]
+    struct GetPos;
+
+    app.command(|In(GetPos), conn: Option<Res<Connection>>| -> ExecResult {
+        let Some(conn) = conn else {
+            return "getpos: not connected".into();
+        };
+        let state = &conn.state;
+
+        let Some(ent) = state.entities.get(state.view_entity_id()) else {
+            return "getpos: no view entity".into();
+        };
+        let angles = state.camera_angles(conn.kind.is_demo());
+
+        format!(
+            "setpos {:.1} {:.1} {:.1} {:.1} {:.1}",
+            ent.origin.x, ent.origin.y, ent.origin.z, angles.pitch.0, angles.yaw.0
+        )
+        .into()
+    });
+
     #[derive(Parser)]
     #[command(name = "music", about = "Play a named music track")]
     struct Music {
@@ -249,7 +379,10 @@ pub fn register_commands(app: &mut App) {
     }
 
     app.command(|In(Music { track }), mut events: EventWriter<MixerEvent>| {
-        events.send(MixerEvent::StartMusic(Some(MusicSource::Named(track))));
+        events.send(MixerEvent::StartMusic(Some(StartMusic {
+            source: MusicSource::Named(track),
+            looping: true,
+        })));
         default()
     });
 
@@ -290,131 +423,59 @@ pub fn register_commands(app: &mut App) {
     });
 
     #[derive(Parser)]
-    #[command(name = "echo", about = "Echo to the console")]
-    struct Echo {
-        args: Vec<String>,
+    #[command(
+        name = "cd",
+        about = "Play music tracks from music/trackNN.ogg or .mp3 in place of CD audio"
+    )]
+    struct Cd {
+        #[command(subcommand)]
+        command: CdCommand,
     }
 
-    app.command(|In(Echo { args })| args.join(" ").trim().to_owned().into());
-
-    #[derive(Parser)]
-    #[command(name = "alias", about = "Make an alias command")]
-    struct Alias {
-        alias_name: Option<String>,
-        commands: Option<String>,
+    #[derive(Subcommand)]
+    enum CdCommand {
+        /// Play a track once
+        Play { track: usize },
+        /// Play a track over and over
+        Loop { track: usize },
+        /// Stop the current track
+        Stop,
+        /// Pause the current track
+        Pause,
+        /// Resume the current track
+        Resume,
+        /// Print the current track
+        Info,
     }
 
     app.command(
-        |In(Alias {
-             alias_name,
-             commands,
-         }),
-         mut registry: ResMut<Registry>| {
-            match (alias_name, commands) {
-                (None, None) => {
-                    let aliases = registry.aliases();
-
-                    // TODO: There's probably a better way to do this
-                    let mut count = 0;
-                    let alias_text = aliases.flat_map(
-                        |AliasInfo {
-                             name,
-                             target,
-                             help: _,
-                         }| {
-                            count += 1;
-                            ["    ", name, ": ", target, "\n"]
-                        },
-                    );
-                    let mut out = String::new();
-                    for text in alias_text {
-                        out.push_str(text);
-                    }
-                    out.push_str(&count.to_string());
-                    out.push_str("alias command(s)");
-
-                    out.into()
-                }
-
-                (Some(from), Some(to)) => {
-                    registry.alias(from, to);
-
-                    default()
+        |In(Cd { command }),
+         music_player: Res<MusicPlayer>,
+         mut events: EventWriter<MixerEvent>|
+         -> ExecResult {
+            let event = match command {
+                CdCommand::Play { track } | CdCommand::Loop { track } => {
+                    MixerEvent::StartMusic(Some(StartMusic {
+                        source: MusicSource::TrackId(track),
+                        looping: matches!(command, CdCommand::Loop { .. }),
+                    }))
                 }
-
-                // TODO: Alias info for just one alias
-                _ => String::new().into(),
-            }
-        },
-    );
-
-    #[derive(Parser)]
-    #[command(name = "find", about = "Find a command by name")]
-    struct Find {
-        pattern: String,
-    }
-
-    app.command(move |In(Find { pattern }), cmds: Res<Registry>| {
-        // Take every item starting with the target.
-        let it = cmds
-            .all_names()
-            .skip_while(|item| !item.starts_with(&pattern))
-            .take_while(|item| item.starts_with(&pattern))
-            .collect::<Vec<_>>()
-            .join("\n");
-
-        it.into()
-    });
-
-    #[derive(Parser)]
-    #[command(name = "exec", about = "Execute a cfg file")]
-    struct Exec {
-        #[arg(required = true)]
-        cfgs: Vec<String>,
-    }
-
-    app.command(move |In(Exec { cfgs }), vfs: Res<Vfs>| {
-        let mut script = String::new();
-
-        for cfg in &cfgs {
-            let mut script_file = match vfs.open(&cfg) {
-                Ok(s) => s,
-                Err(e) => {
-                    return ExecResult {
-                        output: format!("Couldn't exec {}: {:?}", cfg, e).into(),
-                        ..default()
+                CdCommand::Stop => MixerEvent::StopMusic,
+                CdCommand::Pause => MixerEvent::PauseMusic,
+                CdCommand::Resume => MixerEvent::StartMusic(None),
+                CdCommand::Info => {
+                    return match music_player.playing() {
+                        Some((name, true)) => format!("Looping {}", name).into(),
+                        Some((name, false)) => format!("Playing {}", name).into(),
+                        None => "No track is playing".into(),
                     };
                 }
             };
 
-            // TODO: Error handling
-            script_file.read_to_string(&mut script).unwrap();
-            script.push('\n');
-        }
-
-        let script = match RunCmd::parse_many(&*script) {
-            Ok(commands) => commands,
-            Err(e) => {
-                return ExecResult {
-                    output: format!("Couldn't exec: {}", e).into(),
-                    ..default()
-                }
-            }
-        };
-
-        let extra_commands = Box::new(
-            script
-                .into_iter()
-                .map(RunCmd::into_owned)
-                .collect::<Vec<_>>()
-                .into_iter(),
-        );
-
-        ExecResult {
-            extra_commands,
-            ..default()
-        }
-    });
+            events.send(event);
+            default()
+        },
+    );
 
     #[derive(Parser)]
     #[command(name = "bf", about = "Flash the screen")]
@@ -433,17 +494,27 @@ pub fn register_commands(app: &mut App) {
     );
 
     #[derive(Parser)]
-    #[command(name = "name", about = "Set the player name")]
-    struct Name {
-        new_name: String,
+    #[command(
+        name = "color",
+        about = "Set the player's shirt and pants colors, or both to the same color"
+    )]
+    struct Color {
+        top: u8,
+        bottom: Option<u8>,
     }
 
     app.command(
-        move |In(Name { new_name }), mut registry: ResMut<Registry>| -> ExecResult {
-            match registry.set_cvar_raw("_cl_name", new_name.into()) {
-                Ok(_) => default(),
-                Err(e) => format!("Error: {}", e).into(),
+        move |In(Color { top, bottom }), mut registry: ResMut<Registry>| -> ExecResult {
+            let bottom = bottom.unwrap_or(top);
+
+            // colors 14 and 15 are fullbright, so they aren't allowed
+            for (cvar, color) in [("topcolor", top), ("bottomcolor", bottom)] {
+                if let Err(e) = registry.set_cvar_raw(cvar, u64::from(color.min(13)).into()) {
+                    return format!("Error: {}", e).into();
+                }
             }
+
+            default()
         },
     );
 }
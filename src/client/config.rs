@@ -0,0 +1,76 @@
+//! `config.cfg`, which keeps the key bindings and archived cvars between sessions.
+//!
+//! It's run at startup by `startup_commands`, before `autoexec.cfg`, or by the game's `quake.rc` if
+//! it has one. It's written again when the game exits.
+
+use std::io::Write as _;
+
+use bevy::prelude::*;
+use clap::Parser;
+
+use crate::{
+    client::input::game::GameInput,
+    common::{
+        console::{ExecResult, RegisterCmdExt, Registry},
+        vfs::Vfs,
+    },
+};
+
+const CONFIG_NAME: &str = "config.cfg";
+
+/// The contents of `config.cfg`: every key binding, replacing the defaults, then every archived
+/// cvar.
+pub fn config(registry: &Registry, game_input: &GameInput) -> String {
+    let mut out = String::from("unbindall\n");
+    game_input.write_bindings(&mut out).unwrap();
+    registry.write_archived_cvars(&mut out).unwrap();
+    out
+}
+
+fn write_config(
+    registry: &Registry,
+    game_input: &GameInput,
+    vfs: &Vfs,
+) -> Result<(), failure::Error> {
+    let mut file = vfs.write(CONFIG_NAME)?;
+    file.write_all(config(registry, game_input).as_bytes())?;
+    file.flush()?;
+    Ok(())
+}
+
+pub fn register_commands(app: &mut App) {
+    #[derive(Parser)]
+    #[command(
+        name = "writeconfig",
+        about = "Save the key bindings and archived cvars to config.cfg"
+    )]
+    struct WriteConfig;
+
+    app.command(
+        |In(WriteConfig),
+         registry: Res<Registry>,
+         game_input: Res<GameInput>,
+         vfs: Res<Vfs>|
+         -> ExecResult {
+            match write_config(&registry, &game_input, &vfs) {
+                Ok(()) => default(),
+                Err(e) => format!("Couldn't write {}: {}", CONFIG_NAME, e).into(),
+            }
+        },
+    );
+}
+
+pub mod systems {
+    use super::*;
+
+    /// Save the config as the game exits, whether from `quit` or from closing the window.
+    pub fn write_config_on_exit(
+        registry: Res<Registry>,
+        game_input: Res<GameInput>,
+        vfs: Res<Vfs>,
+    ) {
+        if let Err(e) = write_config(&registry, &game_input, &vfs) {
+            warn!("Couldn't write {}: {}", CONFIG_NAME, e);
+        }
+    }
+}
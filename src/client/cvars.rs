@@ -18,11 +18,46 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use bevy::app::App;
+use bevy::{
+    app::App,
+    ecs::system::{In, Res, ResMut},
+    log::warn,
+};
+use serde_lexpr::Value;
 
-use crate::common::console::{Cvar, RegisterCmdExt};
+use crate::common::console::{Cvar, RegisterCmdExt, Registry};
+
+use super::{player_color, Connection};
+
+/// Tell the server about a new name, if connected.
+fn send_name(In(value): In<Value>, conn: Option<ResMut<Connection>>) {
+    let (Some(mut conn), Some(name)) = (conn, value.as_name()) else {
+        return;
+    };
+
+    if let Err(e) = conn.send_string_cmd(format!("name \"{}\"", name)) {
+        warn!("Couldn't send name: {}", e);
+    }
+}
+
+/// Tell the server about new colors, if connected.
+fn send_color(In(_): In<Value>, registry: Res<Registry>, conn: Option<ResMut<Connection>>) {
+    let Some(mut conn) = conn else {
+        return;
+    };
+
+    let color = player_color(&registry);
+    if let Err(e) = conn.send_string_cmd(format!("color {} {}", color.top(), color.bottom())) {
+        warn!("Couldn't send colors: {}", e);
+    }
+}
 
 pub fn register_cvars(app: &mut App) {
+    app.cvar(
+        "cl_alwaysrun",
+        Cvar::new("0").archive(),
+        "1: move at run speed without holding +speed, which walks instead",
+    );
     app.cvar(
         "cl_anglespeedkey",
         "1.5",
@@ -48,11 +83,6 @@ pub fn register_cvars(app: &mut App) {
         "0.5",
         "adjusts how much your viewmodel & weapon bobs up when running",
     );
-    app.cvar(
-        "_cl_color",
-        Cvar::new("0").archive(),
-        "the client's colors, as set by the color command - use cl_color instead",
-    );
     app.cvar("cl_crossx", "0", "the x offset of the crosshair");
     app.cvar("cl_crossy", "0", "the y offset of the crosshair");
     app.cvar(
@@ -60,16 +90,16 @@ pub fn register_cvars(app: &mut App) {
         Cvar::new("400").archive(),
         "the base speed you move when pressing +forward",
     );
+    app.cvar(
+        "cl_lerp",
+        "1",
+        "smooth the movement of other entities between server updates, extrapolating briefly when one is late",
+    );
     app.cvar(
         "cl_movespeedkey",
         "2.0",
         "the speed multiplier when pressing the run key",
     );
-    app.cvar(
-        "_cl_name",
-        Cvar::new("player").archive(),
-        "the player's name - use the name command instead",
-    );
     app.cvar(
         "cl_nolerp",
         "0",
@@ -118,11 +148,40 @@ pub fn register_cvars(app: &mut App) {
     );
     app.cvar(
         "fov",
-        "90",
-        "sets the camera's field of view angle (in degrees)",
+        Cvar::new("90").archive(),
+        "sets the camera's field of view angle (in degrees), from 10 to 170",
     );
     // TODO: What is the difference between this and `cl_skipCrosshair`?
-    app.cvar("crosshair", "1", "Whether to draw the crosshair");
+    app.cvar(
+        "crosshair",
+        Cvar::new("1").archive(),
+        "the crosshair style: 0 for none, 1 for the classic '+', 2 for a cross, 3 for a cross with a gap, 4 for a dot and 5 for a gapped cross with a dot",
+    );
+    app.cvar(
+        "crosshaircolor",
+        Cvar::new("15").archive(),
+        "the palette index (0 to 254) to draw crosshair styles 2 and up in",
+    );
+    app.cvar(
+        "crosshairsize",
+        Cvar::new("1").archive(),
+        "how large to draw the crosshair, as a multiple of its normal size",
+    );
+    app.cvar(
+        "scr_colorblind",
+        Cvar::new("0").archive(),
+        "mark low health, armor and ammo with a symbol as well as red digits",
+    );
+    app.cvar(
+        "scr_hudcontrast",
+        Cvar::new("0").archive(),
+        "draw the status bar on a plain black background for higher contrast",
+    );
+    app.cvar(
+        "scr_sbarscale",
+        Cvar::new("2").archive(),
+        "how large to draw the status bar and the rest of the HUD, as a multiple of its original size",
+    );
     app.cvar(
         "m_pitch",
         Cvar::new("0.022").archive(),
@@ -138,6 +197,31 @@ pub fn register_cvars(app: &mut App) {
         Cvar::new("3").archive(),
         "sets the mouse sensitivity",
     );
+    app.cvar(
+        "m_forward",
+        Cvar::new("1").archive(),
+        "how far moving the mouse up and down moves you when not using mouse look",
+    );
+    app.cvar(
+        "m_side",
+        Cvar::new("0.8").archive(),
+        "how far moving the mouse left and right moves you while holding +strafe",
+    );
+    app.cvar(
+        "m_filter",
+        Cvar::new("0").archive(),
+        "1 to average the mouse movement over the last two frames, smoothing it at the cost of some lag",
+    );
+    app.cvar(
+        "m_rawinput",
+        Cvar::new("1").archive(),
+        "1 to read the mouse directly, skipping the system's pointer acceleration",
+    );
+    app.cvar(
+        "freelook",
+        Cvar::new("1").archive(),
+        "1 to always look up and down with the mouse, 0 to only do so while holding +mlook",
+    );
     app.cvar(
         "v_idlescale",
         "0",
@@ -149,6 +233,11 @@ pub fn register_cvars(app: &mut App) {
     app.cvar("v_iroll_level", "0.1", "");
     app.cvar("v_iyaw_cycle", "2", "");
     app.cvar("v_iyaw_level", "0.3", "");
+    app.cvar(
+        "v_flashscale",
+        Cvar::new("1").archive(),
+        "scales the full-screen flashes for damage and item pickups - 0 disables them",
+    );
     app.cvar(
         "v_kickpitch",
         "0.6",
@@ -169,5 +258,44 @@ pub fn register_cvars(app: &mut App) {
         "2",
         "sets the duration that center text remains on the screen",
     );
+    app.cvar(
+        "con_notifytime",
+        Cvar::new("3").archive(),
+        "how many seconds messages from the server stay at the top of the screen",
+    );
+    app.cvar(
+        "con_notifylines",
+        Cvar::new("4").archive(),
+        "how many messages from the server to show at the top of the screen at once",
+    );
     app.cvar("sv_gravity", "800", "sets the server's gravity");
+    app.cvar(
+        "zoom_fov",
+        Cvar::new("30").archive(),
+        "the field of view angle (in degrees) used while +zoom is held",
+    );
+    app.cvar(
+        "zoom_speed",
+        Cvar::new("8").archive(),
+        "how quickly the view zooms in and out - 0 to zoom instantly",
+    );
+    app.action("zoom", "zoom the view in to zoom_fov while held");
+    app.cvar_on_set(
+        "name",
+        Cvar::new("player").archive(),
+        send_name,
+        "the player's name",
+    );
+    app.cvar_on_set(
+        "topcolor",
+        Cvar::new("0").archive(),
+        send_color,
+        "the player's shirt color, from 0 to 13",
+    );
+    app.cvar_on_set(
+        "bottomcolor",
+        Cvar::new("0").archive(),
+        send_color,
+        "the player's pants color, from 0 to 13",
+    );
 }
@@ -1,18 +1,30 @@
-use std::{io, ops::Range};
+use std::{fmt, io, ops::Range};
 
-use crate::common::{
-    net::{self, NetError},
-    util::read_f32_3,
-    vfs::VirtualFile,
+use crate::{
+    client::{runtimer, Connection, ConnectionKind},
+    common::{
+        console::{ExecResult, RegisterCmdExt},
+        engine,
+        net::{self, ClientStat, EntityEffects, EntityState, ItemFlags, NetError, ServerCmd},
+        util::{self, read_f32_3},
+        vfs::{Vfs, VfsError, VirtualFile},
+    },
 };
 
 use arrayvec::ArrayVec;
-use bevy::log::warn;
+use bevy::prelude::*;
 use byteorder::{LittleEndian, ReadBytesExt};
-use cgmath::{Deg, Vector3};
+use cgmath::{Deg, InnerSpace as _, Vector3};
+use chrono::Duration;
+use clap::Parser;
+use hashbrown::HashMap;
 use io::BufReader;
 use thiserror::Error;
 
+/// Player movements longer than this between two updates are teleports or respawns, and aren't
+/// counted towards the route length.
+const MAX_ROUTE_STEP: f32 = 256.0;
+
 /// An error returned by a demo server.
 #[derive(Error, Debug)]
 pub enum DemoServerError {
@@ -28,6 +40,13 @@ pub enum DemoServerError {
     Net(#[from] NetError),
 }
 
+/// Open the demo `name`, which may leave off the `.dem` extension and may be in `demos/`.
+pub fn open_demo(vfs: &Vfs, name: &str) -> Result<VirtualFile, VfsError> {
+    let name = name.strip_suffix(".dem").unwrap_or(name);
+    vfs.open(format!("{}.dem", name))
+        .or_else(|_| vfs.open(format!("demos/{}.dem", name)))
+}
+
 #[derive(Clone)]
 struct DemoMessage {
     view_angles: Vector3<Deg<f32>>,
@@ -64,6 +83,15 @@ pub struct DemoServer {
 
     // all message data
     message_data: Vec<u8>,
+
+    // the playback time at the end of each message, counted from the start of the demo
+    message_times: Vec<Duration>,
+
+    paused: bool,
+    speed: f32,
+
+    // the number of messages that should have been played once the current seek is done
+    seek: Option<usize>,
 }
 
 impl DemoServer {
@@ -143,11 +171,17 @@ impl DemoServer {
             });
         }
 
+        let message_times = message_times(&messages, &message_data);
+
         Ok(DemoServer {
             track_override,
             message_id: 0,
             messages,
             message_data,
+            message_times,
+            paused: false,
+            speed: 1.0,
+            seek: None,
         })
     }
 
@@ -176,4 +210,447 @@ impl DemoServer {
     pub fn track_override(&self) -> Option<u32> {
         self.track_override
     }
+
+    /// The number of messages that have been played so far.
+    pub fn message_id(&self) -> usize {
+        self.message_id
+    }
+
+    /// Go back to the start of the demo.
+    pub fn rewind(&mut self) {
+        self.message_id = 0;
+    }
+
+    /// How far into the demo playback is.
+    pub fn position(&self) -> Duration {
+        self.message_id
+            .checked_sub(1)
+            .map(|id| self.message_times[id])
+            .unwrap_or_else(Duration::zero)
+    }
+
+    /// The playback time of the whole demo.
+    pub fn length(&self) -> Duration {
+        self.message_times
+            .last()
+            .copied()
+            .unwrap_or_else(Duration::zero)
+    }
+
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    pub fn speed(&self) -> f32 {
+        self.speed
+    }
+
+    pub fn set_speed(&mut self, speed: f32) {
+        self.speed = speed;
+    }
+
+    /// Scale the time that a frame took by the playback speed.
+    pub fn playback_time(&self, frame_time: Duration) -> Duration {
+        if self.paused {
+            return Duration::zero();
+        }
+
+        engine::duration_from_f32(engine::duration_to_f32(frame_time) * self.speed)
+    }
+
+    /// Skip to `target`, counted from the start of the demo. The client catches up on the next
+    /// frame by replaying every message up to there, from the start if it has to go backwards.
+    pub fn seek(&mut self, target: Duration) {
+        self.seek = Some(self.message_times.partition_point(|t| *t <= target));
+    }
+
+    /// Take the number of messages that should have been played once the pending seek is done.
+    pub fn take_seek(&mut self) -> Option<usize> {
+        self.seek.take()
+    }
+}
+
+/// Work out the playback time at the end of each message from the `Time` commands in it.
+///
+/// The server's clock starts over with every level, so the times of later levels carry on from
+/// the end of the ones before.
+fn message_times(messages: &[DemoMessage], message_data: &[u8]) -> Vec<Duration> {
+    let mut times = Vec::with_capacity(messages.len());
+    let mut elapsed = Duration::zero();
+    let mut level_base = Duration::zero();
+    let mut level_start = None;
+
+    for msg in messages {
+        let reader = &mut &message_data[msg.msg_range.clone()];
+
+        // a message that doesn't parse will stop playback anyway
+        while let Ok(Some(cmd)) = ServerCmd::deserialize(reader) {
+            match cmd {
+                ServerCmd::ServerInfo { .. } => {
+                    level_base = elapsed;
+                    level_start = None;
+                }
+                ServerCmd::Time { time } => {
+                    let time = engine::duration_from_f32(time);
+                    let start = *level_start.get_or_insert(time);
+                    elapsed = elapsed.max(level_base + (time - start));
+                }
+                _ => {}
+            }
+        }
+
+        times.push(elapsed);
+    }
+
+    times
+}
+
+/// Statistics for one level of a demo, gathered by [`analyze`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LevelStats {
+    pub map: String,
+
+    /// The time from the player's first update to the intermission.
+    pub time: Duration,
+
+    /// Whether the level reached intermission before the demo ended.
+    pub completed: bool,
+
+    pub kills: i32,
+    pub total_monsters: i32,
+    pub secrets: i32,
+    pub total_secrets: i32,
+    pub armor_taken: u32,
+    pub health_taken: u32,
+
+    /// The number of times each weapon was fired, keyed by the `ActiveWeapon` stat.
+    pub shots: Vec<(u8, u32)>,
+
+    /// How far the player moved, in world units.
+    pub route_length: f32,
+}
+
+impl LevelStats {
+    fn add_shot(&mut self, weapon: u8) {
+        match self.shots.iter_mut().find(|(w, _)| *w == weapon) {
+            Some((_, count)) => *count += 1,
+            None => self.shots.push((weapon, 1)),
+        }
+    }
+}
+
+/// The name of a weapon from the `ActiveWeapon` stat, which only has room for the low byte of
+/// its item flag.
+fn weapon_name(active_weapon: u8) -> &'static str {
+    const WEAPONS: &[(ItemFlags, &str)] = &[
+        (ItemFlags::SHOTGUN, "shotgun"),
+        (ItemFlags::SUPER_SHOTGUN, "super shotgun"),
+        (ItemFlags::NAILGUN, "nailgun"),
+        (ItemFlags::SUPER_NAILGUN, "super nailgun"),
+        (ItemFlags::GRENADE_LAUNCHER, "grenade launcher"),
+        (ItemFlags::ROCKET_LAUNCHER, "rocket launcher"),
+        (ItemFlags::LIGHTNING, "lightning gun"),
+        (ItemFlags::SUPER_LIGHTNING, "super lightning"),
+    ];
+
+    WEAPONS
+        .iter()
+        .find(|(flag, _)| flag.bits() == active_weapon as u32)
+        .map(|(_, name)| *name)
+        // the axe's flag doesn't fit in a byte
+        .unwrap_or("axe")
+}
+
+impl fmt::Display for LevelStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{}: {}{}",
+            self.map,
+            runtimer::format_time(self.time),
+            if self.completed {
+                ""
+            } else {
+                " (not finished)"
+            }
+        )?;
+        writeln!(
+            f,
+            "  kills {}/{}, secrets {}/{}",
+            self.kills, self.total_monsters, self.secrets, self.total_secrets
+        )?;
+        writeln!(
+            f,
+            "  damage taken {} health, {} armor",
+            self.health_taken, self.armor_taken
+        )?;
+
+        if !self.shots.is_empty() {
+            let shots = self
+                .shots
+                .iter()
+                .map(|(weapon, count)| format!("{} {}", weapon_name(*weapon), count))
+                .collect::<Vec<_>>();
+            writeln!(f, "  shots: {}", shots.join(", "))?;
+        }
+
+        write!(f, "  route {:.0} units", self.route_length)
+    }
+}
+
+/// The level being analyzed, along with what's needed to follow the player through it.
+struct LevelAnalysis {
+    stats: LevelStats,
+    view_ent: usize,
+    time: Duration,
+    start: Option<Duration>,
+    active_weapon: u8,
+    baselines: HashMap<usize, EntityState>,
+    last_origin: Option<Vector3<f32>>,
+}
+
+/// Read through a demo without playing it, gathering statistics for each level.
+pub fn analyze(demo: &mut DemoServer) -> Result<Vec<LevelStats>, NetError> {
+    let mut levels = Vec::new();
+    let mut level: Option<LevelAnalysis> = None;
+
+    while let Some(msg) = demo.next() {
+        let reader = &mut msg.message();
+
+        while let Some(cmd) = ServerCmd::deserialize(reader)? {
+            if let ServerCmd::ServerInfo { model_precache, .. } = &cmd {
+                levels.extend(level.take().map(|l| l.stats));
+
+                let map = model_precache.first().map(String::as_str).unwrap_or("");
+                level = Some(LevelAnalysis {
+                    stats: LevelStats {
+                        map: util::file_stem(map, ".bsp").to_owned(),
+                        ..Default::default()
+                    },
+                    view_ent: 0,
+                    time: Duration::zero(),
+                    start: None,
+                    active_weapon: 0,
+                    baselines: HashMap::new(),
+                    last_origin: None,
+                });
+                continue;
+            }
+
+            let Some(level) = level.as_mut().filter(|l| !l.stats.completed) else {
+                continue;
+            };
+            let stats = &mut level.stats;
+
+            match cmd {
+                ServerCmd::SetView { ent_id } => level.view_ent = ent_id.max(0) as usize,
+                ServerCmd::Time { time } => {
+                    level.time = engine::duration_from_f32(time);
+                    if let Some(start) = level.start {
+                        stats.time = level.time - start;
+                    }
+                }
+                ServerCmd::SpawnBaseline {
+                    ent_id,
+                    model_id,
+                    frame_id,
+                    colormap,
+                    skin_id,
+                    origin,
+                    angles,
+                } => {
+                    level.baselines.insert(
+                        ent_id as usize,
+                        EntityState {
+                            origin,
+                            angles,
+                            model_id: model_id as usize,
+                            frame_id: frame_id as usize,
+                            colormap,
+                            skin_id: skin_id as usize,
+                            effects: EntityEffects::empty(),
+                        },
+                    );
+                }
+                ServerCmd::FastUpdate(update) if update.ent_id as usize == level.view_ent => {
+                    // the client counts the level as started from the first entity update
+                    level.start.get_or_insert(level.time);
+
+                    let state = update.to_entity_state(
+                        level
+                            .baselines
+                            .entry(level.view_ent)
+                            .or_insert_with(EntityState::uninitialized),
+                    );
+                    if let Some(last) = level.last_origin {
+                        let step = (state.origin - last).magnitude();
+                        if step <= MAX_ROUTE_STEP {
+                            stats.route_length += step;
+                        }
+                    }
+                    level.last_origin = Some(state.origin);
+
+                    if state.effects.contains(EntityEffects::MUZZLE_FLASH) {
+                        stats.add_shot(level.active_weapon);
+                    }
+                }
+                ServerCmd::PlayerData(data) => level.active_weapon = data.active_weapon,
+                ServerCmd::UpdateStat { stat, value } => match stat {
+                    ClientStat::KilledMonsters => stats.kills = value,
+                    ClientStat::TotalMonsters => stats.total_monsters = value,
+                    ClientStat::FoundSecrets => stats.secrets = value,
+                    ClientStat::TotalSecrets => stats.total_secrets = value,
+                    ClientStat::ActiveWeapon => level.active_weapon = value as u8,
+                    _ => {}
+                },
+                ServerCmd::KilledMonster => stats.kills += 1,
+                ServerCmd::FoundSecret => stats.secrets += 1,
+                ServerCmd::Damage { armor, blood, .. } => {
+                    stats.armor_taken += armor as u32;
+                    stats.health_taken += blood as u32;
+                }
+                ServerCmd::Intermission | ServerCmd::Finale { .. } | ServerCmd::Cutscene { .. } => {
+                    stats.completed = true;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    levels.extend(level.map(|l| l.stats));
+
+    Ok(levels)
+}
+
+/// The demo that's playing, if any.
+fn playing_demo(conn: Option<&mut Connection>) -> Option<&mut DemoServer> {
+    match &mut conn?.kind {
+        ConnectionKind::Demo(demo) => Some(demo),
+        ConnectionKind::Server { .. } => None,
+    }
+}
+
+pub fn register_commands(app: &mut App) {
+    #[derive(Parser)]
+    #[command(
+        name = "demo_pause",
+        about = "Pause or unpause the demo that's playing"
+    )]
+    struct DemoPause;
+
+    app.command(
+        |In(DemoPause), mut conn: Option<ResMut<Connection>>| -> ExecResult {
+            let Some(demo) = playing_demo(conn.as_deref_mut()) else {
+                return "No demo is playing".into();
+            };
+
+            demo.set_paused(!demo.paused());
+            default()
+        },
+    );
+
+    #[derive(Parser)]
+    #[command(
+        name = "demo_seek",
+        about = "Skip to a time in the demo that's playing, in seconds from its start"
+    )]
+    struct DemoSeek {
+        seconds: f32,
+    }
+
+    app.command(
+        |In(DemoSeek { seconds }), mut conn: Option<ResMut<Connection>>| -> ExecResult {
+            let Some(demo) = playing_demo(conn.as_deref_mut()) else {
+                return "No demo is playing".into();
+            };
+
+            let target = engine::duration_from_f32(seconds.max(0.0)).min(demo.length());
+            demo.seek(target);
+            format!(
+                "Seeking to {} of {}",
+                runtimer::format_time(target),
+                runtimer::format_time(demo.length())
+            )
+            .into()
+        },
+    );
+
+    #[derive(Parser)]
+    #[command(
+        name = "demo_speed",
+        about = "Show or set the playback speed of demos, where 1 is normal speed"
+    )]
+    struct DemoSpeed {
+        factor: Option<f32>,
+    }
+
+    app.command(
+        |In(DemoSpeed { factor }), mut conn: Option<ResMut<Connection>>| -> ExecResult {
+            let Some(demo) = playing_demo(conn.as_deref_mut()) else {
+                return "No demo is playing".into();
+            };
+
+            match factor {
+                None => format!("demo_speed is {}", demo.speed()).into(),
+                Some(factor) if factor > 0.0 && factor.is_finite() => {
+                    demo.set_speed(factor);
+                    default()
+                }
+                Some(_) => "demo_speed must be above 0, use demo_pause to pause".into(),
+            }
+        },
+    );
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_weapon_name() {
+        assert_eq!(
+            weapon_name(ItemFlags::ROCKET_LAUNCHER.bits() as u8),
+            "rocket launcher"
+        );
+        assert_eq!(weapon_name(ItemFlags::AXE.bits() as u8), "axe");
+    }
+
+    #[test]
+    fn test_level_stats_shots() {
+        let mut stats = LevelStats::default();
+        stats.add_shot(ItemFlags::SHOTGUN.bits() as u8);
+        stats.add_shot(ItemFlags::NAILGUN.bits() as u8);
+        stats.add_shot(ItemFlags::SHOTGUN.bits() as u8);
+
+        assert_eq!(stats.shots, vec![(1, 2), (4, 1)]);
+    }
+
+    #[test]
+    fn test_message_times() {
+        let mut message_data = Vec::new();
+        let mut messages = Vec::new();
+        for time in [Some(2.0), None, Some(2.5), Some(3.25)] {
+            let start = message_data.len();
+            if let Some(time) = time {
+                ServerCmd::Time { time }
+                    .serialize(&mut message_data)
+                    .unwrap();
+            }
+            ServerCmd::NoOp.serialize(&mut message_data).unwrap();
+            messages.push(DemoMessage {
+                view_angles: Vector3::new(Deg(0.0), Deg(0.0), Deg(0.0)),
+                msg_range: start..message_data.len(),
+            });
+        }
+
+        let times = message_times(&messages, &message_data)
+            .into_iter()
+            .map(|t| t.num_milliseconds())
+            .collect::<Vec<_>>();
+        assert_eq!(times, vec![0, 0, 500, 1250]);
+    }
 }
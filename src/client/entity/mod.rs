@@ -37,18 +37,50 @@ pub const MAX_BEAMS: usize = 24;
 pub const MAX_TEMP_ENTITIES: usize = 1 << 7;
 pub const MAX_STATIC_ENTITIES: usize = 128;
 
-#[derive(Debug, Clone, Component)]
+#[derive(Debug, Clone, Default, Component)]
 pub struct DynamicEntity;
 
-#[derive(Debug, Clone, Component)]
+#[derive(Debug, Clone, Default, Component)]
 pub struct TempEntity;
 
-#[derive(Debug, Clone, Component)]
+#[derive(Debug, Clone, Default, Component)]
 pub struct StaticEntity;
 
-#[derive(Debug, Clone, Component)]
+#[derive(Debug, Clone, Default, Component)]
 pub struct ViewEntity;
 
+/// Identifies the client entity an ECS entity mirrors. For dynamic entities this is the entity
+/// number sent by the server; temporary and static entities are numbered in order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Component)]
+pub struct EntityId(pub usize);
+
+/// The interpolated position and orientation of an entity.
+#[derive(Debug, Clone, Copy, Component)]
+pub struct EntityTransform {
+    pub origin: Vector3<f32>,
+    pub angles: Vector3<Deg<f32>>,
+}
+
+/// The model an entity is drawn with, as an index into the model precache.
+#[derive(Debug, Clone, Copy, Component)]
+pub struct EntityModel {
+    pub model_id: usize,
+    pub frame_id: usize,
+    /// The frame before `frame_id`, which alias models are blended from.
+    pub prev_frame_id: usize,
+    /// When the entity changed to `frame_id`.
+    pub frame_time: Duration,
+    pub skin_id: usize,
+    pub colormap: Option<u8>,
+}
+
+#[derive(Debug, Clone, Copy, Component)]
+pub struct Effects(pub EntityEffects);
+
+/// The dynamic light attached to an entity, such as a muzzle flash or a glowing powerup.
+#[derive(Debug, Clone, Component)]
+pub struct LightEmitter(pub Light);
+
 #[derive(Debug, Clone)]
 pub struct ClientEntity {
     pub id: usize,
@@ -62,6 +94,8 @@ pub struct ClientEntity {
     pub model_id: usize,
     model_changed: bool,
     pub frame_id: usize,
+    pub prev_frame_id: usize,
+    pub frame_time: Duration,
     pub skin_id: usize,
     pub colormap: Option<u8>,
     pub sync_base: Duration,
@@ -104,6 +138,8 @@ impl ClientEntity {
             model_id: baseline.model_id,
             model_changed: false,
             frame_id: baseline.frame_id,
+            prev_frame_id: baseline.frame_id,
+            frame_time: Duration::zero(),
             skin_id: baseline.skin_id,
             colormap: None,
             sync_base: Duration::zero(),
@@ -128,6 +164,8 @@ impl ClientEntity {
             model_id: 0,
             model_changed: false,
             frame_id: 0,
+            prev_frame_id: 0,
+            frame_time: Duration::zero(),
             skin_id: 0,
             colormap: None,
             sync_base: Duration::zero(),
@@ -164,12 +202,18 @@ impl ClientEntity {
             self.model_id = new_state.model_id;
         }
 
-        self.frame_id = new_state.frame_id;
+        if self.frame_id != new_state.frame_id {
+            self.prev_frame_id = self.frame_id;
+            self.frame_time = msg_times[0];
+            self.frame_id = new_state.frame_id;
+        }
         self.skin_id = new_state.skin_id;
         self.effects = new_state.effects;
         self.colormap = update.colormap;
 
         if self.force_link {
+            // a new model's frames can't be blended with the old one's
+            self.prev_frame_id = self.frame_id;
             self.msg_origins[1] = self.msg_origins[0];
             self.origin = self.msg_origins[0];
             self.msg_angles[1] = self.msg_angles[0];
@@ -230,6 +274,13 @@ impl ClientEntity {
     }
 }
 
+/// The color of most dynamic lights.
+pub const LIGHT_WHITE: [f32; 3] = [1.0, 1.0, 1.0];
+pub const LIGHT_MUZZLE_FLASH: [f32; 3] = [1.0, 0.8, 0.5];
+pub const LIGHT_ROCKET: [f32; 3] = [1.0, 0.6, 0.2];
+pub const LIGHT_EXPLOSION: [f32; 3] = [1.0, 0.5, 0.2];
+pub const LIGHT_LIGHTNING: [f32; 3] = [0.5, 0.6, 1.0];
+
 /// A descriptor used to spawn dynamic lights.
 #[derive(Clone, Debug)]
 pub struct LightDesc {
@@ -247,6 +298,9 @@ pub struct LightDesc {
 
     /// Time-to-live of the light.
     pub ttl: Duration,
+
+    /// The linear RGB color of the light.
+    pub color: [f32; 3],
 }
 
 /// A dynamic point light.
@@ -258,6 +312,7 @@ pub struct Light {
     min_radius: Option<f32>,
     spawned: Duration,
     ttl: Duration,
+    color: [f32; 3],
 }
 
 impl Light {
@@ -270,6 +325,7 @@ impl Light {
             min_radius: desc.min_radius,
             spawned: time,
             ttl: desc.ttl,
+            color: desc.color,
         }
     }
 
@@ -278,6 +334,11 @@ impl Light {
         self.origin
     }
 
+    /// Return the linear RGB color of the light.
+    pub fn color(&self) -> [f32; 3] {
+        self.color
+    }
+
     /// Return the radius of the light for the given time.
     ///
     /// If the radius would decay to a negative value, returns 0.
@@ -375,4 +436,145 @@ pub struct Beam {
     pub expire: Duration,
     pub start: Vector3<f32>,
     pub end: Vector3<f32>,
+
+    /// The color of the light at the end of the beam, if it gives off light.
+    pub light_color: Option<[f32; 3]>,
+    pub light_id: Option<usize>,
+}
+
+impl From<&ClientEntity> for EntityTransform {
+    fn from(ent: &ClientEntity) -> Self {
+        EntityTransform {
+            origin: ent.origin,
+            angles: ent.angles,
+        }
+    }
+}
+
+impl From<&ClientEntity> for EntityModel {
+    fn from(ent: &ClientEntity) -> Self {
+        EntityModel {
+            model_id: ent.model_id,
+            frame_id: ent.frame_id,
+            prev_frame_id: ent.prev_frame_id,
+            frame_time: ent.frame_time,
+            skin_id: ent.skin_id,
+            colormap: ent.colormap,
+        }
+    }
+}
+
+pub mod systems {
+    use super::*;
+
+    use bevy::{
+        ecs::query::QueryFilter,
+        prelude::{Commands, Entity, Query, Res, With, Without},
+    };
+    use hashbrown::HashMap;
+
+    use crate::client::Connection;
+
+    type EntityComponents<'a> = (
+        Entity,
+        &'a EntityId,
+        &'a mut EntityTransform,
+        &'a mut EntityModel,
+        &'a mut Effects,
+        Option<&'a mut LightEmitter>,
+    );
+
+    /// Update the ECS entities matched by `query` to mirror `source`, spawning them with marker
+    /// `M` as client entities appear and despawning them as they disappear.
+    fn sync<'a, M, F>(
+        commands: &mut Commands,
+        query: &mut Query<EntityComponents, F>,
+        lights: &Lights,
+        source: impl Iterator<Item = (usize, &'a ClientEntity)>,
+    ) where
+        M: Component + Default,
+        F: QueryFilter,
+    {
+        let mut source: HashMap<usize, &ClientEntity> = source.collect();
+
+        for (entity, id, mut transform, mut model, mut effects, emitter) in query.iter_mut() {
+            let Some(ent) = source.remove(&id.0) else {
+                commands.entity(entity).despawn();
+                continue;
+            };
+
+            *transform = EntityTransform::from(ent);
+            *model = EntityModel::from(ent);
+            effects.0 = ent.effects;
+
+            match (emitter, ent.light_id.and_then(|id| lights.get(id))) {
+                (Some(mut emitter), Some(light)) => emitter.0 = light.clone(),
+                (None, Some(light)) => {
+                    commands.entity(entity).insert(LightEmitter(light.clone()));
+                }
+                (Some(_), None) => {
+                    commands.entity(entity).remove::<LightEmitter>();
+                }
+                (None, None) => {}
+            }
+        }
+
+        for (id, ent) in source {
+            let mut entity = commands.spawn((
+                M::default(),
+                EntityId(id),
+                EntityTransform::from(ent),
+                EntityModel::from(ent),
+                Effects(ent.effects),
+            ));
+
+            if let Some(light) = ent.light_id.and_then(|id| lights.get(id)) {
+                entity.insert(LightEmitter(light.clone()));
+            }
+        }
+    }
+
+    /// Mirror the client's visible entities into the ECS.
+    ///
+    /// `ClientState` is still the authority on entities, as it's what the network code updates,
+    /// but everything downstream of it (such as the renderer) reads the components instead.
+    pub fn sync_entities(
+        mut commands: Commands,
+        conn: Option<Res<Connection>>,
+        mut dynamic: Query<EntityComponents, (With<DynamicEntity>, Without<TempEntity>)>,
+        mut temp: Query<EntityComponents, (With<TempEntity>, Without<StaticEntity>)>,
+        mut statics: Query<EntityComponents, (With<StaticEntity>, Without<DynamicEntity>)>,
+    ) {
+        let Some(conn) = conn else {
+            for (entity, ..) in dynamic.iter().chain(temp.iter()).chain(statics.iter()) {
+                commands.entity(entity).despawn();
+            }
+            return;
+        };
+
+        let state = &conn.state;
+        let lights = &state.lights;
+
+        sync::<DynamicEntity, _>(
+            &mut commands,
+            &mut dynamic,
+            lights,
+            state
+                .visible_entity_ids
+                .iter()
+                .map(|&id| (id, &state.entities[id])),
+        );
+        sync::<TempEntity, _>(
+            &mut commands,
+            &mut temp,
+            lights,
+            state.temp_entities.iter().enumerate(),
+        );
+        sync::<StaticEntity, _>(
+            &mut commands,
+            &mut statics,
+            lights,
+            state.static_entities.iter().enumerate(),
+        );
+    }
 }
@@ -0,0 +1,132 @@
+//! Distance fog (`fog`).
+//!
+//! Maps set their fog with the `fog` key of `worldspawn`, in the same `<density> <r> <g> <b>`
+//! form as the `fog` command, which changes it until the next map is loaded. As in FitzQuake the
+//! fog is exponential squared, and a density of around 0.05 is a light haze while 1 hides
+//! everything a few dozen units away.
+
+use bevy::prelude::*;
+use clap::Parser;
+use failure::{bail, Error};
+
+use crate::common::console::{ExecResult, RegisterCmdExt};
+
+use super::Connection;
+
+/// The fog density is divided by this, so that densities are the same as in FitzQuake.
+const DENSITY_SCALE: f32 = 64.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Fog {
+    pub density: f32,
+
+    /// The color of the fog, from 0 to 1.
+    pub color: [f32; 3],
+}
+
+impl Default for Fog {
+    fn default() -> Self {
+        Fog {
+            density: 0.0,
+            color: [0.3; 3],
+        }
+    }
+}
+
+impl Fog {
+    /// Parse the value of the `fog` worldspawn key.
+    pub fn parse(value: &str) -> Result<Fog, Error> {
+        let values = value
+            .split_whitespace()
+            .map(|v| {
+                v.parse::<f32>()
+                    .map_err(|_| failure::format_err!("invalid number \"{}\"", v))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        match values[..] {
+            [density, r, g, b] => Ok(Fog {
+                density,
+                color: [r, g, b],
+            }),
+            _ => bail!("expected \"<density> <r> <g> <b>\", found \"{}\"", value),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.density > 0.0
+    }
+
+    /// The density and color as they're given to the shaders.
+    pub fn uniform(&self) -> [f32; 4] {
+        if !self.is_enabled() {
+            return [0.0; 4];
+        }
+
+        let [r, g, b] = self.color.map(|c| c.clamp(0.0, 1.0));
+        [r, g, b, self.density / DENSITY_SCALE]
+    }
+}
+
+pub fn register_commands(app: &mut App) {
+    #[derive(Parser)]
+    #[command(
+        name = "fog",
+        about = "Show or set the fog, as <density>, <r> <g> <b> or <density> <r> <g> <b>"
+    )]
+    struct FogCmd {
+        values: Vec<f32>,
+    }
+
+    app.command(
+        |In(FogCmd { values }), conn: Option<ResMut<Connection>>| -> ExecResult {
+            let Some(mut conn) = conn else {
+                return "fog needs a map to be loaded".into();
+            };
+
+            let fog = &mut conn.state.fog;
+            match values[..] {
+                [] => {
+                    return format!(
+                        "density {} color {} {} {}",
+                        fog.density, fog.color[0], fog.color[1], fog.color[2]
+                    )
+                    .into()
+                }
+                [density] => fog.density = density.max(0.0),
+                [r, g, b] => fog.color = [r, g, b],
+                [density, r, g, b] => {
+                    fog.density = density.max(0.0);
+                    fog.color = [r, g, b];
+                }
+                _ => return "usage: fog [density] [r g b]".into(),
+            }
+
+            default()
+        },
+    );
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_fog() {
+        assert_eq!(
+            Fog::parse("0.05 0.3 0.25 0.2").unwrap(),
+            Fog {
+                density: 0.05,
+                color: [0.3, 0.25, 0.2],
+            }
+        );
+        assert!(Fog::parse("0.05").is_err());
+        assert!(Fog::parse("thick 0 0 0").is_err());
+
+        assert_eq!(Fog::default().uniform(), [0.0; 4]);
+        assert_eq!(
+            Fog::parse("64 2 0 0").unwrap().uniform(),
+            [1.0, 0.0, 0.0, 1.0]
+        );
+    }
+}
@@ -0,0 +1,409 @@
+//! Racing a recorded run (`ghost`).
+//!
+//! A ghost is the path the player took in a demo, with one track for each level in it. While one
+//! of those levels is being played, the ghost is drawn as a translucent player model wherever the
+//! demo's player was at the same time into the level. The HUD shows how far ahead or behind the
+//! live player is, compared to when the ghost passed the nearest point on its route.
+
+use bevy::{prelude::*, render::extract_resource::ExtractResource};
+use cgmath::{Deg, InnerSpace as _, Vector3, VectorSpace as _};
+use chrono::Duration;
+use clap::Parser;
+use hashbrown::HashMap;
+
+use crate::common::{
+    console::{Cvar, ExecResult, RegisterCmdExt, Registry},
+    engine,
+    net::{NetError, ServerCmd},
+    util,
+    vfs::Vfs,
+};
+
+use super::{
+    demo::{self, DemoServer},
+    Connection, ConnectionState,
+};
+
+/// The model the ghost is drawn with. It's in the precache of every level.
+const GHOST_MODEL: &str = "progs/player.mdl";
+
+/// How far along its route the ghost is searched for the point nearest the player.
+const MATCH_WINDOW: i64 = 10;
+
+/// How close the player needs to be to the ghost's route to be compared against it.
+const MATCH_DISTANCE: f32 = 256.0;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct GhostSample {
+    /// The time since the level began.
+    time: Duration,
+    origin: Vector3<f32>,
+    angles: Vector3<Deg<f32>>,
+    frame_id: usize,
+}
+
+#[derive(Clone, Debug)]
+struct GhostTrack {
+    map: String,
+    samples: Vec<GhostSample>,
+}
+
+impl GhostTrack {
+    /// Where the ghost was at `time`, interpolating its position between samples.
+    fn sample_at(&self, time: Duration) -> Option<GhostSample> {
+        let next = self.samples.partition_point(|s| s.time <= time);
+        let prev = self.samples.get(next.checked_sub(1)?)?;
+        let Some(next) = self.samples.get(next) else {
+            return Some(*prev);
+        };
+
+        let span = engine::duration_to_f32(next.time - prev.time);
+        let t = engine::duration_to_f32(time - prev.time) / span;
+        Some(GhostSample {
+            time,
+            origin: prev.origin.lerp(next.origin, t),
+            ..*prev
+        })
+    }
+
+    /// Find the sample nearest to `origin`, starting from `from` and looking `MATCH_WINDOW`
+    /// seconds ahead, so that a route which crosses itself doesn't jump between laps.
+    fn nearest(&self, from: usize, origin: Vector3<f32>) -> Option<usize> {
+        let start = self.samples.get(from)?.time;
+        let end = start + Duration::try_seconds(MATCH_WINDOW).unwrap();
+
+        self.samples[from..]
+            .iter()
+            .take_while(|s| s.time <= end)
+            .enumerate()
+            .map(|(i, s)| (from + i, (s.origin - origin).magnitude2()))
+            .filter(|(_, dist2)| *dist2 <= MATCH_DISTANCE * MATCH_DISTANCE)
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(i, _)| i)
+    }
+}
+
+/// The level being read from a demo.
+struct LevelReader {
+    map: String,
+    view_ent: usize,
+    time: Duration,
+    start: Option<Duration>,
+    finished: bool,
+    baselines: HashMap<usize, GhostSample>,
+    samples: Vec<GhostSample>,
+}
+
+impl LevelReader {
+    fn new(map: String) -> LevelReader {
+        LevelReader {
+            map,
+            view_ent: 0,
+            time: Duration::zero(),
+            start: None,
+            finished: false,
+            baselines: HashMap::new(),
+            samples: Vec::new(),
+        }
+    }
+
+    fn into_track(self) -> Option<GhostTrack> {
+        (!self.samples.is_empty()).then_some(GhostTrack {
+            map: self.map,
+            samples: self.samples,
+        })
+    }
+}
+
+/// Read the player's path through each level in a demo.
+fn read_tracks(demo: &mut DemoServer) -> Result<Vec<GhostTrack>, NetError> {
+    let mut tracks = Vec::new();
+    let mut level: Option<LevelReader> = None;
+
+    while let Some(msg) = demo.next() {
+        let reader = &mut msg.message();
+
+        while let Some(cmd) = ServerCmd::deserialize(reader)? {
+            if let ServerCmd::ServerInfo { model_precache, .. } = &cmd {
+                tracks.extend(level.take().and_then(LevelReader::into_track));
+
+                let map = model_precache.first().map(String::as_str).unwrap_or("");
+                level = Some(LevelReader::new(util::file_stem(map, ".bsp").to_owned()));
+                continue;
+            }
+
+            let Some(level) = level.as_mut().filter(|l| !l.finished) else {
+                continue;
+            };
+
+            match cmd {
+                ServerCmd::SetView { ent_id } => level.view_ent = ent_id.max(0) as usize,
+                ServerCmd::Time { time } => level.time = engine::duration_from_f32(time),
+                ServerCmd::SpawnBaseline {
+                    ent_id,
+                    frame_id,
+                    origin,
+                    angles,
+                    ..
+                } => {
+                    level.baselines.insert(
+                        ent_id as usize,
+                        GhostSample {
+                            time: Duration::zero(),
+                            origin,
+                            angles,
+                            frame_id: frame_id as usize,
+                        },
+                    );
+                }
+                ServerCmd::FastUpdate(update) if update.ent_id as usize == level.view_ent => {
+                    // the client counts the level as started from the first entity update
+                    let start = *level.start.get_or_insert(level.time);
+                    let time = level.time - start;
+                    if level.samples.last().is_some_and(|s| s.time >= time) {
+                        continue;
+                    }
+
+                    // fields which aren't sent are the same as the baseline
+                    let base =
+                        level
+                            .baselines
+                            .get(&level.view_ent)
+                            .copied()
+                            .unwrap_or(GhostSample {
+                                time,
+                                origin: Vector3::new(0.0, 0.0, 0.0),
+                                angles: Vector3::new(Deg(0.0), Deg(0.0), Deg(0.0)),
+                                frame_id: 0,
+                            });
+                    level.samples.push(GhostSample {
+                        time,
+                        origin: Vector3::new(
+                            update.origin_x.unwrap_or(base.origin.x),
+                            update.origin_y.unwrap_or(base.origin.y),
+                            update.origin_z.unwrap_or(base.origin.z),
+                        ),
+                        angles: Vector3::new(
+                            update.pitch.unwrap_or(base.angles.x),
+                            update.yaw.unwrap_or(base.angles.y),
+                            update.roll.unwrap_or(base.angles.z),
+                        ),
+                        frame_id: update.frame_id.map_or(base.frame_id, |f| f as usize),
+                    });
+                }
+                ServerCmd::Intermission | ServerCmd::Finale { .. } | ServerCmd::Cutscene { .. } => {
+                    level.finished = true;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    tracks.extend(level.and_then(LevelReader::into_track));
+
+    Ok(tracks)
+}
+
+/// The demo being raced, if any.
+#[derive(Resource, Default)]
+pub struct Ghost {
+    name: String,
+    tracks: Vec<GhostTrack>,
+
+    /// The track for the current level, when the level began and the sample the player was last
+    /// matched to.
+    current: Option<(usize, Duration, usize)>,
+}
+
+/// What the renderer needs to know about the ghost this frame.
+#[derive(Resource, ExtractResource, Clone, Default)]
+pub struct GhostView {
+    /// The ghost's position, drawn with the given model.
+    pub entity: Option<GhostEntity>,
+
+    /// How far the player is behind the ghost at the nearest point on its route. Negative if the
+    /// player is ahead.
+    pub delta: Option<Duration>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct GhostEntity {
+    pub origin: Vector3<f32>,
+    pub angles: Vector3<Deg<f32>>,
+    pub model_id: usize,
+    pub frame_id: usize,
+    pub alpha: f32,
+}
+
+pub fn register_cvars(app: &mut App) {
+    app.cvar(
+        "cl_ghost_alpha",
+        Cvar::new("0.4").archive(),
+        "how opaque the ghost loaded with the ghost command is, from 0 to 1",
+    );
+}
+
+pub fn register_commands(app: &mut App) {
+    #[derive(Parser)]
+    #[command(
+        name = "ghost",
+        about = "Race the player from a demo, or show which demo is being raced"
+    )]
+    struct GhostCmd {
+        demo: Option<String>,
+    }
+
+    app.command(
+        |In(GhostCmd { demo: name }), vfs: Res<Vfs>, mut ghost: ResMut<Ghost>| -> ExecResult {
+            let Some(name) = name else {
+                return if ghost.tracks.is_empty() {
+                    "No ghost loaded".into()
+                } else {
+                    format!("Racing {}", ghost.name).into()
+                };
+            };
+
+            let mut file = match demo::open_demo(&vfs, &name) {
+                Ok(f) => f,
+                Err(e) => return format!("Couldn't open {}: {}", name, e).into(),
+            };
+            let tracks = match DemoServer::new(&mut file) {
+                Ok(mut server) => read_tracks(&mut server),
+                Err(e) => return format!("Couldn't read {}: {}", name, e).into(),
+            };
+
+            match tracks {
+                Ok(tracks) if tracks.is_empty() => format!("{} has no player to race", name).into(),
+                Ok(tracks) => {
+                    let maps = tracks
+                        .iter()
+                        .map(|t| t.map.as_str())
+                        .collect::<Vec<_>>()
+                        .join(" ");
+                    *ghost = Ghost {
+                        name: name.clone(),
+                        tracks,
+                        current: None,
+                    };
+                    format!("Racing {} ({})", name, maps).into()
+                }
+                Err(e) => format!("Couldn't read {}: {}", name, e).into(),
+            }
+        },
+    );
+
+    #[derive(Parser)]
+    #[command(name = "ghost_stop", about = "Stop racing the ghost")]
+    struct GhostStop;
+
+    app.command(|In(GhostStop), mut ghost: ResMut<Ghost>| -> ExecResult {
+        *ghost = default();
+        default()
+    });
+}
+
+pub mod systems {
+    use super::*;
+
+    pub fn update_ghost(
+        registry: Res<Registry>,
+        conn: Option<Res<Connection>>,
+        conn_state: Res<ConnectionState>,
+        mut ghost: ResMut<Ghost>,
+        mut view: ResMut<GhostView>,
+    ) {
+        let conn = conn.filter(|_| matches!(&*conn_state, ConnectionState::Connected(_)));
+        let (Some(conn), false) = (conn, ghost.tracks.is_empty()) else {
+            if view.entity.is_some() || view.delta.is_some() {
+                *view = default();
+            }
+            return;
+        };
+        let state = &conn.state;
+
+        let Some(track_id) = ghost.tracks.iter().position(|t| t.map == state.map_name) else {
+            *view = default();
+            return;
+        };
+
+        // start matching from the beginning whenever the level is entered
+        let matched = match ghost.current {
+            Some((id, start, matched)) if id == track_id && start == state.start_time => matched,
+            _ => 0,
+        };
+
+        let track = &ghost.tracks[track_id];
+        let time = state.completion_time.unwrap_or(state.time) - state.start_time;
+
+        let nearest = state
+            .entities
+            .get(state.view_entity_id())
+            .and_then(|ent| track.nearest(matched, ent.origin));
+        let delta = nearest.map(|i| time - track.samples[i].time).or(view.delta);
+        let alpha = registry.read_cvar::<f32>("cl_ghost_alpha").unwrap_or(0.4);
+
+        view.entity = state
+            .model_names
+            .get(GHOST_MODEL)
+            .zip(track.sample_at(time))
+            .filter(|_| alpha > 0.0)
+            .map(|(&model_id, sample)| GhostEntity {
+                origin: sample.origin,
+                angles: sample.angles,
+                model_id,
+                frame_id: sample.frame_id,
+                alpha: alpha.min(1.0),
+            });
+        view.delta = delta;
+        ghost.current = Some((track_id, state.start_time, nearest.unwrap_or(matched)));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sample(secs: i64, x: f32) -> GhostSample {
+        GhostSample {
+            time: Duration::try_seconds(secs).unwrap(),
+            origin: Vector3::new(x, 0.0, 0.0),
+            angles: Vector3::new(Deg(0.0), Deg(0.0), Deg(0.0)),
+            frame_id: 0,
+        }
+    }
+
+    fn track() -> GhostTrack {
+        GhostTrack {
+            map: "e1m1".into(),
+            samples: vec![
+                sample(0, 0.0),
+                sample(1, 100.0),
+                sample(2, 200.0),
+                sample(20, 0.0),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_sample_at() {
+        let track = track();
+        let halfway = track
+            .sample_at(Duration::try_milliseconds(1500).unwrap())
+            .unwrap();
+        assert_eq!(halfway.origin, Vector3::new(150.0, 0.0, 0.0));
+
+        // the ghost waits at the end of its route
+        let end = track.sample_at(Duration::try_seconds(30).unwrap()).unwrap();
+        assert_eq!(end.origin, Vector3::new(0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_nearest_stays_on_route() {
+        let track = track();
+
+        // the route comes back to the start, but not within the match window
+        assert_eq!(track.nearest(0, Vector3::new(10.0, 0.0, 0.0)), Some(0));
+        assert_eq!(track.nearest(1, Vector3::new(10.0, 0.0, 0.0)), Some(1));
+        assert_eq!(track.nearest(0, Vector3::new(0.0, 1000.0, 0.0)), None);
+    }
+}
@@ -1,34 +1,67 @@
-use crate::{client, common::console::RegisterCmdExt};
+use crate::{
+    client,
+    common::console::{Cvar, RegisterCmdExt},
+};
 
 use bevy::prelude::*;
 use clap::Parser;
+use strum::IntoEnumIterator as _;
 
-use super::game::GameInput;
+use super::game::{Action, GameInput};
 
 pub fn register_commands(app: &mut App) {
+    app.cvar(
+        "in_grab",
+        Cvar::new("1").archive(),
+        "grab and hide the mouse cursor while playing - set to 0 to leave it free",
+    );
+
+    for action in Action::iter() {
+        app.action(action.to_string(), action.help());
+    }
+
     #[derive(Parser)]
     #[command(name = "bind", about = "Attach a command to a key")]
     struct Bind {
+        #[arg(allow_hyphen_values = true)]
         from: String,
-        to: Option<String>,
+        // `bind k impulse 1` works as well as `bind k "impulse 1"`
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        to: Vec<String>,
     }
 
-    app.command(
-        |In(Bind { from, to }), mut game_input: ResMut<GameInput>| match to {
-            None => match game_input.binding(&from[..]) {
-                Ok(Some(t)) => format!("\"{}\" = \"{}\"", from.to_string(), t.to_string()).into(),
+    app.command(|In(Bind { from, to }), mut game_input: ResMut<GameInput>| {
+        if to.is_empty() {
+            return match game_input.binding(&from[..]) {
+                Ok(Some(t)) => format!("\"{}\" = \"{}\"", from, t.command_text()).into(),
                 _ => format!("\"{}\" is not bound", from).into(),
-            },
-            // bind (key) [command]
-            Some(to) => match game_input.bind(&from[..], &to[..]) {
-                Ok(_) => {
-                    debug!("Bound {:?} to {:?}", from, to);
-                    default()
-                }
-                Err(e) => format!("Bind failed: {}", e).into(),
-            },
-        },
-    );
+            };
+        }
+
+        // bind (key) [command]
+        let to = to.join(" ");
+        match game_input.bind(&from[..], &to[..]) {
+            Ok(_) => {
+                debug!("Bound {:?} to {:?}", from, to);
+                default()
+            }
+            Err(e) => format!("Bind failed: {}", e).into(),
+        }
+    });
+
+    #[derive(Parser)]
+    #[command(name = "unbind", about = "Remove the binding from a key")]
+    struct Unbind {
+        key: String,
+    }
+
+    app.command(|In(Unbind { key }), mut game_input: ResMut<GameInput>| {
+        match game_input.unbind(&key[..]) {
+            Ok(Some(_)) => default(),
+            Ok(None) => format!("\"{}\" is not bound", key).into(),
+            Err(e) => format!("Unbind failed: {}", e).into(),
+        }
+    });
 
     #[derive(Parser)]
     #[command(name = "unbindall", about = "Delete all keybindings")]
@@ -40,6 +73,21 @@ pub fn register_commands(app: &mut App) {
         default()
     });
 
+    #[derive(Parser)]
+    #[command(name = "bindlist", about = "List every keybinding")]
+    struct BindList;
+
+    app.command(|In(BindList), game_input: Res<GameInput>| {
+        let bindings = game_input.sorted_bindings();
+        let mut out = String::new();
+        for (input, binding) in &bindings {
+            out.push_str(&format!("{:<16} \"{}\"\n", input, binding));
+        }
+        out.push_str(&format!("{} binding(s)", bindings.len()));
+
+        out.into()
+    });
+
     #[derive(Parser)]
     #[command(name = "impulse", about = "Apply various effects depending on number")]
     /// Apply various effects depending on number:
@@ -15,14 +15,24 @@
 // DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 
-use std::{fmt::Display, hash::Hash, ops::Not, str::FromStr};
+use std::{
+    fmt::{self, Display},
+    hash::Hash,
+    iter,
+    ops::Not,
+    str::FromStr,
+};
 
-use crate::common::{console::RunCmd, parse};
+use crate::common::{
+    console::{quote, quote_if_needed, RunCmd},
+    parse,
+};
 
 use bevy::{
-    input::{keyboard::Key, prelude::*},
+    input::{keyboard::Key, prelude::*, ButtonState},
     prelude::*,
 };
+use bitflags::bitflags;
 use failure::{bail, format_err, Error};
 use hashbrown::HashMap;
 use lazy_static::lazy_static;
@@ -80,11 +90,15 @@ lazy_static! {
     static ref KEYMAP: HashMap<UppercaseStr<'static>, AnyInput> = KEYBOARD_NAMES
         .into_iter()
         .chain(MOUSE_NAMES)
+        .chain(WHEEL_NAMES)
+        .chain(GAMEPAD_NAMES)
         .map(|(n, i)| (UppercaseStr(n), i.clone()))
         .collect();
     static ref INVERSE_KEYMAP: HashMap<AnyInput, UppercaseStr<'static>> = KEYBOARD_NAMES
         .into_iter()
         .chain(MOUSE_NAMES)
+        .chain(WHEEL_NAMES)
+        .chain(GAMEPAD_NAMES)
         .map(|(n, i)| (i.clone(), UppercaseStr(n)))
         .collect();
 }
@@ -120,6 +134,18 @@ macro_rules! mouse {
     }
 }
 
+macro_rules! gamepad {
+    ($($inner:tt)*) => {
+        buttons!((AnyInput::Gamepad, GamepadButtonType) $($inner)*)
+    }
+}
+
+macro_rules! wheel {
+    ($($inner:tt)*) => {
+        buttons!((AnyInput::Wheel, WheelDirection) $($inner)*)
+    }
+}
+
 const KEYBOARD_NAMES: &[(&str, AnyInput)] = &keys![
     ",",
     ".",
@@ -134,6 +160,7 @@ const KEYBOARD_NAMES: &[(&str, AnyInput)] = &keys![
     "7",
     "8",
     "9",
+    "'",
     "A",
     ("ALT", Alt),
     "B",
@@ -206,10 +233,31 @@ const MOUSE_NAMES: &[(&str, AnyInput)] = &mouse![
     ("MOUSE1", Left),
     ("MOUSE2", Right),
     ("MOUSE3", Middle),
-    // TODO: "MWHEELDOWN"
-    // TODO: "MWHEELUP"
+    ("MOUSE4", Back),
+    ("MOUSE5", Forward),
+];
+
+const GAMEPAD_NAMES: &[(&str, AnyInput)] = &gamepad![
+    ("ABUTTON", South),
+    ("BBUTTON", East),
+    ("XBUTTON", West),
+    ("YBUTTON", North),
+    ("LSHOULDER", LeftTrigger),
+    ("RSHOULDER", RightTrigger),
+    ("LTRIGGER", LeftTrigger2),
+    ("RTRIGGER", RightTrigger2),
+    ("LTHUMB", LeftThumb),
+    ("RTHUMB", RightThumb),
+    ("BACK", Select),
+    ("START", Start),
+    ("DPAD_UP", DPadUp),
+    ("DPAD_DOWN", DPadDown),
+    ("DPAD_LEFT", DPadLeft),
+    ("DPAD_RIGHT", DPadRight),
 ];
 
+const WHEEL_NAMES: &[(&str, AnyInput)] = &wheel![("MWHEELUP", Up), ("MWHEELDOWN", Down),];
+
 /// A unique identifier for an in-game action.
 #[derive(Clone, Copy, Debug, Eq, PartialEq, EnumIter)]
 pub enum Action {
@@ -253,6 +301,33 @@ pub enum Action {
     ShowTeamScores = 18,
 }
 
+impl Action {
+    /// A short description of the action, for the console's help output.
+    pub fn help(&self) -> &'static str {
+        match *self {
+            Action::Forward => "move forward",
+            Action::Back => "move backward",
+            Action::MoveLeft => "strafe left",
+            Action::MoveRight => "strafe right",
+            Action::MoveUp => "move up (when swimming)",
+            Action::MoveDown => "move down (when swimming)",
+            Action::LookUp => "look up",
+            Action::LookDown => "look down",
+            Action::Left => "look left",
+            Action::Right => "look right",
+            Action::Speed => "change move speed (walk/run)",
+            Action::Jump => "jump",
+            Action::Strafe => "interpret +left/+right like +moveleft/+moveright",
+            Action::Attack => "attack with the current weapon",
+            Action::Use => "interact with an object (not used)",
+            Action::KLook => "interpret +forward/+back like +lookup/+lookdown",
+            Action::MLook => "look up and down with the mouse",
+            Action::ShowScores => "show the level stats or scoreboard",
+            Action::ShowTeamScores => "show the team scoreboard",
+        }
+    }
+}
+
 impl FromStr for Action {
     type Err = Error;
 
@@ -316,11 +391,33 @@ impl ToString for Action {
     }
 }
 
+/// A notch of the mouse wheel, which can be bound like a button that's pressed and released at
+/// once.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum WheelDirection {
+    Up,
+    Down,
+}
+
+impl WheelDirection {
+    /// The direction of a wheel movement, or `None` if it was purely horizontal.
+    pub fn of(y: f32) -> Option<Self> {
+        if y > 0.0 {
+            Some(Self::Up)
+        } else if y < 0.0 {
+            Some(Self::Down)
+        } else {
+            None
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum AnyInput {
     Mouse(MouseButton),
+    Wheel(WheelDirection),
     Keyboard(Key),
-    Gamepad(GamepadButton),
+    Gamepad(GamepadButtonType),
 }
 
 impl AnyInput {
@@ -358,6 +455,11 @@ impl AnyInput {
     pub fn char(char: &str) -> Self {
         Self::Keyboard(Key::Character(char.into()))
     }
+
+    /// Returns `true` if this input has a name which can be used with `bind`.
+    pub fn has_name(&self) -> bool {
+        INVERSE_KEYMAP.contains_key(self)
+    }
 }
 
 impl From<Key> for AnyInput {
@@ -380,6 +482,21 @@ impl From<MouseButton> for AnyInput {
     }
 }
 
+impl From<bevy::input::mouse::MouseButton> for AnyInput {
+    fn from(value: bevy::input::mouse::MouseButton) -> Self {
+        use bevy::input::mouse::MouseButton as BevyMouseButton;
+
+        Self::Mouse(match value {
+            BevyMouseButton::Left => MouseButton::Left,
+            BevyMouseButton::Right => MouseButton::Right,
+            BevyMouseButton::Middle => MouseButton::Middle,
+            BevyMouseButton::Back => MouseButton::Back,
+            BevyMouseButton::Forward => MouseButton::Forward,
+            BevyMouseButton::Other(n) => MouseButton::Other(n),
+        })
+    }
+}
+
 impl FromStr for AnyInput {
     type Err = Error;
 
@@ -410,6 +527,126 @@ impl Display for AnyInput {
     }
 }
 
+bitflags! {
+    /// The modifier keys which can be combined with another input in a binding, e.g. `CTRL+K`.
+    #[derive(Default, Debug, Copy, Clone, PartialEq, Eq, Hash)]
+    pub struct Modifiers: u8 {
+        const CTRL  = 0b001;
+        const SHIFT = 0b010;
+        const ALT   = 0b100;
+    }
+}
+
+impl Modifiers {
+    const NAMES: [(&'static str, Modifiers); 3] = [
+        ("CTRL", Modifiers::CTRL),
+        ("SHIFT", Modifiers::SHIFT),
+        ("ALT", Modifiers::ALT),
+    ];
+
+    /// Read the modifiers which are currently held down.
+    pub fn pressed(keys: &ButtonInput<KeyCode>) -> Self {
+        let mut out = Modifiers::empty();
+        out.set(
+            Modifiers::CTRL,
+            keys.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]),
+        );
+        out.set(
+            Modifiers::SHIFT,
+            keys.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]),
+        );
+        out.set(
+            Modifiers::ALT,
+            keys.any_pressed([KeyCode::AltLeft, KeyCode::AltRight]),
+        );
+        out
+    }
+
+    /// The modifier that this input represents, if any.
+    fn of(input: &AnyInput) -> Self {
+        match input {
+            AnyInput::Keyboard(Key::Control) => Modifiers::CTRL,
+            AnyInput::Keyboard(Key::Shift) => Modifiers::SHIFT,
+            AnyInput::Keyboard(Key::Alt) => Modifiers::ALT,
+            _ => Modifiers::empty(),
+        }
+    }
+
+    fn parse_name(name: &str) -> Option<Self> {
+        Self::NAMES
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, m)| *m)
+    }
+}
+
+/// An input along with the modifiers that must be held for it to trigger, e.g. `CTRL+SHIFT+K`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct KeyChord {
+    pub modifiers: Modifiers,
+    pub input: AnyInput,
+}
+
+impl From<AnyInput> for KeyChord {
+    fn from(input: AnyInput) -> Self {
+        Self {
+            modifiers: Modifiers::empty(),
+            input,
+        }
+    }
+}
+
+impl From<Key> for KeyChord {
+    fn from(value: Key) -> Self {
+        AnyInput::from(value).into()
+    }
+}
+
+impl FromStr for KeyChord {
+    type Err = Error;
+
+    fn from_str(src: &str) -> Result<Self, Error> {
+        let mut modifiers = Modifiers::empty();
+        let mut rest = src;
+
+        // The key itself may be `+`, so only split off prefixes that are actually modifier names.
+        while let Some((prefix, suffix)) = rest.split_once('+') {
+            match Modifiers::parse_name(prefix) {
+                Some(modifier) if !suffix.is_empty() => {
+                    modifiers |= modifier;
+                    rest = suffix;
+                }
+                _ => break,
+            }
+        }
+
+        Ok(Self {
+            modifiers,
+            input: rest.parse()?,
+        })
+    }
+}
+
+impl TryInto<KeyChord> for &'_ str {
+    type Error = <KeyChord as FromStr>::Err;
+
+    fn try_into(self) -> Result<KeyChord, Self::Error> {
+        self.parse()
+    }
+}
+
+impl Display for KeyChord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (name, modifier) in Modifiers::NAMES {
+            if self.modifiers.contains(modifier) {
+                write!(f, "{}+", name)?;
+            }
+        }
+
+        write!(f, "{}", self.input)
+    }
+}
+
 /// Whether to trigger an action on pressing or releasing a key
 #[derive(Default, Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub enum Trigger {
@@ -463,13 +700,47 @@ pub struct Binding<'a> {
     pub valid: BindingValidState,
 }
 
-impl Binding<'_> {
+impl<'a> Binding<'a> {
     pub fn into_owned(self) -> Binding<'static> {
         Binding {
             commands: self.commands.into_iter().map(RunCmd::into_owned).collect(),
             valid: self.valid,
         }
     }
+
+    /// The commands to run when the bound input is pressed or released: pressing runs every
+    /// command, releasing runs `-name` for each `+name`.
+    ///
+    /// `GameInput::bind` refuses `-name` commands, so they never run.
+    pub fn commands_for(&self, state: ButtonState) -> impl Iterator<Item = RunCmd<'a>> + '_ {
+        self.commands
+            .iter()
+            .filter_map(move |cmd| match (cmd.0.trigger, state) {
+                (Some(Trigger::Positive) | None, ButtonState::Pressed) => Some(cmd.clone()),
+                (Some(Trigger::Positive) | None, ButtonState::Released) => cmd.clone().invert(),
+                (Some(Trigger::Negative), _) => None,
+            })
+    }
+
+    /// The commands as they're written after the key in `bind`.
+    ///
+    /// The whole binding is one quoted argument, so the arguments of its commands can't be
+    /// quoted as they are by `RunCmd`'s `Display`.
+    pub fn command_text(&self) -> String {
+        let commands = self
+            .commands
+            .iter()
+            .map(|RunCmd(name, args)| {
+                iter::once(name.to_string())
+                    .chain(args.iter().map(|arg| quote_if_needed(arg).into_owned()))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+            .collect::<Vec<_>>()
+            .join("; ");
+
+        format!("{}{}", self.valid, commands)
+    }
 }
 
 impl FromStr for Binding<'static> {
@@ -506,7 +777,7 @@ impl Display for Binding<'_> {
 
 #[derive(Debug, Clone, Resource)]
 pub struct GameInput {
-    pub bindings: HashMap<AnyInput, Binding<'static>>,
+    pub bindings: HashMap<KeyChord, Binding<'static>>,
     pub mouse_delta: (f64, f64),
 }
 
@@ -556,12 +827,17 @@ impl GameInput {
         self.bind("7", "impulse 7").unwrap();
         self.bind("8", "impulse 8").unwrap();
         self.bind("9", "impulse 9").unwrap();
+        self.bind("ABUTTON", "+jump").unwrap();
+        self.bind("RTRIGGER", "+attack").unwrap();
+        self.bind("LSHOULDER", "impulse 12").unwrap();
+        self.bind("RSHOULDER", "impulse 10").unwrap();
+        self.bind("START", "togglemenu").unwrap();
     }
 
     /// Bind a `BindInput` to a `BindTarget`.
     pub fn bind<I, T>(&mut self, input: I, target: T) -> Result<Option<Binding<'static>>, Error>
     where
-        I: TryInto<AnyInput>,
+        I: TryInto<KeyChord>,
         T: AsRef<str>,
         I::Error: Display,
     {
@@ -569,6 +845,15 @@ impl GameInput {
             .as_ref()
             .parse()
             .map_err(|e| format_err!("Failed to parse target: {}", e))?;
+        // releasing the input runs `-name` for each `+name`, so binding `-name` itself would
+        // leave the action with nothing to start it
+        if let Some(cmd) = target
+            .commands
+            .iter()
+            .find(|cmd| cmd.0.trigger == Some(Trigger::Negative))
+        {
+            bail!("Can't bind {}, bind +{} instead", cmd, cmd.0.name);
+        }
         let input = input
             .try_into()
             .map_err(|e| format_err!("Failed to parse input: {}", e))?;
@@ -576,10 +861,57 @@ impl GameInput {
         Ok(self.bindings.insert(input, target))
     }
 
+    /// Remove the binding for `input`, returning the old binding if there was one.
+    pub fn unbind<I>(&mut self, input: I) -> Result<Option<Binding<'static>>, Error>
+    where
+        I: TryInto<KeyChord>,
+        I::Error: Display,
+    {
+        let input = input
+            .try_into()
+            .map_err(|e| format_err!("Failed to parse input: {}", e))?;
+
+        Ok(self.bindings.remove(&input))
+    }
+
+    /// Every binding as its input's name and its command text, sorted by input.
+    pub fn sorted_bindings(&self) -> Vec<(String, String)> {
+        let mut bindings = self
+            .bindings
+            .iter()
+            .map(|(input, binding)| (input.to_string(), binding.command_text()))
+            .collect::<Vec<_>>();
+        bindings.sort();
+        bindings
+    }
+
+    /// Write every binding as a `bind` command, sorted by input, the way they're saved in
+    /// `config.cfg`.
+    pub fn write_bindings<W: fmt::Write>(&self, out: &mut W) -> fmt::Result {
+        for (input, binding) in self.sorted_bindings() {
+            writeln!(out, "bind {} {}", quote(&input), quote(&binding))?;
+        }
+
+        Ok(())
+    }
+
+    /// Return every input which is bound to exactly `command` and nothing else.
+    pub fn bound_inputs<'a>(
+        &'a self,
+        command: &'a RunCmd<'_>,
+    ) -> impl Iterator<Item = &'a KeyChord> {
+        self.bindings
+            .iter()
+            .filter(move |(_, binding)| {
+                matches!(&binding.commands[..], [cmd] if cmd.to_string() == command.to_string())
+            })
+            .map(|(input, _)| input)
+    }
+
     /// Return the `BindTarget` that `input` is bound to, or `None` if `input` is not present.
     pub fn binding<I>(&self, input: I) -> Result<Option<&Binding<'static>>, Error>
     where
-        I: TryInto<AnyInput>,
+        I: TryInto<KeyChord>,
         I::Error: Display,
     {
         Ok(self.bindings.get(
@@ -588,6 +920,42 @@ impl GameInput {
                 .map_err(|e| format_err!("Failed to parse input: {}", e))?,
         ))
     }
+
+    /// Find the most specific binding for `input` given the modifiers currently held.
+    ///
+    /// A binding which uses more of the held modifiers wins, so with `CTRL+K` and `K` both bound,
+    /// pressing ctrl+k only triggers the former. Modifiers which aren't part of any binding are
+    /// ignored, so holding shift while pressing `K` still triggers `K`'s binding.
+    pub fn resolve(
+        &self,
+        input: AnyInput,
+        held: Modifiers,
+    ) -> Option<(KeyChord, &Binding<'static>)> {
+        // A modifier key never modifies itself
+        let held = held - Modifiers::of(&input);
+
+        let mut best: Option<(KeyChord, &Binding<'static>)> = None;
+        for bits in 0..=held.bits() {
+            let modifiers = Modifiers::from_bits_truncate(bits);
+            if !held.contains(modifiers)
+                || best.as_ref().is_some_and(|(chord, _)| {
+                    chord.modifiers.bits().count_ones() >= modifiers.bits().count_ones()
+                })
+            {
+                continue;
+            }
+
+            let chord = KeyChord {
+                modifiers,
+                input: input.clone(),
+            };
+            if let Some(binding) = self.bindings.get(&chord) {
+                best = Some((chord, binding));
+            }
+        }
+
+        best
+    }
 }
 
 #[cfg(test)]
@@ -611,4 +979,105 @@ mod test {
 
         assert_eq!(target.to_string(), "+forward");
     }
+
+    #[test]
+    fn test_parse_key_chord() {
+        let chord: KeyChord = "ctrl+shift+k".parse().unwrap();
+        assert_eq!(chord.modifiers, Modifiers::CTRL | Modifiers::SHIFT);
+        assert_eq!(chord.input, AnyInput::char("K"));
+        assert_eq!(chord.to_string(), "CTRL+SHIFT+K");
+
+        let plus: KeyChord = "ctrl++".parse().unwrap();
+        assert_eq!(plus.modifiers, Modifiers::CTRL);
+        assert_eq!(plus.input, AnyInput::char("+"));
+    }
+
+    #[test]
+    fn test_write_bindings() {
+        let mut input = GameInput {
+            bindings: default(),
+            mouse_delta: default(),
+        };
+        input.bind("W", "+forward").unwrap();
+        input.bind("1", "impulse 1; echo one").unwrap();
+        input.bind("T", r#"say "hello there""#).unwrap();
+
+        let mut out = String::new();
+        input.write_bindings(&mut out).unwrap();
+        assert_eq!(
+            out,
+            concat!(
+                "bind \"1\" \"impulse 1; echo one\"\n",
+                "bind \"T\" \"say \\\"hello there\\\"\"\n",
+                "bind \"W\" \"+forward\"\n",
+            )
+        );
+
+        // reading config.cfg back gives the same binding
+        let line = out.lines().nth(1).unwrap();
+        let RunCmd(_, args) = RunCmd::parse(line).unwrap();
+        assert_eq!(&args[..], ["T", r#"say "hello there""#]);
+    }
+
+    #[test]
+    fn test_resolve_most_specific() {
+        let mut input = GameInput {
+            bindings: default(),
+            mouse_delta: default(),
+        };
+        input.bind("K", "echo k").unwrap();
+        input.bind("CTRL+K", "echo ctrl").unwrap();
+
+        let k = AnyInput::char("K");
+        let (chord, _) = input.resolve(k.clone(), Modifiers::empty()).unwrap();
+        assert_eq!(chord.modifiers, Modifiers::empty());
+        let (chord, _) = input
+            .resolve(k.clone(), Modifiers::CTRL | Modifiers::SHIFT)
+            .unwrap();
+        assert_eq!(chord.modifiers, Modifiers::CTRL);
+        let (chord, _) = input.resolve(k, Modifiers::SHIFT).unwrap();
+        assert_eq!(chord.modifiers, Modifiers::empty());
+    }
+
+    #[test]
+    fn test_parse_mouse_names() {
+        let wheel: KeyChord = "shift+mwheelup".parse().unwrap();
+        assert_eq!(wheel.input, AnyInput::Wheel(WheelDirection::Up));
+        assert_eq!(wheel.to_string(), "SHIFT+MWHEELUP");
+
+        let mouse4: AnyInput = "MOUSE4".parse().unwrap();
+        assert_eq!(mouse4, AnyInput::Mouse(MouseButton::Back));
+        assert_eq!(WheelDirection::of(-1.0), Some(WheelDirection::Down));
+        assert_eq!(WheelDirection::of(0.0), None);
+
+        let trigger: AnyInput = "rtrigger".parse().unwrap();
+        assert_eq!(trigger, AnyInput::Gamepad(GamepadButtonType::RightTrigger2));
+        assert_eq!(trigger.to_string(), "RTRIGGER");
+    }
+
+    #[test]
+    fn test_binding_commands_for() {
+        let binding: Binding = "+attack; impulse 1".parse().unwrap();
+
+        let pressed = binding
+            .commands_for(ButtonState::Pressed)
+            .map(|cmd| cmd.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(pressed, ["+attack", "impulse \"1\""]);
+
+        let released = binding
+            .commands_for(ButtonState::Released)
+            .map(|cmd| cmd.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(released, ["-attack"]);
+    }
+
+    #[test]
+    fn test_bind_rejects_negative_edge() {
+        let mut input = GameInput::new();
+        assert!(input.bind("x", "-attack").is_err());
+        assert!(input.bind("x", "impulse 1; -attack").is_err());
+        assert!(input.binding("x").unwrap().is_none());
+        assert!(input.bind("x", "+attack").is_ok());
+    }
 }
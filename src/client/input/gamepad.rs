@@ -0,0 +1,237 @@
+//! Gamepad sticks and buttons.
+//!
+//! The buttons are bound like keys (`bind RTRIGGER +attack`), while the sticks move and turn the
+//! player directly, through [`GamepadSticks`].
+
+use bevy::{input::ButtonState, prelude::*, window::PrimaryWindow};
+use serde::Deserialize;
+
+use crate::{
+    client::menu::Menu,
+    common::console::{Cvar, RegisterCmdExt, Registry, RunCmd},
+};
+
+use super::{
+    game::{AnyInput, GameInput, KeyChord},
+    systems::{bind_captured, window_is_focused},
+    InputFocus,
+};
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+struct JoyVars {
+    #[serde(rename(deserialize = "joy_sensitivity"))]
+    sensitivity: f32,
+    #[serde(rename(deserialize = "joy_deadzone"))]
+    deadzone: f32,
+    #[serde(rename(deserialize = "joy_exponent"))]
+    exponent: f32,
+    #[serde(rename(deserialize = "joy_invert"))]
+    invert: f32,
+}
+
+/// The positions of the gamepad sticks this frame, after the deadzone and response curve, from -1
+/// to 1 on each axis with up and right positive.
+///
+/// `look` is also scaled by `joy_sensitivity` and flipped by `joy_invert`.
+#[derive(Resource, Default, Clone, Copy, Debug, PartialEq)]
+pub struct GamepadSticks {
+    pub movement: Vec2,
+    pub look: Vec2,
+}
+
+pub fn register_cvars(app: &mut App) {
+    app.cvar(
+        "joy_sensitivity",
+        Cvar::new("2").archive(),
+        "how fast the right stick turns the view, as a multiple of cl_yawspeed and cl_pitchspeed",
+    );
+    app.cvar(
+        "joy_deadzone",
+        Cvar::new("0.2").archive(),
+        "how far a stick has to be pushed, from 0 to 1, before it does anything",
+    );
+    app.cvar(
+        "joy_exponent",
+        Cvar::new("2").archive(),
+        "the response curve of the sticks - 1 is linear, higher values give finer control near the centre",
+    );
+    app.cvar(
+        "joy_invert",
+        Cvar::new("0").archive(),
+        "1 to invert looking up and down with the right stick",
+    );
+}
+
+/// Apply a radial deadzone to `stick`, rescaling what's left so that it still reaches 1, then
+/// raise its length to `exponent`.
+pub fn response_curve(stick: Vec2, deadzone: f32, exponent: f32) -> Vec2 {
+    let length = stick.length();
+    if length <= deadzone || deadzone >= 1.0 {
+        return Vec2::ZERO;
+    }
+
+    let scaled = ((length.min(1.0) - deadzone) / (1.0 - deadzone)).powf(exponent.max(0.1));
+    stick * (scaled / length)
+}
+
+/// Gamepad buttons are bound the same whichever gamepad they're on.
+fn button_input(button: &GamepadButton) -> AnyInput {
+    AnyInput::Gamepad(button.button_type)
+}
+
+pub mod systems {
+    use super::*;
+
+    /// Read the sticks of whichever gamepad is being pushed furthest, or let go of them if the
+    /// game doesn't have focus.
+    pub fn update_sticks(
+        registry: Res<Registry>,
+        focus: Res<InputFocus>,
+        windows: Query<&Window, With<PrimaryWindow>>,
+        gamepads: Res<Gamepads>,
+        axes: Res<Axis<GamepadAxis>>,
+        mut sticks: ResMut<GamepadSticks>,
+    ) {
+        let playing = *focus == InputFocus::Game && window_is_focused(windows);
+        let Some(vars) = registry.read_cvars::<JoyVars>().filter(|_| playing) else {
+            if *sticks != GamepadSticks::default() {
+                *sticks = GamepadSticks::default();
+            }
+            return;
+        };
+
+        let stick = |gamepad, x, y| {
+            Vec2::new(
+                axes.get(GamepadAxis::new(gamepad, x)).unwrap_or(0.0),
+                axes.get(GamepadAxis::new(gamepad, y)).unwrap_or(0.0),
+            )
+        };
+        let furthest = |a: Vec2, b: Vec2| {
+            if b.length_squared() > a.length_squared() {
+                b
+            } else {
+                a
+            }
+        };
+
+        let (movement, look) =
+            gamepads
+                .iter()
+                .fold((Vec2::ZERO, Vec2::ZERO), |(movement, look), gamepad| {
+                    (
+                        furthest(
+                            movement,
+                            stick(
+                                gamepad,
+                                GamepadAxisType::LeftStickX,
+                                GamepadAxisType::LeftStickY,
+                            ),
+                        ),
+                        furthest(
+                            look,
+                            stick(
+                                gamepad,
+                                GamepadAxisType::RightStickX,
+                                GamepadAxisType::RightStickY,
+                            ),
+                        ),
+                    )
+                });
+
+        let mut look = response_curve(look, vars.deadzone, vars.exponent) * vars.sensitivity;
+        if vars.invert != 0.0 {
+            look.y = -look.y;
+        }
+
+        let new = GamepadSticks {
+            movement: response_curve(movement, vars.deadzone, vars.exponent),
+            look,
+        };
+        if *sticks != new {
+            *sticks = new;
+        }
+    }
+
+    /// Run the commands bound to the gamepad buttons pressed and released this frame.
+    pub fn game_buttons(
+        buttons: Res<ButtonInput<GamepadButton>>,
+        input: Res<GameInput>,
+        mut run_cmds: EventWriter<RunCmd<'static>>,
+    ) {
+        let pressed = buttons
+            .get_just_pressed()
+            .map(|button| (button, ButtonState::Pressed));
+        let released = buttons
+            .get_just_released()
+            .map(|button| (button, ButtonState::Released));
+
+        for (button, state) in pressed.chain(released) {
+            if let Some(binding) = input.bindings.get(&KeyChord::from(button_input(button))) {
+                run_cmds.send_batch(binding.commands_for(state));
+            }
+        }
+    }
+
+    /// Move around the menu with the d-pad, choose with A and go back with B.
+    pub fn menu_buttons(
+        buttons: Res<ButtonInput<GamepadButton>>,
+        mut commands: Commands,
+        mut run_cmds: EventWriter<RunCmd<'static>>,
+        mut menu: ResMut<Menu>,
+    ) {
+        for button in buttons.get_just_pressed() {
+            if menu.capturing_binding().is_some() {
+                bind_captured(&mut menu, button_input(button).into(), &mut run_cmds);
+                continue;
+            }
+
+            match button.button_type {
+                GamepadButtonType::DPadUp => {
+                    menu.prev().expect("TODO: Handle menu failures");
+                }
+                GamepadButtonType::DPadDown => {
+                    menu.next().expect("TODO: Handle menu failures");
+                }
+                GamepadButtonType::DPadLeft => {
+                    let func = menu.left().expect("TODO: Handle menu failures");
+                    func(commands.reborrow());
+                }
+                GamepadButtonType::DPadRight => {
+                    let func = menu.right().expect("TODO: Handle menu failures");
+                    func(commands.reborrow());
+                }
+                GamepadButtonType::South => {
+                    let func = menu.activate().expect("TODO: Handle menu failures");
+                    func(commands.reborrow());
+                }
+                GamepadButtonType::East | GamepadButtonType::Start => {
+                    if menu.at_root() {
+                        run_cmds.send("togglemenu".into());
+                    } else {
+                        menu.back().expect("TODO: Handle menu failures");
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_response_curve() {
+        assert_eq!(response_curve(Vec2::new(0.1, 0.0), 0.2, 1.0), Vec2::ZERO);
+        assert_eq!(
+            response_curve(Vec2::new(0.0, 1.0), 0.2, 2.0),
+            Vec2::new(0.0, 1.0)
+        );
+
+        let half = response_curve(Vec2::new(0.6, 0.0), 0.2, 1.0);
+        assert!((half.x - 0.5).abs() < 1e-6);
+        let curved = response_curve(Vec2::new(0.6, 0.0), 0.2, 2.0);
+        assert!((curved.x - 0.25).abs() < 1e-6);
+    }
+}
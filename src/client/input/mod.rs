@@ -18,13 +18,24 @@
 pub mod commands;
 pub mod console;
 pub mod game;
+pub mod gamepad;
+pub mod rumble;
+pub mod touch;
 
 use bevy::{
     ecs::system::Resource, input::keyboard::KeyboardInput, prelude::*,
-    render::extract_resource::ExtractResource,
+    render::extract_resource::ExtractResource, window::PrimaryWindow,
 };
 
-use self::{game::GameInput, systems::InputEventReader};
+use crate::client::menu::Menu;
+
+use self::{
+    game::GameInput,
+    gamepad::GamepadSticks,
+    rumble::Rumble,
+    systems::{CursorGrabState, InputEventReader},
+    touch::TouchControls,
+};
 
 pub struct SeismonInputPlugin;
 
@@ -33,11 +44,48 @@ impl Plugin for SeismonInputPlugin {
         app.init_resource::<InputFocus>()
             .init_resource::<GameInput>()
             .init_resource::<InputEventReader<KeyboardInput>>()
+            .init_resource::<CursorGrabState>()
+            .init_resource::<TouchControls>()
+            .init_resource::<GamepadSticks>()
+            .add_event::<Rumble>()
+            .add_systems(
+                Update,
+                (
+                    rumble::apply_rumble.run_if(resource_exists::<Gamepads>),
+                    gamepad::systems::update_sticks.run_if(resource_exists::<Gamepads>),
+                    touch::touch_input.run_if(resource_exists::<Touches>),
+                    systems::update_menu_bindings
+                        .run_if(resource_exists::<Menu>.and_then(resource_changed::<GameInput>)),
+                    systems::update_menu_cvars.run_if(resource_exists::<Menu>),
+                    systems::update_menu_save_slots.run_if(
+                        resource_exists::<Menu>
+                            .and_then(resource_exists_and_equals(InputFocus::Menu))
+                            .and_then(resource_changed::<Menu>),
+                    ),
+                ),
+            )
             .add_systems(
                 Update,
                 (
-                    systems::game_input
+                    systems::update_cursor_grab,
+                    systems::update_ime.run_if(resource_changed::<InputFocus>),
+                )
+                    .run_if(any_with_component::<PrimaryWindow>),
+            )
+            .add_systems(
+                Update,
+                (
+                    (systems::game_input, systems::mouse_motion)
                         .run_if(resource_exists_and_equals::<InputFocus>(InputFocus::Game)),
+                    gamepad::systems::game_buttons.run_if(
+                        resource_exists::<Gamepads>
+                            .and_then(resource_exists_and_equals(InputFocus::Game)),
+                    ),
+                    gamepad::systems::menu_buttons.run_if(
+                        resource_exists::<Gamepads>
+                            .and_then(resource_exists::<Menu>)
+                            .and_then(resource_exists_and_equals(InputFocus::Menu)),
+                    ),
                     systems::console_input.run_if(resource_exists_and_equals::<InputFocus>(
                         InputFocus::Console,
                     )),
@@ -48,6 +96,9 @@ impl Plugin for SeismonInputPlugin {
             );
 
         commands::register_commands(app);
+        gamepad::register_cvars(app);
+        rumble::register_cvars(app);
+        touch::register_cvars(app);
     }
 }
 
@@ -62,18 +113,37 @@ pub enum InputFocus {
 pub mod systems {
     use bevy::{
         ecs::event::ManualEventReader,
-        input::{keyboard::KeyboardInput, ButtonState},
+        input::{
+            keyboard::{Key, KeyboardInput},
+            mouse::{MouseButtonInput, MouseMotion, MouseWheel},
+            ButtonState,
+        },
         prelude::*,
-        window::PrimaryWindow,
+        window::{CursorGrabMode, Ime, PrimaryWindow, WindowFocused},
     };
     use chrono::TimeDelta;
+    use hashbrown::HashMap;
+
+    use std::io::BufReader;
 
     use crate::{
-        client::menu::Menu,
-        common::console::{to_terminal_key, ConsoleInput, ConsoleOutput, Registry, RunCmd},
+        client::menu::{layout, Item, Menu},
+        common::{
+            console::{
+                to_quake_char, to_terminal_key, ConsoleInput, ConsoleInputEvent, ConsoleOutput,
+                Registry, RenderConsoleOutput, RunCmd,
+            },
+            savegame::{slot_filename, SaveHeader},
+            vfs::Vfs,
+        },
     };
 
-    use super::game::{AnyInput, Binding, BindingValidState, GameInput, Trigger};
+    use super::{
+        game::{
+            AnyInput, Binding, BindingValidState, GameInput, KeyChord, Modifiers, WheelDirection,
+        },
+        InputFocus,
+    };
 
     pub fn window_is_focused(windows: Query<&Window, With<PrimaryWindow>>) -> bool {
         let Ok(window) = windows.get_single() else {
@@ -86,6 +156,74 @@ pub mod systems {
         true
     }
 
+    /// Tracks whether the cursor grab has been released because the window lost focus.
+    ///
+    /// When the window regains focus we don't immediately grab the cursor again, as the user may
+    /// have alt-tabbed back in order to click something in the window - instead we wait for a click.
+    #[derive(Resource, Default)]
+    pub struct CursorGrabState {
+        suspended: bool,
+    }
+
+    pub fn update_cursor_grab(
+        focus: Res<InputFocus>,
+        registry: Res<Registry>,
+        mouse_buttons: Res<ButtonInput<MouseButton>>,
+        mut focus_events: EventReader<WindowFocused>,
+        mut grab_state: ResMut<CursorGrabState>,
+        mut windows: Query<&mut Window, With<PrimaryWindow>>,
+    ) {
+        let Ok(mut window) = windows.get_single_mut() else {
+            return;
+        };
+
+        for event in focus_events.read() {
+            if !event.focused {
+                grab_state.suspended = true;
+            }
+        }
+
+        if focus.is_changed() || mouse_buttons.get_just_pressed().next().is_some() {
+            grab_state.suspended = false;
+        }
+
+        let in_grab = registry.read_cvar::<u8>("in_grab").unwrap_or(1) != 0;
+        let grab = *focus == InputFocus::Game && in_grab && window.focused && !grab_state.suspended;
+
+        let (grab_mode, visible) = if grab {
+            // macOS only supports locking the cursor, while Windows and X11 only support confining it
+            if cfg!(target_os = "macos") {
+                (CursorGrabMode::Locked, false)
+            } else {
+                (CursorGrabMode::Confined, false)
+            }
+        } else {
+            (CursorGrabMode::None, true)
+        };
+
+        // only touch the window if something changed, as any change is sent to winit
+        if window.cursor.grab_mode != grab_mode || window.cursor.visible != visible {
+            window.cursor.grab_mode = grab_mode;
+            window.cursor.visible = visible;
+        }
+    }
+
+    /// Only accept IME input while the console has focus, so that typing in-game doesn't pop up
+    /// the system's candidate window.
+    pub fn update_ime(
+        focus: Res<InputFocus>,
+        mut windows: Query<&mut Window, With<PrimaryWindow>>,
+    ) {
+        let Ok(mut window) = windows.get_single_mut() else {
+            return;
+        };
+
+        let ime_enabled = *focus == InputFocus::Console;
+        if window.ime_enabled != ime_enabled {
+            window.ime_enabled = ime_enabled;
+        }
+    }
+
     #[derive(Resource)]
     pub struct InputEventReader<E: Event> {
         reader: ManualEventReader<E>,
@@ -100,39 +238,138 @@ pub mod systems {
     pub fn game_input(
         mut reader: ResMut<InputEventReader<KeyboardInput>>,
         keyboard_events: Res<Events<KeyboardInput>>,
+        button_state: Res<ButtonInput<KeyCode>>,
+        mut mouse_events: EventReader<MouseButtonInput>,
+        mut wheel: EventReader<MouseWheel>,
         mut run_cmds: EventWriter<RunCmd<'static>>,
         input: Res<GameInput>,
+        mut held_chords: Local<HashMap<AnyInput, KeyChord>>,
     ) {
+        let modifiers = Modifiers::pressed(&button_state);
+
         for key in reader.reader.read(&keyboard_events) {
+            let any_input = AnyInput::from(key.logical_key.clone());
+
+            // Releasing a key must release whatever chord it pressed, even if the modifiers have
+            // changed in the meantime.
+            let binding = match key.state {
+                ButtonState::Pressed => {
+                    input.resolve(any_input, modifiers).map(|(chord, binding)| {
+                        held_chords.insert(chord.input.clone(), chord);
+                        binding
+                    })
+                }
+                ButtonState::Released => held_chords
+                    .remove(&any_input)
+                    .and_then(|chord| input.bindings.get(&chord)),
+            };
+
             // TODO: Make this work better if we have arguments - currently we clone the arguments every time
-            // TODO: Error handling
-            if let Ok(Some(binding)) = input.binding(key.logical_key.clone()) {
-                run_cmds.send_batch(binding.commands.iter().filter_map(|cmd| {
-                    match (cmd.0.trigger, key.state) {
-                        (Some(Trigger::Positive) | None, ButtonState::Pressed) => Some(cmd.clone()),
-                        (Some(Trigger::Positive) | None, ButtonState::Released) => {
-                            cmd.clone().invert()
-                        }
-                        (Some(Trigger::Negative), _) => unreachable!(
-                            "Binding found to a negative edge! TODO: Do we want to support this?"
-                        ),
-                    }
-                }));
+            if let Some(binding) = binding {
+                run_cmds.send_batch(binding.commands_for(key.state));
+            }
+        }
+
+        for event in mouse_events.read() {
+            let any_input = AnyInput::from(event.button);
+            let binding = match event.state {
+                ButtonState::Pressed => {
+                    input.resolve(any_input, modifiers).map(|(chord, binding)| {
+                        held_chords.insert(chord.input.clone(), chord);
+                        binding
+                    })
+                }
+                ButtonState::Released => held_chords
+                    .remove(&any_input)
+                    .and_then(|chord| input.bindings.get(&chord)),
+            };
+
+            if let Some(binding) = binding {
+                run_cmds.send_batch(binding.commands_for(event.state));
+            }
+        }
+
+        // a notch of the wheel is a press and an immediate release
+        for direction in wheel.read().filter_map(|event| WheelDirection::of(event.y)) {
+            if let Some((_, binding)) = input.resolve(AnyInput::Wheel(direction), modifiers) {
+                run_cmds.send_batch(
+                    binding
+                        .commands_for(ButtonState::Pressed)
+                        .chain(binding.commands_for(ButtonState::Released)),
+                );
             }
         }
     }
 
+    /// Add up this frame's mouse movement for mouse look.
+    ///
+    /// With `m_rawinput` it's read from the device, skipping the system's pointer acceleration.
+    /// Otherwise it's how far the cursor moved, and the cursor is kept in the middle of the window
+    /// while it's grabbed so that it never stops at an edge.
+    pub fn mouse_motion(
+        registry: Res<Registry>,
+        mut motion: EventReader<MouseMotion>,
+        mut windows: Query<&mut Window, With<PrimaryWindow>>,
+        mut last_cursor: Local<Option<Vec2>>,
+        mut input: ResMut<GameInput>,
+    ) {
+        let delta = if registry.read_cvar::<u8>("m_rawinput").unwrap_or(1) != 0 {
+            *last_cursor = None;
+            motion.read().map(|motion| motion.delta).sum::<Vec2>()
+        } else {
+            motion.clear();
+
+            let Ok(mut window) = windows.get_single_mut() else {
+                return;
+            };
+            let Some(cursor) = window.cursor_position() else {
+                *last_cursor = None;
+                return;
+            };
+
+            let delta = last_cursor.map_or(Vec2::ZERO, |last| cursor - last);
+            if window.cursor.grab_mode == CursorGrabMode::None {
+                *last_cursor = Some(cursor);
+            } else {
+                let center = Vec2::new(window.width(), window.height()) / 2.0;
+                if cursor != center {
+                    window.set_cursor_position(Some(center));
+                }
+                *last_cursor = Some(center);
+            }
+
+            delta
+        };
+
+        // the delta is taken every frame, which mustn't look like the bindings changing
+        if delta != Vec2::ZERO {
+            let input = input.bypass_change_detection();
+            input.mouse_delta.0 += delta.x as f64;
+            input.mouse_delta.1 += delta.y as f64;
+        }
+    }
+
     pub fn console_input(
         mut reader: ResMut<InputEventReader<KeyboardInput>>,
         keyboard_events: Res<Events<KeyboardInput>>,
         button_state: Res<ButtonInput<KeyCode>>,
         mut run_cmds: EventWriter<RunCmd<'static>>,
         input: Res<GameInput>,
+        mut ime_events: EventReader<Ime>,
+        mut ime_composing: Local<bool>,
         mut console_in: ResMut<ConsoleInput>,
         mut console_out: ResMut<ConsoleOutput>,
+        mut render_out: ResMut<RenderConsoleOutput>,
+        mut wheel: EventReader<MouseWheel>,
         time: Res<Time<Virtual>>,
         registry: Res<Registry>,
     ) {
+        for MouseWheel { y, .. } in wheel.read() {
+            if *y != 0. {
+                render_out.scroll_by(RenderConsoleOutput::WHEEL_LINES * y.signum() as isize);
+            }
+        }
+
         // TODO: Use a thread_local vector instead of reallocating
         let mut keys = Vec::new();
         for key in reader.reader.read(&keyboard_events) {
@@ -145,27 +382,48 @@ pub mod systems {
                 return;
             }
 
-            if let Ok(Some(Binding {
-                commands,
-                valid: BindingValidState::Any,
-            })) = input.binding(logical_key.clone())
+            let page = match logical_key {
+                Key::PageUp => Some(RenderConsoleOutput::PAGE_LINES),
+                Key::PageDown => Some(-RenderConsoleOutput::PAGE_LINES),
+                _ => None,
+            };
+            if let Some(page) = page {
+                if *state == ButtonState::Pressed {
+                    render_out.scroll_by(page);
+                }
+                continue;
+            }
+
+            if let Ok(Some(
+                binding @ Binding {
+                    valid: BindingValidState::Any,
+                    ..
+                },
+            )) = input.binding(logical_key.clone())
             {
-                run_cmds.send_batch(commands.iter().filter_map(|cmd| {
-                    match (cmd.0.trigger, state) {
-                        (Some(Trigger::Positive) | None, ButtonState::Pressed) => Some(cmd.clone()),
-                        (Some(Trigger::Positive) | None, ButtonState::Released) => {
-                            cmd.clone().invert()
-                        }
-                        (Some(Trigger::Negative), _) => unreachable!(
-                            "Binding found to a negative edge! TODO: Do we want to support this?"
-                        ),
-                    }
-                }));
+                run_cmds.send_batch(binding.commands_for(*state));
             } else {
                 keys.push(key);
             }
         }
 
+        // Some platforms send a commit for every key typed while the IME is enabled, even if no
+        // composition happened, and those keys have already been handled above. We only take text
+        // from the IME if it was composed, since anything else was typed with a key.
+        let mut ime_text = String::new();
+        for event in ime_events.read() {
+            match event {
+                Ime::Preedit { value, .. } => *ime_composing = !value.is_empty(),
+                Ime::Commit { value, .. } => {
+                    if *ime_composing {
+                        ime_text.extend(value.chars().filter_map(to_quake_char));
+                    }
+                    *ime_composing = false;
+                }
+                Ime::Enabled { .. } | Ime::Disabled { .. } => *ime_composing = false,
+            }
+        }
+
         let elapsed = TimeDelta::from_std(time.elapsed()).unwrap();
 
         for exec in console_in.update(
@@ -183,11 +441,21 @@ pub mod systems {
                         }
                     },
                 )
-                .flatten(),
+                .flatten()
+                .chain(ime_text.chars().map(liner::Key::Char)),
             registry.all_names(),
         ) {
             match exec {
-                Ok(cmd) => {
+                Ok(ConsoleInputEvent::Candidates(candidates)) => {
+                    for candidate in candidates {
+                        console_out.println(format!("  {}", candidate), elapsed);
+                    }
+                }
+                Ok(ConsoleInputEvent::Exec(cmd)) => {
+                    if render_out.scroll() != 0 {
+                        render_out.scroll_to_bottom();
+                    }
+
                     console_out.print(ConsoleInput::PROMPT, elapsed);
                     console_out.println(&cmd, elapsed);
 
@@ -206,36 +474,261 @@ pub mod systems {
         }
     }
 
+    /// Keep the keys shown in the controls menu in sync with the current bindings.
+    pub fn update_menu_bindings(input: Res<GameInput>, mut menu: ResMut<Menu>) {
+        menu.update_bindings(&mut |command| {
+            let Ok(command) = RunCmd::parse(command) else {
+                return Vec::new();
+            };
+
+            let mut keys = input
+                .bound_inputs(&command)
+                .map(|chord| chord.to_string())
+                .collect::<Vec<_>>();
+            keys.sort();
+            keys
+        });
+    }
+
+    /// Keep the toggles, enums and sliders in the menus in sync with their cvars, which can also be
+    /// set from the console or a config file.
+    pub fn update_menu_cvars(
+        registry: Res<Registry>,
+        mut menu: ResMut<Menu>,
+        mut last_generation: Local<Option<u64>>,
+    ) {
+        // a menu which has changed might have been replaced, and need its values filled in
+        let generation = registry.cvar_generation();
+        if *last_generation == Some(generation) && !menu.is_changed() {
+            return;
+        }
+        *last_generation = Some(generation);
+
+        let changed = menu
+            .bypass_change_detection()
+            .update_cvars(&mut |cvar| registry.get_cvar(cvar).map(|cvar| cvar.value().clone()));
+
+        if changed {
+            menu.set_changed();
+        }
+    }
+
+    /// Describe the savegames in the save and load menus by their level name and play time.
+    pub fn update_menu_save_slots(vfs: Res<Vfs>, mut menu: ResMut<Menu>) {
+        // only flag the menu as changed if a description did, otherwise we'd run again next frame
+        let changed = menu
+            .bypass_change_detection()
+            .update_save_slots(&mut |slot| {
+                let file = vfs.open(slot_filename(slot)).ok()?;
+                let header = SaveHeader::read(&mut BufReader::new(file)).ok()?;
+                let secs = header.time as u32;
+
+                Some(format!(
+                    "{:22} {:02}:{:02}",
+                    header.level_name(),
+                    secs / 60,
+                    secs % 60
+                ))
+            });
+
+        if changed {
+            menu.set_changed();
+        }
+    }
+
+    /// Bind the command that the menu is waiting on to `chord`.
+    pub(super) fn bind_captured(
+        menu: &mut Menu,
+        chord: KeyChord,
+        run_cmds: &mut EventWriter<RunCmd<'static>>,
+    ) {
+        if let Some(command) = menu.capturing_binding() {
+            run_cmds.send(RunCmd(
+                "bind".into(),
+                Box::new([chord.to_string(), command.to_owned()]),
+            ));
+        }
+
+        menu.end_capture().expect("TODO: Handle menu failures");
+    }
+
+    #[derive(Default)]
+    pub struct MenuMouseState {
+        last_cursor: Option<Vec2>,
+
+        /// Whether a slider is being dragged.
+        dragging: bool,
+    }
+
+    /// Select menu items by hovering over them and activate them by clicking. Sliders can be
+    /// clicked or dragged, the wheel scrolls and the right button goes back.
+    fn menu_mouse_input(
+        window: &Window,
+        mouse_buttons: &ButtonInput<MouseButton>,
+        wheel: &mut EventReader<MouseWheel>,
+        state: &mut MenuMouseState,
+        // not `&mut Menu`, so that the menu is only flagged as changed when it actually is
+        menu: &mut ResMut<Menu>,
+        mut commands: Commands,
+        run_cmds: &mut EventWriter<RunCmd<'static>>,
+    ) {
+        for event in wheel.read() {
+            if event.y > 0.0 {
+                menu.prev().expect("TODO: Handle menu failures");
+            } else if event.y < 0.0 {
+                menu.next().expect("TODO: Handle menu failures");
+            }
+        }
+
+        if mouse_buttons.just_pressed(MouseButton::Right) {
+            if menu.at_root() {
+                run_cmds.send("togglemenu".into());
+            } else {
+                menu.back().expect("TODO: Handle menu failures");
+            }
+
+            return;
+        }
+
+        if !mouse_buttons.pressed(MouseButton::Left) {
+            state.dragging = false;
+        }
+
+        let Some(cursor) = window.physical_cursor_position() else {
+            state.last_cursor = None;
+            return;
+        };
+
+        // only follow the mouse when it moves, so that it doesn't fight the keyboard
+        let moved = state.last_cursor != Some(cursor);
+        state.last_cursor = Some(cursor);
+
+        // the cursor is measured from the top-left, the menu from the bottom-left
+        let (width, height) = (
+            window.physical_width() as f32,
+            window.physical_height() as f32,
+        );
+        let (x, y) = layout::screen_to_menu(cursor.x, height - cursor.y, width, height);
+
+        if state.dragging {
+            if moved {
+                let func = menu
+                    .set_slider_position(layout::slider_position(x))
+                    .expect("TODO: Handle menu failures");
+                func(commands.reborrow());
+            }
+
+            return;
+        }
+
+        let Some(hit) = layout::hit_test(menu, x, y) else {
+            return;
+        };
+
+        let clicked = mouse_buttons.just_pressed(MouseButton::Left);
+        if moved || clicked {
+            menu.select(hit.index).expect("TODO: Handle menu failures");
+        }
+
+        if !clicked {
+            return;
+        }
+
+        if let Ok(Item::Slider(_)) = menu.selected() {
+            state.dragging = true;
+
+            let func = menu
+                .set_slider_position(layout::slider_position(hit.x))
+                .expect("TODO: Handle menu failures");
+            func(commands.reborrow());
+        } else {
+            let func = menu.activate().expect("TODO: Handle menu failures");
+            func(commands.reborrow());
+        }
+    }
+
     pub fn menu_input(
         mut reader: ResMut<InputEventReader<KeyboardInput>>,
         keyboard_events: Res<Events<KeyboardInput>>,
+        button_state: Res<ButtonInput<KeyCode>>,
+        mouse_buttons: Res<ButtonInput<MouseButton>>,
         mut commands: Commands,
         mut run_cmds: EventWriter<RunCmd<'static>>,
         mut menu: ResMut<Menu>,
         input: Res<GameInput>,
+        vfs: Res<Vfs>,
+        windows: Query<&Window, With<PrimaryWindow>>,
+        mut wheel: EventReader<MouseWheel>,
+        mut mouse_state: Local<MenuMouseState>,
     ) {
+        if menu.capturing_binding().is_some() {
+            if let Some(button) = mouse_buttons.get_just_pressed().next() {
+                let chord = KeyChord {
+                    modifiers: Modifiers::pressed(&button_state),
+                    input: AnyInput::from(*button),
+                };
+                bind_captured(&mut menu, chord, &mut run_cmds);
+            } else if let Some(direction) =
+                wheel.read().find_map(|event| WheelDirection::of(event.y))
+            {
+                let chord = KeyChord {
+                    modifiers: Modifiers::pressed(&button_state),
+                    input: AnyInput::Wheel(direction),
+                };
+                bind_captured(&mut menu, chord, &mut run_cmds);
+            }
+
+            wheel.clear();
+        } else if let Ok(window) = windows.get_single() {
+            menu_mouse_input(
+                window,
+                &mouse_buttons,
+                &mut wheel,
+                &mut mouse_state,
+                &mut menu,
+                commands.reborrow(),
+                &mut run_cmds,
+            );
+        }
+
         // TODO: Use a thread_local vector instead of reallocating
         for key in reader.reader.read(&keyboard_events) {
             let KeyboardInput {
                 logical_key, state, ..
             } = key;
 
-            if let Ok(Some(Binding {
-                commands,
-                valid: BindingValidState::Any,
-            })) = input.binding(logical_key.clone())
+            if menu.capturing_binding().is_some() {
+                let input = AnyInput::from(logical_key.clone());
+
+                // wait for the non-modifier key of a chord, and ignore keys we don't have a name for
+                if *state != ButtonState::Pressed
+                    || [AnyInput::CTRL, AnyInput::SHIFT, AnyInput::ALT].contains(&input)
+                    || !input.has_name()
+                {
+                    continue;
+                }
+
+                if input == AnyInput::ESCAPE {
+                    menu.end_capture().expect("TODO: Handle menu failures");
+                } else {
+                    let chord = KeyChord {
+                        modifiers: Modifiers::pressed(&button_state),
+                        input,
+                    };
+                    bind_captured(&mut menu, chord, &mut run_cmds);
+                }
+
+                continue;
+            }
+
+            if let Ok(Some(
+                binding @ Binding {
+                    valid: BindingValidState::Any,
+                    ..
+                },
+            )) = input.binding(logical_key.clone())
             {
-                run_cmds.send_batch(commands.iter().filter_map(|cmd| {
-                    match (cmd.0.trigger, state) {
-                        (Some(Trigger::Positive) | None, ButtonState::Pressed) => Some(cmd.clone()),
-                        (Some(Trigger::Positive) | None, ButtonState::Released) => {
-                            cmd.clone().invert()
-                        }
-                        (Some(Trigger::Negative), _) => unreachable!(
-                            "Binding found to a negative edge! TODO: Do we want to support this?"
-                        ),
-                    }
-                }));
+                run_cmds.send_batch(binding.commands_for(*state));
 
                 continue;
             }
@@ -249,6 +742,15 @@ pub mod systems {
                 continue;
             };
 
+            if let (Key::Character(text), true) = (key, menu.editing_text()) {
+                for ch in text.chars().filter_map(to_quake_char) {
+                    let func = menu.insert_char(ch).expect("TODO: Handle menu failures");
+                    func(commands.reborrow());
+                }
+
+                continue;
+            }
+
             let input = AnyInput::from(key.clone());
 
             // TODO: Make this actually respect the `togglemenu` keybinding
@@ -258,6 +760,27 @@ pub mod systems {
                 } else {
                     menu.back().expect("TODO: Handle menu failures");
                 }
+            } else if input == AnyInput::BACKSPACE || input == AnyInput::DEL {
+                if menu.editing_text() {
+                    let func = menu
+                        .erase_char(input == AnyInput::DEL)
+                        .expect("TODO: Handle menu failures");
+                    func(commands.reborrow());
+                } else if let Some(bind) = menu.selected_binding() {
+                    run_cmds.send_batch(
+                        bind.keys()
+                            .iter()
+                            .map(|key| RunCmd("unbind".into(), Box::new([key.clone()]))),
+                    );
+                } else if let Some(slot) = menu.selected_save_slot() {
+                    if slot.description().is_some() {
+                        match vfs.remove(slot_filename(slot.slot())) {
+                            // refresh the slot descriptions
+                            Ok(()) => menu.set_changed(),
+                            Err(e) => warn!("Couldn't delete savegame: {}", e),
+                        }
+                    }
+                }
             } else if input == AnyInput::ENTER {
                 let func = menu.activate().expect("TODO: Handle menu failures");
                 func(commands.reborrow());
@@ -0,0 +1,99 @@
+//! Gamepad rumble driven by events parsed from the server.
+
+use std::time::Duration;
+
+use bevy::{
+    input::gamepad::{GamepadRumbleIntensity, GamepadRumbleRequest},
+    prelude::*,
+};
+use cgmath::{InnerSpace as _, Vector3};
+
+use crate::common::console::{Cvar, RegisterCmdExt, Registry};
+
+/// Explosions further away from the view origin than this don't cause any rumble.
+const EXPLOSION_RUMBLE_RADIUS: f32 = 512.0;
+
+/// A request to rumble any connected gamepads, before `joy_rumble` scaling is applied.
+#[derive(Event, Debug, Clone, Copy)]
+pub struct Rumble {
+    /// Intensity of the low-frequency motor, from 0 to 1.
+    pub strong: f32,
+    /// Intensity of the high-frequency motor, from 0 to 1.
+    pub weak: f32,
+    pub duration: Duration,
+}
+
+impl Rumble {
+    /// Rumble caused by the player taking `armor + health` points of damage.
+    pub fn damage(armor: u8, health: u8) -> Self {
+        let amount = (armor as f32 + health as f32).min(40.0) / 40.0;
+
+        Self {
+            strong: 0.3 + 0.7 * amount,
+            weak: 0.5 * amount,
+            duration: Duration::from_millis(200),
+        }
+    }
+
+    /// A short kick when the player fires their weapon.
+    pub fn weapon_fire() -> Self {
+        Self {
+            strong: 0.0,
+            weak: 0.4,
+            duration: Duration::from_millis(80),
+        }
+    }
+
+    /// Rumble for an explosion at `origin`, or `None` if it's too far from `view_origin` to feel.
+    pub fn explosion(origin: Vector3<f32>, view_origin: Vector3<f32>) -> Option<Self> {
+        let falloff = 1.0 - (origin - view_origin).magnitude() / EXPLOSION_RUMBLE_RADIUS;
+
+        if falloff <= 0.0 {
+            return None;
+        }
+
+        Some(Self {
+            strong: falloff,
+            weak: 0.5 * falloff,
+            duration: Duration::from_millis(350),
+        })
+    }
+}
+
+pub fn register_cvars(app: &mut App) {
+    app.cvar(
+        "joy_rumble",
+        Cvar::new("1").archive(),
+        "gamepad rumble intensity - 0 disables rumble entirely",
+    );
+}
+
+pub fn apply_rumble(
+    registry: Res<Registry>,
+    gamepads: Res<Gamepads>,
+    mut rumble_events: EventReader<Rumble>,
+    mut requests: EventWriter<GamepadRumbleRequest>,
+) {
+    let scale = registry
+        .read_cvar::<f32>("joy_rumble")
+        .unwrap_or(1.)
+        .clamp(0., 1.);
+
+    if scale <= 0. {
+        rumble_events.clear();
+        return;
+    }
+
+    for rumble in rumble_events.read() {
+        let intensity = GamepadRumbleIntensity {
+            strong_motor: (rumble.strong * scale).clamp(0., 1.),
+            weak_motor: (rumble.weak * scale).clamp(0., 1.),
+        };
+
+        requests.send_batch(gamepads.iter().map(|gamepad| GamepadRumbleRequest::Add {
+            duration: rumble.duration,
+            intensity,
+            gamepad,
+        }));
+    }
+}
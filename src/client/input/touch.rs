@@ -0,0 +1,239 @@
+//! On-screen touch controls, for platforms without a keyboard and mouse.
+//!
+//! The controls don't move the player directly - they press and release the same actions as
+//! keyboard bindings (`+forward`, `+attack` etc.), so everything downstream is shared.
+
+use bevy::{
+    input::touch::Touches, prelude::*, render::extract_resource::ExtractResource,
+    window::PrimaryWindow,
+};
+use cgmath::{InnerSpace as _, Vector2};
+
+use crate::common::console::{CmdName, Cvar, RegisterCmdExt, Registry, RunCmd};
+
+use super::{
+    game::{Action, Trigger},
+    InputFocus,
+};
+
+/// How far a stick has to be pushed, as a proportion of its radius, before it presses an action.
+const STICK_DEADZONE: f32 = 0.35;
+
+/// Touches this far outside a control, as a proportion of its radius, still grab it.
+const TOUCH_SLOP: f32 = 1.5;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TouchControl {
+    MoveStick,
+    LookStick,
+    Attack,
+    Jump,
+}
+
+impl TouchControl {
+    pub const ALL: [TouchControl; 4] = [
+        TouchControl::MoveStick,
+        TouchControl::LookStick,
+        TouchControl::Attack,
+        TouchControl::Jump,
+    ];
+
+    /// The center of the control as a proportion of the screen size measured from the bottom-left,
+    /// and its radius as a proportion of the smaller screen dimension.
+    pub fn placement(self) -> (Vector2<f32>, f32) {
+        match self {
+            TouchControl::MoveStick => (Vector2::new(0.15, 0.25), 0.13),
+            TouchControl::LookStick => (Vector2::new(0.85, 0.25), 0.13),
+            TouchControl::Attack => (Vector2::new(0.87, 0.6), 0.08),
+            TouchControl::Jump => (Vector2::new(0.66, 0.15), 0.07),
+        }
+    }
+
+    pub fn is_stick(self) -> bool {
+        matches!(self, TouchControl::MoveStick | TouchControl::LookStick)
+    }
+
+    pub fn label(self) -> Option<&'static str> {
+        match self {
+            TouchControl::Attack => Some("FIRE"),
+            TouchControl::Jump => Some("JUMP"),
+            _ => None,
+        }
+    }
+
+    /// The actions pressed by this control when it's pushed by `offset`.
+    fn actions(self, offset: Vector2<f32>, out: &mut Vec<Action>) {
+        let (left, right, down, up) = match self {
+            TouchControl::MoveStick => (
+                Action::MoveLeft,
+                Action::MoveRight,
+                Action::Back,
+                Action::Forward,
+            ),
+            TouchControl::LookStick => (
+                Action::Left,
+                Action::Right,
+                Action::LookDown,
+                Action::LookUp,
+            ),
+            TouchControl::Attack => return out.push(Action::Attack),
+            TouchControl::Jump => return out.push(Action::Jump),
+        };
+
+        if offset.x < -STICK_DEADZONE {
+            out.push(left);
+        } else if offset.x > STICK_DEADZONE {
+            out.push(right);
+        }
+
+        if offset.y < -STICK_DEADZONE {
+            out.push(down);
+        } else if offset.y > STICK_DEADZONE {
+            out.push(up);
+        }
+    }
+
+    /// How far `position` (as a proportion of the screen size) is from the center of the control,
+    /// as a proportion of its radius.
+    fn offset(self, position: Vector2<f32>, width: f32, height: f32) -> Vector2<f32> {
+        let (center, radius) = self.placement();
+        let radius = radius * width.min(height);
+
+        Vector2::new(
+            (position.x - center.x) * width / radius,
+            (position.y - center.y) * height / radius,
+        )
+    }
+}
+
+fn clamp_to_unit(v: Vector2<f32>) -> Vector2<f32> {
+    if v.magnitude2() > 1.0 {
+        v.normalize()
+    } else {
+        v
+    }
+}
+
+/// A control which is currently being touched.
+#[derive(Clone, Copy, Debug)]
+pub struct HeldControl {
+    /// The id of the finger holding the control.
+    pub touch_id: u64,
+
+    /// How far the control has been pushed from its center, as a proportion of its radius.
+    pub offset: Vector2<f32>,
+}
+
+#[derive(Resource, ExtractResource, Clone, Default, Debug)]
+pub struct TouchControls {
+    pub enabled: bool,
+    held: [Option<HeldControl>; TouchControl::ALL.len()],
+    pressed: Vec<Action>,
+}
+
+impl TouchControls {
+    pub fn held(&self, control: TouchControl) -> Option<HeldControl> {
+        self.held[control as usize]
+    }
+}
+
+pub fn register_cvars(app: &mut App) {
+    app.cvar(
+        "in_touch",
+        Cvar::new(if cfg!(target_arch = "wasm32") {
+            "1"
+        } else {
+            "0"
+        })
+        .archive(),
+        "show on-screen touch controls",
+    );
+}
+
+pub fn touch_input(
+    registry: Res<Registry>,
+    focus: Res<InputFocus>,
+    touches: Res<Touches>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    mut controls: ResMut<TouchControls>,
+    mut run_cmds: EventWriter<RunCmd<'static>>,
+) {
+    let Ok(window) = windows.get_single() else {
+        return;
+    };
+
+    let (width, height) = (window.width(), window.height());
+    // touch positions are measured from the top-left
+    let to_screen = |pos: Vec2| Vector2::new(pos.x / width, 1.0 - pos.y / height);
+
+    controls.enabled = registry.read_cvar::<u8>("in_touch").unwrap_or(0) != 0;
+
+    if controls.enabled && *focus == InputFocus::Game {
+        for control in TouchControl::ALL {
+            let held = &mut controls.held[control as usize];
+
+            *held = held.and_then(|held| {
+                let touch = touches.get_pressed(held.touch_id)?;
+                let offset = control.offset(to_screen(touch.position()), width, height);
+
+                Some(HeldControl {
+                    offset: clamp_to_unit(offset),
+                    ..held
+                })
+            });
+        }
+
+        for touch in touches.iter_just_pressed() {
+            let position = to_screen(touch.position());
+
+            let grabbed = TouchControl::ALL.into_iter().find_map(|control| {
+                let offset = control.offset(position, width, height);
+
+                (controls.held(control).is_none() && offset.magnitude() <= TOUCH_SLOP)
+                    .then_some((control, offset))
+            });
+
+            if let Some((control, offset)) = grabbed {
+                controls.held[control as usize] = Some(HeldControl {
+                    touch_id: touch.id(),
+                    offset: clamp_to_unit(offset),
+                });
+            }
+        }
+    } else {
+        controls.held = default();
+    }
+
+    let mut pressed = Vec::new();
+    for control in TouchControl::ALL {
+        if let Some(held) = controls.held(control) {
+            control.actions(held.offset, &mut pressed);
+        }
+    }
+
+    let action_cmd = |action: &Action, trigger| {
+        RunCmd(
+            CmdName {
+                trigger: Some(trigger),
+                name: action.to_string().into(),
+            },
+            default(),
+        )
+    };
+
+    run_cmds.send_batch(
+        controls
+            .pressed
+            .iter()
+            .filter(|action| !pressed.contains(action))
+            .map(|action| action_cmd(action, Trigger::Negative))
+            .chain(
+                pressed
+                    .iter()
+                    .filter(|action| !controls.pressed.contains(action))
+                    .map(|action| action_cmd(action, Trigger::Positive)),
+            ),
+    );
+
+    controls.pressed = pressed;
+}
@@ -0,0 +1,355 @@
+//! Translations of engine and game text (`language`).
+//!
+//! Translations are read from `localization/loc_<language>.txt` and `localization/loc_<language>.csv`
+//! in the VFS, the latter overriding the former. The text files have one `key = "value"` pair per
+//! line, as used by the rerelease. The CSV files have the key in the first column and the
+//! translation in the second, or, if there is a header row, in the column named after the language.
+//!
+//! Engine text such as menu and HUD labels and error messages is looked up by its English text. Text sent by the server is
+//! only translated when it starts with `$`, which is how the rerelease's progs refer to strings in
+//! its localization files.
+//!
+//! The charset only has ASCII letters, so translations are mapped to it when they're loaded:
+//! accented letters lose their accents, typographic punctuation becomes its ASCII equivalent and
+//! anything else is drawn as `?`.
+
+use std::{io::Read as _, sync::Arc};
+
+use bevy::{prelude::*, render::extract_resource::ExtractResource};
+use hashbrown::HashMap;
+use serde_lexpr::Value;
+
+use crate::common::{
+    console::{Cvar, RegisterCmdExt, Registry},
+    vfs::Vfs,
+};
+
+#[derive(Resource, ExtractResource, Clone, Default)]
+pub struct Locale {
+    strings: Arc<HashMap<String, String>>,
+}
+
+impl Locale {
+    pub fn load(vfs: &Vfs, language: &str) -> Locale {
+        let mut strings = HashMap::new();
+
+        for ext in ["txt", "csv"] {
+            let path = format!("localization/loc_{}.{}", language, ext);
+            let Ok(mut file) = vfs.open(&path) else {
+                continue;
+            };
+
+            let mut text = String::new();
+            if let Err(e) = file.read_to_string(&mut text) {
+                warn!("Couldn't read {}: {}", path, e);
+                continue;
+            }
+
+            let pairs = match ext {
+                "txt" => parse_txt(&text),
+                _ => parse_csv(&text, language),
+            };
+            strings.extend(
+                pairs
+                    .into_iter()
+                    .map(|(k, v)| (normalize_key(&k).to_owned(), to_charset(&v))),
+            );
+        }
+
+        if strings.is_empty() && language != "english" {
+            warn!("No translations found for language \"{}\"", language);
+        }
+
+        Locale {
+            strings: Arc::new(strings),
+        }
+    }
+
+    /// The translation of `text`, or `text` itself if there isn't one.
+    pub fn get<'a>(&'a self, text: &'a str) -> &'a str {
+        self.strings
+            .get(normalize_key(text))
+            .map_or(text, String::as_str)
+    }
+
+    /// Translate text sent by the server, if it names a localization string.
+    pub fn get_server<'a>(&'a self, text: &'a [u8]) -> &'a [u8] {
+        if !text.starts_with(b"$") {
+            return text;
+        }
+
+        std::str::from_utf8(text)
+            .ok()
+            .and_then(|key| self.strings.get(normalize_key(key)))
+            .map_or(text, String::as_bytes)
+    }
+}
+
+/// Drawn in place of characters that the charset has nothing close to.
+const FALLBACK_GLYPH: char = '?';
+
+/// Map `text` to the characters the Quake charset can draw.
+fn to_charset(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        if c.is_ascii() {
+            out.push(c);
+            continue;
+        }
+
+        let mapped = match c {
+            'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' | 'ā' | 'ă' | 'ą' => "a",
+            'À' | 'Á' | 'Â' | 'Ã' | 'Ä' | 'Å' | 'Ā' | 'Ă' | 'Ą' => "A",
+            'ç' | 'ć' | 'č' => "c",
+            'Ç' | 'Ć' | 'Č' => "C",
+            'ď' | 'đ' => "d",
+            'Ď' | 'Đ' => "D",
+            'è' | 'é' | 'ê' | 'ë' | 'ē' | 'ė' | 'ę' | 'ě' => "e",
+            'È' | 'É' | 'Ê' | 'Ë' | 'Ē' | 'Ė' | 'Ę' | 'Ě' => "E",
+            'ğ' => "g",
+            'Ğ' => "G",
+            'ì' | 'í' | 'î' | 'ï' | 'ī' | 'ı' => "i",
+            'Ì' | 'Í' | 'Î' | 'Ï' | 'Ī' | 'İ' => "I",
+            'ł' => "l",
+            'Ł' => "L",
+            'ñ' | 'ń' | 'ň' => "n",
+            'Ñ' | 'Ń' | 'Ň' => "N",
+            'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' | 'ō' | 'ő' => "o",
+            'Ò' | 'Ó' | 'Ô' | 'Õ' | 'Ö' | 'Ø' | 'Ō' | 'Ő' => "O",
+            'ř' => "r",
+            'Ř' => "R",
+            'ś' | 'š' | 'ş' => "s",
+            'Ś' | 'Š' | 'Ş' => "S",
+            'ť' => "t",
+            'Ť' => "T",
+            'ù' | 'ú' | 'û' | 'ü' | 'ū' | 'ů' | 'ű' => "u",
+            'Ù' | 'Ú' | 'Û' | 'Ü' | 'Ū' | 'Ů' | 'Ű' => "U",
+            'ý' | 'ÿ' => "y",
+            'Ý' | 'Ÿ' => "Y",
+            'ź' | 'ż' | 'ž' => "z",
+            'Ź' | 'Ż' | 'Ž' => "Z",
+            'ß' => "ss",
+            'æ' => "ae",
+            'Æ' => "AE",
+            'œ' => "oe",
+            'Œ' => "OE",
+            '‘' | '’' | '‚' | '′' => "'",
+            '“' | '”' | '„' | '«' | '»' | '″' => "\"",
+            '‐' | '‑' | '‒' | '–' | '—' | '−' => "-",
+            '…' => "...",
+            '¡' => "!",
+            '¿' => "?",
+            '\u{a0}' | '\u{2009}' | '\u{202f}' => " ",
+            _ => {
+                out.push(FALLBACK_GLYPH);
+                continue;
+            }
+        };
+        out.push_str(mapped);
+    }
+
+    out
+}
+
+/// The rerelease writes keys with a leading `$` in some places and without it in others.
+fn normalize_key(key: &str) -> &str {
+    key.trim().trim_start_matches('$')
+}
+
+/// Parse `key = "value"` lines, skipping blank lines and `//` comments.
+fn parse_txt(text: &str) -> Vec<(String, String)> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with("//"))
+        .filter_map(|line| {
+            let (key, value) = line.split_once('=')?;
+            let value = value.trim();
+            let value = match value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+                Some(quoted) => unescape(quoted),
+                None => value.to_owned(),
+            };
+
+            Some((key.trim().to_owned(), value))
+        })
+        .collect()
+}
+
+fn unescape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n')) => {
+                chars.next();
+                out.push('\n');
+            }
+            ('\\', Some(escaped @ ('"' | '\\'))) => {
+                chars.next();
+                out.push(escaped);
+            }
+            (c, _) => out.push(c),
+        }
+    }
+
+    out
+}
+
+/// Parse CSV rows of keys and translations. If the first row is a header (its first cell is `key`
+/// or `id`), the translation is taken from the column named after `language`.
+fn parse_csv(text: &str, language: &str) -> Vec<(String, String)> {
+    let mut rows = csv_rows(text).into_iter();
+    let mut column = 1;
+
+    let mut first = rows.next();
+    if let Some(header) = &first {
+        let is_header = header.first().is_some_and(|cell| {
+            cell.eq_ignore_ascii_case("key") || cell.eq_ignore_ascii_case("id")
+        });
+        if is_header {
+            column = match header
+                .iter()
+                .position(|cell| cell.eq_ignore_ascii_case(language))
+            {
+                Some(column) => column,
+                None => return Vec::new(),
+            };
+            first = None;
+        }
+    }
+
+    first
+        .into_iter()
+        .chain(rows)
+        .filter_map(|row| {
+            let key = row.first()?;
+            let value = row.get(column)?;
+            (!key.is_empty() && !value.is_empty()).then(|| (key.clone(), value.clone()))
+        })
+        .collect()
+}
+
+/// Split CSV text into rows of cells. Quoted cells may contain commas, newlines and doubled
+/// quotes.
+fn csv_rows(text: &str) -> Vec<Vec<String>> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut cell = String::new();
+    let mut quoted = false;
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                cell.push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => row.push(std::mem::take(&mut cell)),
+            '\r' if !quoted => {}
+            '\n' if !quoted => {
+                row.push(std::mem::take(&mut cell));
+                rows.push(std::mem::take(&mut row));
+            }
+            c => cell.push(c),
+        }
+    }
+
+    if !cell.is_empty() || !row.is_empty() {
+        row.push(cell);
+        rows.push(row);
+    }
+
+    rows.retain(|row| row.iter().any(|cell| !cell.is_empty()));
+    rows
+}
+
+fn language(registry: &Registry) -> String {
+    registry
+        .get_cvar("language")
+        .and_then(|cvar| cvar.value().as_name().map(str::to_owned))
+        .unwrap_or_else(|| "english".to_owned())
+}
+
+fn apply_language(In(value): In<Value>, vfs: Res<Vfs>, mut locale: ResMut<Locale>) {
+    match value.as_name() {
+        Some(language) => *locale = Locale::load(&vfs, language),
+        None => warn!("language must be the name of a language, such as english"),
+    }
+}
+
+pub fn register_cvars(app: &mut App) {
+    app.cvar_on_set(
+        "language",
+        Cvar::new("english").archive(),
+        apply_language,
+        "the language of menus and game text, e.g. english, french or german",
+    );
+}
+
+pub mod systems {
+    use super::*;
+
+    /// Load the translations for the current language, e.g. after the game directory changes.
+    pub fn reload_locale(registry: Res<Registry>, vfs: Res<Vfs>, mut locale: ResMut<Locale>) {
+        *locale = Locale::load(&vfs, &language(&registry));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_txt() {
+        let text = "// comment\n$m_quit = \"Quitter\"\n\nqc_hello=\"Say \\\"hi\\\"\\n\"\n";
+        assert_eq!(
+            parse_txt(text),
+            vec![
+                ("$m_quit".to_owned(), "Quitter".to_owned()),
+                ("qc_hello".to_owned(), "Say \"hi\"\n".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_csv() {
+        let text = "key,english,french\r\nOptions,Options,\"Options, etc\"\n\"Quit\",Quit,\"Quitter\"\"\"\n";
+        assert_eq!(
+            parse_csv(text, "french"),
+            vec![
+                ("Options".to_owned(), "Options, etc".to_owned()),
+                ("Quit".to_owned(), "Quitter\"".to_owned()),
+            ]
+        );
+        assert!(parse_csv(text, "german").is_empty());
+
+        assert_eq!(
+            parse_csv("Quit,Beenden\n", "german"),
+            vec![("Quit".to_owned(), "Beenden".to_owned())]
+        );
+    }
+
+    #[test]
+    fn test_to_charset() {
+        assert_eq!(to_charset("Sauvegarder"), "Sauvegarder");
+        assert_eq!(to_charset("Élément à « ôter »…"), "Element a \" oter \"...");
+        assert_eq!(to_charset("Straße – Ende"), "Strasse - Ende");
+        assert_eq!(to_charset("выход"), "?????");
+    }
+
+    #[test]
+    fn test_get() {
+        let locale = Locale {
+            strings: Arc::new(
+                [("qc_hello".to_owned(), "Bonjour".to_owned())]
+                    .into_iter()
+                    .collect(),
+            ),
+        };
+
+        assert_eq!(locale.get("$qc_hello"), "Bonjour");
+        assert_eq!(locale.get("Quit"), "Quit");
+        assert_eq!(locale.get_server(b"$qc_hello"), b"Bonjour");
+        assert_eq!(locale.get_server(b"qc_hello"), b"qc_hello");
+    }
+}
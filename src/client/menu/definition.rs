@@ -0,0 +1,251 @@
+//! Menus defined by mods in `menu.json`.
+//!
+//! The file lists pages to add to the built-in menus. Each page is added as a submenu of the page
+//! found by following `parent` from the main menu, or if it has no `name`, its items are added
+//! to that page directly:
+//!
+//! ```json
+//! {
+//!     "pages": [
+//!         {
+//!             "parent": ["Options"],
+//!             "name": "Gameplay",
+//!             "items": [
+//!                 { "type": "toggle", "name": "Gibs", "cvar": "sv_gibs", "default": true },
+//!                 { "type": "slider", "name": "Gore", "cvar": "sv_gore", "min": 0, "max": 1, "steps": 5 },
+//!                 { "type": "command", "name": "Reset", "command": "exec gameplay.cfg" }
+//!             ]
+//!         }
+//!     ]
+//! }
+//! ```
+
+use bevy::ecs::{event::EventWriter, world::World};
+use failure::{bail, format_err, Error};
+use serde::Deserialize;
+
+use crate::common::{console::RunCmd, vfs::Vfs};
+
+use super::{EnumBuilder, Menu, MenuBodyView, MenuBuilder, MenuView};
+
+/// The path of the menu definition file in the VFS.
+pub const MENU_DEFINITION_PATH: &str = "menu.json";
+
+const DEFAULT_TITLE: &str = "gfx/p_option.lmp";
+
+#[derive(Deserialize, Debug)]
+pub struct MenuDefinition {
+    #[serde(default)]
+    pub pages: Vec<PageDefinition>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct PageDefinition {
+    /// The names of the submenus leading from the main menu to the page this one is added to.
+    #[serde(default)]
+    pub parent: Vec<String>,
+
+    /// The name of the page, or `None` to add the items to the parent page itself.
+    pub name: Option<String>,
+
+    /// The picture shown at the top of the page.
+    pub title: Option<String>,
+
+    pub items: Vec<ItemDefinition>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct EnumOptionDefinition {
+    pub name: String,
+    pub value: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ItemDefinition {
+    /// Runs console commands, separated by `;`.
+    Command {
+        name: String,
+        command: String,
+    },
+    Toggle {
+        name: String,
+        cvar: String,
+        #[serde(default)]
+        default: bool,
+    },
+    Slider {
+        name: String,
+        cvar: String,
+        min: f32,
+        max: f32,
+        steps: usize,
+        #[serde(default)]
+        default: usize,
+    },
+    Enum {
+        name: String,
+        cvar: String,
+        options: Vec<EnumOptionDefinition>,
+        #[serde(default)]
+        default: usize,
+    },
+    TextField {
+        name: String,
+        cvar: String,
+        default: Option<String>,
+        max_len: Option<usize>,
+    },
+    Binding {
+        name: String,
+        command: String,
+    },
+    Page {
+        name: String,
+        title: Option<String>,
+        items: Vec<ItemDefinition>,
+    },
+}
+
+impl MenuDefinition {
+    /// Load the menu definition from the VFS, if there is one.
+    pub fn load(vfs: &Vfs) -> Result<Option<MenuDefinition>, Error> {
+        match vfs.open(MENU_DEFINITION_PATH) {
+            Ok(file) => Ok(Some(serde_json::from_reader(file)?)),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Add the pages in this definition to `menu`. If any page is invalid, `menu` is left as it
+    /// was rather than getting only the pages before it.
+    pub fn apply(self, menu: &mut Menu, vfs: &Vfs, world: &mut World) -> Result<(), Error> {
+        // later pages can be added to ones earlier in the file, so they're built up on a copy
+        let mut updated = menu.clone();
+        for page in self.pages {
+            let parent = updated.submenu_mut(&page.parent).ok_or_else(|| {
+                format_err!("{}: no menu at {:?}", MENU_DEFINITION_PATH, page.parent)
+            })?;
+
+            let built = build_page(MenuBuilder::new(world), page.title, page.items, vfs)?;
+
+            match page.name {
+                Some(name) => parent.push_submenu(name, built),
+                None => parent.items.extend(built.items),
+            }
+        }
+
+        *menu = updated;
+        Ok(())
+    }
+}
+
+fn build_page(
+    mut builder: MenuBuilder,
+    title: Option<String>,
+    items: Vec<ItemDefinition>,
+    vfs: &Vfs,
+) -> Result<Menu, Error> {
+    let title = title.unwrap_or_else(|| DEFAULT_TITLE.to_owned());
+
+    // the menu renderer loads every title up front, so catch missing pictures here
+    if vfs.open(&title).is_err() {
+        bail!("{}: no such picture {}", MENU_DEFINITION_PATH, title);
+    }
+
+    for item in items {
+        builder = match item {
+            ItemDefinition::Command { name, command } => {
+                let commands = RunCmd::parse_many(&command)
+                    .map_err(|e| format_err!("{}: invalid command: {}", MENU_DEFINITION_PATH, e))?
+                    .into_iter()
+                    .map(RunCmd::into_owned)
+                    .collect::<Vec<_>>();
+
+                builder.add_action(name, move |mut run_cmds: EventWriter<RunCmd<'static>>| {
+                    run_cmds.send_batch(commands.iter().cloned());
+                })
+            }
+            ItemDefinition::Toggle {
+                name,
+                cvar,
+                default,
+            } => builder.add_toggle(name, default, cvar),
+            ItemDefinition::Slider {
+                name,
+                cvar,
+                min,
+                max,
+                steps,
+                default,
+            } => builder.add_slider(name, min, max, steps, default, cvar)?,
+            ItemDefinition::Enum {
+                name,
+                cvar,
+                options,
+                default,
+            } => {
+                if options.is_empty() {
+                    bail!("{}: {} has no options", MENU_DEFINITION_PATH, name);
+                }
+                let default = default.min(options.len() - 1);
+
+                let options = options
+                    .into_iter()
+                    .try_fold(EnumBuilder::new(), |options, option| {
+                        options.with(option.name, option.value)
+                    })?
+                    .build();
+
+                builder.add_enum(name, cvar, default, |_| options)
+            }
+            ItemDefinition::TextField {
+                name,
+                cvar,
+                default,
+                max_len,
+            } => builder.add_text_field(name, default, max_len, cvar)?,
+            ItemDefinition::Binding { name, command } => builder.add_binding(name, command),
+            ItemDefinition::Page { name, title, items } => {
+                builder.add_submenu(name, |sub| build_page(sub, title, items, vfs))?
+            }
+        };
+    }
+
+    Ok(builder.build(MenuView {
+        draw_plaque: true,
+        title_path: title.into(),
+        body: MenuBodyView::Dynamic,
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_definition() {
+        let def: MenuDefinition = serde_json::from_str(
+            r#"{
+                "pages": [{
+                    "parent": ["Options"],
+                    "name": "Gameplay",
+                    "items": [
+                        { "type": "toggle", "name": "Gibs", "cvar": "sv_gibs", "default": true },
+                        { "type": "command", "name": "Reset", "command": "exec gameplay.cfg" },
+                        { "type": "page", "name": "More", "items": [] }
+                    ]
+                }]
+            }"#,
+        )
+        .unwrap();
+
+        let page = &def.pages[0];
+        assert_eq!(page.parent, ["Options"]);
+        assert_eq!(page.name.as_deref(), Some("Gameplay"));
+        assert!(matches!(
+            &page.items[0],
+            ItemDefinition::Toggle { default: true, .. }
+        ));
+        assert!(matches!(&page.items[2], ItemDefinition::Page { .. }));
+    }
+}
@@ -21,8 +21,8 @@
 use std::{fmt::Debug, str::FromStr};
 
 use crate::{
-    client::menu::Menu,
-    common::console::{CName, SetCvar},
+    client::{menu::Menu, serverlist::ServerInfo},
+    common::console::{CName, RunCmd, SetCvar},
 };
 
 use bevy::ecs::system::{Commands, SystemId};
@@ -37,6 +37,9 @@ pub enum Item {
     Enum(Enum),
     Slider(Slider),
     TextField(TextField),
+    KeyBind(KeyBind),
+    SaveSlot(SaveSlot),
+    Server(ServerInfo),
 }
 
 #[derive(Debug, Clone)]
@@ -72,7 +75,7 @@ impl Toggle {
     }
 
     pub fn set_true(&mut self) -> impl FnOnce(Commands) + '_ {
-        let val = if self.state {
+        let val = if !self.state {
             self.state = true;
             Some(self.state)
         } else {
@@ -95,6 +98,25 @@ impl Toggle {
     pub fn get(&self) -> bool {
         self.state
     }
+
+    pub fn cvar(&self) -> &str {
+        &self.cvar
+    }
+
+    /// Show `value`, the current value of the cvar, returning `true` if the state changed.
+    pub fn sync(&mut self, value: &Value) -> bool {
+        let state = match value {
+            Value::Bool(b) => *b,
+            _ => match value.as_f64() {
+                Some(n) => n != 0.,
+                None => return false,
+            },
+        };
+
+        let changed = self.state != state;
+        self.state = state;
+        changed
+    }
 }
 
 // TODO: add wrapping configuration to enums
@@ -119,6 +141,10 @@ impl Enum {
         }
     }
 
+    pub fn cvar(&self) -> &str {
+        &self.cvar
+    }
+
     pub fn selected_name(&self) -> &str {
         self.items[self.selected].name.as_ref()
     }
@@ -142,9 +168,21 @@ impl Enum {
         }
     }
 
+    /// Select the item with `value`, the current value of the cvar, returning `true` if the
+    /// selection changed. Values that aren't in the list leave the selection alone.
+    pub fn sync(&mut self, value: &Value) -> bool {
+        let Some(index) = self.items.iter().position(|item| item.value == *value) else {
+            return false;
+        };
+
+        let changed = self.selected != index;
+        self.selected = index;
+        changed
+    }
+
     pub fn select_prev(&mut self) -> impl FnOnce(Commands) + '_ {
-        let val = if self.selected > 1 {
-            self.selected += 1;
+        let val = if self.selected > 0 {
+            self.selected -= 1;
             Some(self.selected_value())
         } else {
             None
@@ -243,10 +281,48 @@ impl Slider {
         }
     }
 
+    /// Select the step nearest to `position`, as returned by [`Slider::position`].
+    pub fn set_position(&mut self, position: f32) -> impl FnOnce(Commands) + '_ {
+        let old = self.selected;
+        let new = ((position * self.steps as f32).round() as usize).min(self.steps - 1);
+
+        let val = if old != new {
+            self.selected = new;
+            Some(self.value())
+        } else {
+            None
+        };
+
+        move |mut c| {
+            if let Some(val) = val {
+                c.add(SetCvar(self.cvar.clone(), val.into()))
+            }
+        }
+    }
+
     pub fn value(&self) -> f32 {
         self.min + self.selected as f32 * self.increment
     }
 
+    pub fn cvar(&self) -> &str {
+        &self.cvar
+    }
+
+    /// Select the step nearest to `value`, the current value of the cvar, returning `true` if the
+    /// selection changed.
+    pub fn sync(&mut self, value: &Value) -> bool {
+        let Some(value) = value.as_f64() else {
+            return false;
+        };
+
+        let step = ((value as f32 - self.min) / self.increment).round();
+        let selected = (step.max(0.) as usize).min(self.steps - 1);
+
+        let changed = self.selected != selected;
+        self.selected = selected;
+        changed
+    }
+
     pub fn position(&self) -> f32 {
         self.selected as f32 / self.steps as f32
     }
@@ -257,6 +333,7 @@ pub struct TextField {
     chars: String,
     max_len: Option<usize>,
     cvar: CName,
+    /// The byte offset of the cursor in `chars`, which is always on a char boundary.
     cursor: usize,
 }
 
@@ -292,6 +369,10 @@ impl TextField {
 
     pub fn set_cursor(&mut self, cursor: usize) -> Result<(), Error> {
         ensure!(cursor <= self.len(), "Index out of range");
+        ensure!(
+            self.chars.is_char_boundary(cursor),
+            "Index is inside a character"
+        );
 
         self.cursor = cursor;
 
@@ -307,28 +388,21 @@ impl TextField {
     }
 
     pub fn cursor_right(&mut self) {
-        let curs = self.cursor;
-        if curs < self.len() {
-            self.cursor = curs + 1;
+        if let Some(c) = self.chars[self.cursor..].chars().next() {
+            self.cursor += c.len_utf8();
         }
     }
 
     pub fn cursor_left(&mut self) {
-        let curs = self.cursor;
-        if curs > 1 {
-            self.cursor = curs - 1;
+        if let Some(c) = self.chars[..self.cursor].chars().next_back() {
+            self.cursor -= c.len_utf8();
         }
     }
 
-    pub fn insert(&mut self, c: char) -> impl FnOnce(Commands) + '_ {
-        let val = if self.max_len == Some(self.len()) {
-            None
-        } else {
-            self.chars.insert(self.cursor, c);
-            Some(self.text())
-        };
-
+    /// Returns a function which copies the text to the field's cvar.
+    fn sync(&self, changed: bool) -> impl FnOnce(Commands) + '_ {
         let cvar = &self.cvar;
+        let val = changed.then(|| self.text());
 
         move |mut c| {
             if let Some(val) = val {
@@ -337,16 +411,142 @@ impl TextField {
         }
     }
 
-    pub fn backspace(&mut self) {
-        if self.cursor > 1 {
-            self.chars.remove(self.cursor - 1);
+    pub fn insert(&mut self, c: char) -> impl FnOnce(Commands) + '_ {
+        let changed = self
+            .max_len
+            .map_or(true, |max_len| self.len() + c.len_utf8() <= max_len);
+        if changed {
+            self.chars.insert(self.cursor, c);
+            self.cursor += c.len_utf8();
         }
+
+        self.sync(changed)
     }
 
-    pub fn delete(&mut self) {
-        if self.cursor < self.len() {
+    pub fn backspace(&mut self) -> impl FnOnce(Commands) + '_ {
+        let changed = self.cursor > 0;
+        if changed {
+            self.cursor_left();
             self.chars.remove(self.cursor);
         }
+
+        self.sync(changed)
+    }
+
+    pub fn delete(&mut self) -> impl FnOnce(Commands) + '_ {
+        let changed = self.cursor < self.len();
+        if changed {
+            self.chars.remove(self.cursor);
+        }
+
+        self.sync(changed)
+    }
+
+    /// The number of characters before the cursor, which is the column it's drawn in.
+    pub fn cursor(&self) -> usize {
+        self.chars[..self.cursor].chars().count()
+    }
+}
+
+/// A command which can be bound to a key from the menu.
+#[derive(Debug, Clone)]
+pub struct KeyBind {
+    command: CName,
+    keys: Vec<String>,
+    capturing: bool,
+}
+
+impl KeyBind {
+    pub fn new<C>(command: C) -> KeyBind
+    where
+        C: Into<CName>,
+    {
+        KeyBind {
+            command: command.into(),
+            keys: Vec::new(),
+            capturing: false,
+        }
+    }
+
+    pub fn command(&self) -> &str {
+        &self.command
+    }
+
+    /// The names of the keys currently bound to this command.
+    pub fn keys(&self) -> &[String] {
+        &self.keys
+    }
+
+    pub fn set_keys(&mut self, keys: Vec<String>) {
+        self.keys = keys;
+    }
+
+    /// Returns `true` if this item is waiting for the user to press the key to bind.
+    pub fn is_capturing(&self) -> bool {
+        self.capturing
+    }
+
+    pub fn set_capturing(&mut self, capturing: bool) {
+        self.capturing = capturing;
+    }
+}
+
+/// Whether a save slot item saves to or loads from its slot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SaveSlotKind {
+    Save,
+    Load,
+}
+
+/// A savegame slot, as shown in the Save and Load menus.
+#[derive(Debug, Clone)]
+pub struct SaveSlot {
+    slot: usize,
+    kind: SaveSlotKind,
+    description: Option<String>,
+}
+
+impl SaveSlot {
+    pub fn new(slot: usize, kind: SaveSlotKind) -> SaveSlot {
+        SaveSlot {
+            slot,
+            kind,
+            description: None,
+        }
+    }
+
+    pub fn slot(&self) -> usize {
+        self.slot
+    }
+
+    pub fn kind(&self) -> SaveSlotKind {
+        self.kind
+    }
+
+    /// A description of the savegame in this slot, or `None` if the slot is unused.
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    /// Update the description, returning `true` if it changed.
+    pub fn set_description(&mut self, description: Option<String>) -> bool {
+        if self.description == description {
+            false
+        } else {
+            self.description = description;
+            true
+        }
+    }
+
+    /// The command to run when this slot is activated. Empty slots can't be loaded.
+    pub fn command(&self) -> Option<RunCmd<'static>> {
+        let name = match self.kind {
+            SaveSlotKind::Save => "save",
+            SaveSlotKind::Load if self.description.is_some() => "load",
+            SaveSlotKind::Load => return None,
+        };
+
+        Some(RunCmd(name.into(), Box::new([format!("s{}", self.slot)])))
     }
 }
 
@@ -0,0 +1,120 @@
+//! Where menu items are placed on the screen.
+//!
+//! Positions are in the 320x200 space of the original menus, measured from the bottom-left, and
+//! are shared by the renderer and by mouse input.
+
+use super::{Menu, MenuBodyView, MenuState};
+
+// original minimum Quake resolution
+pub const MENU_WIDTH: i32 = 320;
+pub const MENU_HEIGHT: i32 = 200;
+
+// TODO: use cvar
+pub const MENU_SCALE: f32 = 2.0;
+
+/// The top of the menu body.
+pub const BODY_TOP: i32 = MENU_HEIGHT - 32;
+
+/// The left edge of a predefined menu body.
+pub const PREDEF_LEFT: i32 = 72;
+
+/// The height of each item in a predefined menu body.
+pub const PREDEF_ITEM_HEIGHT: i32 = 20;
+
+/// The height of each item in a dynamic menu body, which is one line of text.
+pub const DYNAMIC_ITEM_HEIGHT: i32 = 8;
+
+/// Item names in a dynamic menu end here, and their values start here.
+pub const VALUE_X: i32 = 16 + 24 * 8;
+
+/// The number of items that fit on the screen. Longer menus scroll to keep the cursor visible.
+pub const MAX_VISIBLE_ITEMS: usize = 20;
+
+/// The number of glyphs between the ends of a slider.
+pub const SLIDER_WIDTH: i32 = 10;
+
+/// The first item drawn in a dynamic menu with the cursor on item `cursor_pos`.
+pub fn first_visible_item(cursor_pos: usize) -> usize {
+    (cursor_pos + 1).saturating_sub(MAX_VISIBLE_ITEMS)
+}
+
+/// The top of the `row`th item drawn in a dynamic menu.
+pub fn dynamic_item_top(row: usize) -> i32 {
+    BODY_TOP - DYNAMIC_ITEM_HEIGHT * row as i32
+}
+
+/// Convert a position in pixels from the bottom-left of the display to menu space.
+pub fn screen_to_menu(x: f32, y: f32, display_width: f32, display_height: f32) -> (i32, i32) {
+    (
+        ((x - display_width / 2.0) / MENU_SCALE) as i32 + MENU_WIDTH / 2,
+        ((y - display_height / 2.0) / MENU_SCALE) as i32 + MENU_HEIGHT / 2,
+    )
+}
+
+/// The proportion of the way along a slider drawn at `VALUE_X` that `x` falls.
+pub fn slider_position(x: i32) -> f32 {
+    // the handle is one glyph wide, and moves between the ends of the slider
+    let track = 8 * (SLIDER_WIDTH - 1);
+    ((x - VALUE_X - 4) as f32 / track as f32).clamp(0.0, 1.0)
+}
+
+/// An item under a point in menu space.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MenuHit {
+    /// The index of the item in the active menu.
+    pub index: usize,
+
+    /// The x coordinate of the point, for items like sliders which care where they're clicked.
+    pub x: i32,
+}
+
+/// Find the item of the active menu under `(x, y)`.
+pub fn hit_test(menu: &Menu, x: i32, y: i32) -> Option<MenuHit> {
+    let active = menu.active_submenu().ok()?;
+    let MenuState::Active { index: cursor_pos } = active.state() else {
+        return None;
+    };
+
+    if !(0..MENU_WIDTH).contains(&x) || y > BODY_TOP {
+        return None;
+    }
+
+    let index = match active.view().body() {
+        MenuBodyView::Predefined { .. } => {
+            if x < PREDEF_LEFT {
+                return None;
+            }
+
+            ((BODY_TOP - y) / PREDEF_ITEM_HEIGHT) as usize
+        }
+        MenuBodyView::Dynamic => {
+            let row = ((BODY_TOP - y) / DYNAMIC_ITEM_HEIGHT) as usize;
+            if row >= MAX_VISIBLE_ITEMS {
+                return None;
+            }
+
+            first_visible_item(cursor_pos) + row
+        }
+    };
+
+    (index < active.items().len()).then_some(MenuHit { index, x })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_screen_to_menu() {
+        // the menu is centered on the display
+        assert_eq!(screen_to_menu(640.0, 360.0, 1280.0, 720.0), (160, 100));
+        assert_eq!(screen_to_menu(320.0, 160.0, 1280.0, 720.0), (0, 0));
+    }
+
+    #[test]
+    fn test_slider_position() {
+        assert_eq!(slider_position(VALUE_X), 0.0);
+        assert_eq!(slider_position(VALUE_X + 4 + 36), 0.5);
+        assert_eq!(slider_position(MENU_WIDTH), 1.0);
+    }
+}
@@ -18,7 +18,9 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+pub mod definition;
 mod item;
+pub mod layout;
 
 use bevy::{
     ecs::{
@@ -27,11 +29,21 @@ use bevy::{
     },
     render::extract_resource::ExtractResource,
 };
-use failure::{bail, Error};
-
-use crate::common::console::CName;
+use failure::{bail, ensure, Error};
+use serde_lexpr::Value;
+
+use crate::{
+    client::serverlist::ServerInfo,
+    common::{
+        console::{CName, RunCmd},
+        net::PlayerColor,
+        savegame::MAX_SAVEGAMES,
+    },
+};
 
-pub use self::item::{Enum, EnumItem, Item, Slider, TextField, Toggle};
+pub use self::item::{
+    Enum, EnumItem, Item, KeyBind, SaveSlot, SaveSlotKind, Slider, TextField, Toggle,
+};
 
 #[derive(Default, Clone, Copy, Debug)]
 pub enum MenuState {
@@ -88,6 +100,18 @@ pub struct Menu {
     items: im::Vector<NamedMenuItem>,
     state: MenuState,
     view: MenuView,
+
+    /// Whether servers found by `slist` are listed at the end of this menu.
+    server_list: bool,
+
+    /// The cvars whose values color the player picture shown below this menu, if it has one.
+    player_preview: Option<PlayerPreview>,
+}
+
+#[derive(Debug, Clone)]
+struct PlayerPreview {
+    top_cvar: CName,
+    bottom_cvar: CName,
 }
 
 impl Menu {
@@ -189,6 +213,21 @@ impl Menu {
         Ok(())
     }
 
+    /// Select the element of the active menu at `index`.
+    pub fn select(&mut self, index: usize) -> Result<(), Error> {
+        let m = self.active_submenu_mut()?;
+
+        ensure!(index < m.items.len(), "Index out of range");
+        ensure!(
+            matches!(m.state, MenuState::Active { .. }),
+            "Selected menu is inactive (invariant violation)"
+        );
+
+        m.state = MenuState::Active { index };
+
+        Ok(())
+    }
+
     /// Return a reference to the currently selected menu item.
     pub fn selected(&self) -> Result<&Item, Error> {
         let m = self.active_submenu()?;
@@ -212,10 +251,19 @@ impl Menu {
     /// Otherwise, this has no effect.
     #[must_use]
     pub fn activate(&mut self) -> Result<impl FnOnce(Commands), Error> {
-        fn run(action: Option<SystemId>) -> impl FnOnce(Commands) {
-            move |mut c: Commands| match action {
-                Some(action) => c.run_system(action),
-                None => {}
+        fn run(action: Option<SystemId>, cmds: Vec<RunCmd<'static>>) -> impl FnOnce(Commands) {
+            move |mut c: Commands| {
+                if let Some(action) = action {
+                    c.run_system(action);
+                }
+
+                if !cmds.is_empty() {
+                    c.add(move |world: &mut World| {
+                        for cmd in cmds {
+                            world.send_event(cmd);
+                        }
+                    });
+                }
             }
         }
 
@@ -227,18 +275,40 @@ impl Menu {
                     m.state = MenuState::InSubMenu { index };
                     submenu.state = MenuState::Active { index: 0 };
 
-                    Ok(run(None))
+                    Ok(run(None, vec![]))
                 }
 
                 Item::Action(action) => {
                     let action = *action;
-                    Ok(run(Some(action)))
+                    Ok(run(Some(action), vec![]))
                 }
 
-                _ => Ok(run(None)),
+                Item::KeyBind(bind) => {
+                    bind.set_capturing(true);
+                    Ok(run(None, vec![]))
+                }
+
+                Item::Server(server) => Ok(run(
+                    None,
+                    vec![RunCmd(
+                        "connect".into(),
+                        Box::new([server.address.to_string()]),
+                    )],
+                )),
+
+                // saving or loading closes the menu
+                Item::SaveSlot(slot) => Ok(run(
+                    None,
+                    slot.command()
+                        .into_iter()
+                        .chain(Some("togglemenu".into()))
+                        .collect(),
+                )),
+
+                _ => Ok(run(None, vec![])),
             }
         } else {
-            Ok(run(None))
+            Ok(run(None, vec![]))
         }
     }
 
@@ -276,6 +346,238 @@ impl Menu {
         })
     }
 
+    /// Move the selected slider to `position`, as a proportion of its length.
+    #[must_use]
+    pub fn set_slider_position(
+        &mut self,
+        position: f32,
+    ) -> Result<impl FnOnce(Commands) + '_, Error> {
+        let m = self.active_submenu_mut()?;
+
+        Ok(move |c: Commands| {
+            if let MenuState::Active { index } = m.state {
+                if let Item::Slider(slider) = &mut m.items[index].item {
+                    (slider.set_position(position))(c);
+                }
+            }
+        })
+    }
+
+    /// Return `true` if the selected item is a text field, which takes keyboard input.
+    pub fn editing_text(&self) -> bool {
+        matches!(self.selected(), Ok(Item::TextField(_)))
+    }
+
+    /// Type a character into the selected text field.
+    #[must_use]
+    pub fn insert_char(&mut self, ch: char) -> Result<impl FnOnce(Commands) + '_, Error> {
+        let m = self.active_submenu_mut()?;
+
+        Ok(move |c: Commands| {
+            if let MenuState::Active { index } = m.state {
+                if let Item::TextField(text) = &mut m.items[index].item {
+                    (text.insert(ch))(c);
+                }
+            }
+        })
+    }
+
+    /// Erase the character before the cursor in the selected text field, or after it if
+    /// `forward` is set.
+    #[must_use]
+    pub fn erase_char(&mut self, forward: bool) -> Result<impl FnOnce(Commands) + '_, Error> {
+        let m = self.active_submenu_mut()?;
+
+        Ok(move |c: Commands| {
+            if let MenuState::Active { index } = m.state {
+                if let Item::TextField(text) = &mut m.items[index].item {
+                    if forward {
+                        (text.delete())(c);
+                    } else {
+                        (text.backspace())(c);
+                    }
+                }
+            }
+        })
+    }
+
+    /// Return the selected key binding item, if the selected item is one.
+    pub fn selected_binding(&self) -> Option<&KeyBind> {
+        match self.selected() {
+            Ok(Item::KeyBind(bind)) => Some(bind),
+            _ => None,
+        }
+    }
+
+    /// If the selected item is a key binding waiting for the user to press a key, returns the
+    /// command to bind.
+    pub fn capturing_binding(&self) -> Option<&str> {
+        self.selected_binding()
+            .filter(|bind| bind.is_capturing())
+            .map(KeyBind::command)
+    }
+
+    /// Stop waiting for a key press on the selected key binding item.
+    pub fn end_capture(&mut self) -> Result<(), Error> {
+        let m = self.active_submenu_mut()?;
+
+        if let MenuState::Active { index } = m.state {
+            if let Item::KeyBind(bind) = &mut m.items[index].item {
+                bind.set_capturing(false);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Refresh the displayed keys for every key binding item in this menu and its submenus.
+    pub fn update_bindings<F>(&mut self, keys_for: &mut F)
+    where
+        F: FnMut(&str) -> Vec<String>,
+    {
+        for item in self.items.iter_mut() {
+            match &mut item.item {
+                Item::KeyBind(bind) => {
+                    let keys = keys_for(bind.command());
+                    bind.set_keys(keys);
+                }
+                Item::Submenu(submenu) => submenu.update_bindings(keys_for),
+                _ => {}
+            }
+        }
+    }
+
+    /// Show the current values of the cvars behind the toggles, enums and sliders in this menu and
+    /// its submenus, returning `true` if any of them changed.
+    pub fn update_cvars<F>(&mut self, value_of: &mut F) -> bool
+    where
+        F: FnMut(&str) -> Option<Value>,
+    {
+        let mut changed = false;
+
+        for item in self.items.iter_mut() {
+            match &mut item.item {
+                Item::Toggle(toggle) => {
+                    if let Some(value) = value_of(toggle.cvar()) {
+                        changed |= toggle.sync(&value);
+                    }
+                }
+                Item::Enum(e) => {
+                    if let Some(value) = value_of(e.cvar()) {
+                        changed |= e.sync(&value);
+                    }
+                }
+                Item::Slider(slider) => {
+                    if let Some(value) = value_of(slider.cvar()) {
+                        changed |= slider.sync(&value);
+                    }
+                }
+                Item::Submenu(submenu) => changed |= submenu.update_cvars(value_of),
+                _ => {}
+            }
+        }
+
+        changed
+    }
+
+    /// Return the selected save slot item, if the selected item is one.
+    pub fn selected_save_slot(&self) -> Option<&SaveSlot> {
+        match self.selected() {
+            Ok(Item::SaveSlot(slot)) => Some(slot),
+            _ => None,
+        }
+    }
+
+    /// Refresh the description of every save slot in this menu and its submenus, returning `true`
+    /// if any of them changed.
+    pub fn update_save_slots<F>(&mut self, describe: &mut F) -> bool
+    where
+        F: FnMut(usize) -> Option<String>,
+    {
+        let mut changed = false;
+
+        for item in self.items.iter_mut() {
+            match &mut item.item {
+                Item::SaveSlot(slot) => {
+                    let description = describe(slot.slot());
+                    changed |= slot.set_description(description);
+                }
+                Item::Submenu(submenu) => changed |= submenu.update_save_slots(describe),
+                _ => {}
+            }
+        }
+
+        changed
+    }
+
+    /// Replace the servers listed in this menu and its submenus.
+    pub fn update_servers(&mut self, servers: &[ServerInfo]) {
+        if self.server_list {
+            self.items
+                .retain(|item| !matches!(item.item, Item::Server(_)));
+            self.items.extend(servers.iter().map(|server| {
+                NamedMenuItem::new(server.address.to_string(), Item::Server(server.clone()))
+            }));
+
+            // keep the cursor in the list if the server under it went away
+            if let MenuState::Active { index } = &mut self.state {
+                *index = (*index).min(self.items.len().saturating_sub(1));
+            }
+        }
+
+        for item in self.items.iter_mut() {
+            if let Item::Submenu(submenu) = &mut item.item {
+                submenu.update_servers(servers);
+            }
+        }
+    }
+
+    /// The colors chosen in this menu for its player picture, or `None` if it doesn't show one.
+    pub fn player_preview(&self) -> Option<PlayerColor> {
+        let preview = self.player_preview.as_ref()?;
+        let color = |cvar: &str| {
+            self.items
+                .iter()
+                .find_map(|item| match &item.item {
+                    Item::Enum(e) if e.cvar() == cvar => e.selected_value().as_u64(),
+                    _ => None,
+                })
+                .unwrap_or(0) as u8
+        };
+
+        Some(PlayerColor::new(
+            color(&preview.top_cvar),
+            color(&preview.bottom_cvar),
+        ))
+    }
+
+    /// Find the submenu reached by following the items named in `path` from this menu.
+    pub fn submenu_mut<S>(&mut self, path: &[S]) -> Option<&mut Menu>
+    where
+        S: AsRef<str>,
+    {
+        let mut m = self;
+
+        for name in path {
+            m = m.items.iter_mut().find_map(|item| match &mut item.item {
+                Item::Submenu(submenu) if &*item.name == name.as_ref() => Some(submenu),
+                _ => None,
+            })?;
+        }
+
+        Some(m)
+    }
+
+    /// Add a submenu after the existing items of this menu.
+    pub fn push_submenu<S>(&mut self, name: S, mut submenu: Menu)
+    where
+        S: Into<CName>,
+    {
+        submenu.state = MenuState::Inactive;
+        self.items
+            .push_back(NamedMenuItem::new(name, Item::Submenu(submenu)));
+    }
+
     /// Return `true` if the root menu is active, `false` otherwise.
     pub fn at_root(&self) -> bool {
         match self.state {
@@ -324,6 +626,8 @@ impl Menu {
 pub struct MenuBuilder<'a> {
     world: &'a mut World,
     items: im::Vector<NamedMenuItem>,
+    server_list: bool,
+    player_preview: Option<PlayerPreview>,
 }
 
 impl<'a> MenuBuilder<'a> {
@@ -331,6 +635,8 @@ impl<'a> MenuBuilder<'a> {
         MenuBuilder {
             world,
             items: Default::default(),
+            server_list: false,
+            player_preview: None,
         }
     }
 
@@ -350,6 +656,8 @@ impl<'a> MenuBuilder<'a> {
             items: self.items,
             state: MenuState::Active { index: 0 },
             view,
+            server_list: self.server_list,
+            player_preview: self.player_preview,
         }
     }
 
@@ -423,6 +731,49 @@ impl<'a> MenuBuilder<'a> {
         Ok(self)
     }
 
+    pub fn add_binding<N, C>(mut self, name: N, command: C) -> Self
+    where
+        N: Into<CName>,
+        C: Into<CName>,
+    {
+        self.items.push_back(NamedMenuItem::new(
+            name,
+            Item::KeyBind(KeyBind::new(command)),
+        ));
+        self
+    }
+
+    /// List the servers found by `slist` after the other items in this menu.
+    pub fn add_server_list(mut self) -> Self {
+        self.server_list = true;
+        self
+    }
+
+    /// Show the player model below this menu, colored by the enum items bound to `top_cvar` and
+    /// `bottom_cvar`.
+    pub fn add_player_preview<T, B>(mut self, top_cvar: T, bottom_cvar: B) -> Self
+    where
+        T: Into<CName>,
+        B: Into<CName>,
+    {
+        self.player_preview = Some(PlayerPreview {
+            top_cvar: top_cvar.into(),
+            bottom_cvar: bottom_cvar.into(),
+        });
+        self
+    }
+
+    /// Add an item for each savegame slot.
+    pub fn add_save_slots(mut self, kind: SaveSlotKind) -> Self {
+        for slot in 0..MAX_SAVEGAMES {
+            self.items.push_back(NamedMenuItem::new(
+                format!("s{}", slot),
+                Item::SaveSlot(SaveSlot::new(slot, kind)),
+            ));
+        }
+        self
+    }
+
     pub fn add_text_field<N, D, S>(
         mut self,
         name: N,
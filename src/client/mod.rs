@@ -18,26 +18,43 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+pub mod bugreport;
 pub mod commands;
+pub mod config;
 mod cvars;
 pub mod demo;
 pub mod entity;
+pub mod fog;
+pub mod ghost;
 pub mod input;
+pub mod locale;
 pub mod menu;
+pub mod photo;
 pub mod render;
+pub mod runtimer;
+pub mod serverlist;
+pub mod sky;
 pub mod sound;
 pub mod state;
+pub mod tas;
 pub mod trace;
+pub mod video;
 pub mod view;
 
 use self::{
-    input::SeismonInputPlugin,
-    menu::{MenuBodyView, MenuBuilder, MenuView},
+    ghost::{Ghost, GhostView},
+    input::{rumble::Rumble, SeismonInputPlugin},
+    locale::Locale,
+    menu::{definition::MenuDefinition, MenuBodyView, MenuBuilder, MenuView},
+    photo::{PhotoCaptures, PhotoMode},
     render::{RenderResolution, SeismonRenderPlugin},
+    runtimer::RunTimer,
+    serverlist::ServerList,
     sound::{MixerEvent, SeismonSoundPlugin},
+    tas::Tas,
 };
 
-use std::{iter, mem, net::ToSocketAddrs, ops::Range, path::PathBuf};
+use std::{iter, mem, net::ToSocketAddrs, ops::Range, path::PathBuf, sync::Arc};
 
 use crate::{
     client::{
@@ -46,26 +63,31 @@ use crate::{
         sound::{MusicPlayer, StartSound, StartStaticSound, StopSound},
         state::{ClientState, PlayerInfo},
         trace::{TraceEntity, TraceFrame},
-        view::{IdleVars, KickVars, MouseVars, RollVars},
+        view::{Fov, IdleVars, KickVars, MouseVars, RollVars, ZoomVars},
     },
     common::{
         self,
-        console::{ConsoleError, ConsoleOutput, RunCmd, SeismonConsolePlugin},
+        bsp::LevelCache,
+        console::{ConsoleError, ConsoleOutput, Registry, RunCmd, SeismonConsolePlugin},
         engine,
+        host::{self, HostError},
         model::{Model, ModelError},
         net::{
             self,
             connect::{ConnectSocket, Request, Response, CONNECT_PROTOCOL_VERSION},
             BlockingMode, ClientCmd, ClientMessage, ClientStat, EntityEffects, EntityState,
-            GameType, NetError, PlayerColor, QSocket, ServerCmd, ServerMessage, SignOnStage,
+            NetError, PlayerColor, PointEntityKind, QSocket, ServerCmd, ServerMessage, SignOnStage,
+            TempEntity,
         },
+        pak::{Pak, PakLoader},
         util::QString,
-        vfs::{Vfs, VfsError},
+        vfs::{self, PendingPaks, Vfs, VfsError},
     },
 };
 use cgmath::{Deg, Vector3};
 
 use bevy::{
+    app::AppExit,
     asset::AssetServer,
     ecs::{
         event::{EventWriter, ManualEventReader},
@@ -77,7 +99,7 @@ use bevy::{
     window::PrimaryWindow,
 };
 use chrono::Duration;
-use input::InputFocus;
+use input::{game::GameInput, gamepad::GamepadSticks, InputFocus};
 use menu::Menu;
 use num_derive::FromPrimitive;
 use serde::Deserialize;
@@ -136,15 +158,45 @@ pub struct SeismonGameSettings {
     pub game: Option<String>,
 }
 
+/// Builds the main menu, kept so that it can be rebuilt for a different game.
+#[derive(Clone, Resource)]
+pub struct MainMenu(Arc<dyn Fn(MenuBuilder) -> Result<Menu, failure::Error> + Send + Sync>);
+
+/// Sent when the `game` command switches to a different game directory.
+#[derive(Event, Clone, Copy, Debug)]
+pub struct GameChanged;
+
+/// Build the main menu, along with any pages added by the current game's `menu.json`.
+fn build_menu(world: &mut World) -> Option<Menu> {
+    let MainMenu(build) = world.resource::<MainMenu>().clone();
+
+    // menus can look in the VFS to decide what to show, e.g. mission pack episodes
+    let mut menu = match build(MenuBuilder::new(world)) {
+        Ok(menu) => menu,
+        Err(e) => {
+            error!("Couldn't build menu: {}", e);
+            return None;
+        }
+    };
+    let vfs = world.resource::<Vfs>().clone();
+
+    // mods can add their own pages to the menus
+    let custom = MenuDefinition::load(&vfs).and_then(|def| match def {
+        Some(def) => def.apply(&mut menu, &vfs, world),
+        None => Ok(()),
+    });
+    if let Err(e) = custom {
+        error!("Couldn't load custom menus: {}", e);
+    }
+
+    Some(menu)
+}
+
 impl<F> Plugin for SeismonClientPlugin<F>
 where
     F: Fn(MenuBuilder) -> Result<Menu, failure::Error> + Clone + Send + Sync + 'static,
 {
     fn build(&self, app: &mut bevy::prelude::App) {
-        if let Ok(menu) = (self.main_menu)(MenuBuilder::new(&mut app.world)) {
-            app.insert_resource(menu);
-        }
-
         let app = app
             .insert_resource(SeismonGameSettings {
                 base_dir: self
@@ -154,46 +206,135 @@ where
                 game: self.game.clone(),
             })
             .init_resource::<Vfs>()
+            .init_resource::<LevelCache>()
+            .insert_resource(MainMenu(Arc::new(self.main_menu.clone())));
+
+        if let Some(menu) = build_menu(&mut app.world) {
+            app.insert_resource(menu);
+        }
+
+        app.init_asset::<Pak>().init_asset_loader::<PakLoader>();
+        if cfg!(target_arch = "wasm32") {
+            let asset_server = app.world.resource::<AssetServer>();
+            let pending = PendingPaks::new(asset_server, self.game.as_deref());
+            app.insert_resource(pending);
+        }
+
+        let app = app
             .init_resource::<MusicPlayer>()
             .init_resource::<DemoQueue>()
+            .init_resource::<Fov>()
+            .init_resource::<ServerList>()
+            .init_resource::<RunTimer>()
+            .init_resource::<Ghost>()
+            .init_resource::<GhostView>()
+            .init_resource::<Tas>()
+            .init_resource::<Locale>()
+            .init_resource::<PhotoMode>()
+            .init_resource::<PhotoCaptures>()
             .add_event::<Impulse>()
             .add_event::<ClientMessage>()
             .add_event::<ServerMessage>()
+            .add_event::<GameChanged>()
+            .add_event::<HostError>()
             // TODO: Use bevy's state system
             .insert_resource(ConnectionState::SignOn(SignOnStage::Not))
             .add_systems(
                 Main,
                 (
                     systems::set_resolution.run_if(any_with_component::<PrimaryWindow>),
+                    systems::update_fov,
                     systems::handle_input.pipe(|In(res)| {
                         // TODO: Error handling
                         if let Err(e) = res {
                             error!("Error handling input: {}", e);
                         }
                     }),
-                    systems::frame.pipe(|In(res)| {
-                        // TODO: Error handling
-                        if let Err(e) = res {
-                            error!("Error handling frame: {}", e);
-                        }
-                    }),
+                    (
+                        systems::frame.pipe(|In(res), mut host_errors: EventWriter<HostError>| {
+                            if let Err(e) = res {
+                                host_errors.send(HostError(e.to_string()));
+                            }
+                        }),
+                        entity::systems::sync_entities,
+                        runtimer::systems::update_run_timer,
+                        ghost::systems::update_ghost,
+                    )
+                        .chain(),
                     systems::process_network_messages
-                        .pipe(|In(res)| {
-                            // TODO: Error handling
+                        .pipe(|In(res), mut host_errors: EventWriter<HostError>| {
                             if let Err(e) = res {
-                                error!("Error handling frame: {}", e);
+                                host_errors.send(HostError(e.to_string()));
                             }
                         })
                         .run_if(resource_exists::<QSocket>),
                 ),
             )
+            .add_systems(
+                Update,
+                (
+                    systems::rebuild_menu.run_if(on_event::<GameChanged>()),
+                    locale::systems::reload_locale.run_if(on_event::<GameChanged>()),
+                    serverlist::systems::poll_server_list
+                        .run_if(|list: Res<ServerList>| list.is_searching()),
+                    serverlist::systems::update_menu_servers
+                        .run_if(resource_exists::<Menu>.and_then(resource_changed::<ServerList>)),
+                )
+                    .chain(),
+            )
+            .add_systems(
+                Update,
+                vfs::systems::mount_pending_paks.run_if(resource_exists::<PendingPaks>),
+            )
+            .add_systems(Startup, locale::systems::reload_locale)
+            .add_systems(
+                Update,
+                (photo::systems::move_camera, photo::systems::update_capture),
+            )
+            .add_systems(
+                Update,
+                systems::handle_host_errors.run_if(on_event::<HostError>()),
+            )
+            .add_systems(
+                FixedUpdate,
+                tas::systems::play_script
+                    .before(crate::server::systems::recv_client_messages)
+                    .run_if(resource_exists::<crate::server::Session>),
+            )
+            .add_systems(
+                Last,
+                config::systems::write_config_on_exit.run_if(on_event::<AppExit>()),
+            )
             .add_plugins(SeismonConsolePlugin)
             .add_plugins(SeismonRenderPlugin)
             .add_plugins(SeismonSoundPlugin)
             .add_plugins(SeismonInputPlugin);
 
         cvars::register_cvars(app);
+        bugreport::register_commands(app);
         commands::register_commands(app);
+        config::register_commands(app);
+        demo::register_commands(app);
+        fog::register_commands(app);
+        serverlist::register_cvars(app);
+        serverlist::register_commands(app);
+        sky::register_commands(app);
+        runtimer::register_cvars(app);
+        runtimer::register_commands(app);
+        ghost::register_cvars(app);
+        ghost::register_commands(app);
+        tas::register_commands(app);
+        photo::register_cvars(app);
+        photo::register_commands(app);
+        locale::register_cvars(app);
+        video::register_cvars(app);
+        video::register_commands(app);
+        sound::register_cvars(app);
+        host::cvars::register_cvars(app);
+
+        // the browser paces frames itself
+        #[cfg(not(target_arch = "wasm32"))]
+        app.add_systems(Last, host::limit_frame_rate);
     }
 
     fn finish(&self, app: &mut bevy::prelude::App) {
@@ -250,6 +391,8 @@ pub enum ClientError {
     Sound(#[from] SoundError),
     #[error("Virtual filesystem error: {0}")]
     Vfs(#[from] VfsError),
+    #[error("Couldn't load {0}: {1}")]
+    Level(String, failure::Error),
 }
 
 impl From<ConsoleError> for ClientError {
@@ -276,6 +419,16 @@ pub struct MoveVars {
     cl_backspeed: f32,
     #[serde(rename(deserialize = "cl_movespeedkey"))]
     cl_movespeedkey: f32,
+    #[serde(rename(deserialize = "cl_alwaysrun"))]
+    cl_alwaysrun: f32,
+}
+
+impl MoveVars {
+    /// Whether to move at `cl_movespeedkey` times the usual speed: while `+speed` is held, or while
+    /// it isn't if `cl_alwaysrun` is on.
+    fn running(&self, speed_pressed: bool) -> bool {
+        speed_pressed != (self.cl_alwaysrun != 0.0)
+    }
 }
 
 #[derive(Debug, FromPrimitive)]
@@ -286,11 +439,6 @@ enum ColorShiftCode {
     Powerup = 3,
 }
 
-struct ServerInfo {
-    _max_clients: u8,
-    _game_type: GameType,
-}
-
 #[derive(Clone, Debug)]
 pub enum IntermissionKind {
     Intermission,
@@ -366,7 +514,10 @@ impl ConnectionKind {
         match self {
             Self::Server { reader, .. } => {
                 let mut out = Vec::new();
-                for ServerMessage { client_id, packet } in reader.read(events) {
+                for ServerMessage {
+                    client_id, packet, ..
+                } in reader.read(events)
+                {
                     // TODO: Actually use correct client id
                     if *client_id == 0 {
                         out.extend(packet);
@@ -418,6 +569,14 @@ pub struct ClientVars<'a> {
     pub color: u8,
 }
 
+/// The player's colors, from `topcolor` and `bottomcolor`.
+fn player_color(registry: &Registry) -> PlayerColor {
+    PlayerColor::new(
+        registry.read_cvar("topcolor").unwrap_or(0),
+        registry.read_cvar("bottomcolor").unwrap_or(0),
+    )
+}
+
 /// A connection to a game server of some kind.
 ///
 /// The exact nature of the connected server is specified by [`ConnectionKind`].
@@ -440,10 +599,24 @@ impl Connection {
 }
 
 impl Connection {
+    /// Queue a console command to be run by the server. Does nothing when playing a demo.
+    fn send_string_cmd(&mut self, cmd: String) -> Result<(), ClientError> {
+        if let ConnectionKind::Server { compose, .. } = &mut self.kind {
+            ClientCmd::StringCmd { cmd }.serialize(compose)?;
+        }
+
+        Ok(())
+    }
+
     pub fn view_entity_id(&self) -> usize {
         self.state.view_entity_id()
     }
 
+    /// Whether this is a demo being played back rather than a connection to a server.
+    pub fn is_demo(&self) -> bool {
+        self.kind.is_demo()
+    }
+
     pub fn trace<'a, I>(&self, entity_ids: I) -> Result<TraceFrame, ClientError>
     where
         I: IntoIterator<Item = &'a usize>,
@@ -572,13 +745,17 @@ impl Connection {
         mut state: Mut<ConnectionState>,
         time: Time,
         vfs: &Vfs,
+        levels: &LevelCache,
         asset_server: &AssetServer,
         server_events: &Events<ServerMessage>,
         mixer_events: &mut EventWriter<MixerEvent>,
+        rumble_events: &mut EventWriter<Rumble>,
         console_commands: &mut EventWriter<RunCmd<'static>>,
         mut console_output: Mut<ConsoleOutput>,
+        locale: &Locale,
         kick_vars: KickVars,
         client_vars: ClientVars,
+        replay: bool,
     ) -> Result<ConnectionStatus, ClientError> {
         use ConnectionStatus::*;
 
@@ -626,21 +803,23 @@ impl Connection {
                 ServerCmd::NoOp => {}
 
                 ServerCmd::CdTrack { track, .. } => {
-                    mixer_events.send(MixerEvent::StartMusic(Some(sound::MusicSource::TrackId(
-                        match track_override {
+                    mixer_events.send(MixerEvent::StartMusic(Some(sound::StartMusic {
+                        source: sound::MusicSource::TrackId(match track_override {
                             Some(t) => t as usize,
                             None => track as usize,
-                        },
-                    ))));
+                        }),
+                        looping: true,
+                    })));
                 }
 
                 ServerCmd::CenterPrint { text } => {
-                    console_output.set_center_print(text, time);
+                    console_output.set_center_print(locale.get_server(&text.raw).to_vec(), time);
                 }
 
                 ServerCmd::PlayerData(player_data) => self.state.update_player(player_data),
 
                 ServerCmd::Cutscene { text } => {
+                    console_output.set_center_print(locale.get_server(&text.raw).to_vec(), time);
                     self.state.intermission = Some(IntermissionKind::Cutscene { text });
                     self.state.completion_time = Some(self.state.time);
                 }
@@ -649,7 +828,12 @@ impl Connection {
                     armor,
                     blood,
                     source,
-                } => self.state.handle_damage(armor, blood, source, kick_vars),
+                } => {
+                    self.state.handle_damage(armor, blood, source, kick_vars);
+                    if !replay {
+                        rumble_events.send(Rumble::damage(armor, blood));
+                    }
+                }
 
                 ServerCmd::Disconnect => {
                     return Ok(match self.kind {
@@ -663,6 +847,15 @@ impl Connection {
                     self.handle_signon(&client_vars, state.reborrow(), SignOnStage::Done)?;
 
                     let ent_id = ent_update.ent_id as usize;
+                    if !replay
+                        && ent_id == self.state.view_entity_id()
+                        && ent_update
+                            .effects
+                            .is_some_and(|e| e.contains(EntityEffects::MUZZLE_FLASH))
+                    {
+                        rumble_events.send(Rumble::weapon_fire());
+                    }
+
                     self.state.update_entity(ent_id, ent_update)?;
 
                     // patch view angles in demos
@@ -674,6 +867,7 @@ impl Connection {
                 }
 
                 ServerCmd::Finale { text } => {
+                    console_output.set_center_print(locale.get_server(&text.raw).to_vec(), time);
                     self.state.intermission = Some(IntermissionKind::Finale { text });
                     self.state.completion_time = Some(self.state.time);
                 }
@@ -682,6 +876,10 @@ impl Connection {
                 ServerCmd::Intermission => {
                     self.state.intermission = Some(IntermissionKind::Intermission);
                     self.state.completion_time = Some(self.state.time);
+
+                    // the next level is usually one of these, so start loading it while the
+                    // scores are up
+                    levels.prefetch_next_levels(vfs);
                 }
                 ServerCmd::KilledMonster => {
                     self.state.stats[ClientStat::KilledMonsters as usize] += 1
@@ -716,7 +914,9 @@ impl Connection {
                     }
                 }
 
-                ServerCmd::Print { text } => console_output.print_alert(text.raw, time),
+                ServerCmd::Print { text } => {
+                    console_output.print_alert(locale.get_server(&text.raw), time)
+                }
 
                 ServerCmd::ServerInfo {
                     protocol_version,
@@ -732,18 +932,15 @@ impl Connection {
                     }
 
                     console_output.println_alert(CONSOLE_DIVIDER, time);
-                    console_output.println_alert(message.raw, time);
+                    console_output.println_alert(locale.get_server(&message.raw), time);
                     console_output.println_alert(CONSOLE_DIVIDER, time);
 
-                    let _server_info = ServerInfo {
-                        _max_clients: max_clients,
-                        _game_type: game_type,
-                    };
-
                     self.state = ClientState::from_server_info(
                         vfs,
+                        levels,
                         asset_server,
                         max_clients,
+                        game_type,
                         model_precache,
                         sound_precache,
                     )?;
@@ -786,6 +983,11 @@ impl Connection {
                         break;
                     }
 
+                    // sounds that would have finished by now aren't worth playing
+                    if replay {
+                        continue;
+                    }
+
                     let volume = volume.unwrap_or(DEFAULT_SOUND_PACKET_VOLUME);
                     let attenuation = attenuation.unwrap_or(DEFAULT_SOUND_PACKET_ATTENUATION);
                     // TODO: apply volume, attenuation, spatialization
@@ -878,6 +1080,18 @@ impl Connection {
                 }
 
                 ServerCmd::TempEntity { temp_entity } => {
+                    if let TempEntity::Point {
+                        kind:
+                            PointEntityKind::Explosion
+                            | PointEntityKind::ColorExplosion { .. }
+                            | PointEntityKind::TarExplosion,
+                        origin,
+                    } = &temp_entity
+                    {
+                        rumble_events
+                            .send_batch(Rumble::explosion(*origin, self.state.view.final_origin()));
+                    }
+
                     self.state.spawn_temp_entity(mixer_events, &temp_entity);
                 }
 
@@ -1007,23 +1221,67 @@ impl Connection {
         mut state: Mut<ConnectionState>,
         time: Time,
         vfs: &Vfs,
+        levels: &LevelCache,
         asset_server: &AssetServer,
         from_server: &Events<ServerMessage>,
         to_server: &mut EventWriter<ClientMessage>,
         mixer_events: &mut EventWriter<MixerEvent>,
+        rumble_events: &mut EventWriter<Rumble>,
         console_commands: &mut EventWriter<RunCmd<'static>>,
         mut console: Mut<ConsoleOutput>,
+        locale: &Locale,
         idle_vars: IdleVars,
         kick_vars: KickVars,
         roll_vars: RollVars,
         bob_vars: BobVars,
         client_vars: ClientVars,
         cl_nolerp: bool,
+        cl_lerp: bool,
         sv_gravity: f32,
     ) -> Result<ConnectionStatus, ClientError> {
-        let frame_time = Duration::from_std(time.delta()).unwrap();
+        let mut frame_time = Duration::from_std(time.delta()).unwrap();
         debug!("frame time: {}ms", frame_time.num_milliseconds());
 
+        let mut seek = None;
+        if let ConnectionKind::Demo(demo) = &mut self.kind {
+            frame_time = demo.playback_time(frame_time);
+
+            seek = demo.take_seek();
+            if seek.is_some_and(|target| target < demo.message_id()) {
+                demo.rewind();
+                self.state = ClientState::new();
+                *state = ConnectionState::SignOn(SignOnStage::Prespawn);
+            }
+        }
+
+        // catch up with a seek by parsing every message up to the new position at once
+        while let (Some(target), ConnectionKind::Demo(demo)) = (seek, &self.kind) {
+            if demo.message_id() >= target {
+                break;
+            }
+
+            self.state.time = self.state.msg_times[0];
+            match self.parse_server_msg(
+                state.reborrow(),
+                time,
+                vfs,
+                levels,
+                asset_server,
+                from_server,
+                mixer_events,
+                rumble_events,
+                console_commands,
+                console.reborrow(),
+                locale,
+                kick_vars,
+                client_vars.clone(),
+                true,
+            )? {
+                ConnectionStatus::Maintain => {}
+                s => return Ok(s),
+            }
+        }
+
         // do this _before_ parsing server messages so that we know when to
         // request the next message from the demo server.
         self.state.advance_time(frame_time);
@@ -1031,20 +1289,24 @@ impl Connection {
             state.reborrow(),
             time,
             vfs,
+            levels,
             asset_server,
             from_server,
             mixer_events,
+            rumble_events,
             console_commands,
             console.reborrow(),
+            locale,
             kick_vars,
             client_vars,
+            false,
         )? {
             ConnectionStatus::Maintain => {}
             // if Disconnect or NextDemo, delegate up the chain
             s => return Ok(s),
         };
 
-        self.state.update_interp_ratio(cl_nolerp);
+        self.state.update_interp_ratio(cl_nolerp, cl_lerp);
 
         // interpolate entity data and spawn particle effects, lights
         self.state.update_entities()?;
@@ -1216,23 +1478,105 @@ mod systems {
 
     use super::*;
 
+    /// Replace the menu with one built from the new game's files.
+    pub fn rebuild_menu(world: &mut World) {
+        if let Some(menu) = build_menu(world) {
+            world.insert_resource(menu);
+        }
+    }
+
+    /// Shut down the server and connection after a fatal error and drop back to the console,
+    /// like `Host_Error`.
+    pub fn handle_host_errors(
+        mut commands: Commands,
+        mut host_errors: EventReader<HostError>,
+        time: Res<Time<Real>>,
+        session: Option<Res<crate::server::Session>>,
+        conn: Option<Res<Connection>>,
+        locale: Res<Locale>,
+        mut console: ResMut<ConsoleOutput>,
+        mut conn_state: ResMut<ConnectionState>,
+        mut focus: ResMut<InputFocus>,
+        mut demo_queue: ResMut<DemoQueue>,
+    ) {
+        // later errors in the same frame are usually caused by the first
+        let Some(HostError(message)) = host_errors.read().next().cloned() else {
+            return;
+        };
+        host_errors.clear();
+
+        // there's nothing to shut down, so don't bury the console in the same error every frame
+        if session.is_none() && conn.is_none() {
+            error!("{}", message);
+            return;
+        }
+
+        error!("Host_Error: {}", message);
+        // errors without a fixed message, like most I/O errors, won't have a translation
+        console.println(
+            format!("Host_Error: {}", locale.get(&message)),
+            Duration::from_std(time.elapsed()).unwrap(),
+        );
+
+        commands.remove_resource::<crate::server::Session>();
+        commands.remove_resource::<Connection>();
+        commands.remove_resource::<QSocket>();
+        *conn_state = ConnectionState::SignOn(SignOnStage::Not);
+        *focus = InputFocus::Console;
+
+        // don't carry on with the attract loop, the player needs to see the error
+        *demo_queue = DemoQueue::default();
+    }
+
     pub fn handle_input(
         // mut console: ResMut<Console>,
         registry: ResMut<Registry>,
         conn_state: Option<Res<ConnectionState>>,
         mut conn: Option<ResMut<Connection>>,
         frame_time: Res<Time<Virtual>>,
+        fov: Res<Fov>,
         mut client_events: EventWriter<ClientMessage>,
         mut impulses: EventReader<Impulse>,
+        tas: Res<Tas>,
+        photo: Res<PhotoMode>,
+        mut game_input: ResMut<GameInput>,
+        mut last_mouse_delta: Local<(f32, f32)>,
+        sticks: Res<GamepadSticks>,
     ) -> Result<(), ClientError> {
+        // take the mouse movement every frame, so that it doesn't build up while we aren't playing
+        let (x, y) = mem::take(&mut game_input.bypass_change_detection().mouse_delta);
+        let raw_mouse_delta = (x as f32, y as f32);
+
         match conn_state.as_deref() {
             None | Some(ConnectionState::SignOn(_)) => return Ok(()),
             _ => {}
         }
 
+        // the player's input is coming from a script, or is moving the photo mode camera
+        if tas.is_playing() || photo.is_active() {
+            return Ok(());
+        }
+
         // TODO: Error handling
         let move_vars: MoveVars = registry.read_cvars().unwrap();
-        let mouse_vars: MouseVars = registry.read_cvars().unwrap();
+        let mut mouse_vars: MouseVars = registry.read_cvars().unwrap();
+
+        // scale sensitivity with the zoom level so that aiming feels the same when zoomed in
+        if let Ok(base_fov) = registry.read_cvar::<f32>("fov") {
+            if base_fov > 0. {
+                mouse_vars.sensitivity *= fov.0 .0 / base_fov;
+            }
+        }
+
+        let mouse_delta = if mouse_vars.filter != 0.0 {
+            (
+                (raw_mouse_delta.0 + last_mouse_delta.0) / 2.0,
+                (raw_mouse_delta.1 + last_mouse_delta.1) / 2.0,
+            )
+        } else {
+            raw_mouse_delta
+        };
+        *last_mouse_delta = raw_mouse_delta;
 
         // TODO: Unclear fromm the bevy documentation if this drops all other events for the frame,
         //       but in this case it's almost certainly fine
@@ -1249,6 +1593,8 @@ mod systems {
                     Duration::from_std(frame_time.delta()).unwrap(),
                     move_vars,
                     mouse_vars,
+                    mouse_delta,
+                    *sticks,
                     impulse,
                 );
                 let mut msg = Vec::new();
@@ -1258,8 +1604,6 @@ mod systems {
                     packet: msg,
                     kind: MessageKind::Unreliable,
                 });
-
-                // TODO: Refresh input (e.g. mouse movement)
             }
 
             _ => (),
@@ -1272,6 +1616,8 @@ mod systems {
     struct NetworkVars {
         #[serde(rename(deserialize = "cl_nolerp"))]
         disable_lerp: f32,
+        #[serde(rename(deserialize = "cl_lerp"))]
+        entity_lerp: f32,
         #[serde(rename(deserialize = "sv_gravity"))]
         gravity: f32,
     }
@@ -1280,12 +1626,15 @@ mod systems {
         mut commands: Commands,
         cvars: Res<Registry>,
         vfs: Res<Vfs>,
+        levels: Res<LevelCache>,
         time: Res<Time<Virtual>>,
         asset_server: Res<AssetServer>,
         mut mixer_events: EventWriter<MixerEvent>,
+        mut rumble_events: EventWriter<Rumble>,
         from_server: Res<Events<ServerMessage>>,
         mut to_server: EventWriter<ClientMessage>,
         mut console: ResMut<ConsoleOutput>,
+        locale: Res<Locale>,
         mut console_commands: EventWriter<RunCmd<'static>>,
         mut demo_queue: ResMut<DemoQueue>,
         mut focus: ResMut<InputFocus>,
@@ -1294,6 +1643,7 @@ mod systems {
     ) -> Result<(), ClientError> {
         let NetworkVars {
             disable_lerp,
+            entity_lerp,
             gravity,
         } = cvars
             .read_cvars()
@@ -1314,12 +1664,12 @@ mod systems {
         // as valid strings, so we need to use `.value().as_name()` and can't use `read_cvars`.
         let client_vars: ClientVars = ClientVars {
             name: cvars
-                .get_cvar("_cl_name")
+                .get_cvar("name")
                 .ok_or(ClientError::Cvar(ConsoleError::CvarParseInvalid))?
                 .value()
                 .as_name()
                 .unwrap_or("player"),
-            color: cvars.read_cvar("_cl_color")?,
+            color: player_color(&cvars).bits(),
         };
 
         let status = match conn.as_deref_mut() {
@@ -1331,18 +1681,22 @@ mod systems {
                     time.as_generic()
                 },
                 &*vfs,
+                &*levels,
                 &*asset_server,
                 &*from_server,
                 &mut to_server,
                 &mut mixer_events,
+                &mut rumble_events,
                 &mut console_commands,
                 console.reborrow(),
+                &*locale,
                 idle_vars,
                 kick_vars,
                 roll_vars,
                 bob_vars,
                 client_vars,
                 disable_lerp != 0.,
+                entity_lerp != 0.,
                 gravity,
             )?,
             None => ConnectionStatus::Disconnect,
@@ -1428,12 +1782,53 @@ mod systems {
         Ok(())
     }
 
+    pub fn update_fov(
+        registry: Res<Registry>,
+        time: Res<Time<Virtual>>,
+        photo: Res<PhotoMode>,
+        mut fov: ResMut<Fov>,
+    ) {
+        // the photo mode camera has its own field of view
+        if let Some(camera) = photo.camera() {
+            fov.set_if_neq(Fov(camera.fov));
+            return;
+        }
+
+        let Some(ZoomVars {
+            fov: base_fov,
+            zoom_fov,
+            zoom_speed,
+        }) = registry.read_cvars()
+        else {
+            return;
+        };
+
+        let target = if registry.is_pressed("zoom") {
+            zoom_fov
+        } else {
+            base_fov
+        }
+        .clamp(Fov::MIN, Fov::MAX);
+
+        // avoid triggering change detection (and re-extraction) when nothing has changed
+        let mut new_fov = *fov;
+        new_fov.approach(Deg(target), zoom_speed, time.delta_seconds());
+        fov.set_if_neq(new_fov);
+    }
+
     pub fn set_resolution(
         window: Query<&Window, With<PrimaryWindow>>,
+        photo: Res<PhotoMode>,
         mut target_resource: ResMut<RenderResolution>,
     ) {
-        let res = &window.single().resolution;
-        let res = RenderResolution(res.width() as _, res.height() as _);
+        // photo mode pictures are rendered offscreen at their own size
+        let res = match photo.capture_size() {
+            Some((width, height)) => RenderResolution(width, height),
+            None => {
+                let res = &window.single().resolution;
+                RenderResolution(res.width() as _, res.height() as _)
+            }
+        };
         if *target_resource != res {
             *target_resource = res;
         }
@@ -1457,6 +1852,7 @@ mod systems {
         server_events.send(ServerMessage {
             client_id: 0,
             packet: qsock.recv_msg(blocking_mode)?,
+            kind: default(),
         });
 
         for event in client_events.read() {
@@ -0,0 +1,539 @@
+//! Photo mode (`photomode`).
+//!
+//! Photo mode pauses the game and detaches the camera from the player, so that it can be flown
+//! around the frozen scene with the movement and turning keys or the mouse. The HUD and view
+//! model are hidden. The camera's roll and field of view are set with `photo_roll` and
+//! `photo_fov`, and `photo_focus` and `photo_aperture` add depth of field. Use `screenshot` to
+//! save the picture.
+//!
+//! Pictures are rendered offscreen at `photo_scale` times the window's size. While one is being
+//! captured the camera draws to an image instead of the window, and `RenderResolution` is set to
+//! the image's size, so the window isn't redrawn for the few frames it takes.
+
+use std::collections::VecDeque;
+
+use bevy::{
+    input::mouse::MouseMotion,
+    prelude::*,
+    render::{
+        camera::RenderTarget,
+        extract_resource::ExtractResource,
+        render_asset::{RenderAssetUsages, RenderAssets},
+        render_resource::{Extent3d, TextureDescriptor, TextureDimension, TextureUsages},
+        renderer::{RenderDevice, RenderQueue},
+    },
+    window::{PrimaryWindow, WindowRef},
+};
+use cgmath::{Deg, Vector3, Zero as _};
+use clap::Parser;
+use crossbeam_channel::{Receiver, Sender};
+use serde::Deserialize;
+
+use crate::common::{
+    console::{Cvar, ExecResult, RegisterCmdExt, Registry},
+    math::{self, Angles},
+};
+
+use super::{input::InputFocus, view::MouseVars, Connection, ConnectionState, MoveVars};
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+struct PhotoVars {
+    #[serde(rename(deserialize = "photo_fov"))]
+    fov: f32,
+    #[serde(rename(deserialize = "photo_roll"))]
+    roll: f32,
+    #[serde(rename(deserialize = "photo_focus"))]
+    focus: f32,
+    #[serde(rename(deserialize = "photo_aperture"))]
+    aperture: f32,
+    #[serde(rename(deserialize = "photo_scale"))]
+    scale: f32,
+}
+
+/// The largest `photo_scale`.
+const MAX_SCALE: u32 = 8;
+
+/// The number of frames rendered to a capture's image before it's read back, which gives the
+/// renderer time to rebuild its targets at the new resolution.
+const CAPTURE_FRAMES: u32 = 3;
+
+const CAPTURE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+/// The detached camera.
+#[derive(Clone, Copy, Debug)]
+pub struct PhotoCamera {
+    pub origin: Vector3<f32>,
+    pub angles: Angles,
+    pub fov: Deg<f32>,
+
+    /// The distance in world units which is in focus.
+    pub focus: f32,
+
+    /// How blurry things away from the focus are, or 0 for no depth of field.
+    pub aperture: f32,
+
+    /// Whether the game was already paused, in which case it stays paused afterwards.
+    was_paused: bool,
+}
+
+/// A picture being rendered offscreen.
+#[derive(Clone)]
+struct Capture {
+    id: u64,
+    image: Handle<Image>,
+    width: u32,
+    height: u32,
+
+    /// The number of frames that have been rendered to the image.
+    frames: u32,
+
+    /// Where the render world sends the pixels once they've been read back.
+    send: Sender<CaptureResult>,
+}
+
+type CaptureResult = (u64, Result<Vec<u8>, wgpu::BufferAsyncError>);
+
+#[derive(Resource, ExtractResource, Clone, Default)]
+pub struct PhotoMode {
+    camera: Option<PhotoCamera>,
+    capture: Option<Capture>,
+}
+
+impl PhotoMode {
+    pub fn camera(&self) -> Option<&PhotoCamera> {
+        self.camera.as_ref()
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.camera.is_some()
+    }
+
+    /// The size of the picture being captured, which the scene is rendered at instead of the
+    /// window's size.
+    pub fn capture_size(&self) -> Option<(u32, u32)> {
+        self.capture
+            .as_ref()
+            .map(|capture| (capture.width, capture.height))
+    }
+}
+
+type OnCaptured = Box<dyn FnOnce(Result<Image, wgpu::BufferAsyncError>) + Send + Sync>;
+
+/// Requested high-resolution pictures, which are captured one at a time.
+#[derive(Resource)]
+pub struct PhotoCaptures {
+    send: Sender<CaptureResult>,
+    recv: Receiver<CaptureResult>,
+    next_id: u64,
+    queued: VecDeque<OnCaptured>,
+    current: Option<OnCaptured>,
+}
+
+impl Default for PhotoCaptures {
+    fn default() -> Self {
+        let (send, recv) = crossbeam_channel::unbounded();
+        PhotoCaptures {
+            send,
+            recv,
+            next_id: 0,
+            queued: VecDeque::new(),
+            current: None,
+        }
+    }
+}
+
+impl PhotoCaptures {
+    /// Render a picture at `photo_scale` times the window's size, and pass it to `on_captured`
+    /// once it's been read back from the GPU.
+    pub fn request<F>(&mut self, on_captured: F)
+    where
+        F: FnOnce(Result<Image, wgpu::BufferAsyncError>) + Send + Sync + 'static,
+    {
+        self.queued.push_back(Box::new(on_captured));
+    }
+}
+
+/// An image the camera can render to, read back with `read_back_capture`.
+fn capture_image(width: u32, height: u32) -> Image {
+    let size = Extent3d {
+        width,
+        height,
+        depth_or_array_layers: 1,
+    };
+    let mut image = Image {
+        texture_descriptor: TextureDescriptor {
+            label: Some("photo capture"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: CAPTURE_FORMAT,
+            usage: TextureUsages::TEXTURE_BINDING
+                | TextureUsages::COPY_SRC
+                | TextureUsages::COPY_DST
+                | TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        },
+        asset_usage: RenderAssetUsages::RENDER_WORLD,
+        ..default()
+    };
+    // fills the image with zeroes, which is what it's uploaded with
+    image.resize(size);
+    image
+}
+
+/// Remove the padding wgpu needs at the end of each row of a texture copied to a buffer.
+fn strip_row_padding(data: &[u8], row_bytes: usize, padded_row_bytes: usize) -> Vec<u8> {
+    data.chunks(padded_row_bytes)
+        .flat_map(|row| &row[..row_bytes])
+        .copied()
+        .collect()
+}
+
+/// The forward and right vectors for `angles`, ignoring roll.
+fn move_vectors(angles: Angles) -> (Vector3<f32>, Vector3<f32>) {
+    let (sp, cp) = angles.pitch.0.to_radians().sin_cos();
+    let (sy, cy) = angles.yaw.0.to_radians().sin_cos();
+
+    // positive pitch looks down
+    let forward = Vector3::new(cp * cy, cp * sy, -sp);
+    let right = Vector3::new(sy, -cy, 0.0);
+    (forward, right)
+}
+
+pub fn register_cvars(app: &mut App) {
+    app.cvar(
+        "photo_fov",
+        Cvar::new("90"),
+        "the horizontal field of view of the photo mode camera",
+    );
+    app.cvar(
+        "photo_roll",
+        Cvar::new("0"),
+        "the roll of the photo mode camera in degrees",
+    );
+    app.cvar(
+        "photo_focus",
+        Cvar::new("256"),
+        "the distance in world units that is in focus in photo mode",
+    );
+    app.cvar(
+        "photo_aperture",
+        Cvar::new("0"),
+        "how blurred things nearer or further than photo_focus are, or 0 for no depth of field",
+    );
+    app.cvar(
+        "photo_scale",
+        Cvar::new("2").archive(),
+        "how many times larger than the window photo mode screenshots are",
+    );
+}
+
+pub fn register_commands(app: &mut App) {
+    #[derive(Parser)]
+    #[command(
+        name = "photomode",
+        about = "Pause the game and fly a free camera around to take screenshots"
+    )]
+    struct PhotoModeCmd;
+
+    app.command(
+        |In(PhotoModeCmd),
+         registry: Res<Registry>,
+         conn: Option<Res<Connection>>,
+         conn_state: Res<ConnectionState>,
+         mut time: ResMut<Time<Virtual>>,
+         mut focus: ResMut<InputFocus>,
+         mut photo: ResMut<PhotoMode>|
+         -> ExecResult {
+            if let Some(camera) = photo.camera.take() {
+                if !camera.was_paused {
+                    time.unpause();
+                }
+                return default();
+            }
+
+            let (Some(conn), ConnectionState::Connected(_)) = (conn, &*conn_state) else {
+                return "photomode needs a game or demo to be running".into();
+            };
+
+            let Some(vars) = registry.read_cvars::<PhotoVars>() else {
+                return "photo mode cvars are invalid".into();
+            };
+
+            let mut angles = conn.state.camera_angles(conn.kind.is_demo());
+            angles.roll = Deg(vars.roll);
+
+            photo.camera = Some(PhotoCamera {
+                origin: conn.state.camera_origin(),
+                angles,
+                fov: Deg(vars.fov),
+                focus: vars.focus,
+                aperture: vars.aperture,
+                was_paused: time.is_paused(),
+            });
+            time.pause();
+            *focus = InputFocus::Game;
+
+            "Photo mode, use photomode again to return to the game".into()
+        },
+    );
+}
+
+pub mod systems {
+    use super::*;
+
+    /// Fly the photo mode camera. This uses real time, since the game is paused.
+    pub fn move_camera(
+        registry: Res<Registry>,
+        time: Res<Time<Real>>,
+        mut virtual_time: ResMut<Time<Virtual>>,
+        focus: Res<InputFocus>,
+        conn: Option<Res<Connection>>,
+        mut mouse: EventReader<MouseMotion>,
+        mut photo: ResMut<PhotoMode>,
+    ) {
+        if !photo.is_active() {
+            return;
+        }
+
+        // the game is over, so there's nothing left to look at
+        if conn.is_none() {
+            if let Some(camera) = photo.camera.take() {
+                if !camera.was_paused {
+                    virtual_time.unpause();
+                }
+            }
+            return;
+        }
+
+        let Some(camera) = &mut photo.camera else {
+            return;
+        };
+
+        if let Some(vars) = registry.read_cvars::<PhotoVars>() {
+            camera.angles.roll = Deg(vars.roll);
+            camera.fov = Deg(vars.fov.clamp(1.0, 170.0));
+            camera.focus = vars.focus.max(1.0);
+            camera.aperture = vars.aperture.max(0.0);
+        }
+
+        let mouse_delta = mouse.read().fold((0.0, 0.0), |(x, y), motion| {
+            (x + motion.delta.x, y + motion.delta.y)
+        });
+        if *focus != InputFocus::Game {
+            return;
+        }
+
+        let (Some(move_vars), Some(mouse_vars)) = (
+            registry.read_cvars::<MoveVars>(),
+            registry.read_cvars::<MouseVars>(),
+        ) else {
+            return;
+        };
+
+        let frame_time = time.delta_seconds();
+        let speed = if move_vars.running(registry.is_pressed("speed")) {
+            move_vars.cl_movespeedkey
+        } else {
+            1.0
+        };
+        let pressed = |name: &str| registry.is_pressed(name) as i32 as f32;
+
+        let angles = &mut camera.angles;
+        let turn = frame_time * speed;
+        angles.yaw += Deg(turn * move_vars.cl_yawspeed * (pressed("left") - pressed("right")));
+        angles.pitch +=
+            Deg(turn * move_vars.cl_pitchspeed * (pressed("lookdown") - pressed("lookup")));
+        angles.yaw -= Deg(mouse_delta.0 * mouse_vars.yaw_factor * mouse_vars.sensitivity);
+        angles.pitch += Deg(mouse_delta.1 * mouse_vars.pitch_factor * mouse_vars.sensitivity);
+        angles.pitch = math::clamp_deg(angles.pitch, Deg(-89.0), Deg(89.0));
+
+        let (forward, right) = move_vectors(*angles);
+        let mut velocity = Vector3::zero();
+        velocity += forward
+            * (move_vars.cl_forwardspeed * pressed("forward")
+                - move_vars.cl_backspeed * pressed("back"));
+        velocity += right * move_vars.cl_sidespeed * (pressed("moveright") - pressed("moveleft"));
+        velocity.z += move_vars.cl_upspeed * (pressed("moveup") - pressed("movedown"));
+
+        camera.origin += velocity * speed * frame_time;
+    }
+
+    /// Start the next requested capture, and finish the current one once its pixels are back.
+    pub fn update_capture(
+        registry: Res<Registry>,
+        device: Res<RenderDevice>,
+        window: Query<&Window, With<PrimaryWindow>>,
+        mut cameras: Query<&mut Camera, With<Camera3d>>,
+        mut images: ResMut<Assets<Image>>,
+        mut captures: ResMut<PhotoCaptures>,
+        mut photo: ResMut<PhotoMode>,
+    ) {
+        // only borrow mutably during a capture, so that photo mode isn't extracted every frame
+        if let Some(id) = photo.capture.as_ref().map(|capture| capture.id) {
+            // results for captures that were abandoned are dropped
+            let Some((_, result)) = captures.recv.try_iter().find(|(i, _)| *i == id) else {
+                if let Some(capture) = &mut photo.capture {
+                    capture.frames += 1;
+                }
+                return;
+            };
+
+            let Capture { width, height, .. } = photo.capture.take().unwrap();
+            for mut camera in &mut cameras {
+                camera.target = RenderTarget::Window(WindowRef::Primary);
+            }
+
+            let image = result.map(|data| {
+                Image::new(
+                    Extent3d {
+                        width,
+                        height,
+                        depth_or_array_layers: 1,
+                    },
+                    TextureDimension::D2,
+                    data,
+                    CAPTURE_FORMAT,
+                    RenderAssetUsages::MAIN_WORLD,
+                )
+            });
+            if let Some(on_captured) = captures.current.take() {
+                on_captured(image);
+            }
+        }
+
+        let Some(on_captured) = captures.queued.pop_front() else {
+            return;
+        };
+        let Ok(window) = window.get_single() else {
+            return;
+        };
+
+        let scale = registry
+            .read_cvars::<PhotoVars>()
+            .map_or(1, |vars| (vars.scale.round() as u32).clamp(1, MAX_SCALE));
+        let max_size = device.limits().max_texture_dimension_2d;
+        let width = (window.physical_width() * scale).clamp(1, max_size);
+        let height = (window.physical_height() * scale).clamp(1, max_size);
+
+        let image = images.add(capture_image(width, height));
+        for mut camera in &mut cameras {
+            camera.target = RenderTarget::Image(image.clone());
+        }
+
+        let id = captures.next_id;
+        captures.next_id += 1;
+        captures.current = Some(on_captured);
+        photo.capture = Some(Capture {
+            id,
+            image,
+            width,
+            height,
+            frames: 0,
+            send: captures.send.clone(),
+        });
+    }
+
+    /// Copy a capture's image back from the GPU once enough frames have been rendered to it.
+    /// This runs in the render world after the frame has been submitted.
+    pub fn read_back_capture(
+        photo: Res<PhotoMode>,
+        images: Res<RenderAssets<Image>>,
+        device: Res<RenderDevice>,
+        queue: Res<RenderQueue>,
+        mut read: Local<Option<u64>>,
+    ) {
+        let Some(capture) = &photo.capture else {
+            return;
+        };
+        if capture.frames < CAPTURE_FRAMES || *read == Some(capture.id) {
+            return;
+        }
+        let Some(image) = images.get(&capture.image) else {
+            return;
+        };
+        *read = Some(capture.id);
+
+        // four bytes per pixel, for `CAPTURE_FORMAT`
+        let row_bytes = capture.width as usize * 4;
+        let padded_row_bytes = RenderDevice::align_copy_bytes_per_row(row_bytes);
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("photo capture buffer"),
+            size: (padded_row_bytes * capture.height as usize) as u64,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("photo capture encoder"),
+        });
+        encoder.copy_texture_to_buffer(
+            image.texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(padded_row_bytes as u32),
+                    rows_per_image: None,
+                },
+            },
+            Extent3d {
+                width: capture.width,
+                height: capture.height,
+                depth_or_array_layers: 1,
+            },
+        );
+        queue.submit([encoder.finish()]);
+
+        // pictures are rare enough that blocking until the copy is done is fine
+        let slice = buffer.slice(..);
+        let (mapped_send, mapped_recv) = crossbeam_channel::bounded(1);
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = mapped_send.send(result);
+        });
+        device.poll(wgpu::Maintain::Wait);
+
+        let result = match mapped_recv.try_recv() {
+            Ok(Ok(())) => Ok(strip_row_padding(
+                &slice.get_mapped_range(),
+                row_bytes,
+                padded_row_bytes,
+            )),
+            Ok(Err(e)) => Err(e),
+            Err(_) => Err(wgpu::BufferAsyncError),
+        };
+        // the receiver lives as long as the app
+        let _ = capture.send.send((capture.id, result));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use cgmath::InnerSpace as _;
+
+    use super::*;
+
+    #[test]
+    fn test_move_vectors() {
+        let close = |a: Vector3<f32>, b: Vector3<f32>| (a - b).magnitude() < 1e-5;
+
+        let (forward, right) = move_vectors(Angles::zero());
+        assert!(close(forward, Vector3::unit_x()));
+        assert!(close(right, -Vector3::unit_y()));
+
+        let (forward, right) = move_vectors(Angles {
+            pitch: Deg(90.0),
+            roll: Deg(0.0),
+            yaw: Deg(90.0),
+        });
+        assert!(close(forward, -Vector3::unit_z()));
+        assert!(close(right, Vector3::unit_x()));
+    }
+
+    #[test]
+    fn test_strip_row_padding() {
+        let data = [1, 2, 3, 0, 0, 4, 5, 6, 0, 0];
+        assert_eq!(strip_row_padding(&data, 3, 5), vec![1, 2, 3, 4, 5, 6]);
+    }
+}
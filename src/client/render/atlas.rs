@@ -0,0 +1,132 @@
+//! Packing small images into large shared textures.
+//!
+//! Images are placed on pages of a fixed size the way GLQuake packs lightmaps: each page tracks the
+//! height filled so far in every column, and an image goes where the tallest column it would cover
+//! is lowest. A new page is started when an image doesn't fit on any of the others.
+
+/// Where an image was placed in the atlas.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AtlasRect {
+    pub page: usize,
+    pub x: u32,
+    pub y: u32,
+}
+
+pub struct AtlasAllocator {
+    width: u32,
+    height: u32,
+
+    /// The filled height of each column of each page.
+    pages: Vec<Vec<u32>>,
+}
+
+impl AtlasAllocator {
+    pub fn new(width: u32, height: u32) -> AtlasAllocator {
+        AtlasAllocator {
+            width,
+            height,
+            pages: Vec::new(),
+        }
+    }
+
+    pub fn page_count(&self) -> usize {
+        self.pages.len()
+    }
+
+    /// The height of the part of `page` that's been allocated, so that the page's texture doesn't
+    /// need to be any taller.
+    pub fn used_height(&self, page: usize) -> u32 {
+        self.pages[page].iter().copied().max().unwrap_or(0)
+    }
+
+    /// Find a place for a `width` by `height` image, or `None` if it's bigger than a page.
+    pub fn allocate(&mut self, width: u32, height: u32) -> Option<AtlasRect> {
+        if width > self.width || height > self.height {
+            return None;
+        }
+
+        for (page, columns) in self.pages.iter_mut().enumerate() {
+            if let Some((x, y)) = Self::place(columns, self.height, width, height) {
+                return Some(AtlasRect { page, x, y });
+            }
+        }
+
+        let mut columns = vec![0; self.width as usize];
+        let (x, y) = Self::place(&mut columns, self.height, width, height)?;
+        self.pages.push(columns);
+        Some(AtlasRect {
+            page: self.pages.len() - 1,
+            x,
+            y,
+        })
+    }
+
+    fn place(columns: &mut [u32], page_height: u32, width: u32, height: u32) -> Option<(u32, u32)> {
+        let width = width as usize;
+        let (x, y) = columns
+            .windows(width.max(1))
+            .map(|window| window.iter().copied().max().unwrap_or(0))
+            .enumerate()
+            .min_by_key(|&(_, y)| y)?;
+
+        if y + height > page_height {
+            return None;
+        }
+
+        for column in &mut columns[x..x + width] {
+            *column = y + height;
+        }
+
+        Some((x as u32, y))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_allocate() {
+        let mut atlas = AtlasAllocator::new(4, 4);
+
+        assert_eq!(
+            atlas.allocate(2, 2),
+            Some(AtlasRect {
+                page: 0,
+                x: 0,
+                y: 0
+            })
+        );
+        assert_eq!(
+            atlas.allocate(2, 3),
+            Some(AtlasRect {
+                page: 0,
+                x: 2,
+                y: 0
+            })
+        );
+        // goes on top of the lower of the two
+        assert_eq!(
+            atlas.allocate(2, 1),
+            Some(AtlasRect {
+                page: 0,
+                x: 0,
+                y: 2
+            })
+        );
+        // doesn't fit on the first page any more
+        assert_eq!(
+            atlas.allocate(3, 3),
+            Some(AtlasRect {
+                page: 1,
+                x: 0,
+                y: 0
+            })
+        );
+        assert_eq!(atlas.page_count(), 2);
+        assert_eq!(atlas.used_height(0), 3);
+        assert_eq!(atlas.used_height(1), 3);
+
+        assert_eq!(atlas.allocate(5, 1), None);
+    }
+}
@@ -0,0 +1,288 @@
+//! Block compression for diffuse textures.
+//!
+//! Textures are transcoded to BC1 when they're loaded, which takes them from 4 bytes per texel to
+//! half a byte. Transcoding a whole level's worth of textures is slow enough to notice, so the
+//! result is cached on disk, keyed by the texture contents.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use bevy::prelude::*;
+
+/// The size of a single BC1 block, which covers 4x4 texels.
+pub const BC1_BLOCK_SIZE: usize = 8;
+
+/// Whether a texture of the given size can be stored block-compressed.
+///
+/// wgpu requires block-compressed textures to be a whole number of blocks.
+pub fn can_compress(width: u32, height: u32) -> bool {
+    width > 0 && height > 0 && width % 4 == 0 && height % 4 == 0
+}
+
+fn to_565(c: [u8; 3]) -> u16 {
+    ((c[0] as u16 >> 3) << 11) | ((c[1] as u16 >> 2) << 5) | (c[2] as u16 >> 3)
+}
+
+fn from_565(c: u16) -> [u8; 3] {
+    let r = ((c >> 11) & 0x1F) as u8;
+    let g = ((c >> 5) & 0x3F) as u8;
+    let b = (c & 0x1F) as u8;
+    [
+        (r << 3) | (r >> 2),
+        (g << 2) | (g >> 4),
+        (b << 3) | (b >> 2),
+    ]
+}
+
+fn lerp(a: [u8; 3], b: [u8; 3], num: u32, den: u32) -> [u8; 3] {
+    std::array::from_fn(|i| ((a[i] as u32 * (den - num) + b[i] as u32 * num) / den) as u8)
+}
+
+fn distance(a: [u8; 3], b: [u8; 3]) -> u32 {
+    a.iter()
+        .zip(b.iter())
+        .map(|(a, b)| (*a as i32 - *b as i32).pow(2) as u32)
+        .sum()
+}
+
+/// Compress a single block of 16 RGBA texels.
+///
+/// Endpoints are the bounding box of the opaque texels' colors. Blocks with any texel below half
+/// alpha use BC1's three-color mode, where the fourth index is transparent black.
+fn compress_block(texels: &[[u8; 4]; 16]) -> [u8; BC1_BLOCK_SIZE] {
+    let mut min = [u8::MAX; 3];
+    let mut max = [u8::MIN; 3];
+    let mut transparent = false;
+    let mut opaque = false;
+
+    for texel in texels {
+        if texel[3] < 128 {
+            transparent = true;
+            continue;
+        }
+
+        opaque = true;
+        for (i, c) in texel[..3].iter().enumerate() {
+            min[i] = min[i].min(*c);
+            max[i] = max[i].max(*c);
+        }
+    }
+
+    if !opaque {
+        min = [0; 3];
+        max = [0; 3];
+    }
+
+    let (a, b) = (to_565(max), to_565(min));
+
+    // the endpoint order selects the mode: c0 > c1 is four colors, c0 <= c1 is three colors plus
+    // transparent
+    let (c0, c1) = if transparent {
+        (a.min(b), a.max(b))
+    } else {
+        (a.max(b), a.min(b))
+    };
+
+    let (e0, e1) = (from_565(c0), from_565(c1));
+    let palette = if c0 > c1 {
+        BlockPalette::Four([e0, e1, lerp(e0, e1, 1, 3), lerp(e0, e1, 2, 3)])
+    } else {
+        BlockPalette::Three([e0, e1, lerp(e0, e1, 1, 2)])
+    };
+
+    let mut indices = 0u32;
+    for (i, texel) in texels.iter().enumerate() {
+        let index = if texel[3] < 128 {
+            3
+        } else {
+            let color = [texel[0], texel[1], texel[2]];
+            palette
+                .colors()
+                .iter()
+                .enumerate()
+                .min_by_key(|(_, c)| distance(**c, color))
+                .map(|(i, _)| i as u32)
+                .unwrap()
+        };
+
+        indices |= index << (i * 2);
+    }
+
+    let mut block = [0; BC1_BLOCK_SIZE];
+    block[0..2].copy_from_slice(&c0.to_le_bytes());
+    block[2..4].copy_from_slice(&c1.to_le_bytes());
+    block[4..8].copy_from_slice(&indices.to_le_bytes());
+    block
+}
+
+enum BlockPalette {
+    Four([[u8; 3]; 4]),
+    Three([[u8; 3]; 3]),
+}
+
+impl BlockPalette {
+    fn colors(&self) -> &[[u8; 3]] {
+        match self {
+            BlockPalette::Four(c) => c,
+            BlockPalette::Three(c) => c,
+        }
+    }
+}
+
+/// Compress RGBA8 texture data to BC1.
+///
+/// `width` and `height` must be multiples of 4 (see `can_compress`).
+pub fn compress_bc1(rgba: &[u8], width: u32, height: u32) -> Vec<u8> {
+    let (width, height) = (width as usize, height as usize);
+    assert_eq!(rgba.len(), width * height * 4);
+    assert!(can_compress(width as u32, height as u32));
+
+    let mut out = Vec::with_capacity(width * height / 16 * BC1_BLOCK_SIZE);
+    let mut texels = [[0; 4]; 16];
+
+    for block_y in (0..height).step_by(4) {
+        for block_x in (0..width).step_by(4) {
+            for (i, texel) in texels.iter_mut().enumerate() {
+                let x = block_x + i % 4;
+                let y = block_y + i / 4;
+                let ofs = (y * width + x) * 4;
+                texel.copy_from_slice(&rgba[ofs..ofs + 4]);
+            }
+
+            out.extend_from_slice(&compress_block(&texels));
+        }
+    }
+
+    out
+}
+
+// FNV-1a, which unlike `DefaultHasher` is stable between builds
+fn hash(data: &[u8]) -> u64 {
+    data.iter().fold(0xCBF29CE484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001B3)
+    })
+}
+
+/// An on-disk cache of compressed textures.
+#[derive(Resource, Clone, Debug, Default)]
+pub struct TextureCache {
+    dir: Option<PathBuf>,
+}
+
+impl TextureCache {
+    /// A cache stored in `dir`, which is created when the first texture is written.
+    pub fn new<P>(dir: P) -> TextureCache
+    where
+        P: AsRef<Path>,
+    {
+        TextureCache {
+            dir: Some(dir.as_ref().to_owned()),
+        }
+    }
+
+    /// A cache that doesn't store anything, so every texture is compressed on load.
+    pub fn uncached() -> TextureCache {
+        TextureCache { dir: None }
+    }
+
+    /// Compress `rgba` to BC1, or read it from the cache if it's been compressed before.
+    pub fn bc1(&self, rgba: &[u8], width: u32, height: u32) -> Vec<u8> {
+        let Some(dir) = &self.dir else {
+            return compress_bc1(rgba, width, height);
+        };
+
+        let expected_len = (width * height / 2) as usize;
+        let path = dir.join(format!("{:016x}_{}x{}.bc1", hash(rgba), width, height));
+
+        if let Ok(data) = fs::read(&path) {
+            if data.len() == expected_len {
+                return data;
+            }
+        }
+
+        let data = compress_bc1(rgba, width, height);
+        if let Err(e) = fs::create_dir_all(dir).and_then(|_| fs::write(&path, &data)) {
+            warn!(
+                "Couldn't cache compressed texture {}: {}",
+                path.display(),
+                e
+            );
+        }
+
+        data
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn decode_block(block: &[u8]) -> [[u8; 4]; 16] {
+        let c0 = u16::from_le_bytes([block[0], block[1]]);
+        let c1 = u16::from_le_bytes([block[2], block[3]]);
+        let indices = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+        let (e0, e1) = (from_565(c0), from_565(c1));
+
+        let mut out = [[0; 4]; 16];
+        for (i, texel) in out.iter_mut().enumerate() {
+            let color = match ((indices >> (i * 2)) & 3, c0 > c1) {
+                (0, _) => Some(e0),
+                (1, _) => Some(e1),
+                (2, true) => Some(lerp(e0, e1, 1, 3)),
+                (3, true) => Some(lerp(e0, e1, 2, 3)),
+                (2, false) => Some(lerp(e0, e1, 1, 2)),
+                _ => None,
+            };
+
+            *texel = match color {
+                Some([r, g, b]) => [r, g, b, 255],
+                None => [0; 4],
+            };
+        }
+
+        out
+    }
+
+    #[test]
+    fn test_compress_solid() {
+        let rgba = [255, 0, 0, 255].repeat(16);
+        let out = compress_bc1(&rgba, 4, 4);
+        assert_eq!(out.len(), BC1_BLOCK_SIZE);
+        assert!(decode_block(&out)
+            .iter()
+            .all(|texel| *texel == [255, 0, 0, 255]));
+    }
+
+    #[test]
+    fn test_compress_gradient() {
+        let rgba: Vec<u8> = (0..16u8)
+            .flat_map(|i| [i * 16, i * 16, i * 16, 255])
+            .collect();
+        let out = compress_bc1(&rgba, 4, 4);
+        let decoded = decode_block(&out);
+
+        for (texel, expected) in decoded.iter().zip(rgba.chunks(4)) {
+            assert!((texel[0] as i32 - expected[0] as i32).abs() <= 48);
+            assert_eq!(texel[3], 255);
+        }
+    }
+
+    #[test]
+    fn test_compress_transparent() {
+        let mut rgba = [0, 0, 255, 255].repeat(16);
+        rgba[3] = 0;
+        let out = compress_bc1(&rgba, 4, 4);
+        let decoded = decode_block(&out);
+
+        assert_eq!(decoded[0][3], 0);
+        assert!(decoded[1..].iter().all(|texel| *texel == [0, 0, 255, 255]));
+    }
+
+    #[test]
+    fn test_compress_size() {
+        let rgba = vec![0; 16 * 8 * 4];
+        assert_eq!(compress_bc1(&rgba, 16, 8).len(), 4 * 2 * BC1_BLOCK_SIZE);
+    }
+}
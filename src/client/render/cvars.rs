@@ -20,7 +20,7 @@
 
 use bevy::prelude::*;
 
-use crate::common::console::RegisterCmdExt;
+use crate::common::console::{Cvar, RegisterCmdExt};
 
 pub fn register_cvars(app: &mut App) {
     // TODO: Implement this
@@ -35,6 +35,111 @@ pub fn register_cvars(app: &mut App) {
         "1",
         "set the multi-sampled anti-aliasing sample count",
     )
+    .cvar(
+        "r_texture_compression",
+        Cvar::new("0").archive(),
+        "compress world and model textures to save video memory (from the next map on)",
+    )
+    .cvar(
+        "r_externaltextures",
+        Cvar::new("1").archive(),
+        "replace map textures with images from textures/ when they exist (from the next map on)",
+    )
+    .cvar(
+        "r_scale",
+        Cvar::new("1").archive(),
+        "render the world at this fraction of the window resolution (0.25 to 1), the HUD and menus stay sharp",
+    )
+    .cvar(
+        "gamma",
+        Cvar::new("1").archive(),
+        "Adjust the gamma of the screen, where higher values are brighter",
+    )
+    .cvar(
+        "contrast",
+        Cvar::new("1").archive(),
+        "Adjust the contrast of the screen, where 1 is unchanged",
+    )
+    .cvar(
+        "r_vignette",
+        Cvar::new("0").archive(),
+        "Darken the corners of the screen by this much, from 0 to 1",
+    )
+    .cvar(
+        "r_dither",
+        Cvar::new("0").archive(),
+        "Add a little noise to the screen to hide banding in smooth gradients",
+    )
+    .cvar(
+        "r_waterwarp",
+        Cvar::new("1").archive(),
+        "How much to warp the screen when the view is under water, slime or lava (0 to disable)",
+    )
+    .cvar(
+        "r_bloom",
+        Cvar::new("1").archive(),
+        "Enable/disable bloom around the brightest parts of the screen",
+    )
+    .cvar(
+        "r_bloom_intensity",
+        Cvar::new("0.15").archive(),
+        "How strongly bloom is added to the screen",
+    )
+    .cvar(
+        "r_bloom_threshold",
+        Cvar::new("1").archive(),
+        "Only bloom lighting brighter than this, where 1 is a surface at full light (overbright lights and fullbrights go past it)",
+    )
+    .cvar(
+        "r_wateralpha",
+        Cvar::new("1").archive(),
+        "How opaque to draw water, slime and lava, from 0 to 1 (maps need to be vised for water to see through it)",
+    )
+    .cvar(
+        "r_lerpmodels",
+        Cvar::new("1").archive(),
+        "Smoothly blend between the animation frames of models",
+    )
+    .cvar(
+        "r_drawviewmodel",
+        Cvar::new("1").archive(),
+        "Draw the weapon in front of the view",
+    )
+    .cvar(
+        "r_particles",
+        Cvar::new("1").archive(),
+        "The style of particles: 0 for Quake's square ones, 1 for round ones that fade into surfaces",
+    )
+    .cvar(
+        "r_particlesize",
+        Cvar::new("1").archive(),
+        "The size of round particles (see r_particles)",
+    )
+    .cvar(
+        "r_particleblend",
+        Cvar::new("alpha").archive(),
+        "How round particles are blended (see r_particles): \"alpha\" or \"additive\"",
+    )
+    .cvar(
+        "r_particlesoftness",
+        Cvar::new("8").archive(),
+        "How far round particles fade out over where they meet a surface (see r_particles)",
+    )
+    .cvar(
+        "r_showcull",
+        "0",
+        "Log how many leaves and entities are culled for being out of view each frame",
+    )
+    .cvar(
+        "r_showfps",
+        Cvar::new("0").archive(),
+        "Show the frame rate and frame time in the corner of the screen",
+    )
+    .cvar(
+        "r_speeds",
+        "0",
+        "Show draw calls, polygons and texture uploads for each frame in the corner of the screen",
+    )
     .cvar(
         "post_blendmode",
         "softlight",
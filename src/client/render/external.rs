@@ -0,0 +1,116 @@
+//! External replacements for the textures in BSP files.
+//!
+//! With `r_externaltextures` on, a brush texture is replaced by `textures/<name>.tga` or
+//! `textures/<name>.png` if either exists, as in FitzQuake-derived engines. Since `*` can't be used
+//! in file names, the `*` at the start of liquid textures is written as `#`, so `*water0` is
+//! replaced by `textures/#water0.tga`. Frames of animated textures are replaced individually, by
+//! their full names like `+0button`.
+//!
+//! The fullbright parts of a replaced texture come from `textures/<name>_glow` or
+//! `textures/<name>_luma` in the same formats. Replacements without one have no fullbright texels,
+//! since the palette indices of the original texture don't line up with the new image.
+
+use std::io::Read as _;
+
+use bevy::prelude::*;
+
+use crate::common::vfs::Vfs;
+
+const EXTENSIONS: [&str; 2] = ["tga", "png"];
+const FULLBRIGHT_SUFFIXES: [&str; 2] = ["_glow", "_luma"];
+
+/// A decoded replacement texture.
+pub struct ExternalTexture {
+    pub width: u32,
+    pub height: u32,
+    pub rgba: Vec<u8>,
+
+    /// The fullbright mask, with its own width and height.
+    pub fullbright: Option<(u32, u32, Vec<u8>)>,
+}
+
+/// Looks up replacement textures in the virtual filesystem.
+#[derive(Clone)]
+pub struct ExternalTextures {
+    vfs: Vfs,
+}
+
+impl ExternalTextures {
+    pub fn new(vfs: Vfs) -> ExternalTextures {
+        ExternalTextures { vfs }
+    }
+
+    /// Load the replacement for the texture named `name`, if there is one.
+    pub fn load(&self, name: &str) -> Option<ExternalTexture> {
+        let stem = file_stem(name);
+        let (width, height, rgba) = self.load_image(&stem)?;
+        let fullbright = FULLBRIGHT_SUFFIXES
+            .iter()
+            .find_map(|suffix| self.load_image(&format!("{}{}", stem, suffix)))
+            .map(|(width, height, rgba)| (width, height, fullbright_mask(&rgba)));
+
+        debug!("Replacing texture {} with textures/{}", name, stem);
+        Some(ExternalTexture {
+            width,
+            height,
+            rgba,
+            fullbright,
+        })
+    }
+
+    fn load_image(&self, stem: &str) -> Option<(u32, u32, Vec<u8>)> {
+        EXTENSIONS.iter().find_map(|ext| {
+            let path = format!("textures/{}.{}", stem, ext);
+            let mut data = Vec::new();
+            self.vfs.open(&path).ok()?.read_to_end(&mut data).ok()?;
+
+            match image::load_from_memory(&data) {
+                Ok(image) => {
+                    let image = image.into_rgba8();
+                    let (width, height) = image.dimensions();
+                    Some((width, height, image.into_raw()))
+                }
+                Err(e) => {
+                    warn!("Couldn't load {}: {}", path, e);
+                    None
+                }
+            }
+        })
+    }
+}
+
+/// The file name, without an extension, of the replacement for the texture named `name`.
+fn file_stem(name: &str) -> String {
+    name.to_lowercase().replace('*', "#")
+}
+
+/// Convert a glow map to the single channel fullbright format, where black or transparent texels
+/// aren't fullbright.
+fn fullbright_mask(rgba: &[u8]) -> Vec<u8> {
+    rgba.chunks_exact(4)
+        .map(|texel| {
+            let brightest = texel[0].max(texel[1]).max(texel[2]) as u32;
+            (brightest * texel[3] as u32 / 255) as u8
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_file_stem() {
+        assert_eq!(file_stem("*WATER0"), "#water0");
+        assert_eq!(file_stem("+0button"), "+0button");
+        assert_eq!(file_stem("sky1"), "sky1");
+    }
+
+    #[test]
+    fn test_fullbright_mask() {
+        let rgba = [
+            0, 0, 0, 255, 255, 10, 10, 255, 200, 100, 0, 0, 255, 255, 255, 128,
+        ];
+        assert_eq!(fullbright_mask(&rgba), vec![0, 255, 0, 128]);
+    }
+}
@@ -26,6 +26,7 @@
 /// - Initial geometry pass
 ///   - Inputs:
 ///     - `AliasPipeline`
+///     - `IqmPipeline`
 ///     - `BrushPipeline`
 ///     - `SpritePipeline`
 ///   - Output: `InitialPassTarget`
@@ -35,6 +36,10 @@
 ///     - `QuadPipeline`
 ///     - `GlyphPipeline`
 ///   - Output: `DeferredPassTarget`
+/// - Bloom pass
+///   - Inputs:
+///     - `BloomPipeline`
+///   - Output: the view target, with the bloom added
 /// - Final pass
 ///   - Inputs:
 ///     - `PostProcessPipeline`
@@ -43,16 +48,25 @@
 ///   - Inputs:
 ///     - `BlitPipeline`
 ///   - Output: `SwapChainTarget`
+///
+/// With `r_scale` below 1, the initial and deferred passes only draw to the top-left of their
+/// targets, and the final pass stretches that to fill the window. The UI is drawn afterwards, so
+/// it stays at the window's resolution.
+mod atlas;
+mod compress;
 mod cvars;
 mod error;
+mod external;
 pub mod palette;
 mod pipeline;
+mod stats;
 mod target;
 mod ui;
 mod uniform;
 mod warp;
 mod world;
 
+use arrayvec::ArrayVec;
 use beef::Cow;
 use bevy::{
     core_pipeline::{
@@ -65,30 +79,34 @@ use bevy::{
     },
     prelude::*,
     render::{
-        extract_resource::{ExtractResource, ExtractResourcePlugin},
+        extract_resource::{extract_resource, ExtractResource, ExtractResourcePlugin},
         render_graph::{RenderGraphApp, ViewNodeRunner},
         render_resource::{
             BindGroup, BindGroupLayout, Buffer, Sampler, SpecializedRenderPipelines, Texture,
             TextureView,
         },
         renderer::{RenderDevice, RenderQueue},
-        view::ViewTarget,
-        Render, RenderApp, RenderSet,
+        view::{create_surfaces, ViewTarget},
+        Extract, ExtractSchedule, Render, RenderApp, RenderSet,
     },
+    tasks::{AsyncComputeTaskPool, Task},
     ui::graph::NodeUi,
     window::PrimaryWindow,
 };
+use cgmath::{Deg, Vector3};
+use chrono::Duration;
+pub use compress::TextureCache;
 pub use cvars::register_cvars;
 pub use error::{RenderError, RenderErrorKind};
 pub use palette::Palette;
-use parking_lot::RwLock;
-pub use pipeline::Pipeline;
+use parking_lot::{Mutex, RwLock};
+pub use pipeline::{Pipeline, ShaderCompiler};
 pub use postprocess::PostProcessBindGroup;
 use serde::{Deserialize, Serialize};
 pub use target::{PreferredFormat, RenderTarget, RenderTargetResolve};
-pub use ui::{hud::HudState, UiRenderer, UiState};
+pub use ui::{charset::Charset, hud::HudState, UiRenderer, UiState};
 pub use world::{
-    deferred::{DeferredRenderer, DeferredUniforms, PointLight},
+    deferred::{DeferredRenderer, DeferredRenderers, DeferredUniforms, PointLight},
     Camera,
 };
 
@@ -97,42 +115,76 @@ use std::{
     mem::size_of,
     num::NonZeroU64,
     ops::{Deref, DerefMut},
+    sync::Arc,
 };
 
 use crate::{
     client::{
-        input::InputFocus,
+        ghost::GhostView,
+        input::{touch::TouchControls, InputFocus},
+        locale::Locale,
         menu::Menu,
+        photo::{self, PhotoMode},
         render::{
-            ui::{glyph::GlyphPipeline, hud::HudVars, quad::QuadPipeline},
+            ui::{
+                glyph::GlyphPipeline,
+                hud::{HudVars, MissionPack, Scoreboard},
+                quad::QuadPipeline,
+            },
             uniform::DynamicUniformBuffer,
             world::{
-                alias::AliasPipeline,
+                alias::{AliasInstanceBuffer, AliasPipeline},
+                bloom::{BloomPipeline, BloomTextures, BloomVars},
                 brush::BrushPipeline,
                 deferred::DeferredPipeline,
-                particle::ParticlePipeline,
-                postprocess::{self, PostProcessPipeline, PostProcessVars},
+                iqm::IqmPipeline,
+                particle::{ParticleBlend, ParticlePipeline},
+                postprocess::{
+                    self, EffectVars, FlashVars, PostProcessBindGroups, PostProcessPipeline,
+                    PostProcessVars,
+                },
+                sky::Skybox,
                 sprite::SpritePipeline,
                 EntityUniforms,
             },
         },
+        runtimer::RunTimer,
+        video::{self, VidRestart},
+        SeismonGameSettings,
+    },
+    common::{
+        console::{ConsoleOutput, NotifyView, Registry},
+        engine,
+        math::{self, Angles},
+        net::{ClientStat, ColorShift, GameType, ItemFlags, MAX_ITEMS},
+        vfs::Vfs,
+        wad::Wad,
     },
-    common::{console::Registry, vfs::Vfs, wad::Wad},
 };
 
 use self::{
+    external::ExternalTextures,
     target::{InitPass, InitPassLabel},
     ui::{UiPass, UiPassLabel},
     world::{
+        bloom::{BloomPass, BloomPassLabel},
         deferred::{DeferredPass, DeferredPassLabel},
         extract_world_renderer,
         postprocess::{PostProcessPass, PostProcessPassLabel},
+        ModelRenderers, WorldRenderer,
     },
 };
 
 use failure::Error;
-
-use super::{state::ClientState, Connection, ConnectionKind, ConnectionState};
+use futures::FutureExt as _;
+
+use super::{
+    entity::{particle::Particle, EntityModel, EntityTransform, Light},
+    fog::Fog,
+    state::{PlayerInfo, MAX_LIGHT_STYLES},
+    view::Fov,
+    Connection, ConnectionKind, ConnectionState, IntermissionKind, MAX_STATS,
+};
 
 pub struct SeismonRenderPlugin;
 
@@ -153,37 +205,93 @@ impl Plugin for SeismonRenderPlugin {
             ExtractResourcePlugin::<Menu>::default(),
             ExtractResourcePlugin::<RenderState>::default(),
             ExtractResourcePlugin::<InputFocus>::default(),
+            ExtractResourcePlugin::<TouchControls>::default(),
             ExtractResourcePlugin::<RenderVars>::default(),
+            ExtractResourcePlugin::<Fov>::default(),
             ExtractResourcePlugin::<HudVars>::default(),
+            ExtractResourcePlugin::<RunTimer>::default(),
+            ExtractResourcePlugin::<GhostView>::default(),
+            ExtractResourcePlugin::<Locale>::default(),
             ExtractResourcePlugin::<PostProcessVars>::default(),
+            ExtractResourcePlugin::<FlashVars>::default(),
+            ExtractResourcePlugin::<EffectVars>::default(),
             ExtractResourcePlugin::<ConnectionState>::default(),
+            ExtractResourcePlugin::<SeismonGameSettings>::default(),
             // TODO: Do all loading on the main thread (this is currently just for the palette and gfx wad)
             ExtractResourcePlugin::<Vfs>::default(),
         ));
 
+        // the tuple above is at its maximum size
+        app.add_plugins((
+            ExtractResourcePlugin::<VidRestart>::default(),
+            ExtractResourcePlugin::<PhotoMode>::default(),
+            ExtractResourcePlugin::<NotifyView>::default(),
+            ExtractResourcePlugin::<BloomVars>::default(),
+        ));
+
         register_cvars(app);
 
         extract_now::<Menu, Menu>(app);
         extract_now::<Vfs, Vfs>(app);
         extract_now::<ConnectionState, ConnectionState>(app);
+        extract_now::<Locale, Locale>(app);
     }
 
     fn finish(&self, app: &mut bevy::prelude::App) {
         extract_now::<RenderResolution, RenderResolution>(app);
 
+        let texture_cache = match app.world.get_resource::<SeismonGameSettings>() {
+            Some(settings) if cfg!(not(target_arch = "wasm32")) => {
+                TextureCache::new(settings.base_dir.join("cache").join("textures"))
+            }
+            _ => TextureCache::uncached(),
+        };
+
+        let graphics_error = GraphicsStateError::default();
+        app.insert_resource(graphics_error.clone())
+            .add_systems(Update, systems::show_graphics_state_error);
+
         let Ok(render_app) = app.get_sub_app_mut(RenderApp) else {
             return;
         };
 
         render_app
+            .insert_resource(texture_cache)
+            .insert_resource(graphics_error)
+            .init_resource::<ModelRenderers>()
+            .init_resource::<DeferredRenderers>()
+            .init_resource::<PostProcessBindGroups>()
+            .init_resource::<stats::RenderSpeeds>()
+            .add_systems(
+                ExtractSchedule,
+                systems::extract_entities.after(extract_resource::<RenderState>),
+            )
+            .init_resource::<BloomPipeline>()
+            .init_resource::<BloomTextures>()
             .init_resource::<PostProcessPipeline>()
             .init_resource::<SpecializedRenderPipelines<PostProcessPipeline>>()
             .add_systems(
                 Render,
                 (
+                    systems::restart_graphics_state.run_if(
+                        resource_exists::<VidRestart>.and_then(resource_changed::<VidRestart>),
+                    ),
+                    // the palette and textures come from the VFS, so rebuild if the game changes
+                    // don't retry after a failure until there are new files to try with
                     systems::create_graphics_state.run_if(
                         not(resource_exists::<GraphicsState>)
-                            .or_else(resource_changed::<RenderResolution>),
+                            .and_then(not(resource_exists::<PendingGraphicsState>))
+                            .and_then(|error: Res<GraphicsStateError>| error.get().is_none())
+                            .or_else(resource_changed::<Vfs>),
+                    ),
+                    systems::finish_graphics_state.run_if(resource_exists::<PendingGraphicsState>),
+                    // the state may have been built for a different target than the current one
+                    systems::update_graphics_state.run_if(
+                        resource_exists::<GraphicsState>.and_then(
+                            resource_changed::<RenderResolution>
+                                .or_else(resource_changed::<RenderVars>)
+                                .or_else(resource_added::<GraphicsState>),
+                        ),
                     ),
                     systems::create_menu_renderer.run_if(
                         resource_exists::<GraphicsState>.and_then(
@@ -192,14 +300,32 @@ impl Plugin for SeismonRenderPlugin {
                     ),
                     extract_world_renderer.run_if(
                         resource_changed::<ConnectionState>
+                            .or_else(resource_added::<GraphicsState>)
                             .and_then(resource_exists::<GraphicsState>),
                     ),
+                    systems::update_skybox.run_if(resource_exists::<GraphicsState>),
+                    systems::reserve_entity_uniforms.run_if(
+                        resource_exists::<GraphicsState>.and_then(resource_exists::<RenderState>),
+                    ),
                 )
                     .chain()
                     .in_set(RenderSet::Prepare),
             )
+            .add_systems(
+                Render,
+                video::systems::check_present_mode.before(create_surfaces),
+            )
+            .add_systems(
+                Render,
+                (
+                    stats::systems::collect_speeds,
+                    photo::systems::read_back_capture.run_if(resource_exists::<PhotoMode>),
+                )
+                    .in_set(RenderSet::Cleanup),
+            )
             .add_render_graph_node::<ViewNodeRunner<InitPass>>(Core3d, InitPassLabel)
             .add_render_graph_node::<ViewNodeRunner<DeferredPass>>(Core3d, DeferredPassLabel)
+            .add_render_graph_node::<ViewNodeRunner<BloomPass>>(Core3d, BloomPassLabel)
             .add_render_graph_node::<ViewNodeRunner<PostProcessPass>>(Core3d, PostProcessPassLabel)
             .add_render_graph_node::<ViewNodeRunner<UiPass>>(Core3d, UiPassLabel)
             .add_render_graph_edges(
@@ -208,6 +334,7 @@ impl Plugin for SeismonRenderPlugin {
                     Node3d::MainOpaquePass,
                     InitPassLabel,
                     DeferredPassLabel,
+                    BloomPassLabel,
                     PostProcessPassLabel,
                     Node3d::EndMainPass,
                 ),
@@ -218,7 +345,8 @@ impl Plugin for SeismonRenderPlugin {
 
 const DIFFUSE_TEXTURE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
 const FULLBRIGHT_TEXTURE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R8Unorm;
-const LIGHTMAP_TEXTURE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R8Unorm;
+// lightmaps are colored by .lit files, and there's no three-channel format to put them in
+const LIGHTMAP_TEXTURE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
 
 /// Create a `wgpu::TextureDescriptor` appropriate for the provided texture data.
 pub fn texture_descriptor<'a>(
@@ -275,7 +403,7 @@ pub fn create_texture<'a>(
         data.data(),
         wgpu::ImageDataLayout {
             offset: 0,
-            bytes_per_row: Some(width * data.stride()),
+            bytes_per_row: Some(data.bytes_per_row(width)),
             rows_per_image: None,
         },
         wgpu::Extent3d {
@@ -284,10 +412,188 @@ pub fn create_texture<'a>(
             depth_or_array_layers: 1,
         },
     );
+    stats::add(stats::Counter::UploadBytes, data.data().len() as u64);
 
     texture
 }
 
+/// Texture uploads recorded while a level loads, to be submitted all at once.
+///
+/// `queue.write_texture` stages and copies each texture on its own, which adds up for a level with
+/// hundreds of textures and lightmaps. Recorded uploads are instead packed into one staging
+/// buffer and copied with a single command buffer.
+#[derive(Default)]
+struct TextureUploads {
+    // rows are padded to `COPY_BYTES_PER_ROW_ALIGNMENT`, as buffer-to-texture copies require
+    staging: Vec<u8>,
+    copies: Vec<TextureUpload>,
+}
+
+struct TextureUpload {
+    texture: Texture,
+    offset: wgpu::BufferAddress,
+    bytes_per_row: u32,
+    extent: wgpu::Extent3d,
+}
+
+impl TextureUploads {
+    fn push(&mut self, texture: &Texture, width: u32, height: u32, data: &TextureData) {
+        if width == 0 || height == 0 {
+            return;
+        }
+
+        let row_len = data.bytes_per_row(width) as usize;
+        let padded_len = wgpu::util::align_to(row_len, wgpu::COPY_BYTES_PER_ROW_ALIGNMENT as usize);
+        let offset = self.staging.len() as wgpu::BufferAddress;
+
+        stats::add(stats::Counter::UploadBytes, data.data().len() as u64);
+        for row in data.data().chunks_exact(row_len) {
+            self.staging.extend_from_slice(row);
+            self.staging
+                .resize(self.staging.len() + padded_len - row_len, 0);
+        }
+
+        self.copies.push(TextureUpload {
+            texture: texture.clone(),
+            offset,
+            bytes_per_row: padded_len as u32,
+            extent: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        });
+    }
+
+    fn submit(self, device: &RenderDevice, queue: &RenderQueue) {
+        if self.copies.is_empty() {
+            return;
+        }
+
+        debug!(
+            "Uploading {} textures ({} bytes)",
+            self.copies.len(),
+            self.staging.len()
+        );
+
+        let staging = device.create_buffer_with_data(&wgpu::util::BufferInitDescriptor {
+            label: Some("texture upload staging buffer"),
+            contents: &self.staging,
+            usage: wgpu::BufferUsages::COPY_SRC,
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("texture upload encoder"),
+        });
+
+        for copy in &self.copies {
+            encoder.copy_buffer_to_texture(
+                wgpu::ImageCopyBuffer {
+                    buffer: &staging,
+                    layout: wgpu::ImageDataLayout {
+                        offset: copy.offset,
+                        bytes_per_row: Some(copy.bytes_per_row),
+                        rows_per_image: None,
+                    },
+                },
+                wgpu::ImageCopyTexture {
+                    texture: &copy.texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d::ZERO,
+                    aspect: Default::default(),
+                },
+                copy.extent,
+            );
+        }
+
+        queue.submit([encoder.finish()]);
+    }
+}
+
+/// A region of a texture which has changed since it was last uploaded.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DirtyRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl DirtyRect {
+    pub fn new(x: u32, y: u32, width: u32, height: u32) -> DirtyRect {
+        DirtyRect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    /// Grow this rectangle to also cover `other`.
+    pub fn union(&self, other: &DirtyRect) -> DirtyRect {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        let right = (self.x + self.width).max(other.x + other.width);
+        let bottom = (self.y + self.height).max(other.y + other.height);
+
+        DirtyRect::new(x, y, right - x, bottom - y)
+    }
+
+    /// Add `rect` to an optional accumulated dirty region.
+    pub fn extend(region: &mut Option<DirtyRect>, rect: DirtyRect) {
+        *region = Some(match region {
+            Some(r) => r.union(&rect),
+            None => rect,
+        });
+    }
+}
+
+/// Upload only the `rect` region of `data` to `texture`.
+///
+/// `data` holds the entire texture (`width` texels per row), so the rest of the texture is left as
+/// it was on the GPU. Used for lightmaps, where a flickering light only touches a few texels.
+pub fn write_texture_region(
+    queue: &RenderQueue,
+    texture: &wgpu::Texture,
+    width: u32,
+    data: &TextureData,
+    rect: DirtyRect,
+) {
+    if rect.width == 0 || rect.height == 0 {
+        return;
+    }
+
+    let stride = data.stride();
+
+    queue.write_texture(
+        wgpu::ImageCopyTexture {
+            texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d {
+                x: rect.x,
+                y: rect.y,
+                z: 0,
+            },
+            aspect: Default::default(),
+        },
+        data.data(),
+        wgpu::ImageDataLayout {
+            offset: ((rect.y * width + rect.x) * stride) as wgpu::BufferAddress,
+            bytes_per_row: Some(width * stride),
+            rows_per_image: None,
+        },
+        wgpu::Extent3d {
+            width: rect.width,
+            height: rect.height,
+            depth_or_array_layers: 1,
+        },
+    );
+    stats::add(
+        stats::Counter::UploadBytes,
+        (rect.width * rect.height * stride) as u64,
+    );
+}
+
 pub struct DiffuseData<'a> {
     pub rgba: Cow<'a, [u8]>,
 }
@@ -300,10 +606,17 @@ pub struct LightmapData<'a> {
     pub lightmap: Cow<'a, [u8]>,
 }
 
+/// Block-compressed texture data, stored a row of blocks at a time.
+pub struct CompressedData<'a> {
+    pub format: wgpu::TextureFormat,
+    pub blocks: Cow<'a, [u8]>,
+}
+
 pub enum TextureData<'a> {
     Diffuse(DiffuseData<'a>),
     Fullbright(FullbrightData<'a>),
     Lightmap(LightmapData<'a>),
+    Compressed(CompressedData<'a>),
 }
 
 impl<'a> TextureData<'a> {
@@ -312,6 +625,7 @@ impl<'a> TextureData<'a> {
             TextureData::Diffuse(_) => DIFFUSE_TEXTURE_FORMAT,
             TextureData::Fullbright(_) => FULLBRIGHT_TEXTURE_FORMAT,
             TextureData::Lightmap(_) => LIGHTMAP_TEXTURE_FORMAT,
+            TextureData::Compressed(d) => d.format,
         }
     }
 
@@ -320,9 +634,11 @@ impl<'a> TextureData<'a> {
             TextureData::Diffuse(d) => &d.rgba,
             TextureData::Fullbright(d) => &d.fullbright,
             TextureData::Lightmap(d) => &d.lightmap,
+            TextureData::Compressed(d) => &d.blocks,
         }
     }
 
+    /// The size of a texel, or of a block for block-compressed formats.
     pub fn stride(&self) -> u32 {
         use std::mem;
         use wgpu::TextureFormat::*;
@@ -336,10 +652,18 @@ impl<'a> TextureData<'a> {
             Rgba16Uint | Rgba16Sint | Rgba16Unorm | Rgba16Snorm | Rgba16Float => {
                 mem::size_of::<[u16; 4]>()
             }
+            Bc1RgbaUnorm | Bc1RgbaUnormSrgb => mem::size_of::<[u8; 8]>(),
+            Bc7RgbaUnorm | Bc7RgbaUnormSrgb => mem::size_of::<[u8; 16]>(),
             _ => todo!(),
         }) as u32
     }
 
+    /// The number of bytes in a row of `width` texels.
+    pub fn bytes_per_row(&self, width: u32) -> u32 {
+        let (block_width, _) = self.format().block_dimensions();
+        width.div_ceil(block_width) * self.stride()
+    }
+
     pub fn size(&self) -> wgpu::BufferAddress {
         self.data().len() as wgpu::BufferAddress
     }
@@ -382,16 +706,100 @@ impl FromWorld for RenderResolution {
     }
 }
 
-pub enum RenderConnectionKind {
-    Server,
-    Demo,
+/// The player's weapon, which is drawn in front of everything else from the camera.
+#[derive(Clone, Copy, Debug)]
+pub struct ViewModel {
+    pub renderer_id: usize,
+    pub frame_id: usize,
+    pub origin: Vector3<f32>,
+    pub angles: Angles,
+}
+
+/// An entity to draw, extracted from the client's ECS entities.
+#[derive(Clone, Copy, Debug)]
+pub struct RenderEntity {
+    pub origin: Vector3<f32>,
+    pub angles: Vector3<Deg<f32>>,
+    pub model_id: usize,
+    pub frame_id: usize,
+    /// The frame that alias models are blended from, by `1 - frame_blend`.
+    pub prev_frame_id: usize,
+    pub frame_blend: f32,
+    pub skin_id: usize,
+    pub alpha: f32,
 }
 
+impl RenderEntity {
+    pub fn get_origin(&self) -> Vector3<f32> {
+        self.origin
+    }
+
+    pub fn get_angles(&self) -> Vector3<Deg<f32>> {
+        self.angles
+    }
+
+    pub fn model_id(&self) -> usize {
+        self.model_id
+    }
+
+    pub fn frame_id(&self) -> usize {
+        self.frame_id
+    }
+
+    pub fn prev_frame_id(&self) -> usize {
+        self.prev_frame_id
+    }
+
+    pub fn frame_blend(&self) -> f32 {
+        self.frame_blend
+    }
+
+    pub fn skin_id(&self) -> usize {
+        self.skin_id
+    }
+
+    pub fn alpha(&self) -> f32 {
+        self.alpha
+    }
+}
+
+/// The parts of the client state read by the renderer, extracted each frame.
+///
+/// The entities are filled in separately by `systems::extract_entities`.
 #[derive(Resource)]
 pub struct RenderState {
-    // TODO: Make a stripped-down version of this
-    state: ClientState,
-    kind: RenderConnectionKind,
+    time: Duration,
+    camera_origin: Vector3<f32>,
+    camera_angles: Angles,
+    viewmodel_id: usize,
+    viewmodel_origin: Vector3<f32>,
+    viewmodel_frame: usize,
+
+    /// Whether the camera is the photo mode camera, which hides the view model and HUD.
+    photo_mode: bool,
+
+    entities: Vec<RenderEntity>,
+    particles: Vec<Particle>,
+    lights: Vec<Light>,
+    lightstyle_values: ArrayVec<f32, MAX_LIGHT_STYLES>,
+    color_shifts: [ColorShift; 4],
+    fog: Fog,
+    sky: Option<String>,
+    underwater: bool,
+
+    intermission: Option<IntermissionKind>,
+    start_time: Duration,
+    completion_time: Option<Duration>,
+    stats: [i32; MAX_STATS],
+    items: ItemFlags,
+    item_get_time: [Duration; MAX_ITEMS],
+    face_anim_time: Duration,
+
+    game_type: GameType,
+    max_players: usize,
+    /// The connected players' slots and info, from most to fewest frags.
+    players: Vec<(usize, PlayerInfo)>,
+    local_player: usize,
 }
 
 impl ExtractResource for RenderState {
@@ -399,17 +807,198 @@ impl ExtractResource for RenderState {
 
     fn extract_resource(source: &Self::Source) -> Self {
         let Connection { state, kind } = source;
+        let demo = matches!(kind, ConnectionKind::Demo(_));
 
         RenderState {
-            state: state.clone(),
-            kind: match kind {
-                ConnectionKind::Server { .. } => RenderConnectionKind::Server,
-                ConnectionKind::Demo(_) => RenderConnectionKind::Demo,
+            time: state.time(),
+            camera_origin: state.camera_origin(),
+            camera_angles: state.camera_angles(demo),
+            viewmodel_id: state.viewmodel_id(),
+            viewmodel_origin: state.viewmodel_origin(),
+            viewmodel_frame: state.viewmodel_frame(),
+            photo_mode: false,
+
+            entities: Vec::new(),
+            particles: state.iter_particles().copied().collect(),
+            lights: state.iter_lights().cloned().collect(),
+            lightstyle_values: state.lightstyle_values(),
+            color_shifts: state.color_shifts,
+            fog: state.fog,
+            sky: state.sky.clone(),
+            underwater: state.view_underwater(),
+
+            intermission: state.intermission().cloned(),
+            start_time: state.start_time(),
+            completion_time: state.completion_time(),
+            stats: state.stats,
+            items: state.items(),
+            item_get_time: state.item_get_time,
+            face_anim_time: state.face_anim_time(),
+
+            game_type: state.game_type,
+            max_players: state.max_players,
+            players: {
+                let mut players: Vec<_> = state
+                    .player_info
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(id, info)| Some((id, info.as_ref()?)))
+                    // the server clears the names of empty slots
+                    .filter(|(_, info)| !info.name.is_empty())
+                    .map(|(id, info)| (id, info.clone()))
+                    .collect();
+                players.sort_by_key(|(_, info)| std::cmp::Reverse(info.frags));
+                players
             },
+            // the player entities come straight after the world
+            local_player: state.view_entity_id().saturating_sub(1),
+        }
+    }
+}
+
+impl RenderState {
+    pub fn time(&self) -> Duration {
+        self.time
+    }
+
+    pub fn camera(&self, aspect: f32, fov: Deg<f32>) -> Camera {
+        let fov_y = math::fov_x_to_fov_y(fov, aspect).unwrap();
+        Camera::new(
+            self.camera_origin,
+            self.camera_angles,
+            cgmath::perspective(fov_y, aspect, 4.0, 4096.0),
+        )
+    }
+
+    pub fn viewmodel_id(&self) -> usize {
+        self.viewmodel_id
+    }
+
+    /// The player's weapon, unless it's hidden because the player is dead or invisible or the
+    /// camera isn't the player's view.
+    pub fn viewmodel(&self) -> Option<ViewModel> {
+        if self.intermission.is_some()
+            || self.photo_mode
+            || self.items.contains(ItemFlags::INVISIBILITY)
+            || self.stats[ClientStat::Health as usize] <= 0
+        {
+            return None;
+        }
+
+        Some(ViewModel {
+            renderer_id: self.viewmodel_id,
+            frame_id: self.viewmodel_frame,
+            origin: self.viewmodel_origin,
+            angles: self.camera_angles,
+        })
+    }
+
+    pub fn photo_mode(&self) -> bool {
+        self.photo_mode
+    }
+
+    pub fn iter_visible_entities(&self) -> impl Iterator<Item = &RenderEntity> {
+        self.entities.iter()
+    }
+
+    pub fn iter_particles(&self) -> impl Iterator<Item = &Particle> {
+        self.particles.iter()
+    }
+
+    pub fn iter_lights(&self) -> impl Iterator<Item = &Light> {
+        self.lights.iter()
+    }
+
+    pub fn lightstyle_values(&self) -> &[f32] {
+        &self.lightstyle_values
+    }
+
+    pub fn color_shifts(&self) -> &[ColorShift; 4] {
+        &self.color_shifts
+    }
+
+    pub fn fog(&self) -> &Fog {
+        &self.fog
+    }
+
+    /// The name of the skybox to draw on sky brushes, if there is one.
+    pub fn sky(&self) -> Option<&str> {
+        self.sky.as_deref()
+    }
+
+    /// Whether the view is in a liquid, in which case the screen is warped.
+    pub fn underwater(&self) -> bool {
+        self.underwater
+    }
+
+    pub fn intermission(&self) -> Option<&IntermissionKind> {
+        self.intermission.as_ref()
+    }
+
+    pub fn start_time(&self) -> Duration {
+        self.start_time
+    }
+
+    pub fn completion_time(&self) -> Option<Duration> {
+        self.completion_time
+    }
+
+    pub fn stats(&self) -> &[i32] {
+        &self.stats
+    }
+
+    pub fn items(&self) -> ItemFlags {
+        self.items
+    }
+
+    pub fn item_pickup_times(&self) -> &[Duration] {
+        &self.item_get_time
+    }
+
+    pub fn face_anim_time(&self) -> Duration {
+        self.face_anim_time
+    }
+
+    pub fn game_type(&self) -> GameType {
+        self.game_type
+    }
+
+    pub fn max_players(&self) -> usize {
+        self.max_players
+    }
+
+    pub fn scoreboard(&self) -> Scoreboard<'_> {
+        Scoreboard {
+            players: &self.players,
+            local_player: self.local_player,
         }
     }
 }
 
+/// A `GraphicsState` being built in the background.
+#[derive(Resource)]
+pub struct PendingGraphicsState(Task<Result<GraphicsState, Error>>);
+
+/// Why the last attempt to build the `GraphicsState` failed, if it did. This is shared between the
+/// main and render worlds so that the error can be shown to the player, since nothing else can be
+/// drawn without the state.
+#[derive(Resource, Clone, Default)]
+pub struct GraphicsStateError(Arc<Mutex<Option<String>>>);
+
+impl GraphicsStateError {
+    pub fn get(&self) -> Option<String> {
+        self.0.lock().clone()
+    }
+
+    fn set(&self, error: Option<String>) {
+        *self.0.lock() = error;
+    }
+}
+
+/// Marks the text showing a `GraphicsStateError`.
+#[derive(Component)]
+struct GraphicsStateErrorText;
+
 #[derive(Resource)]
 pub struct GraphicsState {
     world_bind_group_layouts: Vec<BindGroupLayout>,
@@ -419,12 +1008,14 @@ pub struct GraphicsState {
 
     // TODO: This probably doesn't need to be a rwlock
     entity_uniform_buffer: RwLock<DynamicUniformBuffer<EntityUniforms>>,
+    alias_instance_buffer: AliasInstanceBuffer,
 
     diffuse_sampler: Sampler,
     nearest_sampler: Sampler,
     lightmap_sampler: Sampler,
 
     alias_pipeline: AliasPipeline,
+    iqm_pipeline: IqmPipeline,
     brush_pipeline: BrushPipeline,
     sprite_pipeline: SpritePipeline,
     deferred_pipeline: DeferredPipeline,
@@ -437,25 +1028,69 @@ pub struct GraphicsState {
 
     palette: Palette,
     gfx_wad: Wad,
+
+    // if set, diffuse textures are compressed
+    texture_cache: Option<TextureCache>,
+    external_textures: Option<ExternalTextures>,
+    // the skybox that was asked for, and the skybox itself if it could be loaded
+    skybox_name: Option<String>,
+    skybox: Option<Skybox>,
+    // if set, texture uploads are batched (see `begin_texture_uploads`)
+    texture_uploads: Mutex<Option<TextureUploads>>,
+
+    // the pipelines must be rebuilt if either of these change
+    diffuse_format: wgpu::TextureFormat,
+    sample_count: u32,
+}
+
+fn create_entity_bind_group(
+    device: &RenderDevice,
+    world_bind_group_layouts: &[BindGroupLayout],
+    entity_uniform_buffer: &DynamicUniformBuffer<EntityUniforms>,
+    diffuse_sampler: &Sampler,
+    lightmap_sampler: &Sampler,
+) -> BindGroup {
+    device.create_bind_group(
+        Some("brush per-entity bind group"),
+        &world_bind_group_layouts[world::BindGroupLayoutId::PerEntity as usize],
+        &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: entity_uniform_buffer.buffer(),
+                    offset: 0,
+                    size: Some(NonZeroU64::new(size_of::<EntityUniforms>() as u64).unwrap()),
+                }),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(diffuse_sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::Sampler(lightmap_sampler),
+            },
+        ],
+    )
 }
 
 thread_local! {
-    static COMPILER: RefCell<shaderc::Compiler> = shaderc::Compiler::new().unwrap().into();
+    static COMPILER: RefCell<ShaderCompiler> = ShaderCompiler::new().unwrap().into();
 }
 
 impl GraphicsState {
     pub fn new(
         device: &RenderDevice,
         queue: &RenderQueue,
-        view_target: &ViewTarget,
+        diffuse_format: wgpu::TextureFormat,
         sample_count: u32,
         vfs: &Vfs,
     ) -> Result<GraphicsState, Error> {
-        let diffuse_format = view_target.main_texture_format();
+        profile_span!("graphics_state_new");
         let normal_format = NORMAL_PREPASS_FORMAT;
 
-        let palette = Palette::load(&vfs, "gfx/palette.lmp");
-        let gfx_wad = Wad::load(vfs.open("gfx.wad")?).unwrap();
+        let palette = Palette::load(&vfs, "gfx/palette.lmp")?;
+        let gfx_wad = Wad::load(vfs.open("gfx.wad")?)?;
 
         let frame_uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("frame uniform buffer"),
@@ -464,6 +1099,7 @@ impl GraphicsState {
             mapped_at_creation: false,
         });
         let entity_uniform_buffer = DynamicUniformBuffer::new(device);
+        let alias_instance_buffer = AliasInstanceBuffer::new(device);
 
         let diffuse_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
             label: None,
@@ -524,34 +1160,18 @@ impl GraphicsState {
                     }),
                 }],
             ),
-            device.create_bind_group(
-                Some("brush per-entity bind group"),
-                &world_bind_group_layouts[world::BindGroupLayoutId::PerEntity as usize],
-                &[
-                    wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                            buffer: &entity_uniform_buffer.buffer(),
-                            offset: 0,
-                            size: Some(
-                                NonZeroU64::new(size_of::<EntityUniforms>() as u64).unwrap(),
-                            ),
-                        }),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 1,
-                        resource: wgpu::BindingResource::Sampler(&diffuse_sampler),
-                    },
-                    wgpu::BindGroupEntry {
-                        binding: 2,
-                        resource: wgpu::BindingResource::Sampler(&lightmap_sampler),
-                    },
-                ],
+            create_entity_bind_group(
+                device,
+                &world_bind_group_layouts,
+                &entity_uniform_buffer,
+                &diffuse_sampler,
+                &lightmap_sampler,
             ),
         ];
 
         let (
             alias_pipeline,
+            iqm_pipeline,
             brush_pipeline,
             sprite_pipeline,
             deferred_pipeline,
@@ -567,6 +1187,14 @@ impl GraphicsState {
                 normal_format,
                 sample_count,
             );
+            let iqm_pipeline = IqmPipeline::new(
+                device,
+                compiler,
+                &world_bind_group_layouts,
+                diffuse_format,
+                normal_format,
+                sample_count,
+            );
             let brush_pipeline = BrushPipeline::new(
                 device,
                 compiler,
@@ -599,6 +1227,7 @@ impl GraphicsState {
 
             (
                 alias_pipeline,
+                iqm_pipeline,
                 brush_pipeline,
                 sprite_pipeline,
                 deferred_pipeline,
@@ -615,7 +1244,7 @@ impl GraphicsState {
             1,
             1,
             &TextureData::Lightmap(LightmapData {
-                lightmap: (&[0xFF][..]).into(),
+                lightmap: (&[0xFF; 4][..]).into(),
             }),
         );
         let default_lightmap_view = default_lightmap.create_view(&Default::default());
@@ -623,11 +1252,13 @@ impl GraphicsState {
         Ok(GraphicsState {
             frame_uniform_buffer,
             entity_uniform_buffer: entity_uniform_buffer.into(),
+            alias_instance_buffer,
 
             world_bind_group_layouts,
             world_bind_groups,
 
             alias_pipeline,
+            iqm_pipeline,
             brush_pipeline,
             sprite_pipeline,
             deferred_pipeline,
@@ -643,9 +1274,84 @@ impl GraphicsState {
             default_lightmap_view,
             palette,
             gfx_wad,
+
+            texture_cache: None,
+            external_textures: None,
+            skybox_name: None,
+            skybox: None,
+            texture_uploads: Mutex::new(None),
+
+            diffuse_format,
+            sample_count,
         })
     }
 
+    /// Adapt to a change in the view target or the MSAA sample count.
+    ///
+    /// The pass targets belong to the view, so a resize alone doesn't need anything here to be
+    /// recreated. Only a new target format or sample count requires the pipelines to be rebuilt,
+    /// and everything else (samplers, layouts, the palette and `gfx.wad`) is kept.
+    pub fn update(&mut self, device: &RenderDevice, view_target: &ViewTarget, sample_count: u32) {
+        let diffuse_format = view_target.main_texture_format();
+        if diffuse_format == self.diffuse_format && sample_count == self.sample_count {
+            return;
+        }
+
+        let normal_format = NORMAL_PREPASS_FORMAT;
+        let layouts = &self.world_bind_group_layouts;
+
+        COMPILER.with_borrow_mut(|compiler| {
+            self.alias_pipeline.rebuild(
+                device,
+                compiler,
+                diffuse_format,
+                normal_format,
+                layouts,
+                sample_count,
+            );
+            self.iqm_pipeline.rebuild(
+                device,
+                compiler,
+                diffuse_format,
+                normal_format,
+                layouts,
+                sample_count,
+            );
+            self.brush_pipeline.rebuild(
+                device,
+                compiler,
+                diffuse_format,
+                normal_format,
+                layouts,
+                sample_count,
+            );
+            self.sprite_pipeline.rebuild(
+                device,
+                compiler,
+                diffuse_format,
+                normal_format,
+                layouts,
+                sample_count,
+            );
+            self.particle_pipeline.rebuild(
+                device,
+                compiler,
+                diffuse_format,
+                normal_format,
+                sample_count,
+            );
+            self.deferred_pipeline
+                .rebuild(device, compiler, diffuse_format, sample_count);
+            self.quad_pipeline.set_format(diffuse_format);
+            self.quad_pipeline.rebuild(device, compiler, sample_count);
+            self.glyph_pipeline.set_format(diffuse_format);
+            self.glyph_pipeline.rebuild(device, compiler, sample_count);
+        });
+
+        self.diffuse_format = diffuse_format;
+        self.sample_count = sample_count;
+    }
+
     pub fn create_texture<'a>(
         &self,
         device: &RenderDevice,
@@ -655,7 +1361,84 @@ impl GraphicsState {
         height: u32,
         data: &TextureData,
     ) -> Texture {
-        create_texture(device, queue, label, width, height, data)
+        let mut uploads = self.texture_uploads.lock();
+        let Some(uploads) = &mut *uploads else {
+            return create_texture(device, queue, label, width, height, data);
+        };
+
+        let texture = device.create_texture(&texture_descriptor(
+            label,
+            width.max(1),
+            height.max(1),
+            data.format(),
+        ));
+        uploads.push(&texture, width, height, data);
+
+        texture
+    }
+
+    /// Record the uploads for textures created by `create_texture` instead of writing them to the
+    /// queue, until `submit_texture_uploads` is called.
+    ///
+    /// The textures mustn't be used in a pass until their uploads have been submitted.
+    pub fn begin_texture_uploads(&self) {
+        self.texture_uploads
+            .lock()
+            .get_or_insert_with(Default::default);
+    }
+
+    /// Submit every upload recorded since `begin_texture_uploads` in a single command buffer.
+    pub fn submit_texture_uploads(&self, device: &RenderDevice, queue: &RenderQueue) {
+        if let Some(uploads) = self.texture_uploads.lock().take() {
+            uploads.submit(device, queue);
+        }
+    }
+
+    /// Compress diffuse textures created after this to BC1, caching them in `cache`.
+    ///
+    /// Passing `None` turns compression off again.
+    pub fn set_texture_compression(&mut self, cache: Option<TextureCache>) {
+        self.texture_cache = cache;
+    }
+
+    /// Set where replacements for brush textures are loaded from, or `None` to always use the
+    /// textures in the BSP file.
+    pub fn set_external_textures(&mut self, textures: Option<ExternalTextures>) {
+        self.external_textures = textures;
+    }
+
+    pub fn external_textures(&self) -> Option<&ExternalTextures> {
+        self.external_textures.as_ref()
+    }
+
+    /// The skybox drawn in place of sky textures, if one is set and could be loaded.
+    pub fn skybox(&self) -> Option<&Skybox> {
+        self.skybox.as_ref()
+    }
+
+    /// Create a diffuse texture, compressing it if texture compression is enabled.
+    ///
+    /// Textures which aren't a whole number of blocks are left uncompressed.
+    pub fn create_diffuse_texture<'a>(
+        &self,
+        device: &RenderDevice,
+        queue: &RenderQueue,
+        label: Option<&'a str>,
+        width: u32,
+        height: u32,
+        data: DiffuseData,
+    ) -> Texture {
+        let data = match &self.texture_cache {
+            Some(cache) if compress::can_compress(width, height) => {
+                TextureData::Compressed(CompressedData {
+                    format: wgpu::TextureFormat::Bc1RgbaUnormSrgb,
+                    blocks: Cow::owned(cache.bc1(&data.rgba, width, height)),
+                })
+            }
+            _ => TextureData::Diffuse(data),
+        };
+
+        self.create_texture(device, queue, label, width, height, &data)
     }
 
     pub fn frame_uniform_buffer(&self) -> &Buffer {
@@ -674,6 +1457,31 @@ impl GraphicsState {
         self.entity_uniform_buffer.write()
     }
 
+    pub fn alias_instance_buffer(&self) -> &AliasInstanceBuffer {
+        &self.alias_instance_buffer
+    }
+
+    /// Make room for `count` more entities in the entity uniform buffer, and for `count` alias
+    /// model instances.
+    ///
+    /// This runs before any passes are recorded, so if the buffer has to grow its bind group can
+    /// be replaced without invalidating one that's already in use.
+    pub fn reserve_entity_uniforms(&mut self, device: &RenderDevice, count: usize) {
+        self.alias_instance_buffer.reserve(device, count);
+
+        let entity_uniform_buffer = self.entity_uniform_buffer.get_mut();
+        if entity_uniform_buffer.reserve(device, count) {
+            self.world_bind_groups[world::BindGroupLayoutId::PerEntity as usize] =
+                create_entity_bind_group(
+                    device,
+                    &self.world_bind_group_layouts,
+                    entity_uniform_buffer,
+                    &self.diffuse_sampler,
+                    &self.lightmap_sampler,
+                );
+        }
+    }
+
     pub fn diffuse_sampler(&self) -> &Sampler {
         &self.diffuse_sampler
     }
@@ -708,6 +1516,10 @@ impl GraphicsState {
         &self.alias_pipeline
     }
 
+    pub fn iqm_pipeline(&self) -> &IqmPipeline {
+        &self.iqm_pipeline
+    }
+
     pub fn brush_pipeline(&self) -> &BrushPipeline {
         &self.brush_pipeline
     }
@@ -743,19 +1555,70 @@ impl GraphicsState {
 
 #[derive(Resource, Deserialize)]
 pub struct RenderVars {
-    pub fov: f32,
     #[serde(rename(deserialize = "r_lightmap"))]
     pub lightmap: bool,
     #[serde(rename(deserialize = "r_msaa_samples"))]
     pub msaa_samples: u32,
+    #[serde(rename(deserialize = "r_texture_compression"))]
+    pub texture_compression: bool,
+    #[serde(rename(deserialize = "r_scale"))]
+    pub scale: f32,
+    #[serde(rename(deserialize = "r_externaltextures"))]
+    pub external_textures: bool,
+    #[serde(rename(deserialize = "r_wateralpha"))]
+    pub water_alpha: f32,
+    #[serde(rename(deserialize = "r_showcull"))]
+    pub show_cull: bool,
+    #[serde(rename(deserialize = "r_lerpmodels"))]
+    pub lerp_models: bool,
+    #[serde(rename(deserialize = "r_drawviewmodel"))]
+    pub draw_viewmodel: bool,
+    #[serde(rename(deserialize = "r_particles"))]
+    pub enhanced_particles: bool,
+    #[serde(rename(deserialize = "r_particlesize"))]
+    pub particle_size: f32,
+    #[serde(default, rename(deserialize = "r_particleblend"))]
+    pub particle_blend: ParticleBlend,
+    #[serde(rename(deserialize = "r_particlesoftness"))]
+    pub particle_softness: f32,
+}
+
+impl RenderVars {
+    /// The smallest fraction of the window that `r_scale` can render the world at.
+    pub const MIN_SCALE: f32 = 0.25;
+
+    /// The size that the world is rendered at for a `width` by `height` window. The world is drawn
+    /// to the top-left of the render targets and stretched to fill the window in postprocess.
+    pub fn scaled_size(&self, width: u32, height: u32) -> (u32, u32) {
+        let scale = if self.scale > 0.0 {
+            self.scale.clamp(Self::MIN_SCALE, 1.0)
+        } else {
+            1.0
+        };
+
+        (
+            ((width as f32 * scale).round() as u32).clamp(1, width.max(1)),
+            ((height as f32 * scale).round() as u32).clamp(1, height.max(1)),
+        )
+    }
 }
 
 impl Default for RenderVars {
     fn default() -> Self {
         Self {
-            fov: 90.,
             lightmap: false,
             msaa_samples: 1,
+            texture_compression: false,
+            scale: 1.0,
+            external_textures: true,
+            water_alpha: 1.0,
+            show_cull: false,
+            lerp_models: true,
+            draw_viewmodel: true,
+            enhanced_particles: true,
+            particle_size: 1.0,
+            particle_blend: ParticleBlend::Alpha,
+            particle_softness: 8.0,
         }
     }
 }
@@ -769,7 +1632,15 @@ impl ExtractResource for RenderVars {
 }
 
 mod systems {
+    use bevy::ecs::query::Has;
+    use cgmath::InnerSpace as _;
+    use hashbrown::HashSet;
+
     use super::*;
+    use crate::{
+        client::entity::LightEmitter,
+        common::model::{Model, ModelKind},
+    };
 
     pub fn create_graphics_state(
         targets: Query<&ViewTarget, With<Camera3d>>,
@@ -779,18 +1650,281 @@ mod systems {
         vfs: Res<Vfs>,
         render_vars: Res<RenderVars>,
     ) {
+        let Ok(view_target) = targets.get_single() else {
+            return;
+        };
+
+        let device = device.clone();
+        let queue = queue.clone();
+        let vfs = vfs.clone();
+        let diffuse_format = view_target.main_texture_format();
         let sample_count = render_vars.msaa_samples;
 
-        if let Ok(view_target) = targets.get_single() {
-            match GraphicsState::new(&*device, &*queue, view_target, sample_count, &*vfs) {
-                Ok(state) => {
-                    commands.insert_resource(state);
-                }
-                Err(e) => {
-                    warn!("Failed to create graphics state: {}", e);
+        // compiling the shaders takes a while, so keep rendering with the old state (if any)
+        // until the new one is ready
+        let task = AsyncComputeTaskPool::get().spawn(async move {
+            GraphicsState::new(&device, &queue, diffuse_format, sample_count, &vfs)
+        });
+        commands.insert_resource(PendingGraphicsState(task));
+    }
+
+    /// Throw away everything built for the current surface for `vid_restart`. The systems after
+    /// this rebuild it all with the current settings.
+    pub fn restart_graphics_state(
+        mut commands: Commands,
+        restart: Res<VidRestart>,
+        graphics_error: Res<GraphicsStateError>,
+        mut last: Local<VidRestart>,
+    ) {
+        // the first extraction isn't a restart
+        if *last == *restart {
+            return;
+        }
+        *last = *restart;
+
+        info!("Restarting the renderer");
+        commands.remove_resource::<PendingGraphicsState>();
+        commands.remove_resource::<GraphicsState>();
+        commands.remove_resource::<UiRenderer>();
+        commands.remove_resource::<WorldRenderer>();
+        graphics_error.set(None);
+    }
+
+    pub fn finish_graphics_state(
+        mut commands: Commands,
+        mut pending: ResMut<PendingGraphicsState>,
+        graphics_error: Res<GraphicsStateError>,
+    ) {
+        let Some(result) = (&mut pending.0).now_or_never() else {
+            return;
+        };
+
+        commands.remove_resource::<PendingGraphicsState>();
+        match result {
+            Ok(state) => {
+                commands.insert_resource(state);
+                // the model renderers' bind groups belong to the old state
+                commands.insert_resource(ModelRenderers::default());
+                graphics_error.set(None);
+            }
+            Err(e) => {
+                error!("Failed to create graphics state: {}", e);
+                graphics_error.set(Some(e.to_string()));
+            }
+        }
+    }
+
+    /// Tell the player why nothing is being drawn, both in the console and with a message in
+    /// place of the title screen.
+    pub fn show_graphics_state_error(
+        mut commands: Commands,
+        graphics_error: Res<GraphicsStateError>,
+        time: Res<Time<Real>>,
+        locale: Res<Locale>,
+        mut console: ResMut<ConsoleOutput>,
+        text: Query<Entity, With<GraphicsStateErrorText>>,
+        mut shown: Local<Option<String>>,
+    ) {
+        let error = graphics_error.get();
+        if error == *shown {
+            return;
+        }
+
+        for entity in &text {
+            commands.entity(entity).despawn_recursive();
+        }
+
+        if let Some(error) = &error {
+            let message = format!("{}: {}", locale.get("Couldn't start the renderer"), error);
+            console.println(&message, Duration::from_std(time.elapsed()).unwrap());
+
+            commands.spawn((
+                TextBundle::from_section(
+                    format!(
+                        "{}\n{}",
+                        message,
+                        locale.get("Check that the game files are complete")
+                    ),
+                    TextStyle {
+                        font_size: 20.,
+                        color: Color::WHITE,
+                        ..default()
+                    },
+                )
+                .with_text_justify(JustifyText::Center)
+                .with_style(Style {
+                    position_type: PositionType::Absolute,
+                    top: Val::Percent(45.),
+                    width: Val::Percent(100.),
+                    ..default()
+                }),
+                GraphicsStateErrorText,
+            ));
+        }
+
+        *shown = error;
+    }
+
+    /// The world-space bounds of an entity's model, or `None` if it has no model.
+    fn entity_bounds(
+        model: &Model,
+        transform: &EntityTransform,
+    ) -> Option<(Vector3<f32>, Vector3<f32>)> {
+        let (min, max) = match model.kind() {
+            ModelKind::None => return None,
+            ModelKind::Brush(bmodel) => (bmodel.min(), bmodel.max()),
+            ModelKind::Sprite(smodel) => (smodel.min(), smodel.max()),
+            ModelKind::Alias(amodel) => {
+                let r = amodel.radius();
+                (Vector3::new(-r, -r, -r), Vector3::new(r, r, r))
+            }
+            ModelKind::Md3(md3_model) => (md3_model.min(), md3_model.max()),
+            ModelKind::Iqm(iqm_model) => (iqm_model.min(), iqm_model.max()),
+        };
+
+        // a rotated model can reach past its unrotated bounds
+        let (min, max) = if transform.angles != Vector3::new(Deg(0.0), Deg(0.0), Deg(0.0)) {
+            let r = min.magnitude().max(max.magnitude());
+            (Vector3::new(-r, -r, -r), Vector3::new(r, r, r))
+        } else {
+            (min, max)
+        };
+
+        Some((transform.origin + min, transform.origin + max))
+    }
+
+    pub fn extract_entities(
+        render_state: Option<ResMut<RenderState>>,
+        conn: Extract<Option<Res<Connection>>>,
+        entities: Extract<Query<(&EntityTransform, &EntityModel, Has<LightEmitter>)>>,
+        ghost: Extract<Option<Res<GhostView>>>,
+        photo: Extract<Option<Res<PhotoMode>>>,
+        mut pvs: Local<HashSet<usize>>,
+        mut touched_leaves: Local<Vec<usize>>,
+    ) {
+        let Some(mut render_state) = render_state else {
+            return;
+        };
+
+        // this has to come first, since what's visible depends on where the camera is
+        if let Some(camera) = photo.as_ref().and_then(|photo| photo.camera()) {
+            render_state.camera_origin = camera.origin;
+            render_state.camera_angles = camera.angles;
+            render_state.photo_mode = true;
+        }
+
+        let models = conn.as_ref().map(|conn| conn.state.models());
+        let bsp_data = match models.and_then(|models| models.get(1)).map(|m| m.kind()) {
+            Some(ModelKind::Brush(worldmodel)) => Some(worldmodel.bsp_data()),
+            _ => None,
+        };
+
+        pvs.clear();
+        if let Some(bsp_data) = bsp_data.as_ref() {
+            let camera_leaf = bsp_data.find_leaf(render_state.camera_origin);
+            pvs.extend(bsp_data.get_pvs(camera_leaf, bsp_data.leaves().len()));
+        }
+
+        let render_state = &mut *render_state;
+        render_state.entities.clear();
+        for (transform, model, emits_light) in entities.iter() {
+            // an empty PVS means the camera is outside the map or the map has no visibility
+            // data, in which case everything is drawn. entities which give off light are kept
+            // since they can light surfaces that are visible.
+            if !pvs.is_empty() && !emits_light {
+                let bounds = models
+                    .and_then(|models| models.get(model.model_id))
+                    .and_then(|model| entity_bounds(model, transform));
+
+                if let (Some(bsp_data), Some((min, max))) = (bsp_data.as_ref(), bounds) {
+                    touched_leaves.clear();
+                    bsp_data.find_touched_leaves(min, max, &mut touched_leaves);
+                    if !touched_leaves.iter().any(|leaf_id| pvs.contains(leaf_id)) {
+                        continue;
+                    }
                 }
             }
+
+            // monsters animate at 10 frames a second, so each frame is blended in over 0.1s
+            let frame_blend = engine::duration_to_f32(render_state.time - model.frame_time) / 0.1;
+            render_state.entities.push(RenderEntity {
+                origin: transform.origin,
+                angles: transform.angles,
+                model_id: model.model_id,
+                frame_id: model.frame_id,
+                prev_frame_id: model.prev_frame_id,
+                frame_blend: frame_blend.clamp(0.0, 1.0),
+                skin_id: model.skin_id,
+                alpha: 1.0,
+            });
+        }
+
+        if let Some(ghost) = ghost.as_ref().and_then(|g| g.entity) {
+            render_state.entities.push(RenderEntity {
+                origin: ghost.origin,
+                angles: ghost.angles,
+                model_id: ghost.model_id,
+                frame_id: ghost.frame_id,
+                prev_frame_id: ghost.frame_id,
+                frame_blend: 1.0,
+                skin_id: 0,
+                alpha: ghost.alpha,
+            });
+        }
+    }
+
+    pub fn update_graphics_state(
+        targets: Query<&ViewTarget, With<Camera3d>>,
+        mut state: ResMut<GraphicsState>,
+        device: Res<RenderDevice>,
+        render_vars: Res<RenderVars>,
+        texture_cache: Res<TextureCache>,
+        vfs: Res<Vfs>,
+    ) {
+        if let Ok(view_target) = targets.get_single() {
+            state.update(&*device, view_target, render_vars.msaa_samples);
         }
+
+        let compress = render_vars.texture_compression
+            && device
+                .features()
+                .contains(wgpu::Features::TEXTURE_COMPRESSION_BC);
+        state.set_texture_compression(compress.then(|| texture_cache.clone()));
+        state.set_external_textures(
+            render_vars
+                .external_textures
+                .then(|| ExternalTextures::new(vfs.clone())),
+        );
+    }
+
+    /// Load the skybox when a different one is set.
+    pub fn update_skybox(
+        mut state: ResMut<GraphicsState>,
+        render_state: Option<Res<RenderState>>,
+        device: Res<RenderDevice>,
+        queue: Res<RenderQueue>,
+        vfs: Res<Vfs>,
+    ) {
+        let name = render_state.as_ref().and_then(|s| s.sky());
+        if state.skybox_name.as_deref() == name {
+            return;
+        }
+
+        let skybox = name.and_then(|name| {
+            Skybox::load(&state, &device, &queue, &vfs, name)
+                .map_err(|e| warn!("Couldn't load skybox {}: {}", name, e))
+                .ok()
+        });
+        state.skybox_name = name.map(ToOwned::to_owned);
+        state.skybox = skybox;
+    }
+
+    pub fn reserve_entity_uniforms(
+        mut state: ResMut<GraphicsState>,
+        device: Res<RenderDevice>,
+        render_state: Res<RenderState>,
+    ) {
+        state.reserve_entity_uniforms(&device, render_state.iter_visible_entities().count());
     }
 
     pub fn create_menu_renderer(
@@ -800,9 +1934,18 @@ mod systems {
         device: Res<RenderDevice>,
         queue: Res<RenderQueue>,
         menu: Res<Menu>,
+        settings: Option<Res<SeismonGameSettings>>,
     ) {
+        let game = settings.as_ref().and_then(|s| s.game.as_deref());
         if let Some(state) = state.as_ref() {
-            commands.insert_resource(UiRenderer::new(&*state, &*vfs, &*device, &*queue, &*menu));
+            commands.insert_resource(UiRenderer::new(
+                &*state,
+                &*vfs,
+                &*device,
+                &*queue,
+                &*menu,
+                MissionPack::from_game(game),
+            ));
         }
     }
 }
@@ -16,6 +16,7 @@ use bevy::{
     sprite::Material2d,
 };
 use byteorder::ReadBytesExt;
+use failure::Error;
 use futures::AsyncReadExt;
 use wgpu::{Extent3d, TextureUsages};
 
@@ -134,21 +135,21 @@ impl Palette {
         Palette { rgb }
     }
 
-    pub fn load<S>(vfs: &Vfs, path: S) -> Palette
+    pub fn load<S>(vfs: &Vfs, path: S) -> Result<Palette, Error>
     where
         S: AsRef<str>,
     {
-        let mut data = BufReader::new(vfs.open(path).unwrap());
+        let mut data = BufReader::new(vfs.open(path)?);
 
         let mut rgb = [[0u8; 3]; 256];
 
         for color in 0..256 {
             for component in 0..3 {
-                rgb[color][component] = data.read_u8().unwrap();
+                rgb[color][component] = data.read_u8()?;
             }
         }
 
-        Palette { rgb }
+        Ok(Palette { rgb })
     }
 
     // TODO: this will not render console characters correctly, as they use index 0 (black) to
@@ -18,7 +18,12 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
-use std::mem::size_of;
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash as _, Hasher as _},
+    mem::size_of,
+    sync::Arc,
+};
 
 use bevy::{
     prelude::*,
@@ -28,29 +33,107 @@ use bevy::{
         renderer::RenderDevice,
     },
 };
+use hashbrown::HashMap;
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
 use wgpu::BindGroupLayoutEntry;
 
 use crate::common::util::{any_as_bytes, Pod};
 
 /// The `Pipeline` trait, which allows render pipelines to be defined more-or-less declaratively.
 
+lazy_static! {
+    /// Compiled SPIR-V, keyed by a hash of the shader's name and source. Pipelines are rebuilt
+    /// whenever the target format or sample count changes, but their shaders stay the same.
+    static ref SPIRV_CACHE: Mutex<HashMap<u64, Arc<[u32]>>> = Default::default();
+}
+
+/// Compiles the GLSL shaders to SPIR-V. shaderc has no wasm32 build, so on the web the GLSL is
+/// passed to wgpu as it is and compiled by naga.
+#[cfg(not(target_arch = "wasm32"))]
+pub type ShaderCompiler = shaderc::Compiler;
+
+#[cfg(target_arch = "wasm32")]
+pub struct ShaderCompiler;
+
+#[cfg(target_arch = "wasm32")]
+impl ShaderCompiler {
+    pub fn new() -> Option<ShaderCompiler> {
+        Some(ShaderCompiler)
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+fn create_shader<S>(
+    device: &RenderDevice,
+    _compiler: &mut ShaderCompiler,
+    name: S,
+    stage: wgpu::ShaderStages,
+    source: S,
+) -> wgpu::ShaderModule
+where
+    S: AsRef<str>,
+{
+    use bevy::render::render_resource::ShaderStage;
+
+    let stage = if stage == wgpu::ShaderStages::VERTEX {
+        ShaderStage::Vertex
+    } else {
+        ShaderStage::Fragment
+    };
+
+    debug!("creating shader {}", name.as_ref());
+    device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some(name.as_ref()),
+        source: wgpu::ShaderSource::Glsl {
+            shader: source.as_ref().to_owned().into(),
+            stage,
+            defines: Default::default(),
+        },
+    })
+}
+
+#[cfg(not(target_arch = "wasm32"))]
 fn create_shader<S>(
     device: &RenderDevice,
-    compiler: &mut shaderc::Compiler,
+    compiler: &mut ShaderCompiler,
     name: S,
-    kind: shaderc::ShaderKind,
+    stage: wgpu::ShaderStages,
     source: S,
 ) -> wgpu::ShaderModule
 where
     S: AsRef<str>,
 {
+    let kind = if stage == wgpu::ShaderStages::VERTEX {
+        shaderc::ShaderKind::Vertex
+    } else {
+        shaderc::ShaderKind::Fragment
+    };
+
+    let mut hasher = DefaultHasher::new();
+    name.as_ref().hash(&mut hasher);
+    source.as_ref().hash(&mut hasher);
+    let key = hasher.finish();
+
+    let cached = SPIRV_CACHE.lock().get(&key).cloned();
+    let spirv = match cached {
+        Some(spirv) => spirv,
+        None => {
+            debug!("compiling shader {}", name.as_ref());
+            let spirv: Arc<[u32]> = compiler
+                .compile_into_spirv(source.as_ref(), kind, name.as_ref(), "main", None)
+                .unwrap()
+                .as_binary()
+                .into();
+            SPIRV_CACHE.lock().insert(key, spirv.clone());
+            spirv
+        }
+    };
+
     debug!("creating shader {}", name.as_ref());
-    let spirv = compiler
-        .compile_into_spirv(source.as_ref(), kind, name.as_ref(), "main", None)
-        .unwrap();
     device.create_shader_module(wgpu::ShaderModuleDescriptor {
         label: Some(name.as_ref()),
-        source: wgpu::ShaderSource::SpirV(spirv.as_binary().into()),
+        source: wgpu::ShaderSource::SpirV((&*spirv).into()),
     })
 }
 
@@ -189,7 +272,7 @@ pub trait Pipeline {
     /// `RenderPipeline`. This permits the reuse of `BindGroupLayout`s between pipelines.
     fn create(
         device: &RenderDevice,
-        compiler: &mut shaderc::Compiler,
+        compiler: &mut ShaderCompiler,
         bind_group_layout_prefix: &[BindGroupLayout],
         sample_count: u32,
         args: Self::Args,
@@ -229,14 +312,14 @@ pub trait Pipeline {
             device,
             compiler,
             format!("{}.vert", Self::name()).as_str(),
-            shaderc::ShaderKind::Vertex,
+            wgpu::ShaderStages::VERTEX,
             Self::vertex_shader(),
         );
         let fragment_shader = create_shader(
             device,
             compiler,
             format!("{}.frag", Self::name()).as_str(),
-            shaderc::ShaderKind::Fragment,
+            wgpu::ShaderStages::FRAGMENT,
             Self::fragment_shader(),
         );
 
@@ -272,7 +355,7 @@ pub trait Pipeline {
     /// Pipelines must be reconstructed when the MSAA sample count is changed.
     fn recreate<'a, I: IntoIterator<Item = &'a BindGroupLayout>>(
         device: &RenderDevice,
-        compiler: &mut shaderc::Compiler,
+        compiler: &mut ShaderCompiler,
         bind_group_layouts: I,
         sample_count: u32,
         args: Self::Args,
@@ -294,14 +377,14 @@ pub trait Pipeline {
             device,
             compiler,
             format!("{}.vert", Self::name()).as_str(),
-            shaderc::ShaderKind::Vertex,
+            wgpu::ShaderStages::VERTEX,
             Self::vertex_shader(),
         );
         let fragment_shader = create_shader(
             device,
             compiler,
             format!("{}.frag", Self::name()).as_str(),
-            shaderc::ShaderKind::Fragment,
+            wgpu::ShaderStages::FRAGMENT,
             Self::fragment_shader(),
         );
         let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
@@ -0,0 +1,135 @@
+//! Per-frame renderer counters for `r_showfps` and `r_speeds`.
+//!
+//! The passes record their commands on the task pool, so they count into atomics rather than a
+//! resource. [`systems::collect_speeds`] takes the totals once the frame has been submitted.
+
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use bevy::{prelude::*, utils::Instant};
+
+/// How long the frame time is averaged over, so that the overlay is readable.
+const FRAME_TIME_WINDOW: Duration = Duration::from_millis(500);
+
+static DRAW_CALLS: AtomicU64 = AtomicU64::new(0);
+static BRUSH_POLYS: AtomicU64 = AtomicU64::new(0);
+static ALIAS_POLYS: AtomicU64 = AtomicU64::new(0);
+static PARTICLES: AtomicU64 = AtomicU64::new(0);
+static UPLOAD_BYTES: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Clone, Copy, Debug)]
+pub enum Counter {
+    DrawCalls,
+    /// Triangles in world and brush model faces.
+    BrushPolys,
+    /// Triangles in alias, IQM and sprite models.
+    AliasPolys,
+    Particles,
+    /// Bytes written to textures.
+    UploadBytes,
+}
+
+impl Counter {
+    fn atomic(self) -> &'static AtomicU64 {
+        match self {
+            Counter::DrawCalls => &DRAW_CALLS,
+            Counter::BrushPolys => &BRUSH_POLYS,
+            Counter::AliasPolys => &ALIAS_POLYS,
+            Counter::Particles => &PARTICLES,
+            Counter::UploadBytes => &UPLOAD_BYTES,
+        }
+    }
+
+    fn take(self) -> u64 {
+        self.atomic().swap(0, Ordering::Relaxed)
+    }
+}
+
+/// Add `amount` to `counter` for this frame.
+pub fn add(counter: Counter, amount: u64) {
+    counter.atomic().fetch_add(amount, Ordering::Relaxed);
+}
+
+/// Count a draw call which drew `amount` of `counter`.
+pub fn count_draw(counter: Counter, amount: u64) {
+    add(Counter::DrawCalls, 1);
+    add(counter, amount);
+}
+
+/// The counters for a single frame.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FrameCounts {
+    pub draw_calls: u64,
+    pub brush_polys: u64,
+    pub alias_polys: u64,
+    pub particles: u64,
+    pub upload_bytes: u64,
+}
+
+impl FrameCounts {
+    /// Take the counts so far and start the next frame from zero.
+    fn take() -> FrameCounts {
+        FrameCounts {
+            draw_calls: Counter::DrawCalls.take(),
+            brush_polys: Counter::BrushPolys.take(),
+            alias_polys: Counter::AliasPolys.take(),
+            particles: Counter::Particles.take(),
+            upload_bytes: Counter::UploadBytes.take(),
+        }
+    }
+}
+
+/// What the renderer did last frame, and how long frames have been taking.
+#[derive(Resource, Default, Debug)]
+pub struct RenderSpeeds {
+    counts: FrameCounts,
+    frame_time: Duration,
+    window_start: Option<Instant>,
+    window_frames: u32,
+}
+
+impl RenderSpeeds {
+    pub fn counts(&self) -> &FrameCounts {
+        &self.counts
+    }
+
+    /// The average frame time over the last half second or so.
+    pub fn frame_time(&self) -> Duration {
+        self.frame_time
+    }
+
+    pub fn fps(&self) -> f32 {
+        match self.frame_time.as_secs_f32() {
+            secs if secs > 0.0 => 1.0 / secs,
+            _ => 0.0,
+        }
+    }
+
+    fn end_frame(&mut self, now: Instant) {
+        self.counts = FrameCounts::take();
+
+        let Some(start) = self.window_start else {
+            self.window_start = Some(now);
+            return;
+        };
+
+        self.window_frames += 1;
+        let elapsed = now - start;
+        if elapsed >= FRAME_TIME_WINDOW {
+            self.frame_time = elapsed / self.window_frames;
+            self.window_start = Some(now);
+            self.window_frames = 0;
+        }
+    }
+}
+
+pub mod systems {
+    use super::*;
+
+    /// Take this frame's counters once everything has been drawn.
+    pub fn collect_speeds(mut speeds: ResMut<RenderSpeeds>) {
+        speeds.end_frame(Instant::now());
+    }
+}
@@ -22,20 +22,23 @@ use std::cell::RefCell;
 
 use bevy::{
     core_pipeline::{core_3d::Camera3d, prepass::ViewPrepassTextures},
+    log::info,
     render::{
         render_graph::{RenderLabel, ViewNode},
         render_phase::TrackedRenderPass,
         render_resource::{RenderPassColorAttachment, Texture, TextureView},
-        renderer::{RenderDevice, RenderQueue},
+        renderer::RenderQueue,
         view::ViewTarget,
     },
 };
 use bumpalo::Bump;
-use cgmath::Deg;
 
-use crate::client::render::{
-    world::WorldRenderer, GraphicsState, RenderConnectionKind, RenderResolution, RenderState,
-    RenderVars,
+use crate::client::{
+    render::{
+        world::{brush::LightmapLight, WorldRenderer},
+        GraphicsState, RenderResolution, RenderState, RenderVars,
+    },
+    view::Fov,
 };
 
 /// Intermediate object that can generate `RenderPassDescriptor`s.
@@ -103,16 +106,26 @@ impl ViewNode for InitPass {
         &self,
         _graph: &mut bevy::render::render_graph::RenderGraphContext,
         render_context: &mut bevy::render::renderer::RenderContext<'w>,
-        (target, prepass, _): (&ViewTarget, &ViewPrepassTextures, &Camera3d),
+        (target, prepass, _): (&'w ViewTarget, &'w ViewPrepassTextures, &'w Camera3d),
         world: &'w bevy::prelude::World,
     ) -> Result<(), bevy::render::render_graph::NodeRunError> {
-        let gfx_state = world.resource::<GraphicsState>();
+        profile_span!("init_pass");
+        // the graphics state is built in the background, so it might not be ready yet
+        let Some(gfx_state) = world.get_resource::<GraphicsState>() else {
+            return Ok(());
+        };
         let queue = world.resource::<RenderQueue>();
-        let device = world.resource::<RenderDevice>();
-        let render_state = world.get_resource::<RenderState>();
-        let world_renderer = world.get_resource::<WorldRenderer>();
+        // if client is fully connected, draw world
+        let (Some(cl_state), Some(world_renderer)) = (
+            world.get_resource::<RenderState>(),
+            world.get_resource::<WorldRenderer>(),
+        ) else {
+            return Ok(());
+        };
         let &RenderResolution(width, height) = world.resource::<RenderResolution>();
         let render_vars = world.resource::<RenderVars>();
+        let (scaled_width, scaled_height) = render_vars.scaled_size(width, height);
+        let fov = world.resource::<Fov>();
 
         let diffuse_target = target.get_unsampled_color_attachment().view;
         let ViewPrepassTextures {
@@ -129,92 +142,113 @@ impl ViewNode for InitPass {
             view: depth_target, ..
         } = depth_target.get_unsampled_attachment();
 
-        // TODO: Remove this
-        thread_local! {
-            static BUMP: RefCell<Bump> =Bump::new().into();
-        }
+        // each pass records into its own encoder on the compute task pool, and Bevy submits the
+        // resulting command buffers in render graph order
+        render_context.add_command_buffer_generation_task(move |device| {
+            profile_span!("init_pass_encode");
+            // TODO: Remove this
+            thread_local! {
+                static BUMP: RefCell<Bump> = Bump::new().into();
+            }
+
+            let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Initial pass encoder"),
+            });
 
-        let encoder = render_context.command_encoder();
-
-        BUMP.with_borrow_mut(|bump| bump.reset());
-        BUMP.with_borrow(|bump| {
-            if let (
-                Some(RenderState {
-                    state: ref cl_state,
-                    kind,
-                }),
-                Some(world),
-            ) = (render_state, world_renderer)
-            {
-                // if client is fully connected, draw world
-                let camera = match kind {
-                    RenderConnectionKind::Demo => {
-                        cl_state.demo_camera(width as f32 / height as f32, Deg(render_vars.fov))
-                    }
-                    RenderConnectionKind::Server => {
-                        cl_state.camera(width as f32 / height as f32, Deg(render_vars.fov))
-                    }
+            BUMP.with_borrow_mut(|bump| bump.reset());
+            BUMP.with_borrow(|bump| {
+                let camera = cl_state.camera(width as f32 / height as f32, fov.0);
+
+                let viewmodel = if render_vars.draw_viewmodel {
+                    cl_state.viewmodel()
+                } else {
+                    None
                 };
 
-                // initial render pass
-                {
-                    let lightstyle_values = cl_state.lightstyle_values();
-                    world.update_uniform_buffers(
-                        gfx_state,
-                        queue,
-                        &camera,
-                        cl_state.time(),
-                        cl_state.iter_visible_entities(),
-                        &lightstyle_values,
-                        render_vars,
-                    );
+                let lightstyle_values = cl_state.lightstyle_values();
+                let lights = cl_state
+                    .iter_lights()
+                    .map(|light| LightmapLight {
+                        origin: light.origin(),
+                        radius: light.radius(cl_state.time()),
+                        brightness: light.color().into_iter().fold(0.0, f32::max),
+                    })
+                    .collect::<Vec<_>>();
+                world_renderer.update_uniform_buffers(
+                    gfx_state,
+                    queue,
+                    &camera,
+                    cl_state.time(),
+                    cl_state.iter_visible_entities(),
+                    viewmodel,
+                    &lightstyle_values,
+                    &lights,
+                    render_vars,
+                );
 
-                    let mut init_pass = TrackedRenderPass::new(
-                        device,
-                        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                            label: Some("Initial pass"),
-                            color_attachments: &[
-                                Some(wgpu::RenderPassColorAttachment {
-                                    view: diffuse_target,
-                                    resolve_target: None,
-                                    ops: wgpu::Operations {
-                                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
-                                        store: wgpu::StoreOp::Store,
-                                    },
-                                }),
-                                Some(normal_target),
-                            ],
-                            depth_stencil_attachment: Some(
-                                wgpu::RenderPassDepthStencilAttachment {
-                                    view: &depth_target,
-                                    depth_ops: Some(wgpu::Operations {
-                                        load: wgpu::LoadOp::Clear(1.0),
-                                        store: wgpu::StoreOp::Store,
-                                    }),
-                                    stencil_ops: None,
+                let mut init_pass = TrackedRenderPass::new(
+                    &device,
+                    encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                        label: Some("Initial pass"),
+                        color_attachments: &[
+                            Some(wgpu::RenderPassColorAttachment {
+                                view: diffuse_target,
+                                resolve_target: None,
+                                ops: wgpu::Operations {
+                                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                                    store: wgpu::StoreOp::Store,
                                 },
-                            ),
-                            timestamp_writes: Default::default(),
-                            occlusion_query_set: Default::default(),
+                            }),
+                            Some(normal_target),
+                        ],
+                        depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                            view: &depth_target,
+                            depth_ops: Some(wgpu::Operations {
+                                load: wgpu::LoadOp::Clear(1.0),
+                                store: wgpu::StoreOp::Store,
+                            }),
+                            stencil_ops: None,
                         }),
-                    );
+                        timestamp_writes: Default::default(),
+                        occlusion_query_set: Default::default(),
+                    }),
+                );
+                // with `r_scale` below 1, only the top-left of the targets is drawn to
+                init_pass.set_viewport(
+                    0.0,
+                    0.0,
+                    scaled_width as f32,
+                    scaled_height as f32,
+                    0.0,
+                    1.0,
+                );
 
-                    world.render_pass(
-                        gfx_state,
-                        &mut init_pass,
-                        bump,
-                        &camera,
-                        cl_state.time(),
-                        cl_state.iter_visible_entities(),
-                        cl_state.iter_particles(),
-                        if cl_state.intermission().is_none() {
-                            Some(cl_state.viewmodel_id())
-                        } else {
-                            None
-                        },
+                let cull_stats = world_renderer.render_pass(
+                    gfx_state,
+                    &mut init_pass,
+                    bump,
+                    &camera,
+                    cl_state.time(),
+                    cl_state.iter_visible_entities(),
+                    // enhanced particles are drawn in the deferred pass
+                    cl_state
+                        .iter_particles()
+                        .filter(|_| !render_vars.enhanced_particles),
+                    viewmodel,
+                    render_vars.water_alpha,
+                );
+                if render_vars.show_cull {
+                    info!(
+                        "{} leaves drawn, {} culled; {} entities drawn, {} culled",
+                        cull_stats.leaves_drawn,
+                        cull_stats.leaves_culled,
+                        cull_stats.entities_drawn,
+                        cull_stats.entities_culled,
                     );
                 }
-            }
+            });
+
+            encoder.finish()
         });
 
         Ok(())
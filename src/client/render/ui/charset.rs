@@ -0,0 +1,231 @@
+//! The character set used for console, menu and HUD text.
+//!
+//! By default this is `conchars` from `gfx.wad`, but it can be replaced by an image in the game
+//! directory, looked up in the order `gfx/conchars@4x.png`, `gfx/conchars@2x.png` and
+//! `gfx/conchars.png`. The image is a 16x16 grid of glyphs in the same order as `conchars`, at
+//! any resolution, and is drawn with smooth filtering at the same size as the original 8x8
+//! glyphs.
+//!
+//! If `gfx/conchars.widths` exists, the text is drawn with proportional spacing. The file lists
+//! the width in pixels of each of the 256 glyphs in order, separated by whitespace. Widths are in
+//! pixels of the charset image, and `//` starts a comment.
+
+use std::io::Read as _;
+
+use bevy::prelude::*;
+use failure::{bail, Error};
+
+use crate::{
+    client::render::Palette,
+    common::{vfs::Vfs, wad::Wad},
+};
+
+const CHARSET_COLS: u32 = 16;
+const CHARSET_ROWS: u32 = 16;
+const CHARSET_GLYPHS: usize = (CHARSET_COLS * CHARSET_ROWS) as usize;
+
+const CHARSET_IMAGES: [&str; 3] = [
+    "gfx/conchars@4x.png",
+    "gfx/conchars@2x.png",
+    "gfx/conchars.png",
+];
+const CHARSET_WIDTHS: &str = "gfx/conchars.widths";
+
+pub struct Charset {
+    width: u32,
+    height: u32,
+    rgba: Vec<u8>,
+
+    /// Whether this is the original charset from `gfx.wad`.
+    original: bool,
+
+    /// The advance of each glyph, as a fraction of the glyph cell width.
+    advances: Vec<f32>,
+}
+
+impl Charset {
+    /// Load the replacement charset if there is one, or `conchars` from `wad` otherwise.
+    pub fn load(vfs: &Vfs, wad: &Wad, palette: &Palette) -> Result<Charset, Error> {
+        let mut charset = match CHARSET_IMAGES
+            .iter()
+            .find_map(|path| Self::load_image(vfs, path))
+        {
+            Some(charset) => charset,
+            None => Self::from_wad(wad, palette)?,
+        };
+
+        if let Ok(mut file) = vfs.open(CHARSET_WIDTHS) {
+            let mut text = String::new();
+            let widths = file
+                .read_to_string(&mut text)
+                .map_err(Error::from)
+                .and_then(|_| parse_widths(&text));
+            match widths {
+                Ok(widths) => {
+                    let cell_width = charset.glyph_width() as f32;
+                    charset.advances = widths
+                        .into_iter()
+                        .map(|w| (w as f32 / cell_width).min(1.0))
+                        .collect();
+                }
+                Err(e) => warn!("Ignoring {}: {}", CHARSET_WIDTHS, e),
+            }
+        }
+
+        Ok(charset)
+    }
+
+    fn from_wad(wad: &Wad, palette: &Palette) -> Result<Charset, Error> {
+        let conchars = wad.open_conchars()?;
+
+        // index 0 is the background of the glyphs
+        let indices = conchars
+            .indices()
+            .iter()
+            .map(|i| if *i == 0 { 0xFF } else { *i })
+            .collect::<Vec<_>>();
+        let (diffuse_data, _) = palette.translate(&indices);
+
+        Ok(Charset {
+            width: conchars.width(),
+            height: conchars.height(),
+            rgba: diffuse_data.rgba.into_owned(),
+            original: true,
+            advances: vec![1.0; CHARSET_GLYPHS],
+        })
+    }
+
+    fn load_image(vfs: &Vfs, path: &str) -> Option<Charset> {
+        let mut data = Vec::new();
+        vfs.open(path).ok()?.read_to_end(&mut data).ok()?;
+
+        let image = match image::load_from_memory(&data) {
+            Ok(image) => image.into_rgba8(),
+            Err(e) => {
+                warn!("Couldn't load {}: {}", path, e);
+                return None;
+            }
+        };
+
+        let (width, height) = image.dimensions();
+        if width == 0 || width % CHARSET_COLS != 0 || height == 0 || height % CHARSET_ROWS != 0 {
+            warn!(
+                "Couldn't load {}: {}x{} isn't a 16x16 grid of glyphs",
+                path, width, height
+            );
+            return None;
+        }
+
+        Some(Charset {
+            width,
+            height,
+            rgba: image.into_raw(),
+            original: false,
+            advances: vec![1.0; CHARSET_GLYPHS],
+        })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// The RGBA pixels of the whole charset.
+    pub fn rgba(&self) -> &[u8] {
+        &self.rgba
+    }
+
+    pub fn glyph_width(&self) -> u32 {
+        self.width / CHARSET_COLS
+    }
+
+    pub fn glyph_height(&self) -> u32 {
+        self.height / CHARSET_ROWS
+    }
+
+    /// The RGBA pixels of a single glyph.
+    pub fn glyph_rgba(&self, glyph_id: u8) -> Vec<u8> {
+        let (glyph_w, glyph_h) = (self.glyph_width() as usize, self.glyph_height() as usize);
+        let left = glyph_w * (glyph_id as usize % CHARSET_COLS as usize);
+        let top = glyph_h * (glyph_id as usize / CHARSET_COLS as usize);
+
+        let mut out = Vec::with_capacity(glyph_w * glyph_h * 4);
+        for row in top..top + glyph_h {
+            let start = 4 * (row * self.width as usize + left);
+            out.extend_from_slice(&self.rgba[start..start + 4 * glyph_w]);
+        }
+
+        out
+    }
+
+    /// Whether the glyphs should be filtered when scaled. The original charset is meant to be
+    /// drawn with sharp pixels.
+    pub fn smooth(&self) -> bool {
+        !self.original
+    }
+
+    /// The advance of each glyph, as a fraction of the glyph cell width.
+    pub fn advances(&self) -> &[f32] {
+        &self.advances
+    }
+}
+
+fn parse_widths(text: &str) -> Result<Vec<u32>, Error> {
+    let widths = text
+        .lines()
+        .map(|line| line.split("//").next().unwrap_or(""))
+        .flat_map(str::split_whitespace)
+        .map(|word| {
+            word.parse::<u32>()
+                .map_err(|_| failure::format_err!("invalid width \"{}\"", word))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if widths.len() != CHARSET_GLYPHS {
+        bail!("expected {} widths, found {}", CHARSET_GLYPHS, widths.len());
+    }
+
+    Ok(widths)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_widths() {
+        let text = format!("// comment\n{}\n3 4 // last two\n", "8 ".repeat(254));
+        let widths = parse_widths(&text).unwrap();
+        assert_eq!(widths.len(), 256);
+        assert_eq!(&widths[252..], &[8, 8, 3, 4]);
+
+        assert!(parse_widths("8 8 8").is_err());
+        assert!(parse_widths(&"x ".repeat(256)).is_err());
+    }
+
+    #[test]
+    fn test_glyph_rgba() {
+        // a 32x32 charset, where every pixel holds the id of the glyph it belongs to
+        let rgba = (0..32 * 32)
+            .flat_map(|i| {
+                let (x, y) = (i % 32, i / 32);
+                [(y / 2 * 16 + x / 2) as u8; 4]
+            })
+            .collect();
+        let charset = Charset {
+            width: 32,
+            height: 32,
+            rgba,
+            original: false,
+            advances: vec![1.0; CHARSET_GLYPHS],
+        };
+
+        assert_eq!(charset.glyph_width(), 2);
+        assert_eq!(charset.glyph_rgba(0), vec![0; 16]);
+        assert_eq!(charset.glyph_rgba(17), vec![17; 16]);
+        assert_eq!(charset.glyph_rgba(255), vec![255; 16]);
+    }
+}
@@ -2,16 +2,19 @@ use std::{mem::size_of, num::NonZeroU32};
 
 use crate::{
     client::render::{
+        stats::{self, Counter},
         ui::{
+            charset::Charset,
             layout::{Anchor, ScreenPosition},
             quad::{QuadPipeline, QuadVertex},
             screen_space_vertex_scale, screen_space_vertex_translate,
         },
-        Extent2d, GraphicsState, Pipeline, TextureData,
+        DiffuseData, Extent2d, GraphicsState, Pipeline, ShaderCompiler, TextureData,
     },
-    common::util::any_slice_as_bytes,
+    common::{util::any_slice_as_bytes, vfs::Vfs},
 };
 
+use beef::Cow;
 use bevy::render::{
     render_phase::TrackedRenderPass,
     render_resource::{
@@ -22,13 +25,13 @@ use bevy::render::{
 };
 use cgmath::Vector2;
 use lazy_static::lazy_static;
+use parking_lot::Mutex;
 
 pub const GLYPH_WIDTH: usize = 8;
 pub const GLYPH_HEIGHT: usize = 8;
 const GLYPH_COLS: usize = 16;
 const GLYPH_ROWS: usize = 8;
 const GLYPH_COUNT: usize = GLYPH_ROWS * GLYPH_COLS;
-const GLYPH_TEXTURE_WIDTH: usize = GLYPH_WIDTH * GLYPH_COLS;
 
 /// The maximum number of glyphs that can be rendered at once.
 pub const MAX_INSTANCES: usize = 65536;
@@ -42,7 +45,8 @@ lazy_static! {
         wgpu::vertex_attr_array![
             2 => Float32x2, // a_instance_position
             3 => Float32x2, // a_instance_scale
-            4 => Uint32 // a_instance_layer
+            4 => Uint32, // a_instance_layer
+            5 => Float32 // a_instance_alpha
         ].to_vec(),
     ];
 }
@@ -52,12 +56,14 @@ pub struct GlyphPipeline {
     bind_group_layouts: Vec<BindGroupLayout>,
     format: wgpu::TextureFormat,
     instance_buffer: Buffer,
+    // the contents of `instance_buffer`
+    uploaded: Mutex<Vec<GlyphInstance>>,
 }
 
 impl GlyphPipeline {
     pub fn new(
         device: &RenderDevice,
-        compiler: &mut shaderc::Compiler,
+        compiler: &mut ShaderCompiler,
         format: wgpu::TextureFormat,
         sample_count: u32,
     ) -> GlyphPipeline {
@@ -65,7 +71,7 @@ impl GlyphPipeline {
             GlyphPipeline::create(device, compiler, &[], sample_count, format);
 
         let instance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("quad instance buffer"),
+            label: Some("glyph instance buffer"),
             size: (MAX_INSTANCES * size_of::<GlyphInstance>()) as u64,
             usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
             mapped_at_creation: false,
@@ -76,6 +82,7 @@ impl GlyphPipeline {
             bind_group_layouts,
             format,
             instance_buffer,
+            uploaded: Default::default(),
         }
     }
 
@@ -86,7 +93,7 @@ impl GlyphPipeline {
     pub fn rebuild(
         &mut self,
         device: &RenderDevice,
-        compiler: &mut shaderc::Compiler,
+        compiler: &mut ShaderCompiler,
         sample_count: u32,
     ) {
         let layout_refs = self.bind_group_layouts.iter();
@@ -104,6 +111,17 @@ impl GlyphPipeline {
     pub fn instance_buffer(&self) -> &Buffer {
         &self.instance_buffer
     }
+
+    /// Upload `instances` to the instance buffer, unless it already holds them.
+    pub fn write_instances(&self, queue: &RenderQueue, instances: Vec<GlyphInstance>) {
+        let mut uploaded = self.uploaded.lock();
+        if *uploaded != instances {
+            queue.write_buffer(&self.instance_buffer, 0, unsafe {
+                any_slice_as_bytes(&instances)
+            });
+            *uploaded = instances;
+        }
+    }
 }
 
 const BIND_GROUP_LAYOUT_ENTRIES: &[wgpu::BindGroupLayoutEntry] = &[
@@ -155,7 +173,12 @@ impl Pipeline for GlyphPipeline {
     }
 
     fn color_target_states_with_args(format: Self::Args) -> Vec<Option<wgpu::ColorTargetState>> {
-        QuadPipeline::color_target_states_with_args(format)
+        // blended so that text can fade out
+        vec![Some(wgpu::ColorTargetState {
+            format,
+            blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+            write_mask: wgpu::ColorWrites::ALL,
+        })]
     }
 
     fn depth_stencil_state() -> Option<wgpu::DepthStencilState> {
@@ -179,14 +202,15 @@ impl Pipeline for GlyphPipeline {
 }
 
 #[repr(C)]
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct GlyphInstance {
     pub position: Vector2<f32>,
     pub scale: Vector2<f32>,
     pub layer: u32,
+    pub alpha: f32,
 }
 
-pub enum GlyphRendererCommand {
+pub enum GlyphRendererCommand<'a> {
     Glyph {
         glyph_id: u8,
         position: ScreenPosition,
@@ -194,10 +218,18 @@ pub enum GlyphRendererCommand {
         scale: f32,
     },
     Text {
-        text: String,
+        text: &'a str,
+        position: ScreenPosition,
+        anchor: Anchor,
+        scale: f32,
+    },
+    /// Text drawn partly transparent, from 0 (invisible) to 1 (the same as `Text`).
+    FadedText {
+        text: &'a str,
         position: ScreenPosition,
         anchor: Anchor,
         scale: f32,
+        alpha: f32,
     },
 }
 
@@ -207,44 +239,32 @@ pub struct GlyphRenderer {
     #[allow(dead_code)]
     texture_views: Vec<TextureView>,
     const_bind_group: BindGroup,
+
+    /// How far each glyph advances the text, as a fraction of `GLYPH_WIDTH`.
+    advances: Vec<f32>,
 }
 
 impl GlyphRenderer {
-    pub fn new(state: &GraphicsState, device: &RenderDevice, queue: &RenderQueue) -> GlyphRenderer {
-        let conchars = state.gfx_wad().open_conchars().unwrap();
-
-        // TODO: validate conchars dimensions
-
-        let indices = conchars
-            .indices()
-            .iter()
-            .map(|i| if *i == 0 { 0xFF } else { *i })
-            .collect::<Vec<_>>();
-
-        // reorder indices from atlas order to array order
-        let mut array_order = Vec::new();
-        for glyph_id in 0..GLYPH_COUNT {
-            for glyph_r in 0..GLYPH_HEIGHT {
-                for glyph_c in 0..GLYPH_WIDTH {
-                    let atlas_r = GLYPH_HEIGHT * (glyph_id / GLYPH_COLS) + glyph_r;
-                    let atlas_c = GLYPH_WIDTH * (glyph_id % GLYPH_COLS) + glyph_c;
-                    array_order.push(indices[atlas_r * GLYPH_TEXTURE_WIDTH + atlas_c]);
-                }
-            }
-        }
+    pub fn new(
+        state: &GraphicsState,
+        vfs: &Vfs,
+        device: &RenderDevice,
+        queue: &RenderQueue,
+    ) -> GlyphRenderer {
+        let charset = Charset::load(vfs, state.gfx_wad(), state.palette()).unwrap();
 
-        let textures = array_order
-            .chunks_exact(GLYPH_WIDTH * GLYPH_HEIGHT)
-            .enumerate()
-            .map(|(id, indices)| {
-                let (diffuse_data, _) = state.palette().translate(&indices);
+        let textures = (0..GLYPH_COUNT)
+            .map(|id| {
+                let rgba = charset.glyph_rgba(id as u8);
                 state.create_texture(
                     device,
                     queue,
                     Some(&format!("conchars[{}]", id)),
-                    GLYPH_WIDTH as u32,
-                    GLYPH_HEIGHT as u32,
-                    &TextureData::Diffuse(diffuse_data),
+                    charset.glyph_width(),
+                    charset.glyph_height(),
+                    &TextureData::Diffuse(DiffuseData {
+                        rgba: Cow::owned(rgba),
+                    }),
                 )
             })
             .collect::<Vec<_>>();
@@ -255,13 +275,20 @@ impl GlyphRenderer {
             .collect::<Vec<_>>();
         let texture_view_refs = texture_views.iter().map(|t| &**t).collect::<Vec<_>>();
 
+        // replacement charsets are usually higher resolution, so they need filtering
+        let sampler = if charset.smooth() {
+            state.diffuse_sampler()
+        } else {
+            state.nearest_sampler()
+        };
+
         let const_bind_group = device.create_bind_group(
             Some("glyph constant bind group"),
             &state.glyph_pipeline().bind_group_layouts()[0],
             &[
                 wgpu::BindGroupEntry {
                     binding: 0,
-                    resource: wgpu::BindingResource::Sampler(state.nearest_sampler()),
+                    resource: wgpu::BindingResource::Sampler(sampler),
                 },
                 wgpu::BindGroupEntry {
                     binding: 1,
@@ -274,9 +301,16 @@ impl GlyphRenderer {
             textures,
             texture_views,
             const_bind_group,
+            advances: charset.advances().to_vec(),
         }
     }
 
+    fn advance(&self, chr: char) -> f32 {
+        self.advances
+            .get(chr as usize)
+            .map_or(GLYPH_WIDTH as f32, |a| a * GLYPH_WIDTH as f32)
+    }
+
     pub fn generate_instances(
         &self,
         commands: &[GlyphRendererCommand],
@@ -288,7 +322,7 @@ impl GlyphRenderer {
             height: display_height,
         } = target_size;
         for cmd in commands {
-            match cmd {
+            let (text, position, anchor, scale, alpha) = match cmd {
                 GlyphRendererCommand::Glyph {
                     glyph_id,
                     position,
@@ -318,65 +352,96 @@ impl GlyphRenderer {
                             (GLYPH_HEIGHT as f32 * scale) as u32,
                         ),
                         layer: *glyph_id as u32,
+                        alpha: 1.0,
                     });
+                    continue;
                 }
                 GlyphRendererCommand::Text {
                     text,
                     position,
                     anchor,
                     scale,
-                } => {
-                    let (screen_x, screen_y) =
-                        position.to_xy(display_width, display_height, *scale);
-                    let (text_x, text_y) = anchor.to_xy(
-                        ((text.chars().count() * GLYPH_WIDTH) as f32 * scale) as u32,
+                } => (text, position, anchor, *scale, 1.0),
+                GlyphRendererCommand::FadedText {
+                    text,
+                    position,
+                    anchor,
+                    scale,
+                    alpha,
+                } => (text, position, anchor, *scale, alpha.clamp(0.0, 1.0)),
+            };
+
+            let (screen_x, screen_y) = position.to_xy(display_width, display_height, scale);
+            let text_width = text.chars().map(|chr| self.advance(chr)).sum::<f32>();
+            let (text_x, text_y) = anchor.to_xy(
+                (text_width * scale) as u32,
+                (GLYPH_HEIGHT as f32 * scale) as u32,
+            );
+            let x = screen_x - text_x;
+            let y = screen_y - text_y;
+
+            let mut advance = 0.0;
+            for chr in text.chars() {
+                let abs_x = x + (advance * scale) as i32;
+                advance += self.advance(chr);
+
+                instances.push(GlyphInstance {
+                    position: screen_space_vertex_translate(
+                        display_width,
+                        display_height,
+                        abs_x,
+                        y,
+                    ),
+                    scale: screen_space_vertex_scale(
+                        display_width,
+                        display_height,
+                        (GLYPH_WIDTH as f32 * scale) as u32,
                         (GLYPH_HEIGHT as f32 * scale) as u32,
-                    );
-                    let x = screen_x - text_x;
-                    let y = screen_y - text_y;
-
-                    for (chr_id, chr) in text.chars().enumerate() {
-                        let abs_x = x + ((GLYPH_WIDTH * chr_id) as f32 * scale) as i32;
-
-                        instances.push(GlyphInstance {
-                            position: screen_space_vertex_translate(
-                                display_width,
-                                display_height,
-                                abs_x,
-                                y,
-                            ),
-                            scale: screen_space_vertex_scale(
-                                display_width,
-                                display_height,
-                                (GLYPH_WIDTH as f32 * scale) as u32,
-                                (GLYPH_HEIGHT as f32 * scale) as u32,
-                            ),
-                            layer: chr as u32,
-                        });
-                    }
-                }
+                    ),
+                    layer: chr as u32,
+                    alpha,
+                });
             }
         }
 
         instances
     }
 
+    /// Upload the instances for `commands`, returning how many there are.
+    pub fn prepare(
+        &self,
+        state: &GraphicsState,
+        queue: &RenderQueue,
+        target_size: Extent2d,
+        commands: &[GlyphRendererCommand],
+    ) -> u32 {
+        let mut instances = self.generate_instances(commands, target_size);
+        instances.truncate(MAX_INSTANCES);
+        if instances.is_empty() {
+            return 0;
+        }
+
+        let instance_count = instances.len() as u32;
+        state.glyph_pipeline().write_instances(queue, instances);
+        instance_count
+    }
+
+    /// Draw the first `instance_count` glyphs uploaded by [`GlyphRenderer::prepare`].
     pub fn record_draw<'a>(
         &'a self,
         state: &'a GraphicsState,
-        queue: &RenderQueue,
         pass: &mut TrackedRenderPass<'a>,
-        target_size: Extent2d,
-        commands: &[GlyphRendererCommand],
+        instance_count: u32,
     ) {
-        let instances = self.generate_instances(commands, target_size);
-        queue.write_buffer(state.glyph_pipeline().instance_buffer(), 0, unsafe {
-            any_slice_as_bytes(&instances)
-        });
+        if instance_count == 0 {
+            return;
+        }
+
         pass.set_render_pipeline(state.glyph_pipeline().pipeline());
         pass.set_vertex_buffer(0, state.quad_pipeline().vertex_buffer().slice(..));
         pass.set_vertex_buffer(1, state.glyph_pipeline().instance_buffer().slice(..));
         pass.set_bind_group(0, &self.const_bind_group, &[]);
-        pass.draw(0..6, 0..instances.len() as u32);
+        pass.draw(0..6, 0..instance_count);
+        stats::add(Counter::DrawCalls, 1);
     }
 }
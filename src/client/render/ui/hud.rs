@@ -1,17 +1,27 @@
+use std::{
+    hash::{Hash, Hasher},
+    mem,
+};
+
 use crate::{
     client::{
+        ghost::GhostView,
+        locale::Locale,
         render::{
             ui::{
-                glyph::GlyphRendererCommand,
-                layout::{Anchor, Layout, ScreenPosition, Size},
+                glyph::{GlyphRendererCommand, GLYPH_HEIGHT},
+                layout::{Anchor, AnchorCoord, Layout, ScreenPosition, Size},
                 quad::{QuadRendererCommand, QuadTexture},
+                GlyphCommands, QuadCommands,
             },
             GraphicsState,
         },
+        runtimer::{self, RunTimer},
+        state::PlayerInfo,
         IntermissionKind,
     },
     common::{
-        console::Registry,
+        console::{NotifyView, Registry},
         net::{ClientStat, ItemFlags},
         vfs::Vfs,
         wad::QPic,
@@ -42,20 +52,173 @@ const OVERLAY_Y_OFS: i32 = -OVERLAY_HEIGHT / 2;
 
 const OVERLAY_ANCHOR: Anchor = Anchor::CENTER;
 
+const SBAR_HEIGHT: i32 = 24;
+/// The status bar has a slot each for armor, health and ammo, which is an icon followed by a
+/// three-digit number.
+const SBAR_SLOT_WIDTH: i32 = 112;
+
+// The rectangles making up each quad crosshair style, as `[x, y, width, height]` relative to the
+// center of the screen, in crosshair pixels (scaled by `crosshairsize` and the HUD scale).
+type CrosshairRect = [f32; 4];
+
+const CROSSHAIR_CROSS: &[CrosshairRect] = &[[-4.5, -0.5, 9.0, 1.0], [-0.5, -4.5, 1.0, 9.0]];
+const CROSSHAIR_GAP_CROSS: &[CrosshairRect] = &[
+    [-5.5, -0.5, 3.0, 1.0],
+    [2.5, -0.5, 3.0, 1.0],
+    [-0.5, -5.5, 1.0, 3.0],
+    [-0.5, 2.5, 1.0, 3.0],
+];
+const CROSSHAIR_DOT: &[CrosshairRect] = &[[-1.0, -1.0, 2.0, 2.0]];
+const CROSSHAIR_GAP_CROSS_DOT: &[CrosshairRect] = &[
+    [-5.5, -0.5, 3.0, 1.0],
+    [2.5, -0.5, 3.0, 1.0],
+    [-0.5, -5.5, 1.0, 3.0],
+    [-0.5, 2.5, 1.0, 3.0],
+    [-0.5, -0.5, 1.0, 1.0],
+];
+
+/// The mission packs have status bars with their own weapons and items, which are only drawn when
+/// playing them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MissionPack {
+    #[default]
+    None,
+    /// Scourge of Armagon
+    Hipnotic,
+    /// Dissolution of Eternity
+    Rogue,
+}
+
+impl MissionPack {
+    /// The mission pack whose status bar should be used for a game directory.
+    pub fn from_game(game: Option<&str>) -> MissionPack {
+        match game {
+            Some(game) if game.eq_ignore_ascii_case("hipnotic") => MissionPack::Hipnotic,
+            Some(game) if game.eq_ignore_ascii_case("rogue") => MissionPack::Rogue,
+            _ => MissionPack::None,
+        }
+    }
+
+    /// The extra textures used by the mission pack's status bar, all of which are in its
+    /// `gfx.wad`.
+    fn texture_ids(&self) -> Vec<HudTextureId> {
+        use HudTextureId::*;
+        match *self {
+            MissionPack::None => Vec::new(),
+            MissionPack::Hipnotic => HipWeaponId::iter()
+                .flat_map(|id| {
+                    (0..5)
+                        .map(|frame| WeaponFrame::Pickup { frame })
+                        .chain([WeaponFrame::Inactive, WeaponFrame::Active])
+                        .map(move |frame| HipWeapon { id, frame })
+                })
+                .chain(HIPNOTIC_ITEMS.map(|name| Lump { name }))
+                .collect(),
+            MissionPack::Rogue => ROGUE_WEAPONS
+                .iter()
+                .chain(&ROGUE_AMMO)
+                .chain(&ROGUE_ITEMS)
+                .chain(&ROGUE_INV_BARS)
+                .map(|&name| Lump { name })
+                .collect(),
+        }
+    }
+}
+
+// Scourge of Armagon's weapons are drawn from these item bits, in the order of `HipWeaponId`
+// (the proximity gun's bit is used for two of them)
+const HIPNOTIC_WEAPON_BITS: [u32; 4] = [23, 7, 4, 16];
+const HIPNOTIC_PROXIMITY_GUN: u32 = 1 << 16;
+const HIPNOTIC_ITEMS: [&str; 2] = ["SB_WSUIT", "SB_ESHLD"];
+const HIPNOTIC_ITEM_BITS: [u32; 2] = [24, 25];
+
+const ROGUE_WEAPONS: [&str; 5] = ["R_LAVA", "R_SUPERLAVA", "R_GREN", "R_MULTIROCK", "R_PLASMA"];
+/// The first of Dissolution of Eternity's powered-up weapons, which follow it in the order of
+/// `ROGUE_WEAPONS`.
+const ROGUE_LAVA_NAILGUN: u32 = 1 << 12;
+const ROGUE_AMMO: [&str; 3] = ["R_AMMOLAVA", "R_AMMOPLASMA", "R_AMMOMULTI"];
+const ROGUE_AMMO_BITS: [u32; 3] = [26, 27, 28];
+const ROGUE_SHELLS: u32 = 7;
+const ROGUE_ARMOR_1: u32 = 23;
+const ROGUE_ITEMS: [&str; 2] = ["R_SHIELD1", "R_AGRAV1"];
+const ROGUE_ITEM_BITS: [u32; 2] = [29, 30];
+/// The inventory bars for the powered-up and normal weapons.
+const ROGUE_INV_BARS: [&str; 2] = ["R_INVBAR1", "R_INVBAR2"];
+
 pub enum HudState<'a> {
     InGame {
         items: ItemFlags,
         item_pickup_time: &'a [Duration],
         stats: &'a [i32],
         face_anim_time: Duration,
+        /// How long the player has been in the level, drawn with the level's stats over the
+        /// status bar while `+showscores` is held in single player.
+        level_time: Option<Duration>,
+        /// Drawn over the view while `+showscores` is held in multiplayer.
+        scoreboard: Option<Scoreboard<'a>>,
     },
     Intermission {
         kind: &'a IntermissionKind,
         completion_duration: Duration,
         stats: &'a [i32],
+        /// Drawn instead of the level's stats in deathmatch.
+        scoreboard: Option<Scoreboard<'a>>,
     },
 }
 
+impl HudState<'_> {
+    /// Hash everything the HUD is drawn from at `time`, so that states which hash the same are
+    /// drawn the same.
+    pub fn hash_at<H: Hasher>(&self, time: Duration, hasher: &mut H) {
+        mem::discriminant(self).hash(hasher);
+        match self {
+            HudState::InGame {
+                items,
+                item_pickup_time,
+                stats,
+                face_anim_time,
+                level_time,
+                scoreboard,
+            } => {
+                items.bits().hash(hasher);
+                stats.hash(hasher);
+                level_time.map(|t| t.num_seconds()).hash(hasher);
+                scoreboard.hash(hasher);
+
+                // weapons flash in 100ms steps for a second after they're picked up
+                for &pickup_time in item_pickup_time.iter() {
+                    let delta = time - pickup_time;
+                    (delta < Duration::try_seconds(1).unwrap())
+                        .then(|| delta.num_milliseconds() / 100)
+                        .hash(hasher);
+                }
+
+                (*face_anim_time > time).hash(hasher);
+            }
+            HudState::Intermission {
+                kind,
+                completion_duration,
+                stats,
+                scoreboard,
+            } => {
+                mem::discriminant(*kind).hash(hasher);
+                completion_duration.hash(hasher);
+                stats.hash(hasher);
+                scoreboard.hash(hasher);
+            }
+        }
+    }
+}
+
+/// The players listed on the scoreboard.
+#[derive(Hash)]
+pub struct Scoreboard<'a> {
+    /// Each player's slot and info, in the order they're ranked.
+    pub players: &'a [(usize, PlayerInfo)],
+    /// The slot of the player whose view this is, who's marked on the scoreboard.
+    pub local_player: usize,
+}
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 enum HudTextureId {
     Digit { alt: bool, value: usize },
@@ -68,6 +231,8 @@ enum HudTextureId {
     Item { id: ItemId },
     Sigil { id: usize },
     Face { id: FaceId },
+    HipWeapon { id: HipWeaponId, frame: WeaponFrame },
+    Lump { name: &'static str },
     StatusBar,
     InvBar,
     ScoreBar,
@@ -75,6 +240,9 @@ enum HudTextureId {
     // these are not in gfx.wad
     Complete,
     Intermission,
+    Ranking,
+    PlainStatusBar,
+    PlainInvBar,
 }
 
 impl std::fmt::Display for HudTextureId {
@@ -91,6 +259,8 @@ impl std::fmt::Display for HudTextureId {
             Item { id } => write!(f, "SB_{}", id),
             Sigil { id } => write!(f, "SB_SIGIL{}", id + 1),
             Face { id } => write!(f, "{}", id),
+            HipWeapon { id, frame } => write!(f, "INV{}_{}", frame, id),
+            Lump { name } => write!(f, "{}", name),
             StatusBar => write!(f, "SBAR"),
             InvBar => write!(f, "IBAR"),
             ScoreBar => write!(f, "SCOREBAR"),
@@ -98,6 +268,9 @@ impl std::fmt::Display for HudTextureId {
             // these are not in gfx.wad
             Complete => write!(f, "gfx/complete.lmp"),
             Intermission => write!(f, "gfx/inter.lmp"),
+            Ranking => write!(f, "gfx/ranking.lmp"),
+            PlainStatusBar => write!(f, "plain SBAR"),
+            PlainInvBar => write!(f, "plain IBAR"),
         }
     }
 }
@@ -139,6 +312,24 @@ impl std::fmt::Display for WeaponFrame {
     }
 }
 
+const HIP_WEAPON_ID_NAMES: [&str; 5] = ["LASER", "MJOLNIR", "GREN_PROX", "PROX_GREN", "PROX"];
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, FromPrimitive, EnumIter)]
+enum HipWeaponId {
+    LaserCannon = 0,
+    Mjolnir = 1,
+    /// The grenade launcher, when it was the last of the two to be picked up.
+    GrenadeProximity = 2,
+    /// The proximity gun, when it was the last of the two to be picked up.
+    ProximityGrenade = 3,
+    ProximityGun = 4,
+}
+
+impl std::fmt::Display for HipWeaponId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", HIP_WEAPON_ID_NAMES[*self as usize])
+    }
+}
+
 const AMMO_ID_NAMES: [&str; 4] = ["SHELLS", "NAILS", "ROCKET", "CELLS"];
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, FromPrimitive, EnumIter)]
 enum AmmoId {
@@ -200,27 +391,68 @@ pub struct HudVars {
     pub crosshair: u8,
     #[serde(rename(deserialize = "cl_hud"))]
     pub hud_style: u8,
+    #[serde(rename(deserialize = "scr_runtimer"))]
+    pub run_timer: u8,
+    #[serde(rename(deserialize = "scr_colorblind"))]
+    pub colorblind: u8,
+    #[serde(rename(deserialize = "scr_hudcontrast"))]
+    pub high_contrast: u8,
+    #[serde(rename(deserialize = "scr_sbarscale"))]
+    pub sbar_scale: f32,
+    #[serde(rename(deserialize = "crosshaircolor"))]
+    pub crosshair_color: u8,
+    #[serde(rename(deserialize = "crosshairsize"))]
+    pub crosshair_size: f32,
+    #[serde(rename(deserialize = "r_showfps"))]
+    pub show_fps: u8,
+    #[serde(rename(deserialize = "r_speeds"))]
+    pub speeds: u8,
+    /// Whether `+showscores` is held.
+    #[serde(skip)]
+    pub show_scores: bool,
 }
 
 impl Default for HudVars {
     fn default() -> Self {
         Self {
             crosshair: 1,
+            crosshair_color: 15,
+            crosshair_size: 1.0,
             hud_style: 3,
+            run_timer: 0,
+            colorblind: 0,
+            high_contrast: 0,
+            sbar_scale: 2.0,
+            show_fps: 0,
+            speeds: 0,
+            show_scores: false,
         }
     }
 }
 
+impl HudVars {
+    /// How large to draw the HUD, which is never smaller than its original size.
+    pub fn scale(&self) -> f32 {
+        self.sbar_scale.max(1.0)
+    }
+}
+
 impl ExtractResource for HudVars {
     type Source = Registry;
 
     fn extract_resource(source: &Self::Source) -> Self {
-        source.read_cvars().unwrap_or_default()
+        HudVars {
+            show_scores: source.is_pressed("showscores"),
+            ..source.read_cvars().unwrap_or_default()
+        }
     }
 }
 
 pub struct HudRenderer {
     textures: HashMap<HudTextureId, QuadTexture>,
+    // a single texel of each palette color, stretched to draw the crosshairs and scoreboard colors
+    solid_colors: Vec<QuadTexture>,
+    mission_pack: MissionPack,
 }
 
 impl HudRenderer {
@@ -230,6 +462,7 @@ impl HudRenderer {
         vfs: &Vfs,
         device: &RenderDevice,
         queue: &RenderQueue,
+        mission_pack: MissionPack,
     ) -> HudRenderer {
         use HudTextureId::*;
         let mut ids = Vec::new();
@@ -291,14 +524,55 @@ impl HudRenderer {
         }
 
         // new id list for textures not in gfx.wad
-        let ids = vec![Complete, Intermission];
+        let ids = vec![Complete, Intermission, Ranking];
         for id in ids.into_iter() {
             debug!("Opening {}", id);
             let qpic = QPic::load(vfs.open(&format!("{}", id)).unwrap()).unwrap();
             textures.insert(id, QuadTexture::from_qpic(state, device, queue, &qpic));
         }
 
-        HudRenderer { textures }
+        // don't try to draw the mission pack's status bar without its textures
+        let mut mission_pack = mission_pack;
+        let mut mission_textures = HashMap::default();
+        for id in mission_pack.texture_ids() {
+            match state.gfx_wad().open_qpic(id.to_string()) {
+                Ok(qpic) => {
+                    let texture = QuadTexture::from_qpic(state, device, queue, &qpic);
+                    mission_textures.insert(id, texture);
+                }
+                Err(e) => {
+                    warn!(
+                        "Can't draw the {:?} status bar, {}: {}",
+                        mission_pack, id, e
+                    );
+                    mission_pack = MissionPack::None;
+                    mission_textures.clear();
+                    break;
+                }
+            }
+        }
+        textures.extend(mission_textures);
+
+        // solid black stand-ins for the status bar backgrounds, for scr_hudcontrast
+        for (id, plain_id) in [(StatusBar, PlainStatusBar), (InvBar, PlainInvBar)] {
+            let (width, height) = {
+                let bar = &textures[&id];
+                (bar.width(), bar.height())
+            };
+            let indices = vec![0; (width * height) as usize];
+            let texture = QuadTexture::from_indices(state, device, queue, width, height, &indices);
+            textures.insert(plain_id, texture);
+        }
+
+        let solid_colors = (0..=u8::MAX)
+            .map(|index| QuadTexture::from_indices(state, device, queue, 1, 1, &[index]))
+            .collect();
+
+        HudRenderer {
+            textures,
+            solid_colors,
+            mission_pack,
+        }
     }
 
     fn cmd_number<'a>(
@@ -311,7 +585,7 @@ impl HudRenderer {
         screen_y_ofs: i32,
         quad_anchor: Anchor,
         scale: f32,
-        quad_cmds: &mut Vec<QuadRendererCommand<'a>>,
+        quad_cmds: &mut QuadCommands<'_, 'a>,
     ) {
         use HudTextureId::*;
 
@@ -361,7 +635,7 @@ impl HudRenderer {
         x_ofs: i32,
         y_ofs: i32,
         scale: f32,
-        quad_cmds: &mut Vec<QuadRendererCommand<'a>>,
+        quad_cmds: &mut QuadCommands<'_, 'a>,
     ) {
         quad_cmds.push(QuadRendererCommand {
             texture: self.textures.get(&texture_id).unwrap(),
@@ -377,6 +651,67 @@ impl HudRenderer {
         });
     }
 
+    // Draw a quad on the status bar, placed the way the original status bar code places it.
+    //
+    // `x` and `y` are the position of the top-left corner of the quad relative to the top-left
+    // corner of the status bar, with `y` pointing down, so the inventory bar is at negative `y`.
+    fn cmd_sbar_pic<'a>(
+        &'a self,
+        texture_id: HudTextureId,
+        x: i32,
+        y: i32,
+        scale: f32,
+        quad_cmds: &mut QuadCommands<'_, 'a>,
+    ) {
+        let height = self.textures.get(&texture_id).unwrap().height() as i32;
+        self.cmd_sbar_quad(texture_id, x, SBAR_HEIGHT - y - height, scale, quad_cmds);
+    }
+
+    // Draw Scourge of Armagon's weapons, which share the inventory bar with the original ones.
+    fn cmd_hipnotic_weapons<'a>(
+        &'a self,
+        time: Duration,
+        items: ItemFlags,
+        item_pickup_time: &'a [Duration],
+        active_weapon: u32,
+        scale: f32,
+        quad_cmds: &mut QuadCommands<'_, 'a>,
+    ) {
+        use HudTextureId::*;
+
+        // the grenade launcher and proximity gun share a slot
+        let mut grenade_flashing = false;
+        for (i, &bit) in HIPNOTIC_WEAPON_BITS.iter().enumerate() {
+            if items.bits() & (1 << bit) == 0 {
+                continue;
+            }
+
+            let frame = weapon_frame(
+                time,
+                item_pickup_time[bit as usize],
+                active_weapon == 1 << bit,
+            );
+            let flashing = frame != WeaponFrame::Inactive;
+            let (id, x) = match i {
+                2 if items.bits() & HIPNOTIC_PROXIMITY_GUN != 0 && flashing => {
+                    grenade_flashing = true;
+                    (HipWeaponId::GrenadeProximity, 96)
+                }
+                2 => continue,
+                3 if items.contains(ItemFlags::GRENADE_LAUNCHER) => {
+                    if grenade_flashing {
+                        continue;
+                    }
+                    (HipWeaponId::ProximityGrenade, 96)
+                }
+                3 => (HipWeaponId::ProximityGun, 96),
+                _ => (HipWeaponId::from_usize(i).unwrap(), 176 + 24 * i as i32),
+            };
+
+            self.cmd_sbar_pic(HipWeapon { id, frame }, x, -16, scale, quad_cmds);
+        }
+    }
+
     // Draw a quad on the status bar.
     //
     // `x_ofs` and `y_ofs` are specified relative to the bottom-left corner of
@@ -389,7 +724,7 @@ impl HudRenderer {
         x_ofs: i32,
         y_ofs: i32,
         scale: f32,
-        quad_cmds: &mut Vec<QuadRendererCommand<'a>>,
+        quad_cmds: &mut QuadCommands<'_, 'a>,
     ) {
         self.cmd_number(
             number,
@@ -404,6 +739,23 @@ impl HudRenderer {
         );
     }
 
+    // Draw a marker in the first digit of the number in status bar slot `slot`, so that low values
+    // aren't only shown by the red digits. Low values never have more than two digits, so the
+    // first one is always blank.
+    fn cmd_sbar_warning(&self, slot: i32, scale: f32, glyph_cmds: &mut GlyphCommands<'_>) {
+        glyph_cmds.push(GlyphRendererCommand::Glyph {
+            glyph_id: b'!',
+            position: ScreenPosition::Relative {
+                anchor: Anchor::BOTTOM_CENTER,
+                // centered in the digit after the slot's icon, which are both 24 wide
+                x_ofs: OVERLAY_X_OFS + SBAR_SLOT_WIDTH * slot + 24 + 8,
+                y_ofs: 8,
+            },
+            anchor: Anchor::BOTTOM_LEFT,
+            scale,
+        });
+    }
+
     // Draw the status bar.
     fn cmd_sbar<'a>(
         &'a self,
@@ -414,8 +766,8 @@ impl HudRenderer {
         face_anim_time: Duration,
         scale: f32,
         hud_cvars: &HudVars,
-        quad_cmds: &mut Vec<QuadRendererCommand<'a>>,
-        glyph_cmds: &mut Vec<GlyphRendererCommand>,
+        quad_cmds: &mut QuadCommands<'_, 'a>,
+        glyph_cmds: &mut GlyphCommands<'_>,
     ) {
         use HudTextureId::*;
 
@@ -425,12 +777,23 @@ impl HudRenderer {
 
         let sbar = self.textures.get(&StatusBar).unwrap();
         let sbar_x_ofs = -(sbar.width() as i32) / 2;
+        let active_weapon = stats[ClientStat::ActiveWeapon as usize] as u32;
+
+        let (sbar_background, ibar_background) = if hud_cvars.high_contrast != 0 {
+            (PlainStatusBar, PlainInvBar)
+        } else if self.mission_pack == MissionPack::Rogue {
+            let powered_up = active_weapon >= ROGUE_LAVA_NAILGUN;
+            let name = ROGUE_INV_BARS[if powered_up { 0 } else { 1 }];
+            (StatusBar, Lump { name })
+        } else {
+            (StatusBar, InvBar)
+        };
 
         // status bar background
-        self.cmd_sbar_quad(StatusBar, 0, 0, scale, quad_cmds);
+        self.cmd_sbar_quad(sbar_background, 0, 0, scale, quad_cmds);
 
         // inventory bar background
-        self.cmd_sbar_quad(InvBar, 0, sbar.height() as i32, scale, quad_cmds);
+        self.cmd_sbar_quad(ibar_background, 0, sbar.height() as i32, scale, quad_cmds);
 
         // weapon slots
         for i in 0..7 {
@@ -462,6 +825,30 @@ impl HudRenderer {
             }
         }
 
+        match self.mission_pack {
+            MissionPack::Hipnotic => self.cmd_hipnotic_weapons(
+                time,
+                items,
+                item_pickup_time,
+                active_weapon,
+                scale,
+                quad_cmds,
+            ),
+
+            // powered-up weapons are drawn over the weapons they replace
+            MissionPack::Rogue => {
+                let powered_up =
+                    (0..ROGUE_WEAPONS.len()).find(|&i| active_weapon == ROGUE_LAVA_NAILGUN << i);
+                if let Some(i) = powered_up {
+                    let name = ROGUE_WEAPONS[i];
+                    let x = 24 * (i as i32 + 2);
+                    self.cmd_sbar_pic(Lump { name }, x, -16, scale, quad_cmds);
+                }
+            }
+
+            MissionPack::None => (),
+        }
+
         if hud_cvars.hud_style > 2 {
             // ammo counters
             for i in 0..4 {
@@ -485,6 +872,11 @@ impl HudRenderer {
 
         // items (keys and powerups)
         for i in 0..6 {
+            // Scourge of Armagon draws the keys on the status bar instead
+            if self.mission_pack == MissionPack::Hipnotic && i < 2 {
+                continue;
+            }
+
             if items.contains(ItemFlags::from_bits(ItemFlags::KEY_1.bits() << i).unwrap()) {
                 quad_cmds.push(QuadRendererCommand {
                     texture: self
@@ -506,21 +898,34 @@ impl HudRenderer {
             }
         }
 
-        // sigils
-        for i in 0..4 {
-            if items.contains(ItemFlags::from_bits(ItemFlags::SIGIL_1.bits() << i).unwrap()) {
-                quad_cmds.push(QuadRendererCommand {
-                    texture: self.textures.get(&Sigil { id: i }).unwrap(),
-                    layout: Layout {
-                        position: ScreenPosition::Relative {
-                            anchor: Anchor::BOTTOM_CENTER,
-                            x_ofs: sbar_x_ofs + 8 * i as i32 + 288,
-                            y_ofs: sbar.height() as i32,
+        let (pack_items, pack_item_bits): (&[&str], &[u32]) = match self.mission_pack {
+            MissionPack::Hipnotic => (&HIPNOTIC_ITEMS, &HIPNOTIC_ITEM_BITS),
+            MissionPack::Rogue => (&ROGUE_ITEMS, &ROGUE_ITEM_BITS),
+            MissionPack::None => (&[], &[]),
+        };
+        for (i, (&name, &bit)) in pack_items.iter().zip(pack_item_bits).enumerate() {
+            if items.bits() & (1 << bit) != 0 {
+                self.cmd_sbar_pic(Lump { name }, 288 + 16 * i as i32, -16, scale, quad_cmds);
+            }
+        }
+
+        // sigils, whose bits Dissolution of Eternity uses for its items
+        if self.mission_pack != MissionPack::Rogue {
+            for i in 0..4 {
+                if items.contains(ItemFlags::from_bits(ItemFlags::SIGIL_1.bits() << i).unwrap()) {
+                    quad_cmds.push(QuadRendererCommand {
+                        texture: self.textures.get(&Sigil { id: i }).unwrap(),
+                        layout: Layout {
+                            position: ScreenPosition::Relative {
+                                anchor: Anchor::BOTTOM_CENTER,
+                                x_ofs: sbar_x_ofs + 8 * i as i32 + 288,
+                                y_ofs: sbar.height() as i32,
+                            },
+                            anchor: Anchor::BOTTOM_LEFT,
+                            size: Size::Scale { factor: scale },
                         },
-                        anchor: Anchor::BOTTOM_LEFT,
-                        size: Size::Scale { factor: scale },
-                    },
-                });
+                    });
+                }
             }
         }
 
@@ -532,10 +937,17 @@ impl HudRenderer {
         } else {
             let armor = stats[ClientStat::Armor as usize];
             self.cmd_sbar_number(armor, armor <= 25, 3, armor_width, 0, scale, quad_cmds);
+            if hud_cvars.colorblind != 0 && armor <= 25 {
+                self.cmd_sbar_warning(0, scale, glyph_cmds);
+            }
 
+            let armor_1 = match self.mission_pack {
+                MissionPack::Rogue => 1 << ROGUE_ARMOR_1,
+                _ => ItemFlags::ARMOR_1.bits(),
+            };
             let mut armor_id = None;
             for i in (0..3).rev() {
-                if items.contains(ItemFlags::from_bits(ItemFlags::ARMOR_1.bits() << i).unwrap()) {
+                if items.bits() & (armor_1 << i) != 0 {
                     armor_id = Some(Armor { id: i });
                     break;
                 }
@@ -549,10 +961,37 @@ impl HudRenderer {
         // health
         let health = stats[ClientStat::Health as usize];
         self.cmd_sbar_number(health, health <= 25, 3, 136, 0, scale, quad_cmds);
+        if hud_cvars.colorblind != 0 && (0..=25).contains(&health) {
+            self.cmd_sbar_warning(1, scale, glyph_cmds);
+        }
 
         let ammo = stats[ClientStat::Ammo as usize];
         self.cmd_sbar_number(ammo, ammo <= 10, 3, 248, 0, scale, quad_cmds);
 
+        // the type of the current ammo
+        let shells = match self.mission_pack {
+            MissionPack::Rogue => 1 << ROGUE_SHELLS,
+            _ => ItemFlags::SHELLS.bits(),
+        };
+        let ammo_icon = AmmoId::iter()
+            .find(|&id| items.bits() & (shells << id as u32) != 0)
+            .map(|id| Ammo { id })
+            .or_else(|| match self.mission_pack {
+                MissionPack::Rogue => ROGUE_AMMO_BITS
+                    .iter()
+                    .position(|&bit| items.bits() & (1 << bit) != 0)
+                    .map(|i| Lump {
+                        name: ROGUE_AMMO[i],
+                    }),
+                _ => None,
+            });
+        if let Some(id) = ammo_icon {
+            self.cmd_sbar_quad(id, 224, 0, scale, quad_cmds);
+        }
+        if hud_cvars.colorblind != 0 && ammo <= 10 {
+            self.cmd_sbar_warning(2, scale, glyph_cmds);
+        }
+
         let face = if items.contains(ItemFlags::INVISIBILITY | ItemFlags::INVULNERABILITY) {
             FaceId::InvisibleInvulnerable
         } else if items.contains(ItemFlags::QUAD) {
@@ -577,14 +1016,128 @@ impl HudRenderer {
 
         self.cmd_sbar_quad(Face { id: face }, 112, 0, scale, quad_cmds);
 
-        // crosshair
-        if hud_cvars.crosshair != 0 {
-            glyph_cmds.push(GlyphRendererCommand::Glyph {
-                glyph_id: '+' as u8,
-                position: ScreenPosition::Absolute(Anchor::CENTER),
+        if self.mission_pack == MissionPack::Hipnotic {
+            for (i, key) in [ItemFlags::KEY_1, ItemFlags::KEY_2].into_iter().enumerate() {
+                if items.contains(key) {
+                    let id = ItemId::from_usize(i).unwrap();
+                    self.cmd_sbar_pic(Item { id }, 209, 3 + 9 * i as i32, scale, quad_cmds);
+                }
+            }
+        }
+    }
+
+    // Draw the level's stats over the status bar the way the original single player scoreboard
+    // does.
+    fn cmd_level_stats<'a>(
+        &'a self,
+        level_time: Duration,
+        stats: &[i32],
+        scale: f32,
+        locale: &Locale,
+        quad_cmds: &mut QuadCommands<'_, 'a>,
+        glyph_cmds: &mut GlyphCommands<'_>,
+    ) {
+        self.cmd_sbar_quad(HudTextureId::ScoreBar, 0, 0, scale, quad_cmds);
+
+        // `x` and `y` are relative to the top-left corner of the status bar, with `y` pointing
+        // down
+        let mut text = |text: String, x: i32, y: i32| {
+            glyph_cmds.push(GlyphRendererCommand::Text {
+                text: glyph_cmds.bump().alloc_str(&text),
+                position: ScreenPosition::Relative {
+                    anchor: Anchor::BOTTOM_CENTER,
+                    x_ofs: OVERLAY_X_OFS + x,
+                    y_ofs: SBAR_HEIGHT - y,
+                },
                 anchor: Anchor::TOP_LEFT,
                 scale,
             });
+        };
+
+        let stat = |stat: ClientStat| stats[stat as usize];
+        text(
+            format!(
+                "{}{:3} /{:3}",
+                locale.get("Monsters:"),
+                stat(ClientStat::KilledMonsters),
+                stat(ClientStat::TotalMonsters)
+            ),
+            8,
+            4,
+        );
+        text(
+            format!(
+                "{}{:3} /{:3}",
+                locale.get("Secrets :"),
+                stat(ClientStat::FoundSecrets),
+                stat(ClientStat::TotalSecrets)
+            ),
+            8,
+            12,
+        );
+
+        let minutes = level_time.num_minutes();
+        let seconds = level_time.num_seconds() - 60 * minutes;
+        text(
+            format!("{}{:3}:{:02}", locale.get("Time :"), minutes, seconds),
+            184,
+            4,
+        );
+    }
+
+    // Draw the crosshair in the middle of the screen.
+    //
+    // Style 1 is the `+` from the console font, and the styles after it are built out of solid
+    // quads in the `crosshaircolor` palette entry.
+    fn cmd_crosshair<'a>(
+        &'a self,
+        scale: f32,
+        hud_cvars: &HudVars,
+        quad_cmds: &mut QuadCommands<'_, 'a>,
+        glyph_cmds: &mut GlyphCommands<'_>,
+    ) {
+        let scale = scale * hud_cvars.crosshair_size.clamp(0.25, 8.0);
+
+        let rects = match hud_cvars.crosshair {
+            0 => return,
+            1 => {
+                glyph_cmds.push(GlyphRendererCommand::Glyph {
+                    glyph_id: b'+',
+                    position: ScreenPosition::Absolute(Anchor::CENTER),
+                    anchor: Anchor::CENTER,
+                    scale,
+                });
+                return;
+            }
+            2 => CROSSHAIR_CROSS,
+            3 => CROSSHAIR_GAP_CROSS,
+            4 => CROSSHAIR_DOT,
+            _ => CROSSHAIR_GAP_CROSS_DOT,
+        };
+
+        let texture = &self.solid_colors[hud_cvars.crosshair_color as usize];
+        for &[x, y, width, height] in rects {
+            // round the edges rather than the sizes, so the arms stay symmetrical
+            let x0 = (x * scale).round() as i32;
+            let y0 = (y * scale).round() as i32;
+            let x1 = ((x + width) * scale).round() as i32;
+            let y1 = ((y + height) * scale).round() as i32;
+
+            quad_cmds.push(QuadRendererCommand {
+                texture,
+                layout: Layout {
+                    position: ScreenPosition::Relative {
+                        anchor: Anchor::CENTER,
+                        x_ofs: x0,
+                        y_ofs: y0,
+                    },
+                    anchor: Anchor::BOTTOM_LEFT,
+                    size: Size::Absolute {
+                        width: (x1 - x0).max(1) as u32,
+                        height: (y1 - y0).max(1) as u32,
+                    },
+                },
+            });
         }
     }
 
@@ -598,7 +1151,7 @@ impl HudRenderer {
         x_ofs: i32,
         y_ofs: i32,
         scale: f32,
-        quad_cmds: &mut Vec<QuadRendererCommand<'a>>,
+        quad_cmds: &mut QuadCommands<'_, 'a>,
     ) {
         quad_cmds.push(QuadRendererCommand {
             texture: self.textures.get(&texture_id).unwrap(),
@@ -625,7 +1178,7 @@ impl HudRenderer {
         x_ofs: i32,
         y_ofs: i32,
         scale: f32,
-        quad_cmds: &mut Vec<QuadRendererCommand<'a>>,
+        quad_cmds: &mut QuadCommands<'_, 'a>,
     ) {
         self.cmd_number(
             number,
@@ -647,12 +1200,10 @@ impl HudRenderer {
         completion_duration: Duration,
         stats: &'a [i32],
         scale: f32,
-        quad_cmds: &mut Vec<QuadRendererCommand<'a>>,
+        quad_cmds: &mut QuadCommands<'_, 'a>,
     ) {
         use HudTextureId::*;
 
-        // TODO: check gametype
-
         self.cmd_intermission_quad(Complete, 64, OVERLAY_HEIGHT - 24, scale, quad_cmds);
         self.cmd_intermission_quad(Intermission, 0, OVERLAY_HEIGHT - 56, scale, quad_cmds);
 
@@ -681,6 +1232,183 @@ impl HudRenderer {
         self.cmd_intermission_number(monsters_total, 3, 240, monsters_y_ofs, scale, quad_cmds);
     }
 
+    // Draw the scoreboard the way the original deathmatch overlay does, one row per player with
+    // their shirt and pants colors behind their frags.
+    fn cmd_scoreboard<'a>(
+        &'a self,
+        scoreboard: &Scoreboard<'_>,
+        scale: f32,
+        quad_cmds: &mut QuadCommands<'_, 'a>,
+        glyph_cmds: &mut GlyphCommands<'_>,
+    ) {
+        use HudTextureId::*;
+
+        let ranking_x = (OVERLAY_WIDTH - self.textures[&Ranking].width() as i32) / 2;
+        self.cmd_intermission_quad(Ranking, ranking_x, OVERLAY_HEIGHT - 8, scale, quad_cmds);
+
+        // `x` and `y` are the top-left corner relative to the top-left corner of the overlay,
+        // with `y` pointing down
+        let mut fill = |color: u8, x: i32, y: i32, width: i32, height: i32| {
+            // the size is in pixels, so the offsets aren't scaled for us
+            quad_cmds.push(QuadRendererCommand {
+                texture: &self.solid_colors[color as usize],
+                layout: Layout {
+                    position: ScreenPosition::Relative {
+                        anchor: OVERLAY_ANCHOR,
+                        x_ofs: ((OVERLAY_X_OFS + x) as f32 * scale) as i32,
+                        y_ofs: ((OVERLAY_Y_OFS + OVERLAY_HEIGHT - y - height) as f32 * scale)
+                            as i32,
+                    },
+                    anchor: Anchor::BOTTOM_LEFT,
+                    size: Size::Absolute {
+                        width: (width as f32 * scale) as u32,
+                        height: (height as f32 * scale) as u32,
+                    },
+                },
+            });
+        };
+
+        let mut text = |text: &str, x: i32, y: i32| {
+            glyph_cmds.push(GlyphRendererCommand::Text {
+                text: glyph_cmds.bump().alloc_str(text),
+                position: ScreenPosition::Relative {
+                    anchor: OVERLAY_ANCHOR,
+                    x_ofs: OVERLAY_X_OFS + x,
+                    y_ofs: OVERLAY_Y_OFS + OVERLAY_HEIGHT - y,
+                },
+                anchor: Anchor::TOP_LEFT,
+                scale,
+            });
+        };
+
+        for (row, (id, info)) in scoreboard.players.iter().enumerate() {
+            let y = 40 + 10 * row as i32;
+            fill(player_fill_color(info.colors.top()), 80, y, 40, 4);
+            fill(player_fill_color(info.colors.bottom()), 80, y + 4, 40, 4);
+            text(&format!("{:3}", info.frags), 88, y);
+            if *id == scoreboard.local_player {
+                // the brackets from the console font
+                text("\x10", 80, y);
+                text("\x11", 112, y);
+            }
+
+            let name: String = info.name.iter().map(|&b| char::from(b)).collect();
+            text(&name, 144, y);
+        }
+    }
+
+    // Draw the speedrun timer in the top-right corner, with the most recent splits underneath.
+    //
+    // Times behind the personal best are drawn in the alternate character set.
+    fn cmd_run_timer(&self, timer: &RunTimer, scale: f32, glyph_cmds: &mut GlyphCommands<'_>) {
+        const MAX_SPLITS: usize = 5;
+
+        if !timer.is_active() {
+            return;
+        }
+
+        let mut line = |row: usize, text: &str| {
+            glyph_cmds.push(GlyphRendererCommand::Text {
+                text: glyph_cmds.bump().alloc_str(text),
+                position: ScreenPosition::Relative {
+                    anchor: Anchor::TOP_RIGHT,
+                    x_ofs: -4,
+                    y_ofs: -4 - (row * GLYPH_HEIGHT) as i32,
+                },
+                anchor: Anchor::TOP_RIGHT,
+                scale,
+            });
+        };
+
+        let total = match timer.running_delta() {
+            Some(delta) => format!(
+                "{} {}",
+                runtimer::format_time(timer.elapsed()),
+                alt_text(&runtimer::format_delta(delta)),
+            ),
+            None => runtimer::format_time(timer.elapsed()),
+        };
+        line(0, &total);
+
+        let splits = timer.splits();
+        let first = splits.len().saturating_sub(MAX_SPLITS);
+        for (row, (id, split)) in splits.iter().enumerate().skip(first).enumerate() {
+            let delta = match timer.split_delta(id) {
+                Some(delta) if delta > Duration::zero() => alt_text(&runtimer::format_delta(delta)),
+                Some(delta) => runtimer::format_delta(delta),
+                None => String::new(),
+            };
+            line(
+                row + 1,
+                &format!(
+                    "{:<8} {:>9} {:>9}",
+                    split.map,
+                    runtimer::format_time(split.time),
+                    delta
+                ),
+            );
+        }
+    }
+
+    // Draw how far ahead of or behind the ghost the player is, at the top of the screen.
+    fn cmd_ghost_delta(&self, ghost: &GhostView, scale: f32, glyph_cmds: &mut GlyphCommands<'_>) {
+        let Some(delta) = ghost.delta else {
+            return;
+        };
+
+        let text = match runtimer::format_delta(delta) {
+            text if delta > Duration::zero() => alt_text(&text),
+            text => text,
+        };
+        glyph_cmds.push(GlyphRendererCommand::Text {
+            text: glyph_cmds.bump().alloc_str(&text),
+            position: ScreenPosition::Relative {
+                anchor: Anchor::TOP_CENTER,
+                x_ofs: 0,
+                y_ofs: -4,
+            },
+            anchor: Anchor::TOP_CENTER,
+            scale,
+        });
+    }
+
+    // Draw the server's last few messages in the top-left corner, and the center print a little
+    // above the middle of the screen, one centered row per line.
+    fn cmd_notify(&self, notify: &NotifyView, scale: f32, glyph_cmds: &mut GlyphCommands<'_>) {
+        for (row, (line, alpha)) in notify.lines.iter().enumerate() {
+            glyph_cmds.push(GlyphRendererCommand::FadedText {
+                text: glyph_cmds.bump().alloc_str(line),
+                position: ScreenPosition::Relative {
+                    anchor: Anchor::TOP_LEFT,
+                    x_ofs: 4,
+                    y_ofs: -4 - (row * GLYPH_HEIGHT) as i32,
+                },
+                anchor: Anchor::TOP_LEFT,
+                scale,
+                alpha: *alpha,
+            });
+        }
+
+        // the original puts the center print 35% of the way down the screen
+        let center = Anchor {
+            x: AnchorCoord::Center,
+            y: AnchorCoord::Proportion(0.65),
+        };
+        for (row, line) in notify.center_print.iter().enumerate() {
+            glyph_cmds.push(GlyphRendererCommand::FadedText {
+                text: glyph_cmds.bump().alloc_str(line),
+                position: ScreenPosition::Relative {
+                    anchor: center,
+                    x_ofs: 0,
+                    y_ofs: -((row * GLYPH_HEIGHT) as i32),
+                },
+                anchor: Anchor::TOP_CENTER,
+                scale,
+                alpha: notify.center_alpha,
+            });
+        }
+    }
+
     /// Generate render commands to draw the HUD in the specified state.
     // TODO: Should we keep the cvar registry solely on the main thread?
     pub fn generate_commands<'state, 'a>(
@@ -688,11 +1416,26 @@ impl HudRenderer {
         hud_state: &HudState<'a>,
         time: Duration,
         hud_cvars: &HudVars,
-        quad_cmds: &mut Vec<QuadRendererCommand<'a>>,
-        glyph_cmds: &mut Vec<GlyphRendererCommand>,
+        run_timer: Option<&RunTimer>,
+        ghost: Option<&GhostView>,
+        notify: Option<&NotifyView>,
+        locale: &Locale,
+        quad_cmds: &mut QuadCommands<'_, 'a>,
+        glyph_cmds: &mut GlyphCommands<'_>,
     ) {
-        // TODO: get from cvar
-        let scale = 2.0;
+        let scale = hud_cvars.scale();
+
+        if let Some(notify) = notify {
+            self.cmd_notify(notify, scale, glyph_cmds);
+        }
+
+        if let Some(timer) = run_timer.filter(|_| hud_cvars.run_timer != 0) {
+            self.cmd_run_timer(timer, scale, glyph_cmds);
+        }
+
+        if let Some(ghost) = ghost {
+            self.cmd_ghost_delta(ghost, scale, glyph_cmds);
+        }
 
         match hud_state {
             HudState::InGame {
@@ -700,6 +1443,8 @@ impl HudRenderer {
                 item_pickup_time,
                 stats,
                 face_anim_time,
+                level_time,
+                scoreboard,
             } => {
                 self.cmd_sbar(
                     time,
@@ -712,14 +1457,59 @@ impl HudRenderer {
                     quad_cmds,
                     glyph_cmds,
                 );
+                self.cmd_crosshair(scale, hud_cvars, quad_cmds, glyph_cmds);
+
+                if let Some(level_time) = level_time {
+                    self.cmd_level_stats(*level_time, stats, scale, locale, quad_cmds, glyph_cmds);
+                }
+
+                if let Some(scoreboard) = scoreboard {
+                    self.cmd_scoreboard(scoreboard, scale, quad_cmds, glyph_cmds);
+                }
             }
             HudState::Intermission {
                 kind,
                 completion_duration,
                 stats,
-            } => {
-                self.cmd_intermission_overlay(kind, *completion_duration, stats, scale, quad_cmds);
-            }
+                scoreboard,
+            } => match scoreboard {
+                Some(scoreboard) => self.cmd_scoreboard(scoreboard, scale, quad_cmds, glyph_cmds),
+                None => self.cmd_intermission_overlay(
+                    kind,
+                    *completion_duration,
+                    stats,
+                    scale,
+                    quad_cmds,
+                ),
+            },
         };
     }
 }
+
+/// The palette index used to draw a player's shirt or pants color, from the middle of its row.
+fn player_fill_color(color: u8) -> u8 {
+    (color & 0x0F) * 16 + 8
+}
+
+/// The frame of a weapon's icon, which flashes for a second after it's picked up.
+fn weapon_frame(time: Duration, pickup_time: Duration, active: bool) -> WeaponFrame {
+    let delta = time - pickup_time;
+    if delta >= Duration::try_seconds(1).unwrap() {
+        if active {
+            WeaponFrame::Active
+        } else {
+            WeaponFrame::Inactive
+        }
+    } else {
+        WeaponFrame::Pickup {
+            frame: (delta.num_milliseconds() / 100) as usize % 5,
+        }
+    }
+}
+
+/// Shift text into the alternate (red/gold) half of the character set.
+fn alt_text(text: &str) -> String {
+    text.chars()
+        .map(|c| char::from((c as u32 as u8) | 0x80))
+        .collect()
+}
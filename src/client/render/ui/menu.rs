@@ -1,11 +1,19 @@
 use crate::{
     client::{
-        menu::{Item, Menu, MenuBodyView, MenuState, NamedMenuItem},
+        locale::Locale,
+        menu::{
+            layout::{
+                dynamic_item_top, first_visible_item, BODY_TOP, MAX_VISIBLE_ITEMS, MENU_HEIGHT,
+                MENU_SCALE, MENU_WIDTH, PREDEF_ITEM_HEIGHT, PREDEF_LEFT, SLIDER_WIDTH, VALUE_X,
+            },
+            Item, Menu, MenuBodyView, MenuState, NamedMenuItem,
+        },
         render::{
             ui::{
-                glyph::{GlyphRendererCommand, GLYPH_HEIGHT, GLYPH_WIDTH},
+                glyph::{GlyphRendererCommand, GLYPH_WIDTH},
                 layout::{Anchor, Layout, ScreenPosition, Size},
                 quad::{QuadRendererCommand, QuadTexture},
+                GlyphCommands, QuadCommands,
             },
             GraphicsState,
         },
@@ -20,15 +28,22 @@ use bevy::{
 use chrono::Duration;
 use hashbrown::HashMap;
 
-// original minimum Quake resolution
-const MENU_WIDTH: i32 = 320;
-const MENU_HEIGHT: i32 = 200;
-
 const SLIDER_LEFT: u8 = 128;
 const SLIDER_MIDDLE: u8 = 129;
 const SLIDER_RIGHT: u8 = 130;
 const SLIDER_HANDLE: u8 = 131;
-const SLIDER_WIDTH: i32 = 10;
+
+const TEXT_CURSOR: u8 = 11;
+
+/// The frame of the spinning cursor beside predefined menus at `time`.
+pub fn cursor_frame(time: Duration) -> i64 {
+    (time.num_milliseconds() / 100) % 6
+}
+
+/// Whether the blinking cursor of dynamic menus is shown at `time`.
+pub fn cursor_blink(time: Duration) -> bool {
+    time.num_milliseconds() / 250 % 2 == 0
+}
 
 #[derive(Clone, Copy, Debug)]
 enum Align {
@@ -52,8 +67,14 @@ impl Align {
     }
 }
 
+const PREVIEW_BOX: &str = "gfx/bigbox.lmp";
+const PREVIEW_PLAYER: &str = "gfx/menuplyr.lmp";
+
 pub struct MenuRenderer {
     textures: HashMap<String, QuadTexture>,
+
+    /// The player picture in the colors chosen in the active menu, if it shows one.
+    player_preview: Option<QuadTexture>,
 }
 
 impl MenuRenderer {
@@ -77,6 +98,10 @@ impl MenuRenderer {
                 tex_names.insert(path.to_string());
             }
 
+            if m.player_preview().is_some() {
+                tex_names.insert(PREVIEW_BOX.to_string());
+            }
+
             for item in m.items() {
                 if let Item::Submenu(ref sub) = item.item() {
                     menus.push(sub);
@@ -84,7 +109,43 @@ impl MenuRenderer {
             }
         }
 
+        // the renderer is rebuilt whenever the menu changes, so this follows the chosen colors
+        let player_preview = menu
+            .active_submenu()
+            .ok()
+            .and_then(Menu::player_preview)
+            .and_then(|color| {
+                // without the picture the menu still works, it just has no preview
+                let qpic = match vfs.open(PREVIEW_PLAYER) {
+                    Ok(file) => QPic::load(file).map_err(|e| e.to_string()),
+                    Err(e) => Err(e.to_string()),
+                };
+                let qpic = match qpic {
+                    Ok(qpic) => qpic,
+                    Err(e) => {
+                        warn!("Couldn't load {}: {}", PREVIEW_PLAYER, e);
+                        return None;
+                    }
+                };
+
+                let indices = qpic
+                    .indices()
+                    .iter()
+                    .map(|&i| color.translate(i))
+                    .collect::<Vec<_>>();
+
+                Some(QuadTexture::from_indices(
+                    state,
+                    device,
+                    queue,
+                    qpic.width(),
+                    qpic.height(),
+                    &indices,
+                ))
+            });
+
         MenuRenderer {
+            player_preview,
             textures: tex_names
                 .into_iter()
                 .map(|name| {
@@ -117,7 +178,7 @@ impl MenuRenderer {
         x_ofs: i32,
         y_ofs: i32,
         scale: f32,
-        quad_cmds: &mut Vec<QuadRendererCommand<'state>>,
+        quad_cmds: &mut QuadCommands<'_, 'state>,
     ) {
         quad_cmds.push(QuadRendererCommand {
             texture,
@@ -139,7 +200,7 @@ impl MenuRenderer {
         x_ofs: i32,
         y_ofs: i32,
         scale: f32,
-        glyph_cmds: &mut Vec<GlyphRendererCommand>,
+        glyph_cmds: &mut GlyphCommands<'_>,
     ) {
         glyph_cmds.push(GlyphRendererCommand::Glyph {
             glyph_id,
@@ -153,17 +214,13 @@ impl MenuRenderer {
         });
     }
 
-    fn cmd_draw_plaque<'a>(&'a self, scale: f32, quad_cmds: &mut Vec<QuadRendererCommand<'a>>) {
+    fn cmd_draw_plaque<'a>(&'a self, scale: f32, quad_cmds: &mut QuadCommands<'_, 'a>) {
         let plaque = self.texture("gfx/qplaque.lmp");
         self.cmd_draw_quad(plaque, Align::Left, 16, 4, scale, quad_cmds);
     }
 
-    fn cmd_draw_title<'a, S>(
-        &'a self,
-        name: S,
-        scale: f32,
-        quad_cmds: &mut Vec<QuadRendererCommand<'a>>,
-    ) where
+    fn cmd_draw_title<'a, S>(&'a self, name: S, scale: f32, quad_cmds: &mut QuadCommands<'_, 'a>)
+    where
         S: AsRef<str>,
     {
         let title = self.texture(name.as_ref());
@@ -176,19 +233,26 @@ impl MenuRenderer {
         cursor_pos: usize,
         time: Duration,
         scale: f32,
-        quad_cmds: &mut Vec<QuadRendererCommand<'a>>,
+        quad_cmds: &mut QuadCommands<'_, 'a>,
     ) where
         S: AsRef<str>,
     {
         let predef = self.texture(name.as_ref());
-        self.cmd_draw_quad(predef, Align::Left, 72, -32, scale, quad_cmds);
-        let curs_frame = (time.num_milliseconds() / 100) % 6;
+        self.cmd_draw_quad(
+            predef,
+            Align::Left,
+            PREDEF_LEFT,
+            BODY_TOP - MENU_HEIGHT,
+            scale,
+            quad_cmds,
+        );
+        let curs_frame = cursor_frame(time);
         let curs = self.texture(&format!("gfx/menudot{}.lmp", curs_frame + 1));
         self.cmd_draw_quad(
             curs,
             Align::Left,
-            72 - curs.width() as i32,
-            -32 - cursor_pos as i32 * 20,
+            PREDEF_LEFT - curs.width() as i32,
+            BODY_TOP - MENU_HEIGHT - cursor_pos as i32 * PREDEF_ITEM_HEIGHT,
             scale,
             quad_cmds,
         );
@@ -200,12 +264,12 @@ impl MenuRenderer {
         y: i32,
         name: S,
         scale: f32,
-        glyph_cmds: &mut Vec<GlyphRendererCommand>,
+        glyph_cmds: &mut GlyphCommands<'_>,
     ) where
         S: AsRef<str>,
     {
         glyph_cmds.push(GlyphRendererCommand::Text {
-            text: name.as_ref().to_string(),
+            text: glyph_cmds.bump().alloc_str(name.as_ref()),
             position: ScreenPosition::Relative {
                 anchor: Anchor::CENTER,
                 x_ofs: -MENU_WIDTH / 2 + x - GLYPH_WIDTH as i32,
@@ -222,12 +286,12 @@ impl MenuRenderer {
         y: i32,
         text: S,
         scale: f32,
-        glyph_cmds: &mut Vec<GlyphRendererCommand>,
+        glyph_cmds: &mut GlyphCommands<'_>,
     ) where
         S: AsRef<str>,
     {
         glyph_cmds.push(GlyphRendererCommand::Text {
-            text: text.as_ref().to_string(),
+            text: glyph_cmds.bump().alloc_str(text.as_ref()),
             position: ScreenPosition::Relative {
                 anchor: Anchor::CENTER,
                 x_ofs: -MENU_WIDTH / 2 + x + GLYPH_WIDTH as i32,
@@ -244,7 +308,7 @@ impl MenuRenderer {
         y: i32,
         pos: f32,
         scale: f32,
-        glyph_cmds: &mut Vec<GlyphRendererCommand>,
+        glyph_cmds: &mut GlyphCommands<'_>,
     ) {
         self.cmd_draw_glyph(SLIDER_LEFT, x, y, scale, glyph_cmds);
         for i in 0..SLIDER_WIDTH {
@@ -260,56 +324,136 @@ impl MenuRenderer {
         items: I,
         cursor_pos: usize,
         time: Duration,
+        locale: &Locale,
         scale: f32,
-        glyph_cmds: &mut Vec<GlyphRendererCommand>,
+        glyph_cmds: &mut GlyphCommands<'_>,
     ) {
-        for (item_id, item) in items.enumerate() {
-            let y = MENU_HEIGHT - 32 - (GLYPH_HEIGHT * item_id) as i32;
-            let x = 16 + 24 * GLYPH_WIDTH as i32;
-            self.cmd_draw_item_name(x, y, item.name(), scale, glyph_cmds);
+        let mut cursor_x = 200;
+        let first_visible = first_visible_item(cursor_pos);
+        let blink = cursor_blink(time);
+
+        for (item_id, item) in items
+            .enumerate()
+            .skip(first_visible)
+            .take(MAX_VISIBLE_ITEMS)
+        {
+            let y = dynamic_item_top(item_id - first_visible);
+            let x = VALUE_X;
+
+            // save slots and servers are described by their contents rather than their name
+            let description = match item.item() {
+                Item::SaveSlot(slot) => Some(
+                    slot.description()
+                        .unwrap_or_else(|| locale.get("--- UNUSED SLOT ---"))
+                        .to_owned(),
+                ),
+                Item::Server(server) => Some(format!(
+                    "{:15.15} {:8.8} {:2}/{:<2} {:3}",
+                    server.hostname,
+                    server.map,
+                    server.players,
+                    server.max_players,
+                    server.ping.as_millis().min(999),
+                )),
+                _ => None,
+            };
+
+            if let Some(description) = description {
+                self.cmd_draw_item_text(16 - GLYPH_WIDTH as i32, y, description, scale, glyph_cmds);
+
+                if item_id == cursor_pos {
+                    cursor_x = 0;
+                }
+
+                continue;
+            }
+
+            self.cmd_draw_item_name(x, y, locale.get(item.name()), scale, glyph_cmds);
 
             match item.item() {
                 Item::Toggle(toggle) => self.cmd_draw_item_text(
                     x,
                     y,
-                    if toggle.get() { "yes" } else { "no" },
+                    locale.get(if toggle.get() { "yes" } else { "no" }),
                     scale,
                     glyph_cmds,
                 ),
                 Item::Enum(e) => {
-                    self.cmd_draw_item_text(x, y, e.selected_name(), scale, glyph_cmds)
+                    self.cmd_draw_item_text(x, y, locale.get(e.selected_name()), scale, glyph_cmds)
                 }
                 Item::Slider(slider) => {
                     self.cmd_draw_slider(x, y, slider.position(), scale, glyph_cmds)
                 }
-                Item::TextField(_) => (),
+                Item::KeyBind(bind) => {
+                    let text = if bind.is_capturing() {
+                        locale.get("press a key").to_string()
+                    } else if bind.keys().is_empty() {
+                        "???".to_string()
+                    } else {
+                        bind.keys().join(" or ")
+                    };
+
+                    self.cmd_draw_item_text(x, y, text, scale, glyph_cmds)
+                }
+                Item::TextField(text) => {
+                    self.cmd_draw_item_text(x, y, text.text(), scale, glyph_cmds);
+
+                    if item_id == cursor_pos && blink {
+                        self.cmd_draw_glyph(
+                            TEXT_CURSOR,
+                            x + (GLYPH_WIDTH * (text.cursor() + 1)) as i32,
+                            y,
+                            scale,
+                            glyph_cmds,
+                        );
+                    }
+                }
                 _ => (),
             }
         }
 
-        if time.num_milliseconds() / 250 % 2 == 0 {
+        if blink {
             self.cmd_draw_glyph(
                 141,
-                200,
-                MENU_HEIGHT - 32 - 8 * cursor_pos as i32,
+                cursor_x,
+                dynamic_item_top(cursor_pos - first_visible),
                 scale,
                 glyph_cmds,
             );
         }
     }
 
+    fn cmd_draw_player_preview<'a>(
+        &'a self,
+        item_count: usize,
+        scale: f32,
+        quad_cmds: &mut QuadCommands<'_, 'a>,
+    ) {
+        let Some(player) = &self.player_preview else {
+            return;
+        };
+
+        // centered below the items, leaving a blank line
+        let top = dynamic_item_top(item_count.min(MAX_VISIBLE_ITEMS) + 1) - MENU_HEIGHT;
+        let frame = self.texture(PREVIEW_BOX);
+        let border = (frame.height() as i32 - player.height() as i32) / 2;
+
+        self.cmd_draw_quad(frame, Align::Center, 0, top, scale, quad_cmds);
+        self.cmd_draw_quad(player, Align::Center, 0, top - border, scale, quad_cmds);
+    }
+
     pub fn generate_commands<'a>(
         &'a self,
         menu: &Menu,
         time: Duration,
-        quad_cmds: &mut Vec<QuadRendererCommand<'a>>,
-        glyph_cmds: &mut Vec<GlyphRendererCommand>,
+        locale: &Locale,
+        quad_cmds: &mut QuadCommands<'_, 'a>,
+        glyph_cmds: &mut GlyphCommands<'_>,
     ) {
         let active_menu = menu.active_submenu().unwrap();
         let view = active_menu.view();
 
-        // TODO: use cvar
-        let scale = 2.0;
+        let scale = MENU_SCALE;
 
         if view.draw_plaque() {
             self.cmd_draw_plaque(scale, quad_cmds);
@@ -331,9 +475,11 @@ impl MenuRenderer {
                     active_menu.items(),
                     cursor_pos,
                     time,
+                    locale,
                     scale,
                     glyph_cmds,
                 );
+                self.cmd_draw_player_preview(active_menu.items().count(), scale, quad_cmds);
             }
         }
     }
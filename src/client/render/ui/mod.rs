@@ -1,24 +1,37 @@
+pub mod charset;
 pub mod glyph;
 pub mod hud;
 pub mod layout;
 pub mod menu;
 pub mod quad;
+pub mod touch;
+
+use std::{
+    cell::RefCell,
+    hash::{DefaultHasher, Hash, Hasher},
+};
 
 use crate::{
     client::{
-        input::InputFocus,
+        ghost::GhostView,
+        input::{touch::TouchControls, InputFocus},
+        locale::Locale,
         menu::Menu,
         render::{
+            stats::RenderSpeeds,
             ui::{
-                glyph::{GlyphRenderer, GlyphRendererCommand},
-                hud::{HudRenderer, HudState},
-                menu::MenuRenderer,
-                quad::{QuadRenderer, QuadRendererCommand},
+                glyph::{GlyphRenderer, GlyphRendererCommand, GLYPH_HEIGHT},
+                hud::{HudRenderer, HudState, MissionPack},
+                layout::{Anchor, ScreenPosition},
+                menu::{self, MenuRenderer},
+                quad::{QuadBatch, QuadRenderer, QuadRendererCommand},
+                touch::TouchRenderer,
             },
             Extent2d, GraphicsState,
         },
+        runtimer::RunTimer,
     },
-    common::vfs::Vfs,
+    common::{console::NotifyView, net::GameType, vfs::Vfs},
 };
 
 use bevy::{
@@ -30,13 +43,21 @@ use bevy::{
         view::ViewTarget,
     },
 };
-use cgmath::{Matrix4, Vector2};
+use bumpalo::{collections::Vec as BumpVec, Bump};
+use cgmath::Vector2;
 use chrono::Duration;
+use parking_lot::Mutex;
 
 use self::hud::HudVars;
 
 use super::{RenderResolution, RenderState};
 
+/// Quad commands for a single frame, allocated from the UI pass's bump allocator.
+pub type QuadCommands<'b, 'a> = BumpVec<'b, QuadRendererCommand<'a>>;
+
+/// Glyph commands for a single frame. Any text is allocated from the same bump as the list itself.
+pub type GlyphCommands<'b> = BumpVec<'b, GlyphRendererCommand<'b>>;
+
 pub fn screen_space_vertex_translate(
     display_w: u32,
     display_h: u32,
@@ -62,42 +83,58 @@ pub fn screen_space_vertex_scale(
     )
 }
 
-pub fn screen_space_vertex_transform(
-    display_w: u32,
-    display_h: u32,
-    quad_w: u32,
-    quad_h: u32,
-    pos_x: i32,
-    pos_y: i32,
-) -> Matrix4<f32> {
-    let Vector2 { x: ndc_x, y: ndc_y } =
-        screen_space_vertex_translate(display_w, display_h, pos_x, pos_y);
-
-    let Vector2 {
-        x: scale_x,
-        y: scale_y,
-    } = screen_space_vertex_scale(display_w, display_h, quad_w, quad_h);
-
-    Matrix4::from_translation([ndc_x, ndc_y, 0.0].into())
-        * Matrix4::from_nonuniform_scale(scale_x, scale_y, 1.0)
-}
-
 pub enum UiState<'a> {
     Title {
         overlay: Option<&'a Menu>,
     },
     InGame {
         hud: HudState<'a>,
+        touch: Option<&'a TouchControls>,
         overlay: Option<&'a Menu>,
     },
 }
 
+impl UiState<'_> {
+    /// Hash everything in this state that's drawn at `time`.
+    ///
+    /// Menus are only hashed by their animation, so the menu's contents have to be hashed
+    /// separately.
+    fn hash_at<H: Hasher>(&self, time: Duration, hasher: &mut H) {
+        let (hud, touch, overlay) = match self {
+            UiState::Title { overlay } => (None, None, overlay),
+            UiState::InGame {
+                hud,
+                touch,
+                overlay,
+            } => (Some(hud), touch.as_ref(), overlay),
+        };
+
+        if let Some(hud) = hud {
+            hud.hash_at(time, hasher);
+        }
+        touch.is_some().hash(hasher);
+        overlay
+            .map(|_| (menu::cursor_frame(time), menu::cursor_blink(time)))
+            .hash(hasher);
+    }
+}
+
+/// The UI drawn last frame, which is drawn again as long as its key doesn't change.
+#[derive(Default)]
+struct UiCache {
+    key: Option<u64>,
+    quad_batches: Vec<QuadBatch>,
+    glyph_count: u32,
+}
+
 #[derive(Resource)]
 pub struct UiRenderer {
     menu_renderer: MenuRenderer,
     hud_renderer: HudRenderer,
     glyph_renderer: GlyphRenderer,
     quad_renderer: QuadRenderer,
+    touch_renderer: TouchRenderer,
+    cache: Mutex<UiCache>,
 }
 
 impl UiRenderer {
@@ -107,30 +144,92 @@ impl UiRenderer {
         device: &RenderDevice,
         queue: &RenderQueue,
         menu: &Menu,
+        mission_pack: MissionPack,
     ) -> UiRenderer {
         UiRenderer {
             menu_renderer: MenuRenderer::new(state, vfs, device, queue, menu),
-            hud_renderer: HudRenderer::new(state, vfs, device, queue),
-            glyph_renderer: GlyphRenderer::new(state, device, queue),
+            hud_renderer: HudRenderer::new(state, vfs, device, queue, mission_pack),
+            glyph_renderer: GlyphRenderer::new(state, vfs, device, queue),
             quad_renderer: QuadRenderer::new(state, device),
+            touch_renderer: TouchRenderer::new(state, device, queue),
+            cache: Default::default(),
         }
     }
 
-    pub fn render_pass<'this, 'a>(
+    /// Draw the UI, regenerating it only if `key` differs from the last frame's.
+    ///
+    /// `key` is a hash of everything the UI is drawn from, including `target_size` and the
+    /// animations at `time`.
+    fn render_pass<'this, 'a>(
         &'this self,
         state: &'this GraphicsState,
         queue: &'a RenderQueue,
         pass: &'a mut TrackedRenderPass<'this>,
+        cache: &'this mut UiCache,
+        key: u64,
         target_size: Extent2d,
         time: Duration,
         ui_state: &'a UiState<'this>,
         hud_cvars: &'a HudVars,
-        quad_commands: &'a mut Vec<QuadRendererCommand<'this>>,
-        glyph_commands: &'a mut Vec<GlyphRendererCommand>,
+        run_timer: Option<&'a RunTimer>,
+        ghost: Option<&'a GhostView>,
+        notify: Option<&'a NotifyView>,
+        speeds: Option<&'a RenderSpeeds>,
+        locale: &'a Locale,
+        quad_commands: &'a mut QuadCommands<'_, 'this>,
+        glyph_commands: &'a mut GlyphCommands<'_>,
+    ) {
+        if cache.key != Some(key) {
+            self.generate_commands(
+                target_size,
+                time,
+                ui_state,
+                hud_cvars,
+                run_timer,
+                ghost,
+                notify,
+                speeds,
+                locale,
+                quad_commands,
+                glyph_commands,
+            );
+            cache.quad_batches =
+                self.quad_renderer
+                    .prepare(state, queue, target_size, quad_commands);
+            cache.glyph_count =
+                self.glyph_renderer
+                    .prepare(state, queue, target_size, glyph_commands);
+            cache.key = Some(key);
+        }
+
+        let cache: &'this UiCache = cache;
+        self.quad_renderer
+            .record_draw(state, pass, &cache.quad_batches);
+        self.glyph_renderer
+            .record_draw(state, pass, cache.glyph_count);
+    }
+
+    fn generate_commands<'this>(
+        &'this self,
+        target_size: Extent2d,
+        time: Duration,
+        ui_state: &UiState<'this>,
+        hud_cvars: &HudVars,
+        run_timer: Option<&RunTimer>,
+        ghost: Option<&GhostView>,
+        notify: Option<&NotifyView>,
+        speeds: Option<&RenderSpeeds>,
+        locale: &Locale,
+        quad_commands: &mut QuadCommands<'_, 'this>,
+        glyph_commands: &mut GlyphCommands<'_>,
     ) {
-        let (hud_state, overlay) = match ui_state {
-            UiState::Title { overlay } => (None, overlay.as_ref()),
-            UiState::InGame { hud, overlay } => (Some(hud), overlay.as_ref()),
+        let (hud_state, touch, overlay) = match ui_state {
+            UiState::Title { overlay } => (None, None, overlay.as_ref()),
+            UiState::InGame {
+                hud,
+                touch,
+                overlay,
+            } => (Some(hud), touch.as_ref(), overlay.as_ref()),
         };
 
         if let Some(hstate) = hud_state {
@@ -138,24 +237,83 @@ impl UiRenderer {
                 hstate,
                 time,
                 hud_cvars,
+                run_timer,
+                ghost,
+                notify,
+                locale,
+                quad_commands,
+                glyph_commands,
+            );
+        }
+
+        if let Some(touch) = touch {
+            self.touch_renderer.generate_commands(
+                touch,
+                target_size.width,
+                target_size.height,
                 quad_commands,
                 glyph_commands,
             );
         }
 
+        if let Some(speeds) = speeds {
+            cmd_speeds(hud_cvars, speeds, glyph_commands);
+        }
+
         if let Some(menu) = overlay {
             self.menu_renderer
-                .generate_commands(menu, time, quad_commands, glyph_commands);
+                .generate_commands(menu, time, locale, quad_commands, glyph_commands);
         }
+    }
+}
 
-        self.quad_renderer
-            .update_uniforms(state, queue, target_size, quad_commands);
-        self.quad_renderer.record_draw(state, pass, quad_commands);
-        self.glyph_renderer
-            .record_draw(state, queue, pass, target_size, glyph_commands);
+/// Draw the `r_showfps` and `r_speeds` overlay in the bottom-right corner, from last frame's
+/// counters.
+fn cmd_speeds(hud_cvars: &HudVars, speeds: &RenderSpeeds, glyph_cmds: &mut GlyphCommands<'_>) {
+    let mut lines = Vec::new();
+
+    if hud_cvars.show_fps != 0 {
+        lines.push(format!(
+            "{:.0} fps {:6.2} ms",
+            speeds.fps(),
+            speeds.frame_time().as_secs_f64() * 1000.0
+        ));
+    }
+
+    if hud_cvars.speeds != 0 {
+        let counts = speeds.counts();
+        lines.push(format!("{:6} draw calls", counts.draw_calls));
+        lines.push(format!("{:6} brush polys", counts.brush_polys));
+        lines.push(format!("{:6} alias polys", counts.alias_polys));
+        lines.push(format!("{:6} particles", counts.particles));
+        lines.push(format!(
+            "{:6} KB uploaded",
+            counts.upload_bytes.div_ceil(1024)
+        ));
+    }
+
+    // the first line is at the top, so count rows up from the last
+    for (row, line) in lines.iter().rev().enumerate() {
+        glyph_cmds.push(GlyphRendererCommand::Text {
+            text: glyph_cmds.bump().alloc_str(line),
+            position: ScreenPosition::Relative {
+                anchor: Anchor::BOTTOM_RIGHT,
+                x_ofs: -4,
+                y_ofs: 4 + (row * GLYPH_HEIGHT) as i32,
+            },
+            anchor: Anchor::BOTTOM_RIGHT,
+            scale: hud_cvars.scale(),
+        });
     }
 }
 
+/// The tick resource `R` last changed on, if it exists.
+fn changed_tick<R: Resource>(world: &World) -> Option<u32> {
+    world
+        .get_resource_ref::<R>()
+        .map(|res| res.last_changed().get())
+}
+
 #[derive(Debug, Hash, PartialEq, Eq, Clone, RenderLabel)]
 pub struct UiPassLabel;
 
@@ -169,91 +327,164 @@ impl ViewNode for UiPass {
         &self,
         _graph: &mut bevy::render::render_graph::RenderGraphContext,
         render_context: &mut bevy::render::renderer::RenderContext<'w>,
-        (view_target, _): (&ViewTarget, &Camera3d),
+        (view_target, _): (&'w ViewTarget, &'w Camera3d),
         world: &'w World,
     ) -> Result<(), bevy::render::render_graph::NodeRunError> {
-        let gfx_state = world.resource::<GraphicsState>();
-        let ui_renderer = world.resource::<UiRenderer>();
+        profile_span!("ui_pass");
+        let (Some(gfx_state), Some(ui_renderer)) = (
+            world.get_resource::<GraphicsState>(),
+            world.get_resource::<UiRenderer>(),
+        ) else {
+            return Ok(());
+        };
         let hud_cvars = world.resource::<HudVars>();
+        let run_timer = world.get_resource::<RunTimer>();
+        let ghost = world.get_resource::<GhostView>();
+        let notify = world.get_resource::<NotifyView>();
+        let speeds = world.get_resource::<RenderSpeeds>();
+        let locale = world.resource::<Locale>();
         let conn = world.get_resource::<RenderState>();
         let queue = world.resource::<RenderQueue>();
-        let device = world.resource::<RenderDevice>();
         let Some(&RenderResolution(width, height)) = world.get_resource::<RenderResolution>()
         else {
             return Ok(());
         };
         let menu = world.get_resource::<Menu>();
         let focus = world.resource::<InputFocus>();
+        let touch = world.get_resource::<TouchControls>();
 
-        let mut quad_commands = Vec::new();
-        let mut glyph_commands = Vec::new();
+        // the menu and everything else extracted from the main world are hashed by when they
+        // last changed, which is only when the main world changed them
+        let mut revisions = DefaultHasher::new();
+        changed_tick::<Menu>(world).hash(&mut revisions);
+        changed_tick::<TouchControls>(world).hash(&mut revisions);
+        changed_tick::<HudVars>(world).hash(&mut revisions);
+        changed_tick::<RunTimer>(world).hash(&mut revisions);
+        changed_tick::<GhostView>(world).hash(&mut revisions);
+        changed_tick::<NotifyView>(world).hash(&mut revisions);
+        changed_tick::<Locale>(world).hash(&mut revisions);
+        if hud_cvars.show_fps != 0 || hud_cvars.speeds != 0 {
+            changed_tick::<RenderSpeeds>(world).hash(&mut revisions);
+        }
 
-        let encoder = render_context.command_encoder();
         let diffuse_target = view_target.get_unsampled_color_attachment();
 
-        {
-            let final_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Ui pass"),
-                color_attachments: &[Some(diffuse_target)],
-                depth_stencil_attachment: None,
-                ..default()
+        render_context.add_command_buffer_generation_task(move |device| {
+            profile_span!("ui_pass_encode");
+            thread_local! {
+                static BUMP: RefCell<Bump> = Bump::new().into();
+            }
+
+            // the bump keeps its largest chunk across resets, so after the first few frames
+            // generating commands doesn't touch the heap at all
+            BUMP.with_borrow_mut(|bump| bump.reset());
+
+            let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Ui pass encoder"),
             });
 
-            let mut final_pass = TrackedRenderPass::new(device, final_pass);
-
-            if let Some(RenderState { .. }) = conn {
-                let ui_state = match conn {
-                    Some(RenderState {
-                        state: cl_state, ..
-                    }) => UiState::InGame {
-                        hud: match cl_state.intermission() {
-                            Some(kind) => HudState::Intermission {
-                                kind,
-                                completion_duration: cl_state.completion_time().unwrap()
-                                    - cl_state.start_time(),
-                                stats: cl_state.stats(),
-                            },
+            BUMP.with_borrow(|bump| {
+                // the pass draws from the cache, so it's locked for as long as the pass
+                let mut cache = ui_renderer.cache.lock();
+
+                let final_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Ui pass"),
+                    color_attachments: &[Some(diffuse_target)],
+                    depth_stencil_attachment: None,
+                    ..default()
+                });
 
-                            None => HudState::InGame {
-                                items: cl_state.items(),
-                                item_pickup_time: cl_state.item_pickup_times(),
-                                stats: cl_state.stats(),
-                                face_anim_time: cl_state.face_anim_time(),
+                let mut final_pass = TrackedRenderPass::new(&device, final_pass);
+
+                let mut quad_commands = BumpVec::new_in(bump);
+                let mut glyph_commands = BumpVec::new_in(bump);
+
+                if let Some(RenderState { .. }) = conn {
+                    let ui_state = match conn {
+                        // only the menu is drawn over a photo
+                        Some(cl_state) if cl_state.photo_mode() => UiState::Title {
+                            overlay: match (focus, menu) {
+                                (InputFocus::Menu, menu) => menu,
+                                _ => None,
                             },
                         },
 
-                        overlay: match (focus, menu) {
-                            (InputFocus::Game, _) => None,
-                            (InputFocus::Menu, menu) => menu,
-                            _ => None,
+                        Some(cl_state) => UiState::InGame {
+                            hud: match cl_state.intermission() {
+                                Some(kind) => HudState::Intermission {
+                                    kind,
+                                    completion_duration: cl_state.completion_time().unwrap()
+                                        - cl_state.start_time(),
+                                    stats: cl_state.stats(),
+                                    scoreboard: (cl_state.game_type() == GameType::Deathmatch)
+                                        .then(|| cl_state.scoreboard()),
+                                },
+
+                                None => HudState::InGame {
+                                    items: cl_state.items(),
+                                    item_pickup_time: cl_state.item_pickup_times(),
+                                    stats: cl_state.stats(),
+                                    face_anim_time: cl_state.face_anim_time(),
+                                    level_time: (hud_cvars.show_scores
+                                        && cl_state.max_players() == 1)
+                                        .then(|| cl_state.time() - cl_state.start_time()),
+                                    scoreboard: (hud_cvars.show_scores
+                                        && cl_state.max_players() > 1)
+                                        .then(|| cl_state.scoreboard()),
+                                },
+                            },
+
+                            touch: match focus {
+                                InputFocus::Game => touch,
+                                _ => None,
+                            },
+
+                            overlay: match (focus, menu) {
+                                (InputFocus::Game, _) => None,
+                                (InputFocus::Menu, menu) => menu,
+                                _ => None,
+                            },
                         },
-                    },
 
-                    None => UiState::Title {
-                        overlay: match (focus, menu) {
-                            (InputFocus::Menu, menu) => menu,
-                            (InputFocus::Game, _) => unreachable!(),
-                            _ => return Ok(()),
+                        None => UiState::Title {
+                            overlay: match (focus, menu) {
+                                (InputFocus::Menu, menu) => menu,
+                                (InputFocus::Game, _) => unreachable!(),
+                                _ => return,
+                            },
                         },
-                    },
-                };
-
-                let elapsed = conn.as_ref().map(|c| c.state.time).unwrap_or_default();
-                ui_renderer.render_pass(
-                    &*gfx_state,
-                    queue,
-                    &mut final_pass,
-                    Extent2d { width, height },
+                    };
+
                     // use client time when in game, renderer time otherwise
-                    elapsed,
-                    &ui_state,
-                    hud_cvars,
-                    &mut quad_commands,
-                    &mut glyph_commands,
-                );
-            }
+                    let elapsed = conn.as_ref().map(|c| c.time()).unwrap_or_default();
+                    let mut key = revisions.clone();
+                    (width, height).hash(&mut key);
+                    ui_state.hash_at(elapsed, &mut key);
 
-            Ok(())
-        }
+                    ui_renderer.render_pass(
+                        &*gfx_state,
+                        queue,
+                        &mut final_pass,
+                        &mut cache,
+                        key.finish(),
+                        Extent2d { width, height },
+                        elapsed,
+                        &ui_state,
+                        hud_cvars,
+                        run_timer,
+                        ghost,
+                        notify,
+                        speeds,
+                        locale,
+                        &mut quad_commands,
+                        &mut glyph_commands,
+                    );
+                }
+            });
+
+            encoder.finish()
+        });
+
+        Ok(())
     }
 }
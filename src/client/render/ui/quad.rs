@@ -1,17 +1,13 @@
-use std::{
-    mem::size_of,
-    num::NonZeroU64,
-    ops::{Deref, DerefMut},
-};
+use std::{mem::size_of, ptr};
 
 use crate::{
     client::render::{
+        stats::{self, Counter},
         ui::{
             layout::{Layout, Size},
-            screen_space_vertex_transform,
+            screen_space_vertex_scale, screen_space_vertex_translate,
         },
-        uniform::{self, DynamicUniformBuffer, DynamicUniformBufferBlock},
-        Extent2d, GraphicsState, Pipeline, TextureData,
+        Extent2d, GraphicsState, Pipeline, ShaderCompiler, TextureData,
     },
     common::{util::any_slice_as_bytes, wad::QPic},
 };
@@ -24,9 +20,12 @@ use bevy::render::{
     },
     renderer::{RenderDevice, RenderQueue},
 };
-use cgmath::Matrix4;
+use cgmath::Vector2;
 use lazy_static::lazy_static;
-use parking_lot::RwLock;
+use parking_lot::Mutex;
+
+/// The maximum number of quads that can be rendered at once.
+pub const MAX_INSTANCES: usize = 4096;
 
 pub const VERTICES: [QuadVertex; 6] = [
     QuadVertex {
@@ -81,11 +80,10 @@ lazy_static! {
             shader_location: 1,
         },
     ];
-}
-
-struct UniformBuffer {
-    buffer: DynamicUniformBuffer<QuadUniforms>,
-    blocks: Vec<DynamicUniformBufferBlock<QuadUniforms>>,
+    static ref INSTANCE_BUFFER_ATTRIBUTES: Vec<wgpu::VertexAttribute> = wgpu::vertex_attr_array![
+        2 => Float32x2, // a_instance_position
+        3 => Float32x2 // a_instance_scale
+    ].to_vec();
 }
 
 pub struct QuadPipeline {
@@ -93,13 +91,15 @@ pub struct QuadPipeline {
     bind_group_layouts: Vec<BindGroupLayout>,
     format: wgpu::TextureFormat,
     vertex_buffer: Buffer,
-    uniform_buffer: RwLock<UniformBuffer>,
+    instance_buffer: Buffer,
+    // the contents of `instance_buffer`
+    uploaded: Mutex<Vec<QuadInstance>>,
 }
 
 impl QuadPipeline {
     pub fn new(
         device: &RenderDevice,
-        compiler: &mut shaderc::Compiler,
+        compiler: &mut ShaderCompiler,
         format: wgpu::TextureFormat,
         sample_count: u32,
     ) -> QuadPipeline {
@@ -112,18 +112,20 @@ impl QuadPipeline {
             usage: wgpu::BufferUsages::VERTEX,
         });
 
-        let uniform_buffer = DynamicUniformBuffer::new(device);
+        let instance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("quad instance buffer"),
+            size: (MAX_INSTANCES * size_of::<QuadInstance>()) as u64,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
 
         QuadPipeline {
             pipeline,
             bind_group_layouts,
             format,
             vertex_buffer,
-            uniform_buffer: UniformBuffer {
-                buffer: uniform_buffer,
-                blocks: vec![],
-            }
-            .into(),
+            instance_buffer,
+            uploaded: Default::default(),
         }
     }
 
@@ -134,7 +136,7 @@ impl QuadPipeline {
     pub fn rebuild(
         &mut self,
         device: &RenderDevice,
-        compiler: &mut shaderc::Compiler,
+        compiler: &mut ShaderCompiler,
         sample_count: u32,
     ) {
         let layout_refs = self.bind_group_layouts.iter();
@@ -153,12 +155,32 @@ impl QuadPipeline {
         &self.vertex_buffer
     }
 
-    fn uniform_buffer(&self) -> impl Deref<Target = UniformBuffer> + '_ {
-        self.uniform_buffer.read()
+    pub fn instance_buffer(&self) -> &Buffer {
+        &self.instance_buffer
     }
 
-    fn uniform_buffer_mut(&self) -> impl DerefMut<Target = UniformBuffer> + '_ {
-        self.uniform_buffer.write()
+    /// Upload `instances` to the instance buffer, unless it already holds them.
+    ///
+    /// The UI rarely changes from one frame to the next, so most frames skip the upload.
+    pub fn write_instances(&self, queue: &RenderQueue, instances: Vec<QuadInstance>) {
+        let mut uploaded = self.uploaded.lock();
+        if *uploaded != instances {
+            queue.write_buffer(&self.instance_buffer, 0, unsafe {
+                any_slice_as_bytes(&instances)
+            });
+            *uploaded = instances;
+        }
+    }
+
+    /// The layout of the vertex buffer alone, for pipelines which draw a single quad without
+    /// any instance data.
+    // NOTE: if the vertex format is changed, this descriptor must also be changed accordingly.
+    pub fn vertex_layout() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: size_of::<QuadVertex>() as u64,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &VERTEX_BUFFER_ATTRIBUTES[..],
+        }
     }
 }
 
@@ -185,20 +207,6 @@ const BIND_GROUP_LAYOUT_ENTRIES: &[&[wgpu::BindGroupLayoutEntry]] = &[
             count: None,
         },
     ],
-    &[
-        // transform matrix
-        // TODO: move to push constants once they're exposed in wgpu
-        wgpu::BindGroupLayoutEntry {
-            binding: 0,
-            visibility: wgpu::ShaderStages::all(),
-            ty: wgpu::BindingType::Buffer {
-                ty: wgpu::BufferBindingType::Uniform,
-                has_dynamic_offset: true,
-                min_binding_size: NonZeroU64::new(size_of::<QuadUniforms>() as u64),
-            },
-            count: None,
-        },
-    ],
 ];
 
 impl Pipeline for QuadPipeline {
@@ -218,8 +226,6 @@ impl Pipeline for QuadPipeline {
             BIND_GROUP_LAYOUT_ENTRIES[0].to_owned(),
             // group 1: per-texture
             BIND_GROUP_LAYOUT_ENTRIES[1].to_owned(),
-            // group 2: per-quad
-            BIND_GROUP_LAYOUT_ENTRIES[2].to_owned(),
         ]
     }
 
@@ -255,20 +261,23 @@ impl Pipeline for QuadPipeline {
         None
     }
 
-    // NOTE: if the vertex format is changed, this descriptor must also be changed accordingly.
     fn vertex_buffer_layouts() -> Vec<wgpu::VertexBufferLayout<'static>> {
-        vec![wgpu::VertexBufferLayout {
-            array_stride: size_of::<QuadVertex>() as u64,
-            step_mode: wgpu::VertexStepMode::Vertex,
-            attributes: &VERTEX_BUFFER_ATTRIBUTES[..],
-        }]
+        vec![
+            QuadPipeline::vertex_layout(),
+            wgpu::VertexBufferLayout {
+                array_stride: size_of::<QuadInstance>() as u64,
+                step_mode: wgpu::VertexStepMode::Instance,
+                attributes: &INSTANCE_BUFFER_ATTRIBUTES[..],
+            },
+        ]
     }
 }
 
-#[repr(C, align(256))]
-#[derive(Clone, Copy, Debug)]
-pub struct QuadUniforms {
-    transform: Matrix4<f32>,
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct QuadInstance {
+    pub position: Vector2<f32>,
+    pub scale: Vector2<f32>,
 }
 
 pub struct QuadTexture {
@@ -288,13 +297,32 @@ impl QuadTexture {
         queue: &RenderQueue,
         qpic: &QPic,
     ) -> QuadTexture {
-        let (diffuse_data, _) = state.palette().translate(qpic.indices());
-        let texture = state.create_texture(
+        QuadTexture::from_indices(
+            state,
             device,
             queue,
-            None,
             qpic.width(),
             qpic.height(),
+            qpic.indices(),
+        )
+    }
+
+    /// Create a texture from raw palette indices, where index `0xFF` is transparent.
+    pub fn from_indices(
+        state: &GraphicsState,
+        device: &RenderDevice,
+        queue: &RenderQueue,
+        width: u32,
+        height: u32,
+        indices: &[u8],
+    ) -> QuadTexture {
+        let (diffuse_data, _) = state.palette().translate(indices);
+        let texture = state.create_texture(
+            device,
+            queue,
+            None,
+            width,
+            height,
             &TextureData::Diffuse(diffuse_data),
         );
         let texture_view = texture.create_view(&Default::default());
@@ -311,8 +339,8 @@ impl QuadTexture {
             texture,
             texture_view,
             bind_group,
-            width: qpic.width(),
-            height: qpic.height(),
+            width,
+            height,
         }
     }
 
@@ -342,9 +370,14 @@ pub struct QuadRendererCommand<'a> {
     pub layout: Layout,
 }
 
+/// A run of quads with the same texture, drawn with a single instanced draw call.
+pub struct QuadBatch {
+    bind_group: BindGroup,
+    instance_count: u32,
+}
+
 pub struct QuadRenderer {
     sampler_bind_group: BindGroup,
-    transform_bind_group: BindGroup,
 }
 
 impl QuadRenderer {
@@ -357,31 +390,16 @@ impl QuadRenderer {
                 resource: wgpu::BindingResource::Sampler(state.nearest_sampler()),
             }],
         );
-        let transform_bind_group = device.create_bind_group(
-            Some("quad transform bind group"),
-            &state.quad_pipeline().bind_group_layouts()[2],
-            &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
-                    buffer: state.quad_pipeline().uniform_buffer().buffer.buffer(),
-                    offset: 0,
-                    size: Some(NonZeroU64::new(size_of::<QuadUniforms>() as u64).unwrap()),
-                }),
-            }],
-        );
 
-        QuadRenderer {
-            sampler_bind_group,
-            transform_bind_group,
-        }
+        QuadRenderer { sampler_bind_group }
     }
 
-    fn generate_uniforms(
+    pub fn generate_instances(
         &self,
         commands: &[QuadRendererCommand<'_>],
         target_size: Extent2d,
-    ) -> Vec<QuadUniforms> {
-        let mut uniforms = Vec::new();
+    ) -> Vec<QuadInstance> {
+        let mut instances = Vec::with_capacity(commands.len());
 
         for cmd in commands {
             let QuadRendererCommand {
@@ -411,56 +429,71 @@ impl QuadRenderer {
             let (quad_width, quad_height) =
                 size.to_wh(texture.width, texture.height, display_width, display_height);
 
-            uniforms.push(QuadUniforms {
-                transform: screen_space_vertex_transform(
+            instances.push(QuadInstance {
+                position: screen_space_vertex_translate(display_width, display_height, x, y),
+                scale: screen_space_vertex_scale(
                     display_width,
                     display_height,
                     quad_width,
                     quad_height,
-                    x,
-                    y,
                 ),
             });
         }
 
-        uniforms
+        instances
     }
 
-    pub fn update_uniforms(
+    /// Upload the instances for `commands`, returning the batches to draw them in.
+    ///
+    /// Each run of consecutive commands with the same texture becomes a single batch.
+    pub fn prepare(
         &self,
         state: &GraphicsState,
         queue: &RenderQueue,
         target_size: Extent2d,
         commands: &[QuadRendererCommand<'_>],
-    ) {
-        // update uniform buffer
-        let uniforms = self.generate_uniforms(commands, target_size);
-        let mut uniform_buffer = state.quad_pipeline().uniform_buffer_mut();
-        let uniform_buffer = &mut *uniform_buffer;
-        uniform::clear_and_rewrite(
-            queue,
-            &mut uniform_buffer.buffer,
-            &mut uniform_buffer.blocks,
-            &uniforms,
-        );
+    ) -> Vec<QuadBatch> {
+        let commands = &commands[..commands.len().min(MAX_INSTANCES)];
+        if commands.is_empty() {
+            return Vec::new();
+        }
+
+        let instances = self.generate_instances(commands, target_size);
+        state.quad_pipeline().write_instances(queue, instances);
+
+        commands
+            .chunk_by(|a, b| ptr::eq(a.texture, b.texture))
+            .map(|batch| QuadBatch {
+                bind_group: batch[0].texture.bind_group.clone(),
+                instance_count: batch.len() as u32,
+            })
+            .collect()
     }
 
-    pub fn record_draw<'this, 'a>(
-        &'this self,
-        state: &'this GraphicsState,
-        pass: &'a mut TrackedRenderPass<'this>,
-        commands: &'a [QuadRendererCommand<'this>],
+    /// Draw the quads uploaded by [`QuadRenderer::prepare`], with one instanced draw call per
+    /// batch.
+    pub fn record_draw<'a>(
+        &'a self,
+        state: &'a GraphicsState,
+        pass: &mut TrackedRenderPass<'a>,
+        batches: &'a [QuadBatch],
     ) {
+        if batches.is_empty() {
+            return;
+        }
+
         pass.set_render_pipeline(state.quad_pipeline().pipeline());
         pass.set_vertex_buffer(0, state.quad_pipeline().vertex_buffer().slice(..));
+        pass.set_vertex_buffer(1, state.quad_pipeline().instance_buffer().slice(..));
         pass.set_bind_group(0, &self.sampler_bind_group, &[]);
-        for (cmd, block) in commands
-            .iter()
-            .zip(state.quad_pipeline().uniform_buffer().blocks.iter())
-        {
-            pass.set_bind_group(1, &cmd.texture.bind_group, &[]);
-            pass.set_bind_group(2, &self.transform_bind_group, &[block.offset()]);
-            pass.draw(0..6, 0..1);
+
+        let mut start = 0;
+        for batch in batches {
+            let end = start + batch.instance_count;
+            pass.set_bind_group(1, &batch.bind_group, &[]);
+            pass.draw(0..6, start..end);
+            stats::add(Counter::DrawCalls, 1);
+            start = end;
         }
     }
 }
@@ -0,0 +1,146 @@
+use crate::client::{
+    input::touch::{TouchControl, TouchControls},
+    render::{
+        ui::{
+            glyph::{GlyphRendererCommand, GLYPH_WIDTH},
+            layout::{Anchor, Layout, ScreenPosition, Size},
+            quad::{QuadRendererCommand, QuadTexture},
+            GlyphCommands, QuadCommands,
+        },
+        GraphicsState,
+    },
+};
+
+use bevy::render::renderer::{RenderDevice, RenderQueue};
+use cgmath::{Vector2, Zero as _};
+
+const CIRCLE_TEXTURE_SIZE: u32 = 64;
+
+// palette indices
+const RING_COLOR: u8 = 8;
+const KNOB_COLOR: u8 = 15;
+
+/// Generate a circle with the given color between `inner` and `outer`, which are proportions of
+/// the texture's radius. Everything else is transparent.
+fn circle_indices(inner: f32, outer: f32, color: u8) -> Vec<u8> {
+    let size = CIRCLE_TEXTURE_SIZE;
+    let center = size as f32 / 2.0;
+
+    (0..size * size)
+        .map(|i| {
+            let x = (i % size) as f32 + 0.5 - center;
+            let y = (i / size) as f32 + 0.5 - center;
+            let dist = (x * x + y * y).sqrt() / center;
+
+            if dist >= inner && dist <= outer {
+                color
+            } else {
+                0xFF
+            }
+        })
+        .collect()
+}
+
+/// Draws the on-screen touch controls.
+pub struct TouchRenderer {
+    ring: QuadTexture,
+    knob: QuadTexture,
+}
+
+impl TouchRenderer {
+    pub fn new(state: &GraphicsState, device: &RenderDevice, queue: &RenderQueue) -> Self {
+        let size = CIRCLE_TEXTURE_SIZE;
+
+        TouchRenderer {
+            ring: QuadTexture::from_indices(
+                state,
+                device,
+                queue,
+                size,
+                size,
+                &circle_indices(0.9, 1.0, RING_COLOR),
+            ),
+            knob: QuadTexture::from_indices(
+                state,
+                device,
+                queue,
+                size,
+                size,
+                &circle_indices(0.0, 1.0, KNOB_COLOR),
+            ),
+        }
+    }
+
+    fn cmd_circle<'a>(
+        texture: &'a QuadTexture,
+        x: i32,
+        y: i32,
+        radius: f32,
+        quad_cmds: &mut QuadCommands<'_, 'a>,
+    ) {
+        let diameter = (radius * 2.0) as u32;
+
+        quad_cmds.push(QuadRendererCommand {
+            texture,
+            layout: Layout {
+                position: ScreenPosition::Absolute(Anchor::absolute_xy(x, y)),
+                anchor: Anchor::CENTER,
+                size: Size::Absolute {
+                    width: diameter,
+                    height: diameter,
+                },
+            },
+        });
+    }
+
+    pub fn generate_commands<'a>(
+        &'a self,
+        controls: &TouchControls,
+        display_width: u32,
+        display_height: u32,
+        quad_cmds: &mut QuadCommands<'_, 'a>,
+        glyph_cmds: &mut GlyphCommands<'_>,
+    ) {
+        if !controls.enabled {
+            return;
+        }
+
+        let scale = 2.0;
+        let min_dim = display_width.min(display_height) as f32;
+
+        for control in TouchControl::ALL {
+            let (center, radius) = control.placement();
+            let radius = radius * min_dim;
+            let x = (center.x * display_width as f32) as i32;
+            let y = (center.y * display_height as f32) as i32;
+            let held = controls.held(control);
+
+            Self::cmd_circle(&self.ring, x, y, radius, quad_cmds);
+
+            if control.is_stick() {
+                let offset = held.map(|h| h.offset * radius).unwrap_or(Vector2::zero());
+                Self::cmd_circle(
+                    &self.knob,
+                    x + offset.x as i32,
+                    y + offset.y as i32,
+                    radius * 0.4,
+                    quad_cmds,
+                );
+            } else if held.is_some() {
+                Self::cmd_circle(&self.knob, x, y, radius * 0.8, quad_cmds);
+            }
+
+            if let Some(label) = control.label() {
+                // don't let the label overflow the button on small screens
+                let max_scale = radius * 1.6 / (label.len() * GLYPH_WIDTH) as f32;
+
+                glyph_cmds.push(GlyphRendererCommand::Text {
+                    text: glyph_cmds.bump().alloc_str(label),
+                    position: ScreenPosition::Absolute(Anchor::absolute_xy(x, y)),
+                    anchor: Anchor::CENTER,
+                    scale: scale.min(max_scale),
+                });
+            }
+        }
+    }
+}
@@ -1,6 +1,6 @@
 use std::{
     marker::PhantomData,
-    mem::{self, align_of, size_of},
+    mem::{align_of, size_of},
     sync::Arc,
 };
 
@@ -13,12 +13,13 @@ use bevy::{
         renderer::{RenderDevice, RenderQueue},
     },
 };
-use failure::{bail, Error};
 
 // minimum limit is 16384:
 // https://www.khronos.org/registry/vulkan/specs/1.2-extensions/html/vkspec.html#limits-maxUniformBufferRange
 // but https://vulkan.gpuinfo.org/displaydevicelimit.php?name=maxUniformBufferRange&platform=windows
 // indicates that a limit of 65536 or higher is more common
+//
+// this is only the initial size, the buffer grows (see `DynamicUniformBuffer::reserve`) if needed
 const DYNAMIC_UNIFORM_BUFFER_SIZE: wgpu::BufferAddress = 1 << 19;
 
 // https://www.khronos.org/registry/vulkan/specs/1.2-extensions/html/vkspec.html#limits-minUniformBufferOffsetAlignment
@@ -78,15 +79,8 @@ where
         // TODO: is this something we can enforce at compile time?
         assert!(align_of::<T>() % DYNAMIC_UNIFORM_BUFFER_ALIGNMENT == 0);
 
-        let inner = device.create_buffer(&wgpu::BufferDescriptor {
-            label: Some("dynamic uniform buffer"),
-            size: DYNAMIC_UNIFORM_BUFFER_SIZE,
-            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-            mapped_at_creation: false,
-        });
-
-        let mut update_buf = Vec::with_capacity(DYNAMIC_UNIFORM_BUFFER_SIZE as usize);
-        update_buf.resize(DYNAMIC_UNIFORM_BUFFER_SIZE as usize, 0);
+        let inner = Self::create_inner(device, DYNAMIC_UNIFORM_BUFFER_SIZE);
+        let update_buf = vec![0; DYNAMIC_UNIFORM_BUFFER_SIZE as usize];
 
         DynamicUniformBuffer {
             _rc: Arc::new(()),
@@ -97,6 +91,48 @@ where
         }
     }
 
+    fn create_inner(device: &RenderDevice, size: wgpu::BufferAddress) -> Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("dynamic uniform buffer"),
+            size,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    /// The number of bytes this buffer can hold before it has to grow.
+    pub fn capacity(&self) -> wgpu::BufferAddress {
+        self.update_buf.len() as wgpu::BufferAddress
+    }
+
+    /// Makes room for at least `count` more blocks, doubling the size of the buffer until they
+    /// fit.
+    ///
+    /// Returns `true` if the GPU buffer was reallocated, in which case any bind groups which refer
+    /// to it must be recreated. Existing blocks keep their addresses, and their contents are
+    /// carried over by the next `flush`.
+    pub fn reserve(&mut self, device: &RenderDevice, count: usize) -> bool {
+        let required = self.allocated + count as u64 * self.block_size().get();
+        let mut capacity = self.capacity();
+        if required <= capacity {
+            return false;
+        }
+
+        while capacity < required {
+            capacity *= 2;
+        }
+
+        debug!(
+            "Growing dynamic uniform buffer from {} to {} bytes",
+            self.capacity(),
+            capacity
+        );
+
+        self.update_buf.resize(capacity as usize, 0);
+        self.inner = Self::create_inner(device, capacity);
+        true
+    }
+
     pub fn block_size(&self) -> wgpu::BufferSize {
         std::num::NonZeroU64::new(
             ((DYNAMIC_UNIFORM_BUFFER_ALIGNMENT / 8).max(size_of::<T>())) as u64,
@@ -114,9 +150,9 @@ where
             "Allocating dynamic uniform block (allocated: {})",
             allocated
         );
-        if allocated + size > DYNAMIC_UNIFORM_BUFFER_SIZE {
+        if allocated + size > self.capacity() {
             panic!(
-                "Not enough space to allocate {} bytes in dynamic uniform buffer",
+                "Not enough space to allocate {} bytes in dynamic uniform buffer (missing reserve?)",
                 size
             );
         }
@@ -141,27 +177,9 @@ where
         slice.copy_from_slice(unsafe { any_as_bytes(&val) });
     }
 
-    /// Removes all allocations from the underlying buffer.
-    ///
-    /// Returns an error if the buffer is currently mapped or there are
-    /// outstanding allocated blocks.
-    pub fn clear(&mut self) -> Result<(), Error> {
-        let out = mem::take(&mut self._rc);
-        match Arc::try_unwrap(out) {
-            // no outstanding blocks
-            Ok(()) => {
-                self.allocated = 0;
-                Ok(())
-            }
-            Err(rc) => {
-                let _ = mem::replace(&mut self._rc, rc);
-                bail!("Can't clear uniform buffer: there are outstanding references to allocated blocks.");
-            }
-        }
-    }
-
     pub fn flush(&self, queue: &RenderQueue) {
-        queue.write_buffer(&self.inner, 0, &self.update_buf);
+        // the queue stages this for us, and only the allocated part needs uploading
+        queue.write_buffer(&self.inner, 0, &self.update_buf[..self.allocated as usize]);
     }
 
     pub fn buffer(&self) -> &wgpu::Buffer {
@@ -183,24 +201,3 @@ impl<T> DynamicUniformBufferBlock<T> {
         self.addr as wgpu::DynamicOffset
     }
 }
-
-pub fn clear_and_rewrite<T>(
-    queue: &RenderQueue,
-    buffer: &mut DynamicUniformBuffer<T>,
-    blocks: &mut Vec<DynamicUniformBufferBlock<T>>,
-    uniforms: &[T],
-) where
-    T: Pod,
-{
-    blocks.clear();
-    buffer.clear().unwrap();
-    for (uni_id, uni) in uniforms.iter().enumerate() {
-        if uni_id >= blocks.len() {
-            let block = buffer.allocate(*uni);
-            blocks.push(block);
-        } else {
-            buffer.write_block(&blocks[uni_id], *uni);
-        }
-    }
-    buffer.flush(queue);
-}
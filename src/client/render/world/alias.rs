@@ -2,17 +2,21 @@ use std::{mem::size_of, ops::Range};
 
 use crate::{
     client::render::{
+        stats::{self, Counter},
         world::{BindGroupLayoutId, WorldPipelineBase},
-        GraphicsState, Pipeline, TextureData,
+        DiffuseData, GraphicsState, Pipeline, ShaderCompiler,
     },
     common::{
+        md3::Md3Model,
         mdl::{self, AliasModel},
         util::any_slice_as_bytes,
     },
 };
 
+use beef::Cow;
 use bevy::{
     ecs::component::Component,
+    log::{debug, warn},
     render::{
         render_phase::TrackedRenderPass,
         render_resource::{
@@ -22,20 +26,24 @@ use bevy::{
         texture::CachedTexture,
     },
 };
-use cgmath::{InnerSpace as _, Matrix4, Vector3, Zero as _};
+use cgmath::{InnerSpace as _, Matrix4, SquareMatrix as _, Vector3, Zero as _};
 use chrono::Duration;
 use failure::Error;
 use lazy_static::lazy_static;
+use parking_lot::Mutex;
 
 pub struct AliasPipeline {
     pipeline: RenderPipeline,
     bind_group_layouts: Vec<BindGroupLayout>,
+
+    // a single untransformed, opaque instance, for models drawn on their own
+    identity_instance_buffer: Buffer,
 }
 
 impl AliasPipeline {
     pub fn new(
         device: &RenderDevice,
-        compiler: &mut shaderc::Compiler,
+        compiler: &mut ShaderCompiler,
         world_bind_group_layouts: &[BindGroupLayout],
         diffuse_format: wgpu::TextureFormat,
         normal_format: wgpu::TextureFormat,
@@ -49,16 +57,30 @@ impl AliasPipeline {
             (diffuse_format, normal_format),
         );
 
+        let identity_instance_buffer =
+            device.create_buffer_with_data(&wgpu::util::BufferInitDescriptor {
+                label: Some("alias identity instance"),
+                contents: unsafe {
+                    any_slice_as_bytes(&[AliasInstance {
+                        model: Matrix4::identity(),
+                        alpha: 1.0,
+                        blend: 1.0,
+                    }])
+                },
+                usage: wgpu::BufferUsages::VERTEX,
+            });
+
         AliasPipeline {
             pipeline,
             bind_group_layouts,
+            identity_instance_buffer,
         }
     }
 
     pub fn rebuild(
         &mut self,
         device: &RenderDevice,
-        compiler: &mut shaderc::Compiler,
+        compiler: &mut ShaderCompiler,
         diffuse_format: wgpu::TextureFormat,
         normal_format: wgpu::TextureFormat,
         world_bind_group_layouts: &[BindGroupLayout],
@@ -83,31 +105,149 @@ impl AliasPipeline {
     pub fn bind_group_layouts(&self) -> &[BindGroupLayout] {
         &self.bind_group_layouts
     }
+
+    /// A buffer holding one instance with no transform of its own, so that a model can be drawn
+    /// with only the transform from its entity uniforms.
+    pub fn identity_instance_buffer(&self) -> &Buffer {
+        &self.identity_instance_buffer
+    }
 }
 
+/// The per-instance vertex data. Entities sharing a model, keyframe and skin are drawn with one
+/// instanced draw, and the world's entity uniforms hold the camera transforms shared by all of
+/// them.
+///
+/// The camera transforms aren't push constants, since WebGPU doesn't have them.
 #[repr(C)]
 #[derive(Copy, Clone, Debug)]
-pub struct VertexPushConstants {
-    pub transform: Matrix4<f32>,
-    pub model_view: Matrix4<f32>,
+pub struct AliasInstance {
+    /// Model-only transform matrix
+    pub model: Matrix4<f32>,
+
+    /// How opaque to draw the model. The deferred pass can't blend, so anything less than 1 is
+    /// drawn with a dither pattern.
+    pub alpha: f32,
+
+    /// How far to blend from the previous frame to the current one, from 0 to 1.
+    pub blend: f32,
+}
+
+// room for this many instances is allocated up front
+const INITIAL_INSTANCE_CAPACITY: usize = 256;
+
+/// The instances of the alias models drawn in a frame, with the instances of each batch stored
+/// together.
+pub struct AliasInstanceBuffer {
+    inner: Buffer,
+    capacity: usize,
+    instances: Mutex<Vec<AliasInstance>>,
+}
+
+impl AliasInstanceBuffer {
+    pub fn new(device: &RenderDevice) -> AliasInstanceBuffer {
+        AliasInstanceBuffer {
+            inner: Self::create_inner(device, INITIAL_INSTANCE_CAPACITY),
+            capacity: INITIAL_INSTANCE_CAPACITY,
+            instances: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn create_inner(device: &RenderDevice, capacity: usize) -> Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("alias instance buffer"),
+            size: (capacity * size_of::<AliasInstance>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    /// Makes room for `count` instances, doubling the size of the buffer until they fit.
+    pub fn reserve(&mut self, device: &RenderDevice, count: usize) {
+        if count <= self.capacity {
+            return;
+        }
+
+        while self.capacity < count {
+            self.capacity *= 2;
+        }
+
+        debug!(
+            "Growing alias instance buffer to {} instances",
+            self.capacity
+        );
+        self.inner = Self::create_inner(device, self.capacity);
+    }
+
+    /// Forget the instances from the last frame.
+    pub fn clear(&self) {
+        self.instances.lock().clear();
+    }
+
+    /// Add the instances of a batch, returning their range in the buffer.
+    pub fn push_batch<I>(&self, instances: I) -> Range<u32>
+    where
+        I: IntoIterator<Item = AliasInstance>,
+    {
+        let mut all_instances = self.instances.lock();
+        let start = all_instances.len() as u32;
+        all_instances.extend(instances);
+        start..all_instances.len() as u32
+    }
+
+    pub fn flush(&self, queue: &RenderQueue) {
+        let instances = self.instances.lock();
+        if instances.len() > self.capacity {
+            // the instances past the end would be drawn with whatever was there before
+            warn!(
+                "{} alias instances don't fit in a buffer of {} (missing reserve?)",
+                instances.len(),
+                self.capacity
+            );
+        }
+
+        let len = instances.len().min(self.capacity);
+        queue.write_buffer(&self.inner, 0, unsafe {
+            any_slice_as_bytes(&instances[..len])
+        });
+    }
+
+    pub fn buffer(&self) -> &Buffer {
+        &self.inner
+    }
 }
 
 lazy_static! {
     static ref VERTEX_ATTRIBUTES: [wgpu::VertexAttribute; 3] =
         wgpu::vertex_attr_array![
-            // frame 0 position
+            // current frame position
             0 => Float32x3,
-            // frame 1 position
-            // 1 => Float32x3,
             // normal
             2 => Float32x3,
             // texcoord
             3 => Float32x2,
         ];
+    // the same vertices from the previous frame, which only need their positions
+    static ref PREV_FRAME_ATTRIBUTES: [wgpu::VertexAttribute; 1] =
+        wgpu::vertex_attr_array![
+            // previous frame position
+            1 => Float32x3,
+        ];
+    static ref INSTANCE_ATTRIBUTES: [wgpu::VertexAttribute; 5] =
+        wgpu::vertex_attr_array![
+            // model transform, a column at a time
+            4 => Float32x4,
+            5 => Float32x4,
+            6 => Float32x4,
+            7 => Float32x4,
+            // alpha
+            8 => Float32,
+            // blend
+            9 => Float32,
+        ];
 }
 
 impl Pipeline for AliasPipeline {
-    type VertexPushConstants = VertexPushConstants;
+    type VertexPushConstants = ();
     type SharedPushConstants = ();
     type FragmentPushConstants = ();
 
@@ -158,11 +298,23 @@ impl Pipeline for AliasPipeline {
 
     // NOTE: if the vertex format is changed, this descriptor must also be changed accordingly.
     fn vertex_buffer_layouts() -> Vec<wgpu::VertexBufferLayout<'static>> {
-        vec![wgpu::VertexBufferLayout {
-            array_stride: size_of::<AliasVertex>() as u64,
-            step_mode: wgpu::VertexStepMode::Vertex,
-            attributes: &VERTEX_ATTRIBUTES[..],
-        }]
+        vec![
+            wgpu::VertexBufferLayout {
+                array_stride: size_of::<AliasVertex>() as u64,
+                step_mode: wgpu::VertexStepMode::Vertex,
+                attributes: &VERTEX_ATTRIBUTES[..],
+            },
+            wgpu::VertexBufferLayout {
+                array_stride: size_of::<AliasInstance>() as u64,
+                step_mode: wgpu::VertexStepMode::Instance,
+                attributes: &INSTANCE_ATTRIBUTES[..],
+            },
+            wgpu::VertexBufferLayout {
+                array_stride: size_of::<AliasVertex>() as u64,
+                step_mode: wgpu::VertexStepMode::Vertex,
+                attributes: &PREV_FRAME_ATTRIBUTES[..],
+            },
+        ]
     }
 }
 
@@ -228,6 +380,37 @@ enum Texture {
 }
 
 impl Texture {
+    fn new_static(
+        state: &GraphicsState,
+        device: &RenderDevice,
+        queue: &RenderQueue,
+        width: u32,
+        height: u32,
+        diffuse_data: DiffuseData,
+    ) -> Texture {
+        let diffuse_texture =
+            state.create_diffuse_texture(device, queue, None, width, height, diffuse_data);
+        let diffuse_view = diffuse_texture.create_view(&Default::default());
+        let bind_group = device.create_bind_group(
+            None,
+            // TODO: per-pipeline bind group layout ids
+            &state.alias_pipeline().bind_group_layouts()
+                [BindGroupLayoutId::PerTexture as usize - 2],
+            &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&diffuse_view),
+            }],
+        );
+
+        Texture::Static {
+            _diffuse_texture: CachedTexture {
+                texture: diffuse_texture,
+                default_view: diffuse_view,
+            },
+            bind_group,
+        }
+    }
+
     fn animate(&self, time: Duration) -> &BindGroup {
         match self {
             Texture::Static { ref bind_group, .. } => bind_group,
@@ -252,10 +435,20 @@ impl Texture {
     }
 }
 
-#[derive(Component)]
-pub struct AliasRenderer {
+/// A part of a model with skins of its own. An MDL is a single surface, while an MD3 can have
+/// several, which are drawn one after another.
+struct Surface {
     keyframes: Vec<Keyframe>,
     textures: Vec<Texture>,
+}
+
+#[derive(Component)]
+pub struct AliasRenderer {
+    // the bounds of every keyframe, relative to the model
+    min: Vector3<f32>,
+    max: Vector3<f32>,
+
+    surfaces: Vec<Surface>,
     vertex_buffer: Buffer,
 }
 
@@ -368,32 +561,14 @@ impl AliasRenderer {
             match *texture {
                 mdl::Texture::Static(ref tex) => {
                     let (diffuse_data, _fullbright_data) = state.palette.translate(tex.indices());
-                    let diffuse_texture = state.create_texture(
+                    textures.push(Texture::new_static(
+                        state,
                         device,
                         queue,
-                        None,
                         w,
                         h,
-                        &TextureData::Diffuse(diffuse_data),
-                    );
-                    let diffuse_view = diffuse_texture.create_view(&Default::default());
-                    let bind_group = device.create_bind_group(
-                        None,
-                        // TODO: per-pipeline bind group layout ids
-                        &state.alias_pipeline().bind_group_layouts()
-                            [BindGroupLayoutId::PerTexture as usize - 2],
-                        &[wgpu::BindGroupEntry {
-                            binding: 0,
-                            resource: wgpu::BindingResource::TextureView(&diffuse_view),
-                        }],
-                    );
-                    textures.push(Texture::Static {
-                        _diffuse_texture: CachedTexture {
-                            texture: diffuse_texture,
-                            default_view: diffuse_view,
-                        },
-                        bind_group,
-                    });
+                        diffuse_data,
+                    ));
                 }
                 mdl::Texture::Animated(ref tex) => {
                     let mut total_duration = Duration::zero();
@@ -407,14 +582,8 @@ impl AliasRenderer {
 
                         let (diffuse_data, _fullbright_data) =
                             state.palette.translate(frame.indices());
-                        let diffuse_texture = state.create_texture(
-                            device,
-                            queue,
-                            None,
-                            w,
-                            h,
-                            &TextureData::Diffuse(diffuse_data),
-                        );
+                        let diffuse_texture =
+                            state.create_diffuse_texture(device, queue, None, w, h, diffuse_data);
                         let diffuse_view = diffuse_texture.create_view(&Default::default());
                         let bind_group = device.create_bind_group(
                             None,
@@ -443,34 +612,189 @@ impl AliasRenderer {
             }
         }
 
+        let mut min = Vector3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY);
+        let mut max = Vector3::new(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY);
+        for keyframe in alias_model.keyframes() {
+            let (kf_min, kf_max) = match *keyframe {
+                mdl::Keyframe::Static(ref kf) => (kf.min(), kf.max()),
+                mdl::Keyframe::Animated(ref kf) => (kf.min(), kf.max()),
+            };
+
+            for component in 0..3 {
+                min[component] = min[component].min(kf_min[component]);
+                max[component] = max[component].max(kf_max[component]);
+            }
+        }
+
         Ok(AliasRenderer {
-            keyframes,
-            textures,
+            min,
+            max,
+            surfaces: vec![Surface {
+                keyframes,
+                textures,
+            }],
             vertex_buffer,
         })
     }
 
+    /// Build a renderer for an MD3, with a surface for each of the model's surfaces and a keyframe
+    /// for each of its frames. Frames are blended like any other keyframes, so MD3s use the same
+    /// pipeline as MDLs.
+    pub fn from_md3(
+        state: &GraphicsState,
+        device: &RenderDevice,
+        queue: &RenderQueue,
+        md3_model: &Md3Model,
+    ) -> AliasRenderer {
+        let mut vertices = Vec::new();
+        let mut surfaces = Vec::new();
+
+        for surface in md3_model.surfaces() {
+            let mut keyframes = Vec::new();
+            for frame_id in 0..md3_model.frames().len() {
+                let frame_vertices = surface.frame_vertices(frame_id);
+
+                let vertex_start = vertices.len() as u32;
+                for triangle in surface.triangles() {
+                    for &index in triangle {
+                        let vertex = &frame_vertices[index as usize];
+                        vertices.push(AliasVertex {
+                            position: vertex.position().into(),
+                            normal: vertex.normal().into(),
+                            diffuse_texcoord: surface.texcoords()[index as usize],
+                        });
+                    }
+                }
+                let vertex_end = vertices.len() as u32;
+
+                keyframes.push(Keyframe::Static {
+                    vertex_range: vertex_start..vertex_end,
+                });
+            }
+
+            // a skin that couldn't be loaded is drawn plain white so that the surface's shape still
+            // shows, and so that the skins after it keep their numbers
+            let plain = || {
+                Texture::new_static(
+                    state,
+                    device,
+                    queue,
+                    1,
+                    1,
+                    DiffuseData {
+                        rgba: Cow::owned(vec![0xff; 4]),
+                    },
+                )
+            };
+            let mut textures = surface
+                .skins()
+                .iter()
+                .map(|skin| match skin {
+                    Some(skin) => Texture::new_static(
+                        state,
+                        device,
+                        queue,
+                        skin.width,
+                        skin.height,
+                        DiffuseData {
+                            rgba: Cow::borrowed(&skin.rgba),
+                        },
+                    ),
+                    None => plain(),
+                })
+                .collect::<Vec<_>>();
+            if textures.is_empty() {
+                textures.push(plain());
+            }
+
+            surfaces.push(Surface {
+                keyframes,
+                textures,
+            });
+        }
+
+        let vertex_buffer = device.create_buffer_with_data(&wgpu::util::BufferInitDescriptor {
+            label: None,
+            contents: unsafe { any_slice_as_bytes(vertices.as_slice()) },
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+
+        AliasRenderer {
+            min: md3_model.min(),
+            max: md3_model.max(),
+            surfaces,
+            vertex_buffer,
+        }
+    }
+
+    /// The minimum extent of any of the model's keyframes, relative to its origin.
+    pub fn min(&self) -> Vector3<f32> {
+        self.min
+    }
+
+    /// The maximum extent of any of the model's keyframes, relative to its origin.
+    pub fn max(&self) -> Vector3<f32> {
+        self.max
+    }
+
+    /// Draw `instances` from `instance_buffer`, which are all blending between the same two
+    /// keyframes and have the same skin.
     pub fn record_draw<'a>(
         &'a self,
         state: &'a GraphicsState,
         pass: &mut TrackedRenderPass<'a>,
         time: Duration,
+        prev_keyframe_id: usize,
         keyframe_id: usize,
         texture_id: usize,
+        instance_buffer: &'a Buffer,
+        instances: Range<u32>,
     ) {
-        let Some(keyframe) = self.keyframes.get(keyframe_id).map(|k| k.animate(time)) else {
-            return;
-        };
-        let Some(tex) = self.textures.get(texture_id) else {
-            return;
+        let vertex_size = size_of::<AliasVertex>() as u64;
+        let vertices = |range: &Range<u32>| {
+            self.vertex_buffer
+                .slice(range.start as u64 * vertex_size..range.end as u64 * vertex_size)
         };
 
         pass.set_render_pipeline(state.alias_pipeline().pipeline());
-        pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        pass.set_vertex_buffer(1, instance_buffer.slice(..));
+
+        for surface in &self.surfaces {
+            let Some(keyframe) = surface.keyframes.get(keyframe_id).map(|k| k.animate(time)) else {
+                continue;
+            };
+            // an entity's skin number can be out of range for some of the surfaces
+            let Some(tex) = surface
+                .textures
+                .get(texture_id)
+                .or_else(|| surface.textures.first())
+            else {
+                continue;
+            };
+
+            // a previous frame that doesn't exist is drawn as the current one
+            let prev_keyframe = surface
+                .keyframes
+                .get(prev_keyframe_id)
+                .map(|k| k.animate(time))
+                .unwrap_or_else(|| keyframe.clone());
+
+            // a surface without triangles has no vertices to bind
+            if keyframe.is_empty() || prev_keyframe.is_empty() {
+                continue;
+            }
+
+            pass.set_vertex_buffer(0, vertices(&keyframe));
+            pass.set_vertex_buffer(2, vertices(&prev_keyframe));
 
-        let tex = tex.animate(time);
+            let tex = tex.animate(time);
 
-        pass.set_bind_group(BindGroupLayoutId::PerTexture as usize, tex, &[]);
-        pass.draw(keyframe, 0..1)
+            pass.set_bind_group(BindGroupLayoutId::PerTexture as usize, tex, &[]);
+            pass.draw(0..keyframe.len() as u32, instances.clone());
+            stats::count_draw(
+                Counter::AliasPolys,
+                (keyframe.len() / 3 * instances.len()) as u64,
+            );
+        }
     }
 }
@@ -0,0 +1,429 @@
+//! Bloom around the brightest parts of the lit scene, drawn between the deferred pass and
+//! postprocess.
+//!
+//! The parts of the scene brighter than `r_bloom_threshold` are downsampled to a quarter of the
+//! world's resolution, blurred horizontally and then vertically, and added back to the scene
+//! scaled by `r_bloom_intensity`.
+
+use std::{mem::size_of, num::NonZeroU64};
+
+use bevy::{
+    core_pipeline::fullscreen_vertex_shader::fullscreen_shader_vertex_state,
+    prelude::*,
+    render::{
+        camera::ExtractedCamera,
+        extract_resource::ExtractResource,
+        render_graph::{RenderLabel, ViewNode},
+        render_resource::{
+            BindGroup, BindGroupLayout, Buffer, CachedRenderPipelineId, FragmentState,
+            PipelineCache, RenderPassDescriptor, RenderPipeline, RenderPipelineDescriptor, Sampler,
+            TextureView,
+        },
+        renderer::{RenderContext, RenderDevice, RenderQueue},
+        texture::CachedTexture,
+        view::{PostProcessWrite, ViewTarget},
+    },
+};
+use parking_lot::Mutex;
+use serde::Deserialize;
+use wgpu::{ColorTargetState, ColorWrites};
+
+use crate::{
+    client::render::{
+        stats::{self, Counter},
+        world::deferred::EXPOSURE_MULTIPLIER,
+        RenderResolution, RenderState, RenderVars,
+    },
+    common::{console::Registry, util::any_as_bytes},
+};
+
+/// How much smaller the bloom textures are than the world.
+const DOWNSCALE: u32 = 4;
+
+#[repr(C, align(256))]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BloomUniforms {
+    /// The brightness past which the scene blooms, and how strongly the bloom is added back.
+    pub params: [f32; 4],
+    /// The fraction of the scene the world was drawn to in x and y, from `r_scale`.
+    pub scale: [f32; 4],
+}
+
+const BIND_GROUP_LAYOUT_ENTRIES: &[wgpu::BindGroupLayoutEntry] = &[
+    // input texture
+    wgpu::BindGroupLayoutEntry {
+        binding: 0,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            view_dimension: wgpu::TextureViewDimension::D2,
+            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            multisampled: false,
+        },
+        count: None,
+    },
+    // sampler
+    wgpu::BindGroupLayoutEntry {
+        binding: 1,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+        count: None,
+    },
+    // BloomUniforms
+    wgpu::BindGroupLayoutEntry {
+        binding: 2,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: NonZeroU64::new(size_of::<BloomUniforms>() as u64),
+        },
+        count: None,
+    },
+    // blurred bloom, for the composite
+    wgpu::BindGroupLayoutEntry {
+        binding: 3,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            view_dimension: wgpu::TextureViewDimension::D2,
+            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            multisampled: false,
+        },
+        count: None,
+    },
+];
+
+/// The pipelines for each step of the bloom, which all draw a fullscreen triangle.
+#[derive(Resource)]
+pub struct BloomPipeline {
+    /// For the prefilter and the blurs, which only read one texture.
+    blur_layout: BindGroupLayout,
+    composite_layout: BindGroupLayout,
+    sampler: Sampler,
+    uniform_buffer: Buffer,
+    prefilter: CachedRenderPipelineId,
+    blur_horizontal: CachedRenderPipelineId,
+    blur_vertical: CachedRenderPipelineId,
+    composite: CachedRenderPipelineId,
+}
+
+impl FromWorld for BloomPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let device = world.resource::<RenderDevice>();
+        let pipeline_cache = world.resource::<PipelineCache>();
+        let shader = world
+            .resource::<AssetServer>()
+            .load::<Shader>("shaders/bloom.wgsl");
+
+        let blur_layout =
+            device.create_bind_group_layout(Some("bloom blur"), &BIND_GROUP_LAYOUT_ENTRIES[..3]);
+        let composite_layout =
+            device.create_bind_group_layout(Some("bloom composite"), BIND_GROUP_LAYOUT_ENTRIES);
+
+        // the blur reaches past the edges of the bloom textures, which would wrap around with the
+        // samplers that repeat
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("bloom sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..default()
+        });
+        let uniform_buffer = device.create_buffer_with_data(&wgpu::util::BufferInitDescriptor {
+            label: Some("bloom uniforms"),
+            contents: unsafe { any_as_bytes(&BloomUniforms::default()) },
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let queue_pipeline = |layout: &BindGroupLayout, entry_point: &'static str| {
+            pipeline_cache.queue_render_pipeline(RenderPipelineDescriptor {
+                label: Some(format!("bloom {}", entry_point).into()),
+                layout: vec![layout.clone()],
+                push_constant_ranges: Vec::new(),
+                vertex: fullscreen_shader_vertex_state(),
+                fragment: Some(FragmentState {
+                    shader: shader.clone(),
+                    shader_defs: Vec::new(),
+                    entry_point: entry_point.into(),
+                    targets: vec![Some(ColorTargetState {
+                        format: ViewTarget::TEXTURE_FORMAT_HDR,
+                        blend: None,
+                        write_mask: ColorWrites::ALL,
+                    })],
+                }),
+                primitive: default(),
+                depth_stencil: None,
+                multisample: default(),
+            })
+        };
+
+        BloomPipeline {
+            prefilter: queue_pipeline(&blur_layout, "prefilter"),
+            blur_horizontal: queue_pipeline(&blur_layout, "blur_horizontal"),
+            blur_vertical: queue_pipeline(&blur_layout, "blur_vertical"),
+            composite: queue_pipeline(&composite_layout, "composite"),
+            blur_layout,
+            composite_layout,
+            sampler,
+            uniform_buffer,
+        }
+    }
+}
+
+impl BloomPipeline {
+    fn bind_group(
+        &self,
+        device: &RenderDevice,
+        input: &TextureView,
+        bloom: Option<&TextureView>,
+    ) -> BindGroup {
+        let mut entries = vec![
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(input),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(&self.sampler),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &self.uniform_buffer,
+                    offset: 0,
+                    size: None,
+                }),
+            },
+        ];
+
+        let layout = match bloom {
+            Some(bloom) => {
+                entries.push(wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(bloom),
+                });
+                &self.composite_layout
+            }
+            None => &self.blur_layout,
+        };
+
+        device.create_bind_group(Some("bloom bind group"), layout, &entries)
+    }
+}
+
+/// The two textures the bloom is blurred between, kept until the world's resolution changes.
+#[derive(Resource, Default)]
+pub struct BloomTextures {
+    textures: Mutex<Option<(u32, u32, [CachedTexture; 2])>>,
+}
+
+impl BloomTextures {
+    fn get_or_create(&self, device: &RenderDevice, width: u32, height: u32) -> [CachedTexture; 2] {
+        let mut textures = self.textures.lock();
+        if let Some((w, h, textures)) = &*textures {
+            if (*w, *h) == (width, height) {
+                return textures.clone();
+            }
+        }
+
+        let create = || {
+            let texture = device.create_texture(&wgpu::TextureDescriptor {
+                label: Some("bloom texture"),
+                size: wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: ViewTarget::TEXTURE_FORMAT_HDR,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                    | wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            });
+            let default_view = texture.create_view(&default());
+            CachedTexture {
+                texture,
+                default_view,
+            }
+        };
+
+        let created = [create(), create()];
+        *textures = Some((width, height, created.clone()));
+        created
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Resource, Deserialize)]
+pub struct BloomVars {
+    #[serde(rename(deserialize = "r_bloom"))]
+    enabled: f32,
+    #[serde(rename(deserialize = "r_bloom_intensity"))]
+    intensity: f32,
+    #[serde(rename(deserialize = "r_bloom_threshold"))]
+    threshold: f32,
+}
+
+impl Default for BloomVars {
+    fn default() -> Self {
+        Self {
+            enabled: 1.0,
+            intensity: 0.15,
+            threshold: 1.0,
+        }
+    }
+}
+
+impl ExtractResource for BloomVars {
+    type Source = Registry;
+
+    fn extract_resource(source: &Self::Source) -> Self {
+        source.read_cvars().unwrap_or_default()
+    }
+}
+
+/// Draw a fullscreen triangle to `target`.
+fn draw_fullscreen(
+    render_context: &mut RenderContext,
+    label: &'static str,
+    pipeline: &RenderPipeline,
+    bind_group: &BindGroup,
+    target: &TextureView,
+) {
+    let mut pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
+        label: Some(label),
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+            view: target,
+            resolve_target: None,
+            ops: default(),
+        })],
+        ..default()
+    });
+
+    pass.set_render_pipeline(pipeline);
+    pass.set_bind_group(0, bind_group, &[]);
+    pass.draw(0..3, 0..1);
+    stats::add(Counter::DrawCalls, 1);
+}
+
+#[derive(Debug, Hash, PartialEq, Eq, Clone, RenderLabel)]
+pub struct BloomPassLabel;
+
+#[derive(Default)]
+pub struct BloomPass;
+
+impl ViewNode for BloomPass {
+    type ViewQuery = (&'static ViewTarget, &'static ExtractedCamera);
+
+    fn run<'w>(
+        &self,
+        _graph: &mut bevy::render::render_graph::RenderGraphContext,
+        render_context: &mut RenderContext<'w>,
+        (target, extracted_camera): (&ViewTarget, &ExtractedCamera),
+        world: &'w World,
+    ) -> Result<(), bevy::render::render_graph::NodeRunError> {
+        profile_span!("bloom_pass");
+        let vars = world
+            .get_resource::<BloomVars>()
+            .copied()
+            .unwrap_or_default();
+        if vars.enabled == 0.0 || vars.intensity <= 0.0 {
+            return Ok(());
+        }
+
+        // nothing was drawn to bloom
+        if world.get_resource::<RenderState>().is_none() {
+            return Ok(());
+        }
+
+        let bloom_pipeline = world.resource::<BloomPipeline>();
+        let textures = world.resource::<BloomTextures>();
+        let queue = world.resource::<RenderQueue>();
+        let pipeline_cache = world.resource::<PipelineCache>();
+
+        // the pipelines are compiled in the background, so there's no bloom for the first few
+        // frames
+        let (Some(prefilter), Some(blur_horizontal), Some(blur_vertical), Some(composite)) = (
+            pipeline_cache.get_render_pipeline(bloom_pipeline.prefilter),
+            pipeline_cache.get_render_pipeline(bloom_pipeline.blur_horizontal),
+            pipeline_cache.get_render_pipeline(bloom_pipeline.blur_vertical),
+            pipeline_cache.get_render_pipeline(bloom_pipeline.composite),
+        ) else {
+            return Ok(());
+        };
+
+        let Some(&RenderResolution(width, height)) = world.get_resource::<RenderResolution>()
+        else {
+            return Ok(());
+        };
+        let (scaled_width, scaled_height) = world
+            .get_resource::<RenderVars>()
+            .map_or((width, height), |vars| vars.scaled_size(width, height));
+
+        // the threshold is in Quake's light levels, which the deferred pass scales by the exposure
+        let exposure = EXPOSURE_MULTIPLIER * extracted_camera.exposure;
+        queue.write_buffer(&bloom_pipeline.uniform_buffer, 0, unsafe {
+            any_as_bytes(&BloomUniforms {
+                params: [vars.threshold.max(0.0) * exposure, vars.intensity, 0.0, 0.0],
+                scale: [
+                    scaled_width as f32 / width as f32,
+                    scaled_height as f32 / height as f32,
+                    0.0,
+                    0.0,
+                ],
+            })
+        });
+
+        let [bloom, blurred] = textures.get_or_create(
+            render_context.render_device(),
+            scaled_width.div_ceil(DOWNSCALE),
+            scaled_height.div_ceil(DOWNSCALE),
+        );
+
+        let PostProcessWrite {
+            source: scene,
+            destination: scene_target,
+        } = target.post_process_write();
+
+        let device = render_context.render_device();
+        let prefilter_bind_group = bloom_pipeline.bind_group(device, scene, None);
+        let blur_horizontal_bind_group =
+            bloom_pipeline.bind_group(device, &bloom.default_view, None);
+        let blur_vertical_bind_group =
+            bloom_pipeline.bind_group(device, &blurred.default_view, None);
+        let composite_bind_group =
+            bloom_pipeline.bind_group(device, scene, Some(&bloom.default_view));
+
+        draw_fullscreen(
+            render_context,
+            "bloom prefilter",
+            prefilter,
+            &prefilter_bind_group,
+            &bloom.default_view,
+        );
+        draw_fullscreen(
+            render_context,
+            "bloom blur horizontal",
+            blur_horizontal,
+            &blur_horizontal_bind_group,
+            &blurred.default_view,
+        );
+        draw_fullscreen(
+            render_context,
+            "bloom blur vertical",
+            blur_vertical,
+            &blur_vertical_bind_group,
+            &bloom.default_view,
+        );
+        draw_fullscreen(
+            render_context,
+            "bloom composite",
+            composite,
+            &composite_bind_group,
+            scene_target,
+        );
+
+        Ok(())
+    }
+}
@@ -20,7 +20,6 @@
 
 use std::{
     mem::size_of,
-    num::NonZeroU32,
     ops::Range,
     sync::{
         atomic::{AtomicBool, Ordering},
@@ -30,21 +29,26 @@ use std::{
 
 use crate::{
     client::render::{
+        atlas::{AtlasAllocator, AtlasRect},
+        external::ExternalTexture,
         pipeline::PushConstantUpdate,
+        stats::{self, Counter},
         warp,
-        world::{BindGroupLayoutId, WorldPipelineBase},
-        Camera, GraphicsState, LightmapData, Pipeline, TextureData,
+        world::{BindGroupLayoutId, CullStats, WorldPipelineBase},
+        write_texture_region, Camera, DiffuseData, DirtyRect, FullbrightData, GraphicsState,
+        LightmapData, Pipeline, ShaderCompiler, TextureData,
     },
     common::{
         bsp::{
-            self, BspData, BspFace, BspLeaf, BspModel, BspTexInfo, BspTexture, BspTextureKind,
-            BspTextureMipmap,
+            self, BspData, BspFace, BspLeaf, BspModel, BspTexInfo, BspTexture, BspTextureFrame,
+            BspTextureKind, BspTextureMipmap,
         },
         math,
         util::any_slice_as_bytes,
     },
 };
 
+use arrayvec::ArrayVec;
 use beef::Cow;
 use bevy::{
     prelude::*,
@@ -52,10 +56,12 @@ use bevy::{
         render_phase::TrackedRenderPass,
         render_resource::{
             BindGroup, BindGroupLayout, BindGroupLayoutEntry, Buffer, RenderPipeline, Texture,
+            TextureView,
         },
         renderer::{RenderDevice, RenderQueue},
         texture::CachedTexture,
     },
+    tasks::ComputeTaskPool,
 };
 use bumpalo::Bump;
 use cgmath::{InnerSpace as _, Matrix4, Vector3};
@@ -64,16 +70,24 @@ use failure::Error;
 use hashbrown::HashMap;
 use lazy_static::lazy_static;
 use num::Zero;
+use parking_lot::Mutex;
+
+/// The number of faces built by each task when loading a model.
+const FACES_PER_TASK: usize = 256;
+
+/// The width and height of the pages of the lightmap atlas.
+const LIGHTMAP_PAGE_SIZE: u32 = 512;
 
 pub struct BrushPipeline {
     pipeline: RenderPipeline,
+    translucent_pipeline: RenderPipeline,
     bind_group_layouts: Vec<BindGroupLayout>,
 }
 
 impl BrushPipeline {
     pub fn new(
         device: &RenderDevice,
-        compiler: &mut shaderc::Compiler,
+        compiler: &mut ShaderCompiler,
         world_bind_group_layouts: &[BindGroupLayout],
         diffuse_format: wgpu::TextureFormat,
         normal_format: wgpu::TextureFormat,
@@ -86,9 +100,20 @@ impl BrushPipeline {
             sample_count,
             (diffuse_format, normal_format),
         );
+        // shares the layouts, so the same bind groups work with both pipelines
+        let translucent_pipeline = TranslucentBrushPipeline::recreate(
+            device,
+            compiler,
+            world_bind_group_layouts
+                .iter()
+                .chain(bind_group_layouts.iter()),
+            sample_count,
+            (diffuse_format, normal_format),
+        );
 
         BrushPipeline {
             pipeline,
+            translucent_pipeline,
             // TODO: pick a starting capacity
             bind_group_layouts,
         }
@@ -97,7 +122,7 @@ impl BrushPipeline {
     pub fn rebuild(
         &mut self,
         device: &RenderDevice,
-        compiler: &mut shaderc::Compiler,
+        compiler: &mut ShaderCompiler,
         diffuse_format: wgpu::TextureFormat,
         normal_format: wgpu::TextureFormat,
         world_bind_group_layouts: &[BindGroupLayout],
@@ -107,6 +132,13 @@ impl BrushPipeline {
             .iter()
             .chain(self.bind_group_layouts.iter());
         self.pipeline = Self::recreate(
+            device,
+            compiler,
+            layout_refs.clone(),
+            sample_count,
+            (diffuse_format, normal_format),
+        );
+        self.translucent_pipeline = TranslucentBrushPipeline::recreate(
             device,
             compiler,
             layout_refs,
@@ -119,6 +151,12 @@ impl BrushPipeline {
         &self.pipeline
     }
 
+    /// The pipeline for liquids when `r_wateralpha` is below 1, which blends them with what's
+    /// behind them by the blend constant.
+    pub fn translucent_pipeline(&self) -> &RenderPipeline {
+        &self.translucent_pipeline
+    }
+
     pub fn bind_group_layouts(&self) -> &[BindGroupLayout] {
         &self.bind_group_layouts
     }
@@ -168,9 +206,9 @@ const BIND_GROUP_LAYOUT_ENTRIES: &[&[BindGroupLayoutEntry]] = &[
         },
     ],
     &[
-        // lightmap texture array
+        // lightmap texture, blended from the face's light styles
         BindGroupLayoutEntry {
-            count: NonZeroU32::new(4),
+            count: None,
             binding: 0,
             visibility: wgpu::ShaderStages::FRAGMENT,
             ty: wgpu::BindingType::Texture {
@@ -183,7 +221,7 @@ const BIND_GROUP_LAYOUT_ENTRIES: &[&[BindGroupLayoutEntry]] = &[
 ];
 
 lazy_static! {
-    static ref VERTEX_ATTRIBUTES: [wgpu::VertexAttribute; 5] =
+    static ref VERTEX_ATTRIBUTES: [wgpu::VertexAttribute; 4] =
         wgpu::vertex_attr_array![
             // position
             0 => Float32x3,
@@ -193,8 +231,6 @@ lazy_static! {
             2 => Float32x2,
             // lightmap texcoord
             3 => Float32x2,
-            // lightmap animation ids
-            4 => Uint8x4,
         ];
 }
 
@@ -248,6 +284,73 @@ impl Pipeline for BrushPipeline {
     }
 }
 
+/// The brush pipeline with blending, for liquids drawn after everything opaque. Depth isn't written,
+/// so that they don't hide each other.
+struct TranslucentBrushPipeline;
+
+impl Pipeline for TranslucentBrushPipeline {
+    type VertexPushConstants = VertexPushConstants;
+    type SharedPushConstants = SharedPushConstants;
+    type FragmentPushConstants = ();
+
+    type Args = <WorldPipelineBase as Pipeline>::Args;
+
+    fn name() -> &'static str {
+        "translucent_brush"
+    }
+
+    fn vertex_shader() -> &'static str {
+        BrushPipeline::vertex_shader()
+    }
+
+    fn fragment_shader() -> &'static str {
+        BrushPipeline::fragment_shader()
+    }
+
+    fn bind_group_layout_descriptors() -> Vec<Vec<BindGroupLayoutEntry>> {
+        BrushPipeline::bind_group_layout_descriptors()
+    }
+
+    fn primitive_state() -> wgpu::PrimitiveState {
+        BrushPipeline::primitive_state()
+    }
+
+    fn color_target_states_with_args(args: Self::Args) -> Vec<Option<wgpu::ColorTargetState>> {
+        // the light in the diffuse alpha and the normals are blended too
+        let blend = wgpu::BlendComponent {
+            src_factor: wgpu::BlendFactor::Constant,
+            dst_factor: wgpu::BlendFactor::OneMinusConstant,
+            operation: wgpu::BlendOperation::Add,
+        };
+
+        WorldPipelineBase::color_target_states_with_args(args)
+            .into_iter()
+            .map(|target| {
+                target.map(|target| wgpu::ColorTargetState {
+                    blend: Some(wgpu::BlendState {
+                        color: blend,
+                        alpha: blend,
+                    }),
+                    ..target
+                })
+            })
+            .collect()
+    }
+
+    fn depth_stencil_state() -> Option<wgpu::DepthStencilState> {
+        WorldPipelineBase::depth_stencil_state().map(|state| wgpu::DepthStencilState {
+            depth_write_enabled: false,
+            ..state
+        })
+    }
+
+    fn vertex_buffer_layouts() -> Vec<wgpu::VertexBufferLayout<'static>> {
+        BrushPipeline::vertex_buffer_layouts()
+    }
+}
+
+/// The position of a vertex in its face's lightmap, in texels. These are moved to the face's place
+/// in the lightmap atlas once it has one.
 fn calculate_lightmap_texcoords(
     position: Vector3<f32>,
     face: &BspFace,
@@ -255,21 +358,18 @@ fn calculate_lightmap_texcoords(
 ) -> [f32; 2] {
     let mut s = texinfo.s_vector.dot(position) + texinfo.s_offset;
     s -= (face.texture_mins[0] as f32 / 16.0).floor() * 16.0;
-    s += 0.5;
-    s /= face.extents[0] as f32;
 
     let mut t = texinfo.t_vector.dot(position) + texinfo.t_offset;
     t -= (face.texture_mins[1] as f32 / 16.0).floor() * 16.0;
-    t += 0.5;
-    t /= face.extents[1] as f32;
-    [s, t]
+
+    // samples are 16 units apart, and sampled at their centers
+    [s / 16.0 + 0.5, t / 16.0 + 0.5]
 }
 
 type Position = [f32; 3];
 type Normal = [f32; 3];
 type DiffuseTexcoord = [f32; 2];
 type LightmapTexcoord = [f32; 2];
-type LightmapAnim = [u8; 4];
 
 #[repr(C)]
 #[derive(Clone, Copy, Debug)]
@@ -278,7 +378,6 @@ struct BrushVertex {
     normal: Normal,
     diffuse_texcoord: DiffuseTexcoord,
     lightmap_texcoord: LightmapTexcoord,
-    lightmap_anim: LightmapAnim,
 }
 
 #[repr(u32)]
@@ -287,6 +386,35 @@ pub enum TextureKind {
     Normal = 0,
     Warp = 1,
     Sky = 2,
+
+    /// A sky texture replaced by the skybox.
+    Skybox = 3,
+}
+
+/// Create the bind group for a brush texture, which is also used for the skybox.
+pub fn per_texture_bind_group(
+    state: &GraphicsState,
+    device: &RenderDevice,
+    diffuse: &TextureView,
+    fullbright: &TextureView,
+) -> BindGroup {
+    let layout = &state
+        .brush_pipeline()
+        .bind_group_layout(BindGroupLayoutId::PerTexture);
+    device.create_bind_group(
+        Some("per-texture bind group"),
+        layout,
+        &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(diffuse),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(fullbright),
+            },
+        ],
+    )
 }
 
 /// A single frame of a brush texture.
@@ -319,18 +447,28 @@ impl BrushTexture {
             BrushTexture::Animated { ref primary, .. } => primary[0].kind,
         }
     }
+
+    fn frames_mut(&mut self) -> Box<dyn Iterator<Item = &mut BrushTextureFrame> + '_> {
+        match self {
+            BrushTexture::Static(ref mut frame) => Box::new(std::iter::once(frame)),
+            BrushTexture::Animated {
+                ref mut primary,
+                ref mut alternate,
+            } => Box::new(primary.iter_mut().chain(alternate.iter_mut().flatten())),
+        }
+    }
 }
 
 #[derive(Debug)]
 struct BrushFace {
     vertices: Range<u32>,
-    _min: Vector3<f32>,
-    _max: Vector3<f32>,
+    min: Vector3<f32>,
+    max: Vector3<f32>,
 
     texture_id: usize,
 
-    lightmap_ids: Vec<usize>,
-    _light_styles: [u8; 4],
+    /// Liquids and skies aren't lit, so they don't have a lightmap.
+    lightmap: Option<FaceLightmap>,
 
     /// Indicates whether the face should be drawn this frame.
     ///
@@ -340,8 +478,177 @@ struct BrushFace {
     draw_flag: AtomicBool,
 }
 
+/// A face built independently of the rest of the model, before its vertices and lightmaps are
+/// given their final positions in the renderer.
+struct FaceData {
+    vertices: Vec<BrushVertex>,
+    min: Vector3<f32>,
+    max: Vector3<f32>,
+    texture_id: usize,
+    lightmap: Option<LightmapSamples>,
+}
+
+/// A dynamic light, as it's added to the lightmaps of the faces around it.
+#[derive(Clone, Copy, Debug)]
+pub struct LightmapLight {
+    pub origin: Vector3<f32>,
+    pub radius: f32,
+    pub brightness: f32,
+}
+
+/// The samples of a face's lightmaps and where they are on the face.
+struct LightmapSamples {
+    width: u32,
+    height: u32,
+
+    /// The face's light styles, up to the first 255.
+    styles: Vec<u8>,
+
+    /// A `width` by `height` lightmap for each style, as RGB triples.
+    samples: Vec<u8>,
+
+    /// The face's plane, for finding the dynamic lights that reach it.
+    normal: Vector3<f32>,
+    dist: f32,
+
+    /// The texture axes of the face, and the position on them of the first sample.
+    s_vector: Vector3<f32>,
+    t_vector: Vector3<f32>,
+    offset: [f32; 2],
+}
+
+impl LightmapSamples {
+    /// Blend the lightmaps by the values of their styles, and add `lights`, which are relative to
+    /// the model.
+    ///
+    /// The result is RGBA, to match the lightmap texture, and is stored at half brightness as in
+    /// GLQuake, so that light styles and dynamic lights can make faces up to twice as bright as
+    /// their lightmaps.
+    fn compose(&self, lightstyle_values: &[f32], lights: &[LightmapLight]) -> Vec<u8> {
+        let size = (self.width * self.height) as usize;
+        let mut light = vec![[0.0; 3]; size];
+
+        for (style, samples) in self.styles.iter().zip(self.samples.chunks_exact(3 * size)) {
+            let value = lightstyle_values
+                .get(*style as usize)
+                .copied()
+                .unwrap_or(1.0);
+            for (light, sample) in light.iter_mut().zip(samples.chunks_exact(3)) {
+                for (light, sample) in light.iter_mut().zip(sample) {
+                    *light += *sample as f32 / 255.0 * value;
+                }
+            }
+        }
+
+        for dlight in lights {
+            let plane_dist = dlight.origin.dot(self.normal) - self.dist;
+            let radius = dlight.radius - plane_dist.abs();
+            if radius <= 0.0 {
+                continue;
+            }
+
+            // where the light is on the face, in texels
+            let impact = dlight.origin - self.normal * plane_dist;
+            let local_s = impact.dot(self.s_vector) - self.offset[0];
+            let local_t = impact.dot(self.t_vector) - self.offset[1];
+
+            for t in 0..self.height {
+                let td = (local_t - (t * 16) as f32).abs();
+                for s in 0..self.width {
+                    let sd = (local_s - (s * 16) as f32).abs();
+
+                    // the same approximation of the distance as Quake
+                    let dist = if sd > td {
+                        sd + td / 2.0
+                    } else {
+                        td + sd / 2.0
+                    };
+                    if dist < radius {
+                        for light in &mut light[(t * self.width + s) as usize] {
+                            *light += (radius - dist) / 255.0 * dlight.brightness;
+                        }
+                    }
+                }
+            }
+        }
+
+        light
+            .into_iter()
+            .flat_map(|rgb| {
+                let [r, g, b] =
+                    rgb.map(|light| ((light / 2.0).clamp(0.0, 1.0) * 255.0).round() as u8);
+                [r, g, b, 255]
+            })
+            .collect()
+    }
+
+    /// Whether `light` reaches any part of the face.
+    fn touched_by(&self, light: &LightmapLight, min: Vector3<f32>, max: Vector3<f32>) -> bool {
+        let plane_dist = light.origin.dot(self.normal) - self.dist;
+        plane_dist.abs() < light.radius
+            && (0..3).all(|i| {
+                light.origin[i] > min[i] - light.radius && light.origin[i] < max[i] + light.radius
+            })
+    }
+}
+
+/// The lightmap of a face, which is blended from its light styles and lit by dynamic lights on the
+/// CPU as in GLQuake, and uploaded again whenever it changes.
+struct FaceLightmap {
+    rect: AtlasRect,
+    samples: LightmapSamples,
+
+    /// The style values the texture was last blended with, and whether any dynamic lights were
+    /// added to it. `None` until it's first blended.
+    last_update: Mutex<Option<(ArrayVec<f32, 4>, bool)>>,
+}
+
+impl std::fmt::Debug for FaceLightmap {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FaceLightmap")
+            .field("rect", &self.rect)
+            .field("width", &self.samples.width)
+            .field("height", &self.samples.height)
+            .field("styles", &self.samples.styles)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A page of the lightmap atlas, which holds the lightmaps of many faces so that they can be drawn
+/// without switching bind groups.
+struct LightmapPage {
+    texture: Texture,
+    bind_group: BindGroup,
+    width: u32,
+    texels: Mutex<LightmapPageTexels>,
+}
+
+struct LightmapPageTexels {
+    /// RGBA, like the page's texture.
+    texels: Vec<u8>,
+
+    /// The part of the page that's changed since it was last uploaded.
+    dirty: Option<DirtyRect>,
+}
+
+impl LightmapPageTexels {
+    /// Copy a `width` by `height` RGBA lightmap to `(x, y)`.
+    fn write(&mut self, page_width: u32, x: u32, y: u32, width: u32, lightmap: &[u8]) {
+        let row_len = 4 * width as usize;
+        let height = (lightmap.len() / row_len.max(1)) as u32;
+        for (row, texels) in lightmap.chunks_exact(row_len.max(1)).enumerate() {
+            let start = 4 * ((y + row as u32) * page_width + x) as usize;
+            self.texels[start..start + row_len].copy_from_slice(texels);
+        }
+
+        DirtyRect::extend(&mut self.dirty, DirtyRect::new(x, y, width, height));
+    }
+}
+
 struct BrushLeaf {
     facelist_ids: Range<usize>,
+    min: Vector3<f32>,
+    max: Vector3<f32>,
 }
 
 impl<B> std::convert::From<B> for BrushLeaf
@@ -352,10 +659,19 @@ where
         let bsp_leaf = bsp_leaf.borrow();
         BrushLeaf {
             facelist_ids: bsp_leaf.facelist_id..bsp_leaf.facelist_id + bsp_leaf.facelist_count,
+            min: bsp_leaf.min.into(),
+            max: bsp_leaf.max.into(),
         }
     }
 }
 
+/// The leaves visible from the leaf the camera was last in.
+#[derive(Default)]
+struct CachedPvs {
+    leaf_id: Option<usize>,
+    leaves: Vec<usize>,
+}
+
 pub struct BrushRendererBuilder {
     bsp_data: Arc<BspData>,
     face_range: Range<usize>,
@@ -363,14 +679,11 @@ pub struct BrushRendererBuilder {
     leaves: Option<Vec<BrushLeaf>>,
 
     per_texture_bind_groups: Vec<BindGroup>,
-    per_face_bind_groups: Vec<BindGroup>,
 
     vertices: Vec<BrushVertex>,
     faces: Vec<BrushFace>,
     texture_chains: HashMap<usize, Vec<usize>>,
     textures: Vec<BrushTexture>,
-    lightmaps: Vec<Texture>,
-    //lightmap_views: Vec<TextureView>,
 }
 
 impl BrushRendererBuilder {
@@ -389,33 +702,25 @@ impl BrushRendererBuilder {
                 None
             },
             per_texture_bind_groups: Default::default(),
-            per_face_bind_groups: Vec::new(),
             vertices: Vec::new(),
             faces: Vec::new(),
             texture_chains: HashMap::default(),
             textures: Vec::new(),
-            lightmaps: Vec::new(),
-            //lightmap_views: Vec::new(),
         }
     }
 
-    fn create_face(
-        &mut self,
-        state: &GraphicsState,
-        device: &RenderDevice,
-        queue: &RenderQueue,
-        face_id: usize,
-    ) -> BrushFace {
-        let face = &self.bsp_data.faces()[face_id];
-        let face_vert_id = self.vertices.len();
-        let texinfo = &self.bsp_data.texinfo()[face.texinfo_id];
-        let tex = &self.bsp_data.textures()[texinfo.tex_id];
+    /// Build the vertices and lightmaps of a face. This only reads the BSP data, so faces can be
+    /// built in parallel.
+    fn build_face(bsp_data: &BspData, face_id: usize) -> FaceData {
+        let face = &bsp_data.faces()[face_id];
+        let texinfo = &bsp_data.texinfo()[face.texinfo_id];
+        let tex = &bsp_data.textures()[texinfo.tex_id];
+        let mut vertices = Vec::new();
 
         let mut min = Vector3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY);
         let mut max = Vector3::new(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY);
 
-        let no_collinear =
-            math::remove_collinear(self.bsp_data.face_iter_vertices(face_id).collect());
+        let no_collinear = math::remove_collinear(bsp_data.face_iter_vertices(face_id).collect());
 
         for vert in no_collinear.iter() {
             for component in 0..3 {
@@ -432,7 +737,7 @@ impl BrushRendererBuilder {
                 _ => Vector3::zero(),
             };
             for vert in verts.into_iter() {
-                self.vertices.push(BrushVertex {
+                vertices.push(BrushVertex {
                     position: vert.into(),
                     normal: normal.into(),
                     diffuse_texcoord: [
@@ -440,7 +745,6 @@ impl BrushRendererBuilder {
                         ((vert.dot(texinfo.t_vector) + texinfo.t_offset) / tex.height() as f32),
                     ],
                     lightmap_texcoord: calculate_lightmap_texcoords(vert.into(), face, texinfo),
-                    lightmap_anim: face.light_styles,
                 })
             }
         } else {
@@ -466,7 +770,7 @@ impl BrushRendererBuilder {
 
                 // skip collinear points
                 for vert in tri.iter() {
-                    self.vertices.push(BrushVertex {
+                    vertices.push(BrushVertex {
                         position: (*vert).into(),
                         normal: normal.into(),
                         diffuse_texcoord: [
@@ -478,7 +782,6 @@ impl BrushRendererBuilder {
                             face,
                             texinfo,
                         ),
-                        lightmap_anim: face.light_styles,
                     });
                 }
 
@@ -486,42 +789,89 @@ impl BrushRendererBuilder {
             }
         }
 
-        // build the lightmaps
-        let lightmaps = if !texinfo.special {
-            self.bsp_data.face_lightmaps(face_id)
-        } else {
-            Vec::new()
-        };
+        // faces without lightmaps are dark until a dynamic light reaches them
+        let lightmap = (!texinfo.special).then(|| {
+            let width = face.extents[0] as u32 / 16 + 1;
+            let height = face.extents[1] as u32 / 16 + 1;
+            let normal = match &*no_collinear {
+                [a, b, c, ..] => (a - b).cross(c - b).normalize(),
+                _ => Vector3::zero(),
+            };
 
-        let mut lightmap_ids = Vec::new();
-        for lightmap in lightmaps {
-            let lightmap_data = TextureData::Lightmap(LightmapData {
-                lightmap: Cow::borrowed(lightmap.data()),
-            });
+            let (styles, samples) = if bsp_data.lightmaps().is_empty() {
+                // maps compiled without lighting have no light data at all, and are drawn
+                // fullbright with a single white lightmap in the normal style
+                (vec![0], vec![0xFF; 3 * (width * height) as usize])
+            } else {
+                // without a .lit file, the light is white
+                let lightmaps = bsp_data.face_lightmaps(face_id);
+                let samples = lightmaps
+                    .iter()
+                    .flat_map(|l| match l.rgb() {
+                        Some(rgb) => rgb.to_vec(),
+                        None => l.data().iter().flat_map(|&v| [v, v, v]).collect(),
+                    })
+                    .collect();
+                (face.light_styles[..lightmaps.len()].to_vec(), samples)
+            };
 
-            let texture = state.create_texture(
-                device,
-                queue,
-                None,
-                lightmap.width(),
-                lightmap.height(),
-                &lightmap_data,
-            );
+            LightmapSamples {
+                width,
+                height,
+                styles,
+                samples,
+                normal,
+                dist: no_collinear.first().map_or(0.0, |v| v.dot(normal)),
+                s_vector: texinfo.s_vector,
+                t_vector: texinfo.t_vector,
+                offset: [
+                    face.texture_mins[0] as f32 - texinfo.s_offset,
+                    face.texture_mins[1] as f32 - texinfo.t_offset,
+                ],
+            }
+        });
 
-            let id = self.lightmaps.len();
-            self.lightmaps.push(texture);
-            //self.lightmap_views
-            //.push(self.lightmaps[id].create_view(&Default::default()));
-            lightmap_ids.push(id);
+        FaceData {
+            vertices,
+            min,
+            max,
+            texture_id: texinfo.tex_id as usize,
+            lightmap,
         }
+    }
+
+    /// Give a built face its place in the vertex buffer, and move its lightmap texcoords to its
+    /// place in the atlas. `page_sizes` are the sizes of the atlas pages.
+    fn push_face(
+        &mut self,
+        data: FaceData,
+        rect: Option<AtlasRect>,
+        page_sizes: &[(u32, u32)],
+    ) -> BrushFace {
+        let face_vert_id = self.vertices.len();
+        self.vertices
+            .extend(data.vertices.into_iter().map(|mut vertex| {
+                if let Some(rect) = rect {
+                    let (width, height) = page_sizes[rect.page];
+                    let [s, t] = vertex.lightmap_texcoord;
+                    vertex.lightmap_texcoord = [
+                        (rect.x as f32 + s) / width as f32,
+                        (rect.y as f32 + t) / height as f32,
+                    ];
+                }
+                vertex
+            }));
 
         BrushFace {
             vertices: face_vert_id as u32..self.vertices.len() as u32,
-            _min: min,
-            _max: min,
-            texture_id: texinfo.tex_id as usize,
-            lightmap_ids,
-            _light_styles: face.light_styles,
+            min: data.min,
+            max: data.max,
+            texture_id: data.texture_id,
+            lightmap: data.lightmap.zip(rect).map(|(samples, rect)| FaceLightmap {
+                rect,
+                samples,
+                last_update: Mutex::new(None),
+            }),
             draw_flag: true.into(),
         }
     }
@@ -532,42 +882,19 @@ impl BrushRendererBuilder {
         device: &RenderDevice,
         tex: &BrushTextureFrame,
     ) -> BindGroup {
-        let layout = &state
-            .brush_pipeline()
-            .bind_group_layout(BindGroupLayoutId::PerTexture);
-        device.create_bind_group(
-            Some("per-texture bind group"),
-            layout,
-            &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&tex.diffuse.default_view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::TextureView(&tex.fullbright.default_view),
-                },
-            ],
+        per_texture_bind_group(
+            state,
+            device,
+            &tex.diffuse.default_view,
+            &tex.fullbright.default_view,
         )
     }
 
     fn create_per_face_bind_group(
-        &self,
         state: &GraphicsState,
         device: &RenderDevice,
-        face_id: usize,
+        lightmap: &Texture,
     ) -> BindGroup {
-        let mut lightmap_views: Vec<_> = self.faces[face_id]
-            .lightmap_ids
-            .iter()
-            .map(|id| self.lightmaps[*id].create_view(&Default::default()))
-            .collect();
-        lightmap_views.resize_with(4, || {
-            state.default_lightmap().create_view(&Default::default())
-        });
-
-        let lightmap_view_refs = lightmap_views.iter().map(|t| &**t).collect::<Vec<_>>();
-
         let layout = &state
             .brush_pipeline()
             .bind_group_layout(BindGroupLayoutId::PerFace);
@@ -576,17 +903,18 @@ impl BrushRendererBuilder {
             layout,
             &[wgpu::BindGroupEntry {
                 binding: 0,
-                resource: wgpu::BindingResource::TextureViewArray(&lightmap_view_refs[..]),
+                resource: wgpu::BindingResource::TextureView(
+                    &lightmap.create_view(&Default::default()),
+                ),
             }],
         )
     }
 
     fn create_brush_texture_frame<S>(
-        &mut self,
         state: &GraphicsState,
         device: &RenderDevice,
         queue: &RenderQueue,
-        mipmap: &[u8],
+        frame: &BspTextureFrame,
         width: u32,
         height: u32,
         name: S,
@@ -596,23 +924,57 @@ impl BrushRendererBuilder {
     {
         let name = name.as_ref();
 
-        let (diffuse_data, fullbright_data) = state.palette().translate(mipmap);
-        let diffuse = state.create_texture(
-            device,
-            queue,
-            None,
-            width,
-            height,
-            &TextureData::Diffuse(diffuse_data),
-        );
-        let fullbright = state.create_texture(
-            device,
-            queue,
-            None,
-            width,
-            height,
-            &TextureData::Fullbright(fullbright_data),
-        );
+        let external = state
+            .external_textures()
+            .and_then(|textures| textures.load(frame.name()));
+        let (diffuse, fullbright) = match external {
+            Some(ExternalTexture {
+                width,
+                height,
+                rgba,
+                fullbright,
+            }) => {
+                let diffuse = state.create_diffuse_texture(
+                    device,
+                    queue,
+                    None,
+                    width,
+                    height,
+                    DiffuseData {
+                        rgba: Cow::owned(rgba),
+                    },
+                );
+                let (fullbright_width, fullbright_height, mask) =
+                    fullbright.unwrap_or((1, 1, vec![0]));
+                let fullbright = state.create_texture(
+                    device,
+                    queue,
+                    None,
+                    fullbright_width,
+                    fullbright_height,
+                    &TextureData::Fullbright(FullbrightData {
+                        fullbright: Cow::owned(mask),
+                    }),
+                );
+                (diffuse, fullbright)
+            }
+
+            None => {
+                let mipmap = frame.mipmap(BspTextureMipmap::Full);
+                let (diffuse_data, fullbright_data) = state.palette().translate(mipmap);
+                let diffuse =
+                    state.create_diffuse_texture(device, queue, None, width, height, diffuse_data);
+                let fullbright = state.create_texture(
+                    device,
+                    queue,
+                    None,
+                    width,
+                    height,
+                    &TextureData::Fullbright(fullbright_data),
+                );
+                (diffuse, fullbright)
+            }
+        };
 
         let kind = if name.starts_with("sky") {
             TextureKind::Sky
@@ -625,7 +987,8 @@ impl BrushRendererBuilder {
         let diffuse_view = diffuse.create_view(&default());
         let fullbright_view = fullbright.create_view(&default());
 
-        let mut frame = BrushTextureFrame {
+        // the bind group is created once the frame's place in the model is known
+        BrushTextureFrame {
             bind_group_id: 0,
             diffuse: CachedTexture {
                 texture: diffuse,
@@ -636,19 +999,10 @@ impl BrushRendererBuilder {
                 default_view: fullbright_view,
             },
             kind,
-        };
-
-        // generate texture bind group
-        let per_texture_bind_group = self.create_per_texture_bind_group(state, device, &frame);
-        let bind_group_id = self.per_texture_bind_groups.len();
-        self.per_texture_bind_groups.push(per_texture_bind_group);
-
-        frame.bind_group_id = bind_group_id;
-        frame
+        }
     }
 
     pub fn create_brush_texture(
-        &mut self,
         state: &GraphicsState,
         device: &RenderDevice,
         queue: &RenderQueue,
@@ -663,11 +1017,11 @@ impl BrushRendererBuilder {
                 let primary_frames: Vec<_> = primary
                     .iter()
                     .map(|f| {
-                        self.create_brush_texture_frame(
+                        Self::create_brush_texture_frame(
                             state,
                             device,
                             queue,
-                            f.mipmap(BspTextureMipmap::Full),
+                            f,
                             width,
                             height,
                             tex.name(),
@@ -678,11 +1032,11 @@ impl BrushRendererBuilder {
                 let alternate_frames: Option<Vec<_>> = alternate.as_ref().map(|a| {
                     a.iter()
                         .map(|f| {
-                            self.create_brush_texture_frame(
+                            Self::create_brush_texture_frame(
                                 state,
                                 device,
                                 queue,
-                                f.mipmap(BspTextureMipmap::Full),
+                                f,
                                 width,
                                 height,
                                 tex.name(),
@@ -698,11 +1052,11 @@ impl BrushRendererBuilder {
             }
 
             BspTextureKind::Static(bsp_tex) => {
-                BrushTexture::Static(self.create_brush_texture_frame(
+                BrushTexture::Static(Self::create_brush_texture_frame(
                     state,
                     device,
                     queue,
-                    bsp_tex.mipmap(BspTextureMipmap::Full),
+                    bsp_tex,
                     tex.width(),
                     tex.height(),
                     tex.name(),
@@ -717,18 +1071,68 @@ impl BrushRendererBuilder {
         device: &RenderDevice,
         queue: &RenderQueue,
     ) -> Result<BrushRenderer, Error> {
-        // create the diffuse and fullbright textures
-        for tex in self.bsp_data.clone().textures().iter() {
-            let tex = self.create_brush_texture(state, device, queue, tex);
+        profile_span!("brush_renderer_build");
+        let pool = ComputeTaskPool::get();
+        let bsp_data = self.bsp_data.clone();
+
+        // decode and upload the diffuse and fullbright textures
+        let textures = pool.scope(|scope| {
+            for tex in bsp_data.textures().iter() {
+                scope.spawn(async move { Self::create_brush_texture(state, device, queue, tex) });
+            }
+        });
+
+        for mut tex in textures {
+            for frame in tex.frames_mut() {
+                frame.bind_group_id = self.per_texture_bind_groups.len();
+                let per_texture_bind_group =
+                    self.create_per_texture_bind_group(state, device, frame);
+                self.per_texture_bind_groups.push(per_texture_bind_group);
+            }
+
             self.textures.push(tex);
         }
 
-        // generate faces, vertices and lightmaps
-        // bsp_face_id is the id of the face in the bsp data
-        // face_id is the new id of the face in the renderer
-        for bsp_face_id in self.face_range.start..self.face_range.end {
+        // generate vertices and lightmaps, a batch of faces per task
+        let face_range = self.face_range.clone();
+        let face_data = pool.scope(|scope| {
+            for start in face_range.clone().step_by(FACES_PER_TASK) {
+                let end = (start + FACES_PER_TASK).min(face_range.end);
+                let bsp_data = &bsp_data;
+                scope.spawn(async move {
+                    (start..end)
+                        .map(|face_id| Self::build_face(bsp_data, face_id))
+                        .collect::<Vec<_>>()
+                });
+            }
+        });
+        let face_data: Vec<_> = face_data.into_iter().flatten().collect();
+
+        // pack the lightmaps into pages, which are only as tall as they need to be so that small
+        // models don't take up a whole page
+        let mut atlas = AtlasAllocator::new(LIGHTMAP_PAGE_SIZE, LIGHTMAP_PAGE_SIZE);
+        let rects: Vec<_> = face_data
+            .iter()
+            .map(|data| {
+                let lightmap = data.lightmap.as_ref()?;
+                let rect = atlas.allocate(lightmap.width, lightmap.height);
+                if rect.is_none() {
+                    warn!(
+                        "{}x{} lightmap is too big for the atlas",
+                        lightmap.width, lightmap.height
+                    );
+                }
+                rect
+            })
+            .collect();
+        let page_sizes: Vec<_> = (0..atlas.page_count())
+            .map(|page| (LIGHTMAP_PAGE_SIZE, atlas.used_height(page).max(1)))
+            .collect();
+
+        // face_id is the id of the face in the renderer, not in the bsp data
+        for (data, rect) in face_data.into_iter().zip(rects) {
             let face_id = self.faces.len();
-            let face = self.create_face(state, device, queue, bsp_face_id);
+            let face = self.push_face(data, rect, &page_sizes);
             self.faces.push(face);
 
             let face_tex_id = self.faces[face_id].texture_id;
@@ -737,28 +1141,70 @@ impl BrushRendererBuilder {
                 .entry(face_tex_id)
                 .or_insert(Vec::new())
                 .push(face_id);
+        }
 
-            // generate face bind group
-            let per_face_bind_group = self.create_per_face_bind_group(state, device, face_id);
-            self.per_face_bind_groups.push(per_face_bind_group);
+        // faces on the same page are drawn one after another, without switching bind groups
+        let faces = &self.faces;
+        for face_ids in self.texture_chains.values_mut() {
+            face_ids.sort_by_key(|face_id| faces[*face_id].lightmap.as_ref().map(|l| l.rect.page));
         }
 
+        // the lightmaps are blended before they're first drawn
+        let lightmap_pages = page_sizes
+            .iter()
+            .map(|&(width, height)| {
+                let texture = state.create_texture(
+                    device,
+                    queue,
+                    Some("lightmap page"),
+                    width,
+                    height,
+                    &TextureData::Lightmap(LightmapData {
+                        lightmap: Cow::owned(vec![0; 4 * (width * height) as usize]),
+                    }),
+                );
+                LightmapPage {
+                    bind_group: Self::create_per_face_bind_group(state, device, &texture),
+                    texture,
+                    width,
+                    texels: Mutex::new(LightmapPageTexels {
+                        texels: vec![0; 4 * (width * height) as usize],
+                        dirty: None,
+                    }),
+                }
+            })
+            .collect();
+        let default_lightmap_bind_group =
+            Self::create_per_face_bind_group(state, device, state.default_lightmap());
+
         let vertex_buffer = device.create_buffer_with_data(&wgpu::util::BufferInitDescriptor {
             label: None,
             contents: unsafe { any_slice_as_bytes(self.vertices.as_slice()) },
             usage: wgpu::BufferUsages::VERTEX,
         });
 
+        let mut min = Vector3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY);
+        let mut max = Vector3::new(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY);
+        for face in self.faces.iter() {
+            for component in 0..3 {
+                min[component] = min[component].min(face.min[component]);
+                max[component] = max[component].max(face.max[component]);
+            }
+        }
+
         Ok(BrushRenderer {
             bsp_data: self.bsp_data,
+            min,
+            max,
             vertex_buffer,
             leaves: self.leaves,
+            pvs: Default::default(),
             per_texture_bind_groups: self.per_texture_bind_groups,
-            per_face_bind_groups: self.per_face_bind_groups,
+            lightmap_pages,
+            default_lightmap_bind_group,
             texture_chains: self.texture_chains,
             faces: self.faces,
             textures: self.textures,
-            _lightmaps: self.lightmaps,
         })
     }
 }
@@ -767,22 +1213,115 @@ impl BrushRendererBuilder {
 pub struct BrushRenderer {
     bsp_data: Arc<BspData>,
 
+    // the bounds of all of the faces, relative to the model
+    min: Vector3<f32>,
+    max: Vector3<f32>,
+
     leaves: Option<Vec<BrushLeaf>>,
 
+    // decompressing the PVS every frame is wasted work while the camera stays in one leaf
+    pvs: Mutex<CachedPvs>,
+
     vertex_buffer: Buffer,
     per_texture_bind_groups: Vec<BindGroup>,
-    per_face_bind_groups: Vec<BindGroup>,
+    lightmap_pages: Vec<LightmapPage>,
+    // for faces without lightmaps
+    default_lightmap_bind_group: BindGroup,
 
     // faces are grouped by texture to reduce the number of texture rebinds
     // texture_chains maps texture ids to face ids
     texture_chains: HashMap<usize, Vec<usize>>,
     faces: Vec<BrushFace>,
     textures: Vec<BrushTexture>,
-    _lightmaps: Vec<Texture>,
 }
 
 impl BrushRenderer {
+    /// The minimum extent of the model's faces, relative to its origin.
+    pub fn min(&self) -> Vector3<f32> {
+        self.min
+    }
+
+    /// The maximum extent of the model's faces, relative to its origin.
+    pub fn max(&self) -> Vector3<f32> {
+        self.max
+    }
+
+    /// Blend the lightmaps of the faces whose light styles have changed, and add the dynamic
+    /// lights, uploading the lightmaps that are different from last frame. `lights` are relative to
+    /// the model.
+    pub fn update_lightmaps(
+        &self,
+        queue: &RenderQueue,
+        lightstyle_values: &[f32],
+        lights: &[LightmapLight],
+    ) {
+        let mut face_lights = Vec::new();
+        for face in &self.faces {
+            let Some(lightmap) = &face.lightmap else {
+                continue;
+            };
+
+            face_lights.clear();
+            face_lights.extend(
+                lights
+                    .iter()
+                    .filter(|l| lightmap.samples.touched_by(l, face.min, face.max)),
+            );
+
+            let styles = lightmap
+                .samples
+                .styles
+                .iter()
+                .map(|style| {
+                    lightstyle_values
+                        .get(*style as usize)
+                        .copied()
+                        .unwrap_or(1.0)
+                })
+                .collect::<ArrayVec<_, 4>>();
+            let dlit = !face_lights.is_empty();
+
+            // dynamic lights move, so a face that was lit by one last frame is redrawn either way
+            let mut last_update = lightmap.last_update.lock();
+            if let Some((last_styles, last_dlit)) = &*last_update {
+                if *last_styles == styles && !last_dlit && !dlit {
+                    continue;
+                }
+            }
+
+            let page = &self.lightmap_pages[lightmap.rect.page];
+            page.texels.lock().write(
+                page.width,
+                lightmap.rect.x,
+                lightmap.rect.y,
+                lightmap.samples.width,
+                &lightmap.samples.compose(lightstyle_values, &face_lights),
+            );
+            *last_update = Some((styles, dlit));
+        }
+
+        // upload the part of each page that changed
+        for page in &self.lightmap_pages {
+            let mut texels = page.texels.lock();
+            let Some(rect) = texels.dirty.take() else {
+                continue;
+            };
+
+            write_texture_region(
+                queue,
+                &page.texture,
+                page.width,
+                &TextureData::Lightmap(LightmapData {
+                    lightmap: Cow::borrowed(&texels.texels),
+                }),
+                rect,
+            );
+        }
+    }
+
     /// Record the draw commands for this brush model to the given `wgpu::RenderPass`.
+    ///
+    /// If `translucent_liquids` is set, liquids are left to `record_translucent_draw`.
     pub fn record_draw<'a>(
         &'a self,
         state: &'a GraphicsState,
@@ -791,81 +1330,245 @@ impl BrushRenderer {
         time: Duration,
         camera: &Camera,
         frame_id: usize,
+        translucent_liquids: bool,
+        cull_stats: &mut CullStats,
     ) {
         pass.set_render_pipeline(state.brush_pipeline().pipeline());
         pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
 
         // if this is a worldmodel, mark faces to be drawn
         if let Some(ref leaves) = self.leaves {
-            let pvs = self
-                .bsp_data
-                .get_pvs(self.bsp_data.find_leaf(camera.origin), leaves.len());
+            let camera_leaf_id = self.bsp_data.find_leaf(camera.origin);
+            let mut pvs = self.pvs.lock();
+            if pvs.leaf_id != Some(camera_leaf_id) {
+                pvs.leaf_id = Some(camera_leaf_id);
+                pvs.leaves = self.bsp_data.get_pvs(camera_leaf_id, leaves.len());
+            }
 
-            // only draw faces in pvs
-            for leaf_id in pvs {
-                for facelist_id in leaves[leaf_id].facelist_ids.clone() {
-                    let face = &self.faces[self.bsp_data.facelist()[facelist_id]];
+            // only draw faces in pvs and in view
+            for &leaf_id in pvs.leaves.iter() {
+                let leaf = &leaves[leaf_id];
+                if camera.cull_box(leaf.min, leaf.max) {
+                    cull_stats.leaves_culled += 1;
+                    continue;
+                }
+                cull_stats.leaves_drawn += 1;
 
-                    // TODO: frustum culling
+                for facelist_id in leaf.facelist_ids.clone() {
+                    let face = &self.faces[self.bsp_data.facelist()[facelist_id]];
                     face.draw_flag.store(true, Ordering::SeqCst);
                 }
             }
         }
 
         for (tex_id, face_ids) in self.texture_chains.iter() {
-            use PushConstantUpdate::*;
-            BrushPipeline::set_push_constants(
-                pass,
-                Retain,
-                Update(bump.alloc(SharedPushConstants {
-                    texture_kind: self.textures[*tex_id].kind() as u32,
-                })),
-                Retain,
-            );
+            // the faces stay marked until the translucent chains are drawn
+            if translucent_liquids && self.is_liquid(*tex_id) {
+                continue;
+            }
 
-            let bind_group_id = match &self.textures[*tex_id] {
-                BrushTexture::Static(ref frame) => frame.bind_group_id,
-                BrushTexture::Animated { primary, alternate } => {
-                    // if frame is not zero and this texture has an alternate
-                    // animation, use it
-                    let anim = if frame_id == 0 {
-                        primary
-                    } else if let Some(a) = alternate {
-                        a
-                    } else {
-                        primary
-                    };
+            self.record_chain_draw(state, pass, bump, time, frame_id, *tex_id, face_ids);
+        }
+    }
 
-                    let time_ms = time.num_milliseconds();
-                    let total_ms = (bsp::frame_duration() * anim.len() as i32).num_milliseconds();
-                    let anim_ms = if total_ms == 0 { 0 } else { time_ms % total_ms };
-                    anim[(anim_ms / bsp::frame_duration().num_milliseconds()) as usize]
-                        .bind_group_id
-                }
-            };
+    /// The liquid texture chains with faces to draw, and the distance from `origin` to the nearest
+    /// of their faces. `origin` is relative to the model.
+    ///
+    /// These are drawn with `record_translucent_draw`, after `record_draw` has marked the visible
+    /// faces.
+    pub fn translucent_chains(
+        &self,
+        origin: Vector3<f32>,
+    ) -> impl Iterator<Item = (usize, f32)> + '_ {
+        self.texture_chains
+            .iter()
+            .filter(|(tex_id, _)| self.is_liquid(**tex_id))
+            .filter_map(move |(tex_id, face_ids)| {
+                face_ids
+                    .iter()
+                    .map(|face_id| &self.faces[*face_id])
+                    .filter(|face| self.leaves.is_none() || face.draw_flag.load(Ordering::SeqCst))
+                    .map(|face| ((face.min + face.max) / 2.0 - origin).magnitude())
+                    .min_by(f32::total_cmp)
+                    .map(|distance| (*tex_id, distance))
+            })
+    }
+
+    /// Record the draw commands for one of the chains from `translucent_chains`, which are blended
+    /// by the pass's blend constant.
+    pub fn record_translucent_draw<'a>(
+        &'a self,
+        state: &'a GraphicsState,
+        pass: &mut TrackedRenderPass<'a>,
+        bump: &'a Bump,
+        time: Duration,
+        frame_id: usize,
+        tex_id: usize,
+    ) {
+        let Some(face_ids) = self.texture_chains.get(&tex_id) else {
+            return;
+        };
+
+        pass.set_render_pipeline(state.brush_pipeline().translucent_pipeline());
+        pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        self.record_chain_draw(state, pass, bump, time, frame_id, tex_id, face_ids);
+    }
+
+    fn is_liquid(&self, tex_id: usize) -> bool {
+        matches!(self.textures[tex_id].kind(), TextureKind::Warp)
+    }
+
+    fn record_chain_draw<'a>(
+        &'a self,
+        state: &'a GraphicsState,
+        pass: &mut TrackedRenderPass<'a>,
+        bump: &'a Bump,
+        time: Duration,
+        frame_id: usize,
+        tex_id: usize,
+        face_ids: &[usize],
+    ) {
+        let kind = self.textures[tex_id].kind();
+        let skybox = match kind {
+            TextureKind::Sky => state.skybox(),
+            _ => None,
+        };
+
+        use PushConstantUpdate::*;
+        BrushPipeline::set_push_constants(
+            pass,
+            Retain,
+            Update(bump.alloc(SharedPushConstants {
+                texture_kind: match skybox {
+                    Some(_) => TextureKind::Skybox,
+                    None => kind,
+                } as u32,
+            })),
+            Retain,
+        );
+
+        let bind_group_id = match &self.textures[tex_id] {
+            BrushTexture::Static(ref frame) => frame.bind_group_id,
+            BrushTexture::Animated { primary, alternate } => {
+                // if frame is not zero and this texture has an alternate
+                // animation, use it
+                let anim = if frame_id == 0 {
+                    primary
+                } else if let Some(a) = alternate {
+                    a
+                } else {
+                    primary
+                };
+
+                let time_ms = time.num_milliseconds();
+                let total_ms = (bsp::frame_duration() * anim.len() as i32).num_milliseconds();
+                let anim_ms = if total_ms == 0 { 0 } else { time_ms % total_ms };
+                anim[(anim_ms / bsp::frame_duration().num_milliseconds()) as usize].bind_group_id
+            }
+        };
+
+        pass.set_bind_group(
+            BindGroupLayoutId::PerTexture as usize,
+            match skybox {
+                Some(skybox) => skybox.bind_group(),
+                None => &self.per_texture_bind_groups[bind_group_id],
+            },
+            &[],
+        );
+
+        for face_id in face_ids.iter() {
+            let face = &self.faces[*face_id];
+
+            // only skip the face if we have visibility data but it's not marked
+            if self.leaves.is_some() && !face.draw_flag.swap(false, Ordering::SeqCst) {
+                continue;
+            }
 
+            // the pass skips this if the face is on the same page as the last one
             pass.set_bind_group(
-                BindGroupLayoutId::PerTexture as usize,
-                &self.per_texture_bind_groups[bind_group_id],
+                BindGroupLayoutId::PerFace as usize,
+                match &face.lightmap {
+                    Some(lightmap) => &self.lightmap_pages[lightmap.rect.page].bind_group,
+                    None => &self.default_lightmap_bind_group,
+                },
                 &[],
             );
 
-            for face_id in face_ids.iter() {
-                let face = &self.faces[*face_id];
+            pass.draw(face.vertices.clone(), 0..1);
+            stats::count_draw(Counter::BrushPolys, (face.vertices.len() / 3) as u64);
+        }
+    }
+}
 
-                // only skip the face if we have visibility data but it's not marked
-                if self.leaves.is_some() && !face.draw_flag.swap(false, Ordering::SeqCst) {
-                    continue;
-                }
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn samples(styles: Vec<u8>, samples: Vec<u8>) -> LightmapSamples {
+        LightmapSamples {
+            width: 2,
+            height: 1,
+            styles,
+            samples,
+            normal: Vector3::unit_z(),
+            dist: 0.0,
+            s_vector: Vector3::unit_x(),
+            t_vector: Vector3::unit_y(),
+            offset: [0.0, 0.0],
+        }
+    }
 
-                pass.set_bind_group(
-                    BindGroupLayoutId::PerFace as usize,
-                    &self.per_face_bind_groups[*face_id],
-                    &[],
-                );
+    #[test]
+    fn test_compose_styles() {
+        let lightmap = samples(
+            vec![0, 1],
+            vec![255, 255, 255, 100, 100, 100, 0, 0, 0, 100, 100, 100],
+        );
 
-                pass.draw(face.vertices.clone(), 0..1);
-            }
-        }
+        // stored at half brightness
+        assert_eq!(
+            lightmap.compose(&[1.0, 1.0], &[]),
+            vec![128, 128, 128, 255, 100, 100, 100, 255]
+        );
+        assert_eq!(
+            lightmap.compose(&[1.0, 0.0], &[]),
+            vec![128, 128, 128, 255, 50, 50, 50, 255]
+        );
+        assert_eq!(
+            lightmap.compose(&[2.0, 2.0], &[]),
+            vec![255, 255, 255, 255, 200, 200, 200, 255]
+        );
+    }
+
+    #[test]
+    fn test_compose_colored() {
+        let lightmap = samples(vec![0], vec![200, 100, 0, 0, 50, 100]);
+
+        assert_eq!(
+            lightmap.compose(&[1.0], &[]),
+            vec![100, 50, 0, 255, 0, 25, 50, 255]
+        );
+    }
+
+    #[test]
+    fn test_compose_lights() {
+        let lightmap = samples(vec![], vec![]);
+        let light = LightmapLight {
+            origin: Vector3::new(0.0, 0.0, 10.0),
+            radius: 30.0,
+            brightness: 1.0,
+        };
+
+        // 20 units of light at the first sample, and 4 at the second, 16 units away
+        assert_eq!(
+            lightmap.compose(&[], &[light]),
+            vec![10, 10, 10, 255, 2, 2, 2, 255]
+        );
+        assert!(lightmap.touched_by(&light, Vector3::zero(), Vector3::new(16.0, 0.0, 0.0)));
+        assert!(!lightmap.touched_by(
+            &light,
+            Vector3::new(100.0, 0.0, 0.0),
+            Vector3::new(116.0, 0.0, 0.0)
+        ));
     }
 }
@@ -9,28 +9,44 @@ use bevy::{
         render_graph::{RenderLabel, ViewNode},
         render_phase::TrackedRenderPass,
         render_resource::{
-            BindGroup, BindGroupLayout, BindGroupLayoutEntry, Buffer, RenderPipeline, TextureView,
+            BindGroup, BindGroupLayout, BindGroupLayoutEntry, BindGroupLayoutId, Buffer,
+            RenderPipeline, TextureView, TextureViewId,
         },
         renderer::{RenderDevice, RenderQueue},
         texture::{CachedTexture, ColorAttachment},
         view::{PostProcessWrite, ViewTarget},
     },
 };
-use cgmath::{Deg, Matrix4, SquareMatrix as _, Vector3};
+use cgmath::{Matrix4, SquareMatrix as _, Vector3};
+use hashbrown::HashMap;
+use parking_lot::Mutex;
 
 use crate::client::{
-    entity::MAX_LIGHTS,
+    entity::{particle::Particle, MAX_LIGHTS},
     render::{
-        pipeline::Pipeline, ui::quad::QuadPipeline, GraphicsState, RenderConnectionKind,
-        RenderResolution, RenderState, RenderVars,
+        pipeline::{Pipeline, ShaderCompiler},
+        stats::{self, Counter},
+        ui::quad::QuadPipeline,
+        world::particle::{ParticleBlend, SoftParticleUniforms},
+        GraphicsState, RenderResolution, RenderState, RenderVars,
     },
+    view::Fov,
 };
 
+// Bevy's physically-based renderer assumes lighting in lumens, so we multiply the lighting by a "fudge factor"
+// which adapts Quake's more-direct 0..1 lighting levels to something which more-closely matches the expected
+// lighting level. This is calibrated assuming "indoor" lighting levels, as Quake's environments are mostly
+// indoor and so that seems to make most physical sense.
+pub const EXPOSURE_MULTIPLIER: f32 = 200.;
+
 #[repr(C)]
 #[derive(Clone, Copy, Debug, bytemuck::Zeroable, bytemuck::Pod)]
 pub struct PointLight {
     pub origin: [f32; 3],
     pub radius: f32,
+    /// Linear RGB.
+    pub color: [f32; 3],
+    pub _pad: f32,
 }
 
 #[repr(C)]
@@ -39,20 +55,30 @@ pub struct DeferredUniforms {
     pub inv_projection: [[f32; 4]; 4],
     pub light_count: u32,
     pub exposure: f32,
-    pub _pad: [u32; 2],
+    /// The fraction of the input textures that the initial pass drew to, from `r_scale`.
+    pub scale: [f32; 2],
     pub lights: [PointLight; MAX_LIGHTS],
 }
 
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, bytemuck::Zeroable, bytemuck::Pod)]
+pub struct FogUniforms {
+    pub color: [f32; 3],
+    /// 0 for no fog.
+    pub density: f32,
+}
+
 pub struct DeferredPipeline {
     pipeline: RenderPipeline,
     bind_group_layouts: Vec<BindGroupLayout>,
     uniform_buffer: Buffer,
+    fog_buffer: Buffer,
 }
 
 impl DeferredPipeline {
     pub fn new(
         device: &RenderDevice,
-        compiler: &mut shaderc::Compiler,
+        compiler: &mut ShaderCompiler,
         format: wgpu::TextureFormat,
         sample_count: u32,
     ) -> DeferredPipeline {
@@ -65,26 +91,35 @@ impl DeferredPipeline {
                 inv_projection: Matrix4::identity().into(),
                 light_count: 0,
                 exposure: 0.,
-                _pad: default(),
+                scale: [1.0; 2],
                 lights: [PointLight {
                     origin: [0.; 3],
                     radius: 0.0,
+                    color: [0.; 3],
+                    _pad: 0.0,
                 }; MAX_LIGHTS],
             }]),
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
 
+        let fog_buffer = device.create_buffer_with_data(&wgpu::util::BufferInitDescriptor {
+            label: Some("fog uniforms"),
+            contents: bytemuck::cast_slice(&[FogUniforms::default()]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
         DeferredPipeline {
             pipeline,
             bind_group_layouts,
             uniform_buffer,
+            fog_buffer,
         }
     }
 
     pub fn rebuild(
         &mut self,
         device: &RenderDevice,
-        compiler: &mut shaderc::Compiler,
+        compiler: &mut ShaderCompiler,
         format: wgpu::TextureFormat,
         sample_count: u32,
     ) {
@@ -104,6 +139,10 @@ impl DeferredPipeline {
     pub fn uniform_buffer(&self) -> &wgpu::Buffer {
         &self.uniform_buffer
     }
+
+    pub fn fog_buffer(&self) -> &wgpu::Buffer {
+        &self.fog_buffer
+    }
 }
 
 const BIND_GROUP_LAYOUT_ENTRIES: &[wgpu::BindGroupLayoutEntry] = &[
@@ -164,6 +203,17 @@ const BIND_GROUP_LAYOUT_ENTRIES: &[wgpu::BindGroupLayoutEntry] = &[
         },
         count: None,
     },
+    // fog
+    wgpu::BindGroupLayoutEntry {
+        binding: 6,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: NonZeroU64::new(size_of::<FogUniforms>() as u64),
+        },
+        count: None,
+    },
 ];
 
 impl Pipeline for DeferredPipeline {
@@ -212,13 +262,17 @@ impl Pipeline for DeferredPipeline {
     }
 
     fn vertex_buffer_layouts() -> Vec<wgpu::VertexBufferLayout<'static>> {
-        QuadPipeline::vertex_buffer_layouts()
+        vec![QuadPipeline::vertex_layout()]
     }
 }
 
-#[derive(Resource)]
+#[derive(Resource, Clone)]
 pub struct DeferredRenderer {
     bind_group: BindGroup,
+
+    /// The bind group for drawing enhanced particles over the output, which reads the same depth
+    /// buffer.
+    particle_bind_group: BindGroup,
 }
 
 impl DeferredRenderer {
@@ -267,6 +321,15 @@ impl DeferredRenderer {
                         size: None,
                     }),
                 },
+                // fog
+                wgpu::BindGroupEntry {
+                    binding: 6,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: state.deferred_pipeline().fog_buffer(),
+                        offset: 0,
+                        size: None,
+                    }),
+                },
             ],
         )
     }
@@ -280,8 +343,15 @@ impl DeferredRenderer {
     ) -> DeferredRenderer {
         let bind_group =
             Self::create_bind_group(state, device, diffuse_buffer, normal_buffer, depth_buffer);
-
-        DeferredRenderer { bind_group }
+        let particle_bind_group = state
+            .particle_pipeline()
+            .soft()
+            .create_bind_group(device, depth_buffer);
+
+        DeferredRenderer {
+            bind_group,
+            particle_bind_group,
+        }
     }
 
     pub fn rebuild(
@@ -294,6 +364,10 @@ impl DeferredRenderer {
     ) {
         self.bind_group =
             Self::create_bind_group(state, device, diffuse_buffer, normal_buffer, depth_buffer);
+        self.particle_bind_group = state
+            .particle_pipeline()
+            .soft()
+            .create_bind_group(device, depth_buffer);
     }
 
     pub fn update_uniform_buffers(
@@ -301,6 +375,7 @@ impl DeferredRenderer {
         state: &GraphicsState,
         queue: &RenderQueue,
         uniforms: DeferredUniforms,
+        fog: FogUniforms,
     ) {
         // update color shift
         queue.write_buffer(
@@ -308,6 +383,11 @@ impl DeferredRenderer {
             0,
             bytemuck::cast_slice(slice::from_ref(&uniforms)),
         );
+        queue.write_buffer(
+            state.deferred_pipeline().fog_buffer(),
+            0,
+            bytemuck::cast_slice(slice::from_ref(&fog)),
+        );
     }
 
     pub fn record_draw<'this, 'a>(
@@ -316,12 +396,89 @@ impl DeferredRenderer {
         queue: &'a RenderQueue,
         pass: &'a mut TrackedRenderPass<'this>,
         uniforms: DeferredUniforms,
+        fog: FogUniforms,
     ) {
-        self.update_uniform_buffers(state, queue, uniforms);
+        self.update_uniform_buffers(state, queue, uniforms, fog);
         pass.set_render_pipeline(state.deferred_pipeline().pipeline());
         pass.set_vertex_buffer(0, state.quad_pipeline().vertex_buffer().slice(..));
         pass.set_bind_group(0, &self.bind_group, &[]);
         pass.draw(0..6, 0..1);
+        stats::add(Counter::DrawCalls, 1);
+    }
+
+    /// Draw enhanced particles over the lit world.
+    pub fn record_particle_draw<'this, 'a>(
+        &'this self,
+        state: &'this GraphicsState,
+        queue: &'a RenderQueue,
+        pass: &'a mut TrackedRenderPass<'this>,
+        blend: ParticleBlend,
+        uniforms: SoftParticleUniforms,
+        particles: impl Iterator<Item = &'a Particle>,
+    ) {
+        let particle_pipeline = state.particle_pipeline();
+        particle_pipeline.soft().record_draw(
+            queue,
+            pass,
+            particle_pipeline.vertex_buffer(),
+            &self.particle_bind_group,
+            blend,
+            uniforms,
+            particles,
+        );
+    }
+}
+
+/// The resources a `DeferredRenderer`'s bind group refers to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct DeferredInputs {
+    layout: BindGroupLayoutId,
+    diffuse: TextureViewId,
+    normal: TextureViewId,
+    depth: TextureViewId,
+}
+
+/// Deferred renderers, kept between frames for as long as their input textures exist.
+///
+/// The diffuse input alternates between the view target's two main textures, so there are
+/// normally two of these.
+#[derive(Resource, Default)]
+pub struct DeferredRenderers {
+    renderers: Mutex<HashMap<DeferredInputs, DeferredRenderer>>,
+}
+
+impl DeferredRenderers {
+    fn get_or_create(
+        &self,
+        state: &GraphicsState,
+        device: &RenderDevice,
+        diffuse_buffer: &TextureView,
+        normal_buffer: &TextureView,
+        depth_buffer: &TextureView,
+    ) -> DeferredRenderer {
+        let inputs = DeferredInputs {
+            layout: state.deferred_pipeline().bind_group_layouts()[0].id(),
+            diffuse: diffuse_buffer.id(),
+            normal: normal_buffer.id(),
+            depth: depth_buffer.id(),
+        };
+
+        let mut renderers = self.renderers.lock();
+        if let Some(renderer) = renderers.get(&inputs) {
+            return renderer.clone();
+        }
+
+        // a miss with both slots full means the targets were resized or the graphics state was
+        // replaced, so none of the existing bind groups will be used again
+        if renderers.len() >= 2 {
+            renderers.clear();
+        }
+
+        let renderer =
+            DeferredRenderer::new(state, device, diffuse_buffer, normal_buffer, depth_buffer);
+        renderers.insert(inputs, renderer.clone());
+
+        renderer
     }
 }
 
@@ -342,30 +499,32 @@ impl ViewNode for DeferredPass {
         &self,
         _graph: &mut bevy::render::render_graph::RenderGraphContext,
         render_context: &mut bevy::render::renderer::RenderContext<'w>,
-        (target, prepass, extracted_camera): (&ViewTarget, &ViewPrepassTextures, &ExtractedCamera),
+        (target, prepass, extracted_camera): (
+            &'w ViewTarget,
+            &'w ViewPrepassTextures,
+            &'w ExtractedCamera,
+        ),
         world: &'w bevy::prelude::World,
     ) -> Result<(), bevy::render::render_graph::NodeRunError> {
-        // Bevy's physically-based renderer assumes lighting in lumens, so we multiply the lighting by a "fudge factor"
-        // which adapts Quake's more-direct 0..1 lighting levels to something which more-closely matches the expected
-        // lighting level. This is calibrated assuming "indoor" lighting levels, as Quake's environments are mostly
-        // indoor and so that seems to make most physical sense.
-        const EXPOSURE_MULTIPLIER: f32 = 200.;
-
-        let gfx_state = world.resource::<GraphicsState>();
+        profile_span!("deferred_pass");
+        let Some(gfx_state) = world.get_resource::<GraphicsState>() else {
+            return Ok(());
+        };
+        let Some(renderers) = world.get_resource::<DeferredRenderers>() else {
+            return Ok(());
+        };
         let conn = world.get_resource::<RenderState>();
         let queue = world.resource::<RenderQueue>();
-        let device = world.resource::<RenderDevice>();
         let Some(&RenderResolution(width, height)) = world.get_resource::<RenderResolution>()
         else {
             return Ok(());
         };
-        let render_vars = world.resource::<RenderVars>();
+        let render_vars = world.get_resource::<RenderVars>();
+        let (scaled_width, scaled_height) =
+            render_vars.map_or((width, height), |vars| vars.scaled_size(width, height));
+        let fov = world.resource::<Fov>();
 
-        let Some(RenderState {
-            state: cl_state,
-            kind,
-        }) = conn
-        else {
+        let Some(cl_state) = conn else {
             return Ok(());
         };
 
@@ -398,64 +557,113 @@ impl ViewNode for DeferredPass {
             return Ok(());
         };
 
-        // TODO: Cache
-        let deferred_renderer =
-            DeferredRenderer::new(gfx_state, device, diffuse_input, normal_input, depth_input);
-
-        let encoder = render_context.command_encoder();
+        let deferred_renderer = renderers.get_or_create(
+            gfx_state,
+            render_context.render_device(),
+            diffuse_input,
+            normal_input,
+            depth_input,
+        );
 
-        // if client is fully connected, draw world
-        let camera = match kind {
-            RenderConnectionKind::Demo => {
-                cl_state.demo_camera(width as f32 / height as f32, Deg(render_vars.fov))
-            }
-            RenderConnectionKind::Server => {
-                cl_state.camera(width as f32 / height as f32, Deg(render_vars.fov))
+        render_context.add_command_buffer_generation_task(move |device| {
+            profile_span!("deferred_pass_encode");
+            let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Deferred pass encoder"),
+            });
+
+            {
+                // if client is fully connected, draw world
+                let camera = cl_state.camera(width as f32 / height as f32, fov.0);
+
+                let deferred_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Deferred pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: diffuse_target,
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                            store: wgpu::StoreOp::Store,
+                        },
+                    })],
+                    depth_stencil_attachment: None,
+                    ..default()
+                });
+
+                let mut deferred_pass = TrackedRenderPass::new(&device, deferred_pass);
+                deferred_pass.set_viewport(
+                    0.0,
+                    0.0,
+                    scaled_width as f32,
+                    scaled_height as f32,
+                    0.0,
+                    1.0,
+                );
+
+                let mut lights = [PointLight {
+                    origin: [0.; 3],
+                    radius: 0.0,
+                    color: [0.; 3],
+                    _pad: 0.0,
+                }; MAX_LIGHTS];
+
+                let mut light_count = 0;
+                for (light_id, light) in cl_state.iter_lights().enumerate().take(MAX_LIGHTS) {
+                    light_count += 1;
+                    let light_origin = light.origin();
+                    let converted_origin =
+                        Vector3::new(-light_origin.y, light_origin.z, -light_origin.x);
+                    lights[light_id].origin = (camera.view() * converted_origin.extend(1.0))
+                        .truncate()
+                        .into();
+                    lights[light_id].radius = light.radius(cl_state.time());
+                    lights[light_id].color = light.color();
+                }
+
+                let uniforms = DeferredUniforms {
+                    inv_projection: camera.inverse_projection().into(),
+                    light_count,
+                    exposure: EXPOSURE_MULTIPLIER * extracted_camera.exposure,
+                    scale: [
+                        scaled_width as f32 / width as f32,
+                        scaled_height as f32 / height as f32,
+                    ],
+                    lights,
+                };
+                let [r, g, b, density] = cl_state.fog().uniform();
+                let fog = FogUniforms {
+                    color: [r, g, b],
+                    density,
+                };
+
+                deferred_renderer.record_draw(gfx_state, queue, &mut deferred_pass, uniforms, fog);
+
+                if let Some(vars) = render_vars.filter(|vars| vars.enhanced_particles) {
+                    let particle_uniforms = SoftParticleUniforms {
+                        view_projection: camera.view_projection().into(),
+                        view: camera.view().into(),
+                        inv_projection: camera.inverse_projection().into(),
+                        params: [
+                            // particles are fullbright, which the initial pass draws at 0.25
+                            0.25 * uniforms.exposure,
+                            vars.particle_softness,
+                            vars.particle_size,
+                            0.0,
+                        ],
+                    };
+                    deferred_renderer.record_particle_draw(
+                        gfx_state,
+                        queue,
+                        &mut deferred_pass,
+                        vars.particle_blend,
+                        particle_uniforms,
+                        cl_state.iter_particles(),
+                    );
+                }
             }
-        };
 
-        let deferred_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-            label: Some("Deferred pass"),
-            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                view: diffuse_target,
-                resolve_target: None,
-                ops: wgpu::Operations {
-                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
-                    store: wgpu::StoreOp::Store,
-                },
-            })],
-            depth_stencil_attachment: None,
-            ..default()
+            encoder.finish()
         });
 
-        let mut deferred_pass = TrackedRenderPass::new(device, deferred_pass);
-
-        let mut lights = [PointLight {
-            origin: [0.; 3],
-            radius: 0.0,
-        }; MAX_LIGHTS];
-
-        let mut light_count = 0;
-        for (light_id, light) in cl_state.iter_lights().enumerate().take(MAX_LIGHTS) {
-            light_count += 1;
-            let light_origin = light.origin();
-            let converted_origin = Vector3::new(-light_origin.y, light_origin.z, -light_origin.x);
-            lights[light_id].origin = (camera.view() * converted_origin.extend(1.0))
-                .truncate()
-                .into();
-            lights[light_id].radius = light.radius(cl_state.time());
-        }
-
-        let uniforms = DeferredUniforms {
-            inv_projection: camera.inverse_projection().into(),
-            light_count,
-            exposure: EXPOSURE_MULTIPLIER * extracted_camera.exposure,
-            _pad: default(),
-            lights,
-        };
-
-        deferred_renderer.record_draw(gfx_state, queue, &mut deferred_pass, uniforms);
-
         Ok(())
     }
 }
@@ -0,0 +1,411 @@
+//! Skeletal models, see [`crate::common::iqm`].
+//!
+//! Vertices are uploaded once in their bind pose, and the skinning matrices of every pose of a
+//! model are kept in a storage buffer of its own. Instances are shared with alias models, so an
+//! entity's frame blend is applied by mixing the matrices of the two poses in the vertex shader.
+
+use std::{mem::size_of, ops::Range};
+
+use crate::{
+    client::render::{
+        pipeline::PushConstantUpdate,
+        stats::{self, Counter},
+        world::{alias::AliasInstance, BindGroupLayoutId, WorldPipelineBase},
+        DiffuseData, GraphicsState, Pipeline, ShaderCompiler,
+    },
+    common::{
+        iqm::{IqmAnim, IqmModel},
+        util::any_slice_as_bytes,
+    },
+};
+
+use beef::Cow;
+use bevy::render::{
+    render_phase::TrackedRenderPass,
+    render_resource::{
+        BindGroup, BindGroupLayout, BindGroupLayoutEntry, Buffer, RenderPipeline, Texture,
+    },
+    renderer::{RenderDevice, RenderQueue},
+};
+use bumpalo::Bump;
+use cgmath::{Matrix4, Vector3};
+use chrono::Duration;
+use lazy_static::lazy_static;
+
+// the poses take the place of the per-face bind group used by brush models
+const POSE_BIND_GROUP: usize = 3;
+
+pub struct IqmPipeline {
+    pipeline: RenderPipeline,
+    bind_group_layouts: Vec<BindGroupLayout>,
+}
+
+impl IqmPipeline {
+    pub fn new(
+        device: &RenderDevice,
+        compiler: &mut ShaderCompiler,
+        world_bind_group_layouts: &[BindGroupLayout],
+        diffuse_format: wgpu::TextureFormat,
+        normal_format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) -> IqmPipeline {
+        let (pipeline, bind_group_layouts) = IqmPipeline::create(
+            device,
+            compiler,
+            world_bind_group_layouts,
+            sample_count,
+            (diffuse_format, normal_format),
+        );
+
+        IqmPipeline {
+            pipeline,
+            bind_group_layouts,
+        }
+    }
+
+    pub fn rebuild(
+        &mut self,
+        device: &RenderDevice,
+        compiler: &mut ShaderCompiler,
+        diffuse_format: wgpu::TextureFormat,
+        normal_format: wgpu::TextureFormat,
+        world_bind_group_layouts: &[BindGroupLayout],
+        sample_count: u32,
+    ) {
+        let layout_refs = world_bind_group_layouts
+            .iter()
+            .chain(self.bind_group_layouts.iter());
+        self.pipeline = Self::recreate(
+            device,
+            compiler,
+            layout_refs,
+            sample_count,
+            (diffuse_format, normal_format),
+        );
+    }
+
+    pub fn pipeline(&self) -> &RenderPipeline {
+        &self.pipeline
+    }
+
+    pub fn bind_group_layouts(&self) -> &[BindGroupLayout] {
+        &self.bind_group_layouts
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct VertexPushConstants {
+    pub transform: Matrix4<f32>,
+    pub model_view: Matrix4<f32>,
+
+    /// Where the matrices of the previous and current poses start in the model's pose buffer.
+    pub prev_pose_offset: u32,
+    pub pose_offset: u32,
+    pub _padding: [u32; 2],
+}
+
+lazy_static! {
+    static ref VERTEX_ATTRIBUTES: [wgpu::VertexAttribute; 5] =
+        wgpu::vertex_attr_array![
+            // position
+            0 => Float32x3,
+            // normal
+            1 => Float32x3,
+            // texcoord
+            2 => Float32x2,
+            // joints
+            3 => Uint8x4,
+            // joint weights
+            4 => Unorm8x4,
+        ];
+    static ref INSTANCE_ATTRIBUTES: [wgpu::VertexAttribute; 6] =
+        wgpu::vertex_attr_array![
+            // model transform, a column at a time
+            5 => Float32x4,
+            6 => Float32x4,
+            7 => Float32x4,
+            8 => Float32x4,
+            // alpha
+            9 => Float32,
+            // blend
+            10 => Float32,
+        ];
+}
+
+impl Pipeline for IqmPipeline {
+    type VertexPushConstants = VertexPushConstants;
+    type SharedPushConstants = ();
+    type FragmentPushConstants = ();
+
+    type Args = <WorldPipelineBase as Pipeline>::Args;
+
+    fn name() -> &'static str {
+        "iqm"
+    }
+
+    fn vertex_shader() -> &'static str {
+        include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/shaders/iqm.vert"))
+    }
+
+    // skinned models are shaded exactly like alias models
+    fn fragment_shader() -> &'static str {
+        include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/shaders/alias.frag"))
+    }
+
+    fn bind_group_layout_descriptors() -> Vec<Vec<BindGroupLayoutEntry>> {
+        vec![
+            // group 2: updated per-texture
+            vec![wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Texture {
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    multisampled: false,
+                },
+                count: None,
+            }],
+            // group 3: updated per-model
+            vec![wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: wgpu::BufferSize::new(size_of::<Matrix4<f32>>() as u64),
+                },
+                count: None,
+            }],
+        ]
+    }
+
+    fn primitive_state() -> wgpu::PrimitiveState {
+        WorldPipelineBase::primitive_state()
+    }
+
+    fn color_target_states_with_args(args: Self::Args) -> Vec<Option<wgpu::ColorTargetState>> {
+        WorldPipelineBase::color_target_states_with_args(args)
+    }
+
+    fn depth_stencil_state() -> Option<wgpu::DepthStencilState> {
+        WorldPipelineBase::depth_stencil_state()
+    }
+
+    // NOTE: if the vertex format is changed, this descriptor must also be changed accordingly.
+    fn vertex_buffer_layouts() -> Vec<wgpu::VertexBufferLayout<'static>> {
+        vec![
+            wgpu::VertexBufferLayout {
+                array_stride: size_of::<IqmVertex>() as u64,
+                step_mode: wgpu::VertexStepMode::Vertex,
+                attributes: &VERTEX_ATTRIBUTES[..],
+            },
+            wgpu::VertexBufferLayout {
+                array_stride: size_of::<AliasInstance>() as u64,
+                step_mode: wgpu::VertexStepMode::Instance,
+                attributes: &INSTANCE_ATTRIBUTES[..],
+            },
+        ]
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct IqmVertex {
+    position: [f32; 3],
+    normal: [f32; 3],
+    diffuse_texcoord: [f32; 2],
+    joints: [u8; 4],
+    weights: [u8; 4],
+}
+
+struct Mesh {
+    indices: Range<u32>,
+    _diffuse_texture: Texture,
+    bind_group: BindGroup,
+}
+
+pub struct IqmRenderer {
+    min: Vector3<f32>,
+    max: Vector3<f32>,
+
+    anims: Vec<IqmAnim>,
+    joint_count: u32,
+    meshes: Vec<Mesh>,
+    vertex_buffer: Buffer,
+    index_buffer: Buffer,
+    _pose_buffer: Buffer,
+    pose_bind_group: BindGroup,
+}
+
+impl IqmRenderer {
+    pub fn new(
+        state: &GraphicsState,
+        device: &RenderDevice,
+        queue: &RenderQueue,
+        iqm_model: &IqmModel,
+    ) -> IqmRenderer {
+        let vertices = iqm_model
+            .vertices()
+            .iter()
+            .map(|v| IqmVertex {
+                position: v.position,
+                normal: v.normal,
+                diffuse_texcoord: v.texcoord,
+                joints: v.joints,
+                weights: v.weights,
+            })
+            .collect::<Vec<_>>();
+        let vertex_buffer = device.create_buffer_with_data(&wgpu::util::BufferInitDescriptor {
+            label: Some("iqm vertex buffer"),
+            contents: unsafe { any_slice_as_bytes(&vertices) },
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let index_buffer = device.create_buffer_with_data(&wgpu::util::BufferInitDescriptor {
+            label: Some("iqm index buffer"),
+            contents: unsafe { any_slice_as_bytes(iqm_model.triangles()) },
+            usage: wgpu::BufferUsages::INDEX,
+        });
+
+        let pose_buffer = device.create_buffer_with_data(&wgpu::util::BufferInitDescriptor {
+            label: Some("iqm pose buffer"),
+            contents: unsafe { any_slice_as_bytes(iqm_model.poses()) },
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let pose_bind_group = device.create_bind_group(
+            Some("iqm pose bind group"),
+            &state.iqm_pipeline().bind_group_layouts()[POSE_BIND_GROUP - 2],
+            &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: pose_buffer.as_entire_binding(),
+            }],
+        );
+
+        let meshes = iqm_model
+            .meshes()
+            .iter()
+            .map(|mesh| {
+                // a mesh without a skin is drawn plain white so that its shape still shows
+                let diffuse_texture = match mesh.skin() {
+                    Some(skin) => state.create_diffuse_texture(
+                        device,
+                        queue,
+                        None,
+                        skin.width,
+                        skin.height,
+                        DiffuseData {
+                            rgba: Cow::borrowed(&skin.rgba),
+                        },
+                    ),
+                    None => state.create_diffuse_texture(
+                        device,
+                        queue,
+                        None,
+                        1,
+                        1,
+                        DiffuseData {
+                            rgba: Cow::owned(vec![0xff; 4]),
+                        },
+                    ),
+                };
+                let diffuse_view = diffuse_texture.create_view(&Default::default());
+                let bind_group = device.create_bind_group(
+                    None,
+                    &state.iqm_pipeline().bind_group_layouts()
+                        [BindGroupLayoutId::PerTexture as usize - 2],
+                    &[wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&diffuse_view),
+                    }],
+                );
+
+                let triangles = mesh.triangles();
+                Mesh {
+                    indices: triangles.start as u32 * 3..triangles.end as u32 * 3,
+                    _diffuse_texture: diffuse_texture,
+                    bind_group,
+                }
+            })
+            .collect();
+
+        IqmRenderer {
+            min: iqm_model.min(),
+            max: iqm_model.max(),
+            anims: iqm_model.anims().to_vec(),
+            joint_count: iqm_model.joint_count() as u32,
+            meshes,
+            vertex_buffer,
+            index_buffer,
+            _pose_buffer: pose_buffer,
+            pose_bind_group,
+        }
+    }
+
+    /// The minimum extent of the model, relative to its origin.
+    pub fn min(&self) -> Vector3<f32> {
+        self.min
+    }
+
+    /// The maximum extent of the model, relative to its origin.
+    pub fn max(&self) -> Vector3<f32> {
+        self.max
+    }
+
+    /// Draw `instances` from `instance_buffer`, which are all blending between the same two
+    /// frames. `transform` and `model_view` are applied on top of each instance's own transform.
+    pub fn record_draw<'a>(
+        &'a self,
+        state: &'a GraphicsState,
+        pass: &mut TrackedRenderPass<'a>,
+        bump: &'a Bump,
+        transform: Matrix4<f32>,
+        model_view: Matrix4<f32>,
+        time: Duration,
+        prev_frame_id: usize,
+        frame_id: usize,
+        instance_buffer: &'a Buffer,
+        instances: Range<u32>,
+    ) {
+        use PushConstantUpdate::*;
+
+        let Some(pose) = self.anims.get(frame_id).map(|a| a.pose_at(time)) else {
+            return;
+        };
+        // a previous frame that doesn't exist is drawn as the current one
+        let prev_pose = self
+            .anims
+            .get(prev_frame_id)
+            .map_or(pose, |a| a.pose_at(time));
+
+        pass.set_render_pipeline(state.iqm_pipeline().pipeline());
+        IqmPipeline::set_push_constants(
+            pass,
+            Update(bump.alloc(VertexPushConstants {
+                transform,
+                model_view,
+                prev_pose_offset: prev_pose as u32 * self.joint_count,
+                pose_offset: pose as u32 * self.joint_count,
+                _padding: [0; 2],
+            })),
+            Clear,
+            Clear,
+        );
+        pass.set_bind_group(POSE_BIND_GROUP, &self.pose_bind_group, &[]);
+        pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        pass.set_vertex_buffer(1, instance_buffer.slice(..));
+        pass.set_index_buffer(self.index_buffer.slice(..), 0, wgpu::IndexFormat::Uint32);
+
+        for mesh in &self.meshes {
+            pass.set_bind_group(
+                BindGroupLayoutId::PerTexture as usize,
+                &mesh.bind_group,
+                &[],
+            );
+            pass.draw_indexed(mesh.indices.clone(), 0, instances.clone());
+            stats::count_draw(
+                Counter::AliasPolys,
+                (mesh.indices.len() / 3 * instances.len()) as u64,
+            );
+        }
+    }
+}
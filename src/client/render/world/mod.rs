@@ -1,11 +1,14 @@
 pub mod alias;
+pub mod bloom;
 pub mod brush;
 pub mod deferred;
+pub mod iqm;
 pub mod particle;
 pub mod postprocess;
+pub mod sky;
 pub mod sprite;
 
-use std::mem::size_of;
+use std::{mem::size_of, ops::Range, sync::Arc};
 
 use crate::{
     client::{
@@ -14,13 +17,14 @@ use crate::{
             pipeline::{Pipeline, PushConstantUpdate},
             uniform::{DynamicUniformBufferBlock, UniformBool},
             world::{
-                alias::{AliasPipeline, AliasRenderer},
-                brush::{BrushPipeline, BrushRenderer, BrushRendererBuilder},
+                alias::{AliasInstance, AliasRenderer},
+                brush::{BrushPipeline, BrushRenderer, BrushRendererBuilder, LightmapLight},
+                iqm::IqmRenderer,
                 sprite::{SpritePipeline, SpriteRenderer},
             },
-            GraphicsState,
+            GraphicsState, RenderEntity, ViewModel,
         },
-        ClientEntity, ConnectionState,
+        ConnectionState,
     },
     common::{
         engine,
@@ -39,15 +43,21 @@ use bevy::{
         render_resource::BindGroupLayoutEntry,
         renderer::{RenderDevice, RenderQueue},
     },
+    tasks::ComputeTaskPool,
 };
 use bumpalo::Bump;
-use cgmath::{Euler, InnerSpace, Matrix4, SquareMatrix as _, Vector3, Vector4};
+use cgmath::{Euler, InnerSpace, Matrix as _, Matrix4, SquareMatrix as _, Vector3, Vector4};
 use chrono::Duration;
+use hashbrown::HashMap;
 use lazy_static::lazy_static;
 use parking_lot::RwLock;
 
 use super::RenderVars;
 
+/// The fraction of the depth range that the view model is squashed into, so that it's drawn over
+/// any wall that it pokes into, like `glDepthRange` in GLQuake.
+const VIEWMODEL_DEPTH_RANGE: f32 = 0.3;
+
 lazy_static! {
     static ref BIND_GROUP_LAYOUT_DESCRIPTOR_BINDINGS: [Vec<BindGroupLayoutEntry>; 2] = [
         vec![
@@ -211,19 +221,20 @@ impl Camera {
         let view_projection = projection * view;
 
         // see https://www.gamedevs.org/uploads/fast-extraction-viewing-frustum-planes-from-world-view-projection-matrix.pdf
+        let rows = [0, 1, 2, 3].map(|i| view_projection.row(i));
         let clipping_planes = [
             // left
-            view_projection.w + view_projection.x,
+            rows[3] + rows[0],
             // right
-            view_projection.w - view_projection.x,
+            rows[3] - rows[0],
             // bottom
-            view_projection.w + view_projection.y,
+            rows[3] + rows[1],
             // top
-            view_projection.w - view_projection.y,
+            rows[3] - rows[1],
             // near
-            view_projection.w + view_projection.z,
+            rows[3] + rows[2],
             // far
-            view_projection.w - view_projection.z,
+            rows[3] - rows[2],
         ];
 
         Camera {
@@ -261,16 +272,32 @@ impl Camera {
         self.inverse_projection
     }
 
-    // TODO: this seems to be too lenient
     /// Determines whether a point falls outside the viewing frustum.
     pub fn cull_point(&self, p: Vector3<f32>) -> bool {
-        for plane in self.clipping_planes.iter() {
-            if (self.view_projection() * p.extend(1.0)).dot(*plane) < 0.0 {
-                return true;
-            }
-        }
+        let p = Vector4::new(-p.y, p.z, -p.x, 1.0);
+        self.clipping_planes.iter().any(|plane| plane.dot(p) < 0.0)
+    }
 
-        false
+    /// Determines whether the box from `min` to `max` falls entirely outside the viewing frustum.
+    ///
+    /// This is conservative: a box near a corner of the frustum may be outside of it without being
+    /// culled.
+    pub fn cull_box(&self, min: Vector3<f32>, max: Vector3<f32>) -> bool {
+        let min_conv = Vector3::new(-max.y, min.z, -max.x);
+        let max_conv = Vector3::new(-min.y, max.z, -min.x);
+
+        // the coordinate of the corner of the box furthest along the plane normal
+        let furthest = |n: f32, min: f32, max: f32| if n >= 0.0 { max } else { min };
+
+        self.clipping_planes.iter().any(|plane| {
+            let corner = Vector4::new(
+                furthest(plane.x, min_conv.x, max_conv.x),
+                furthest(plane.y, min_conv.y, max_conv.y),
+                furthest(plane.z, min_conv.z, max_conv.z),
+                1.0,
+            );
+            plane.dot(corner) < 0.0
+        })
     }
 }
 
@@ -293,31 +320,135 @@ pub struct EntityUniforms {
 
     /// Model-only transform matrix
     model: Matrix4<f32>,
+
+    /// Model-view transform matrix
+    model_view: Matrix4<f32>,
 }
 
+#[derive(Clone)]
 enum EntityRenderer {
-    Alias(AliasRenderer),
-    Brush(BrushRenderer),
-    Sprite(SpriteRenderer),
+    Alias(Arc<AliasRenderer>),
+    Iqm(Arc<IqmRenderer>),
+    Brush(Arc<BrushRenderer>),
+    Sprite(Arc<SpriteRenderer>),
     None,
 }
 
 static NO_ENTITY_RENDERER: EntityRenderer = EntityRenderer::None;
 
+impl EntityRenderer {
+    /// The bounds of the model relative to its origin, if it has any.
+    fn bounds(&self) -> Option<(Vector3<f32>, Vector3<f32>)> {
+        match self {
+            EntityRenderer::Alias(alias) => Some((alias.min(), alias.max())),
+            EntityRenderer::Iqm(iqm) => Some((iqm.min(), iqm.max())),
+            EntityRenderer::Brush(bmodel) => Some((bmodel.min(), bmodel.max())),
+            EntityRenderer::Sprite(sprite) => Some((sprite.min(), sprite.max())),
+            EntityRenderer::None => None,
+        }
+    }
+}
+
+/// Alias model entities sharing a model, keyframe and skin, which are drawn together with one
+/// instanced draw.
+struct AliasBatch {
+    /// The index of the model's renderer in `WorldRenderer::entity_renderers`.
+    renderer_id: usize,
+    prev_keyframe_id: usize,
+    keyframe_id: usize,
+    skin_id: usize,
+    instances: Range<u32>,
+}
+
+/// The alias model batches for the frame, built along with the uniforms.
+#[derive(Default)]
+struct AliasBatches {
+    batches: Vec<AliasBatch>,
+
+    /// The number of alias model entities culled while batching.
+    culled: usize,
+}
+
+/// How much of the scene frustum culling skipped in a frame, logged when `r_showcull` is set.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CullStats {
+    /// The number of leaves in the PVS that were in view.
+    pub leaves_drawn: usize,
+    /// The number of leaves in the PVS that were out of view.
+    pub leaves_culled: usize,
+    pub entities_drawn: usize,
+    pub entities_culled: usize,
+}
+
+/// A liquid texture chain of a brush model, waiting to be drawn after everything opaque.
+struct TranslucentChain<'a> {
+    renderer: &'a BrushRenderer,
+    tex_id: usize,
+    distance: f32,
+
+    /// The position of the entity in the entity list, or `None` for the worldmodel.
+    ent_pos: Option<usize>,
+    frame_id: usize,
+    transform: Matrix4<f32>,
+    model_view: Matrix4<f32>,
+}
+
+/// Identifies the renderer for a model, so it can be reused by later levels.
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum ModelKey {
+    /// Alias models and sprites are loaded from their own files, so they're the same in every
+    /// level.
+    File(String),
+
+    /// Brush models belong to a BSP, so they can only be reused while it's still loaded. The
+    /// cached renderer holds on to the BSP data, so its address can't be taken by another BSP.
+    Brush(usize, String),
+
+    None,
+}
+
+impl ModelKey {
+    fn new(model: &Model) -> ModelKey {
+        match *model.kind() {
+            ModelKind::Alias(_) | ModelKind::Md3(_) | ModelKind::Iqm(_) | ModelKind::Sprite(_) => {
+                ModelKey::File(model.name().to_owned())
+            }
+            ModelKind::Brush(ref bmodel) => ModelKey::Brush(
+                Arc::as_ptr(&bmodel.bsp_data()) as usize,
+                model.name().to_owned(),
+            ),
+            _ => ModelKey::None,
+        }
+    }
+}
+
+/// The renderers for the models of the current level.
+///
+/// These outlive the `WorldRenderer` so that a level change only uploads the models the new
+/// level doesn't share with the old one. They're tied to the `GraphicsState` they were built
+/// with, and are dropped along with it.
+#[derive(Resource, Default)]
+pub struct ModelRenderers {
+    renderers: HashMap<ModelKey, EntityRenderer>,
+}
+
 /// Top-level renderer.
 #[derive(Resource)]
 pub struct WorldRenderer {
-    worldmodel_renderer: BrushRenderer,
+    worldmodel_renderer: Arc<BrushRenderer>,
     entity_renderers: Vec<EntityRenderer>,
 
     world_uniform_block: DynamicUniformBufferBlock<EntityUniforms>,
+    viewmodel_uniform_block: DynamicUniformBufferBlock<EntityUniforms>,
     entity_uniform_blocks: RwLock<Vec<DynamicUniformBufferBlock<EntityUniforms>>>,
+    alias_batches: RwLock<AliasBatches>,
 }
 
 pub fn extract_world_renderer(
     mut commands: Commands,
     world_renderer: Option<ResMut<WorldRenderer>>,
     mut gfx_state: ResMut<GraphicsState>,
+    mut model_renderers: ResMut<ModelRenderers>,
     device: Res<RenderDevice>,
     queue: Res<RenderQueue>,
     game_state: Res<ConnectionState>,
@@ -329,6 +460,7 @@ pub fn extract_world_renderer(
                 &mut *gfx_state,
                 &*device,
                 &*queue,
+                &mut *model_renderers,
                 state.model_precache.iter(),
                 state.worldmodel_id,
             );
@@ -349,62 +481,106 @@ impl WorldRenderer {
         state: &'a mut GraphicsState,
         device: &RenderDevice,
         queue: &RenderQueue,
+        model_renderers: &mut ModelRenderers,
         models: M,
         worldmodel_id: usize,
     ) -> WorldRenderer {
-        let mut worldmodel_renderer = None;
-        let mut entity_renderers = Vec::new();
-
+        profile_span!("world_renderer_new");
         let world_uniform_block = state.entity_uniform_buffer_mut().allocate(EntityUniforms {
             transform: Matrix4::identity(),
             model: Matrix4::identity(),
+            model_view: Matrix4::identity(),
+        });
+        let viewmodel_uniform_block = state.entity_uniform_buffer_mut().allocate(EntityUniforms {
+            transform: Matrix4::identity(),
+            model: Matrix4::identity(),
+            model_view: Matrix4::identity(),
         });
 
-        for (i, model) in models.enumerate() {
-            if i == worldmodel_id {
-                match *model.kind() {
-                    ModelKind::Brush(ref bmodel) => {
-                        worldmodel_renderer = Some(
-                            BrushRendererBuilder::new(bmodel, true)
-                                .build(state, device, queue)
-                                .unwrap(),
-                        );
-                    }
-                    _ => panic!("Invalid worldmodel"),
-                }
-            } else {
-                match *model.kind() {
-                    ModelKind::Alias(ref amodel) => entity_renderers.push(EntityRenderer::Alias(
-                        AliasRenderer::new(state, device, queue, amodel).unwrap(),
-                    )),
-
-                    ModelKind::Brush(ref bmodel) => {
-                        entity_renderers.push(EntityRenderer::Brush(
-                            BrushRendererBuilder::new(bmodel, false)
-                                .build(state, device, queue)
-                                .unwrap(),
-                        ));
-                    }
-
-                    ModelKind::Sprite(ref smodel) => {
-                        entity_renderers.push(EntityRenderer::Sprite(SpriteRenderer::new(
-                            &state, device, queue, smodel,
-                        )));
+        let state = &*state;
+        let models: Vec<_> = models.map(|model| (ModelKey::new(model), model)).collect();
+        let cached = &model_renderers.renderers;
+
+        // models are independent of each other, so build their renderers in parallel
+        state.begin_texture_uploads();
+        let mut renderers = ComputeTaskPool::get().scope(|scope| {
+            for (i, (key, model)) in models.iter().enumerate() {
+                scope.spawn(async move {
+                    if let Some(renderer) = cached.get(key) {
+                        return renderer.clone();
                     }
 
-                    _ => {
-                        warn!("Non-brush renderers not implemented!");
-                        entity_renderers.push(EntityRenderer::None);
+                    if i == worldmodel_id {
+                        match *model.kind() {
+                            ModelKind::Brush(ref bmodel) => EntityRenderer::Brush(Arc::new(
+                                BrushRendererBuilder::new(bmodel, true)
+                                    .build(state, device, queue)
+                                    .unwrap(),
+                            )),
+                            _ => panic!("Invalid worldmodel"),
+                        }
+                    } else {
+                        match *model.kind() {
+                            ModelKind::Alias(ref amodel) => EntityRenderer::Alias(Arc::new(
+                                AliasRenderer::new(state, device, queue, amodel).unwrap(),
+                            )),
+
+                            ModelKind::Md3(ref md3_model) => EntityRenderer::Alias(Arc::new(
+                                AliasRenderer::from_md3(state, device, queue, md3_model),
+                            )),
+
+                            ModelKind::Iqm(ref iqm_model) => EntityRenderer::Iqm(Arc::new(
+                                IqmRenderer::new(state, device, queue, iqm_model),
+                            )),
+
+                            ModelKind::Brush(ref bmodel) => EntityRenderer::Brush(Arc::new(
+                                BrushRendererBuilder::new(bmodel, false)
+                                    .build(state, device, queue)
+                                    .unwrap(),
+                            )),
+
+                            ModelKind::Sprite(ref smodel) => EntityRenderer::Sprite(Arc::new(
+                                SpriteRenderer::new(state, device, queue, smodel),
+                            )),
+
+                            _ => {
+                                warn!("Non-brush renderers not implemented!");
+                                EntityRenderer::None
+                            }
+                        }
                     }
-                }
+                });
             }
-        }
+        });
+        state.submit_texture_uploads(device, queue);
+
+        let reused = models
+            .iter()
+            .filter(|(key, _)| cached.contains_key(key))
+            .count();
+        info!("Reused {} of {} model renderers", reused, models.len());
+
+        // keep only this level's renderers, so models that are no longer used can be freed
+        model_renderers.renderers = models
+            .into_iter()
+            .zip(renderers.iter().cloned())
+            .filter(|((key, _), _)| *key != ModelKey::None)
+            .map(|((key, _), renderer)| (key, renderer))
+            .collect();
+
+        let worldmodel_renderer = match renderers.remove(worldmodel_id) {
+            EntityRenderer::Brush(brush) => brush,
+            _ => unreachable!(),
+        };
+        let entity_renderers = renderers;
 
         WorldRenderer {
-            worldmodel_renderer: worldmodel_renderer.unwrap(),
+            worldmodel_renderer,
             entity_renderers,
             world_uniform_block,
+            viewmodel_uniform_block,
             entity_uniform_blocks: Default::default(),
+            alias_batches: Default::default(),
         }
     }
 
@@ -415,10 +591,12 @@ impl WorldRenderer {
         camera: &Camera,
         time: Duration,
         entities: I,
+        viewmodel: Option<ViewModel>,
         lightstyle_values: &[f32],
+        lights: &[LightmapLight],
         render_vars: &RenderVars,
     ) where
-        I: Iterator<Item = &'a ClientEntity>,
+        I: Iterator<Item = &'a RenderEntity>,
     {
         trace!("Updating frame uniform buffer");
         queue.write_buffer(state.frame_uniform_buffer(), 0, unsafe { any_as_bytes(&FrameUniforms {
@@ -446,15 +624,72 @@ impl WorldRenderer {
         let world_uniforms = EntityUniforms {
             transform: camera.view_projection(),
             model: Matrix4::identity(),
+            model_view: camera.view(),
         };
         state
             .entity_uniform_buffer_mut()
             .write_block(&self.world_uniform_block, world_uniforms);
 
+        if let Some(viewmodel) = viewmodel {
+            let (transform, model) = viewmodel_transforms(camera, &viewmodel);
+            state.entity_uniform_buffer_mut().write_block(
+                &self.viewmodel_uniform_block,
+                EntityUniforms {
+                    transform,
+                    model,
+                    model_view: camera.view() * model,
+                },
+            );
+        }
+
+        trace!("Updating lightmaps");
+        self.worldmodel_renderer
+            .update_lightmaps(queue, lightstyle_values, lights);
+
+        let mut entity_lights = Vec::new();
+        let mut alias_instances = HashMap::new();
+        let mut alias_culled = 0;
         for (ent_pos, ent) in entities.into_iter().enumerate() {
+            match self.renderer_for_entity(ent) {
+                EntityRenderer::Brush(ref bmodel) => {
+                    // rotation is ignored, like in Quake
+                    entity_lights.clear();
+                    entity_lights.extend(lights.iter().map(|light| LightmapLight {
+                        origin: light.origin - ent.get_origin(),
+                        ..*light
+                    }));
+                    bmodel.update_lightmaps(queue, lightstyle_values, &entity_lights);
+                }
+
+                // skeletal models are batched the same way, and drawn after the alias models
+                EntityRenderer::Alias(_) | EntityRenderer::Iqm(_) => {
+                    if self.cull_entity(camera, ent) {
+                        alias_culled += 1;
+                    } else {
+                        let (prev_frame_id, blend) = if render_vars.lerp_models {
+                            (ent.prev_frame_id(), ent.frame_blend())
+                        } else {
+                            (ent.frame_id(), 1.0)
+                        };
+
+                        alias_instances
+                            .entry((ent.model_id(), prev_frame_id, ent.frame_id(), ent.skin_id()))
+                            .or_insert_with(Vec::new)
+                            .push(AliasInstance {
+                                model: self.calculate_model_transform(camera, ent),
+                                alpha: ent.alpha(),
+                                blend,
+                            });
+                    }
+                }
+
+                _ => {}
+            }
+
             let ent_uniforms = EntityUniforms {
                 transform: self.calculate_mvp_transform(camera, ent),
                 model: self.calculate_model_transform(camera, ent),
+                model_view: self.calculate_mv_transform(camera, ent),
             };
 
             if ent_pos >= self.entity_uniform_blocks.read().len() {
@@ -469,6 +704,29 @@ impl WorldRenderer {
         }
 
         state.entity_uniform_buffer().flush(queue);
+
+        trace!("Updating alias instance buffer");
+        let instance_buffer = state.alias_instance_buffer();
+        instance_buffer.clear();
+        let batches = alias_instances
+            .into_iter()
+            .map(
+                |((model_id, prev_keyframe_id, keyframe_id, skin_id), instances)| AliasBatch {
+                    // the world entity isn't counted, see `renderer_for_entity`
+                    renderer_id: model_id.saturating_sub(1),
+                    prev_keyframe_id,
+                    keyframe_id,
+                    skin_id,
+                    instances: instance_buffer.push_batch(instances),
+                },
+            )
+            .collect();
+        instance_buffer.flush(queue);
+
+        *self.alias_batches.write() = AliasBatches {
+            batches,
+            culled: alias_culled,
+        };
     }
 
     pub fn render_pass<'a, E, P>(
@@ -480,9 +738,11 @@ impl WorldRenderer {
         time: Duration,
         entities: E,
         particles: P,
-        viewmodel_id: Option<usize>,
-    ) where
-        E: Iterator<Item = &'a ClientEntity>,
+        viewmodel: Option<ViewModel>,
+        water_alpha: f32,
+    ) -> CullStats
+    where
+        E: Iterator<Item = &'a RenderEntity>,
         P: Iterator<Item = &'a Particle>,
     {
         use PushConstantUpdate::*;
@@ -511,20 +771,56 @@ impl WorldRenderer {
             &state.world_bind_groups()[BindGroupLayoutId::PerEntity as usize],
             &[self.world_uniform_block.offset()],
         );
+        // liquids are drawn once everything opaque has been, so they can be seen through
+        let translucent_liquids = water_alpha < 1.0;
+        let mut translucent_chains = Vec::new();
+        let mut cull_stats = CullStats::default();
+
         // HACK: Hardcoded frame time (TODO: Actually track frame number)
+        let world_frame_id = ((engine::duration_to_f32(time) + (0.05 / 2.)) / 0.05) as usize;
         self.worldmodel_renderer.record_draw(
             state,
             pass,
             &bump,
             time,
             camera,
-            ((engine::duration_to_f32(time) + (0.05 / 2.)) / 0.05) as usize,
+            world_frame_id,
+            translucent_liquids,
+            &mut cull_stats,
         );
+        if translucent_liquids {
+            translucent_chains.extend(
+                self.worldmodel_renderer
+                    .translucent_chains(camera.origin())
+                    .map(|(tex_id, distance)| TranslucentChain {
+                        renderer: &self.worldmodel_renderer,
+                        tex_id,
+                        distance,
+                        ent_pos: None,
+                        frame_id: world_frame_id,
+                        transform: camera.view_projection(),
+                        model_view: camera.view(),
+                    }),
+            );
+        }
 
         // draw entities
         info!("Drawing entities");
         for (ent_pos, ent) in entities.enumerate() {
             if let Some(uniforms) = self.entity_uniform_blocks.read().get(ent_pos) {
+                // alias models were culled and batched along with the uniforms
+                if let EntityRenderer::Alias(_) | EntityRenderer::Iqm(_) =
+                    self.renderer_for_entity(ent)
+                {
+                    continue;
+                }
+
+                if self.cull_entity(camera, ent) {
+                    cull_stats.entities_culled += 1;
+                    continue;
+                }
+                cull_stats.entities_drawn += 1;
+
                 pass.set_bind_group(
                     BindGroupLayoutId::PerEntity as usize,
                     &state.world_bind_groups()[BindGroupLayoutId::PerEntity as usize],
@@ -533,80 +829,250 @@ impl WorldRenderer {
 
                 match self.renderer_for_entity(&ent) {
                     EntityRenderer::Brush(ref bmodel) => {
+                        let transform = self.calculate_mvp_transform(camera, ent);
+                        let model_view = self.calculate_mv_transform(camera, ent);
                         pass.set_render_pipeline(state.brush_pipeline().pipeline());
                         BrushPipeline::set_push_constants(
                             pass,
                             Update(bump.alloc(brush::VertexPushConstants {
-                                transform: self.calculate_mvp_transform(camera, ent),
-                                model_view: self.calculate_mv_transform(camera, ent),
+                                transform,
+                                model_view,
                             })),
                             Clear,
                             Clear,
                         );
-                        bmodel.record_draw(state, pass, &bump, time, camera, ent.frame_id);
-                    }
-                    EntityRenderer::Alias(ref alias) => {
-                        pass.set_render_pipeline(state.alias_pipeline().pipeline());
-                        AliasPipeline::set_push_constants(
+                        bmodel.record_draw(
+                            state,
                             pass,
-                            Update(bump.alloc(alias::VertexPushConstants {
-                                transform: self.calculate_mvp_transform(camera, ent),
-                                model_view: self.calculate_mv_transform(camera, ent),
-                            })),
-                            Clear,
-                            Clear,
+                            &bump,
+                            time,
+                            camera,
+                            ent.frame_id,
+                            translucent_liquids,
+                            &mut cull_stats,
                         );
-                        alias.record_draw(state, pass, time, ent.frame_id(), ent.skin_id());
+
+                        if translucent_liquids {
+                            // rotation is ignored, it only makes a difference to the order
+                            let origin = camera.origin() - ent.get_origin();
+                            translucent_chains.extend(bmodel.translucent_chains(origin).map(
+                                |(tex_id, distance)| TranslucentChain {
+                                    renderer: bmodel,
+                                    tex_id,
+                                    distance,
+                                    ent_pos: Some(ent_pos),
+                                    frame_id: ent.frame_id,
+                                    transform,
+                                    model_view,
+                                },
+                            ));
+                        }
                     }
                     EntityRenderer::Sprite(ref sprite) => {
                         pass.set_render_pipeline(state.sprite_pipeline().pipeline());
                         SpritePipeline::set_push_constants(pass, Clear, Clear, Clear);
                         sprite.record_draw(state, pass, ent.frame_id(), time);
                     }
-                    EntityRenderer::None => {}
+                    EntityRenderer::Alias(_) | EntityRenderer::Iqm(_) | EntityRenderer::None => {}
                 }
             }
         }
 
-        let viewmodel_orig = camera.origin();
-        let cam_angles = camera.angles();
-        let viewmodel_mat = Matrix4::from_translation(Vector3::new(
-            -viewmodel_orig.y,
-            viewmodel_orig.z,
-            -viewmodel_orig.x,
-        )) * Matrix4::from_angle_y(cam_angles.yaw)
-            * Matrix4::from_angle_x(-cam_angles.pitch)
-            * Matrix4::from_angle_z(cam_angles.roll);
-        match viewmodel_id.and_then(|vid| self.entity_renderers.get(vid)) {
-            Some(EntityRenderer::Alias(ref alias)) => {
-                pass.set_render_pipeline(state.alias_pipeline().pipeline());
-                AliasPipeline::set_push_constants(
+        self.record_alias_draws(state, pass, bump, camera, time, &mut cull_stats);
+
+        if !translucent_chains.is_empty() {
+            debug!("Drawing {} translucent chains", translucent_chains.len());
+            self.record_translucent_draws(state, pass, bump, time, translucent_chains, water_alpha);
+        }
+
+        if let Some(viewmodel) = viewmodel {
+            self.record_viewmodel_draw(state, pass, bump, camera, time, viewmodel);
+        }
+
+        debug!("Drawing particles");
+        state
+            .particle_pipeline()
+            .record_draw(pass, &bump, camera, particles);
+
+        cull_stats
+    }
+
+    /// Draw the player's weapon.
+    fn record_viewmodel_draw<'a>(
+        &'a self,
+        state: &'a GraphicsState,
+        pass: &mut TrackedRenderPass<'a>,
+        bump: &'a Bump,
+        camera: &Camera,
+        time: Duration,
+        viewmodel: ViewModel,
+    ) {
+        let (transform, model) = viewmodel_transforms(camera, &viewmodel);
+
+        let alias = match self.entity_renderers.get(viewmodel.renderer_id) {
+            Some(EntityRenderer::Alias(ref alias)) => alias,
+            Some(EntityRenderer::Iqm(ref iqm)) => {
+                iqm.record_draw(
+                    state,
                     pass,
-                    Update(bump.alloc(alias::VertexPushConstants {
-                        transform: camera.view_projection() * viewmodel_mat,
-                        model_view: camera.view() * viewmodel_mat,
-                    })),
-                    Clear,
-                    Clear,
+                    bump,
+                    transform,
+                    camera.view() * model,
+                    time,
+                    viewmodel.frame_id,
+                    viewmodel.frame_id,
+                    state.alias_pipeline().identity_instance_buffer(),
+                    0..1,
                 );
-                alias.record_draw(state, pass, time, 0, 0);
+                return;
             }
             Some(EntityRenderer::Brush(..)) => {
                 unreachable!("Viewmodel is brush - this should never happen")
             }
-            Some(EntityRenderer::Sprite(..)) => {
-                // TODO: This is actually ok, how should we handle it?
-            }
-            None | Some(EntityRenderer::None) => {}
+            // TODO: This is actually ok, how should we handle it?
+            Some(EntityRenderer::Sprite(..)) => return,
+            None | Some(EntityRenderer::None) => return,
+        };
+
+        // the weapon's transforms were written with the other entities' by
+        // `update_uniform_buffers`
+        pass.set_bind_group(
+            BindGroupLayoutId::PerEntity as usize,
+            &state.world_bind_groups()[BindGroupLayoutId::PerEntity as usize],
+            &[self.viewmodel_uniform_block.offset()],
+        );
+        pass.set_render_pipeline(state.alias_pipeline().pipeline());
+        alias.record_draw(
+            state,
+            pass,
+            time,
+            viewmodel.frame_id,
+            viewmodel.frame_id,
+            0,
+            state.alias_pipeline().identity_instance_buffer(),
+            0..1,
+        );
+    }
+
+    /// Draw the alias model batches built by `update_uniform_buffers`.
+    fn record_alias_draws<'a>(
+        &'a self,
+        state: &'a GraphicsState,
+        pass: &mut TrackedRenderPass<'a>,
+        bump: &'a Bump,
+        camera: &Camera,
+        time: Duration,
+        cull_stats: &mut CullStats,
+    ) {
+        let alias_batches = self.alias_batches.read();
+        cull_stats.entities_culled += alias_batches.culled;
+        if alias_batches.batches.is_empty() {
+            return;
         }
 
-        debug!("Drawing particles");
-        state
-            .particle_pipeline()
-            .record_draw(pass, &bump, camera, particles);
+        // the models' own transforms are in the instances, so the alias shader takes the camera's
+        // from the world's uniforms
+        pass.set_bind_group(
+            BindGroupLayoutId::PerEntity as usize,
+            &state.world_bind_groups()[BindGroupLayoutId::PerEntity as usize],
+            &[self.world_uniform_block.offset()],
+        );
+        pass.set_render_pipeline(state.alias_pipeline().pipeline());
+
+        for batch in alias_batches.batches.iter() {
+            let Some(EntityRenderer::Alias(ref alias)) =
+                self.entity_renderers.get(batch.renderer_id)
+            else {
+                continue;
+            };
+
+            cull_stats.entities_drawn += batch.instances.len();
+            alias.record_draw(
+                state,
+                pass,
+                time,
+                batch.prev_keyframe_id,
+                batch.keyframe_id,
+                batch.skin_id,
+                state.alias_instance_buffer().buffer(),
+                batch.instances.clone(),
+            );
+        }
+
+        // the skeletal models go last so that the pipelines aren't switched back and forth
+        for batch in alias_batches.batches.iter() {
+            let Some(EntityRenderer::Iqm(ref iqm)) = self.entity_renderers.get(batch.renderer_id)
+            else {
+                continue;
+            };
+
+            cull_stats.entities_drawn += batch.instances.len();
+            iqm.record_draw(
+                state,
+                pass,
+                bump,
+                camera.view_projection(),
+                camera.view(),
+                time,
+                batch.prev_keyframe_id,
+                batch.keyframe_id,
+                state.alias_instance_buffer().buffer(),
+                batch.instances.clone(),
+            );
+        }
+    }
+
+    /// Draw liquids over what's been drawn so far, farthest first.
+    fn record_translucent_draws<'a>(
+        &'a self,
+        state: &'a GraphicsState,
+        pass: &mut TrackedRenderPass<'a>,
+        bump: &'a Bump,
+        time: Duration,
+        mut chains: Vec<TranslucentChain<'a>>,
+        water_alpha: f32,
+    ) {
+        use PushConstantUpdate::*;
+
+        chains.sort_by(|a, b| b.distance.total_cmp(&a.distance));
+
+        let alpha = water_alpha.clamp(0.0, 1.0);
+        pass.set_blend_constant(Color::rgba_linear(alpha, alpha, alpha, alpha));
+
+        let entity_uniform_blocks = self.entity_uniform_blocks.read();
+        for chain in chains {
+            let offset = match chain.ent_pos {
+                Some(ent_pos) => entity_uniform_blocks[ent_pos].offset(),
+                None => self.world_uniform_block.offset(),
+            };
+            pass.set_bind_group(
+                BindGroupLayoutId::PerEntity as usize,
+                &state.world_bind_groups()[BindGroupLayoutId::PerEntity as usize],
+                &[offset],
+            );
+
+            pass.set_render_pipeline(state.brush_pipeline().translucent_pipeline());
+            BrushPipeline::set_push_constants(
+                pass,
+                Update(bump.alloc(brush::VertexPushConstants {
+                    transform: chain.transform,
+                    model_view: chain.model_view,
+                })),
+                Clear,
+                Clear,
+            );
+            chain.renderer.record_translucent_draw(
+                state,
+                pass,
+                bump,
+                time,
+                chain.frame_id,
+                chain.tex_id,
+            );
+        }
     }
 
-    fn renderer_for_entity(&self, ent: &ClientEntity) -> &EntityRenderer {
+    fn renderer_for_entity(&self, ent: &RenderEntity) -> &EntityRenderer {
         // subtract 1 from index because world entity isn't counted
         match &self.entity_renderers.get(ent.model_id().saturating_sub(1)) {
             Some(r) => r,
@@ -614,19 +1080,42 @@ impl WorldRenderer {
         }
     }
 
-    fn calculate_mvp_transform(&self, camera: &Camera, entity: &ClientEntity) -> Matrix4<f32> {
+    /// Whether the model of `ent` is entirely out of view.
+    fn cull_entity(&self, camera: &Camera, ent: &RenderEntity) -> bool {
+        let Some((min, max)) = self.renderer_for_entity(ent).bounds() else {
+            return false;
+        };
+
+        let origin = ent.get_origin();
+        let angles = ent.get_angles();
+        if [angles.x, angles.y, angles.z].iter().all(|a| a.0 == 0.0) {
+            camera.cull_box(origin + min, origin + max)
+        } else {
+            // turned any which way, the model stays within its furthest corner from the origin
+            let corner = Vector3::new(
+                min.x.abs().max(max.x.abs()),
+                min.y.abs().max(max.y.abs()),
+                min.z.abs().max(max.z.abs()),
+            );
+            let radius = corner.magnitude();
+            let extent = Vector3::new(radius, radius, radius);
+            camera.cull_box(origin - extent, origin + extent)
+        }
+    }
+
+    fn calculate_mvp_transform(&self, camera: &Camera, entity: &RenderEntity) -> Matrix4<f32> {
         let model_transform = self.calculate_model_transform(camera, entity);
 
         camera.view_projection() * model_transform
     }
 
-    fn calculate_mv_transform(&self, camera: &Camera, entity: &ClientEntity) -> Matrix4<f32> {
+    fn calculate_mv_transform(&self, camera: &Camera, entity: &RenderEntity) -> Matrix4<f32> {
         let model_transform = self.calculate_model_transform(camera, entity);
 
         camera.view() * model_transform
     }
 
-    fn calculate_model_transform(&self, camera: &Camera, entity: &ClientEntity) -> Matrix4<f32> {
+    fn calculate_model_transform(&self, camera: &Camera, entity: &RenderEntity) -> Matrix4<f32> {
         let origin = entity.get_origin();
         let angles = entity.get_angles();
         let rotation = match self.renderer_for_entity(entity) {
@@ -653,3 +1142,51 @@ impl WorldRenderer {
         Matrix4::from_translation(Vector3::new(-origin.y, origin.z, -origin.x)) * rotation
     }
 }
+
+/// The weapon's model-view-projection and model transforms. It's squashed into the front of the
+/// depth range, so the projection differs from the camera's.
+fn viewmodel_transforms(camera: &Camera, viewmodel: &ViewModel) -> (Matrix4<f32>, Matrix4<f32>) {
+    let origin = viewmodel.origin;
+    let angles = viewmodel.angles;
+    let model = Matrix4::from_translation(Vector3::new(-origin.y, origin.z, -origin.x))
+        * Matrix4::from_angle_y(angles.yaw)
+        * Matrix4::from_angle_x(-angles.pitch)
+        * Matrix4::from_angle_z(angles.roll);
+    let projection =
+        Matrix4::from_nonuniform_scale(1.0, 1.0, VIEWMODEL_DEPTH_RANGE) * camera.projection();
+
+    (projection * camera.view() * model, model)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use cgmath::{Deg, Zero as _};
+
+    #[test]
+    fn test_cull_box() {
+        // looking down the x axis
+        let camera = Camera::new(
+            Vector3::zero(),
+            Angles::zero(),
+            cgmath::perspective(Deg(90.0), 1.0, 4.0, 4096.0),
+        );
+
+        let cube = |x: f32, y: f32, z: f32| {
+            let center = Vector3::new(x, y, z);
+            let extent = Vector3::new(8.0, 8.0, 8.0);
+            camera.cull_box(center - extent, center + extent)
+        };
+
+        assert!(!cube(100.0, 0.0, 0.0));
+        assert!(cube(-100.0, 0.0, 0.0));
+        assert!(cube(100.0, 200.0, 0.0));
+        assert!(cube(100.0, -200.0, 0.0));
+        assert!(cube(100.0, 0.0, 200.0));
+        assert!(cube(10000.0, 0.0, 0.0));
+
+        // straddling the edge of the view
+        assert!(!cube(100.0, 105.0, 0.0));
+    }
+}
@@ -1,28 +1,44 @@
-use std::{mem::size_of, num::NonZeroU32};
+//! Particles, drawn in one of two styles (`r_particles`).
+//!
+//! Classic particles are Quake's square dots, drawn into the G-buffer with the rest of the world.
+//! Enhanced particles are round, blended billboards, which can't go in the G-buffer since it holds
+//! one surface per pixel. They're drawn over the lit image at the end of the deferred pass instead,
+//! reading the initial pass's depth to hide the ones behind the world and to fade out the edges
+//! where they meet it.
+
+use std::{
+    mem::size_of,
+    num::{NonZeroU32, NonZeroU64},
+    slice,
+};
 
 use crate::{
     client::{
-        entity::particle::Particle,
+        entity::particle::{Particle, MAX_PARTICLES},
         render::{
             create_texture,
-            pipeline::{Pipeline, PushConstantUpdate},
+            pipeline::{Pipeline, PushConstantUpdate, ShaderCompiler},
+            stats::{self, Counter},
             world::{Camera, WorldPipelineBase},
-            Palette, TextureData,
+            DiffuseData, Palette, TextureData,
         },
     },
     common::{math::Angles, util::any_slice_as_bytes},
 };
 
+use beef::Cow;
 use bevy::render::{
     render_phase::TrackedRenderPass,
     render_resource::{
-        BindGroup, BindGroupLayout, BindGroupLayoutEntry, Buffer, RenderPipeline, Texture,
+        BindGroup, BindGroupLayout, BindGroupLayoutEntry, Buffer, RenderPipeline, Sampler, Texture,
+        TextureView,
     },
     renderer::{RenderDevice, RenderQueue},
 };
 use bumpalo::Bump;
 use cgmath::Matrix4;
 use lazy_static::lazy_static;
+use serde::Deserialize;
 
 lazy_static! {
     static ref VERTEX_BUFFER_ATTRIBUTES: [Vec<wgpu::VertexAttribute>; 1] = [
@@ -47,19 +63,23 @@ const PARTICLE_TEXTURE_PIXELS: [u8; 64] = [
     0, 0, 1, 1, 1, 1, 0, 0,
 ];
 
+/// The width and height of the enhanced particle texture.
+const SOFT_TEXTURE_SIZE: u32 = 32;
+
 pub struct ParticlePipeline {
     pipeline: RenderPipeline,
     bind_group_layouts: Vec<BindGroupLayout>,
     _textures: Vec<Texture>,
     vertex_buffer: Buffer,
     bind_group: BindGroup,
+    soft: SoftParticlePipeline,
 }
 
 impl ParticlePipeline {
     pub fn new(
         device: &RenderDevice,
         queue: &RenderQueue,
-        compiler: &mut shaderc::Compiler,
+        compiler: &mut ShaderCompiler,
         diffuse_format: wgpu::TextureFormat,
         normal_format: wgpu::TextureFormat,
         sample_count: u32,
@@ -138,19 +158,29 @@ impl ParticlePipeline {
             ],
         );
 
+        let soft = SoftParticlePipeline::new(
+            device,
+            queue,
+            compiler,
+            diffuse_format,
+            sample_count,
+            palette,
+        );
+
         ParticlePipeline {
             pipeline,
             bind_group_layouts,
             _textures: textures,
             bind_group,
             vertex_buffer,
+            soft,
         }
     }
 
     pub fn rebuild(
         &mut self,
         device: &RenderDevice,
-        compiler: &mut shaderc::Compiler,
+        compiler: &mut ShaderCompiler,
         diffuse_format: wgpu::TextureFormat,
         normal_format: wgpu::TextureFormat,
         sample_count: u32,
@@ -163,12 +193,19 @@ impl ParticlePipeline {
             sample_count,
             (diffuse_format, normal_format),
         );
+        self.soft
+            .rebuild(device, compiler, diffuse_format, sample_count);
     }
 
     pub fn pipeline(&self) -> &RenderPipeline {
         &self.pipeline
     }
 
+    /// The pipeline for enhanced particles.
+    pub fn soft(&self) -> &SoftParticlePipeline {
+        &self.soft
+    }
+
     pub fn bind_group_layouts(&self) -> &[BindGroupLayout] {
         &self.bind_group_layouts
     }
@@ -217,6 +254,7 @@ impl ParticlePipeline {
             );
 
             pass.draw(0..6, 0..1);
+            stats::count_draw(Counter::Particles, 1);
         }
     }
 }
@@ -359,3 +397,393 @@ pub const VERTICES: [ParticleVertex; 6] = [
         texcoord: [1.0, 1.0],
     },
 ];
+
+/// How enhanced particles are blended with what's behind them (`r_particleblend`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all(deserialize = "lowercase"))]
+pub enum ParticleBlend {
+    #[default]
+    Alpha = 0,
+    Additive = 1,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, bytemuck::Zeroable, bytemuck::Pod)]
+pub struct SoftParticleUniforms {
+    pub view_projection: [[f32; 4]; 4],
+    pub view: [[f32; 4]; 4],
+    pub inv_projection: [[f32; 4]; 4],
+    /// The brightness, the distance over which particles fade out into surfaces, the size and one
+    /// unused value.
+    pub params: [f32; 4],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Debug, bytemuck::Zeroable, bytemuck::Pod)]
+struct SoftParticleInstance {
+    origin: [f32; 3],
+    /// Linear RGB.
+    color: [f32; 3],
+}
+
+/// Enhanced particles, drawn in the deferred pass. See the module documentation.
+pub struct SoftParticlePipeline {
+    /// One pipeline for each `ParticleBlend`.
+    pipelines: [RenderPipeline; 2],
+    bind_group_layouts: Vec<BindGroupLayout>,
+    sampler: Sampler,
+    nearest_sampler: Sampler,
+    _texture: Texture,
+    texture_view: TextureView,
+    instance_buffer: Buffer,
+    uniform_buffer: Buffer,
+    /// The palette in linear RGB.
+    colors: [[f32; 3]; 256],
+}
+
+impl SoftParticlePipeline {
+    fn new(
+        device: &RenderDevice,
+        queue: &RenderQueue,
+        compiler: &mut ShaderCompiler,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+        palette: &Palette,
+    ) -> SoftParticlePipeline {
+        let (alpha_pipeline, bind_group_layouts) = Self::create(
+            device,
+            compiler,
+            &[],
+            sample_count,
+            (format, ParticleBlend::Alpha),
+        );
+        let additive_pipeline = Self::recreate(
+            device,
+            compiler,
+            bind_group_layouts.iter(),
+            sample_count,
+            (format, ParticleBlend::Additive),
+        );
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("soft particle sampler"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let nearest_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("soft particle depth sampler"),
+            ..Default::default()
+        });
+
+        let texture = create_texture(
+            device,
+            queue,
+            Some("soft particle texture"),
+            SOFT_TEXTURE_SIZE,
+            SOFT_TEXTURE_SIZE,
+            &TextureData::Diffuse(DiffuseData {
+                rgba: Cow::owned(soft_texture_rgba(SOFT_TEXTURE_SIZE)),
+            }),
+        );
+        let texture_view = texture.create_view(&Default::default());
+
+        let instance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("soft particle instance buffer"),
+            size: (MAX_PARTICLES * size_of::<SoftParticleInstance>()) as u64,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("soft particle uniform buffer"),
+            size: size_of::<SoftParticleUniforms>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        // the palette is in the same space as the textures, which are sampled as sRGB
+        let colors = palette
+            .rgb
+            .map(|rgb| rgb.map(|c| (c as f32 / 255.0).powf(2.2)));
+
+        SoftParticlePipeline {
+            pipelines: [alpha_pipeline, additive_pipeline],
+            bind_group_layouts,
+            sampler,
+            nearest_sampler,
+            _texture: texture,
+            texture_view,
+            instance_buffer,
+            uniform_buffer,
+            colors,
+        }
+    }
+
+    fn rebuild(
+        &mut self,
+        device: &RenderDevice,
+        compiler: &mut ShaderCompiler,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+    ) {
+        for blend in [ParticleBlend::Alpha, ParticleBlend::Additive] {
+            self.pipelines[blend as usize] = Self::recreate(
+                device,
+                compiler,
+                self.bind_group_layouts.iter(),
+                sample_count,
+                (format, blend),
+            );
+        }
+    }
+
+    /// Create the bind group for drawing over a world with the depth buffer `depth_buffer`.
+    pub fn create_bind_group(
+        &self,
+        device: &RenderDevice,
+        depth_buffer: &TextureView,
+    ) -> BindGroup {
+        device.create_bind_group(
+            Some("soft particle bind group"),
+            &self.bind_group_layouts[0],
+            &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.nearest_sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&self.texture_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(depth_buffer),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: &self.uniform_buffer,
+                        offset: 0,
+                        size: None,
+                    }),
+                },
+            ],
+        )
+    }
+
+    /// Draw `particles` over the lit world. `bind_group` is from `create_bind_group` with the
+    /// depth buffer that the world was drawn with.
+    pub fn record_draw<'a, 'b, P>(
+        &'a self,
+        queue: &RenderQueue,
+        pass: &mut TrackedRenderPass<'a>,
+        vertex_buffer: &'a Buffer,
+        bind_group: &'a BindGroup,
+        blend: ParticleBlend,
+        uniforms: SoftParticleUniforms,
+        particles: P,
+    ) where
+        P: Iterator<Item = &'b Particle>,
+    {
+        let instances = particles
+            .take(MAX_PARTICLES)
+            .map(|particle| {
+                let origin = particle.origin();
+                SoftParticleInstance {
+                    origin: [-origin.y, origin.z, -origin.x],
+                    color: self.colors[particle.color() as usize],
+                }
+            })
+            .collect::<Vec<_>>();
+        if instances.is_empty() {
+            return;
+        }
+
+        queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&instances));
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(slice::from_ref(&uniforms)),
+        );
+
+        pass.set_render_pipeline(&self.pipelines[blend as usize]);
+        pass.set_vertex_buffer(0, vertex_buffer.slice(..));
+        pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        pass.set_bind_group(0, bind_group, &[]);
+        pass.draw(0..VERTICES.len() as u32, 0..instances.len() as u32);
+        stats::count_draw(Counter::Particles, instances.len() as u64);
+    }
+}
+
+/// A white disc that's opaque in the middle and fades out toward the edge.
+fn soft_texture_rgba(size: u32) -> Vec<u8> {
+    let center = size as f32 / 2.0;
+    (0..size * size)
+        .flat_map(|i| {
+            let x = (i % size) as f32 + 0.5 - center;
+            let y = (i / size) as f32 + 0.5 - center;
+            let r = (x * x + y * y).sqrt() / center;
+            let alpha = ((1.0 - r) * 2.0).clamp(0.0, 1.0);
+            [0xFF, 0xFF, 0xFF, (alpha * 255.0).round() as u8]
+        })
+        .collect()
+}
+
+const SOFT_BIND_GROUP_LAYOUT_ENTRIES: &[wgpu::BindGroupLayoutEntry] = &[
+    wgpu::BindGroupLayoutEntry {
+        binding: 0,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+        count: None,
+    },
+    wgpu::BindGroupLayoutEntry {
+        binding: 1,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::NonFiltering),
+        count: None,
+    },
+    // particle texture
+    wgpu::BindGroupLayoutEntry {
+        binding: 2,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            view_dimension: wgpu::TextureViewDimension::D2,
+            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            multisampled: false,
+        },
+        count: None,
+    },
+    // depth buffer
+    wgpu::BindGroupLayoutEntry {
+        binding: 3,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            view_dimension: wgpu::TextureViewDimension::D2,
+            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+            multisampled: false,
+        },
+        count: None,
+    },
+    // uniform buffer
+    wgpu::BindGroupLayoutEntry {
+        binding: 4,
+        visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: NonZeroU64::new(size_of::<SoftParticleUniforms>() as u64),
+        },
+        count: None,
+    },
+];
+
+impl Pipeline for SoftParticlePipeline {
+    type VertexPushConstants = ();
+    type SharedPushConstants = ();
+    type FragmentPushConstants = ();
+
+    type Args = (wgpu::TextureFormat, ParticleBlend);
+
+    fn name() -> &'static str {
+        "soft_particle"
+    }
+
+    fn vertex_shader() -> &'static str {
+        include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/shaders/soft_particle.vert"
+        ))
+    }
+
+    fn fragment_shader() -> &'static str {
+        include_str!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/shaders/soft_particle.frag"
+        ))
+    }
+
+    fn bind_group_layout_descriptors() -> Vec<Vec<BindGroupLayoutEntry>> {
+        vec![SOFT_BIND_GROUP_LAYOUT_ENTRIES.to_owned()]
+    }
+
+    fn primitive_state() -> wgpu::PrimitiveState {
+        WorldPipelineBase::primitive_state()
+    }
+
+    fn color_target_states_with_args(
+        (format, blend): Self::Args,
+    ) -> Vec<Option<wgpu::ColorTargetState>> {
+        let color = wgpu::BlendComponent {
+            src_factor: wgpu::BlendFactor::SrcAlpha,
+            dst_factor: match blend {
+                ParticleBlend::Alpha => wgpu::BlendFactor::OneMinusSrcAlpha,
+                ParticleBlend::Additive => wgpu::BlendFactor::One,
+            },
+            operation: wgpu::BlendOperation::Add,
+        };
+
+        vec![Some(wgpu::ColorTargetState {
+            format,
+            blend: Some(wgpu::BlendState {
+                color,
+                alpha: wgpu::BlendComponent {
+                    src_factor: wgpu::BlendFactor::Zero,
+                    dst_factor: wgpu::BlendFactor::One,
+                    operation: wgpu::BlendOperation::Add,
+                },
+            }),
+            write_mask: wgpu::ColorWrites::ALL,
+        })]
+    }
+
+    // particles behind the world are hidden by the fragment shader, since the depth buffer is
+    // read from there
+    fn depth_stencil_state() -> Option<wgpu::DepthStencilState> {
+        None
+    }
+
+    fn vertex_buffer_layouts() -> Vec<wgpu::VertexBufferLayout<'static>> {
+        vec![
+            wgpu::VertexBufferLayout {
+                array_stride: size_of::<ParticleVertex>() as u64,
+                step_mode: wgpu::VertexStepMode::Vertex,
+                attributes: &VERTEX_ATTRIBUTES[0],
+            },
+            wgpu::VertexBufferLayout {
+                array_stride: size_of::<SoftParticleInstance>() as u64,
+                step_mode: wgpu::VertexStepMode::Instance,
+                attributes: &SOFT_INSTANCE_ATTRIBUTES[..],
+            },
+        ]
+    }
+}
+
+lazy_static! {
+    static ref SOFT_INSTANCE_ATTRIBUTES: [wgpu::VertexAttribute; 2] = wgpu::vertex_attr_array![
+        // origin
+        2 => Float32x3,
+        // color
+        3 => Float32x3,
+    ];
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_soft_texture_rgba() {
+        let rgba = soft_texture_rgba(4);
+        let alpha = |x: usize, y: usize| rgba[(y * 4 + x) * 4 + 3];
+
+        // opaque in the middle, clear in the corners
+        assert_eq!(alpha(1, 1), 0xFF);
+        assert_eq!(alpha(2, 2), 0xFF);
+        assert_eq!(alpha(0, 0), 0);
+        assert_eq!(alpha(3, 3), 0);
+    }
+}
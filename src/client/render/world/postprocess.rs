@@ -1,33 +1,59 @@
 use std::{mem::size_of, num::NonZeroU64};
 
 use bevy::{
-    core_pipeline::fullscreen_vertex_shader::fullscreen_shader_vertex_state,
+    core_pipeline::{
+        fullscreen_vertex_shader::fullscreen_shader_vertex_state, prepass::ViewPrepassTextures,
+    },
     prelude::*,
     render::{
         extract_resource::ExtractResource,
         render_graph::{RenderLabel, ViewNode},
         render_phase::TrackedRenderPass,
         render_resource::{
-            BindGroup, BindGroupLayout, Buffer, CachedRenderPipelineId, FragmentState,
-            PipelineCache, RenderPassDescriptor, RenderPipeline, RenderPipelineDescriptor,
-            ShaderDefVal, SpecializedRenderPipeline, SpecializedRenderPipelines,
+            BindGroup, BindGroupLayout, BindGroupLayoutId, Buffer, CachedRenderPipelineId,
+            FragmentState, PipelineCache, RenderPassDescriptor, RenderPipeline,
+            RenderPipelineDescriptor, ShaderDefVal, SpecializedRenderPipeline,
+            SpecializedRenderPipelines, TextureView, TextureViewId,
         },
         renderer::{RenderDevice, RenderQueue},
+        texture::{CachedTexture, ColorAttachment},
         view::{PostProcessWrite, ViewTarget},
     },
 };
+use hashbrown::HashMap;
+use parking_lot::Mutex;
 use serde::Deserialize;
 use wgpu::{BindGroupLayoutEntry, BlendState, ColorTargetState, ColorWrites, PrimitiveState};
 
 use crate::{
-    client::render::{pipeline::Pipeline, ui::quad::QuadPipeline, GraphicsState, RenderState},
-    common::{console::Registry, net::ColorShift, util::any_as_bytes},
+    client::{
+        photo::PhotoMode,
+        render::{
+            pipeline::Pipeline,
+            stats::{self, Counter},
+            ui::quad::QuadPipeline,
+            GraphicsState, RenderResolution, RenderState, RenderVars,
+        },
+        view::Fov,
+        ColorShiftCode,
+    },
+    common::{console::Registry, engine, net::ColorShift, util::any_as_bytes},
 };
 
 #[repr(C, align(256))]
 #[derive(Clone, Copy, Debug, Default)]
 pub struct PostProcessUniforms {
     pub color_shift: [[f32; 4]; 4],
+    pub inv_projection: [[f32; 4]; 4],
+    /// The distance in focus and the aperture, which is 0 for no depth of field.
+    pub depth_of_field: [f32; 4],
+    /// The fraction of the input textures the world was drawn to in x and y, from `r_scale`.
+    pub scale: [f32; 4],
+    /// `gamma`, `contrast`, `r_vignette` and `r_dither`.
+    pub effects: [f32; 4],
+    /// The client time in seconds and the strength of the underwater warp, which is 0 when the
+    /// view isn't underwater.
+    pub warp: [f32; 4],
 }
 
 #[derive(Resource)]
@@ -141,6 +167,17 @@ const BIND_GROUP_LAYOUT_ENTRIES: &[wgpu::BindGroupLayoutEntry] = &[
         },
         count: None,
     },
+    // depth buffer, for depth of field
+    wgpu::BindGroupLayoutEntry {
+        binding: 3,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            view_dimension: wgpu::TextureViewDimension::D2,
+            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+            multisampled: false,
+        },
+        count: None,
+    },
 ];
 
 impl Pipeline for PostProcessPipeline {
@@ -179,11 +216,11 @@ impl Pipeline for PostProcessPipeline {
     }
 
     fn vertex_buffer_layouts() -> Vec<wgpu::VertexBufferLayout<'static>> {
-        QuadPipeline::vertex_buffer_layouts()
+        vec![QuadPipeline::vertex_layout()]
     }
 }
 
-#[derive(Resource)]
+#[derive(Resource, Clone)]
 pub struct PostProcessBindGroup {
     bind_group: BindGroup,
 }
@@ -194,6 +231,7 @@ impl PostProcessBindGroup {
         state: &GraphicsState,
         post_pipeline: &PostProcessPipeline,
         color_buffer: &wgpu::TextureView,
+        depth_buffer: &wgpu::TextureView,
     ) -> Self {
         Self {
             bind_group: device.create_bind_group(
@@ -219,6 +257,11 @@ impl PostProcessBindGroup {
                             size: None,
                         }),
                     },
+                    // depth buffer
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: wgpu::BindingResource::TextureView(depth_buffer),
+                    },
                 ],
             ),
         }
@@ -229,11 +272,10 @@ impl PostProcessBindGroup {
         &self,
         queue: &RenderQueue,
         post_pipeline: &PostProcessPipeline,
-        color_shift: [[f32; 4]; 4],
+        uniforms: PostProcessUniforms,
     ) {
-        // update color shift
         queue.write_buffer(&post_pipeline.uniform_buffer, 0, unsafe {
-            any_as_bytes(&PostProcessUniforms { color_shift })
+            any_as_bytes(&uniforms)
         });
     }
 
@@ -245,6 +287,57 @@ impl PostProcessBindGroup {
         pass.set_render_pipeline(pipeline);
         pass.set_bind_group(0, &self.bind_group, &[]);
         pass.draw(0..3, 0..1);
+        stats::add(Counter::DrawCalls, 1);
+    }
+}
+
+/// The resources a `PostProcessBindGroup` refers to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct PostProcessInputs {
+    layout: BindGroupLayoutId,
+    color: TextureViewId,
+    depth: TextureViewId,
+}
+
+/// Post-processing bind groups, kept between frames for as long as their input textures exist.
+///
+/// Like the deferred pass, the color input alternates between the view target's two main
+/// textures, so there are normally two of these.
+#[derive(Resource, Default)]
+pub struct PostProcessBindGroups {
+    bind_groups: Mutex<HashMap<PostProcessInputs, PostProcessBindGroup>>,
+}
+
+impl PostProcessBindGroups {
+    fn get_or_create(
+        &self,
+        device: &RenderDevice,
+        state: &GraphicsState,
+        post_pipeline: &PostProcessPipeline,
+        color_buffer: &TextureView,
+        depth_buffer: &TextureView,
+    ) -> PostProcessBindGroup {
+        let inputs = PostProcessInputs {
+            layout: post_pipeline.bind_group_layouts[0].id(),
+            color: color_buffer.id(),
+            depth: depth_buffer.id(),
+        };
+
+        let mut bind_groups = self.bind_groups.lock();
+        if let Some(bind_group) = bind_groups.get(&inputs) {
+            return bind_group.clone();
+        }
+
+        // see `DeferredRenderers::get_or_create`
+        if bind_groups.len() >= 2 {
+            bind_groups.clear();
+        }
+
+        let bind_group =
+            PostProcessBindGroup::new(device, state, post_pipeline, color_buffer, depth_buffer);
+        bind_groups.insert(inputs, bind_group.clone());
+
+        bind_group
     }
 }
 
@@ -310,6 +403,90 @@ impl ExtractResource for PostProcessVars {
     }
 }
 
+/// Kept apart from `PostProcessVars` since it doesn't affect the pipeline.
+#[derive(Clone, Copy, Debug, Resource, Deserialize)]
+pub struct FlashVars {
+    #[serde(rename(deserialize = "v_flashscale"))]
+    flash_scale: f32,
+}
+
+impl Default for FlashVars {
+    fn default() -> Self {
+        Self { flash_scale: 1.0 }
+    }
+}
+
+impl ExtractResource for FlashVars {
+    type Source = Registry;
+
+    fn extract_resource(source: &Self::Source) -> Self {
+        source.read_cvars().unwrap_or_default()
+    }
+}
+
+/// The effects applied after the color shift, in the order they're applied in.
+#[derive(Clone, Copy, Debug, PartialEq, Resource, Deserialize)]
+pub struct EffectVars {
+    contrast: f32,
+    gamma: f32,
+    #[serde(rename(deserialize = "r_vignette"))]
+    vignette: f32,
+    #[serde(rename(deserialize = "r_dither"))]
+    dither: f32,
+    #[serde(rename(deserialize = "r_waterwarp"))]
+    water_warp: f32,
+}
+
+impl Default for EffectVars {
+    fn default() -> Self {
+        Self {
+            contrast: 1.0,
+            gamma: 1.0,
+            vignette: 0.0,
+            dither: 0.0,
+            water_warp: 1.0,
+        }
+    }
+}
+
+impl EffectVars {
+    fn uniform(&self) -> [f32; 4] {
+        [
+            self.gamma.clamp(0.1, 4.0),
+            self.contrast.clamp(0.0, 4.0),
+            self.vignette.clamp(0.0, 1.0),
+            if self.dither != 0.0 { 1.0 } else { 0.0 },
+        ]
+    }
+
+    /// Whether the effects leave the screen as it is, so they don't need a pass of their own.
+    fn is_identity(&self) -> bool {
+        self.uniform() == Self::default().uniform()
+    }
+
+    /// Like the surfaces of liquids, the screen is warped with sine waves while underwater.
+    fn warp(&self, state: &RenderState) -> [f32; 4] {
+        if !state.underwater() {
+            return [0.0; 4];
+        }
+
+        [
+            engine::duration_to_f32(state.time()),
+            self.water_warp.clamp(0.0, 4.0),
+            0.0,
+            0.0,
+        ]
+    }
+}
+
+impl ExtractResource for EffectVars {
+    type Source = Registry;
+
+    fn extract_resource(source: &Self::Source) -> Self {
+        source.read_cvars().unwrap_or_default()
+    }
+}
+
 #[derive(Debug, Hash, PartialEq, Eq, Clone, RenderLabel)]
 pub struct PostProcessPassLabel;
 
@@ -319,7 +496,7 @@ pub struct PostProcessPass {
 }
 
 impl ViewNode for PostProcessPass {
-    type ViewQuery = &'static ViewTarget;
+    type ViewQuery = (&'static ViewTarget, &'static ViewPrepassTextures);
 
     fn update(&mut self, world: &mut World) {
         let world = world.cell();
@@ -339,10 +516,13 @@ impl ViewNode for PostProcessPass {
         &self,
         _graph: &mut bevy::render::render_graph::RenderGraphContext,
         render_context: &mut bevy::render::renderer::RenderContext<'w>,
-        target: &ViewTarget,
+        (target, prepass): (&ViewTarget, &ViewPrepassTextures),
         world: &'w bevy::prelude::World,
     ) -> Result<(), bevy::render::render_graph::NodeRunError> {
-        let gfx_state = world.resource::<GraphicsState>();
+        profile_span!("postprocess_pass");
+        let Some(gfx_state) = world.get_resource::<GraphicsState>() else {
+            return Ok(());
+        };
         let queue = world.resource::<RenderQueue>();
         let pipeline_cache = world.resource::<PipelineCache>();
         let post_pipeline = world.resource::<PostProcessPipeline>();
@@ -359,15 +539,64 @@ impl ViewNode for PostProcessPass {
             return Ok(());
         };
 
-        if conn
-            .state
-            .color_shifts
+        // damage and pickup flashes can be toned down, but not underwater or powerup tints
+        let flash_scale = world
+            .get_resource::<FlashVars>()
+            .map_or(1.0, |vars| vars.flash_scale.clamp(0.0, 1.0));
+        let mut color_shifts = *conn.color_shifts();
+        for code in [ColorShiftCode::Damage, ColorShiftCode::Bonus] {
+            let shift = &mut color_shifts[code as usize];
+            shift.percent = (shift.percent as f32 * flash_scale) as i32;
+        }
+
+        let depth_of_field = world
+            .get_resource::<PhotoMode>()
+            .and_then(|photo| photo.camera())
+            .filter(|camera| camera.aperture > 0.0)
+            .map_or([0.0; 4], |camera| [camera.focus, camera.aperture, 0.0, 0.0]);
+
+        let Some(&RenderResolution(width, height)) = world.get_resource::<RenderResolution>()
+        else {
+            return Ok(());
+        };
+        let (scaled_width, scaled_height) = world
+            .get_resource::<RenderVars>()
+            .map_or((width, height), |vars| vars.scaled_size(width, height));
+        let scaled = (scaled_width, scaled_height) != (width, height);
+
+        let effects = world
+            .get_resource::<EffectVars>()
+            .copied()
+            .unwrap_or_default();
+        let warp = effects.warp(conn);
+
+        // a world drawn at a lower resolution always needs to be stretched to fill the window
+        if color_shifts
             .iter()
             .all(|ColorShift { percent, .. }| *percent == 0)
+            && depth_of_field[1] == 0.0
+            && !scaled
+            && effects.is_identity()
+            && warp[1] == 0.0
         {
             return Ok(());
         }
 
+        let Some(ColorAttachment {
+            texture:
+                CachedTexture {
+                    default_view: depth_input,
+                    ..
+                },
+            ..
+        }) = &prepass.depth
+        else {
+            return Ok(());
+        };
+
+        let fov = world.resource::<Fov>();
+        let camera = conn.camera(width as f32 / height as f32, fov.0);
+
         let PostProcessWrite {
             source: diffuse_input,
             destination: diffuse_target,
@@ -375,12 +604,15 @@ impl ViewNode for PostProcessPass {
 
         let pipeline = pipeline_cache.get_render_pipeline(*pipeline_id).unwrap();
 
-        // TODO: Cache
-        let bind_group = PostProcessBindGroup::new(
+        let Some(bind_groups) = world.get_resource::<PostProcessBindGroups>() else {
+            return Ok(());
+        };
+        let bind_group = bind_groups.get_or_create(
             render_context.render_device(),
             gfx_state,
             post_pipeline,
             diffuse_input,
+            depth_input,
         );
 
         let mut post_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
@@ -396,15 +628,28 @@ impl ViewNode for PostProcessPass {
         bind_group.update_uniform_buffers(
             queue,
             post_pipeline,
-            conn.state
-                .color_shifts
-                .map(
-                    |ColorShift {
-                         dest_color: [r, g, b],
-                         percent,
-                     }| [r, g, b, ((percent * 256) / 100).min(255) as u8],
-                )
-                .map(|rgba| rgba.map(|v| v as f32 / 255.)),
+            PostProcessUniforms {
+                color_shift: color_shifts
+                    .map(
+                        |ColorShift {
+                             dest_color: [r, g, b],
+                             percent,
+                         }| {
+                            [r, g, b, ((percent * 256) / 100).min(255) as u8]
+                        },
+                    )
+                    .map(|rgba| rgba.map(|v| v as f32 / 255.)),
+                inv_projection: camera.inverse_projection().into(),
+                depth_of_field,
+                scale: [
+                    scaled_width as f32 / width as f32,
+                    scaled_height as f32 / height as f32,
+                    0.0,
+                    0.0,
+                ],
+                effects: effects.uniform(),
+                warp,
+            },
         );
         bind_group.record_draw(pipeline, &mut post_pass);
 
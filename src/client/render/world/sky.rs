@@ -0,0 +1,170 @@
+//! The skybox drawn on sky brushes, see [`crate::client::sky`].
+//!
+//! The six faces are packed into one texture, three across and two down in the order of
+//! [`FACE_SUFFIXES`], so that the skybox can be drawn with the same bind group layout as any other
+//! brush texture. The brush shader picks the face and the position in it from the direction of
+//! each pixel from the camera.
+
+use std::io::Read as _;
+
+use beef::Cow;
+use bevy::{
+    log::warn,
+    render::{
+        render_resource::{BindGroup, Texture},
+        renderer::{RenderDevice, RenderQueue},
+    },
+};
+use failure::{format_err, Error};
+use image::{imageops::FilterType, RgbaImage};
+
+use crate::{
+    client::{
+        render::{world::brush, DiffuseData, FullbrightData, GraphicsState, TextureData},
+        sky::{self, FACE_SUFFIXES},
+    },
+    common::vfs::Vfs,
+};
+
+const ATLAS_COLUMNS: u32 = 3;
+const ATLAS_ROWS: u32 = 2;
+
+pub struct Skybox {
+    bind_group: BindGroup,
+    _diffuse: Texture,
+    _fullbright: Texture,
+}
+
+impl Skybox {
+    pub fn load(
+        state: &GraphicsState,
+        device: &RenderDevice,
+        queue: &RenderQueue,
+        vfs: &Vfs,
+        name: &str,
+    ) -> Result<Skybox, Error> {
+        let faces = sky::find_faces(vfs, name)?
+            .into_iter()
+            .map(|path| {
+                let mut data = Vec::new();
+                vfs.open(&path)?.read_to_end(&mut data)?;
+                let image = image::load_from_memory(&data)
+                    .map_err(|e| format_err!("couldn't load {}: {}", path, e))?;
+                Ok(image.into_rgba8())
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        // the atlas is wider than it is tall, so its width is what has to fit
+        let max_size = device.limits().max_texture_dimension_2d / ATLAS_COLUMNS;
+        let (width, height, rgba) = atlas(&faces, max_size);
+        let diffuse = state.create_diffuse_texture(
+            device,
+            queue,
+            Some("skybox"),
+            width,
+            height,
+            DiffuseData {
+                rgba: Cow::owned(rgba),
+            },
+        );
+        // the sky isn't lit, so there's nothing to mark as fullbright
+        let fullbright = state.create_texture(
+            device,
+            queue,
+            None,
+            1,
+            1,
+            &TextureData::Fullbright(FullbrightData {
+                fullbright: Cow::owned(vec![0]),
+            }),
+        );
+
+        let bind_group = brush::per_texture_bind_group(
+            state,
+            device,
+            &diffuse.create_view(&Default::default()),
+            &fullbright.create_view(&Default::default()),
+        );
+
+        Ok(Skybox {
+            bind_group,
+            _diffuse: diffuse,
+            _fullbright: fullbright,
+        })
+    }
+
+    pub fn bind_group(&self) -> &BindGroup {
+        &self.bind_group
+    }
+}
+
+/// Pack the faces into one RGBA image. The faces are all scaled to the size of the largest one,
+/// or down to `max_size` if that's smaller.
+fn atlas(faces: &[RgbaImage], max_size: u32) -> (u32, u32, Vec<u8>) {
+    let largest = faces
+        .iter()
+        .map(|face| face.width().max(face.height()))
+        .max()
+        .unwrap_or(1);
+    let size = largest.min(max_size).max(1);
+    if size < largest {
+        warn!(
+            "Skybox faces are {0}x{0}, which is too big for this GPU; scaling them down to {1}x{1}",
+            largest, size
+        );
+    }
+
+    let mut atlas = RgbaImage::new(size * ATLAS_COLUMNS, size * ATLAS_ROWS);
+    for (i, face) in faces.iter().take(FACE_SUFFIXES.len()).enumerate() {
+        let i = i as u32;
+        let resized;
+        let face = if face.dimensions() == (size, size) {
+            face
+        } else {
+            resized = image::imageops::resize(face, size, size, FilterType::Triangle);
+            &resized
+        };
+
+        image::imageops::replace(
+            &mut atlas,
+            face,
+            ((i % ATLAS_COLUMNS) * size) as i64,
+            ((i / ATLAS_COLUMNS) * size) as i64,
+        );
+    }
+
+    (atlas.width(), atlas.height(), atlas.into_raw())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_atlas() {
+        let faces = (0..6u8)
+            .map(|i| RgbaImage::from_pixel(2, 2, image::Rgba([i, 0, 0, 255])))
+            .collect::<Vec<_>>();
+
+        let (width, height, rgba) = atlas(&faces, 1024);
+        assert_eq!((width, height), (6, 4));
+
+        // the red channel of the top-left texel of each face is its index
+        let texel = |x: u32, y: u32| rgba[((y * width + x) * 4) as usize];
+        assert_eq!(
+            [
+                texel(0, 0),
+                texel(2, 0),
+                texel(4, 0),
+                texel(0, 2),
+                texel(2, 2),
+                texel(4, 2)
+            ],
+            [0, 1, 2, 3, 4, 5]
+        );
+
+        // faces that are too big for the GPU are scaled down
+        let (width, height, _) = atlas(&faces, 1);
+        assert_eq!((width, height), (3, 2));
+    }
+}
@@ -2,8 +2,9 @@ use std::mem::size_of;
 
 use crate::{
     client::render::{
+        stats::{self, Counter},
         world::{BindGroupLayoutId, WorldPipelineBase},
-        GraphicsState, Pipeline, TextureData,
+        GraphicsState, Pipeline, ShaderCompiler, TextureData,
     },
     common::{
         sprite::{SpriteFrame, SpriteKind, SpriteModel, SpriteSubframe},
@@ -22,6 +23,7 @@ use bevy::{
         renderer::{RenderDevice, RenderQueue},
     },
 };
+use cgmath::Vector3;
 use chrono::Duration;
 use lazy_static::lazy_static;
 
@@ -34,7 +36,7 @@ pub struct SpritePipeline {
 impl SpritePipeline {
     pub fn new(
         device: &RenderDevice,
-        compiler: &mut shaderc::Compiler,
+        compiler: &mut ShaderCompiler,
         world_bind_group_layouts: &[BindGroupLayout],
         diffuse_format: wgpu::TextureFormat,
         normal_format: wgpu::TextureFormat,
@@ -64,7 +66,7 @@ impl SpritePipeline {
     pub fn rebuild(
         &mut self,
         device: &RenderDevice,
-        compiler: &mut shaderc::Compiler,
+        compiler: &mut ShaderCompiler,
         diffuse_format: wgpu::TextureFormat,
         normal_format: wgpu::TextureFormat,
         world_bind_group_layouts: &[BindGroupLayout],
@@ -326,6 +328,8 @@ impl Frame {
 #[derive(Component)]
 pub struct SpriteRenderer {
     kind: SpriteKind,
+    min: Vector3<f32>,
+    max: Vector3<f32>,
     frames: Vec<Frame>,
 }
 
@@ -343,6 +347,8 @@ impl SpriteRenderer {
 
         SpriteRenderer {
             kind: sprite.kind(),
+            min: sprite.min(),
+            max: sprite.max(),
             frames,
         }
     }
@@ -362,9 +368,18 @@ impl SpriteRenderer {
             &[],
         );
         pass.draw(0..VERTICES.len() as u32, 0..1);
+        stats::count_draw(Counter::AliasPolys, (VERTICES.len() / 3) as u64);
     }
 
     pub fn kind(&self) -> SpriteKind {
         self.kind
     }
+
+    pub fn min(&self) -> Vector3<f32> {
+        self.min
+    }
+
+    pub fn max(&self) -> Vector3<f32> {
+        self.max
+    }
 }
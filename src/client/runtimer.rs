@@ -0,0 +1,400 @@
+//! The speedrun timer (`scr_runtimer`).
+//!
+//! A run starts when a map begins and the map's split is recorded when it reaches intermission.
+//! If another map begins straight after, the run carries on from where it stopped, so an episode
+//! or a full game is timed as one run with a split per map. Beginning a map without finishing the
+//! last one (e.g. restarting after dying) starts a new attempt.
+//!
+//! The best run starting from each map is kept in `runtimer/<map>.txt` in the game directory, and
+//! splits are shown against it.
+
+use std::{
+    fmt::Write as _,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use bevy::{prelude::*, render::extract_resource::ExtractResource};
+use chrono::Duration;
+use clap::Parser;
+
+use crate::common::{
+    console::{ExecResult, RegisterCmdExt},
+    vfs::BASE_GAME,
+};
+
+use super::{Connection, ConnectionState, SeismonGameSettings};
+
+/// A completed map in a run.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Split {
+    pub map: String,
+
+    /// The run time when the map was finished.
+    pub time: Duration,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Phase {
+    /// No run is in progress.
+    Idle,
+
+    /// A map is being timed.
+    Running {
+        map: String,
+        /// The client time when the map began.
+        started: Duration,
+    },
+
+    /// The last map reached intermission and the run is waiting for the next map.
+    Finished { map: String },
+}
+
+#[derive(Resource, ExtractResource, Clone)]
+pub struct RunTimer {
+    phase: Phase,
+    splits: Vec<Split>,
+    elapsed: Duration,
+    best: Vec<Split>,
+
+    /// Whether this run can replace the personal best. Demo playback is timed but not saved.
+    record: bool,
+}
+
+impl Default for RunTimer {
+    fn default() -> Self {
+        Self {
+            phase: Phase::Idle,
+            splits: Vec::new(),
+            elapsed: Duration::zero(),
+            best: Vec::new(),
+            record: false,
+        }
+    }
+}
+
+impl RunTimer {
+    /// The total time of the run so far.
+    pub fn elapsed(&self) -> Duration {
+        self.elapsed
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.phase != Phase::Idle
+    }
+
+    pub fn is_running(&self) -> bool {
+        matches!(self.phase, Phase::Running { .. })
+    }
+
+    /// The maps completed so far in this run.
+    pub fn splits(&self) -> &[Split] {
+        &self.splits
+    }
+
+    /// The map currently being timed, if any.
+    pub fn current_map(&self) -> Option<&str> {
+        match &self.phase {
+            Phase::Running { map, .. } => Some(map),
+            _ => None,
+        }
+    }
+
+    /// The difference between the split at `index` and the personal best for the same map, if
+    /// the personal best got that far on the same route.
+    pub fn split_delta(&self, index: usize) -> Option<Duration> {
+        let split = self.splits.get(index)?;
+        let best = self.best.get(index)?;
+        (best.map == split.map).then(|| split.time - best.time)
+    }
+
+    /// The difference between the current time and the personal best at the end of the current
+    /// map. Only available once the run is behind, so that it doesn't give away the best time.
+    pub fn running_delta(&self) -> Option<Duration> {
+        let map = self.current_map()?;
+        let best = self.best.get(self.splits.len())?;
+        let delta = self.elapsed - best.time;
+        (best.map == map && delta > Duration::zero()).then_some(delta)
+    }
+
+    /// Start timing `map`, either continuing the run or starting a new attempt.
+    fn begin_map(&mut self, map: &str, now: Duration, record: bool, dir: Option<&Path>) {
+        let continues = matches!(&self.phase, Phase::Finished { map: last } if last != map);
+        if !continues {
+            self.splits.clear();
+            self.best = dir
+                .and_then(|dir| match load_splits(&pb_path(dir, map)) {
+                    Ok(best) => Some(best),
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+                    Err(e) => {
+                        warn!("Couldn't load personal best for {}: {}", map, e);
+                        None
+                    }
+                })
+                .unwrap_or_default();
+            self.record = record;
+        }
+
+        self.phase = Phase::Running {
+            map: map.to_owned(),
+            started: now,
+        };
+        self.update(now);
+    }
+
+    /// Update the running time for the client time `now`.
+    fn update(&mut self, now: Duration) {
+        if let Phase::Running { started, .. } = &self.phase {
+            let offset = self
+                .splits
+                .last()
+                .map(|s| s.time)
+                .unwrap_or_else(Duration::zero);
+            self.elapsed = offset + (now - *started).max(Duration::zero());
+        }
+    }
+
+    /// Record the split for the current map, which finished at client time `completed`.
+    fn finish_map(&mut self, completed: Duration, dir: Option<&Path>) {
+        self.update(completed);
+        let Phase::Running { map, .. } = std::mem::replace(&mut self.phase, Phase::Idle) else {
+            return;
+        };
+
+        self.splits.push(Split {
+            map: map.clone(),
+            time: self.elapsed,
+        });
+        self.phase = Phase::Finished { map };
+
+        if self.record && is_better(&self.splits, &self.best) {
+            self.best = self.splits.clone();
+
+            if let Some(dir) = dir {
+                let path = pb_path(dir, &self.splits[0].map);
+                if let Err(e) = save_splits(&path, &self.splits) {
+                    warn!("Couldn't save personal best to {}: {}", path.display(), e);
+                }
+            }
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = default();
+    }
+}
+
+/// A run is better than another if it got further, or got as far in less time.
+fn is_better(run: &[Split], best: &[Split]) -> bool {
+    match (run.last(), best.last()) {
+        (_, None) => true,
+        (None, Some(_)) => false,
+        (Some(run_end), Some(best_end)) => {
+            run.len() > best.len() || (run.len() == best.len() && run_end.time < best_end.time)
+        }
+    }
+}
+
+fn pb_path(dir: &Path, first_map: &str) -> PathBuf {
+    dir.join(format!("{}.txt", first_map))
+}
+
+/// Load splits saved by `save_splits`, one `<map> <milliseconds>` pair per line.
+fn load_splits(path: &Path) -> io::Result<Vec<Split>> {
+    parse_splits(&fs::read_to_string(path)?)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed splits"))
+}
+
+fn parse_splits(text: &str) -> Option<Vec<Split>> {
+    text.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            let (map, ms) = line.trim().split_once(' ')?;
+            Some(Split {
+                map: map.to_owned(),
+                time: Duration::try_milliseconds(ms.trim().parse().ok()?)?,
+            })
+        })
+        .collect()
+}
+
+fn save_splits(path: &Path, splits: &[Split]) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut out = String::new();
+    for split in splits {
+        writeln!(out, "{} {}", split.map, split.time.num_milliseconds()).unwrap();
+    }
+
+    fs::write(path, out)
+}
+
+/// Format a run time as `m:ss.cc`, or `h:mm:ss.cc` for runs of an hour or more.
+pub fn format_time(time: Duration) -> String {
+    let centis = time.num_milliseconds().abs() / 10;
+    let (hours, minutes, seconds) = (centis / 360_000, centis / 6_000 % 60, centis / 100 % 60);
+    let sign = if time < Duration::zero() { "-" } else { "" };
+
+    if hours > 0 {
+        format!(
+            "{}{}:{:02}:{:02}.{:02}",
+            sign,
+            hours,
+            minutes,
+            seconds,
+            centis % 100
+        )
+    } else {
+        format!("{}{}:{:02}.{:02}", sign, minutes, seconds, centis % 100)
+    }
+}
+
+/// Format the difference from a personal best, always with a sign.
+pub fn format_delta(delta: Duration) -> String {
+    if delta < Duration::zero() {
+        format_time(delta)
+    } else {
+        format!("+{}", format_time(delta))
+    }
+}
+
+pub fn register_cvars(app: &mut App) {
+    app.cvar(
+        "scr_runtimer",
+        "0",
+        "show the speedrun timer with splits against your personal best",
+    );
+}
+
+pub fn register_commands(app: &mut App) {
+    #[derive(Parser)]
+    #[command(
+        name = "runtimer_reset",
+        about = "Discard the current run so that the next map starts a new one"
+    )]
+    struct RunTimerReset;
+
+    app.command(
+        |In(RunTimerReset), mut timer: ResMut<RunTimer>| -> ExecResult {
+            timer.reset();
+            default()
+        },
+    );
+}
+
+pub mod systems {
+    use super::*;
+
+    pub fn update_run_timer(
+        settings: Res<SeismonGameSettings>,
+        conn: Option<Res<Connection>>,
+        conn_state: Res<ConnectionState>,
+        mut timer: ResMut<RunTimer>,
+        mut was_connected: Local<bool>,
+    ) {
+        let connected = matches!(&*conn_state, ConnectionState::Connected(_));
+        let just_connected = connected && !*was_connected;
+        *was_connected = connected;
+
+        let Some(conn) = conn.filter(|_| connected) else {
+            return;
+        };
+        let state = &conn.state;
+
+        let dir = cfg!(not(target_arch = "wasm32")).then(|| {
+            settings
+                .base_dir
+                .join(settings.game.as_deref().unwrap_or(BASE_GAME))
+                .join("runtimer")
+        });
+
+        if just_connected {
+            timer.begin_map(
+                &state.map_name,
+                state.start_time,
+                !conn.kind.is_demo(),
+                dir.as_deref(),
+            );
+        }
+
+        if !timer.is_running() {
+            return;
+        }
+
+        match state.completion_time {
+            Some(completed) if state.intermission.is_some() => {
+                timer.finish_map(completed, dir.as_deref())
+            }
+            _ => timer.update(state.time),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn split(map: &str, secs: i64) -> Split {
+        Split {
+            map: map.to_owned(),
+            time: Duration::try_seconds(secs).unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_format_time() {
+        assert_eq!(
+            format_time(Duration::try_milliseconds(61_234).unwrap()),
+            "1:01.23"
+        );
+        assert_eq!(
+            format_time(Duration::try_milliseconds(-1_500).unwrap()),
+            "-0:01.50"
+        );
+        assert_eq!(
+            format_time(Duration::try_seconds(3_725).unwrap()),
+            "1:02:05.00"
+        );
+        assert_eq!(format_delta(Duration::try_seconds(2).unwrap()), "+0:02.00");
+    }
+
+    #[test]
+    fn test_parse_splits() {
+        assert_eq!(
+            parse_splits("e1m1 61000\ne1m2 130000\n"),
+            Some(vec![split("e1m1", 61), split("e1m2", 130)])
+        );
+        assert_eq!(parse_splits("e1m1"), None);
+    }
+
+    #[test]
+    fn test_is_better() {
+        let best = vec![split("e1m1", 60), split("e1m2", 120)];
+        assert!(is_better(&[split("e1m1", 60), split("e1m2", 110)], &best));
+        assert!(!is_better(&[split("e1m1", 50)], &best));
+        assert!(is_better(&[split("e1m1", 500)], &[]));
+    }
+
+    #[test]
+    fn test_run_continues_across_maps() {
+        let secs = |s| Duration::try_seconds(s).unwrap();
+        let mut timer = RunTimer::default();
+
+        timer.begin_map("e1m1", secs(1), false, None);
+        timer.update(secs(31));
+        timer.finish_map(secs(61), None);
+        assert_eq!(timer.splits(), &[split("e1m1", 60)]);
+
+        // the next map's client time starts over, but the run carries on
+        timer.begin_map("e1m2", secs(2), false, None);
+        timer.update(secs(12));
+        assert_eq!(timer.elapsed(), secs(70));
+
+        // starting a map without finishing the last one is a new attempt
+        timer.begin_map("e1m2", secs(0), false, None);
+        assert!(timer.splits().is_empty());
+        assert_eq!(timer.elapsed(), secs(0));
+    }
+}
@@ -0,0 +1,282 @@
+//! Finding servers to join.
+//!
+//! A search broadcasts a server info request to the local network and asks the master server
+//! (`cl_master`) for its list of public servers, each of which is then sent the same request.
+//! Servers are listed as their replies arrive.
+
+use std::{
+    collections::HashMap,
+    io::ErrorKind,
+    net::{Ipv4Addr, SocketAddr, SocketAddrV4, ToSocketAddrs, UdpSocket},
+    time::Duration,
+};
+
+use bevy::{prelude::*, utils::Instant};
+use clap::Parser;
+
+use crate::{
+    client::menu::Menu,
+    common::{
+        console::{Cvar, RegisterCmdExt, Registry},
+        net::{
+            connect::{ConnectPacket as _, Request, Response},
+            NetError, GAME_NAME, MAX_MESSAGE,
+        },
+    },
+};
+
+/// The port that servers listen on by default.
+pub const DEFAULT_PORT: u16 = 26000;
+
+/// How long to wait for replies before ending a search.
+const SEARCH_DURATION: Duration = Duration::from_secs(3);
+
+const MASTER_HEADER: &[u8] = b"\xFF\xFF\xFF\xFF";
+const MASTER_QUERY: &[u8] = b"getservers DarkPlaces-Quake 3 empty full";
+const MASTER_RESPONSE: &[u8] = b"getserversResponse";
+const MASTER_END: &[u8] = b"EOT\0\0\0";
+
+/// A server which replied to a search.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ServerInfo {
+    pub address: SocketAddr,
+    pub hostname: String,
+    pub map: String,
+    pub players: u8,
+    pub max_players: u8,
+    pub ping: Duration,
+}
+
+struct Search {
+    socket: UdpSocket,
+    started: Instant,
+    master: Option<SocketAddr>,
+
+    /// When each server was sent a request, for measuring ping. Servers which reply to the
+    /// broadcast are measured from the start of the search.
+    sent: HashMap<SocketAddr, Instant>,
+}
+
+#[derive(Resource, Default)]
+pub struct ServerList {
+    servers: Vec<ServerInfo>,
+    search: Option<Search>,
+}
+
+impl ServerList {
+    pub fn servers(&self) -> &[ServerInfo] {
+        &self.servers
+    }
+
+    pub fn is_searching(&self) -> bool {
+        self.search.is_some()
+    }
+
+    /// Clear the list and start a new search, optionally including the servers known to the
+    /// master server at `master`.
+    pub fn refresh(&mut self, master: Option<&str>) -> Result<(), NetError> {
+        self.servers.clear();
+        self.search = None;
+
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.set_broadcast(true)?;
+        socket.set_nonblocking(true)?;
+
+        socket.send_to(
+            &Request::server_info(GAME_NAME).to_bytes()?,
+            SocketAddrV4::new(Ipv4Addr::BROADCAST, DEFAULT_PORT),
+        )?;
+
+        let master = match master {
+            Some(master) => {
+                let addr = master
+                    .to_socket_addrs()?
+                    .next()
+                    .ok_or_else(|| NetError::with_msg(format!("Unknown master {}", master)))?;
+
+                let mut query = MASTER_HEADER.to_vec();
+                query.extend_from_slice(MASTER_QUERY);
+                socket.send_to(&query, addr)?;
+
+                Some(addr)
+            }
+            None => None,
+        };
+
+        self.search = Some(Search {
+            socket,
+            started: Instant::now(),
+            master,
+            sent: HashMap::new(),
+        });
+
+        Ok(())
+    }
+
+    /// Handle any replies to the current search, returning `true` if the list changed.
+    fn poll(&mut self) -> Result<bool, NetError> {
+        let Some(search) = &mut self.search else {
+            return Ok(false);
+        };
+
+        let mut changed = false;
+        let mut recv_buf = [0u8; MAX_MESSAGE];
+
+        loop {
+            let (len, remote) = match search.socket.recv_from(&mut recv_buf) {
+                Ok(ret) => ret,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e.into()),
+            };
+            let packet = &recv_buf[..len];
+
+            if Some(remote) == search.master {
+                for server in parse_master_response(packet).unwrap_or_default() {
+                    let server = SocketAddr::V4(server);
+                    search
+                        .socket
+                        .send_to(&Request::server_info(GAME_NAME).to_bytes()?, server)?;
+                    search.sent.insert(server, Instant::now());
+                }
+
+                continue;
+            }
+
+            let info = match Response::from_bytes(packet) {
+                Ok(Response::ServerInfo(info)) => info,
+                Ok(_) => continue,
+                Err(e) => {
+                    debug!("Invalid server info from {}: {}", remote, e);
+                    continue;
+                }
+            };
+
+            let sent = search.sent.get(&remote).copied().unwrap_or(search.started);
+            let server = ServerInfo {
+                address: remote,
+                hostname: info.hostname,
+                map: info.levelname,
+                players: info.client_count,
+                max_players: info.client_max,
+                ping: sent.elapsed(),
+            };
+
+            // servers on the local network can reply to both the broadcast and the master query
+            match self.servers.iter_mut().find(|s| s.address == remote) {
+                Some(existing) => *existing = server,
+                None => self.servers.push(server),
+            }
+            changed = true;
+        }
+
+        if search.started.elapsed() >= SEARCH_DURATION {
+            self.search = None;
+            self.servers.sort_by_key(|s| s.ping);
+            changed = true;
+        }
+
+        Ok(changed)
+    }
+}
+
+/// Parse the list of servers sent by a master server. Returns `None` if the packet isn't a valid
+/// response.
+fn parse_master_response(packet: &[u8]) -> Option<Vec<SocketAddrV4>> {
+    let mut rest = packet
+        .strip_prefix(MASTER_HEADER)?
+        .strip_prefix(MASTER_RESPONSE)?;
+    let mut servers = Vec::new();
+
+    // each server is a backslash followed by a 4-byte IP address and 2-byte port, big-endian
+    while let Some(entry) = rest.strip_prefix(b"\\") {
+        if entry.starts_with(MASTER_END) || entry.len() < 6 {
+            break;
+        }
+
+        let ip = Ipv4Addr::new(entry[0], entry[1], entry[2], entry[3]);
+        let port = u16::from_be_bytes([entry[4], entry[5]]);
+        servers.push(SocketAddrV4::new(ip, port));
+
+        rest = &entry[6..];
+    }
+
+    Some(servers)
+}
+
+pub fn register_cvars(app: &mut App) {
+    app.cvar(
+        "cl_master",
+        Cvar::new("\"dpmaster.deathmask.net:27950\"").archive(),
+        "the master server to ask for public servers, or empty to only search the local network",
+    );
+    app.cvar(
+        "cl_joinaddress",
+        Cvar::new("\"\"").archive(),
+        "the server address entered in the Join Game menu",
+    );
+}
+
+pub fn register_commands(app: &mut App) {
+    #[derive(Parser)]
+    #[command(name = "slist", about = "Search for servers to join")]
+    struct Slist;
+
+    app.command(
+        |In(Slist), registry: Res<Registry>, mut list: ResMut<ServerList>| {
+            let master = registry
+                .read_cvar::<String>("cl_master")
+                .ok()
+                .filter(|m| !m.is_empty());
+
+            match list.refresh(master.as_deref()) {
+                Ok(()) => "Looking for Quake servers...".into(),
+                Err(e) => format!("Couldn't search for servers: {}", e).into(),
+            }
+        },
+    );
+}
+
+pub mod systems {
+    use super::*;
+
+    pub fn poll_server_list(mut list: ResMut<ServerList>) {
+        match list.bypass_change_detection().poll() {
+            Ok(true) => list.set_changed(),
+            Ok(false) => {}
+            Err(e) => {
+                warn!("Server search failed: {}", e);
+                list.search = None;
+            }
+        }
+    }
+
+    pub fn update_menu_servers(list: Res<ServerList>, mut menu: ResMut<Menu>) {
+        menu.update_servers(list.servers());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_master_response() {
+        let mut packet = b"\xFF\xFF\xFF\xFFgetserversResponse".to_vec();
+        packet.extend_from_slice(b"\\\x7F\x00\x00\x01\x65\x90");
+        packet.extend_from_slice(b"\\\xC0\xA8\x01\x02\x6D\x38");
+        packet.extend_from_slice(b"\\EOT\0\0\0");
+
+        assert_eq!(
+            parse_master_response(&packet),
+            Some(vec![
+                SocketAddrV4::new(Ipv4Addr::new(127, 0, 0, 1), 26000),
+                SocketAddrV4::new(Ipv4Addr::new(192, 168, 1, 2), 27960),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_parse_master_response_invalid() {
+        assert_eq!(parse_master_response(b"\x80\x00\x00\x05\x83"), None);
+    }
+}
@@ -0,0 +1,78 @@
+//! Skyboxes (`sky`).
+//!
+//! Maps set their skybox with the `sky` key of `worldspawn`, and the `sky` command changes it until
+//! the next map is loaded. A skybox named `<name>` is made of six images, `env/<name>rt.tga`,
+//! `bk`, `lf`, `ft`, `up` and `dn`, which can also be PNGs or be in `gfx/env/` as in FitzQuake.
+//! While a skybox is set it's drawn on sky brushes in place of the scrolling sky texture.
+
+use bevy::prelude::*;
+use clap::Parser;
+use failure::{format_err, Error};
+
+use crate::common::{
+    console::{ExecResult, RegisterCmdExt},
+    vfs::Vfs,
+};
+
+use super::Connection;
+
+/// The suffixes of the six faces of a skybox, in the order that they're laid out in the renderer.
+pub const FACE_SUFFIXES: [&str; 6] = ["rt", "bk", "lf", "ft", "up", "dn"];
+
+const DIRECTORIES: [&str; 2] = ["env", "gfx/env"];
+const EXTENSIONS: [&str; 2] = ["tga", "png"];
+
+/// The paths of the six faces of the skybox `name`, in the order of [`FACE_SUFFIXES`].
+pub fn find_faces(vfs: &Vfs, name: &str) -> Result<Vec<String>, Error> {
+    FACE_SUFFIXES
+        .iter()
+        .map(|suffix| {
+            DIRECTORIES
+                .iter()
+                .flat_map(|dir| {
+                    EXTENSIONS
+                        .iter()
+                        .map(move |ext| format!("{}/{}{}.{}", dir, name, suffix, ext))
+                })
+                .find(|path| vfs.open(path).is_ok())
+                .ok_or_else(|| format_err!("no env/{}{}.tga", name, suffix))
+        })
+        .collect()
+}
+
+pub fn register_commands(app: &mut App) {
+    #[derive(Parser)]
+    #[command(
+        name = "sky",
+        about = "Show or set the skybox, or use \"none\" for the map's own sky"
+    )]
+    struct SkyCmd {
+        name: Option<String>,
+    }
+
+    app.command(
+        |In(SkyCmd { name }), conn: Option<ResMut<Connection>>, vfs: Res<Vfs>| -> ExecResult {
+            let Some(mut conn) = conn else {
+                return "sky needs a map to be loaded".into();
+            };
+
+            match name.as_deref() {
+                None => match &conn.state.sky {
+                    Some(sky) => format!("sky is \"{}\"", sky).into(),
+                    None => "No skybox is set".into(),
+                },
+                Some("" | "none") => {
+                    conn.state.sky = None;
+                    default()
+                }
+                Some(name) => match find_faces(&vfs, name) {
+                    Ok(_) => {
+                        conn.state.sky = Some(name.to_owned());
+                        default()
+                    }
+                    Err(e) => format!("Couldn't load skybox {}: {}", name, e).into(),
+                },
+            }
+        },
+    );
+}
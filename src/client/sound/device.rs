@@ -0,0 +1,148 @@
+//! Playing the mixed sound on an output device other than the system default (`snd_device`).
+//!
+//! The audio plugin always opens the default device, so the mixer's output is snooped and sent to
+//! a stream on the chosen device instead, while the default output is silenced. The snooped
+//! samples are read once a frame, so the chosen device lags the default one by about a frame.
+
+use bevy::log::warn;
+use cpal::{
+    traits::{DeviceTrait as _, HostTrait as _, StreamTrait as _},
+    SampleFormat, SampleRate, Stream, StreamConfig,
+};
+use crossbeam_channel::{Receiver, Sender, TrySendError};
+use fundsp::snoop::Snoop;
+
+use super::SoundError;
+
+/// How much sound can be queued for the device, in seconds, before new samples are dropped.
+const MAX_LATENCY: f32 = 0.1;
+
+/// The names of the audio output devices, for `snd_device`.
+pub fn output_devices() -> Vec<String> {
+    match cpal::default_host().output_devices() {
+        Ok(devices) => devices.filter_map(|device| device.name().ok()).collect(),
+        Err(e) => {
+            warn!("Couldn't list audio output devices: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// The sample rate the mixer runs at. The audio plugin opens the default device with its default
+/// configuration, so that's the rate the mixed samples come out at.
+fn mixer_sample_rate() -> Option<SampleRate> {
+    let device = cpal::default_host().default_output_device()?;
+    Some(device.default_output_config().ok()?.sample_rate())
+}
+
+/// A stream playing the mixed sound on a chosen device, if one is open.
+#[derive(Default)]
+pub struct OutputDevice {
+    stream: Option<(Stream, Sender<[f32; 2]>)>,
+    /// The number of snooped samples which had been sent when the last frame's were read.
+    sent: u64,
+}
+
+impl OutputDevice {
+    /// Play the sound on the device with the given name, closing any stream that's already open.
+    pub fn open(&mut self, name: &str) -> Result<(), SoundError> {
+        self.close();
+
+        let device = cpal::default_host()
+            .output_devices()?
+            .find(|device| device.name().map_or(false, |n| n == name))
+            .ok_or_else(|| SoundError::NoSuchDevice(name.to_owned()))?;
+
+        let rate = mixer_sample_rate().unwrap_or(SampleRate(44100));
+        let configs = device
+            .supported_output_configs()?
+            .filter(|config| config.sample_format() == SampleFormat::F32)
+            .collect::<Vec<_>>();
+        // prefer a configuration at the mixer's rate, since the samples aren't resampled
+        let config: StreamConfig = match configs
+            .iter()
+            .find(|config| config.min_sample_rate() <= rate && rate <= config.max_sample_rate())
+        {
+            Some(config) => config.with_sample_rate(rate).config(),
+            None => {
+                let config = configs
+                    .into_iter()
+                    .next()
+                    .ok_or_else(|| SoundError::NoOutputConfig(name.to_owned()))?;
+                warn!(
+                    "{} can't play at {} Hz, so sound will be at the wrong pitch",
+                    name, rate.0
+                );
+                config.with_max_sample_rate().config()
+            }
+        };
+
+        let capacity = (config.sample_rate.0 as f32 * MAX_LATENCY) as usize;
+        let (sender, receiver) = crossbeam_channel::bounded(capacity);
+        let stream = device.build_output_stream(
+            &config,
+            write_samples(receiver, config.channels as usize),
+            |e| warn!("Audio output error: {}", e),
+            None,
+        )?;
+        stream.play()?;
+
+        self.stream = Some((stream, sender));
+        Ok(())
+    }
+
+    /// Close the stream, if one is open, so that the sound goes back to the default device.
+    pub fn close(&mut self) {
+        self.stream = None;
+    }
+
+    /// Whether the sound is going to a chosen device rather than the default one.
+    pub fn is_open(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    /// Send the samples the mixer has produced since the last call to the device.
+    pub fn send(&mut self, left: &Snoop<f32>, right: &Snoop<f32>) {
+        let total = left.total();
+        let new = (total - self.sent.min(total)).min(left.capacity() as u64) as usize;
+        self.sent = total;
+
+        let Some((_, sender)) = &self.stream else {
+            return;
+        };
+
+        // `at(0)` is the newest sample
+        for i in (0..new).rev() {
+            match sender.try_send([left.at(i), right.at(i)]) {
+                Ok(()) => (),
+                Err(TrySendError::Full(_)) => break,
+                Err(TrySendError::Disconnected(_)) => {
+                    warn!("Audio output stream closed");
+                    self.close();
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// The stream's callback, which plays queued samples and fills any gap with silence.
+fn write_samples(
+    receiver: Receiver<[f32; 2]>,
+    channels: usize,
+) -> impl FnMut(&mut [f32], &cpal::OutputCallbackInfo) + Send + 'static {
+    move |data, _| {
+        for frame in data.chunks_mut(channels) {
+            let [left, right] = receiver.try_recv().unwrap_or_default();
+            match frame {
+                [mono] => *mono = (left + right) / 2.0,
+                [l, r, rest @ ..] => {
+                    *l = left;
+                    *r = right;
+                    rest.fill(0.0);
+                }
+                [] => (),
+            }
+        }
+    }
+}
@@ -18,39 +18,79 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+mod device;
 mod music;
 use bevy::{
-    app::{Main, Plugin},
+    app::{App, Main, Plugin},
     asset::{AssetServer, Handle},
     audio::{
-        AudioBundle, AudioSinkPlayback as _, AudioSource, PlaybackMode, PlaybackSettings, Volume,
+        AudioBundle, AudioSinkPlayback as _, AudioSource, PlaybackMode, PlaybackSettings,
+        SpatialListener, SpatialScale, Volume,
     },
     ecs::{
         bundle::Bundle,
         component::Component,
         entity::Entity,
         event::{Event, EventReader},
-        system::{Commands, Query, Res, ResMut, Resource},
+        system::{Commands, In, NonSendMut, Query, Res, ResMut, Resource},
     },
+    log::warn,
+    math::Vec3,
+    transform::{components::Transform, TransformBundle},
+};
+use fundsp::{
+    shared::Shared,
+    snoop::{Snoop, SnoopBackend},
 };
-use fundsp::snoop::{Snoop, SnoopBackend};
 
 use bevy_mod_dynamicaudio::{
     audio::{AudioSink, Mixer},
     AddAudioMixer,
 };
 
+pub use device::{output_devices, OutputDevice};
 pub use music::MusicPlayer;
 
 use std::io::{self, Read as _};
 
-use crate::common::vfs::{Vfs, VfsError};
+use crate::common::{
+    bsp::MAX_SOUNDS,
+    console::{Cvar, RegisterCmdExt, Registry},
+    vfs::{Vfs, VfsError},
+};
 
 use cgmath::{InnerSpace, Vector3};
+use serde::Deserialize;
+use serde_lexpr::Value;
 use thiserror::Error;
 
 pub const DISTANCE_ATTENUATION_FACTOR: f32 = 0.001;
 
+/// Sounds are attenuated by `Listener::attenuate`, so their positions are scaled down for the
+/// spatializer until its own falloff only starts where they'd be silent anyway. It still pans them.
+const SPATIAL_SCALE: f32 = DISTANCE_ATTENUATION_FACTOR;
+
+/// The looping sounds played at the ambient levels of the leaf the view is in, for water, sky,
+/// slime and lava. As in Quake, slime and lava are silent.
+const AMBIENT_SOUNDS: [Option<&str>; MAX_SOUNDS] = [
+    Some("ambience/water1.wav"),
+    Some("ambience/wind2.wav"),
+    None,
+    None,
+];
+
+/// How fast ambient sounds fade towards the levels of the current leaf, per second.
+const AMBIENT_FADE: f32 = 100.0 / 255.0;
+
+/// Ambient sounds quieter than this are silenced.
+const MIN_AMBIENT_LEVEL: f32 = 8.0 / 255.0;
+
+/// The cutoff of the mixer's low-pass filter while the view is underwater, in Hz.
+const UNDERWATER_CUTOFF: f32 = 800.0;
+
+/// The cutoff of the low-pass filter the rest of the time, which lets everything audible through.
+const OPEN_CUTOFF: f32 = 20000.0;
+
 #[derive(Error, Debug)]
 pub enum SoundError {
     #[error("No such music track: {0}")]
@@ -59,6 +99,18 @@ pub enum SoundError {
     Io(#[from] io::Error),
     #[error("Virtual filesystem error: {0}")]
     Vfs(#[from] VfsError),
+    #[error("No audio output device named {0}")]
+    NoSuchDevice(String),
+    #[error("{0} has no supported output format")]
+    NoOutputConfig(String),
+    #[error("Couldn't list audio output devices: {0}")]
+    Devices(#[from] cpal::DevicesError),
+    #[error("Couldn't read the device's output formats: {0}")]
+    OutputConfigs(#[from] cpal::SupportedStreamConfigsError),
+    #[error("Couldn't open an output stream: {0}")]
+    BuildStream(#[from] cpal::BuildStreamError),
+    #[error("Couldn't start the output stream: {0}")]
+    PlayStream(#[from] cpal::PlayStreamError),
 }
 
 /// Data needed for sound spatialization.
@@ -106,6 +158,14 @@ impl Listener {
         self.right_ear = new_origin;
     }
 
+    /// Where to put the spatializer's ears, relative to its origin at `self.origin()`.
+    fn ear_offsets(&self) -> (Vec3, Vec3) {
+        (
+            to_vec3(self.left_ear - self.origin),
+            to_vec3(self.right_ear - self.origin),
+        )
+    }
+
     pub fn attenuate(
         &self,
         emitter_origin: Vector3<f32>,
@@ -119,6 +179,22 @@ impl Listener {
     }
 }
 
+/// Positions are given to the spatializer in Quake coordinates, as the listener's are too.
+fn to_vec3(v: Vector3<f32>) -> Vec3 {
+    Vec3::new(v.x, v.y, v.z)
+}
+
+/// Playback settings for a sound which is panned between the listener's ears.
+fn spatial_settings(mode: PlaybackMode, volume: f32) -> PlaybackSettings {
+    PlaybackSettings {
+        mode,
+        volume: Volume::new(volume),
+        spatial: true,
+        spatial_scale: Some(SpatialScale::new(SPATIAL_SCALE)),
+        ..Default::default()
+    }
+}
+
 pub fn load<S>(vfs: &Vfs, name: S) -> Result<AudioSource, SoundError>
 where
     S: AsRef<str>,
@@ -133,7 +209,13 @@ where
 
 type ReverbNode = impl fundsp::audionode::AudioNode<Sample = f32> + Send + Sync + 'static;
 
-fn create_mixer(sender_l: SnoopBackend<f32>, sender_r: SnoopBackend<f32>) -> ReverbNode {
+fn create_mixer(
+    sender_l: SnoopBackend<f32>,
+    sender_r: SnoopBackend<f32>,
+    reverb: &Shared<f32>,
+    cutoff: &Shared<f32>,
+    default_output: &Shared<f32>,
+) -> ReverbNode {
     use fundsp::hacker32::*;
 
     let sender_l = An(sender_l);
@@ -145,19 +227,37 @@ fn create_mixer(sender_l: SnoopBackend<f32>, sender_r: SnoopBackend<f32>) -> Rev
             >> (moog_hz(1500., 0.) | moog_hz(1500., 0.))),
     );
 
-    ((multipass() & 0.3 * reverb_stereo(20.0, 0.8) & 0.2 * delay)
-        >> limiter_stereo(0.05)
-        >> (sender_l | sender_r))
-        .0
+    // the wet signal is scaled by `reverb`, so it can be switched off without rebuilding the graph
+    let wet = (var(reverb) | var(reverb)) * (0.3 * reverb_stereo(20.0, 0.8) & 0.2 * delay);
+
+    // the cutoff is smoothed so that going in and out of water doesn't click
+    let muffle = || (pass() | (var(cutoff) >> follow(0.1)) | dc(0.707)) >> lowpass();
+
+    // the snoop sees the sound before it's silenced, so it can still be played on another device
+    let output = (var(default_output) | var(default_output)) * (sender_l | sender_r);
+
+    ((multipass() & wet) >> (muffle() | muffle()) >> limiter_stereo(0.05) >> output).0
 }
 
 pub struct SeismonSoundPlugin;
 
 impl Plugin for SeismonSoundPlugin {
     fn build(&self, app: &mut bevy::prelude::App) {
-        let (snoop_l, send_l) = Snoop::new(1024);
-        let (snoop_r, send_r) = Snoop::new(1024);
-        let mixer = create_mixer(send_l, send_r);
+        // enough to hold a few frames' worth of samples, since they're only read once a frame
+        let (snoop_l, send_l) = Snoop::new(8192);
+        let (snoop_r, send_r) = Snoop::new(8192);
+        let controls = MixerControls {
+            reverb: Shared::new(1.0),
+            cutoff: Shared::new(OPEN_CUTOFF),
+            default_output: Shared::new(1.0),
+        };
+        let mixer = create_mixer(
+            send_l,
+            send_r,
+            &controls.reverb,
+            &controls.cutoff,
+            &controls.default_output,
+        );
 
         let global_audio = GetGlobalAudio {
             left: snoop_l,
@@ -171,17 +271,27 @@ impl Plugin for SeismonSoundPlugin {
                 processor: Some(mixer),
             })
             .id();
+        app.world
+            .spawn((SpatialListener::new(8.0), TransformBundle::default()));
         app.insert_resource(GlobalMixer { mixer: mixer_id })
             .insert_resource(global_audio)
+            .insert_resource(controls)
+            .init_resource::<SoundVars>()
             .init_resource::<MusicPlayer>()
             .init_resource::<Listener>()
+            .init_non_send_resource::<OutputDevice>()
             .add_event::<MixerEvent>()
             .add_systems(
                 Main,
                 (
                     systems::update_entities,
+                    update_static_sounds,
+                    systems::update_music_volume,
+                    systems::update_music_focus,
                     systems::update_mixer,
                     systems::update_listener,
+                    systems::update_ambient_sounds,
+                    systems::update_underwater,
                     systems::write_audio,
                 ),
             );
@@ -198,9 +308,10 @@ pub struct StaticSound {
 pub fn update_static_sounds(
     static_sounds: Query<(&AudioSink, &StaticSound)>,
     listener: Res<Listener>,
+    vars: Res<SoundVars>,
 ) {
     for (sink, sound) in static_sounds.iter() {
-        sound.update(sink, &*listener);
+        sound.update(sink, &*listener, vars.effects());
     }
 }
 
@@ -216,10 +327,11 @@ pub struct StartStaticSound {
 struct StaticSoundBundle {
     static_sound: StaticSound,
     audio: AudioBundle,
+    transform: TransformBundle,
 }
 
 impl StaticSoundBundle {
-    fn new(value: &StartStaticSound, listener: &Listener) -> Self {
+    fn new(value: &StartStaticSound, listener: &Listener, volume: f32) -> Self {
         Self {
             static_sound: StaticSound {
                 origin: value.origin,
@@ -228,26 +340,23 @@ impl StaticSoundBundle {
             },
             audio: AudioBundle {
                 source: value.src.clone(),
-                settings: PlaybackSettings {
-                    mode: PlaybackMode::Loop,
-                    // TODO: Use Bevy's built-in spacialiser
-                    volume: Volume::new(listener.attenuate(
-                        value.origin,
-                        value.volume,
-                        value.attenuation,
-                    )),
-                    ..Default::default()
-                },
+                settings: spatial_settings(
+                    PlaybackMode::Loop,
+                    listener.attenuate(value.origin, value.volume, value.attenuation) * volume,
+                ),
             },
+            transform: TransformBundle::from_transform(Transform::from_translation(to_vec3(
+                value.origin,
+            ))),
         }
     }
 }
 
 impl StaticSound {
-    fn update(&self, audio_sink: &AudioSink, listener: &Listener) {
+    fn update(&self, audio_sink: &AudioSink, listener: &Listener, volume: f32) {
         // attenuate using quake coordinates since distance is the same either way
-        // TODO: Use Bevy's built-in spacialiser
-        audio_sink.set_volume(listener.attenuate(self.origin, self.volume, self.attenuation));
+        audio_sink
+            .set_volume(listener.attenuate(self.origin, self.volume, self.attenuation) * volume);
     }
 }
 
@@ -256,6 +365,143 @@ pub struct GlobalMixer {
     pub mixer: Entity,
 }
 
+/// Parameters of the global mixer which can be changed while it's running.
+#[derive(Clone, Resource)]
+pub struct MixerControls {
+    /// The level of the reverb and echo effects, from 0 to 1.
+    pub reverb: Shared<f32>,
+    /// The cutoff of the low-pass filter on the mixed sound, in Hz.
+    pub cutoff: Shared<f32>,
+    /// The gain of the sound going to the default device, which is 0 while `snd_device` plays it
+    /// on another one.
+    pub default_output: Shared<f32>,
+}
+
+/// Volume levels from 0 to 1, set by the `volume`, `sfxvolume`, `bgmvolume` and `ambient_level`
+/// cvars.
+#[derive(Clone, Copy, Debug, Resource, Deserialize)]
+pub struct SoundVars {
+    #[serde(rename(deserialize = "volume"))]
+    pub master_volume: f32,
+    #[serde(rename(deserialize = "sfxvolume"))]
+    pub effects_volume: f32,
+    #[serde(rename(deserialize = "bgmvolume"))]
+    pub music_volume: f32,
+    #[serde(rename(deserialize = "ambient_level"))]
+    pub ambient_volume: f32,
+}
+
+impl Default for SoundVars {
+    fn default() -> Self {
+        Self {
+            master_volume: 0.7,
+            effects_volume: 1.0,
+            music_volume: 1.0,
+            ambient_volume: 0.3,
+        }
+    }
+}
+
+impl SoundVars {
+    /// The volume of sound effects, taking the master volume into account.
+    pub fn effects(&self) -> f32 {
+        (self.master_volume * self.effects_volume).clamp(0.0, 1.0)
+    }
+
+    /// The volume of music, taking the master volume into account.
+    pub fn music(&self) -> f32 {
+        (self.master_volume * self.music_volume).clamp(0.0, 1.0)
+    }
+}
+
+/// Whether a toggle cvar is set. Menu toggles set booleans, the console usually sets numbers.
+fn is_enabled(value: &Value) -> bool {
+    match value {
+        Value::Bool(b) => *b,
+        Value::Number(n) => n.as_f64().map_or(false, |n| n != 0.0),
+        _ => false,
+    }
+}
+
+fn apply_volume(In(_): In<Value>, registry: Res<Registry>, mut vars: ResMut<SoundVars>) {
+    match registry.read_cvars::<SoundVars>() {
+        Some(new_vars) => *vars = new_vars,
+        None => warn!("volume, sfxvolume, bgmvolume and ambient_level must be numbers from 0 to 1"),
+    }
+}
+
+fn apply_reverb(In(value): In<Value>, controls: Res<MixerControls>) {
+    controls
+        .reverb
+        .set_value(if is_enabled(&value) { 1.0 } else { 0.0 });
+}
+
+fn apply_device(
+    In(value): In<Value>,
+    controls: Res<MixerControls>,
+    mut output: NonSendMut<OutputDevice>,
+) {
+    let name = match &value {
+        Value::String(name) | Value::Symbol(name) => name,
+        _ => return,
+    };
+
+    output.close();
+    if !name.is_empty() {
+        if let Err(e) = output.open(name) {
+            warn!("{}", e);
+        }
+    }
+
+    controls
+        .default_output
+        .set_value(if output.is_open() { 0.0 } else { 1.0 });
+}
+
+pub fn register_cvars(app: &mut App) {
+    app.cvar_on_set(
+        "volume",
+        Cvar::new("0.7").archive(),
+        apply_volume,
+        "the master volume, from 0 to 1",
+    );
+    app.cvar_on_set(
+        "sfxvolume",
+        Cvar::new("1").archive(),
+        apply_volume,
+        "the volume of sound effects relative to the master volume",
+    );
+    app.cvar_on_set(
+        "bgmvolume",
+        Cvar::new("1").archive(),
+        apply_volume,
+        "the volume of music relative to the master volume",
+    );
+    app.cvar_on_set(
+        "ambient_level",
+        Cvar::new("0.3").archive(),
+        apply_volume,
+        "the volume of the ambient water and wind sounds relative to sound effects",
+    );
+    app.cvar_on_set(
+        "snd_device",
+        Cvar::new("\"\"").archive(),
+        apply_device,
+        "the name of the audio output device, or empty for the system default",
+    );
+    app.cvar_on_set(
+        "snd_reverb",
+        Cvar::new("1").archive(),
+        apply_reverb,
+        "1: add reverb and echo to the mixed sound, 0: play sounds dry",
+    );
+    app.cvar(
+        "snd_waterfx",
+        Cvar::new("1").archive(),
+        "1: muffle sound while the view is underwater",
+    );
+}
+
 /// Represents a single audio channel, capable of playing one sound at a time.
 #[derive(Clone, Debug, Component)]
 pub struct Channel {
@@ -276,17 +522,27 @@ struct EntitySoundBundle {
     entity: EntityChannel,
     chan: Channel,
     audio: AudioBundle,
+    transform: TransformBundle,
 }
 
 #[derive(Bundle)]
 struct TempEntitySoundBundle {
     chan: Channel,
     audio: AudioBundle,
+    transform: TransformBundle,
+}
+
+/// A looping ambient sound, which fades towards the level for its kind of the leaf the view is in.
+#[derive(Clone, Debug, Component)]
+pub struct AmbientSound {
+    index: usize,
+    level: f32,
 }
 
 fn make_bundle(
     value: &StartSound,
     listener: &Listener,
+    volume: f32,
 ) -> Result<EntitySoundBundle, TempEntitySoundBundle> {
     let chan = Channel {
         origin: value.origin.into(),
@@ -296,33 +552,35 @@ fn make_bundle(
     };
     let audio = AudioBundle {
         source: value.src.clone(),
-        settings: PlaybackSettings {
-            mode: PlaybackMode::Despawn,
-            // TODO: Use Bevy's built-in spacialiser
-            volume: Volume::new(listener.attenuate(
-                value.origin.into(),
-                value.volume,
-                value.attenuation,
-            )),
-            ..Default::default()
-        },
+        settings: spatial_settings(
+            PlaybackMode::Despawn,
+            listener.attenuate(value.origin.into(), value.volume, value.attenuation) * volume,
+        ),
     };
+    let transform =
+        TransformBundle::from_transform(Transform::from_translation(Vec3::from(value.origin)));
 
     match value.ent_id {
         Some(id) => Ok(EntitySoundBundle {
             chan,
             audio,
+            transform,
             entity: EntityChannel { id },
         }),
-        None => Err(TempEntitySoundBundle { chan, audio }),
+        None => Err(TempEntitySoundBundle {
+            chan,
+            audio,
+            transform,
+        }),
     }
 }
 
 impl Channel {
-    pub fn update(&self, sink: &mut AudioSink, listener: &Listener) {
+    pub fn update(&self, sink: &mut AudioSink, listener: &Listener, volume: f32) {
         // attenuate using quake coordinates since distance is the same either way
-        // TODO: Use Bevy's built-in spacialiser
-        sink.set_volume(listener.attenuate(self.origin, self.master_vol, self.attenuation));
+        sink.set_volume(
+            listener.attenuate(self.origin, self.master_vol, self.attenuation) * volume,
+        );
     }
 }
 
@@ -349,13 +607,20 @@ pub enum MusicSource {
     TrackId(usize),
 }
 
+#[derive(Debug, Clone)]
+pub struct StartMusic {
+    pub source: MusicSource,
+    /// Whether to start the track again when it ends, rather than stopping.
+    pub looping: bool,
+}
+
 #[derive(Event, Debug, Clone)]
 pub enum MixerEvent {
     StartSound(StartSound),
     StopSound(StopSound),
     StartStaticSound(StartStaticSound),
     /// If None, restarts already-playing music
-    StartMusic(Option<MusicSource>),
+    StartMusic(Option<StartMusic>),
     PauseMusic,
     StopMusic,
 }
@@ -367,6 +632,9 @@ pub struct GetGlobalAudio {
 }
 
 mod systems {
+    use bevy::{
+        audio::AudioSinkPlayback as _, ecs::system::Local, time::Time, window::WindowFocused,
+    };
     use bevy_mod_dynamicaudio::audio::AudioTarget;
 
     use crate::client::Connection;
@@ -377,6 +645,7 @@ mod systems {
         channels: Query<(Entity, &Channel, Option<&EntityChannel>)>,
         vfs: Res<Vfs>,
         listener: Res<Listener>,
+        vars: Res<SoundVars>,
         mut music_player: ResMut<MusicPlayer>,
         asset_server: Res<AssetServer>,
         mixer: Res<GlobalMixer>,
@@ -407,31 +676,40 @@ mod systems {
             }
 
             match *event {
-                MixerEvent::StartSound(ref start) => match make_bundle(start, &*listener) {
-                    Ok(bundle) => {
-                        commands.spawn((
-                            bundle,
-                            AudioTarget {
-                                target: mixer.mixer,
-                            },
-                        ));
-                    }
-                    Err(bundle) => {
-                        commands.spawn((
-                            bundle,
-                            AudioTarget {
-                                target: mixer.mixer,
-                            },
-                        ));
+                MixerEvent::StartSound(ref start) => {
+                    match make_bundle(start, &*listener, vars.effects()) {
+                        Ok(bundle) => {
+                            commands.spawn((
+                                bundle,
+                                AudioTarget {
+                                    target: mixer.mixer,
+                                },
+                            ));
+                        }
+                        Err(bundle) => {
+                            commands.spawn((
+                                bundle,
+                                AudioTarget {
+                                    target: mixer.mixer,
+                                },
+                            ));
+                        }
                     }
-                },
+                }
                 MixerEvent::StopSound(StopSound { .. }) => {
                     // Handled by previous match
                 }
                 MixerEvent::StartStaticSound(ref static_sound) => {
-                    commands.spawn(StaticSoundBundle::new(static_sound, &*listener));
+                    commands.spawn(StaticSoundBundle::new(
+                        static_sound,
+                        &*listener,
+                        vars.effects(),
+                    ));
                 }
-                MixerEvent::StartMusic(Some(MusicSource::Named(ref named))) => {
+                MixerEvent::StartMusic(Some(StartMusic {
+                    source: MusicSource::Named(ref named),
+                    looping,
+                })) => {
                     // TODO: Error handling
                     music_player
                         .play_named(
@@ -442,10 +720,14 @@ mod systems {
                                 target: mixer.mixer,
                             }),
                             named,
+                            looping,
                         )
                         .unwrap();
                 }
-                MixerEvent::StartMusic(Some(MusicSource::TrackId(id))) => {
+                MixerEvent::StartMusic(Some(StartMusic {
+                    source: MusicSource::TrackId(id),
+                    looping,
+                })) => {
                     // TODO: Error handling
                     music_player
                         .play_track(
@@ -456,6 +738,7 @@ mod systems {
                                 target: mixer.mixer,
                             }),
                             id,
+                            looping,
                         )
                         .unwrap();
                 }
@@ -467,32 +750,150 @@ mod systems {
     }
 
     pub fn update_entities(
-        mut entities: Query<(&mut AudioSink, Option<&EntityChannel>, &mut Channel)>,
+        mut entities: Query<(
+            &mut AudioSink,
+            Option<&EntityChannel>,
+            &mut Channel,
+            &mut Transform,
+        )>,
         listener: Res<Listener>,
+        vars: Res<SoundVars>,
         conn: Option<Res<Connection>>,
     ) {
         let Some(conn) = conn else {
             return;
         };
 
-        for (mut sink, e_chan, mut chan) in entities.iter_mut() {
+        for (mut sink, e_chan, mut chan, mut transform) in entities.iter_mut() {
             if let Some(e) = e_chan.and_then(|e| conn.state.entities.get(e.id)) {
                 chan.origin = e.origin;
+                transform.translation = to_vec3(e.origin);
             }
 
-            chan.update(&mut *sink, &*listener)
+            chan.update(&mut *sink, &*listener, vars.effects())
         }
     }
 
-    pub fn update_listener(mut listener: ResMut<Listener>, conn: Option<Res<Connection>>) {
+    pub fn update_music_volume(
+        music_player: Res<MusicPlayer>,
+        vars: Res<SoundVars>,
+        sinks: Query<&AudioSink>,
+    ) {
+        music_player.set_volume(&sinks, vars.music());
+    }
+
+    /// Pause the music while the window doesn't have focus.
+    pub fn update_music_focus(
+        mut focus_events: EventReader<WindowFocused>,
+        mut music_player: ResMut<MusicPlayer>,
+        sinks: Query<&AudioSink>,
+    ) {
+        for event in focus_events.read() {
+            music_player.set_focused(&sinks, event.focused);
+        }
+    }
+
+    pub fn update_listener(
+        mut listener: ResMut<Listener>,
+        mut spatial_listeners: Query<(&mut Transform, &mut SpatialListener)>,
+        conn: Option<Res<Connection>>,
+    ) {
         if let Some(new_listener) = conn.and_then(|conn| conn.state.update_listener()) {
             *listener = new_listener;
         }
+
+        let (left_ear, right_ear) = listener.ear_offsets();
+        for (mut transform, mut spatial_listener) in spatial_listeners.iter_mut() {
+            transform.translation = to_vec3(listener.origin());
+            spatial_listener.left_ear_offset = left_ear;
+            spatial_listener.right_ear_offset = right_ear;
+        }
+    }
+
+    /// Muffle the mixed sound while the view is in water, slime or lava, if `snd_waterfx` is set.
+    pub fn update_underwater(
+        controls: Res<MixerControls>,
+        registry: Res<Registry>,
+        conn: Option<Res<Connection>>,
+    ) {
+        let waterfx = registry
+            .get_cvar("snd_waterfx")
+            .map_or(true, |cvar| is_enabled(cvar.value()));
+        let underwater = conn.map_or(false, |conn| conn.state.view_underwater());
+
+        controls.cutoff.set_value(if waterfx && underwater {
+            UNDERWATER_CUTOFF
+        } else {
+            OPEN_CUTOFF
+        });
+    }
+
+    /// Start the ambient sounds the first time this runs, and fade them towards the levels of the
+    /// leaf the view is in, or out when disconnected.
+    pub fn update_ambient_sounds(
+        mut ambient_sounds: Query<(&AudioSink, &mut AmbientSound)>,
+        mut started: Local<bool>,
+        conn: Option<Res<Connection>>,
+        vars: Res<SoundVars>,
+        time: Res<Time>,
+        vfs: Res<Vfs>,
+        asset_server: Res<AssetServer>,
+        mixer: Res<GlobalMixer>,
+        mut commands: Commands,
+    ) {
+        if !*started {
+            *started = true;
+            for (index, name) in AMBIENT_SOUNDS.iter().enumerate() {
+                let Some(name) = name else {
+                    continue;
+                };
+
+                match load(&vfs, name) {
+                    Ok(source) => {
+                        commands.spawn((
+                            AmbientSound { index, level: 0.0 },
+                            AudioBundle {
+                                source: asset_server.add(source),
+                                settings: PlaybackSettings {
+                                    mode: PlaybackMode::Loop,
+                                    volume: Volume::new(0.0),
+                                    ..Default::default()
+                                },
+                            },
+                            AudioTarget {
+                                target: mixer.mixer,
+                            },
+                        ));
+                    }
+                    Err(e) => warn!("Couldn't load ambient sound {}: {}", name, e),
+                }
+            }
+        }
+
+        let levels = conn.map_or([0; MAX_SOUNDS], |conn| conn.state.view_ambient_levels());
+        let step = AMBIENT_FADE * time.delta_seconds();
+        for (sink, mut ambient) in ambient_sounds.iter_mut() {
+            let mut target = levels[ambient.index] as f32 / 255.0 * vars.ambient_volume;
+            if target < MIN_AMBIENT_LEVEL {
+                target = 0.0;
+            }
+
+            ambient.level = if ambient.level < target {
+                (ambient.level + step).min(target)
+            } else {
+                (ambient.level - step).max(target)
+            };
+            sink.set_volume(ambient.level * vars.effects());
+        }
     }
 
     // TODO: Use this for `startvideo`
-    pub fn write_audio(mut global_audio: ResMut<GetGlobalAudio>) {
+    pub fn write_audio(
+        mut global_audio: ResMut<GetGlobalAudio>,
+        mut output: NonSendMut<OutputDevice>,
+    ) {
         global_audio.left.update();
         global_audio.right.update();
+        output.send(&global_audio.left, &global_audio.right);
     }
 }
@@ -17,11 +17,28 @@ use bevy_mod_dynamicaudio::audio::{AudioSink, AudioTarget};
 #[derive(Resource, Default)]
 pub struct MusicPlayer {
     playing: Option<(String, Entity)>,
+    looping: bool,
+
+    /// Whether the track was paused because the window lost focus, so it's resumed when the window
+    /// gets it back.
+    focus_paused: bool,
 }
 
 impl MusicPlayer {
     pub fn new() -> MusicPlayer {
-        MusicPlayer { playing: None }
+        MusicPlayer {
+            playing: None,
+            looping: false,
+            focus_paused: false,
+        }
+    }
+
+    /// The name of the track that was last started and whether it loops, if it hasn't been
+    /// stopped. A track that doesn't loop is still returned after it ends.
+    pub fn playing(&self) -> Option<(&str, bool)> {
+        self.playing
+            .as_ref()
+            .map(|(name, _)| (name.as_str(), self.looping))
     }
 
     /// Start playing the track with the given name.
@@ -31,7 +48,8 @@ impl MusicPlayer {
     /// `"id1/music/"` or packaged in a PAK archive with a path beginning with
     /// `"music/"`.
     ///
-    /// If the specified track is already playing, this has no effect.
+    /// If `looping` is false, the track stops when it reaches the end. If the specified track is
+    /// already playing, this has no effect.
     pub fn play_named<S>(
         &mut self,
         asset_server: &AssetServer,
@@ -39,15 +57,16 @@ impl MusicPlayer {
         vfs: &Vfs,
         mixer: Option<AudioTarget>,
         name: S,
+        looping: bool,
     ) -> Result<(), SoundError>
     where
         S: AsRef<str>,
     {
         let name = name.as_ref();
 
-        // don't replay the same track
-        if let Some((playing, _)) = &self.playing {
-            if playing == name {
+        // don't replay the same track, unless it's played once and finished
+        if let Some((playing, entity)) = &self.playing {
+            if playing == name && commands.get_entity(*entity).is_some() {
                 return Ok(());
             }
         }
@@ -78,27 +97,22 @@ impl MusicPlayer {
 
         self.stop(commands);
 
+        let settings = PlaybackSettings {
+            mode: if looping {
+                PlaybackMode::Loop
+            } else {
+                PlaybackMode::Despawn
+            },
+            ..Default::default()
+        };
         let entity = match mixer {
-            Some(target) => commands.spawn((
-                AudioBundle {
-                    source,
-                    settings: PlaybackSettings {
-                        mode: PlaybackMode::Loop,
-                        ..Default::default()
-                    },
-                },
-                target,
-            )),
-            None => commands.spawn(AudioBundle {
-                source,
-                settings: PlaybackSettings {
-                    mode: PlaybackMode::Loop,
-                    ..Default::default()
-                },
-            }),
+            Some(target) => commands.spawn((AudioBundle { source, settings }, target)),
+            None => commands.spawn(AudioBundle { source, settings }),
         }
         .id();
         self.playing = Some((name.to_string(), entity));
+        self.looping = looping;
+        self.focus_paused = false;
 
         Ok(())
     }
@@ -114,6 +128,7 @@ impl MusicPlayer {
         vfs: &Vfs,
         mixer: Option<AudioTarget>,
         track_id: usize,
+        looping: bool,
     ) -> Result<(), SoundError> {
         self.play_named(
             asset_server,
@@ -121,6 +136,7 @@ impl MusicPlayer {
             vfs,
             mixer,
             format!("track{:02}", track_id),
+            looping,
         )
     }
 
@@ -130,11 +146,11 @@ impl MusicPlayer {
     /// resumed later, use `MusicPlayer::pause()`.
     ///
     /// If no music track is currently playing, this has no effect.
-    pub fn stop(&self, commands: &mut Commands) {
+    pub fn stop(&mut self, commands: &mut Commands) {
         if let Some(mut entity) = self
             .playing
-            .as_ref()
-            .and_then(|(_, e)| commands.get_entity(*e))
+            .take()
+            .and_then(|(_, e)| commands.get_entity(e))
         {
             entity.despawn();
         }
@@ -150,13 +166,43 @@ impl MusicPlayer {
         }
     }
 
+    /// Set the volume of the current music track.
+    ///
+    /// If no music track is currently playing, this has no effect.
+    pub fn set_volume(&self, query: &Query<&AudioSink>, volume: f32) {
+        if let Some(sink) = self.playing.as_ref().and_then(|(_, e)| query.get(*e).ok()) {
+            if sink.volume() != volume {
+                sink.set_volume(volume);
+            }
+        }
+    }
+
     /// Resume playback of the current music track.
     ///
     /// If no music track is currently playing, or if the current track is not
     /// paused, this has no effect.
-    pub fn resume(&self, query: &Query<&AudioSink>) {
+    pub fn resume(&mut self, query: &Query<&AudioSink>) {
         if let Some(sink) = self.playing.as_ref().and_then(|(_, e)| query.get(*e).ok()) {
             sink.play();
         }
+        self.focus_paused = false;
+    }
+
+    /// Pause the current track while the window doesn't have focus, and resume it when the window
+    /// gets focus back.
+    ///
+    /// A track that was already paused when the window lost focus stays paused.
+    pub fn set_focused(&mut self, query: &Query<&AudioSink>, focused: bool) {
+        let Some(sink) = self.playing.as_ref().and_then(|(_, e)| query.get(*e).ok()) else {
+            return;
+        };
+
+        if !focused && !sink.is_paused() {
+            sink.pause();
+            self.focus_paused = true;
+        } else if focused && self.focus_paused {
+            sink.play();
+            self.focus_paused = false;
+        }
     }
 }
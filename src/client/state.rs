@@ -1,13 +1,14 @@
 use std::{io::Read, iter};
 
-use super::{sound::MixerEvent, view::BobVars};
+use super::{fog::Fog, sound::MixerEvent, view::BobVars};
 use crate::{
     client::{
         entity::{
             particle::{Particle, Particles, TrailKind},
-            Beam, ClientEntity, Light, LightDesc, Lights, MAX_BEAMS, MAX_TEMP_ENTITIES,
+            Beam, ClientEntity, Light, LightDesc, Lights, LIGHT_EXPLOSION, LIGHT_LIGHTNING,
+            LIGHT_MUZZLE_FLASH, LIGHT_ROCKET, LIGHT_WHITE, MAX_BEAMS, MAX_TEMP_ENTITIES,
         },
-        render::Camera,
+        input::gamepad::GamepadSticks,
         sound::{Listener, StartSound},
         view::{IdleVars, KickVars, MouseVars, RollVars, View},
         ClientError, ColorShiftCode, IntermissionKind, MoveVars, MAX_STATS,
@@ -16,13 +17,14 @@ use crate::{
         bsp,
         console::Registry,
         engine,
-        math::{self, Angles},
+        math::Angles,
         model::{Model, ModelFlags, ModelKind, SyncType},
         net::{
             self, BeamEntityKind, ButtonFlags, ColorShift, EntityEffects, ItemFlags, PlayerData,
             PointEntityKind, TempEntity,
         },
-        util::QString,
+        parse,
+        util::{self, QString},
         vfs::Vfs,
     },
 };
@@ -32,7 +34,7 @@ use cgmath::{Angle as _, Deg, InnerSpace as _, Matrix4, Vector3, Zero as _};
 use chrono::Duration;
 use hashbrown::HashMap;
 use lazy_static::lazy_static;
-use net::{ClientCmd, ClientStat, EntityState, EntityUpdate, PlayerColor};
+use net::{ClientCmd, ClientStat, EntityState, EntityUpdate, GameType, PlayerColor};
 use rand::{
     distributions::{Distribution as _, Uniform},
     rngs::SmallRng,
@@ -49,9 +51,13 @@ const CACHED_SOUND_NAMES: &[&str] = &[
     "wizard/hit.wav",
 ];
 
-const MAX_LIGHT_STYLES: usize = 64;
+pub const MAX_LIGHT_STYLES: usize = 64;
 
-#[derive(Clone)]
+/// How far past the last server update non-player entities are allowed to keep moving when the
+/// next update is late.
+const MAX_EXTRAPOLATION_MS: i64 = 50;
+
+#[derive(Clone, Hash)]
 pub struct PlayerInfo {
     pub name: QString,
     pub frags: i32,
@@ -59,8 +65,6 @@ pub struct PlayerInfo {
     // translations: [u8; VID_GRADES],
 }
 
-// TODO: We clone this into the render world but this is inefficient
-//       e.g., none of the elements related to sound need to be accessed from the renderer
 // client information regarding the current level
 #[derive(Clone)]
 pub struct ClientState {
@@ -72,6 +76,15 @@ pub struct ClientState {
 
     pub worldmodel_id: usize,
 
+    // the name of the level, e.g. `e1m1` for `maps/e1m1.bsp`
+    pub map_name: String,
+
+    // set by the level's worldspawn, or the `fog` command
+    pub fog: Fog,
+
+    // the skybox drawn in place of sky brushes, set by the level's worldspawn or the `sky` command
+    pub sky: Option<String>,
+
     // name-to-id map
     pub model_names: im::HashMap<String, usize>,
 
@@ -101,12 +114,16 @@ pub struct ClientState {
     pub stats: [i32; MAX_STATS],
 
     pub max_players: usize,
+    pub game_type: GameType,
     pub player_info: [Option<PlayerInfo>; net::MAX_CLIENTS],
 
     // the last two timestamps sent by the server (for lerping)
     pub msg_times: [Duration; 2],
     pub time: Duration,
     pub lerp_factor: f32,
+    /// The interpolation ratio for entities other than the player, which goes past 1 when
+    /// extrapolating.
+    pub entity_lerp_factor: f32,
 
     pub items: ItemFlags,
     pub item_get_time: [Duration; net::MAX_ITEMS],
@@ -136,6 +153,37 @@ pub struct ClientInfo {
     pub entity_map: HashMap<usize, Entity>,
 }
 
+/// The fog and skybox set by the `fog` and `sky` keys of the level's worldspawn, if it has them.
+fn worldspawn_settings(ent_string: &str) -> (Fog, Option<String>) {
+    let Ok(entities) = parse::map::entities(ent_string) else {
+        return default();
+    };
+    let Some(worldspawn) = entities
+        .first()
+        .filter(|worldspawn| worldspawn.get("classname") == Some(&"worldspawn"))
+    else {
+        return default();
+    };
+
+    let fog = match worldspawn.get("fog").map(|value| Fog::parse(value)) {
+        Some(Ok(fog)) => fog,
+        Some(Err(e)) => {
+            warn!("Ignoring worldspawn fog: {}", e);
+            Fog::default()
+        }
+        None => Fog::default(),
+    };
+
+    // other engines have used these keys for the skybox too
+    let sky = ["sky", "skyname", "q1sky"]
+        .iter()
+        .find_map(|key| worldspawn.get(key))
+        .map(|name| name.trim().to_owned())
+        .filter(|name| !name.is_empty());
+
+    (fog, sky)
+}
+
 impl ClientState {
     // TODO: add parameter for number of player slots and reserve them in entity list
     pub fn new() -> ClientState {
@@ -143,6 +191,9 @@ impl ClientState {
             rng: SmallRng::from_entropy(),
             models: iter::once(Model::none()).collect(),
             worldmodel_id: 1,
+            map_name: String::new(),
+            fog: Fog::default(),
+            sky: None,
             model_names: default(),
             sounds: default(),
             cached_sounds: default(),
@@ -156,10 +207,12 @@ impl ClientState {
             light_styles: iter::repeat_n("".into(), MAX_LIGHT_STYLES).collect(),
             stats: [0; MAX_STATS],
             max_players: 0,
+            game_type: GameType::CoOp,
             player_info: default(),
             msg_times: [Duration::zero(), Duration::zero()],
             time: Duration::zero(),
             lerp_factor: 0.0,
+            entity_lerp_factor: 0.0,
             items: ItemFlags::empty(),
             item_get_time: [Duration::zero(); net::MAX_ITEMS],
             color_shifts: default(),
@@ -177,19 +230,35 @@ impl ClientState {
 
     pub fn from_server_info<SName: AsRef<str>>(
         vfs: &Vfs,
+        levels: &bsp::LevelCache,
         asset_server: &AssetServer,
         max_clients: u8,
+        game_type: GameType,
         model_precache: Vec<String>,
         sound_precache: Vec<SName>,
     ) -> Result<ClientState, ClientError> {
+        // the world is always the first model
+        let map_name = model_precache
+            .first()
+            .map(|name| util::file_stem(name, ".bsp").to_owned())
+            .unwrap_or_default();
+
         // TODO: validate submodel names
         let mut models: im::Vector<_> = iter::once(Model::none()).collect();
         let mut model_names = im::HashMap::new();
+        let mut fog = Fog::default();
+        let mut sky = None;
         for mod_name in model_precache {
             // BSPs can have more than one model
             if mod_name.ends_with(".bsp") {
-                let bsp_data = vfs.open(&mod_name)?;
-                let (mut brush_models, _) = bsp::load(bsp_data).unwrap();
+                // shared with the server, so a local game only parses the level once
+                let (mut brush_models, ent_string) = levels
+                    .load(vfs, &mod_name)
+                    .map_err(|e| ClientError::Level(mod_name.clone(), e))?;
+                if models.len() == 1 {
+                    (fog, sky) = worldspawn_settings(&ent_string);
+                }
+
                 for bmodel in brush_models.drain(..) {
                     let id = models.len();
                     let name = bmodel.name().to_owned();
@@ -240,10 +309,14 @@ impl ClientState {
 
         Ok(ClientState {
             models,
+            map_name,
+            fog,
+            sky,
             model_names,
             sounds,
             cached_sounds,
             max_players: max_clients as usize,
+            game_type,
             ..ClientState::new()
         })
     }
@@ -258,11 +331,14 @@ impl ClientState {
     /// Update the client state interpolation ratio.
     ///
     /// This calculates the ratio used to interpolate entities between the last
-    /// two updates from the server.
-    pub fn update_interp_ratio(&mut self, cl_nolerp: bool) {
+    /// two updates from the server. If `cl_lerp` is set, entities other than the player keep
+    /// moving for up to [`MAX_EXTRAPOLATION_MS`] past the last update when the next one is late;
+    /// otherwise they're drawn where the last update put them.
+    pub fn update_interp_ratio(&mut self, cl_nolerp: bool, cl_lerp: bool) {
         if cl_nolerp {
             self.time = self.msg_times[0];
             self.lerp_factor = 1.0;
+            self.entity_lerp_factor = 1.0;
             return;
         }
 
@@ -271,6 +347,7 @@ impl ClientState {
             d if d == Duration::zero() => {
                 self.time = self.msg_times[0];
                 self.lerp_factor = 1.0;
+                self.entity_lerp_factor = 1.0;
                 return;
             }
 
@@ -290,9 +367,17 @@ impl ClientState {
             d => d,
         });
 
+        let max_extrapolation = if cl_lerp {
+            Duration::try_milliseconds(MAX_EXTRAPOLATION_MS).unwrap()
+        } else {
+            Duration::zero()
+        };
+        let max_factor = 1.0 + engine::duration_to_f32(max_extrapolation) / server_delta;
+
         let frame_delta = engine::duration_to_f32(self.time - self.msg_times[1]);
+        let f = frame_delta / server_delta;
 
-        self.lerp_factor = match frame_delta / server_delta {
+        self.lerp_factor = match f {
             f if f < 0.0 => {
                 if f < -0.01 {
                     self.time = self.msg_times[1];
@@ -302,15 +387,23 @@ impl ClientState {
             }
 
             f if f > 1.0 => {
-                if f > 1.01 {
-                    self.time = self.msg_times[0];
+                // don't let the clock run any further ahead of the server than entities are
+                // allowed to be extrapolated
+                if f > max_factor + 0.01 {
+                    self.time = self.msg_times[0] + max_extrapolation;
                 }
 
                 1.0
             }
 
             f => f,
-        }
+        };
+
+        self.entity_lerp_factor = if cl_lerp {
+            f.clamp(0.0, max_factor)
+        } else {
+            1.0
+        };
     }
 
     /// Update all entities in the game world.
@@ -328,6 +421,7 @@ impl ClientState {
         }
 
         let lerp_factor = self.lerp_factor;
+        let view_entity_id = self.view_entity_id();
 
         self.velocity =
             self.msg_velocity[1] + lerp_factor * (self.msg_velocity[0] - self.msg_velocity[1]);
@@ -364,8 +458,12 @@ impl ClientState {
                     // if the entity moved more than 100 units in one frame,
                     // assume it was teleported and don't lerp anything
                     1.0
-                } else {
+                } else if ent.id == view_entity_id {
+                    // the player is never extrapolated, since the view would overshoot and snap
+                    // back every time an update is late
                     lerp_factor
+                } else {
+                    self.entity_lerp_factor
                 };
 
                 ent.origin = ent.msg_origins[1] + ent_lerp_factor * origin_delta;
@@ -377,7 +475,7 @@ impl ClientState {
                 for i in 0..3 {
                     let mut angle_delta = ent.msg_angles[0][i] - ent.msg_angles[1][i];
                     if angle_delta > Deg(180.0) {
-                        angle_delta = Deg(360.0) - angle_delta;
+                        angle_delta = angle_delta - Deg(360.0);
                     } else if angle_delta < Deg(-180.0) {
                         angle_delta = Deg(360.0) + angle_delta;
                     }
@@ -407,6 +505,7 @@ impl ClientState {
                         decay_rate: 0.0,
                         min_radius: Some(32.0),
                         ttl: Duration::try_milliseconds(100).unwrap(),
+                        color: LIGHT_MUZZLE_FLASH,
                     },
                     ent.light_id,
                 ));
@@ -421,6 +520,7 @@ impl ClientState {
                         decay_rate: 0.0,
                         min_radius: None,
                         ttl: Duration::try_milliseconds(1).unwrap(),
+                        color: LIGHT_WHITE,
                     },
                     ent.light_id,
                 ));
@@ -435,6 +535,7 @@ impl ClientState {
                         decay_rate: 0.0,
                         min_radius: None,
                         ttl: Duration::try_milliseconds(1).unwrap(),
+                        color: LIGHT_WHITE,
                     },
                     ent.light_id,
                 ));
@@ -458,6 +559,7 @@ impl ClientState {
                         decay_rate: 0.0,
                         min_radius: None,
                         ttl: Duration::try_milliseconds(10).unwrap(),
+                        color: LIGHT_ROCKET,
                     },
                     ent.light_id,
                 ));
@@ -498,6 +600,7 @@ impl ClientState {
                         decay_rate: 0.0,
                         min_radius: None,
                         ttl: Duration::try_milliseconds(1).unwrap(),
+                        color: LIGHT_WHITE,
                     },
                     ent.light_id,
                 ));
@@ -513,6 +616,7 @@ impl ClientState {
                         decay_rate: 0.0,
                         min_radius: None,
                         ttl: Duration::try_milliseconds(1).unwrap(),
+                        color: LIGHT_WHITE,
                     },
                     ent.light_id,
                 ));
@@ -542,6 +646,22 @@ impl ClientState {
                     beam.start = self.entities[view_ent].origin;
                 }
 
+                // light up wherever the beam hits
+                if let Some(color) = beam.light_color {
+                    beam.light_id = Some(self.lights.insert(
+                        self.time,
+                        LightDesc {
+                            origin: beam.end,
+                            init_radius: 150.0,
+                            decay_rate: 0.0,
+                            min_radius: None,
+                            ttl: Duration::try_milliseconds(1).unwrap(),
+                            color,
+                        },
+                        beam.light_id,
+                    ));
+                }
+
                 let vec = beam.end - beam.start;
                 let yaw = Deg::from(cgmath::Rad(vec.y.atan2(vec.x))).normalize();
                 let forward = (vec.x.powf(2.0) + vec.y.powf(2.0)).sqrt();
@@ -623,9 +743,11 @@ impl ClientState {
         frame_time: Duration,
         move_vars: MoveVars,
         mouse_vars: MouseVars,
+        mouse_delta: (f32, f32),
+        sticks: GamepadSticks,
         impulse: Option<u8>,
     ) -> ClientCmd {
-        let mlook = registry.is_pressed("mlook");
+        let mlook = registry.is_pressed("mlook") || mouse_vars.freelook != 0.0;
         self.view.handle_input(
             frame_time,
             &*registry,
@@ -635,6 +757,8 @@ impl ClientState {
             move_vars.cl_pitchspeed,
             move_vars.cl_yawspeed,
             mouse_vars,
+            mouse_delta,
+            (sticks.look.x, sticks.look.y),
         );
 
         let mut move_left = registry.is_pressed("moveleft");
@@ -656,7 +780,15 @@ impl ClientState {
             forwardmove -= move_vars.cl_backspeed * registry.is_pressed("back") as i32 as f32;
         }
 
-        if registry.is_pressed("speed") {
+        sidemove += move_vars.cl_sidespeed * sticks.movement.x;
+        forwardmove += sticks.movement.y
+            * if sticks.movement.y > 0.0 {
+                move_vars.cl_forwardspeed
+            } else {
+                move_vars.cl_backspeed
+            };
+
+        if move_vars.running(registry.is_pressed("speed")) {
             sidemove *= move_vars.cl_movespeedkey;
             upmove *= move_vars.cl_movespeedkey;
             forwardmove *= move_vars.cl_movespeedkey;
@@ -672,8 +804,14 @@ impl ClientState {
             button_flags |= ButtonFlags::JUMP;
         }
 
-        if !mlook {
-            // TODO: IN_Move (mouse / joystick / gamepad)
+        // the mouse moves the player along the axes that it isn't turning the view around
+        let (mouse_x, mouse_y) = mouse_vars.scale(mouse_delta);
+        let strafe = registry.is_pressed("strafe");
+        if strafe {
+            sidemove += mouse_vars.side_factor * mouse_x;
+        }
+        if strafe || !mlook {
+            forwardmove -= mouse_vars.forward_factor * mouse_y;
         }
 
         let send_time = self.msg_times[0];
@@ -898,6 +1036,7 @@ impl ClientState {
                                 decay_rate: 300.0,
                                 min_radius: None,
                                 ttl: Duration::try_milliseconds(500).unwrap(),
+                                color: LIGHT_EXPLOSION,
                             },
                             None,
                         );
@@ -933,6 +1072,7 @@ impl ClientState {
                                 decay_rate: 300.0,
                                 min_radius: None,
                                 ttl: Duration::try_milliseconds(500).unwrap(),
+                                color: LIGHT_EXPLOSION,
                             },
                             None,
                         );
@@ -980,6 +1120,10 @@ impl ClientState {
                 end,
             } => {
                 use BeamEntityKind::*;
+                let light_color = match kind {
+                    Lightning { .. } => Some(LIGHT_LIGHTNING),
+                    Grapple => None,
+                };
                 let model_name = match kind {
                     Lightning { model_id } => format!(
                         "progs/bolt{}.mdl",
@@ -994,7 +1138,14 @@ impl ClientState {
                 };
 
                 if let Some(beam) = self.model_names.get(&model_name) {
-                    self.spawn_beam(self.time, *entity_id as usize, *beam, *start, *end);
+                    self.spawn_beam(
+                        self.time,
+                        *entity_id as usize,
+                        *beam,
+                        *start,
+                        *end,
+                        light_color,
+                    );
                 }
             }
         }
@@ -1007,6 +1158,7 @@ impl ClientState {
         model_id: usize,
         start: Vector3<f32>,
         end: Vector3<f32>,
+        light_color: Option<[f32; 3]>,
     ) {
         // always override beam with same entity_id if it exists
         // otherwise use the first free slot
@@ -1018,6 +1170,7 @@ impl ClientState {
                     beam.expire = time + Duration::try_milliseconds(200).unwrap();
                     beam.start = start;
                     beam.end = end;
+                    beam.light_color = light_color;
                 }
             } else if free.is_none() {
                 free = Some(i);
@@ -1031,6 +1184,8 @@ impl ClientState {
                 expire: time + Duration::try_milliseconds(200).unwrap(),
                 start,
                 end,
+                light_color,
+                light_id: None,
             });
         } else {
             warn!("No free beam slots!");
@@ -1078,6 +1233,30 @@ impl ClientState {
         }
     }
 
+    /// The ambient sound levels of the leaf the view is in, for water, sky, slime and lava.
+    pub fn view_ambient_levels(&self) -> [u8; bsp::MAX_SOUNDS] {
+        let Some(ModelKind::Brush(bmodel)) = self.models.get(1).map(|m| m.kind()) else {
+            return [0; bsp::MAX_SOUNDS];
+        };
+        let bsp_data = bmodel.bsp_data();
+
+        self.entities
+            .get(self.view.entity_id())
+            .map_or([0; bsp::MAX_SOUNDS], |e| {
+                bsp_data.leaves()[bsp_data.find_leaf(e.origin)].sounds
+            })
+    }
+
+    /// Whether the view is in water, slime or lava, which warps the screen.
+    pub fn view_underwater(&self) -> bool {
+        matches!(
+            self.view_leaf_contents(),
+            Ok(bsp::BspLeafContents::Water
+                | bsp::BspLeafContents::Slime
+                | bsp::BspLeafContents::Lava)
+        )
+    }
+
     pub fn update_color_shifts(&mut self, frame_time: Duration) -> Result<(), ClientError> {
         let float_time = engine::duration_to_f32(frame_time);
 
@@ -1198,6 +1377,10 @@ impl ClientState {
         }
     }
 
+    pub fn viewmodel_frame(&self) -> usize {
+        self.stats[ClientStat::WeaponFrame as usize].max(0) as usize
+    }
+
     pub fn iter_visible_entities(&self) -> impl Iterator<Item = &ClientEntity> {
         self.visible_entity_ids
             .iter()
@@ -1222,31 +1405,29 @@ impl ClientState {
         self.view.entity_id()
     }
 
-    pub fn camera(&self, aspect: f32, fov: Deg<f32>) -> Camera {
-        let fov_y = math::fov_x_to_fov_y(fov, aspect).unwrap();
-        Camera::new(
-            self.view.final_origin(),
-            self.view.final_angles(),
-            cgmath::perspective(fov_y, aspect, 4.0, 4096.0),
-        )
+    pub fn camera_origin(&self) -> Vector3<f32> {
+        self.view.final_origin()
     }
 
-    pub fn demo_camera(&self, aspect: f32, fov: Deg<f32>) -> Camera {
-        let fov_y = math::fov_x_to_fov_y(fov, aspect).unwrap();
-        let angles = self
-            .entities
+    pub fn viewmodel_origin(&self) -> Vector3<f32> {
+        self.view.viewmodel_origin()
+    }
+
+    /// Return the angles of the camera. Demos don't record the player's view angles, so when
+    /// playing one back these are taken from the view entity instead.
+    pub fn camera_angles(&self, demo: bool) -> Angles {
+        if !demo {
+            return self.view.final_angles();
+        }
+
+        self.entities
             .get(self.view.entity_id())
             .map(|e| Angles {
                 pitch: e.angles.x,
                 roll: e.angles.z,
                 yaw: e.angles.y,
             })
-            .unwrap_or_default();
-        Camera::new(
-            self.view.final_origin(),
-            angles,
-            cgmath::perspective(fov_y, aspect, 4.0, 4096.0),
-        )
+            .unwrap_or_default()
     }
 
     pub fn lightstyle_values(&self) -> ArrayVec<f32, MAX_LIGHT_STYLES> {
@@ -1337,3 +1518,52 @@ impl ClientState {
 }
 
 pub mod systems {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_update_interp_ratio_extrapolation() {
+        let ms = |ms| Duration::try_milliseconds(ms).unwrap();
+
+        let mut state = ClientState::new();
+        state.msg_times = [ms(1100), ms(1000)];
+
+        state.time = ms(1050);
+        state.update_interp_ratio(false, true);
+        assert!((state.lerp_factor - 0.5).abs() < 1e-4);
+        assert!((state.entity_lerp_factor - 0.5).abs() < 1e-4);
+
+        // the next update is late, so other entities keep going
+        state.time = ms(1125);
+        state.update_interp_ratio(false, true);
+        assert_eq!(state.lerp_factor, 1.0);
+        assert!((state.entity_lerp_factor - 1.25).abs() < 1e-4);
+        assert_eq!(state.time, ms(1125));
+
+        // but only for so long
+        state.time = ms(1200);
+        state.update_interp_ratio(false, true);
+        assert!((state.entity_lerp_factor - 1.5).abs() < 1e-4);
+        assert_eq!(state.time, ms(1150));
+
+        // without cl_lerp other entities are drawn where the last update put them
+        state.time = ms(1050);
+        state.update_interp_ratio(false, false);
+        assert!((state.lerp_factor - 0.5).abs() < 1e-4);
+        assert_eq!(state.entity_lerp_factor, 1.0);
+
+        state.time = ms(1200);
+        state.update_interp_ratio(false, false);
+        assert_eq!(state.entity_lerp_factor, 1.0);
+        assert_eq!(state.time, ms(1100));
+
+        // cl_nolerp draws everything where the last update put it
+        state.time = ms(1200);
+        state.update_interp_ratio(true, true);
+        assert_eq!(state.lerp_factor, 1.0);
+        assert_eq!(state.entity_lerp_factor, 1.0);
+        assert_eq!(state.time, ms(1100));
+    }
+}
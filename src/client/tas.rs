@@ -0,0 +1,386 @@
+//! Tool-assisted input (`tas_*`).
+//!
+//! `tas_pause` freezes the game clock, and `tas_frameadvance` then runs the server a given number
+//! of ticks at a time. `tas_play` replaces the local player's input with a script that gives the
+//! movement command for every tick, so a run can be built up and replayed exactly. Scripts are
+//! only reproducible with `sv_deterministic` set.
+//!
+//! A script has one line for each stretch of ticks with the same input:
+//!
+//! ```text
+//! # ticks forward side up pitch yaw [attack] [jump] [impulse=<n>]
+//! 20 400 0 0 0 90
+//! 1 400 0 0 0 90 jump
+//! ```
+//!
+//! Blank lines and anything after a `#` are ignored. An impulse is only sent on the first tick of
+//! its line.
+
+use std::{fs, io::Read as _};
+
+use bevy::prelude::*;
+use cgmath::{Deg, Vector3};
+use chrono::Duration;
+use clap::Parser;
+
+use crate::{
+    common::{
+        console::{ExecResult, RegisterCmdExt, Registry},
+        net::{ButtonFlags, ClientCmd, ClientMessage, MessageKind},
+        vfs::Vfs,
+    },
+    server::Session,
+};
+
+use super::Connection;
+
+/// The input for a run of ticks in a script.
+#[derive(Clone, Debug, PartialEq)]
+struct TasLine {
+    ticks: u32,
+    forward: i16,
+    side: i16,
+    up: i16,
+    pitch: f32,
+    yaw: f32,
+    buttons: ButtonFlags,
+    impulse: u8,
+}
+
+impl TasLine {
+    fn parse(line: &str) -> Result<Option<TasLine>, String> {
+        let line = line.split('#').next().unwrap_or("");
+        let mut words = line.split_whitespace();
+        let Some(ticks) = words.next() else {
+            return Ok(None);
+        };
+
+        fn num<T: std::str::FromStr>(word: Option<&str>, what: &str) -> Result<T, String> {
+            let word = word.ok_or_else(|| format!("missing {}", what))?;
+            word.parse()
+                .map_err(|_| format!("invalid {} \"{}\"", what, word))
+        }
+
+        let mut out = TasLine {
+            ticks: num(Some(ticks), "tick count")?,
+            forward: num(words.next(), "forward move")?,
+            side: num(words.next(), "side move")?,
+            up: num(words.next(), "up move")?,
+            pitch: num(words.next(), "pitch")?,
+            yaw: num(words.next(), "yaw")?,
+            buttons: ButtonFlags::empty(),
+            impulse: 0,
+        };
+
+        for word in words {
+            match word {
+                "attack" => out.buttons |= ButtonFlags::ATTACK,
+                "jump" => out.buttons |= ButtonFlags::JUMP,
+                _ => match word.strip_prefix("impulse=") {
+                    Some(impulse) => out.impulse = num(Some(impulse), "impulse")?,
+                    None => return Err(format!("unknown flag \"{}\"", word)),
+                },
+            }
+        }
+
+        Ok(Some(out))
+    }
+}
+
+fn parse_script(text: &str) -> Result<Vec<TasLine>, String> {
+    text.lines()
+        .enumerate()
+        .filter_map(|(i, line)| {
+            TasLine::parse(line)
+                .map_err(|e| format!("line {}: {}", i + 1, e))
+                .transpose()
+        })
+        .collect()
+}
+
+/// A script being played back.
+struct TasScript {
+    name: String,
+    lines: Vec<TasLine>,
+
+    /// The line being played and how many of its ticks have been sent.
+    line: usize,
+    tick: u32,
+}
+
+impl TasScript {
+    /// The command for the next tick, or `None` once the script has finished.
+    fn next_move(&mut self) -> Option<ClientCmd> {
+        let line = loop {
+            let line = self.lines.get(self.line)?;
+            if self.tick < line.ticks {
+                break line;
+            }
+            self.line += 1;
+            self.tick = 0;
+        };
+
+        let first = self.tick == 0;
+        self.tick += 1;
+
+        Some(ClientCmd::Move {
+            send_time: Duration::zero(),
+            angles: Vector3::new(Deg(line.pitch), Deg(line.yaw), Deg(0.0)),
+            fwd_move: line.forward,
+            side_move: line.side,
+            up_move: line.up,
+            button_flags: line.buttons,
+            impulse: if first { line.impulse } else { 0 },
+        })
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct Tas {
+    script: Option<TasScript>,
+}
+
+impl Tas {
+    /// Whether a script is providing the local player's input.
+    pub fn is_playing(&self) -> bool {
+        self.script.is_some()
+    }
+}
+
+pub fn register_commands(app: &mut App) {
+    #[derive(Parser)]
+    #[command(name = "tas_pause", about = "Pause or resume the game clock")]
+    struct TasPause;
+
+    app.command(
+        |In(TasPause), mut time: ResMut<Time<Virtual>>| -> ExecResult {
+            if time.is_paused() {
+                time.unpause();
+                "Resumed".into()
+            } else {
+                time.pause();
+                "Paused, use tas_frameadvance to step".into()
+            }
+        },
+    );
+
+    #[derive(Parser)]
+    #[command(
+        name = "tas_frameadvance",
+        about = "Run the server for a number of ticks while paused"
+    )]
+    struct TasFrameAdvance {
+        #[arg(default_value_t = 1)]
+        ticks: u32,
+    }
+
+    app.command(
+        |In(TasFrameAdvance { ticks }),
+         time: Res<Time<Virtual>>,
+         mut fixed: ResMut<Time<Fixed>>|
+         -> ExecResult {
+            if !time.is_paused() {
+                return "tas_frameadvance only works while paused (tas_pause)".into();
+            }
+
+            let step = fixed.timestep() * ticks;
+            fixed.accumulate(step);
+            default()
+        },
+    );
+
+    #[derive(Parser)]
+    #[command(
+        name = "tas_play",
+        about = "Replace the player's input with a script of per-tick movement commands"
+    )]
+    struct TasPlay {
+        script: String,
+    }
+
+    app.command(
+        |In(TasPlay { script: name }),
+         vfs: Res<Vfs>,
+         registry: Res<Registry>,
+         mut tas: ResMut<Tas>|
+         -> ExecResult {
+            let text = match vfs.open(&name) {
+                Ok(mut file) => {
+                    let mut text = String::new();
+                    file.read_to_string(&mut text).map(|_| text)
+                }
+                Err(_) => fs::read_to_string(&name),
+            };
+            let text = match text {
+                Ok(text) => text,
+                Err(e) => return format!("Couldn't read {}: {}", name, e).into(),
+            };
+
+            let lines = match parse_script(&text) {
+                Ok(lines) => lines,
+                Err(e) => return format!("{}: {}", name, e).into(),
+            };
+
+            tas.script = Some(TasScript {
+                name: name.clone(),
+                lines,
+                line: 0,
+                tick: 0,
+            });
+
+            if registry.read_cvar::<u8>("sv_deterministic").unwrap_or(0) == 0 {
+                format!(
+                    "Playing {}. sv_deterministic is off, so the result may differ between runs",
+                    name
+                )
+                .into()
+            } else {
+                format!("Playing {}", name).into()
+            }
+        },
+    );
+
+    #[derive(Parser)]
+    #[command(name = "tas_stop", about = "Stop playing a TAS script")]
+    struct TasStop;
+
+    app.command(|In(TasStop), mut tas: ResMut<Tas>| -> ExecResult {
+        match tas.script.take() {
+            Some(script) => format!("Stopped {}", script.name).into(),
+            None => default(),
+        }
+    });
+}
+
+pub mod systems {
+    use super::*;
+
+    /// Send the script's command for this tick as the local player's input. This runs in the
+    /// fixed-timestep schedule, just before the server reads client messages, so that each
+    /// command is used for exactly one tick.
+    pub fn play_script(
+        mut tas: ResMut<Tas>,
+        session: Res<Session>,
+        conn: Option<ResMut<Connection>>,
+        mut client_events: EventWriter<ClientMessage>,
+    ) {
+        let Some(script) = &mut tas.script else {
+            return;
+        };
+
+        // wait for the player to spawn
+        if session.loading() || session.client(0).and_then(|c| c.entity()).is_none() {
+            return;
+        }
+
+        let Some(cmd) = script.next_move() else {
+            info!("Finished playing {}", script.name);
+            tas.script = None;
+            return;
+        };
+
+        if let (Some(mut conn), ClientCmd::Move { angles, .. }) = (conn, &cmd) {
+            conn.state.set_view_angles(*angles);
+        }
+
+        let mut packet = Vec::new();
+        cmd.serialize(&mut packet).unwrap();
+        client_events.send(ClientMessage {
+            client_id: 0,
+            packet,
+            kind: MessageKind::Unreliable,
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_script() {
+        let lines = parse_script("# comment\n\n2 400 -200 0 10 90 jump impulse=7\n").unwrap();
+        assert_eq!(
+            lines,
+            vec![TasLine {
+                ticks: 2,
+                forward: 400,
+                side: -200,
+                up: 0,
+                pitch: 10.0,
+                yaw: 90.0,
+                buttons: ButtonFlags::JUMP,
+                impulse: 7,
+            }]
+        );
+
+        assert!(parse_script("1 400 0 0 0").is_err());
+        assert!(parse_script("1 400 0 0 0 0 crouch").is_err());
+    }
+
+    #[test]
+    fn test_script_moves() {
+        let mut script = TasScript {
+            name: "test".into(),
+            lines: parse_script("2 400 0 0 0 0 impulse=1\n0 0 0 0 0 0\n1 0 0 0 0 0 attack")
+                .unwrap(),
+            line: 0,
+            tick: 0,
+        };
+
+        let impulses = std::iter::from_fn(|| script.next_move())
+            .map(|cmd| match cmd {
+                ClientCmd::Move {
+                    impulse,
+                    button_flags,
+                    ..
+                } => (impulse, button_flags),
+                _ => unreachable!(),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            impulses,
+            vec![
+                (1, ButtonFlags::empty()),
+                (0, ButtonFlags::empty()),
+                (0, ButtonFlags::ATTACK),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_script_line_sets_entity_fields() {
+        use crate::server::{
+            apply_client_move,
+            world::{Entity, EntityTypeDef, FieldAddrFloat, FieldAddrVector, STATIC_ADDRESS_COUNT},
+        };
+
+        let mut script = TasScript {
+            name: "test".into(),
+            lines: parse_script("1 400 -200 0 10 90 attack jump impulse=7").unwrap(),
+            line: 0,
+            tick: 0,
+        };
+        let type_def = EntityTypeDef::new(STATIC_ADDRESS_COUNT, Box::new([])).unwrap();
+        let mut entity = Entity::new(&type_def);
+
+        apply_client_move(&mut entity, &type_def, &script.next_move().unwrap()).unwrap();
+
+        let float = |addr: FieldAddrFloat| entity.get_float(&type_def, addr as _).unwrap();
+        assert_eq!(float(FieldAddrFloat::Button0), 1.0);
+        assert_eq!(float(FieldAddrFloat::Button2), 1.0);
+        assert_eq!(float(FieldAddrFloat::Impulse), 7.0);
+        assert_eq!(
+            entity
+                .get_vector(&type_def, FieldAddrVector::ViewAngle as _)
+                .unwrap(),
+            [10.0, 90.0, 0.0]
+        );
+        assert_eq!(
+            entity
+                .get_vector(&type_def, FieldAddrVector::MoveDirection as _)
+                .unwrap(),
+            [400.0, -200.0, 0.0]
+        );
+    }
+}
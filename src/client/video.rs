@@ -0,0 +1,377 @@
+//! Window and display mode settings.
+//!
+//! The size comes from `vid_mode`, one of [`VIDEO_MODES`], or from `vid_width` and `vid_height` if
+//! `vid_mode` is -1. Changes are applied to the window as soon as the cvars are set. The new size reaches the
+//! renderer through `RenderResolution` like any other resize.
+//!
+//! `vid_restart` rebuilds the renderer from scratch, so that settings which only take effect when
+//! resources are created, like `r_texture_compression`, apply to everything already loaded.
+//!
+//! A `vid_vsync` mode that the window's surface doesn't support falls back to `fifo`, which every
+//! surface supports.
+
+use bevy::{
+    prelude::*,
+    render::{
+        extract_resource::ExtractResource,
+        renderer::{RenderAdapter, RenderInstance},
+        view::{ExtractedWindow, ExtractedWindows},
+    },
+    window::{PresentMode, PrimaryWindow, WindowMode},
+};
+use clap::Parser;
+use hashbrown::HashMap;
+use serde_lexpr::Value;
+
+use crate::common::console::{Cvar, ExecResult, RegisterCmdExt, Registry};
+
+/// The resolutions that `vid_mode` selects from.
+pub const VIDEO_MODES: &[(u32, u32)] = &[
+    (640, 480),
+    (800, 600),
+    (1024, 768),
+    (1280, 720),
+    (1280, 1024),
+    (1366, 768),
+    (1600, 900),
+    (1920, 1080),
+    (2560, 1440),
+    (3840, 2160),
+];
+
+/// Counts `vid_restart`s. The renderer tears down its state whenever this changes.
+#[derive(Resource, ExtractResource, Clone, Copy, Default, PartialEq, Eq)]
+pub struct VidRestart(pub u32);
+
+/// Parse a `vid_fullscreen` value, either a number or the name of the mode.
+fn parse_window_mode(value: &Value) -> Option<WindowMode> {
+    let name = match value {
+        Value::Number(n) => return n.as_u64().and_then(window_mode_from_index),
+        Value::Symbol(name) | Value::String(name) => name,
+        _ => return None,
+    };
+
+    match name.to_ascii_lowercase().as_str() {
+        "windowed" => Some(WindowMode::Windowed),
+        "exclusive" => Some(WindowMode::Fullscreen),
+        "borderless" => Some(WindowMode::BorderlessFullscreen),
+        _ => None,
+    }
+}
+
+fn window_mode_from_index(index: u64) -> Option<WindowMode> {
+    match index {
+        0 => Some(WindowMode::Windowed),
+        1 => Some(WindowMode::Fullscreen),
+        2 => Some(WindowMode::BorderlessFullscreen),
+        _ => None,
+    }
+}
+
+/// The requested size of the window or fullscreen video mode, or `None` to keep the current size.
+fn video_size(registry: &Registry) -> Option<(u32, u32)> {
+    let mode = registry.read_cvar::<i64>("vid_mode").unwrap_or(-1);
+    if mode >= 0 {
+        let size = VIDEO_MODES.get(mode as usize).copied();
+        if size.is_none() {
+            warn!("vid_mode must be -1 or up to {}", VIDEO_MODES.len() - 1);
+        }
+        return size;
+    }
+
+    // a size of 0 keeps the current size, or the desktop resolution when fullscreen
+    let width = registry.read_cvar::<u32>("vid_width").unwrap_or(0);
+    let height = registry.read_cvar::<u32>("vid_height").unwrap_or(0);
+    (width > 0 && height > 0).then_some((width, height))
+}
+
+/// Reconfigure the window to match `vid_fullscreen` and the size from `vid_mode`, or `vid_width`
+/// and `vid_height`.
+fn apply_video_mode(
+    In(_): In<Value>,
+    registry: Res<Registry>,
+    mut windows: Query<&mut Window, With<PrimaryWindow>>,
+) {
+    if let Ok(mut window) = windows.get_single_mut() {
+        set_video_mode(&registry, &mut window);
+    }
+}
+
+fn set_video_mode(registry: &Registry, window: &mut Window) {
+    let Some(mode) = registry
+        .get_cvar("vid_fullscreen")
+        .and_then(|cvar| parse_window_mode(cvar.value()))
+    else {
+        warn!("vid_fullscreen must be 0 (windowed), 1 (exclusive) or 2 (borderless)");
+        return;
+    };
+
+    let size = video_size(registry);
+
+    let mode = match (mode, size) {
+        // use the video mode closest to the requested size rather than the largest one
+        (WindowMode::Fullscreen, Some(_)) => WindowMode::SizedFullscreen,
+        (mode, _) => mode,
+    };
+
+    if let Some((width, height)) = size {
+        if mode != WindowMode::BorderlessFullscreen
+            && (window.physical_width(), window.physical_height()) != (width, height)
+        {
+            window.resolution.set_physical_resolution(width, height);
+        }
+    }
+
+    if window.mode != mode {
+        window.mode = mode;
+    }
+}
+
+/// Parse a `vid_vsync` value. 0 and 1 pick the best supported mode without or with vsync, the
+/// names select a specific mode, falling back to Fifo if the display doesn't support it.
+fn parse_present_mode(value: &Value) -> Option<PresentMode> {
+    let name = match value {
+        Value::Number(n) => {
+            return match n.as_u64()? {
+                // Immediate, falling back to Mailbox then Fifo
+                0 => Some(PresentMode::AutoNoVsync),
+                // FifoRelaxed, falling back to Fifo
+                1 => Some(PresentMode::AutoVsync),
+                _ => None,
+            };
+        }
+        Value::Symbol(name) | Value::String(name) => name,
+        _ => return None,
+    };
+
+    match name.to_ascii_lowercase().as_str() {
+        "immediate" => Some(PresentMode::Immediate),
+        "mailbox" => Some(PresentMode::Mailbox),
+        "fifo" => Some(PresentMode::Fifo),
+        _ => None,
+    }
+}
+
+fn to_wgpu_present_mode(mode: PresentMode) -> wgpu::PresentMode {
+    match mode {
+        PresentMode::AutoVsync => wgpu::PresentMode::AutoVsync,
+        PresentMode::AutoNoVsync => wgpu::PresentMode::AutoNoVsync,
+        PresentMode::Fifo => wgpu::PresentMode::Fifo,
+        PresentMode::FifoRelaxed => wgpu::PresentMode::FifoRelaxed,
+        PresentMode::Immediate => wgpu::PresentMode::Immediate,
+        PresentMode::Mailbox => wgpu::PresentMode::Mailbox,
+    }
+}
+
+/// The present modes supported by `window`'s surface. The renderer's own surface isn't reachable,
+/// so this asks a temporary one for the same window.
+#[cfg(not(target_arch = "wasm32"))]
+fn surface_present_modes(
+    instance: &RenderInstance,
+    adapter: &RenderAdapter,
+    window: &ExtractedWindow,
+) -> Vec<wgpu::PresentMode> {
+    let target = wgpu::SurfaceTargetUnsafe::RawHandle {
+        raw_display_handle: window.handle.display_handle,
+        raw_window_handle: window.handle.window_handle,
+    };
+
+    // SAFETY: the handles of extracted windows are valid, and the surface doesn't outlive this
+    // function
+    match unsafe { instance.create_surface_unsafe(target) } {
+        Ok(surface) => surface.get_capabilities(adapter).present_modes,
+        Err(e) => {
+            warn!("Couldn't check the window's present modes: {}", e);
+            vec![wgpu::PresentMode::Fifo]
+        }
+    }
+}
+
+/// A second context can't be made for the canvas, and browsers only present in time with the
+/// display anyway.
+#[cfg(target_arch = "wasm32")]
+fn surface_present_modes(
+    _instance: &RenderInstance,
+    _adapter: &RenderAdapter,
+    _window: &ExtractedWindow,
+) -> Vec<wgpu::PresentMode> {
+    vec![wgpu::PresentMode::Fifo]
+}
+
+fn apply_vsync(In(value): In<Value>, mut windows: Query<&mut Window, With<PrimaryWindow>>) {
+    if let Ok(mut window) = windows.get_single_mut() {
+        set_vsync(&value, &mut window);
+    }
+}
+
+fn set_vsync(value: &Value, window: &mut Window) {
+    match parse_present_mode(value) {
+        Some(mode) if window.present_mode != mode => window.present_mode = mode,
+        Some(_) => {}
+        None => warn!("vid_vsync must be 0, 1, immediate, mailbox or fifo"),
+    }
+}
+
+pub fn register_cvars(app: &mut App) {
+    app.cvar_on_set(
+        "vid_fullscreen",
+        Cvar::new("0").archive(),
+        apply_video_mode,
+        "0: windowed, 1: exclusive fullscreen, 2: borderless fullscreen",
+    );
+    app.cvar_on_set(
+        "vid_mode",
+        Cvar::new("-1").archive(),
+        apply_video_mode,
+        "the resolution to use from vid_describemodes, or -1 to use vid_width and vid_height",
+    );
+    app.cvar_on_set(
+        "vid_width",
+        Cvar::new("0").archive(),
+        apply_video_mode,
+        "the width of the window or fullscreen video mode in pixels, or 0 to keep the current size",
+    );
+    app.cvar_on_set(
+        "vid_height",
+        Cvar::new("0").archive(),
+        apply_video_mode,
+        "the height of the window or fullscreen video mode in pixels, or 0 to keep the current size",
+    );
+    app.cvar_on_set(
+        "vid_vsync",
+        Cvar::new("1").archive(),
+        apply_vsync,
+        "1: wait for vertical sync, 0: present immediately, trading tearing for lower latency. \
+         immediate, mailbox or fifo select a specific present mode",
+    );
+}
+
+pub fn register_commands(app: &mut App) {
+    #[derive(Parser)]
+    #[command(
+        name = "vid_restart",
+        about = "Recreate the window and renderer with the current video settings"
+    )]
+    struct VidRestartCmd;
+
+    #[derive(Parser)]
+    #[command(
+        name = "vid_describemodes",
+        about = "List the resolutions vid_mode selects from"
+    )]
+    struct VidDescribeModes;
+
+    app.init_resource::<VidRestart>()
+        .command(
+            |In(VidRestartCmd),
+             registry: Res<Registry>,
+             mut windows: Query<&mut Window, With<PrimaryWindow>>,
+             mut restart: ResMut<VidRestart>|
+             -> ExecResult {
+                if let Ok(mut window) = windows.get_single_mut() {
+                    set_video_mode(&registry, &mut window);
+                    if let Some(vsync) = registry.get_cvar("vid_vsync") {
+                        set_vsync(vsync.value(), &mut window);
+                    }
+                }
+
+                restart.0 = restart.0.wrapping_add(1);
+                default()
+            },
+        )
+        .command(|In(VidDescribeModes)| -> ExecResult {
+            let mut out = String::new();
+            for (i, (width, height)) in VIDEO_MODES.iter().enumerate() {
+                out.push_str(&format!("{:2}: {}x{}\n", i, width, height));
+            }
+            out.into()
+        });
+}
+
+pub mod systems {
+    use super::*;
+
+    /// Replace a present mode that a window's surface doesn't support with `Fifo` before the
+    /// surface is configured, since wgpu panics if it's asked for one.
+    ///
+    /// The window still asks for the unsupported mode, so the extracted window is changed every
+    /// frame, and whether it changed is worked out from the mode that was actually used.
+    pub fn check_present_mode(
+        // surfaces have to be made on the main thread on some platforms
+        #[cfg(any(target_os = "macos", target_os = "ios"))] _marker: Option<
+            NonSend<bevy::core::NonSendMarker>,
+        >,
+        mut windows: ResMut<ExtractedWindows>,
+        instance: Res<RenderInstance>,
+        adapter: Res<RenderAdapter>,
+        mut supported: Local<HashMap<Entity, Vec<wgpu::PresentMode>>>,
+        mut used: Local<HashMap<Entity, (PresentMode, PresentMode)>>,
+    ) {
+        for window in windows.windows.values_mut() {
+            let requested = window.present_mode;
+            let mode = match requested {
+                // wgpu picks a supported mode for the automatic ones, and Fifo is always supported
+                PresentMode::AutoVsync | PresentMode::AutoNoVsync | PresentMode::Fifo => requested,
+                _ => {
+                    let modes = supported
+                        .entry(window.entity)
+                        .or_insert_with(|| surface_present_modes(&instance, &adapter, window));
+                    if modes.contains(&to_wgpu_present_mode(requested)) {
+                        requested
+                    } else {
+                        PresentMode::Fifo
+                    }
+                }
+            };
+
+            window.present_mode = mode;
+            let previous = used.insert(window.entity, (requested, mode));
+            // a new window's surface hasn't been configured yet, so there's nothing to change
+            if let Some((_, previous_mode)) = previous {
+                window.present_mode_changed = previous_mode != mode;
+            }
+
+            if mode != requested && previous.map(|(r, _)| r) != Some(requested) {
+                warn!(
+                    "The display doesn't support the {:?} present mode, using Fifo",
+                    requested
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_window_mode() {
+        assert_eq!(
+            parse_window_mode(&Value::from(0)),
+            Some(WindowMode::Windowed)
+        );
+        assert_eq!(
+            parse_window_mode(&Value::from(2)),
+            Some(WindowMode::BorderlessFullscreen)
+        );
+        assert_eq!(
+            parse_window_mode(&Value::symbol("Exclusive")),
+            Some(WindowMode::Fullscreen)
+        );
+        assert_eq!(parse_window_mode(&Value::from(3)), None);
+    }
+
+    #[test]
+    fn test_parse_present_mode() {
+        assert_eq!(
+            parse_present_mode(&Value::from(0)),
+            Some(PresentMode::AutoNoVsync)
+        );
+        assert_eq!(
+            parse_present_mode(&Value::symbol("mailbox")),
+            Some(PresentMode::Mailbox)
+        );
+        assert_eq!(parse_present_mode(&Value::symbol("tearing")), None);
+    }
+}
@@ -7,10 +7,53 @@ use crate::common::{
 };
 
 use super::IntermissionKind;
+use bevy::{ecs::system::Resource, render::extract_resource::ExtractResource};
 use cgmath::{Angle as _, Deg, InnerSpace as _, Vector3, Zero as _};
 use chrono::Duration;
 use serde::Deserialize;
 
+/// The horizontal field of view that the camera is currently rendered with.
+///
+/// This is derived from the `fov` cvar every frame, but can differ from it while the `+zoom`
+/// action is held or while zooming back out.
+#[derive(Resource, ExtractResource, Clone, Copy, Debug, PartialEq)]
+pub struct Fov(pub Deg<f32>);
+
+impl Default for Fov {
+    fn default() -> Self {
+        Fov(Deg(90.))
+    }
+}
+
+impl Fov {
+    /// The narrowest and widest angles the `fov` and `zoom_fov` cvars can ask for.
+    pub const MIN: f32 = 10.;
+    pub const MAX: f32 = 170.;
+
+    /// Move the field of view towards `target`, approaching it exponentially at `speed` per second.
+    pub fn approach(&mut self, target: Deg<f32>, speed: f32, frame_time: f32) {
+        if speed <= 0. {
+            self.0 = target;
+            return;
+        }
+
+        let t = 1. - (-speed * frame_time).exp();
+        self.0 += (target - self.0) * t;
+
+        // snap once we're close enough, so we don't chase the target forever
+        if (target - self.0).0.abs() < 0.01 {
+            self.0 = target;
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Deserialize)]
+pub struct ZoomVars {
+    pub fov: f32,
+    pub zoom_fov: f32,
+    pub zoom_speed: f32,
+}
+
 #[derive(Clone)]
 pub struct View {
     // entity "holding" the camera
@@ -39,6 +82,9 @@ pub struct View {
 
     // final origin accounting for view bob
     final_origin: Vector3<f32>,
+
+    // view bob, which also moves the weapon back and forth
+    bob: f32,
 }
 
 impl View {
@@ -53,6 +99,7 @@ impl View {
             punch_angles: Angles::zero(),
             final_angles: Angles::zero(),
             final_origin: Vector3::zero(),
+            bob: 0.0,
         }
     }
 
@@ -106,7 +153,9 @@ impl View {
         cl_anglespeedkey: f32,
         cl_pitchspeed: f32,
         cl_yawspeed: f32,
-        _mouse_vars: MouseVars,
+        mouse_vars: MouseVars,
+        mouse_delta: (f32, f32),
+        stick_look: (f32, f32),
     ) {
         let frame_time_f32 = duration_to_f32(frame_time);
         let speed = if game_input.is_pressed("speed") {
@@ -131,12 +180,21 @@ impl View {
         let lookdown_factor = game_input.is_pressed("lookup") as i32 as f32;
         self.input_angles.pitch += Deg(speed * cl_pitchspeed * (lookdown_factor - lookup_factor));
 
-        if mlook {
-            todo!("Reimplement mouse look");
-            // let pitch_factor = mouse_vars.pitch_factor * mouse_vars.sensitivity;
-            // let yaw_factor = mouse_vars.yaw_factor * mouse_vars.sensitivity;
-            // self.input_angles.pitch += Deg(game_input.mouse_delta().1 as f32 * pitch_factor);
-            // self.input_angles.yaw -= Deg(game_input.mouse_delta().0 as f32 * yaw_factor);
+        // the right stick turns at the same rate as the arrow keys, scaled by `joy_sensitivity`
+        self.input_angles.yaw -= Deg(speed * cl_yawspeed * stick_look.0);
+        self.input_angles.yaw = self.input_angles.yaw.normalize();
+        self.input_angles.pitch -= Deg(speed * cl_pitchspeed * stick_look.1);
+
+        // with +strafe held the mouse moves the player instead, see `ClientState::handle_input`
+        let (mouse_x, mouse_y) = mouse_vars.scale(mouse_delta);
+        if !game_input.is_pressed("strafe") {
+            self.input_angles.yaw -= Deg(mouse_vars.yaw_factor * mouse_x);
+            self.input_angles.yaw = self.input_angles.yaw.normalize();
+
+            // a negative `m_pitch` inverts the mouse
+            if mlook {
+                self.input_angles.pitch += Deg(mouse_vars.pitch_factor * mouse_y);
+            }
         }
 
         if lookup_factor != 0.0 || lookdown_factor != 0.0 {
@@ -214,7 +272,8 @@ impl View {
         // offset the view by 1/32 unit to keep it from intersecting liquid planes
         let plane_offset = Vector3::new(1.0 / 32.0, 1.0 / 32.0, 1.0 / 32.0);
         let height_offset = Vector3::new(0.0, 0.0, self.view_height);
-        let bob_offset = Vector3::new(0.0, 0.0, bob(time, velocity, bob_vars));
+        self.bob = bob(time, velocity, bob_vars);
+        let bob_offset = Vector3::new(0.0, 0.0, self.bob);
         self.final_origin = origin + plane_offset + height_offset + bob_offset;
     }
 
@@ -222,6 +281,15 @@ impl View {
         self.final_origin
     }
 
+    /// The origin of the weapon model, which bobs forward and back as well as up and down.
+    pub fn viewmodel_origin(&self) -> Vector3<f32> {
+        let (sin_pitch, cos_pitch) = self.final_angles.pitch.sin_cos();
+        let (sin_yaw, cos_yaw) = self.final_angles.yaw.sin_cos();
+        let forward = Vector3::new(cos_pitch * cos_yaw, cos_pitch * sin_yaw, -sin_pitch);
+
+        self.final_origin + forward * self.bob * 0.4
+    }
+
     pub fn viewmodel_angle(&self) -> Angles {
         // TODO
         self.final_angles()
@@ -234,8 +302,23 @@ pub struct MouseVars {
     pub pitch_factor: f32,
     #[serde(rename(deserialize = "m_yaw"))]
     pub yaw_factor: f32,
+    #[serde(rename(deserialize = "m_forward"))]
+    pub forward_factor: f32,
+    #[serde(rename(deserialize = "m_side"))]
+    pub side_factor: f32,
     #[serde(rename(deserialize = "sensitivity"))]
     pub sensitivity: f32,
+    #[serde(rename(deserialize = "m_filter"))]
+    pub filter: f32,
+    #[serde(rename(deserialize = "freelook"))]
+    pub freelook: f32,
+}
+
+impl MouseVars {
+    /// Scale a mouse movement in pixels by `sensitivity`.
+    pub fn scale(&self, (x, y): (f32, f32)) -> (f32, f32) {
+        (x * self.sensitivity, y * self.sensitivity)
+    }
 }
 
 #[derive(Clone, Copy, Debug, Deserialize)]
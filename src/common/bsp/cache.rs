@@ -0,0 +1,139 @@
+//! Parsed levels, shared by the server and client and optionally loaded ahead of time.
+
+use std::sync::Arc;
+
+use bevy::{
+    prelude::*,
+    tasks::{block_on, AsyncComputeTaskPool, Task},
+};
+use hashbrown::HashMap;
+use parking_lot::Mutex;
+
+use crate::common::{model::Model, parse, vfs::Vfs};
+
+/// The models and entity string of a BSP file, as returned by `bsp::load`.
+pub type Level = (Vec<Model>, String);
+
+/// Load the level `name`, with the colored lightmaps of the `.lit` file next to it if there is one.
+fn load_level(vfs: &Vfs, name: &str) -> Result<Level, failure::Error> {
+    let data = vfs.open(name)?;
+    let lit = name
+        .strip_suffix(".bsp")
+        .and_then(|base| vfs.open(format!("{}.lit", base)).ok());
+    super::load_with_lit(data, lit)
+}
+
+enum CachedLevel {
+    Loading(Task<Option<Level>>),
+    Ready(Level),
+}
+
+/// Levels which have been loaded, or are being loaded in the background.
+///
+/// Only the current level and any levels prefetched since it was loaded are kept.
+#[derive(Resource, Clone, Default)]
+pub struct LevelCache {
+    inner: Arc<Mutex<LevelCacheInner>>,
+}
+
+#[derive(Default)]
+struct LevelCacheInner {
+    levels: HashMap<String, CachedLevel>,
+    // the last level returned by `load`
+    current: Option<String>,
+}
+
+impl LevelCache {
+    /// Start loading `name` in the background, unless it's already loaded or loading.
+    pub fn prefetch(&self, vfs: &Vfs, name: &str) {
+        let mut inner = self.inner.lock();
+        if inner.levels.contains_key(name) {
+            return;
+        }
+
+        debug!("Prefetching {}", name);
+
+        let vfs = vfs.clone();
+        let path = name.to_owned();
+        let task = AsyncComputeTaskPool::get().spawn(async move {
+            profile_span!("level_prefetch");
+            let level = load_level(&vfs, &path);
+
+            match level {
+                Ok(level) => Some(level),
+                Err(e) => {
+                    warn!("Couldn't prefetch {}: {}", path, e);
+                    None
+                }
+            }
+        });
+
+        inner
+            .levels
+            .insert(name.to_owned(), CachedLevel::Loading(task));
+    }
+
+    /// Prefetch every level that the `trigger_changelevel`s in the current level lead to.
+    ///
+    /// Does nothing if no level has been loaded.
+    pub fn prefetch_next_levels(&self, vfs: &Vfs) {
+        let targets: Vec<String> = {
+            let inner = self.inner.lock();
+            let Some(CachedLevel::Ready((_, entmap))) = inner
+                .current
+                .as_ref()
+                .and_then(|current| inner.levels.get(current))
+            else {
+                return;
+            };
+
+            let Ok(entities) = parse::entities(entmap) else {
+                return;
+            };
+
+            entities
+                .iter()
+                .filter(|ent| ent.get("classname") == Some(&"trigger_changelevel"))
+                .filter_map(|ent| ent.get("map"))
+                .map(|map| format!("maps/{}.bsp", map))
+                .collect()
+        };
+
+        for target in targets {
+            self.prefetch(vfs, &target);
+        }
+    }
+
+    /// Load `name`, using the prefetched copy if there is one and waiting for it if it's still
+    /// loading.
+    ///
+    /// Every other level is dropped from the cache.
+    pub fn load(&self, vfs: &Vfs, name: &str) -> Result<Level, failure::Error> {
+        let cached = {
+            let mut inner = self.inner.lock();
+            let cached = inner.levels.remove(name);
+            inner.levels.clear();
+            inner.current = None;
+            cached
+        };
+
+        let level = match cached {
+            Some(CachedLevel::Ready(level)) => Some(level),
+            Some(CachedLevel::Loading(task)) => block_on(task),
+            None => None,
+        };
+
+        let level = match level {
+            Some(level) => level,
+            None => load_level(vfs, name)?,
+        };
+
+        let mut inner = self.inner.lock();
+        inner
+            .levels
+            .insert(name.to_owned(), CachedLevel::Ready(level.clone()));
+        inner.current = Some(name.to_owned());
+
+        Ok(level)
+    }
+}
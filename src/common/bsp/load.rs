@@ -44,6 +44,71 @@ use num_derive::FromPrimitive;
 use thiserror::Error;
 
 const VERSION: i32 = 29;
+const VERSION_BSP2: i32 = i32::from_le_bytes(*b"BSP2");
+const VERSION_2PSB: i32 = i32::from_le_bytes(*b"2PSB");
+
+const LIT_MAGIC: [u8; 4] = *b"QLIT";
+const LIT_VERSION: i32 = 1;
+
+/// The variants of the BSP file format.
+///
+/// BSP2 and its predecessor 2PSB widen the 16-bit indices of the original format to 32 bits, so
+/// maps can have more than 32767 leaves, nodes and faces. BSP2 also stores node and leaf bounds as
+/// floats.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum BspFormat {
+    Bsp29,
+    Bsp2Psb,
+    Bsp2,
+}
+
+impl BspFormat {
+    fn from_version(version: i32) -> Option<BspFormat> {
+        match version {
+            VERSION => Some(BspFormat::Bsp29),
+            VERSION_2PSB => Some(BspFormat::Bsp2Psb),
+            VERSION_BSP2 => Some(BspFormat::Bsp2),
+            _ => None,
+        }
+    }
+
+    fn has_wide_indices(self) -> bool {
+        self != BspFormat::Bsp29
+    }
+
+    // read a signed index, which is 16 bits in the original format
+    fn read_index<R>(self, reader: &mut R) -> Result<i32, std::io::Error>
+    where
+        R: ReadBytesExt,
+    {
+        match self {
+            BspFormat::Bsp29 => reader.read_i16::<LittleEndian>().map(i32::from),
+            _ => reader.read_i32::<LittleEndian>(),
+        }
+    }
+
+    // read an unsigned index, which is 16 bits in the original format
+    fn read_unsigned<R>(self, reader: &mut R) -> Result<usize, std::io::Error>
+    where
+        R: ReadBytesExt,
+    {
+        match self {
+            BspFormat::Bsp29 => reader.read_u16::<LittleEndian>().map(usize::from),
+            _ => reader.read_u32::<LittleEndian>().map(|x| x as usize),
+        }
+    }
+
+    // read the corner of a node or leaf bounding box
+    fn read_bound<R>(self, reader: &mut R) -> Result<[f32; 3], std::io::Error>
+    where
+        R: ReadBytesExt,
+    {
+        match self {
+            BspFormat::Bsp2 => read_f32_3(reader),
+            _ => Ok(read_i16_3(reader)?.map(f32::from)),
+        }
+    }
+}
 
 pub const MAX_MODELS: usize = 256;
 const MAX_LEAVES: usize = 32767;
@@ -70,24 +135,31 @@ const NUM_AMBIENTS: usize = 4;
 pub enum BspFileError {
     #[error("I/O error")]
     Io(#[from] std::io::Error),
-    #[error("unsupported BSP format version (expected {}, found {0})", VERSION)]
+    #[error(
+        "unsupported BSP format version (expected {}, BSP2 or 2PSB, found {0})",
+        VERSION
+    )]
     UnsupportedVersion(i32),
     #[error("negative BSP file section offset: {0}")]
     NegativeSectionOffset(i32),
     #[error("negative BSP file section size: {0}")]
     NegativeSectionSize(i32),
     #[error(
-        "invalid BSP file section size: section {section:?} size is {size}, must be multiple of {}",
-        section.element_size(),
+        "invalid BSP file section size: section {section:?} size is {size}, must be multiple of {element_size}"
     )]
     InvalidSectionSize {
         section: BspFileSectionId,
         size: usize,
+        element_size: usize,
     },
     #[error("invalid BSP texture frame specifier: {0}")]
     InvalidTextureFrameSpecifier(String),
     #[error("texture has primary animation with 0 frames: {0}")]
     EmptyPrimaryAnimation(String),
+    #[error("invalid .lit file header")]
+    InvalidLitHeader,
+    #[error(".lit file has {found} bytes of colored lightmaps, expected {expected}")]
+    LitSizeMismatch { expected: usize, found: usize },
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -137,46 +209,64 @@ pub enum BspFileSectionId {
 
 const PLANE_SIZE: usize = 20;
 const RENDER_NODE_SIZE: usize = 24;
+const RENDER_NODE_SIZE_2PSB: usize = 32;
+const RENDER_NODE_SIZE_BSP2: usize = 44;
 const LEAF_SIZE: usize = 28;
+const LEAF_SIZE_2PSB: usize = 32;
+const LEAF_SIZE_BSP2: usize = 44;
 const TEXTURE_INFO_SIZE: usize = 40;
 const FACE_SIZE: usize = 20;
+const FACE_SIZE_BSP2: usize = 28;
 const COLLISION_NODE_SIZE: usize = 8;
+const COLLISION_NODE_SIZE_BSP2: usize = 12;
 const FACELIST_SIZE: usize = 2;
+const FACELIST_SIZE_BSP2: usize = 4;
 const EDGE_SIZE: usize = 4;
+const EDGE_SIZE_BSP2: usize = 8;
 const EDGELIST_SIZE: usize = 4;
 const MODEL_SIZE: usize = 64;
 const VERTEX_SIZE: usize = 12;
 
 impl BspFileSectionId {
     // the size on disk of one element of a BSP file section.
-    fn element_size(&self) -> usize {
+    fn element_size(&self, format: BspFormat) -> usize {
         use BspFileSectionId::*;
-        match self {
-            Entities => size_of::<u8>(),
-            Planes => PLANE_SIZE,
-            Textures => size_of::<u8>(),
-            Vertices => VERTEX_SIZE,
-            Visibility => size_of::<u8>(),
-            RenderNodes => RENDER_NODE_SIZE,
-            TextureInfo => TEXTURE_INFO_SIZE,
-            Faces => FACE_SIZE,
-            Lightmaps => size_of::<u8>(),
-            CollisionNodes => COLLISION_NODE_SIZE,
-            Leaves => LEAF_SIZE,
-            FaceList => FACELIST_SIZE,
-            Edges => EDGE_SIZE,
-            EdgeList => EDGELIST_SIZE,
-            Models => MODEL_SIZE,
+        use BspFormat::*;
+        match (self, format) {
+            (Entities, _) => size_of::<u8>(),
+            (Planes, _) => PLANE_SIZE,
+            (Textures, _) => size_of::<u8>(),
+            (Vertices, _) => VERTEX_SIZE,
+            (Visibility, _) => size_of::<u8>(),
+            (RenderNodes, Bsp29) => RENDER_NODE_SIZE,
+            (RenderNodes, Bsp2Psb) => RENDER_NODE_SIZE_2PSB,
+            (RenderNodes, Bsp2) => RENDER_NODE_SIZE_BSP2,
+            (TextureInfo, _) => TEXTURE_INFO_SIZE,
+            (Faces, Bsp29) => FACE_SIZE,
+            (Faces, _) => FACE_SIZE_BSP2,
+            (Lightmaps, _) => size_of::<u8>(),
+            (CollisionNodes, Bsp29) => COLLISION_NODE_SIZE,
+            (CollisionNodes, _) => COLLISION_NODE_SIZE_BSP2,
+            (Leaves, Bsp29) => LEAF_SIZE,
+            (Leaves, Bsp2Psb) => LEAF_SIZE_2PSB,
+            (Leaves, Bsp2) => LEAF_SIZE_BSP2,
+            (FaceList, Bsp29) => FACELIST_SIZE,
+            (FaceList, _) => FACELIST_SIZE_BSP2,
+            (Edges, Bsp29) => EDGE_SIZE,
+            (Edges, _) => EDGE_SIZE_BSP2,
+            (EdgeList, _) => EDGELIST_SIZE,
+            (Models, _) => MODEL_SIZE,
         }
     }
 }
 
 struct BspFileTable {
+    format: BspFormat,
     sections: [BspFileSection; SECTION_COUNT],
 }
 
 impl BspFileTable {
-    fn read_from<R>(reader: &mut R) -> Result<BspFileTable, BspFileError>
+    fn read_from<R>(reader: &mut R, format: BspFormat) -> Result<BspFileTable, BspFileError>
     where
         R: ReadBytesExt,
     {
@@ -185,21 +275,29 @@ impl BspFileTable {
         for (id, section) in sections.iter_mut().enumerate() {
             *section = BspFileSection::read_from(reader)?;
             let section_id = BspFileSectionId::from_usize(id).unwrap();
-            if section.size % section_id.element_size() != 0 {
+            let element_size = section_id.element_size(format);
+            if section.size % element_size != 0 {
                 Err(BspFileError::InvalidSectionSize {
                     section: section_id,
                     size: section.size,
+                    element_size,
                 })?
             }
         }
 
-        Ok(BspFileTable { sections })
+        Ok(BspFileTable { format, sections })
     }
 
     fn section(&self, section_id: BspFileSectionId) -> BspFileSection {
         self.sections[section_id as usize]
     }
 
+    // the number of elements in a section
+    fn count(&self, section_id: BspFileSectionId) -> usize {
+        let element_size = section_id.element_size(self.format);
+        self.section(section_id).size / element_size
+    }
+
     fn check_end_position<S>(
         &self,
         seeker: &mut S,
@@ -295,7 +393,7 @@ where
     })
 }
 
-fn load_render_node<R>(reader: &mut R) -> Result<BspRenderNode, failure::Error>
+fn load_render_node<R>(reader: &mut R, format: BspFormat) -> Result<BspRenderNode, failure::Error>
 where
     R: ReadBytesExt,
 {
@@ -307,26 +405,29 @@ where
     // If the child ID is positive, it points to another internal node. If it is negative, its
     // bitwise negation points to a leaf node.
 
-    let front = match reader.read_i16::<LittleEndian>()? {
+    let front = match format.read_index(reader)? {
         f if f < 0 => BspRenderNodeChild::Leaf((!f) as usize),
         f => BspRenderNodeChild::Node(f as usize),
     };
 
-    let back = match reader.read_i16::<LittleEndian>()? {
+    let back = match format.read_index(reader)? {
         b if b < 0 => BspRenderNodeChild::Leaf((!b) as usize),
         b => BspRenderNodeChild::Node(b as usize),
     };
 
-    let min = read_i16_3(reader)?;
-    let max = read_i16_3(reader)?;
+    let min = format.read_bound(reader)?;
+    let max = format.read_bound(reader)?;
 
-    let face_id = reader.read_i16::<LittleEndian>()?;
-    if face_id < 0 {
-        bail!("Invalid face id");
-    }
+    let face_id = match format {
+        BspFormat::Bsp29 => match reader.read_i16::<LittleEndian>()? {
+            f if f < 0 => bail!("Invalid face id"),
+            f => f as usize,
+        },
+        _ => format.read_unsigned(reader)?,
+    };
 
-    let face_count = reader.read_u16::<LittleEndian>()?;
-    if face_count as usize > MAX_FACES {
+    let face_count = format.read_unsigned(reader)?;
+    if !format.has_wide_indices() && face_count > MAX_FACES {
         bail!("Invalid face count");
     }
 
@@ -335,8 +436,8 @@ where
         children: [front, back],
         min,
         max,
-        face_id: face_id as usize,
-        face_count: face_count as usize,
+        face_id,
+        face_count,
     })
 }
 
@@ -377,14 +478,30 @@ pub fn load<R>(data: R) -> Result<(Vec<Model>, String), failure::Error>
 where
     R: Read + Seek,
 {
+    load_with_lit(data, None::<std::io::Empty>)
+}
+
+/// Load a BSP file like `load`, taking its colored lightmaps from `lit`, the contents of the
+/// `.lit` file next to it.
+///
+/// A `.lit` file that can't be read or doesn't match the BSP is ignored, so the map is lit in
+/// white as if it didn't have one.
+pub fn load_with_lit<R, L>(data: R, lit: Option<L>) -> Result<(Vec<Model>, String), failure::Error>
+where
+    R: Read + Seek,
+    L: Read,
+{
+    profile_span!("bsp_load");
     let mut reader = BufReader::new(data);
 
-    let _version = match reader.read_i32::<LittleEndian>()? {
-        VERSION => Ok(VERSION),
-        other => Err(BspFileError::UnsupportedVersion(other)),
-    }?;
+    let version = reader.read_i32::<LittleEndian>()?;
+    let format =
+        BspFormat::from_version(version).ok_or(BspFileError::UnsupportedVersion(version))?;
+    if format != BspFormat::Bsp29 {
+        debug!("Loading {:?} map", format);
+    }
 
-    let table = BspFileTable::read_from(&mut reader)?;
+    let table = BspFileTable::read_from(&mut reader, format)?;
 
     let ent_section = table.section(BspFileSectionId::Entities);
     let plane_section = table.section(BspFileSectionId::Planes);
@@ -402,42 +519,45 @@ where
     let model_section = table.section(BspFileSectionId::Models);
     let render_node_section = table.section(BspFileSectionId::RenderNodes);
 
-    let plane_count = plane_section.size / PLANE_SIZE;
-    let vert_count = vert_section.size / VERTEX_SIZE;
-    let render_node_count = render_node_section.size / RENDER_NODE_SIZE;
-    let texinfo_count = texinfo_section.size / TEXTURE_INFO_SIZE;
-    let face_count = face_section.size / FACE_SIZE;
-    let collision_node_count = collision_node_section.size / COLLISION_NODE_SIZE;
-    let leaf_count = leaf_section.size / LEAF_SIZE;
-    let facelist_count = facelist_section.size / FACELIST_SIZE;
-    let edge_count = edge_section.size / EDGE_SIZE;
-    let edgelist_count = edgelist_section.size / EDGELIST_SIZE;
-    let model_count = model_section.size / MODEL_SIZE;
-
-    // check limits
-    ensure!(plane_count <= MAX_PLANES, "Plane count exceeds MAX_PLANES");
-    ensure!(
-        vert_count <= MAX_VERTICES,
-        "Vertex count exceeds MAX_VERTICES"
-    );
-    ensure!(
-        vis_section.size <= MAX_VISLIST,
-        "Visibility data size exceeds MAX_VISLIST"
-    );
-    ensure!(
-        render_node_count <= MAX_RENDER_NODES,
-        "Render node count exceeds MAX_RENDER_NODES"
-    );
-    ensure!(
-        collision_node_count <= MAX_COLLISION_NODES,
-        "Collision node count exceeds MAX_COLLISION_NODES"
-    );
-    ensure!(leaf_count <= MAX_LEAVES, "Leaf count exceeds MAX_LEAVES");
-    ensure!(edge_count <= MAX_EDGES, "Edge count exceeds MAX_EDGES");
-    ensure!(
-        edgelist_count <= MAX_EDGELIST,
-        "Edge list count exceeds MAX_EDGELIST"
-    );
+    let plane_count = table.count(BspFileSectionId::Planes);
+    let vert_count = table.count(BspFileSectionId::Vertices);
+    let render_node_count = table.count(BspFileSectionId::RenderNodes);
+    let texinfo_count = table.count(BspFileSectionId::TextureInfo);
+    let face_count = table.count(BspFileSectionId::Faces);
+    let collision_node_count = table.count(BspFileSectionId::CollisionNodes);
+    let leaf_count = table.count(BspFileSectionId::Leaves);
+    let facelist_count = table.count(BspFileSectionId::FaceList);
+    let edge_count = table.count(BspFileSectionId::Edges);
+    let edgelist_count = table.count(BspFileSectionId::EdgeList);
+    let model_count = table.count(BspFileSectionId::Models);
+
+    // check limits. these come from the 16-bit indices of the original format, so they don't
+    // apply to BSP2 maps
+    if !format.has_wide_indices() {
+        ensure!(plane_count <= MAX_PLANES, "Plane count exceeds MAX_PLANES");
+        ensure!(
+            vert_count <= MAX_VERTICES,
+            "Vertex count exceeds MAX_VERTICES"
+        );
+        ensure!(
+            vis_section.size <= MAX_VISLIST,
+            "Visibility data size exceeds MAX_VISLIST"
+        );
+        ensure!(
+            render_node_count <= MAX_RENDER_NODES,
+            "Render node count exceeds MAX_RENDER_NODES"
+        );
+        ensure!(
+            collision_node_count <= MAX_COLLISION_NODES,
+            "Collision node count exceeds MAX_COLLISION_NODES"
+        );
+        ensure!(leaf_count <= MAX_LEAVES, "Leaf count exceeds MAX_LEAVES");
+        ensure!(edge_count <= MAX_EDGES, "Edge count exceeds MAX_EDGES");
+        ensure!(
+            edgelist_count <= MAX_EDGELIST,
+            "Edge list count exceeds MAX_EDGELIST"
+        );
+    }
     ensure!(
         model_count > 0,
         "No brush models (need at least 1 for worldmodel)"
@@ -577,10 +697,10 @@ where
                 static_texture_ids.insert(file_texture_id, texture_id);
 
                 textures.push(BspTexture {
-                    name,
+                    name: name.clone(),
                     width,
                     height,
-                    kind: BspTextureKind::Static(BspTextureFrame { mipmaps }),
+                    kind: BspTextureKind::Static(BspTextureFrame { name, mipmaps }),
                 });
             }
         };
@@ -617,6 +737,7 @@ where
             );
             corresponding_file_ids.push(file_id);
             primary.push(BspTextureFrame {
+                name: file_texture.name,
                 mipmaps: file_texture.mipmaps,
             });
         }
@@ -630,6 +751,7 @@ where
                 for (file_id, file_texture) in alt {
                     alt_corresp_file_ids.push(file_id);
                     alternate.push(BspTextureFrame {
+                        name: file_texture.name,
                         mipmaps: file_texture.mipmaps,
                     });
                 }
@@ -695,7 +817,7 @@ where
     debug!("Render node count = {}", render_node_count);
     let mut render_nodes = Vec::with_capacity(render_node_count);
     for _ in 0..render_node_count {
-        render_nodes.push(load_render_node(&mut reader)?);
+        render_nodes.push(load_render_node(&mut reader, format)?);
     }
     table.check_end_position(&mut reader, BspFileSectionId::RenderNodes)?;
 
@@ -714,12 +836,12 @@ where
     reader.seek(SeekFrom::Start(face_section.offset))?;
     let mut faces = Vec::with_capacity(face_count);
     for _ in 0..face_count {
-        let plane_id = reader.read_i16::<LittleEndian>()?;
+        let plane_id = format.read_index(&mut reader)?;
         if plane_id < 0 || plane_id as usize > plane_count {
             bail!("Invalid plane count");
         }
 
-        let side = match reader.read_i16::<LittleEndian>()? {
+        let side = match format.read_index(&mut reader)? {
             0 => BspFaceSide::Front,
             1 => BspFaceSide::Back,
             _ => bail!("Invalid face side"),
@@ -730,12 +852,12 @@ where
             bail!("Invalid edge ID");
         }
 
-        let edge_count = reader.read_i16::<LittleEndian>()?;
+        let edge_count = format.read_index(&mut reader)?;
         if edge_count < 3 {
             bail!("Invalid edge count");
         }
 
-        let texinfo_id = reader.read_i16::<LittleEndian>()?;
+        let texinfo_id = format.read_index(&mut reader)?;
         if texinfo_id < 0 || texinfo_id as usize > texinfo_count {
             bail!("Invalid texinfo ID");
         }
@@ -772,6 +894,15 @@ where
         .read_to_end(&mut lightmaps)?;
     table.check_end_position(&mut reader, BspFileSectionId::Lightmaps)?;
 
+    let colored_lightmaps = match lit.map(|lit| load_lit(lit, lightmaps.len())) {
+        Some(Ok(rgb)) => Some(rgb.into_boxed_slice()),
+        Some(Err(e)) => {
+            warn!("Ignoring .lit file: {}", e);
+            None
+        }
+        None => None,
+    };
+
     reader.seek(SeekFrom::Start(collision_node_section.offset))?;
 
     let mut collision_nodes = Vec::with_capacity(collision_node_count);
@@ -781,16 +912,16 @@ where
             x => x as usize,
         };
 
-        let front = match reader.read_i16::<LittleEndian>()? {
-            x if x < 0 => match BspLeafContents::from_i16(-x) {
+        let front = match format.read_index(&mut reader)? {
+            x if x < 0 => match BspLeafContents::from_i32(-x) {
                 Some(c) => BspCollisionNodeChild::Contents(c),
                 None => bail!("Invalid leaf contents ({})", -x),
             },
             x => BspCollisionNodeChild::Node(x as usize),
         };
 
-        let back = match reader.read_i16::<LittleEndian>()? {
-            x if x < 0 => match BspLeafContents::from_i16(-x) {
+        let back = match format.read_index(&mut reader)? {
+            x if x < 0 => match BspLeafContents::from_i32(-x) {
                 Some(c) => BspCollisionNodeChild::Contents(c),
                 None => bail!("Invalid leaf contents ({})", -x),
             },
@@ -858,11 +989,11 @@ where
             x => Some(x as usize),
         };
 
-        let min = read_i16_3(&mut reader)?;
-        let max = read_i16_3(&mut reader)?;
+        let min = format.read_bound(&mut reader)?;
+        let max = format.read_bound(&mut reader)?;
 
-        let facelist_id = reader.read_u16::<LittleEndian>()? as usize;
-        let facelist_count = reader.read_u16::<LittleEndian>()? as usize;
+        let facelist_id = format.read_unsigned(&mut reader)?;
+        let facelist_count = format.read_unsigned(&mut reader)?;
         let mut sounds = [0u8; NUM_AMBIENTS];
         reader.read(&mut sounds)?;
         leaves.push(BspLeaf {
@@ -880,7 +1011,7 @@ where
     reader.seek(SeekFrom::Start(facelist_section.offset))?;
     let mut facelist = Vec::with_capacity(facelist_count);
     for _ in 0..facelist_count {
-        facelist.push(reader.read_u16::<LittleEndian>()? as usize);
+        facelist.push(format.read_unsigned(&mut reader)?);
     }
     if reader.seek(SeekFrom::Current(0))?
         != reader.seek(SeekFrom::Start(
@@ -895,8 +1026,8 @@ where
     for _ in 0..edge_count {
         edges.push(BspEdge {
             vertex_ids: [
-                reader.read_u16::<LittleEndian>()?,
-                reader.read_u16::<LittleEndian>()?,
+                format.read_unsigned(&mut reader)?,
+                format.read_unsigned(&mut reader)?,
             ],
         });
     }
@@ -939,7 +1070,7 @@ where
         let mut t_max = ::std::f32::NEG_INFINITY;
 
         for edge_idx in &edgelist[face.edge_id..face.edge_id + face.edge_count] {
-            let vertex_id = edges[edge_idx.index].vertex_ids[edge_idx.direction as usize] as usize;
+            let vertex_id = edges[edge_idx.index].vertex_ids[edge_idx.direction as usize];
             let vertex = vertices[vertex_id];
             let s = texinfo.s_vector.dot(vertex) + texinfo.s_offset;
             let t = texinfo.t_vector.dot(vertex) + texinfo.t_offset;
@@ -1011,6 +1142,7 @@ where
         texinfo: texinfo.into_boxed_slice(),
         faces: faces.into_boxed_slice(),
         lightmaps: lightmaps.into_boxed_slice(),
+        colored_lightmaps,
         hulls: [hull_0, hull_1, hull_2],
         leaves: leaves.into_boxed_slice(),
         facelist: facelist.into_boxed_slice(),
@@ -1098,6 +1230,31 @@ where
     Ok((models, ent_string))
 }
 
+/// Read the colored lightmaps of a `.lit` file, which are an RGB sample for each of the
+/// `lightmap_size` samples of the BSP's lightmaps, in the same order.
+fn load_lit<R>(mut reader: R, lightmap_size: usize) -> Result<Vec<u8>, BspFileError>
+where
+    R: Read,
+{
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+    let version = reader.read_i32::<LittleEndian>()?;
+    if magic != LIT_MAGIC || version != LIT_VERSION {
+        return Err(BspFileError::InvalidLitHeader);
+    }
+
+    let mut rgb = Vec::with_capacity(3 * lightmap_size);
+    reader.read_to_end(&mut rgb)?;
+    if rgb.len() != 3 * lightmap_size {
+        return Err(BspFileError::LitSizeMismatch {
+            expected: 3 * lightmap_size,
+            found: rgb.len(),
+        });
+    }
+
+    Ok(rgb)
+}
+
 fn read_i16_3<R>(reader: &mut R) -> Result<[i16; 3], std::io::Error>
 where
     R: ReadBytesExt,
@@ -1106,3 +1263,88 @@ where
     reader.read_i16_into::<LittleEndian>(&mut ar)?;
     Ok(ar)
 }
+
+#[cfg(test)]
+mod test {
+    use byteorder::WriteBytesExt as _;
+
+    use super::*;
+
+    #[test]
+    fn test_load_render_node() {
+        assert_eq!(BspFormat::from_version(29), Some(BspFormat::Bsp29));
+        assert_eq!(
+            BspFormat::from_version(i32::from_le_bytes(*b"BSP2")),
+            Some(BspFormat::Bsp2)
+        );
+        assert_eq!(BspFormat::from_version(30), None);
+
+        for format in [BspFormat::Bsp29, BspFormat::Bsp2Psb, BspFormat::Bsp2] {
+            let mut data = Vec::new();
+            data.write_i32::<LittleEndian>(3).unwrap();
+            for child in [40000, -2] {
+                match format {
+                    BspFormat::Bsp29 => data.write_i16::<LittleEndian>(child as i16).unwrap(),
+                    _ => data.write_i32::<LittleEndian>(child).unwrap(),
+                }
+            }
+            for bound in [-8.0f32, -8.0, -8.0, 64.0, 64.0, 64.0] {
+                match format {
+                    BspFormat::Bsp2 => data.write_f32::<LittleEndian>(bound).unwrap(),
+                    _ => data.write_i16::<LittleEndian>(bound as i16).unwrap(),
+                }
+            }
+            for index in [100, 70000] {
+                match format {
+                    BspFormat::Bsp29 => data.write_u16::<LittleEndian>(index as u16).unwrap(),
+                    _ => data.write_u32::<LittleEndian>(index).unwrap(),
+                }
+            }
+            assert_eq!(
+                data.len(),
+                BspFileSectionId::RenderNodes.element_size(format)
+            );
+
+            let node = load_render_node(&mut data.as_slice(), format).unwrap();
+            assert_eq!(node.plane_id, 3);
+            assert!(matches!(node.children[1], BspRenderNodeChild::Leaf(1)));
+            assert_eq!(node.min, [-8.0; 3]);
+            assert_eq!(node.max, [64.0; 3]);
+            assert_eq!(node.face_id, 100);
+
+            // only the wide formats can refer to the 40000th node or the 70000th face
+            match format {
+                BspFormat::Bsp29 => assert_eq!(node.face_count, 70000 % 65536),
+                _ => {
+                    assert!(matches!(node.children[0], BspRenderNodeChild::Node(40000)));
+                    assert_eq!(node.face_count, 70000);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_load_lit() {
+        let mut data = b"QLIT".to_vec();
+        data.write_i32::<LittleEndian>(1).unwrap();
+        data.extend_from_slice(&[255, 0, 0, 0, 255, 0]);
+
+        assert_eq!(
+            load_lit(data.as_slice(), 2).unwrap(),
+            vec![255, 0, 0, 0, 255, 0]
+        );
+        assert!(matches!(
+            load_lit(data.as_slice(), 3),
+            Err(BspFileError::LitSizeMismatch {
+                expected: 9,
+                found: 6
+            })
+        ));
+
+        data[4] = 2;
+        assert!(matches!(
+            load_lit(data.as_slice(), 2),
+            Err(BspFileError::InvalidLitHeader)
+        ));
+    }
+}
@@ -116,6 +116,7 @@
 //!
 //! The edges are stored as a pair of 16-bit integer vertex IDs.
 
+mod cache;
 mod load;
 
 use std::{collections::HashSet, error::Error, fmt, sync::Arc};
@@ -130,7 +131,10 @@ use cgmath::Vector3;
 use chrono::Duration;
 use num_derive::FromPrimitive;
 
-pub use self::load::{load, BspFileError};
+pub use self::{
+    cache::{Level, LevelCache},
+    load::{load, load_with_lit, BspFileError},
+};
 
 // this is 4 in the original source, but the 4th hull is never used.
 const MAX_HULLS: usize = 3;
@@ -194,10 +198,16 @@ pub enum BspTextureMipmap {
 
 #[derive(Debug)]
 pub struct BspTextureFrame {
+    name: String,
     mipmaps: [Vec<u8>; MIPLEVELS],
 }
 
 impl BspTextureFrame {
+    /// Returns the name of the frame, which includes the frame specifier for animated textures.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
     pub fn mipmap(&self, level: BspTextureMipmap) -> &[u8] {
         &self.mipmaps[level as usize]
     }
@@ -255,8 +265,8 @@ pub enum BspRenderNodeChild {
 pub struct BspRenderNode {
     pub plane_id: usize,
     pub children: [BspRenderNodeChild; 2],
-    pub min: [i16; 3],
-    pub max: [i16; 3],
+    pub min: [f32; 3],
+    pub max: [f32; 3],
     pub face_id: usize,
     pub face_count: usize,
 }
@@ -653,8 +663,8 @@ impl BspCollisionHull {
 pub struct BspLeaf {
     pub contents: BspLeafContents,
     pub vis_offset: Option<usize>,
-    pub min: [i16; 3],
-    pub max: [i16; 3],
+    pub min: [f32; 3],
+    pub max: [f32; 3],
     pub facelist_id: usize,
     pub facelist_count: usize,
     pub sounds: [u8; MAX_SOUNDS],
@@ -662,7 +672,7 @@ pub struct BspLeaf {
 
 #[derive(Debug)]
 pub struct BspEdge {
-    pub vertex_ids: [u16; 2],
+    pub vertex_ids: [usize; 2],
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -682,6 +692,7 @@ pub struct BspLightmap<'a> {
     width: u32,
     height: u32,
     data: &'a [u8],
+    rgb: Option<&'a [u8]>,
 }
 
 impl<'a> BspLightmap<'a> {
@@ -696,6 +707,12 @@ impl<'a> BspLightmap<'a> {
     pub fn data(&self) -> &[u8] {
         self.data
     }
+
+    /// The lightmap's colored samples from the map's `.lit` file, as RGB triples, or `None` if the
+    /// map doesn't have one.
+    pub fn rgb(&self) -> Option<&[u8]> {
+        self.rgb
+    }
 }
 
 #[derive(Debug)]
@@ -708,6 +725,8 @@ pub struct BspData {
     pub(crate) texinfo: Box<[BspTexInfo]>,
     pub(crate) faces: Box<[BspFace]>,
     pub(crate) lightmaps: Box<[u8]>,
+    // from the map's .lit file, if it has one
+    pub(crate) colored_lightmaps: Option<Box<[u8]>>,
     pub(crate) leaves: Box<[BspLeaf]>,
     pub(crate) facelist: Box<[usize]>,
     pub(crate) edges: Box<[BspEdge]>,
@@ -744,9 +763,7 @@ impl BspData {
         let face = &self.faces[face_id];
         self.edgelist[face.edge_id..face.edge_id + face.edge_count]
             .iter()
-            .map(move |id| {
-                self.vertices[self.edges[id.index].vertex_ids[id.direction as usize] as usize]
-            })
+            .map(move |id| self.vertices[self.edges[id.index].vertex_ids[id.direction as usize]])
     }
 
     pub fn face_texinfo(&self, face_id: usize) -> &BspTexInfo {
@@ -772,6 +789,10 @@ impl BspData {
                             width: lightmap_w,
                             height: lightmap_h,
                             data: &self.lightmaps[start..end],
+                            rgb: self
+                                .colored_lightmaps
+                                .as_ref()
+                                .map(|rgb| &rgb[3 * start..3 * end]),
                         }
                     })
                     .collect()
@@ -828,6 +849,54 @@ impl BspData {
         }
     }
 
+    /// Collects the indices of the leaves touched by the box from `min` to `max` into `leaves`.
+    ///
+    /// Leaf 0 (outside the map) is never included.
+    pub fn find_touched_leaves(
+        &self,
+        min: Vector3<f32>,
+        max: Vector3<f32>,
+        leaves: &mut Vec<usize>,
+    ) {
+        self.find_touched_leaves_r(0, min, max, leaves);
+    }
+
+    fn find_touched_leaves_r(
+        &self,
+        node_id: usize,
+        min: Vector3<f32>,
+        max: Vector3<f32>,
+        leaves: &mut Vec<usize>,
+    ) {
+        let node = &self.render_nodes[node_id];
+        let plane = &self.planes[node.plane_id];
+
+        // the box crosses the plane if its corners lie on both sides
+        let mut sides = [false; 2];
+        for corner in 0..8 {
+            let point = Vector3::new(
+                if corner & 1 == 0 { min.x } else { max.x },
+                if corner & 2 == 0 { min.y } else { max.y },
+                if corner & 4 == 0 { min.z } else { max.z },
+            );
+            sides[plane.point_side(point) as usize] = true;
+        }
+
+        for (side, touched) in sides.into_iter().enumerate() {
+            if !touched {
+                continue;
+            }
+
+            match node.children[side] {
+                BspRenderNodeChild::Node(child_id) => {
+                    self.find_touched_leaves_r(child_id, min, max, leaves)
+                }
+                BspRenderNodeChild::Leaf(0) => (),
+                BspRenderNodeChild::Leaf(leaf_id) => leaves.push(leaf_id),
+            }
+        }
+    }
+
     pub fn get_pvs(&self, leaf_id: usize, leaf_count: usize) -> Vec<usize> {
         // leaf 0 is outside the map, everything is visible
         if leaf_id == 0 {
@@ -19,22 +19,27 @@
 // SOFTWARE.
 
 use std::{
-    collections::{BTreeMap, BTreeSet},
+    collections::{BTreeMap, BTreeSet, VecDeque},
     fmt::{self, Write},
-    io, iter,
+    io::{self, Read as _, Write as _},
+    iter,
     marker::PhantomData,
     mem,
     str::FromStr,
+    sync::Arc,
 };
 
 use beef::Cow;
 use bevy::{
+    app::AppExit,
     ecs::{
         system::{Command, Resource, SystemId},
         world::World,
     },
     prelude::*,
-    render::render_asset::RenderAssetUsages,
+    render::{
+        extract_resource::ExtractResource, render_asset::RenderAssetUsages, texture::ImageSampler,
+    },
 };
 use chrono::Duration;
 use clap::{FromArgMatches, Parser};
@@ -50,7 +55,7 @@ use wgpu::{Extent3d, TextureDimension};
 
 use crate::client::{
     input::{game::Trigger, InputFocus},
-    render::{Palette, TextureData},
+    render::{Charset, Palette, TextureData},
     ConnectionState,
 };
 
@@ -64,6 +69,61 @@ use super::{
 pub struct SeismonConsolePlugin;
 
 impl Plugin for SeismonConsolePlugin {
+    fn build(&self, app: &mut App) {
+        #[derive(Parser)]
+        #[command(
+            name = "condump",
+            about = "Write everything printed to the console to a file"
+        )]
+        struct ConDump {
+            #[arg(default_value = "condump.txt")]
+            file: String,
+        }
+
+        app.add_plugins(SeismonConsoleCorePlugin)
+            .init_resource::<RenderConsoleOutput>()
+            .init_resource::<RenderConsoleInput>()
+            .init_resource::<NotifyView>()
+            .add_systems(Startup, systems::startup::init_console)
+            .add_systems(
+                Update,
+                (
+                    systems::update_console_size
+                        .run_if(resource_changed_or_removed::<ConnectionState>()),
+                    systems::update_render_console,
+                    systems::update_notify,
+                    systems::write_console_out.run_if(resource_changed::<RenderConsoleOutput>),
+                    systems::write_console_in.run_if(resource_changed::<RenderConsoleInput>),
+                    systems::update_console_visibility.run_if(resource_changed::<InputFocus>),
+                    console_text::systems::update_atlas_text,
+                ),
+            )
+            .command(
+                |In(ConDump { file }),
+                 vfs: Res<Vfs>,
+                 console: Res<RenderConsoleOutput>|
+                 -> ExecResult {
+                    let mut out = match vfs.write(&file) {
+                        Ok(out) => out,
+                        Err(e) => return format!("Couldn't write {}: {}", file, e).into(),
+                    };
+
+                    match out
+                        .write_all(console.log().as_bytes())
+                        .and_then(|()| out.flush())
+                    {
+                        Ok(()) => format!("Dumped console text to {}", file).into(),
+                        Err(e) => format!("Couldn't write {}: {}", file, e).into(),
+                    }
+                },
+            );
+    }
+}
+
+/// The commands and cvars, without the console's UI. Dedicated servers use this on its own.
+pub struct SeismonConsoleCorePlugin;
+
+impl Plugin for SeismonConsoleCorePlugin {
     fn build(&self, app: &mut App) {
         let vfs = app.world.resource::<Vfs>();
 
@@ -104,37 +164,20 @@ impl Plugin for SeismonConsolePlugin {
         #[command(name = "resetall", about = "Reset all cvars to their initial values")]
         struct ResetAll;
 
+        #[derive(Parser)]
+        #[command(
+            name = "wait",
+            about = "Run the rest of the commands on the next frame"
+        )]
+        struct Wait;
+
         app.init_resource::<ConsoleOutput>()
             .insert_resource(ConsoleInput::new(history).unwrap())
-            .init_resource::<RenderConsoleOutput>()
-            .init_resource::<RenderConsoleInput>()
             .init_resource::<Registry>()
-            .init_resource::<ConsoleAlertSettings>()
-            .init_resource::<Gfx>()
+            .init_resource::<WaitingCmds>()
             .add_event::<RunCmd<'static>>()
-            .add_systems(
-                Startup,
-                (
-                    systems::startup::init_alert_output,
-                    systems::startup::init_console,
-                ),
-            )
-            .add_systems(
-                Update,
-                (
-                    systems::update_console_size
-                        .run_if(resource_changed_or_removed::<ConnectionState>()),
-                    systems::update_render_console,
-                    systems::write_alert,
-                    (systems::write_console_out, systems::write_center_print)
-                        .run_if(resource_changed::<RenderConsoleOutput>),
-                    systems::write_console_in.run_if(resource_changed::<RenderConsoleInput>),
-                    systems::update_console_visibility.run_if(resource_changed::<InputFocus>),
-                    console_text::systems::update_atlas_text,
-                    systems::execute_console,
-                    systems::update_cvars,
-                ),
-            )
+            .add_event::<CvarNotify>()
+            .add_systems(Update, (systems::execute_console, systems::update_cvars))
             .command(
                 |In(StuffCmds), mut input: ResMut<ConsoleInput>| -> ExecResult {
                     ExecResult {
@@ -195,8 +238,185 @@ impl Plugin for SeismonConsolePlugin {
 
                     default()
                 },
-            );
+            )
+            .command(|In(Wait), mut waiting: ResMut<WaitingCmds>| -> ExecResult {
+                waiting.wait = true;
+                default()
+            });
+
+        register_commands(app);
+    }
+}
+
+/// Commands which only need the console and the filesystem.
+fn register_commands(app: &mut App) {
+    #[derive(Parser)]
+    #[command(name = "echo", about = "Echo to the console")]
+    struct Echo {
+        args: Vec<String>,
+    }
+
+    app.command(|In(Echo { args })| args.join(" ").trim().to_owned().into());
+
+    #[derive(Parser)]
+    #[command(
+        name = "alias",
+        about = "Make an alias for one or more commands separated by semicolons, or list the aliases"
+    )]
+    struct Alias {
+        // `-name` is the release half of a `+name` alias
+        #[arg(allow_hyphen_values = true)]
+        alias_name: Option<String>,
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        commands: Vec<String>,
+    }
+
+    app.command(
+        |In(Alias {
+             alias_name,
+             commands,
+         }),
+         mut registry: ResMut<Registry>| {
+            match alias_name {
+                None => {
+                    let mut out = String::new();
+                    let mut count = 0;
+                    for AliasInfo { name, target, .. } in registry.aliases() {
+                        count += 1;
+                        out.push_str(&format!("    {}: {}\n", name, target));
+                    }
+                    out.push_str(&format!("{} alias command(s)", count));
+
+                    out.into()
+                }
+
+                Some(name) if commands.is_empty() => match registry.get(&name) {
+                    Some(CommandImpl {
+                        kind: CmdKind::Alias(target),
+                        ..
+                    }) => format!("{}: {}", name, target).into(),
+                    _ => format!("No such alias: {}", name).into(),
+                },
+
+                Some(name) => {
+                    registry.alias(name, commands.join(" "));
+
+                    default()
+                }
+            }
+        },
+    );
+
+    #[derive(Parser)]
+    #[command(name = "unalias", about = "Remove an alias")]
+    struct Unalias {
+        #[arg(allow_hyphen_values = true)]
+        alias_name: String,
+    }
+
+    app.command(
+        |In(Unalias { alias_name }), mut registry: ResMut<Registry>| -> ExecResult {
+            match registry.remove_alias(&alias_name) {
+                Ok(()) => default(),
+                Err(e) => e.to_string().into(),
+            }
+        },
+    );
+
+    #[derive(Parser)]
+    #[command(name = "find", about = "Find a command by name")]
+    struct Find {
+        pattern: String,
+    }
+
+    app.command(move |In(Find { pattern }), cmds: Res<Registry>| {
+        // Take every item starting with the target.
+        let it = cmds
+            .all_names()
+            .skip_while(|item| !item.starts_with(&pattern))
+            .take_while(|item| item.starts_with(&pattern))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        it.into()
+    });
+
+    #[derive(Parser)]
+    #[command(name = "exec", about = "Execute a cfg file")]
+    struct Exec {
+        #[arg(required = true)]
+        cfgs: Vec<String>,
+    }
+
+    app.command(move |In(Exec { cfgs }), vfs: Res<Vfs>| {
+        let mut script = String::new();
+
+        for cfg in &cfgs {
+            let mut script_file = match vfs.open(&cfg) {
+                Ok(s) => s,
+                Err(e) => {
+                    return ExecResult {
+                        output: format!("Couldn't exec {}: {:?}", cfg, e).into(),
+                        ..default()
+                    };
+                }
+            };
+
+            if let Err(e) = script_file.read_to_string(&mut script) {
+                return format!("Couldn't exec {}: {}", cfg, e).into();
+            }
+            script.push('\n');
+        }
+
+        let script = match RunCmd::parse_many(&*script) {
+            Ok(commands) => commands,
+            Err(e) => {
+                return ExecResult {
+                    output: format!("Couldn't exec: {}", e).into(),
+                    ..default()
+                }
+            }
+        };
+
+        let extra_commands = Box::new(
+            script
+                .into_iter()
+                .map(RunCmd::into_owned)
+                .collect::<Vec<_>>()
+                .into_iter(),
+        );
+
+        ExecResult {
+            extra_commands,
+            ..default()
+        }
+    });
+
+    #[derive(Parser)]
+    #[command(name = "quit", about = "Exit the game")]
+    struct Quit;
+
+    app.command(|In(Quit), mut exit: EventWriter<AppExit>| {
+        exit.send(AppExit);
+        default()
+    });
+}
+
+/// The commands which run the startup scripts.
+///
+/// `quake.rc` runs `config.cfg`, `autoexec.cfg` and `stuffcmds` itself, so they're only run
+/// directly for games which don't have one.
+pub fn startup_commands(vfs: &Vfs) -> Vec<RunCmd<'static>> {
+    if vfs.open("quake.rc").is_ok() {
+        return vec![RunCmd("exec".into(), Box::new(["quake.rc".to_owned()]))];
     }
+
+    ["config.cfg", "autoexec.cfg"]
+        .into_iter()
+        .filter(|cfg| vfs.open(cfg).is_ok())
+        .map(|cfg| RunCmd("exec".into(), Box::new([cfg.to_owned()])))
+        .chain(iter::once(RunCmd::from("stuffcmds")))
+        .collect()
 }
 
 pub type CName = Cow<'static, str>;
@@ -265,7 +485,7 @@ pub enum CmdKind {
         state: Trigger,
         // TODO: Mark when the last state update was, so we know how long a key has been pressed
     },
-    // TODO: Allow `Alias` to invoke an arbitrary sequence of commands
+    /// The text of one or more commands separated by semicolons, run in place of the alias.
     Alias(CName),
     Cvar {
         cvar: Cvar,
@@ -387,14 +607,27 @@ impl std::fmt::Display for RunCmd<'_> {
         write!(f, "{}", &self.0)?;
 
         for arg in self.1.iter() {
-            // TODO: This doesn't work if the value is a string that requires quotes - use `lexpr::Value`?
-            write!(f, " {:?}", arg)?;
+            write!(f, " {}", quote(arg))?;
         }
 
         Ok(())
     }
 }
 
+/// Quote `arg` so that it's parsed back as a single argument, escaping the quotes and backslashes
+/// in it.
+pub fn quote(arg: &str) -> String {
+    format!("\"{}\"", arg.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// `arg` as it would be typed at the console: as it is if it's a single word, or quoted if not.
+pub fn quote_if_needed(arg: &str) -> Cow<'_, str> {
+    match parse::console::basic_arg(arg) {
+        Ok(("", _)) => Cow::borrowed(arg),
+        _ => Cow::owned(quote(arg)),
+    }
+}
+
 impl FromStr for RunCmd<'static> {
     type Err = nom::Err<nom::error::Error<String>>;
 
@@ -433,6 +666,11 @@ pub trait RegisterCmdExt {
         N: Into<CName>,
         C: Into<Cvar>,
         I: Into<CName>;
+
+    fn action<N, I>(&mut self, name: N, usage: I) -> &mut Self
+    where
+        N: Into<CName>,
+        I: Into<CName>;
 }
 
 impl RegisterCmdExt for App {
@@ -467,6 +705,16 @@ impl RegisterCmdExt for App {
 
         self
     }
+
+    fn action<N, I>(&mut self, name: N, usage: I) -> &mut Self
+    where
+        N: Into<CName>,
+        I: Into<CName>,
+    {
+        self.world.action(name, usage);
+
+        self
+    }
 }
 
 struct MaybeSystem<S, F, E> {
@@ -632,6 +880,16 @@ impl RegisterCmdExt for World {
 
         self
     }
+
+    fn action<N, I>(&mut self, name: N, usage: I) -> &mut Self
+    where
+        N: Into<CName>,
+        I: Into<CName>,
+    {
+        self.resource_mut::<Registry>().action(name, None, usage);
+
+        self
+    }
 }
 
 pub trait CmdExt {
@@ -661,6 +919,13 @@ impl Command for SetCvar {
     }
 }
 
+/// The commands left over when `wait` is run, which are run first on the next frame.
+#[derive(Resource, Default)]
+struct WaitingCmds {
+    wait: bool,
+    commands: VecDeque<RunCmd<'static>>,
+}
+
 pub struct ExecResult {
     pub extra_commands: Box<dyn DoubleEndedIterator<Item = RunCmd<'static>>>,
     pub output: CName,
@@ -717,6 +982,10 @@ pub struct Registry {
     // TODO: Implement a compression pass (e.g. after a removal)
     commands: HashMap<CName, (CommandImpl, Vec<CommandImpl>)>,
     changed_cvars: HashMap<EqHack<SystemId<Value>>, Value>,
+    notified_cvars: Vec<CName>,
+    /// Incremented whenever a cvar is added or its value changes. `Registry` is mutably borrowed
+    /// every frame, so its change detection can't tell whether a cvar actually changed.
+    cvar_generation: u64,
     names: BTreeSet<CName>,
 }
 
@@ -725,15 +994,32 @@ impl Registry {
         Self::default()
     }
 
+    /// Defines an alias which runs `command`, which can be several commands separated by
+    /// semicolons. An alias named `+name` runs when `+name` is invoked and `-name` when it's
+    /// released, as with actions.
+    ///
+    /// Redefining an alias replaces it rather than shadowing it.
     pub fn alias<S, C>(&mut self, name: S, command: C)
     where
         S: Into<CName>,
         C: Into<CName>,
     {
+        let name = name.into();
+        let command = command.into();
+
+        if let Some(CommandImpl {
+            kind: CmdKind::Alias(target),
+            ..
+        }) = self.get_mut(&name)
+        {
+            *target = command;
+            return;
+        }
+
         self.insert(
-            name.into(),
+            name,
             CommandImpl {
-                kind: CmdKind::Alias(command.into()),
+                kind: CmdKind::Alias(command),
                 // TODO: Implement help text for aliases?
                 help: "".into(),
             },
@@ -765,6 +1051,7 @@ impl Registry {
         if let Some(sys) = on_set.clone() {
             self.changed_cvars.insert(EqHack(sys), cvar.default.clone());
         }
+        self.cvar_generation += 1;
         self.insert(
             name.into(),
             CommandImpl {
@@ -774,6 +1061,30 @@ impl Registry {
         );
     }
 
+    /// Registers a new action with the given name, which can be invoked with `+name`/`-name`.
+    ///
+    /// The pressed state of the action can be queried with [`Registry::is_pressed`].
+    fn action<N, H>(
+        &mut self,
+        name: N,
+        system: Option<SystemId<(Trigger, Box<[String]>), ()>>,
+        help: H,
+    ) where
+        N: Into<CName>,
+        H: Into<CName>,
+    {
+        self.insert(
+            name.into(),
+            CommandImpl {
+                kind: CmdKind::Action {
+                    system,
+                    state: Trigger::Negative,
+                },
+                help: help.into(),
+            },
+        );
+    }
+
     fn insert<N: Into<CName>>(&mut self, name: N, value: CommandImpl) {
         let name = name.into();
 
@@ -846,6 +1157,7 @@ impl Registry {
                 };
                 if overlays.pop().is_none() {
                     self.commands.remove(name);
+                    self.names.remove(name);
                 }
 
                 Ok(())
@@ -885,6 +1197,11 @@ impl Registry {
         self.commands.contains_key(name.as_ref())
     }
 
+    /// A number which changes whenever a cvar is added or set to a different value.
+    pub fn cvar_generation(&self) -> u64 {
+        self.cvar_generation
+    }
+
     pub fn get_cvar<S: AsRef<str>>(&self, name: S) -> Option<&Cvar> {
         self.get(name).and_then(|info| match &info.kind {
             CmdKind::Cvar { cvar, .. } => Some(cvar),
@@ -917,6 +1234,11 @@ impl Registry {
             .get_cvar_mut(name.as_ref())
             .ok_or_else(|| ConsoleError::NoSuchCvar(name.as_ref().to_owned().into()))?;
 
+        let changed = cvar
+            .value
+            .as_ref()
+            .is_some_and(|value| value != &cvar.default);
+        let notify = changed && cvar.notify;
         let to_insert = if let Some(sys) = on_set {
             if cvar.value.is_some() {
                 Some((EqHack(sys), cvar.default.clone()))
@@ -933,6 +1255,14 @@ impl Registry {
             self.changed_cvars.insert(sys, val);
         }
 
+        if changed {
+            self.cvar_generation += 1;
+        }
+
+        if notify {
+            self.notified_cvars.push(name.as_ref().to_owned().into());
+        }
+
         out
     }
 
@@ -944,8 +1274,10 @@ impl Registry {
             .get_cvar_mut(name.as_ref())
             .ok_or_else(|| ConsoleError::NoSuchCvar(name.as_ref().to_owned().into()))?;
 
+        let changed = cvar.value() != &value;
+        let notify = changed && cvar.notify;
         let to_insert = if let Some(sys) = on_set {
-            if cvar.value.as_ref().unwrap_or(&cvar.default) != &value {
+            if changed {
                 let value = value.clone();
                 Some((EqHack(sys), value))
             } else {
@@ -961,6 +1293,14 @@ impl Registry {
             self.changed_cvars.insert(sys, val);
         }
 
+        if changed {
+            self.cvar_generation += 1;
+        }
+
+        if notify {
+            self.notified_cvars.push(name.as_ref().to_owned().into());
+        }
+
         out
     }
 
@@ -1340,6 +1680,38 @@ impl Registry {
     pub fn all_names(&self) -> impl Iterator<Item = &str> + Clone + '_ {
         self.names.iter().map(AsRef::as_ref)
     }
+
+    /// The names and values of the cvars flagged as server info, which describe the game to
+    /// players browsing for servers.
+    pub fn serverinfo(&self) -> impl Iterator<Item = (&str, String)> + '_ {
+        self.cvar_names().filter_map(move |name| {
+            self.get_cvar(name)
+                .filter(|cvar| cvar.serverinfo)
+                .map(|cvar| (name, cvar.value_text()))
+        })
+    }
+
+    /// Write the archived cvars as commands which set them to their current values, the way
+    /// they're saved in `config.cfg`.
+    pub fn write_archived_cvars<W: fmt::Write>(&self, out: &mut W) -> fmt::Result {
+        for name in self.cvar_names() {
+            let Some(cvar) = self.get_cvar(name).filter(|cvar| cvar.archive) else {
+                continue;
+            };
+
+            writeln!(out, "{} {}", name, quote(&cvar.value_text()))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Sent when a cvar flagged with [`Cvar::notify`] changes value, so that the server can tell its
+/// players.
+#[derive(Event, Debug, Clone)]
+pub struct CvarNotify {
+    pub name: CName,
+    pub value: String,
 }
 
 /// A configuration variable.
@@ -1350,7 +1722,7 @@ pub struct Cvar {
     // Value of this variable
     pub value: Option<Value>,
 
-    // If true, this variable should be archived in vars.rc
+    // If true, this variable should be archived in config.cfg
     pub archive: bool,
 
     // If true:
@@ -1358,6 +1730,9 @@ pub struct Cvar {
     // - If a client cvar, update userinfo
     pub notify: bool,
 
+    // If true, this variable is reported in the server info
+    pub serverinfo: bool,
+
     // The default value of this variable
     pub default: Value,
 }
@@ -1368,6 +1743,7 @@ impl Default for Cvar {
             value: default(),
             archive: default(),
             notify: default(),
+            serverinfo: default(),
             default: Value::Nil,
         }
     }
@@ -1400,9 +1776,26 @@ impl Cvar {
         self
     }
 
+    pub fn serverinfo(mut self) -> Self {
+        self.serverinfo = true;
+
+        self
+    }
+
     pub fn value(&self) -> &Value {
         self.value.as_ref().unwrap_or(&self.default)
     }
+
+    /// The current value as it would be typed at the console, without the quotes that strings
+    /// get when displayed as an S-expression. Use [`quote`] where it has to be read back as a
+    /// single argument.
+    pub fn value_text(&self) -> String {
+        let value = self.value();
+        match value.as_name() {
+            Some(text) => text.to_owned(),
+            None => value.to_string(),
+        }
+    }
 }
 
 /// The line of text currently being edited in the console.
@@ -1490,6 +1883,62 @@ impl EditorContext for ConsoleInputContext {
     }
 }
 
+/// Maps a Unicode character to the closest character available in the Quake charset.
+///
+/// `conchars` only has glyphs for ASCII (plus colored variants in the upper half), so text typed
+/// on non-US layouts or through an IME has to be folded down. Accents are stripped where there's an
+/// obvious base letter, typographic punctuation is replaced with its ASCII equivalent, and anything
+/// else becomes `?`. Control characters return `None`.
+pub fn to_quake_char(c: char) -> Option<char> {
+    let out = match c {
+        ' '..='~' => c,
+        '\u{a0}' | '\u{2000}'..='\u{200a}' | '\u{3000}' => ' ',
+        'À'..='Å' | 'Ā' | 'Ă' | 'Ą' => 'A',
+        'à'..='å' | 'ā' | 'ă' | 'ą' => 'a',
+        'Ç' | 'Ć' | 'Č' => 'C',
+        'ç' | 'ć' | 'č' => 'c',
+        'Ď' | 'Đ' | 'Ð' => 'D',
+        'ď' | 'đ' | 'ð' => 'd',
+        'È'..='Ë' | 'Ē' | 'Ė' | 'Ę' | 'Ě' => 'E',
+        'è'..='ë' | 'ē' | 'ė' | 'ę' | 'ě' => 'e',
+        'Ğ' => 'G',
+        'ğ' => 'g',
+        'Ì'..='Ï' | 'Ī' | 'İ' => 'I',
+        'ì'..='ï' | 'ī' | 'ı' => 'i',
+        'Ł' | 'Ľ' => 'L',
+        'ł' | 'ľ' => 'l',
+        'Ñ' | 'Ń' | 'Ň' => 'N',
+        'ñ' | 'ń' | 'ň' => 'n',
+        'Ò'..='Ö' | 'Ø' | 'Ő' => 'O',
+        'ò'..='ö' | 'ø' | 'ő' => 'o',
+        'Ř' => 'R',
+        'ř' => 'r',
+        'Ś' | 'Š' | 'Ş' => 'S',
+        'ś' | 'š' | 'ş' => 's',
+        'ß' => 's',
+        'Ť' => 'T',
+        'ť' => 't',
+        'Ù'..='Ü' | 'Ů' | 'Ű' => 'U',
+        'ù'..='ü' | 'ů' | 'ű' => 'u',
+        'Ý' | 'Ÿ' => 'Y',
+        'ý' | 'ÿ' => 'y',
+        'Ź' | 'Ż' | 'Ž' => 'Z',
+        'ź' | 'ż' | 'ž' => 'z',
+        '‘' | '’' | '‚' | '′' | '´' => '\'',
+        '“' | '”' | '„' | '″' | '«' | '»' => '"',
+        '‐'..='―' | '−' => '-',
+        '…' => '.',
+        '×' => 'x',
+        '÷' => '/',
+        '¡' => '!',
+        '¿' => '?',
+        _ if c.is_control() => return None,
+        _ => '?',
+    };
+
+    Some(out)
+}
+
 pub fn to_terminal_key<'a>(
     key: &'a bevy::input::keyboard::Key,
     button_state: &bevy::input::ButtonInput<KeyCode>,
@@ -1509,7 +1958,7 @@ pub fn to_terminal_key<'a>(
         };
 
     match key {
-        Character(c) => Either::Left(c.chars().map(make_char)),
+        Character(c) => Either::Left(c.chars().filter_map(to_quake_char).map(make_char)),
         Backspace => Either::Right(Some(Key::Backspace).into_iter()),
         Delete => Either::Right(Some(Key::Delete).into_iter()),
         Enter => Either::Right(Some(Key::Char('\n')).into_iter()),
@@ -1554,10 +2003,58 @@ where
     }
 }
 
+/// Something that happened in the console input as a result of the keys sent to it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConsoleInputEvent {
+    /// A line was entered, and should be run.
+    Exec(String),
+    /// Tab was pressed but the word being typed could be completed more than one way. The
+    /// candidates should be listed, and pressing tab again cycles through them.
+    Candidates(Vec<String>),
+}
+
+/// Tab-completion state, kept between presses of tab so that repeated presses cycle through the
+/// candidates.
+struct Completion {
+    /// The line before the word being completed.
+    line: String,
+    candidates: Vec<String>,
+    /// The candidate currently on the line, once we've started cycling.
+    current: Option<usize>,
+}
+
+/// A reverse search through the history, started with ctrl-R.
+#[derive(Default)]
+struct HistorySearch {
+    query: String,
+    /// The index in the history and the text of the newest entry matching `query`.
+    found: Option<(usize, String)>,
+}
+
+/// The longest prefix shared by every candidate.
+fn common_prefix(candidates: &[String]) -> &str {
+    let Some((first, rest)) = candidates.split_first() else {
+        return "";
+    };
+
+    let len = rest.iter().fold(first.len(), |len, candidate| {
+        first
+            .bytes()
+            .zip(candidate.bytes())
+            .take(len)
+            .take_while(|(a, b)| a == b)
+            .count()
+    });
+
+    &first[..len]
+}
+
 #[derive(Resource)]
 pub struct ConsoleInput {
     editor: Editor<ConsoleInputContext>,
     keymap: Emacs,
+    completion: Option<Completion>,
+    search: Option<HistorySearch>,
     pub stuffcmds: Vec<RunCmd<'static>>,
 }
 
@@ -1574,6 +2071,7 @@ impl Default for ConsoleInput {
 
 impl ConsoleInput {
     pub const PROMPT: &'static str = "] ";
+    pub const SEARCH_PROMPT: &'static str = "(reverse-i-search)";
 
     /// Constructs a new `ConsoleInput`.
     ///
@@ -1596,17 +2094,21 @@ impl ConsoleInput {
         Ok(ConsoleInput {
             editor,
             keymap: Emacs::new(),
+            completion: None,
+            search: None,
             stuffcmds: default(),
         })
     }
 
-    /// Send characters to the inner editor
+    /// Send characters to the inner editor.
+    ///
+    /// `candidates` are the names that tab completes the first word of a command to.
     #[must_use]
     pub fn update<'a, I, C>(
         &'a mut self,
         keys: I,
         candidates: C,
-    ) -> impl Iterator<Item = io::Result<String>> + 'a
+    ) -> impl Iterator<Item = io::Result<ConsoleInputEvent>> + 'a
     where
         I: IntoIterator<Item = Key>,
         I::IntoIter: 'a,
@@ -1614,39 +2116,194 @@ impl ConsoleInput {
         C::Item: AsRef<str>,
     {
         let mut completer = IterCompleter { iter: candidates };
-        keys.into_iter().filter_map(move |key| {
-            // TODO: Completion
-            match self
-                .keymap
-                .handle_key(key, &mut self.editor, &mut completer)
-            {
-                Ok(true) => {
-                    let out = self.editor.take_exec_buffer();
+        keys.into_iter()
+            .filter_map(move |key| self.handle_key(key, &mut completer).transpose())
+    }
 
-                    if let Err(e) = self.editor.move_to_end_of_history() {
-                        warn!("{}", e);
-                    }
+    fn handle_key<C>(
+        &mut self,
+        key: Key,
+        completer: &mut IterCompleter<C>,
+    ) -> io::Result<Option<ConsoleInputEvent>>
+    where
+        C: Iterator + Clone,
+        C::Item: AsRef<str>,
+    {
+        if let Some(search) = &mut self.search {
+            match key {
+                Key::Ctrl('r') => {
+                    let from = search.found.as_ref().map(|(i, _)| *i);
+                    self.search_history(from);
+                    return Ok(None);
+                }
+                Key::Ctrl('g') => {
+                    self.search = None;
+                    return Ok(None);
+                }
+                Key::Backspace => {
+                    search.query.pop();
+                    self.search_history(None);
+                    return Ok(None);
+                }
+                Key::Char(c) if c != '\n' && c != '\t' => {
+                    search.query.push(c);
+                    // The current match might still match the longer query
+                    let from = search.found.as_ref().map(|(i, _)| *i + 1);
+                    self.search_history(from);
+                    return Ok(None);
+                }
+                // Any other key puts the match on the line and then acts on it as usual
+                _ => self.accept_search()?,
+            }
+        }
+
+        if !matches!(key, Key::Char('\t')) {
+            self.completion = None;
+        }
+
+        match key {
+            Key::Char('\t') => return self.complete(completer),
+            Key::Ctrl('r') => {
+                self.search = Some(default());
+                return Ok(None);
+            }
+            _ => {}
+        }
+
+        if !self.keymap.handle_key(key, &mut self.editor, completer)? {
+            return Ok(None);
+        }
+
+        let out = self.editor.take_exec_buffer();
+
+        if let Err(e) = self.editor.move_to_end_of_history() {
+            warn!("{}", e);
+        }
+
+        self.editor.context_mut().history.push(out.clone().into())?;
+        self.editor.move_cursor_to_start_of_line()?;
+
+        Ok(Some(ConsoleInputEvent::Exec(out)))
+    }
 
-                    Some(
-                        self.editor
-                            .context_mut()
-                            .history
-                            .push(out.clone().into())
-                            .and_then(|()| self.editor.move_cursor_to_start_of_line())
-                            .map(|()| out),
-                    )
+    fn replace_line(&mut self, text: &str) -> io::Result<()> {
+        self.editor.move_cursor_to_end_of_line()?;
+        self.editor.delete_all_before_cursor()?;
+        self.editor.insert_str_after_cursor(text)
+    }
+
+    /// Complete the command name being typed, after the last `;`.
+    ///
+    /// A single candidate is completed in full, and several are completed as far as they agree.
+    /// If that doesn't add anything they're listed, and further presses cycle through them.
+    fn complete<C>(
+        &mut self,
+        completer: &mut IterCompleter<C>,
+    ) -> io::Result<Option<ConsoleInputEvent>>
+    where
+        C: Iterator + Clone,
+        C::Item: AsRef<str>,
+    {
+        if let Some(completion) = &mut self.completion {
+            let next = completion
+                .current
+                .map_or(0, |i| (i + 1) % completion.candidates.len());
+            completion.current = Some(next);
+
+            let text = format!("{}{}", completion.line, completion.candidates[next]);
+            self.replace_line(&text)?;
+            return Ok(None);
+        }
+
+        let line = self.editor.current_buffer().chars().collect::<String>();
+        let command_start = line.rfind(';').map_or(0, |i| i + 1);
+        let word_start = line.len() - line[command_start..].trim_start().len();
+        let (before, word) = line.split_at(word_start);
+        if word.is_empty() || word.contains(char::is_whitespace) {
+            return Ok(None);
+        }
+
+        let mut candidates = liner::Completer::completions(completer, word);
+        candidates.sort();
+        candidates.dedup();
+
+        match candidates.len() {
+            0 => Ok(None),
+            1 => {
+                self.replace_line(&format!("{}{} ", before, candidates[0]))?;
+                Ok(None)
+            }
+            _ => {
+                let prefix = common_prefix(&candidates);
+                if prefix.len() > word.len() {
+                    self.replace_line(&format!("{}{}", before, prefix))?;
+                    return Ok(None);
                 }
-                Ok(false) => None,
-                Err(e) => Some(Err(e)),
+
+                self.completion = Some(Completion {
+                    line: before.to_owned(),
+                    candidates: candidates.clone(),
+                    current: None,
+                });
+                Ok(Some(ConsoleInputEvent::Candidates(candidates)))
             }
-        })
+        }
+    }
+
+    /// Find the newest history entry before `from` containing the search query, or the newest of
+    /// all if `from` is `None`. If the search was repeated with ctrl-R and there's nothing older,
+    /// the previous match is kept.
+    fn search_history(&mut self, from: Option<usize>) {
+        let Some(search) = &mut self.search else {
+            return;
+        };
+
+        let history = &self.editor.context_mut().history;
+        let found = (0..from.unwrap_or(history.len()))
+            .rev()
+            .map(|i| (i, history[i].chars().collect::<String>()))
+            .find(|(_, text)| text.contains(&search.query));
+
+        if found.is_some() || search.query.is_empty() || from.is_none() {
+            search.found = found;
+        } else if search.found.as_ref().map(|(i, _)| *i) != from {
+            // The query was extended and the previous match no longer matches
+            search.found = None;
+        }
+    }
+
+    /// Stop searching, leaving the match on the line.
+    fn accept_search(&mut self) -> io::Result<()> {
+        if let Some(HistorySearch {
+            found: Some((_, text)),
+            ..
+        }) = self.search.take()
+        {
+            self.replace_line(&text)?;
+        }
+
+        Ok(())
     }
 
     /// Returns the text currently being edited
     pub fn get_text(&self) -> impl Iterator<Item = char> + '_ {
-        Self::PROMPT
-            .chars()
-            .chain(self.editor.current_buffer().chars().copied())
+        use itertools::Either;
+
+        match &self.search {
+            Some(search) => Either::Left(
+                Self::SEARCH_PROMPT
+                    .chars()
+                    .chain(Some('\''))
+                    .chain(search.query.chars())
+                    .chain("': ".chars())
+                    .chain(search.found.iter().flat_map(|(_, text)| text.chars())),
+            ),
+            None => Either::Right(
+                Self::PROMPT
+                    .chars()
+                    .chain(self.editor.current_buffer().chars().copied()),
+            ),
+        }
     }
 }
 
@@ -1685,6 +2342,8 @@ pub struct ConsoleOutput {
 pub struct RenderConsoleOutput {
     pub text_chunks: BTreeMap<Timestamp, ConsoleText>,
     pub center_print: (Timestamp, QString),
+    /// How many lines the console has been scrolled back from the newest output.
+    scroll: usize,
 }
 
 impl ConsoleOutput {
@@ -1785,13 +2444,62 @@ impl ConsoleOutput {
     }
 }
 
+/// How much of the console output `text` is left when it's scrolled back by `scroll` lines, or
+/// `None` if there aren't that many lines.
+fn scrolled_len(text: &[u8], scroll: usize) -> Option<usize> {
+    // The output ends in a newline, so the newest line scrolled out of view starts after the
+    // newline `scroll` lines before the last one
+    let last = text.iter().rposition(|c| *c == b'\n')?;
+    let (cut, _) = text[..last]
+        .iter()
+        .enumerate()
+        .rev()
+        .filter(|(_, c)| **c == b'\n')
+        .nth(scroll.checked_sub(1)?)?;
+    Some(cut + 1)
+}
+
 impl RenderConsoleOutput {
+    /// Lines scrolled by a page up or page down.
+    pub const PAGE_LINES: isize = 10;
+    /// Lines scrolled by a notch of the mouse wheel.
+    pub const WHEEL_LINES: isize = 3;
+
     pub fn text(&self) -> impl Iterator<Item = (i64, &ConsoleText)> + '_ {
         self.text_chunks
             .iter()
             .map(|(Timestamp { timestamp: k, .. }, v)| (*k, v))
     }
 
+    /// Everything printed to the console, as plain text.
+    pub fn log(&self) -> String {
+        self.text()
+            .map(|(_, chunk)| chunk.text.to_string())
+            .collect()
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.text_chunks
+            .values()
+            .map(|chunk| chunk.text.raw.iter().filter(|c| **c == b'\n').count())
+            .sum()
+    }
+
+    pub fn scroll(&self) -> usize {
+        self.scroll
+    }
+
+    /// Scroll back through the output by `lines`, or forward if `lines` is negative, stopping at
+    /// the oldest and newest lines.
+    pub fn scroll_by(&mut self, lines: isize) {
+        let max = self.line_count().saturating_sub(1);
+        self.scroll = self.scroll.saturating_add_signed(lines).min(max);
+    }
+
+    pub fn scroll_to_bottom(&mut self) {
+        self.scroll = 0;
+    }
+
     pub fn center_print(&self, since: Duration) -> Option<QStr> {
         if self.center_print.0.timestamp >= since.num_milliseconds() {
             Some(self.center_print.1.reborrow())
@@ -1815,24 +2523,33 @@ impl RenderConsoleOutput {
     }
 }
 
-#[derive(Component, Default)]
-struct AlertOutput {
-    last_timestamp: Option<i64>,
+/// How many milliseconds the center print and notify lines take to fade out at the end of their
+/// time on screen.
+const NOTIFY_FADE_MS: f32 = 500.;
+
+/// The text drawn over the game by the UI pass: the center print, and the last few lines printed
+/// by the server at the top of the screen.
+///
+/// Each line comes with its alpha, which falls to 0 over the end of its time on screen.
+#[derive(Resource, ExtractResource, Clone, Default, PartialEq)]
+pub struct NotifyView {
+    pub center_print: Vec<String>,
+    pub center_alpha: f32,
+    pub lines: Vec<(String, f32)>,
 }
 
-#[derive(Resource)]
-pub struct ConsoleAlertSettings {
-    timeout: Duration,
-    max_lines: usize,
+/// The characters of `text` as they're laid out in `conchars`, keeping the high bit for the
+/// alternate character set.
+fn glyph_text(text: &[u8]) -> String {
+    text.iter().map(|&b| char::from(b)).collect()
 }
 
-impl Default for ConsoleAlertSettings {
-    fn default() -> Self {
-        Self {
-            timeout: Duration::try_seconds(3).unwrap(),
-            max_lines: 10,
-        }
-    }
+/// Split `text` into lines, without an empty line after its final newline.
+fn glyph_lines(text: &[u8]) -> impl Iterator<Item = String> + '_ {
+    text.strip_suffix(b"\n")
+        .unwrap_or(text)
+        .split(|&b| b == b'\n')
+        .map(glyph_text)
 }
 
 #[derive(Component)]
@@ -1841,9 +2558,6 @@ struct ConsoleUi;
 #[derive(Component)]
 struct ConsoleTextOutputUi;
 
-#[derive(Component)]
-struct ConsoleTextCenterPrintUi;
-
 #[derive(Component)]
 struct ConsoleTextInputUi;
 
@@ -1852,6 +2566,7 @@ pub struct Conchars {
     pub image: UiImage,
     pub layout: Handle<TextureAtlasLayout>,
     pub glyph_size: (Val, Val),
+    pub advances: Arc<Vec<f32>>,
 }
 
 #[derive(Resource)]
@@ -1861,8 +2576,9 @@ pub struct Gfx {
     pub wad: Wad,
 }
 
-impl FromWorld for Gfx {
-    fn from_world(world: &mut World) -> Self {
+impl Gfx {
+    /// Load the palette and the console charset, failing with a message naming the missing file.
+    pub fn load(vfs: &Vfs, assets: &AssetServer) -> Result<Gfx, failure::Error> {
         // TODO: Deduplicate with glyph.rs
         const GLYPH_WIDTH: usize = 8;
         const GLYPH_HEIGHT: usize = 8;
@@ -1870,24 +2586,19 @@ impl FromWorld for Gfx {
         const GLYPH_ROWS: usize = 16;
         const SCALE: f32 = 2.;
 
-        let vfs = world.resource::<Vfs>();
-        let assets = world.resource::<AssetServer>();
-
-        let palette = Palette::load(&vfs, "gfx/palette.lmp");
-        let wad = Wad::load(vfs.open("gfx.wad").unwrap()).unwrap();
+        let palette = Palette::load(vfs, "gfx/palette.lmp")
+            .map_err(|e| failure::format_err!("Couldn't load gfx/palette.lmp: {}", e))?;
+        let wad = vfs
+            .open("gfx.wad")
+            .map_err(failure::Error::from)
+            .and_then(Wad::load)
+            .map_err(|e| failure::format_err!("Couldn't load gfx.wad: {}", e))?;
 
-        let conchars = wad.open_conchars().unwrap();
-
-        // TODO: validate conchars dimensions
-
-        let indices = conchars
-            .indices()
-            .iter()
-            .map(|i| if *i == 0 { 0xFF } else { *i })
-            .collect::<Vec<_>>();
+        let charset = Charset::load(vfs, &wad, &palette)
+            .map_err(|e| failure::format_err!("Couldn't load the console charset: {}", e))?;
 
         let layout = assets.add(TextureAtlasLayout::from_grid(
-            Vec2::new(GLYPH_WIDTH as _, GLYPH_HEIGHT as _),
+            Vec2::new(charset.glyph_width() as _, charset.glyph_height() as _),
             GLYPH_COLS,
             GLYPH_ROWS,
             None,
@@ -1895,24 +2606,25 @@ impl FromWorld for Gfx {
         ));
 
         let image = {
-            let (diffuse_data, _) = palette.translate(&indices);
-            let diffuse_data = TextureData::Diffuse(diffuse_data);
+            let mut image = Image::new(
+                Extent3d {
+                    width: charset.width(),
+                    height: charset.height(),
+                    depth_or_array_layers: 1,
+                },
+                TextureDimension::D2,
+                charset.rgba().to_owned(),
+                wgpu::TextureFormat::Rgba8UnormSrgb,
+                RenderAssetUsages::RENDER_WORLD,
+            );
+            if charset.smooth() {
+                image.sampler = ImageSampler::linear();
+            }
 
-            assets
-                .add(Image::new(
-                    Extent3d {
-                        width: conchars.width(),
-                        height: conchars.height(),
-                        depth_or_array_layers: 1,
-                    },
-                    TextureDimension::D2,
-                    diffuse_data.data().to_owned(),
-                    diffuse_data.format(),
-                    RenderAssetUsages::RENDER_WORLD,
-                ))
-                .into()
+            assets.add(image).into()
         };
 
+        // glyphs are drawn at the same size whatever the resolution of the charset
         let conchars = Conchars {
             image,
             layout,
@@ -1920,13 +2632,14 @@ impl FromWorld for Gfx {
                 Val::Px(GLYPH_WIDTH as _) * SCALE,
                 Val::Px(GLYPH_HEIGHT as _) * SCALE,
             ),
+            advances: Arc::new(charset.advances().to_vec()),
         };
 
-        Self {
+        Ok(Self {
             palette,
             wad,
             conchars,
-        }
+        })
     }
 }
 
@@ -1942,6 +2655,9 @@ mod console_text {
         pub layout: Handle<TextureAtlasLayout>,
         pub glyph_size: (Val, Val),
         pub justify: JustifyContent,
+
+        /// How far each glyph advances the line, as a fraction of the glyph width.
+        pub advances: Arc<Vec<f32>>,
     }
 
     pub mod systems {
@@ -1973,29 +2689,48 @@ mod console_text {
                             })
                             .with_children(|commands| {
                                 for chr in &*line.raw {
+                                    let glyph = AtlasImageBundle {
+                                        image: text.image.clone(),
+                                        texture_atlas: TextureAtlas {
+                                            layout: text.layout.clone(),
+                                            index: *chr as usize,
+                                        },
+                                        style: Style {
+                                            width: text.glyph_size.0,
+                                            height: text.glyph_size.1,
+                                            flex_shrink: 0.,
+                                            ..default()
+                                        },
+                                        ..default()
+                                    };
+                                    let advance = text.advances[*chr as usize];
+
                                     if chr.is_ascii_whitespace() {
                                         commands.spawn(NodeBundle {
                                             style: Style {
-                                                width: text.glyph_size.0,
+                                                width: text.glyph_size.0 * advance,
                                                 height: text.glyph_size.1,
                                                 ..default()
                                             },
                                             ..default()
                                         });
-                                    } else {
-                                        commands.spawn(AtlasImageBundle {
-                                            image: text.image.clone(),
-                                            texture_atlas: TextureAtlas {
-                                                layout: text.layout.clone(),
-                                                index: *chr as usize,
-                                            },
-                                            style: Style {
-                                                width: text.glyph_size.0,
-                                                height: text.glyph_size.1,
+                                    } else if advance < 1. {
+                                        // proportional glyphs are cut off on the right
+                                        commands
+                                            .spawn(NodeBundle {
+                                                style: Style {
+                                                    width: text.glyph_size.0 * advance,
+                                                    height: text.glyph_size.1,
+                                                    overflow: Overflow::clip(),
+                                                    ..default()
+                                                },
                                                 ..default()
-                                            },
-                                            ..default()
-                                        });
+                                            })
+                                            .with_children(|commands| {
+                                                commands.spawn(glyph);
+                                            });
+                                    } else {
+                                        commands.spawn(glyph);
                                     }
                                 }
                             });
@@ -2007,8 +2742,6 @@ mod console_text {
 }
 
 mod systems {
-    use std::collections::VecDeque;
-
     use chrono::TimeDelta;
 
     use crate::client::{Connection, ConnectionState};
@@ -2022,77 +2755,48 @@ mod systems {
 
         use super::*;
 
-        pub fn init_alert_output(mut commands: Commands, gfx: Res<Gfx>) {
-            let Conchars {
-                image,
-                layout,
-                glyph_size,
-            } = gfx.conchars.clone();
-            commands.spawn((
-                NodeBundle {
-                    style: Style {
-                        position_type: PositionType::Absolute,
-                        width: Val::Percent(100.),
-                        top: Val::Percent(30.),
-                        justify_content: JustifyContent::Center,
-                        flex_direction: FlexDirection::Column,
-                        ..default()
-                    },
-                    z_index: ZIndex::Global(1),
-                    ..default()
-                },
-                AtlasText {
-                    text: "".into(),
-                    image: image.clone(),
-                    layout: layout.clone(),
-                    glyph_size: (glyph_size.0 * 1.5, glyph_size.1 * 1.5),
-                    line_padding: UiRect {
-                        top: Val::Px(4.),
-                        ..default()
-                    },
-                    justify: JustifyContent::Center,
-                },
-                ConsoleTextCenterPrintUi,
-            ));
-            commands.spawn((
-                NodeBundle {
-                    style: Style {
-                        left: glyph_size.0 / 2.,
-                        top: glyph_size.1 / 2.,
-                        flex_direction: FlexDirection::Column,
-                        ..default()
-                    },
-                    ..default()
-                },
-                AtlasText {
-                    text: "".into(),
-                    image,
-                    layout,
-                    line_padding: UiRect {
-                        top: Val::Px(4.),
-                        ..default()
-                    },
-                    glyph_size: (glyph_size.0, glyph_size.1),
-                    justify: JustifyContent::Center,
-                },
-                AlertOutput::default(),
-            ));
-        }
+        pub fn init_console(mut commands: Commands, vfs: Res<Vfs>, assets: Res<AssetServer>) {
+            let loaded = Gfx::load(&vfs, &assets).and_then(|gfx| {
+                let conback = vfs
+                    .open("gfx/conback.lmp")
+                    .map_err(failure::Error::from)
+                    .and_then(|file| Ok(QPic::load(file)?))
+                    .map_err(|e| failure::format_err!("Couldn't load gfx/conback.lmp: {}", e))?;
+                Ok((gfx, conback))
+            });
+            let (gfx, conback) = match loaded {
+                Ok(loaded) => loaded,
+                Err(e) => {
+                    // without the charset the console can't draw this itself
+                    error!("{}", e);
+                    commands.spawn(
+                        TextBundle::from_section(
+                            format!("{}\nCheck that the game files are installed", e),
+                            TextStyle {
+                                font_size: 20.,
+                                color: Color::WHITE,
+                                ..default()
+                            },
+                        )
+                        .with_text_justify(JustifyText::Center)
+                        .with_style(Style {
+                            position_type: PositionType::Absolute,
+                            top: Val::Percent(45.),
+                            width: Val::Percent(100.),
+                            ..default()
+                        }),
+                    );
+                    return;
+                }
+            };
 
-        pub fn init_console(
-            mut commands: Commands,
-            vfs: Res<Vfs>,
-            gfx: Res<Gfx>,
-            assets: Res<AssetServer>,
-        ) {
             let Conchars {
                 image: conchars_img,
                 layout,
                 glyph_size,
+                advances,
             } = gfx.conchars.clone();
 
-            let conback = QPic::load(vfs.open("gfx/conback.lmp").unwrap()).unwrap();
-
             // TODO: validate conchars dimensions
 
             let (diffuse_data, _) = gfx.palette.translate(&conback.indices());
@@ -2171,6 +2875,7 @@ mod systems {
                                         ..default()
                                     },
                                     justify: JustifyContent::FlexStart,
+                                    advances: advances.clone(),
                                 },
                                 ConsoleTextOutputUi,
                             ));
@@ -2192,11 +2897,14 @@ mod systems {
                                         ..default()
                                     },
                                     justify: JustifyContent::FlexStart,
+                                    advances,
                                 },
                                 ConsoleTextInputUi,
                             ));
                         });
                 });
+
+            commands.insert_resource(gfx);
         }
     }
 
@@ -2273,22 +2981,21 @@ mod systems {
             for (_, line) in console_out.text_chunks.iter() {
                 text.text.push_bytes(&*line.text);
             }
-        }
-    }
 
-    pub fn write_center_print(
-        console_out: Res<RenderConsoleOutput>,
-        mut center_ui: Query<&mut AtlasText, With<ConsoleTextCenterPrintUi>>,
-    ) {
-        for mut text in center_ui.iter_mut() {
-            // TODO: Write only extra lines
-            if !text.text.is_empty() {
-                text.text.clear();
+            let scroll = console_out.scroll();
+            if scroll == 0 {
+                continue;
             }
 
-            if !console_out.center_print.1.is_empty() {
-                text.text.push_bytes(&*console_out.center_print.1.raw);
+            if let Some(len) = scrolled_len(&text.text.raw, scroll) {
+                text.text.truncate(len);
             }
+
+            text.text.push_str(format!(
+                "^   ^   ^   {} of {} lines back   ^   ^   ^\n",
+                scroll,
+                console_out.line_count()
+            ));
         }
     }
 
@@ -2312,39 +3019,53 @@ mod systems {
         }
     }
 
-    pub fn write_alert(
-        settings: Res<ConsoleAlertSettings>,
-        time: Res<Time<Virtual>>,
+    /// Work out which center print and notify lines should be on screen, and how faded they are.
+    ///
+    /// They're only drawn while playing, as the console shows them anyway.
+    pub fn update_notify(
         console_out: Res<RenderConsoleOutput>,
-        mut alert: Query<(&mut AtlasText, &mut AlertOutput)>,
+        registry: Res<Registry>,
+        time: Res<Time<Virtual>>,
+        focus: Res<InputFocus>,
+        mut view: ResMut<NotifyView>,
     ) {
-        for (mut text, mut alert) in alert.iter_mut() {
-            let since = TimeDelta::from_std(time.elapsed()).unwrap() - settings.timeout;
-            let mut lines = console_out
-                .recent(since)
-                .filter(|(_, line)| line.output_type == OutputType::Alert)
-                .map(|(ts, line)| (ts, &line.text))
-                .take(settings.max_lines);
-
-            let first = lines.next();
-            let last_timestamp = first.map(|(ts, _)| ts);
-
-            if last_timestamp == alert.last_timestamp {
-                continue;
-            }
+        let mut new = NotifyView::default();
 
-            alert.last_timestamp = last_timestamp;
+        if *focus == InputFocus::Game {
+            let now = TimeDelta::from_std(time.elapsed()).unwrap();
+            let seconds = |name: &str| {
+                let secs = registry.read_cvar::<f32>(name).unwrap_or(0.).max(0.);
+                TimeDelta::milliseconds((secs * 1000.) as i64)
+            };
+            let alpha = |printed: i64, shown: TimeDelta| {
+                let remaining = TimeDelta::milliseconds(printed) + shown - now;
+                (remaining.num_milliseconds() as f32 / NOTIFY_FADE_MS).clamp(0., 1.)
+            };
 
-            text.text.clear();
+            let (timestamp, text) = &console_out.center_print;
+            let center_alpha = alpha(timestamp.timestamp, seconds("scr_centertime"));
+            if !text.is_empty() && center_alpha > 0. {
+                new.center_print = glyph_lines(text).collect();
+                new.center_alpha = center_alpha;
+            }
 
-            let Some((_, first)) = first else {
-                continue;
-            };
-            text.text.push_bytes(first.as_ref());
+            let notify_time = seconds("con_notifytime");
+            let max_lines = registry.read_cvar::<f32>("con_notifylines").unwrap_or(0.) as usize;
+            for (printed, chunk) in console_out.recent(now - notify_time) {
+                if chunk.output_type != OutputType::Alert {
+                    continue;
+                }
 
-            for (_, line) in lines {
-                text.text.push_bytes(&*line.raw);
+                let alpha = alpha(printed, notify_time);
+                new.lines
+                    .extend(glyph_lines(&chunk.text).map(|line| (line, alpha)));
             }
+            let hidden = new.lines.len().saturating_sub(max_lines);
+            new.lines.drain(..hidden);
+        }
+
+        if *view != new {
+            *view = new;
         }
     }
 
@@ -2352,148 +3073,294 @@ mod systems {
         let time = world.resource::<Time<Real>>();
         let timestamp = TimeDelta::from_std(time.elapsed()).unwrap();
 
-        let mut commands = world
-            .resource_mut::<Events<RunCmd>>()
-            .drain()
-            .collect::<VecDeque<_>>();
+        let mut commands = mem::take(&mut world.resource_mut::<WaitingCmds>().commands);
+        commands.extend(world.resource_mut::<Events<RunCmd>>().drain());
 
         let mut changed_cvars = Vec::new();
+        let mut notified_cvars = Vec::<CName>::new();
+        let mut cvars_set = false;
+        let mut expansions = 0;
+
+        loop {
+            let mut waiting = world.resource_mut::<WaitingCmds>();
+            if mem::take(&mut waiting.wait) {
+                waiting.commands = commands;
+                break;
+            }
 
-        while let Some(RunCmd(CmdName { name, trigger }, args)) = commands.pop_front() {
-            let mut name = Cow::from(name);
-            loop {
-                let (output, output_ty) = match world.resource_mut::<Registry>().get_mut(&*name) {
-                    Some(CommandImpl { kind, .. }) => {
-                        match (trigger, kind) {
-                            (None, CmdKind::Cvar { cvar, on_set }) => match args.split_first() {
-                                None => (
-                                    Cow::from(format!("\"{}\" is \"{}\"", name, cvar.value())),
-                                    OutputType::Console,
-                                ),
-                                Some((new_value, [])) => {
-                                    let new_value =
-                                        Value::from_str(new_value).unwrap_or_else(|_| {
-                                            Value::String(new_value.clone().into())
-                                        });
-
-                                    if cvar.value() != &new_value {
-                                        if let Some(on_set) = on_set {
-                                            changed_cvars
-                                                .push((EqHack(on_set.clone()), new_value.clone()));
-                                        }
+            let Some(RunCmd(CmdName { name, trigger }, args)) = commands.pop_front() else {
+                break;
+            };
 
-                                        cvar.value = Some(new_value);
-                                    }
+            // `+name` and `-name` aliases take precedence over an action or alias called `name`
+            if let Some(trigger) = trigger {
+                let registry = world.resource::<Registry>();
+                let full_name = format!("{}{}", trigger, name);
+                if let Some(CommandImpl {
+                    kind: CmdKind::Alias(text),
+                    ..
+                }) = registry.get(&full_name)
+                {
+                    let text = text.clone();
+                    if let Err(e) = expand_alias(&mut commands, &mut expansions, &text, None, &args)
+                    {
+                        world
+                            .resource_mut::<ConsoleOutput>()
+                            .println(e.as_bytes(), timestamp);
+                    }
+                    continue;
+                }
+            }
 
-                                    break;
-                                }
-                                Some(_) => (
-                                    Cow::from("Too many arguments, expected 1"),
-                                    OutputType::Console,
-                                ),
-                            },
-                            (Some(_), CmdKind::Cvar { .. }) => (
-                                Cow::from(format!("{} is a cvar", name)),
+            let (output, output_ty) = match world.resource_mut::<Registry>().get_mut(&*name) {
+                Some(CommandImpl { kind, .. }) => {
+                    match (trigger, kind) {
+                        (None, CmdKind::Cvar { cvar, on_set }) => match args.split_first() {
+                            None => (
+                                Cow::from(format!("\"{}\" is \"{}\"", name, cvar.value())),
                                 OutputType::Console,
                             ),
-                            // Currently this allows action aliases - do we want that?
-                            (_, CmdKind::Alias(alias)) => {
-                                name = alias.clone();
-                                continue;
-                            }
-                            (None, CmdKind::Builtin(cmd)) => {
-                                let args = args.clone();
-                                let cmd = *cmd;
-
-                                match world.run_system_with_input(cmd, args) {
-                                    Err(_) => {
-                                        error!("Command handler was registered in console but not in world");
-                                        break;
+                            Some((new_value, [])) => {
+                                let new_value = Value::from_str(new_value)
+                                    .unwrap_or_else(|_| Value::String(new_value.clone().into()));
+
+                                if cvar.value() != &new_value {
+                                    if let Some(on_set) = on_set {
+                                        changed_cvars
+                                            .push((EqHack(on_set.clone()), new_value.clone()));
                                     }
 
-                                    Ok(ExecResult {
-                                        extra_commands,
-                                        output,
-                                        output_ty,
-                                    }) => {
-                                        for command in extra_commands.rev() {
-                                            commands.push_front(command);
-                                        }
-
-                                        (output, output_ty)
+                                    if cvar.notify {
+                                        notified_cvars.push(name.clone().into_owned().into());
                                     }
+
+                                    cvar.value = Some(new_value);
+                                    cvars_set = true;
                                 }
+
+                                continue;
                             }
-                            (Some(_), CmdKind::Builtin(_)) => (
-                                Cow::from(format!(
-                                    "{} is a command, and cannot be invoked with +/-",
-                                    name
-                                )),
+                            Some(_) => (
+                                Cow::from("Too many arguments, expected 1"),
                                 OutputType::Console,
                             ),
-                            (Some(trigger), CmdKind::Action { system, state }) => {
-                                if *state == trigger {
-                                    break;
+                        },
+                        (Some(_), CmdKind::Cvar { .. }) => (
+                            Cow::from(format!("{} is a cvar", name)),
+                            OutputType::Console,
+                        ),
+                        (_, CmdKind::Alias(text)) => {
+                            match expand_alias(&mut commands, &mut expansions, text, trigger, &args)
+                            {
+                                Ok(()) => continue,
+                                Err(e) => (Cow::from(e), OutputType::Console),
+                            }
+                        }
+                        (None, CmdKind::Builtin(cmd)) => {
+                            let args = args.clone();
+                            let cmd = *cmd;
+
+                            match world.run_system_with_input(cmd, args) {
+                                Err(_) => {
+                                    error!("Command handler was registered in console but not in world");
+                                    continue;
                                 }
 
-                                let args = args.clone();
-                                *state = trigger;
+                                Ok(ExecResult {
+                                    extra_commands,
+                                    output,
+                                    output_ty,
+                                }) => {
+                                    for command in extra_commands.rev() {
+                                        commands.push_front(command);
+                                    }
 
-                                let Some(cmd) = system else {
-                                    // No invocation handler, just mark the pressed/released state
-                                    break;
-                                };
+                                    (output, output_ty)
+                                }
+                            }
+                        }
+                        (Some(_), CmdKind::Builtin(_)) => (
+                            Cow::from(format!(
+                                "{} is a command, and cannot be invoked with +/-",
+                                name
+                            )),
+                            OutputType::Console,
+                        ),
+                        (Some(trigger), CmdKind::Action { system, state }) => {
+                            if *state == trigger {
+                                continue;
+                            }
 
-                                let cmd = *cmd;
+                            let args = args.clone();
+                            *state = trigger;
 
-                                match world.run_system_with_input(cmd, (trigger, args)) {
-                                    Err(_) => {
-                                        error!("Command handler was registered in console but not in world");
-                                        break;
-                                    }
+                            let Some(cmd) = system else {
+                                // No invocation handler, just mark the pressed/released state
+                                continue;
+                            };
+
+                            let cmd = *cmd;
 
-                                    Ok(()) => break,
+                            match world.run_system_with_input(cmd, (trigger, args)) {
+                                Err(_) => {
+                                    error!("Command handler was registered in console but not in world");
+                                    continue;
                                 }
+
+                                Ok(()) => continue,
                             }
-                            (None, CmdKind::Action { .. }) => (
-                                Cow::from(format!(
-                                    "{} is an action, and must be invoked with +/-",
-                                    name
-                                )),
-                                OutputType::Console,
-                            ),
                         }
+                        (None, CmdKind::Action { .. }) => (
+                            Cow::from(format!(
+                                "{} is an action, and must be invoked with +/-",
+                                name
+                            )),
+                            OutputType::Console,
+                        ),
                     }
-                    None => (
-                        Cow::from(format!("Unrecognized command \"{}\"", &*name)),
-                        OutputType::Console,
-                    ),
-                };
+                }
+                None => (
+                    Cow::from(format!("Unrecognized command \"{}\"", &*name)),
+                    OutputType::Console,
+                ),
+            };
 
-                if !output.is_empty() {
-                    match output_ty {
-                        OutputType::Console => world
-                            .resource_mut::<ConsoleOutput>()
-                            .println(output.as_bytes(), timestamp),
-                        OutputType::Alert => world
-                            .resource_mut::<ConsoleOutput>()
-                            .println_alert(output.as_bytes(), timestamp),
-                    }
+            if !output.is_empty() {
+                match output_ty {
+                    OutputType::Console => world
+                        .resource_mut::<ConsoleOutput>()
+                        .println(output.as_bytes(), timestamp),
+                    OutputType::Alert => world
+                        .resource_mut::<ConsoleOutput>()
+                        .println_alert(output.as_bytes(), timestamp),
                 }
+            }
+        }
 
-                break;
+        let mut registry = world.resource_mut::<Registry>();
+        registry.changed_cvars.extend(changed_cvars);
+        registry.notified_cvars.extend(notified_cvars);
+        if cvars_set {
+            registry.cvar_generation += 1;
+        }
+    }
+
+    /// How many aliases can be expanded while running one frame's commands, to stop an alias
+    /// which runs itself from hanging the game.
+    const MAX_ALIAS_EXPANSIONS: usize = 1024;
+
+    /// Queue the commands that an alias runs in front of any that came after it.
+    ///
+    /// An alias invoked as `+name` runs its commands as written, and invoked as `-name` releases
+    /// only its leading `+` command, the same as a key bound to the commands would. This lets
+    /// `alias fwd "+forward; impulse 2"` be bound like `+forward` itself. Any arguments are passed
+    /// on to the last command.
+    fn expand_alias(
+        commands: &mut VecDeque<RunCmd<'static>>,
+        expansions: &mut usize,
+        text: &str,
+        trigger: Option<Trigger>,
+        args: &[String],
+    ) -> Result<(), String> {
+        *expansions += 1;
+        if *expansions > MAX_ALIAS_EXPANSIONS {
+            commands.clear();
+            return Err(format!(
+                "More than {} aliases expanded at once, is there a loop?",
+                MAX_ALIAS_EXPANSIONS
+            ));
+        }
+
+        let mut expanded = RunCmd::parse_many(text)
+            .map_err(|e| format!("Couldn't parse alias: {}", e))?
+            .into_iter()
+            .map(RunCmd::into_owned)
+            .collect::<Vec<_>>();
+
+        if trigger == Some(Trigger::Negative) {
+            expanded.truncate(1);
+            expanded.retain_mut(|RunCmd(name, _)| {
+                let pressed = name.trigger == Some(Trigger::Positive);
+                name.trigger = Some(Trigger::Negative);
+                pressed
+            });
+        }
+
+        if let Some(RunCmd(_, last_args)) = expanded.last_mut() {
+            if !args.is_empty() {
+                *last_args = last_args.iter().chain(args).cloned().collect();
             }
         }
 
-        world
-            .resource_mut::<Registry>()
-            .changed_cvars
-            .extend(changed_cvars);
+        for cmd in expanded.into_iter().rev() {
+            commands.push_front(cmd);
+        }
+
+        Ok(())
     }
 
-    pub fn update_cvars(mut commands: Commands, mut registry: ResMut<Registry>) {
+    pub fn update_cvars(
+        mut commands: Commands,
+        mut registry: ResMut<Registry>,
+        mut notify: EventWriter<CvarNotify>,
+    ) {
         for (sys, val) in registry.changed_cvars.drain() {
             commands.run_system_with_input(sys.0, val);
         }
+
+        let notified = mem::take(&mut registry.notified_cvars);
+        notify.send_batch(notified.into_iter().filter_map(|name| {
+            let value = registry.get_cvar(&name)?.value_text();
+            Some(CvarNotify { name, value })
+        }));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn render_output(lines: usize) -> RenderConsoleOutput {
+        let mut out = ConsoleOutput::new();
+        for i in 0..lines {
+            out.println(format!("line {}", i), Duration::zero());
+        }
+
+        let mut render_out = RenderConsoleOutput::default();
+        render_out.text_chunks.extend(out.drain_unwritten());
+        render_out
+    }
+
+    #[test]
+    fn test_scroll_by() {
+        let mut out = render_output(5);
+        assert_eq!(out.line_count(), 5);
+
+        out.scroll_by(2);
+        assert_eq!(out.scroll(), 2);
+
+        // the oldest line stays on screen
+        out.scroll_by(RenderConsoleOutput::PAGE_LINES);
+        assert_eq!(out.scroll(), 4);
+
+        out.scroll_by(-3);
+        assert_eq!(out.scroll(), 1);
+
+        out.scroll_by(-RenderConsoleOutput::PAGE_LINES);
+        assert_eq!(out.scroll(), 0);
+
+        let mut empty = RenderConsoleOutput::default();
+        empty.scroll_by(RenderConsoleOutput::WHEEL_LINES);
+        assert_eq!(empty.scroll(), 0);
+    }
+
+    #[test]
+    fn test_scrolled_len() {
+        let text = b"one\ntwo\nthree\n";
+        assert_eq!(scrolled_len(text, 1), Some(8));
+        assert_eq!(&text[..scrolled_len(text, 2).unwrap()], b"one\n");
+        assert_eq!(scrolled_len(text, 3), None);
+        assert_eq!(scrolled_len(text, 0), None);
+        assert_eq!(scrolled_len(b"", 1), None);
     }
 }
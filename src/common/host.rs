@@ -18,12 +18,28 @@
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
 // SOFTWARE.
 
+#[cfg(not(target_arch = "wasm32"))]
+use std::{thread, time::Duration as StdDuration};
+
+use bevy::ecs::event::Event as BevyEvent;
+#[cfg(not(target_arch = "wasm32"))]
+use bevy::{
+    ecs::{
+        query::With,
+        system::{Local, Query, Res},
+    },
+    utils::Instant,
+    window::{PresentMode, PrimaryWindow, Window},
+};
 use chrono::{DateTime, Duration, Utc};
 use winit::{
     event::{Event, WindowEvent},
     event_loop::{ControlFlow, EventLoopWindowTarget},
 };
 
+#[cfg(not(target_arch = "wasm32"))]
+use crate::common::console::Registry;
+
 pub trait Program: Sized {
     fn handle_event<T>(
         &mut self,
@@ -58,12 +74,99 @@ pub mod cvars {
     pub fn register_cvars(app: &mut App) {
         app.cvar(
             "host_maxfps",
-            Cvar::new("72"),
-            "sets the maximum desired frames per second",
+            Cvar::new("72").archive(),
+            "the maximum frames per second without vid_vsync, or 0 for no limit",
         );
     }
 }
 
+/// Spaces frames evenly at a target rate.
+///
+/// The wait sleeps until shortly before the deadline and spins for the remainder, since a sleep
+/// can overshoot by a whole scheduler tick.
+#[cfg(not(target_arch = "wasm32"))]
+#[derive(Default)]
+pub struct FramePacer {
+    next_frame: Option<Instant>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl FramePacer {
+    /// How long before the deadline to stop sleeping and start spinning.
+    const SPIN_TIME: StdDuration = StdDuration::from_millis(2);
+
+    /// Returns when the frame which finished at `now` should be allowed to end.
+    fn schedule(&mut self, now: Instant, frame_time: StdDuration) -> Instant {
+        let deadline = match self.next_frame {
+            // if we've fallen more than a frame behind, start over rather than rushing the
+            // following frames to catch up
+            Some(next) if now <= next + frame_time => next,
+            _ => now,
+        };
+
+        self.next_frame = Some(deadline + frame_time);
+        deadline
+    }
+
+    /// Blocks until it's time for the next frame.
+    pub fn wait(&mut self, frame_time: StdDuration) {
+        let deadline = self.schedule(Instant::now(), frame_time);
+
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+
+            let remaining = deadline - now;
+            if remaining > Self::SPIN_TIME {
+                thread::sleep(remaining - Self::SPIN_TIME);
+            } else {
+                std::hint::spin_loop();
+            }
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.next_frame = None;
+    }
+}
+
+/// Limit the client's frame rate to `host_maxfps`.
+///
+/// This only paces the client. The server runs on its own fixed tick. With vsync enabled,
+/// presenting already waits for the display, so the frames aren't held back a second time. The
+/// browser paces frames itself, so this isn't used on the web.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn limit_frame_rate(
+    registry: Res<Registry>,
+    windows: Query<&Window, With<PrimaryWindow>>,
+    mut pacer: Local<FramePacer>,
+) {
+    let vsync = windows.get_single().is_ok_and(|window| {
+        matches!(
+            window.present_mode,
+            PresentMode::AutoVsync | PresentMode::Fifo | PresentMode::FifoRelaxed
+        )
+    });
+    let max_fps = registry.read_cvar::<f32>("host_maxfps").unwrap_or(0.0);
+    if vsync || max_fps <= 0.0 {
+        pacer.reset();
+        return;
+    }
+
+    pacer.wait(StdDuration::from_secs_f32(
+        1.0 / max_fps.clamp(10.0, 1000.0),
+    ));
+}
+
+/// A fatal error in the running game, like `Host_Error` in the original engine.
+///
+/// Instead of bringing down the whole program, the server and the connection to it are shut
+/// down, the message is printed to the console and the player is returned to the console.
+#[derive(BevyEvent, Clone, Debug)]
+pub struct HostError(pub String);
+
 impl<P> Host<P>
 where
     P: Program,
@@ -127,3 +230,30 @@ where
         self.prev_frame_time.signed_duration_since(self.init_time)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_frame_pacer_schedule() {
+        let frame_time = StdDuration::from_millis(10);
+        let start = Instant::now();
+        let mut pacer = FramePacer::default();
+
+        // the first frame isn't held back
+        assert_eq!(pacer.schedule(start, frame_time), start);
+
+        // a quick frame waits for the rest of its slot
+        let deadline = pacer.schedule(start + StdDuration::from_millis(3), frame_time);
+        assert_eq!(deadline, start + frame_time);
+
+        // a frame that ran long isn't held back, and the cadence continues from its slot
+        let deadline = pacer.schedule(start + StdDuration::from_millis(25), frame_time);
+        assert_eq!(deadline, start + 2 * frame_time);
+
+        // a frame that ran more than a whole frame late starts a new cadence
+        let late = start + StdDuration::from_millis(100);
+        assert_eq!(pacer.schedule(late, frame_time), late);
+    }
+}
@@ -0,0 +1,978 @@
+//! Inter-Quake Models (`.iqm`).
+//!
+//! IQM models are skeletal: every vertex is attached to up to four joints with a weight for each,
+//! and animations are stored as joint poses rather than vertex positions. The poses of every
+//! frame are turned into skinning matrices when the model is loaded, each taking a vertex from the
+//! bind pose to where its joint has moved it, so that the renderer only has to blend matrices.
+//!
+//! A model's animations are used as its frames, so an entity's `frame` picks an animation that
+//! plays at its own framerate, the same way as an MDL frame group. A model without animations is
+//! given one for each of its poses, or a single frame with the bind pose if it has none.
+
+use std::{
+    io::{self, BufReader, Read, Seek, SeekFrom},
+    ops::Range,
+};
+
+use crate::common::{
+    md3::{self, Md3Skin},
+    model::ModelFlags,
+    vfs::Vfs,
+};
+
+use byteorder::{LittleEndian, ReadBytesExt};
+use cgmath::{InnerSpace as _, Matrix4, Quaternion, SquareMatrix as _, Vector3};
+use chrono::Duration;
+use thiserror::Error;
+
+pub const MAGIC: &[u8; 16] = b"INTERQUAKEMODEL\0";
+pub const VERSION: u32 = 2;
+
+/// The most joints that a model can have, so that a frame's matrices fit in the renderer's limits.
+pub const MAX_JOINTS: usize = 256;
+
+/// The most frames that a model can have. A frame has a matrix for each joint, so this keeps a
+/// model with many joints from taking up too much memory, even if its frames take up no room in
+/// the file.
+pub const MAX_FRAMES: usize = 4096;
+
+const ANIM_LOOP: u32 = 1;
+
+// vertex array types
+const POSITION: u32 = 0;
+const TEXCOORD: u32 = 1;
+const NORMAL: u32 = 2;
+const BLEND_INDEXES: u32 = 4;
+const BLEND_WEIGHTS: u32 = 5;
+
+// vertex array formats
+const FORMAT_BYTE: u32 = 0;
+const FORMAT_UBYTE: u32 = 1;
+const FORMAT_SHORT: u32 = 2;
+const FORMAT_USHORT: u32 = 3;
+const FORMAT_INT: u32 = 4;
+const FORMAT_UINT: u32 = 5;
+const FORMAT_FLOAT: u32 = 7;
+const FORMAT_DOUBLE: u32 = 8;
+
+#[derive(Error, Debug)]
+pub enum IqmFileError {
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Invalid magic number")]
+    InvalidMagicNumber,
+    #[error("Unrecognized version: {0}")]
+    UnrecognizedVersion(u32),
+    #[error("Missing vertex positions")]
+    NoPositions,
+    #[error("No triangles")]
+    NoTriangles,
+    #[error("Unsupported format {1} for vertex array type {0}")]
+    UnsupportedFormat(u32, u32),
+    #[error("Vertex index {0} out of range")]
+    InvalidVertexIndex(u32),
+    #[error("Too many joints: {0}, the most allowed is {}", MAX_JOINTS)]
+    TooManyJoints(usize),
+    #[error("Too many frames: {0}, the most allowed is {}", MAX_FRAMES)]
+    TooManyFrames(usize),
+    #[error("Joint index {0} out of range")]
+    InvalidJointIndex(f64),
+    #[error("Joint {0} has parent {1}, which doesn't come before it")]
+    InvalidParent(usize, i32),
+    #[error("Poses and joints don't match: {0} poses for {1} joints")]
+    PoseCount(usize, usize),
+    #[error("The {0} run past the end of the file")]
+    Truncated(&'static str),
+}
+
+#[derive(Clone, Debug)]
+pub struct IqmVertex {
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+    pub texcoord: [f32; 2],
+    pub joints: [u8; 4],
+
+    /// The weight of each joint, out of 255.
+    pub weights: [u8; 4],
+}
+
+/// A part of the model with a material of its own.
+#[derive(Clone, Debug)]
+pub struct IqmMesh {
+    name: String,
+    material: String,
+    skin: Option<Md3Skin>,
+    triangles: Range<usize>,
+}
+
+impl IqmMesh {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The name of the mesh's material, usually the path of an image.
+    pub fn material(&self) -> &str {
+        &self.material
+    }
+
+    /// The mesh's skin, or `None` if it wasn't found or [`IqmModel::load_skins`] hasn't been
+    /// called.
+    pub fn skin(&self) -> Option<&Md3Skin> {
+        self.skin.as_ref()
+    }
+
+    /// The range of the model's triangles that belong to this mesh.
+    pub fn triangles(&self) -> Range<usize> {
+        self.triangles.clone()
+    }
+}
+
+/// A run of poses played one after another.
+#[derive(Clone, Debug)]
+pub struct IqmAnim {
+    name: String,
+    first_pose: usize,
+    pose_count: usize,
+    framerate: f32,
+    looping: bool,
+}
+
+impl IqmAnim {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The pose shown `time` after the animation started.
+    pub fn pose_at(&self, time: Duration) -> usize {
+        let elapsed = (time.num_milliseconds() as f32 / 1000.0 * self.framerate).max(0.0) as usize;
+        let offset = if self.looping {
+            elapsed % self.pose_count
+        } else {
+            elapsed.min(self.pose_count - 1)
+        };
+
+        self.first_pose + offset
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct IqmModel {
+    vertices: Vec<IqmVertex>,
+    triangles: Vec<[u32; 3]>,
+    meshes: Vec<IqmMesh>,
+    joint_count: usize,
+
+    // the skinning matrices of each pose, a joint at a time
+    poses: Vec<Matrix4<f32>>,
+    anims: Vec<IqmAnim>,
+    min: Vector3<f32>,
+    max: Vector3<f32>,
+}
+
+impl IqmModel {
+    pub fn vertices(&self) -> &[IqmVertex] {
+        &self.vertices
+    }
+
+    pub fn triangles(&self) -> &[[u32; 3]] {
+        &self.triangles
+    }
+
+    pub fn meshes(&self) -> &[IqmMesh] {
+        &self.meshes
+    }
+
+    /// The number of joints in each pose. A model without joints has a single one that leaves
+    /// vertices where they are.
+    pub fn joint_count(&self) -> usize {
+        self.joint_count
+    }
+
+    pub fn pose_count(&self) -> usize {
+        self.poses.len() / self.joint_count
+    }
+
+    /// The skinning matrices of every pose, one after another.
+    pub fn poses(&self) -> &[Matrix4<f32>] {
+        &self.poses
+    }
+
+    /// The number of frames an entity can pick from.
+    pub fn frame_count(&self) -> usize {
+        self.anims.len()
+    }
+
+    /// The pose shown for frame `frame_id` at `time`, or `None` if there's no such frame.
+    pub fn frame_pose(&self, frame_id: usize, time: Duration) -> Option<usize> {
+        self.anims.get(frame_id).map(|anim| anim.pose_at(time))
+    }
+
+    /// The model's animations, one for each frame.
+    pub fn anims(&self) -> &[IqmAnim] {
+        &self.anims
+    }
+
+    pub fn min(&self) -> Vector3<f32> {
+        self.min
+    }
+
+    pub fn max(&self) -> Vector3<f32> {
+        self.max
+    }
+
+    pub fn flags(&self) -> ModelFlags {
+        ModelFlags::empty()
+    }
+
+    /// Load the images named by the meshes' materials.
+    pub fn load_skins(&mut self, vfs: &Vfs, model_name: &str) {
+        for mesh in &mut self.meshes {
+            mesh.skin = md3::load_skin(vfs, model_name, &mesh.material);
+        }
+    }
+}
+
+struct Header {
+    text: (u32, u32),
+    meshes: (u32, u32),
+    vertex_arrays: (u32, u32),
+    vertex_count: u32,
+    triangles: (u32, u32),
+    joints: (u32, u32),
+    poses: (u32, u32),
+    anims: (u32, u32),
+    frame_count: u32,
+    frame_channel_count: u32,
+    frames_offset: u32,
+    bounds_offset: u32,
+}
+
+fn read_header<R>(reader: &mut R) -> Result<Header, IqmFileError>
+where
+    R: Read,
+{
+    // struct IqmHeader {
+    //     magic: [u8; 16],
+    //     version: u32,
+    //     file_size: u32,
+    //     flags: u32,
+    //     text_count: u32, text_offset: u32,
+    //     mesh_count: u32, meshes_offset: u32,
+    //     vertex_array_count: u32, vertex_count: u32, vertex_arrays_offset: u32,
+    //     triangle_count: u32, triangles_offset: u32, adjacency_offset: u32,
+    //     joint_count: u32, joints_offset: u32,
+    //     pose_count: u32, poses_offset: u32,
+    //     anim_count: u32, anims_offset: u32,
+    //     frame_count: u32, frame_channel_count: u32, frames_offset: u32, bounds_offset: u32,
+    //     comment_count: u32, comment_offset: u32,
+    //     extension_count: u32, extensions_offset: u32,
+    // }
+
+    let mut magic = [0; 16];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        Err(IqmFileError::InvalidMagicNumber)?;
+    }
+
+    let version = reader.read_u32::<LittleEndian>()?;
+    if version != VERSION {
+        Err(IqmFileError::UnrecognizedVersion(version))?;
+    }
+
+    let mut fields = [0; 26];
+    reader.read_u32_into::<LittleEndian>(&mut fields)?;
+    let pair = |i: usize| (fields[i], fields[i + 1]);
+
+    Ok(Header {
+        text: pair(2),
+        meshes: pair(4),
+        vertex_arrays: (fields[6], fields[8]),
+        vertex_count: fields[7],
+        triangles: pair(9),
+        joints: pair(12),
+        poses: pair(14),
+        anims: pair(16),
+        frame_count: fields[18],
+        frame_channel_count: fields[19],
+        frames_offset: fields[20],
+        bounds_offset: fields[21],
+    })
+}
+
+/// Check that `count` records of `size` bytes starting at `offset` are inside the file, before
+/// making room for them.
+fn check_extent(
+    what: &'static str,
+    offset: u32,
+    count: usize,
+    size: usize,
+    file_len: u64,
+) -> Result<(), IqmFileError> {
+    let end = (count as u64)
+        .checked_mul(size as u64)
+        .and_then(|len| len.checked_add(offset as u64));
+    match end {
+        Some(end) if end <= file_len => Ok(()),
+        _ => Err(IqmFileError::Truncated(what)),
+    }
+}
+
+/// The size in bytes of a value in `format`.
+fn format_size(ty: u32, format: u32) -> Result<usize, IqmFileError> {
+    Ok(match format {
+        FORMAT_BYTE | FORMAT_UBYTE => 1,
+        FORMAT_SHORT | FORMAT_USHORT => 2,
+        FORMAT_INT | FORMAT_UINT | FORMAT_FLOAT => 4,
+        FORMAT_DOUBLE => 8,
+        _ => Err(IqmFileError::UnsupportedFormat(ty, format))?,
+    })
+}
+
+/// Read `count` values of an array in `format`, which doesn't have to be the format it's used as.
+fn read_values<R>(
+    reader: &mut R,
+    ty: u32,
+    format: u32,
+    count: usize,
+) -> Result<Vec<f64>, IqmFileError>
+where
+    R: Read,
+{
+    (0..count)
+        .map(|_| -> Result<f64, IqmFileError> {
+            Ok(match format {
+                FORMAT_BYTE => reader.read_i8()? as f64,
+                FORMAT_UBYTE => reader.read_u8()? as f64,
+                FORMAT_SHORT => reader.read_i16::<LittleEndian>()? as f64,
+                FORMAT_USHORT => reader.read_u16::<LittleEndian>()? as f64,
+                FORMAT_INT => reader.read_i32::<LittleEndian>()? as f64,
+                FORMAT_UINT => reader.read_u32::<LittleEndian>()? as f64,
+                FORMAT_FLOAT => reader.read_f32::<LittleEndian>()? as f64,
+                FORMAT_DOUBLE => reader.read_f64::<LittleEndian>()?,
+                _ => Err(IqmFileError::UnsupportedFormat(ty, format))?,
+            })
+        })
+        .collect()
+}
+
+fn text_at(text: &[u8], offset: u32) -> String {
+    let text = text.get(offset as usize..).unwrap_or_default();
+    let end = text.iter().position(|&b| b == 0).unwrap_or(text.len());
+    String::from_utf8_lossy(&text[..end]).into_owned()
+}
+
+/// The transform of a joint relative to its parent.
+fn joint_transform(translate: [f32; 3], rotate: [f32; 4], scale: [f32; 3]) -> Matrix4<f32> {
+    let [x, y, z, w] = rotate;
+    let rotation = Quaternion::new(w, x, y, z).normalize();
+
+    Matrix4::from_translation(translate.into())
+        * Matrix4::from(rotation)
+        * Matrix4::from_nonuniform_scale(scale[0], scale[1], scale[2])
+}
+
+pub fn load<R>(data: R) -> Result<IqmModel, IqmFileError>
+where
+    R: Read + Seek,
+{
+    let mut reader = BufReader::new(data);
+    let file_len = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(0))?;
+    let header = read_header(&mut reader)?;
+
+    check_extent("text", header.text.1, header.text.0 as usize, 1, file_len)?;
+    let mut text = vec![0; header.text.0 as usize];
+    reader.seek(SeekFrom::Start(header.text.1 as u64))?;
+    reader.read_exact(&mut text)?;
+
+    let joint_count = header.joints.0 as usize;
+    if joint_count > MAX_JOINTS {
+        Err(IqmFileError::TooManyJoints(joint_count))?;
+    }
+
+    // every vertex has at least a byte for each coordinate of its position somewhere in the file
+    let vertex_count = header.vertex_count as usize;
+    check_extent("vertices", 0, vertex_count, 3, file_len)?;
+    let mut vertices = vec![
+        IqmVertex {
+            position: [0.0; 3],
+            normal: [0.0, 0.0, 1.0],
+            texcoord: [0.0; 2],
+            joints: [0; 4],
+            weights: [255, 0, 0, 0],
+        };
+        vertex_count
+    ];
+
+    let (vertex_array_count, vertex_arrays_offset) = header.vertex_arrays;
+    check_extent(
+        "vertex arrays",
+        vertex_arrays_offset,
+        vertex_array_count as usize,
+        20,
+        file_len,
+    )?;
+    let mut has_positions = false;
+    for i in 0..vertex_array_count as u64 {
+        // struct IqmVertexArray {
+        //     ty: u32,
+        //     flags: u32,
+        //     format: u32,
+        //     size: u32,
+        //     offset: u32,
+        // }
+
+        reader.seek(SeekFrom::Start(vertex_arrays_offset as u64 + i * 20))?;
+        let ty = reader.read_u32::<LittleEndian>()?;
+        let _flags = reader.read_u32::<LittleEndian>()?;
+        let format = reader.read_u32::<LittleEndian>()?;
+        let size = reader.read_u32::<LittleEndian>()? as usize;
+        let offset = reader.read_u32::<LittleEndian>()?;
+
+        let components = match ty {
+            POSITION | NORMAL => 3,
+            TEXCOORD => 2,
+            BLEND_INDEXES | BLEND_WEIGHTS => 4,
+            // tangents, colors and custom arrays aren't used
+            _ => continue,
+        };
+        if size < components {
+            continue;
+        }
+
+        let value_count = vertex_count
+            .checked_mul(size)
+            .ok_or(IqmFileError::Truncated("vertex arrays"))?;
+        let value_size = format_size(ty, format)?;
+        check_extent("vertex arrays", offset, value_count, value_size, file_len)?;
+        reader.seek(SeekFrom::Start(offset as u64))?;
+        let values = read_values(&mut reader, ty, format, value_count)?;
+        for (vertex, values) in vertices.iter_mut().zip(values.chunks(size)) {
+            match ty {
+                POSITION => {
+                    vertex.position = [values[0] as f32, values[1] as f32, values[2] as f32]
+                }
+                NORMAL => vertex.normal = [values[0] as f32, values[1] as f32, values[2] as f32],
+                TEXCOORD => vertex.texcoord = [values[0] as f32, values[1] as f32],
+                BLEND_INDEXES => {
+                    // a model without joints is drawn with a single identity matrix
+                    for (joint, &value) in vertex.joints.iter_mut().zip(values) {
+                        if !(0.0..joint_count.max(1) as f64).contains(&value) {
+                            Err(IqmFileError::InvalidJointIndex(value))?;
+                        }
+                        *joint = value as u8;
+                    }
+                }
+                BLEND_WEIGHTS => {
+                    // weights stored as integers are already out of 255
+                    let scale = if format == FORMAT_FLOAT || format == FORMAT_DOUBLE {
+                        255.0
+                    } else {
+                        1.0
+                    };
+                    for (weight, value) in vertex.weights.iter_mut().zip(values) {
+                        *weight = (value * scale).round().clamp(0.0, 255.0) as u8;
+                    }
+                }
+                _ => unreachable!(),
+            }
+        }
+
+        has_positions |= ty == POSITION;
+    }
+
+    if !has_positions {
+        Err(IqmFileError::NoPositions)?;
+    }
+
+    let (triangle_count, triangles_offset) = header.triangles;
+    check_extent(
+        "triangles",
+        triangles_offset,
+        triangle_count as usize,
+        12,
+        file_len,
+    )?;
+    reader.seek(SeekFrom::Start(triangles_offset as u64))?;
+    let mut triangles = Vec::with_capacity(header.triangles.0 as usize);
+    for _ in 0..header.triangles.0 {
+        let mut triangle = [0; 3];
+        reader.read_u32_into::<LittleEndian>(&mut triangle)?;
+        if let Some(&i) = triangle.iter().find(|&&i| i >= header.vertex_count) {
+            Err(IqmFileError::InvalidVertexIndex(i))?;
+        }
+        triangles.push(triangle);
+    }
+    // the renderer can't bind an empty vertex or index buffer
+    if triangles.is_empty() {
+        Err(IqmFileError::NoTriangles)?;
+    }
+
+    let (mesh_count, meshes_offset) = header.meshes;
+    check_extent("meshes", meshes_offset, mesh_count as usize, 24, file_len)?;
+    reader.seek(SeekFrom::Start(meshes_offset as u64))?;
+    let mut meshes = Vec::with_capacity(header.meshes.0 as usize);
+    for _ in 0..header.meshes.0 {
+        // struct IqmMesh {
+        //     name: u32,
+        //     material: u32,
+        //     first_vertex: u32,
+        //     vertex_count: u32,
+        //     first_triangle: u32,
+        //     triangle_count: u32,
+        // }
+
+        let mut fields = [0; 6];
+        reader.read_u32_into::<LittleEndian>(&mut fields)?;
+        let first = (fields[4] as usize).min(triangles.len());
+        let end = (first + fields[5] as usize).min(triangles.len());
+        meshes.push(IqmMesh {
+            name: text_at(&text, fields[0]),
+            material: text_at(&text, fields[1]),
+            skin: None,
+            triangles: first..end,
+        });
+    }
+
+    // the bind pose of each joint relative to the model, inverted
+    check_extent("joints", header.joints.1, joint_count, 48, file_len)?;
+    reader.seek(SeekFrom::Start(header.joints.1 as u64))?;
+    let mut parents = Vec::with_capacity(joint_count);
+    let mut inverse_binds: Vec<Matrix4<f32>> = Vec::with_capacity(joint_count);
+    let mut binds: Vec<Matrix4<f32>> = Vec::with_capacity(joint_count);
+    for joint_id in 0..joint_count {
+        // struct IqmJoint {
+        //     name: u32,
+        //     parent: i32,
+        //     translate: [f32; 3],
+        //     rotate: [f32; 4],
+        //     scale: [f32; 3],
+        // }
+
+        let _name = reader.read_u32::<LittleEndian>()?;
+        let parent = reader.read_i32::<LittleEndian>()?;
+        let mut channels = [0.0; 10];
+        reader.read_f32_into::<LittleEndian>(&mut channels)?;
+
+        let local = joint_transform(
+            [channels[0], channels[1], channels[2]],
+            [channels[3], channels[4], channels[5], channels[6]],
+            [channels[7], channels[8], channels[9]],
+        );
+        let bind = match parent {
+            -1 => local,
+            p if p >= 0 && (p as usize) < joint_id => binds[p as usize] * local,
+            p => Err(IqmFileError::InvalidParent(joint_id, p))?,
+        };
+
+        parents.push(parent);
+        inverse_binds.push(bind.invert().unwrap_or_else(Matrix4::identity));
+        binds.push(bind);
+    }
+
+    let pose_joint_count = header.poses.0 as usize;
+    if header.frame_count > 0 && pose_joint_count != joint_count {
+        Err(IqmFileError::PoseCount(pose_joint_count, joint_count))?;
+    }
+
+    // struct IqmPose {
+    //     parent: i32,
+    //     mask: u32,
+    //     channel_offsets: [f32; 10],
+    //     channel_scales: [f32; 10],
+    // }
+    check_extent("poses", header.poses.1, pose_joint_count, 88, file_len)?;
+    reader.seek(SeekFrom::Start(header.poses.1 as u64))?;
+    let mut pose_channels = Vec::with_capacity(pose_joint_count);
+    for _ in 0..pose_joint_count {
+        let _parent = reader.read_i32::<LittleEndian>()?;
+        let mask = reader.read_u32::<LittleEndian>()?;
+        let mut offsets = [0.0; 10];
+        let mut scales = [0.0; 10];
+        reader.read_f32_into::<LittleEndian>(&mut offsets)?;
+        reader.read_f32_into::<LittleEndian>(&mut scales)?;
+        pose_channels.push((mask, offsets, scales));
+    }
+
+    let frame_value_count = (header.frame_count as usize)
+        .checked_mul(header.frame_channel_count as usize)
+        .ok_or(IqmFileError::Truncated("frames"))?;
+    check_extent(
+        "frames",
+        header.frames_offset,
+        frame_value_count,
+        2,
+        file_len,
+    )?;
+    reader.seek(SeekFrom::Start(header.frames_offset as u64))?;
+    let mut frame_data = vec![0; frame_value_count];
+    reader.read_u16_into::<LittleEndian>(&mut frame_data)?;
+
+    // frames without channels take up no room, so their count isn't bounded by the file's size
+    if header.frame_count as usize > MAX_FRAMES {
+        Err(IqmFileError::TooManyFrames(header.frame_count as usize))?;
+    }
+
+    let mut poses = Vec::new();
+    let mut frame_data = frame_data.into_iter();
+    for _ in 0..header.frame_count {
+        let mut posed: Vec<Matrix4<f32>> = Vec::with_capacity(joint_count);
+        for (joint_id, (mask, offsets, scales)) in pose_channels.iter().enumerate() {
+            let mut channels = *offsets;
+            for (c, channel) in channels.iter_mut().enumerate() {
+                if mask & (1 << c) != 0 {
+                    *channel += frame_data.next().unwrap_or(0) as f32 * scales[c];
+                }
+            }
+
+            let local = joint_transform(
+                [channels[0], channels[1], channels[2]],
+                [channels[3], channels[4], channels[5], channels[6]],
+                [channels[7], channels[8], channels[9]],
+            );
+            posed.push(match parents[joint_id] {
+                -1 => local,
+                p => posed[p as usize] * local,
+            });
+        }
+
+        poses.extend(
+            posed
+                .iter()
+                .zip(inverse_binds.iter())
+                .map(|(pose, inverse_bind)| pose * inverse_bind),
+        );
+    }
+
+    // a model that isn't animated is drawn in its bind pose
+    let joint_count = joint_count.max(1);
+    if poses.is_empty() {
+        poses = vec![Matrix4::identity(); joint_count];
+    }
+
+    check_extent(
+        "anims",
+        header.anims.1,
+        header.anims.0 as usize,
+        20,
+        file_len,
+    )?;
+    reader.seek(SeekFrom::Start(header.anims.1 as u64))?;
+    let mut anims = Vec::with_capacity(header.anims.0 as usize);
+    let pose_count = poses.len() / joint_count;
+    for _ in 0..header.anims.0 {
+        // struct IqmAnim {
+        //     name: u32,
+        //     first_frame: u32,
+        //     frame_count: u32,
+        //     framerate: f32,
+        //     flags: u32,
+        // }
+
+        let name = text_at(&text, reader.read_u32::<LittleEndian>()?);
+        let first_pose = (reader.read_u32::<LittleEndian>()? as usize).min(pose_count - 1);
+        let anim_pose_count =
+            (reader.read_u32::<LittleEndian>()? as usize).clamp(1, pose_count - first_pose);
+        let framerate = reader.read_f32::<LittleEndian>()?;
+        let flags = reader.read_u32::<LittleEndian>()?;
+        anims.push(IqmAnim {
+            name,
+            first_pose,
+            pose_count: anim_pose_count,
+            framerate,
+            looping: flags & ANIM_LOOP != 0,
+        });
+    }
+
+    // without animations, each pose is a frame of its own
+    if anims.is_empty() {
+        anims.extend((0..pose_count).map(|first_pose| IqmAnim {
+            name: String::new(),
+            first_pose,
+            pose_count: 1,
+            framerate: 0.0,
+            looping: false,
+        }));
+    }
+
+    // the bounds of the frames if the file has them, or the bind pose if not
+    let (min, max) = if header.bounds_offset != 0 && header.frame_count > 0 {
+        check_extent(
+            "bounds",
+            header.bounds_offset,
+            header.frame_count as usize,
+            32,
+            file_len,
+        )?;
+        reader.seek(SeekFrom::Start(header.bounds_offset as u64))?;
+        let mut min = Vector3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY);
+        let mut max = Vector3::new(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY);
+        for _ in 0..header.frame_count {
+            let mut bounds = [0.0; 8];
+            reader.read_f32_into::<LittleEndian>(&mut bounds)?;
+            for c in 0..3 {
+                min[c] = min[c].min(bounds[c]);
+                max[c] = max[c].max(bounds[c + 3]);
+            }
+        }
+        (min, max)
+    } else {
+        vertices.iter().fold(
+            (
+                Vector3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY),
+                Vector3::new(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY),
+            ),
+            |(mut min, mut max), v| {
+                for c in 0..3 {
+                    min[c] = min[c].min(v.position[c]);
+                    max[c] = max[c].max(v.position[c]);
+                }
+                (min, max)
+            },
+        )
+    };
+
+    Ok(IqmModel {
+        vertices,
+        triangles,
+        meshes,
+        joint_count,
+        poses,
+        anims,
+        min,
+        max,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use byteorder::WriteBytesExt;
+    use cgmath::{Deg, InnerSpace as _, Rotation3 as _, Transform as _};
+    use std::io::{Cursor, Write};
+
+    // a triangle attached to a joint that's moved up by 8 units in the first pose and turned a
+    // quarter turn about the vertical in the second
+    fn test_iqm() -> Vec<u8> {
+        let text = b"\0tri\0skin\0spin\0";
+        let header_size = 124;
+        let text_offset = header_size;
+        let meshes_offset = text_offset + text.len() as u32;
+        let vertex_arrays_offset = meshes_offset + 24;
+        let positions_offset = vertex_arrays_offset + 2 * 20;
+        let indexes_offset = positions_offset + 3 * 12;
+        let triangles_offset = indexes_offset + 3 * 4;
+        let joints_offset = triangles_offset + 12;
+        let poses_offset = joints_offset + 48;
+        let anims_offset = poses_offset + 88;
+        let frames_offset = anims_offset + 20;
+
+        let mut out = Vec::new();
+        out.write_all(MAGIC).unwrap();
+        for v in [
+            VERSION,
+            0,
+            0,
+            text.len() as u32,
+            text_offset,
+            1,
+            meshes_offset,
+            2,
+            3,
+            vertex_arrays_offset,
+            1,
+            triangles_offset,
+            0,
+            1,
+            joints_offset,
+            1,
+            poses_offset,
+            1,
+            anims_offset,
+            2,
+            4,
+            frames_offset,
+            0,
+            0,
+            0,
+            0,
+            0,
+        ] {
+            out.write_u32::<LittleEndian>(v).unwrap();
+        }
+        out.write_all(text).unwrap();
+
+        for v in [1, 5, 0, 3, 0, 1] {
+            out.write_u32::<LittleEndian>(v).unwrap();
+        }
+
+        for v in [
+            POSITION,
+            0,
+            FORMAT_FLOAT,
+            3,
+            positions_offset,
+            BLEND_INDEXES,
+            0,
+            FORMAT_UBYTE,
+            4,
+            indexes_offset,
+        ] {
+            out.write_u32::<LittleEndian>(v).unwrap();
+        }
+        for v in [1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0] {
+            out.write_f32::<LittleEndian>(v).unwrap();
+        }
+        out.write_all(&[0; 12]).unwrap();
+        for i in [0, 1, 2] {
+            out.write_u32::<LittleEndian>(i).unwrap();
+        }
+
+        // the joint's bind pose is 4 units out along x
+        out.write_u32::<LittleEndian>(0).unwrap();
+        out.write_i32::<LittleEndian>(-1).unwrap();
+        for v in [4.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 1.0, 1.0, 1.0] {
+            out.write_f32::<LittleEndian>(v).unwrap();
+        }
+
+        // the pose animates translation along z and the z and w of the rotation
+        out.write_i32::<LittleEndian>(-1).unwrap();
+        out.write_u32::<LittleEndian>(1 << 2 | 1 << 5 | 1 << 6 | 1 << 0)
+            .unwrap();
+        for v in [4.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 1.0, 1.0] {
+            out.write_f32::<LittleEndian>(v).unwrap();
+        }
+        for v in [
+            1.0,
+            0.0,
+            1.0,
+            0.0,
+            0.0,
+            1.0 / 1024.0,
+            1.0 / 1024.0,
+            0.0,
+            0.0,
+            0.0,
+        ] {
+            out.write_f32::<LittleEndian>(v).unwrap();
+        }
+
+        out.write_u32::<LittleEndian>(10).unwrap();
+        out.write_u32::<LittleEndian>(0).unwrap();
+        out.write_u32::<LittleEndian>(2).unwrap();
+        out.write_f32::<LittleEndian>(10.0).unwrap();
+        out.write_u32::<LittleEndian>(ANIM_LOOP).unwrap();
+
+        // channels in order: translate x, translate z, rotate z, rotate w
+        let quarter_turn = (std::f32::consts::FRAC_1_SQRT_2 * 1024.0).round() as u16;
+        for v in [0, 8, 0, 1024, 0, 0, quarter_turn, quarter_turn] {
+            out.write_u16::<LittleEndian>(v).unwrap();
+        }
+
+        out
+    }
+
+    #[test]
+    fn test_load_rejects_bad_counts() {
+        // the header's frame count and channel count
+        let frame_count_offset = 16 + 4 + 18 * 4;
+        let with_frames = |frame_count: u32, channel_count: u32| {
+            let mut data = test_iqm();
+            data[frame_count_offset..frame_count_offset + 4]
+                .copy_from_slice(&frame_count.to_le_bytes());
+            data[frame_count_offset + 4..frame_count_offset + 8]
+                .copy_from_slice(&channel_count.to_le_bytes());
+            load(Cursor::new(data))
+        };
+
+        assert!(matches!(
+            with_frames(u32::MAX, 4),
+            Err(IqmFileError::Truncated("frames"))
+        ));
+        assert!(matches!(
+            with_frames(u32::MAX, u32::MAX),
+            Err(IqmFileError::Truncated("frames"))
+        ));
+        assert!(matches!(
+            with_frames(u32::MAX, 0),
+            Err(IqmFileError::TooManyFrames(_))
+        ));
+    }
+
+    #[test]
+    fn test_load_rejects_bad_joint_index() {
+        // the first blend index, after the header, text, mesh, vertex arrays and positions
+        let index_offset = 124 + 15 + 24 + 2 * 20 + 3 * 12;
+        let mut data = test_iqm();
+        data[index_offset] = 1;
+
+        assert!(matches!(
+            load(Cursor::new(data)),
+            Err(IqmFileError::InvalidJointIndex(_))
+        ));
+    }
+
+    #[test]
+    fn test_load_rejects_empty_model() {
+        // the header's triangle count
+        let triangle_count_offset = 16 + 4 + 9 * 4;
+        let mut data = test_iqm();
+        data[triangle_count_offset..triangle_count_offset + 4].copy_from_slice(&0u32.to_le_bytes());
+
+        assert!(matches!(
+            load(Cursor::new(data)),
+            Err(IqmFileError::NoTriangles)
+        ));
+    }
+
+    #[test]
+    fn test_load() {
+        let model = load(Cursor::new(test_iqm())).unwrap();
+
+        assert_eq!(model.vertices().len(), 3);
+        assert_eq!(model.triangles(), [[0, 1, 2]]);
+        assert_eq!(model.meshes()[0].name(), "tri");
+        assert_eq!(model.meshes()[0].material(), "skin");
+        assert_eq!(model.meshes()[0].triangles(), 0..1);
+        assert_eq!(model.joint_count(), 1);
+        assert_eq!(model.pose_count(), 2);
+        assert_eq!(model.frame_count(), 1);
+        assert_eq!(model.anims()[0].name(), "spin");
+
+        let close = |a: Vector3<f32>, b: Vector3<f32>| (a - b).magnitude() < 1e-2;
+
+        // the first pose moves the joint up, and the vertices with it
+        let moved = model.poses()[0].transform_point([1.0, 0.0, 0.0].into());
+        assert!(close(
+            Vector3::new(moved.x, moved.y, moved.z),
+            Vector3::new(1.0, 0.0, 8.0)
+        ));
+
+        // the second turns it about its own position
+        let turned = model.poses()[1].transform_point([5.0, 0.0, 0.0].into());
+        let expected = Vector3::new(4.0, 0.0, 0.0)
+            + Quaternion::from_angle_z(Deg(90.0)) * Vector3::new(1.0, 0.0, 0.0);
+        assert!(close(Vector3::new(turned.x, turned.y, turned.z), expected));
+    }
+
+    #[test]
+    fn test_pose_at() {
+        let anim = IqmAnim {
+            name: String::new(),
+            first_pose: 3,
+            pose_count: 4,
+            framerate: 10.0,
+            looping: true,
+        };
+        assert_eq!(anim.pose_at(Duration::milliseconds(0)), 3);
+        assert_eq!(anim.pose_at(Duration::milliseconds(250)), 5);
+        assert_eq!(anim.pose_at(Duration::milliseconds(450)), 3);
+
+        let anim = IqmAnim {
+            looping: false,
+            ..anim
+        };
+        assert_eq!(anim.pose_at(Duration::milliseconds(450)), 6);
+    }
+}
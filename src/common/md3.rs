@@ -0,0 +1,747 @@
+//! Quake 3 models (`.md3`).
+//!
+//! An MD3 is made of surfaces, each with its own triangles, texture coordinates and skins, which
+//! are animated together by frames of vertex positions. Unlike MDL, vertices have smooth normals
+//! and texture coordinates of their own, so a vertex on a seam is simply stored twice. Tags are
+//! named points with an orientation in every frame, which other models can be attached to.
+//!
+//! Skins are named by the surfaces' shaders, which in replacement content for Quake are the paths
+//! of TGA or PNG images. These are only loaded on the client, with [`Md3Model::load_skins`].
+
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+
+use crate::common::{model::ModelFlags, util::read_f32_3, vfs::Vfs};
+
+use bevy::prelude::*;
+use byteorder::{LittleEndian, ReadBytesExt};
+use cgmath::{Matrix3, Matrix4, Vector3, VectorSpace as _};
+use thiserror::Error;
+
+pub const MAGIC: i32 =
+    ('I' as i32) << 0 | ('D' as i32) << 8 | ('P' as i32) << 16 | ('3' as i32) << 24;
+pub const VERSION: i32 = 15;
+
+// vertex positions are stored in fixed point with 6 fractional bits
+const XYZ_SCALE: f32 = 1.0 / 64.0;
+
+const MAX_NAME: usize = 64;
+const MAX_FRAME_NAME: usize = 16;
+
+// the extensions tried for a skin, in order, when its shader names an image that isn't there
+const SKIN_EXTENSIONS: [&str; 2] = ["tga", "png"];
+
+// the limits of Quake 3's MD3 loader
+const MD3_MAX_FRAMES: usize = 1024;
+const MD3_MAX_TAGS: usize = 16;
+const MD3_MAX_SURFACES: usize = 32;
+const MD3_MAX_SHADERS: usize = 256;
+const MD3_MAX_VERTS: usize = 4096;
+const MD3_MAX_TRIANGLES: usize = 8192;
+
+// the sizes of the records in the file
+const FRAME_SIZE: usize = 56;
+const TAG_SIZE: usize = 112;
+const TRIANGLE_SIZE: usize = 12;
+const SHADER_SIZE: usize = 68;
+const TEXCOORD_SIZE: usize = 8;
+const VERTEX_SIZE: usize = 8;
+
+#[derive(Error, Debug)]
+pub enum Md3FileError {
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Invalid magic number: found {0}, expected {}", MAGIC)]
+    InvalidMagicNumber(i32),
+    #[error("Unrecognized version: {0}")]
+    UnrecognizedVersion(i32),
+    #[error("Invalid frame count: {0}")]
+    InvalidFrameCount(i32),
+    #[error("Invalid {0} count: {1}")]
+    InvalidCount(&'static str, i32),
+    #[error("The {0} run past the end of the file")]
+    Truncated(&'static str),
+    #[error("Surface {0} has {1} frames, expected {2}")]
+    SurfaceFrameCount(String, i32, i32),
+    #[error("Vertex index {0} out of range in surface {1}")]
+    InvalidVertexIndex(i32, String),
+}
+
+#[derive(Clone, Debug)]
+pub struct Md3Frame {
+    name: String,
+    min: Vector3<f32>,
+    max: Vector3<f32>,
+    radius: f32,
+}
+
+impl Md3Frame {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn min(&self) -> Vector3<f32> {
+        self.min
+    }
+
+    pub fn max(&self) -> Vector3<f32> {
+        self.max
+    }
+
+    pub fn radius(&self) -> f32 {
+        self.radius
+    }
+}
+
+/// A named attachment point in one frame, relative to the model's origin.
+#[derive(Clone, Debug)]
+pub struct Md3Tag {
+    name: String,
+    origin: Vector3<f32>,
+    axis: Matrix3<f32>,
+}
+
+impl Md3Tag {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn origin(&self) -> Vector3<f32> {
+        self.origin
+    }
+
+    /// The tag's forward, left and up vectors, as the columns of a rotation matrix.
+    pub fn axis(&self) -> Matrix3<f32> {
+        self.axis
+    }
+
+    /// The transform of something attached to this tag, blended `blend` of the way towards the
+    /// same tag in another frame.
+    pub fn lerp(&self, other: &Md3Tag, blend: f32) -> Matrix4<f32> {
+        let origin = self.origin.lerp(other.origin, blend);
+        let axis = Matrix3::from_cols(
+            self.axis.x.lerp(other.axis.x, blend),
+            self.axis.y.lerp(other.axis.y, blend),
+            self.axis.z.lerp(other.axis.z, blend),
+        );
+
+        Matrix4::from_translation(origin) * Matrix4::from(axis)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Md3Vertex {
+    position: Vector3<f32>,
+    normal: Vector3<f32>,
+}
+
+impl Md3Vertex {
+    pub fn position(&self) -> Vector3<f32> {
+        self.position
+    }
+
+    pub fn normal(&self) -> Vector3<f32> {
+        self.normal
+    }
+}
+
+/// A decoded skin image.
+#[derive(Clone, Debug)]
+pub struct Md3Skin {
+    pub width: u32,
+    pub height: u32,
+    pub rgba: Vec<u8>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Md3Surface {
+    name: String,
+    shaders: Vec<String>,
+    skins: Vec<Option<Md3Skin>>,
+    triangles: Vec<[u32; 3]>,
+    texcoords: Vec<[f32; 2]>,
+
+    // the vertices of each frame, one after another
+    vertices: Vec<Md3Vertex>,
+    vertex_count: usize,
+}
+
+impl Md3Surface {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The names of the surface's skins, usually image paths.
+    pub fn shaders(&self) -> &[String] {
+        &self.shaders
+    }
+
+    /// The surface's skins, in the same order as its shaders. A skin is `None` if it wasn't found,
+    /// or if [`Md3Model::load_skins`] hasn't been called.
+    pub fn skins(&self) -> &[Option<Md3Skin>] {
+        &self.skins
+    }
+
+    pub fn triangles(&self) -> &[[u32; 3]] {
+        &self.triangles
+    }
+
+    pub fn texcoords(&self) -> &[[f32; 2]] {
+        &self.texcoords
+    }
+
+    /// The vertices of the surface in frame `frame_id`.
+    pub fn frame_vertices(&self, frame_id: usize) -> &[Md3Vertex] {
+        &self.vertices[frame_id * self.vertex_count..(frame_id + 1) * self.vertex_count]
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Md3Model {
+    frames: Vec<Md3Frame>,
+
+    // the tags of each frame, one after another
+    tags: Vec<Md3Tag>,
+    tag_count: usize,
+    surfaces: Vec<Md3Surface>,
+    flags: ModelFlags,
+}
+
+impl Md3Model {
+    pub fn frames(&self) -> &[Md3Frame] {
+        &self.frames
+    }
+
+    pub fn surfaces(&self) -> &[Md3Surface] {
+        &self.surfaces
+    }
+
+    pub fn flags(&self) -> ModelFlags {
+        self.flags
+    }
+
+    /// The tags of frame `frame_id`.
+    pub fn frame_tags(&self, frame_id: usize) -> &[Md3Tag] {
+        &self.tags[frame_id * self.tag_count..(frame_id + 1) * self.tag_count]
+    }
+
+    /// The tag called `name` in frame `frame_id`.
+    pub fn tag(&self, frame_id: usize, name: &str) -> Option<&Md3Tag> {
+        if frame_id >= self.frames.len() {
+            return None;
+        }
+
+        self.frame_tags(frame_id).iter().find(|t| t.name == name)
+    }
+
+    /// The smallest box containing every frame.
+    pub fn min(&self) -> Vector3<f32> {
+        self.frames.iter().fold(
+            Vector3::new(f32::INFINITY, f32::INFINITY, f32::INFINITY),
+            |m, f| Vector3::new(m.x.min(f.min.x), m.y.min(f.min.y), m.z.min(f.min.z)),
+        )
+    }
+
+    pub fn max(&self) -> Vector3<f32> {
+        self.frames.iter().fold(
+            Vector3::new(f32::NEG_INFINITY, f32::NEG_INFINITY, f32::NEG_INFINITY),
+            |m, f| Vector3::new(m.x.max(f.max.x), m.y.max(f.max.y), m.z.max(f.max.z)),
+        )
+    }
+
+    pub fn radius(&self) -> f32 {
+        self.frames.iter().map(|f| f.radius).fold(0.0, f32::max)
+    }
+
+    /// Load the images named by the surfaces' shaders. `model_name` is the path of the model,
+    /// whose directory is searched for skins that aren't found where their shader says.
+    pub fn load_skins(&mut self, vfs: &Vfs, model_name: &str) {
+        for surface in &mut self.surfaces {
+            surface.skins = surface
+                .shaders
+                .iter()
+                .map(|shader| load_skin(vfs, model_name, shader))
+                .collect();
+        }
+    }
+}
+
+/// Load the image named by `shader` for the model `model_name`, or `None` if it can't be found.
+///
+/// IQM models name their skins the same way, so they're loaded with this too.
+pub fn load_skin(vfs: &Vfs, model_name: &str, shader: &str) -> Option<Md3Skin> {
+    let skin = skin_paths(model_name, shader).into_iter().find_map(|path| {
+        let mut data = Vec::new();
+        vfs.open(&path).ok()?.read_to_end(&mut data).ok()?;
+        match image::load_from_memory(&data) {
+            Ok(image) => Some(image.into_rgba8()),
+            Err(e) => {
+                warn!("Couldn't load {}: {}", path, e);
+                None
+            }
+        }
+    });
+
+    if skin.is_none() {
+        warn!("No skin {} for {}", shader, model_name);
+    }
+
+    skin.map(|image| Md3Skin {
+        width: image.width(),
+        height: image.height(),
+        rgba: image.into_raw(),
+    })
+}
+
+/// The paths to try for the skin named by `shader`: the shader itself, then with each image
+/// extension, then the same file names in the model's own directory.
+fn skin_paths(model_name: &str, shader: &str) -> Vec<String> {
+    let stem = match shader.rfind('.') {
+        Some(dot) if !shader[dot..].contains('/') => &shader[..dot],
+        _ => shader,
+    };
+    let model_dir = match model_name.rfind('/') {
+        Some(slash) => &model_name[..=slash],
+        None => "",
+    };
+    let file_stem = match stem.rfind('/') {
+        Some(slash) => &stem[slash + 1..],
+        None => stem,
+    };
+
+    let mut paths = vec![shader.to_owned()];
+    for dir_stem in [stem.to_owned(), format!("{}{}", model_dir, file_stem)] {
+        for ext in SKIN_EXTENSIONS {
+            let path = format!("{}.{}", dir_stem, ext);
+            if !paths.contains(&path) {
+                paths.push(path);
+            }
+        }
+    }
+
+    paths
+}
+
+/// Decode a normal packed as a latitude and longitude, each a byte of a full turn.
+fn decode_normal(packed: u16) -> Vector3<f32> {
+    use std::f32::consts::PI;
+
+    let lat = ((packed >> 8) & 0xff) as f32 * PI / 128.0;
+    let lng = (packed & 0xff) as f32 * PI / 128.0;
+
+    Vector3::new(lat.cos() * lng.sin(), lat.sin() * lng.sin(), lng.cos())
+}
+
+fn read_name<R>(reader: &mut R, len: usize) -> io::Result<String>
+where
+    R: Read,
+{
+    let mut bytes = vec![0; len];
+    reader.read_exact(&mut bytes)?;
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(len);
+    Ok(String::from_utf8_lossy(&bytes[..end]).into_owned())
+}
+
+fn read_count<R>(reader: &mut R, what: &'static str, max: usize) -> Result<usize, Md3FileError>
+where
+    R: Read,
+{
+    let count = reader.read_i32::<LittleEndian>()?;
+    if count < 0 || count as usize > max {
+        Err(Md3FileError::InvalidCount(what, count))?;
+    }
+
+    Ok(count as usize)
+}
+
+/// Check that `count` records of `size` bytes starting at `offset` are inside the file, before
+/// making room for them.
+fn check_extent(
+    what: &'static str,
+    offset: u64,
+    count: usize,
+    size: usize,
+    file_len: u64,
+) -> Result<(), Md3FileError> {
+    let end = (count as u64)
+        .checked_mul(size as u64)
+        .and_then(|len| len.checked_add(offset));
+    match end {
+        Some(end) if end <= file_len => Ok(()),
+        _ => Err(Md3FileError::Truncated(what)),
+    }
+}
+
+pub fn load<R>(data: R) -> Result<Md3Model, Md3FileError>
+where
+    R: Read + Seek,
+{
+    let mut reader = BufReader::new(data);
+    let file_len = reader.seek(SeekFrom::End(0))?;
+    reader.seek(SeekFrom::Start(0))?;
+
+    // struct Md3Header {
+    //     magic: i32,
+    //     version: i32,
+    //     name: [u8; 64],
+    //     flags: i32,
+    //     frame_count: i32,
+    //     tag_count: i32,
+    //     surface_count: i32,
+    //     skin_count: i32,
+    //     frames_offset: i32,
+    //     tags_offset: i32,
+    //     surfaces_offset: i32,
+    //     end_offset: i32,
+    // }
+
+    let magic = reader.read_i32::<LittleEndian>()?;
+    if magic != MAGIC {
+        Err(Md3FileError::InvalidMagicNumber(magic))?;
+    }
+
+    let version = reader.read_i32::<LittleEndian>()?;
+    if version != VERSION {
+        Err(Md3FileError::UnrecognizedVersion(version))?;
+    }
+
+    let _name = read_name(&mut reader, MAX_NAME)?;
+    // Quake's model flags, as used by DarkPlaces for rocket trails and such
+    let flags = ModelFlags::from_bits_truncate(reader.read_i32::<LittleEndian>()? as u8);
+    let frame_count = reader.read_i32::<LittleEndian>()?;
+    if frame_count <= 0 || frame_count as usize > MD3_MAX_FRAMES {
+        Err(Md3FileError::InvalidFrameCount(frame_count))?;
+    }
+    let frame_count = frame_count as usize;
+    let tag_count = read_count(&mut reader, "tag", MD3_MAX_TAGS)?;
+    let surface_count = read_count(&mut reader, "surface", MD3_MAX_SURFACES)?;
+    let _skin_count = reader.read_i32::<LittleEndian>()?;
+    let frames_offset = reader.read_i32::<LittleEndian>()? as u64;
+    let tags_offset = reader.read_i32::<LittleEndian>()? as u64;
+    let surfaces_offset = reader.read_i32::<LittleEndian>()? as u64;
+
+    check_extent("frames", frames_offset, frame_count, FRAME_SIZE, file_len)?;
+    reader.seek(SeekFrom::Start(frames_offset))?;
+    let mut frames = Vec::with_capacity(frame_count);
+    for _ in 0..frame_count {
+        let min = read_f32_3(&mut reader)?.into();
+        let max = read_f32_3(&mut reader)?.into();
+        let _local_origin = read_f32_3(&mut reader)?;
+        let radius = reader.read_f32::<LittleEndian>()?;
+        let name = read_name(&mut reader, MAX_FRAME_NAME)?;
+        frames.push(Md3Frame {
+            name,
+            min,
+            max,
+            radius,
+        });
+    }
+
+    let frame_tag_count = frame_count * tag_count;
+    check_extent("tags", tags_offset, frame_tag_count, TAG_SIZE, file_len)?;
+    reader.seek(SeekFrom::Start(tags_offset))?;
+    let mut tags = Vec::with_capacity(frame_tag_count);
+    for _ in 0..frame_tag_count {
+        let name = read_name(&mut reader, MAX_NAME)?;
+        let origin = read_f32_3(&mut reader)?.into();
+        let axis = Matrix3::from_cols(
+            read_f32_3(&mut reader)?.into(),
+            read_f32_3(&mut reader)?.into(),
+            read_f32_3(&mut reader)?.into(),
+        );
+        tags.push(Md3Tag { name, origin, axis });
+    }
+
+    let mut surfaces = Vec::with_capacity(surface_count);
+    let mut surface_start = surfaces_offset;
+    for _ in 0..surface_count {
+        reader.seek(SeekFrom::Start(surface_start))?;
+
+        // struct Md3SurfaceHeader {
+        //     magic: i32,
+        //     name: [u8; 64],
+        //     flags: i32,
+        //     frame_count: i32,
+        //     shader_count: i32,
+        //     vertex_count: i32,
+        //     triangle_count: i32,
+        //     triangles_offset: i32,
+        //     shaders_offset: i32,
+        //     texcoords_offset: i32,
+        //     vertices_offset: i32,
+        //     end_offset: i32,
+        // }
+        //
+        // the offsets are from the start of the surface
+
+        let magic = reader.read_i32::<LittleEndian>()?;
+        if magic != MAGIC {
+            Err(Md3FileError::InvalidMagicNumber(magic))?;
+        }
+
+        let name = read_name(&mut reader, MAX_NAME)?;
+        let _flags = reader.read_i32::<LittleEndian>()?;
+        let surface_frame_count = reader.read_i32::<LittleEndian>()?;
+        if surface_frame_count != frame_count as i32 {
+            Err(Md3FileError::SurfaceFrameCount(
+                name.clone(),
+                surface_frame_count,
+                frame_count as i32,
+            ))?;
+        }
+        let shader_count = read_count(&mut reader, "shader", MD3_MAX_SHADERS)?;
+        let vertex_count = read_count(&mut reader, "vertex", MD3_MAX_VERTS)?;
+        let triangle_count = read_count(&mut reader, "triangle", MD3_MAX_TRIANGLES)?;
+        let triangles_offset = surface_start + reader.read_i32::<LittleEndian>()? as u32 as u64;
+        let shaders_offset = surface_start + reader.read_i32::<LittleEndian>()? as u32 as u64;
+        let texcoords_offset = surface_start + reader.read_i32::<LittleEndian>()? as u32 as u64;
+        let vertices_offset = surface_start + reader.read_i32::<LittleEndian>()? as u32 as u64;
+        let end_offset = reader.read_i32::<LittleEndian>()? as u32 as u64;
+
+        let frame_vertex_count = frame_count * vertex_count;
+        for (what, offset, count, size) in [
+            ("triangles", triangles_offset, triangle_count, TRIANGLE_SIZE),
+            ("shaders", shaders_offset, shader_count, SHADER_SIZE),
+            ("texcoords", texcoords_offset, vertex_count, TEXCOORD_SIZE),
+            ("vertices", vertices_offset, frame_vertex_count, VERTEX_SIZE),
+        ] {
+            check_extent(what, offset, count, size, file_len)?;
+        }
+
+        reader.seek(SeekFrom::Start(triangles_offset))?;
+        let mut triangles = Vec::with_capacity(triangle_count);
+        for _ in 0..triangle_count {
+            let mut triangle = [0; 3];
+            for index in &mut triangle {
+                let i = reader.read_i32::<LittleEndian>()?;
+                if i < 0 || i as usize >= vertex_count {
+                    Err(Md3FileError::InvalidVertexIndex(i, name.clone()))?;
+                }
+                *index = i as u32;
+            }
+            triangles.push(triangle);
+        }
+
+        reader.seek(SeekFrom::Start(shaders_offset))?;
+        let mut shaders = Vec::with_capacity(shader_count);
+        for _ in 0..shader_count {
+            shaders.push(read_name(&mut reader, MAX_NAME)?);
+            let _shader_index = reader.read_i32::<LittleEndian>()?;
+        }
+
+        reader.seek(SeekFrom::Start(texcoords_offset))?;
+        let mut texcoords = Vec::with_capacity(vertex_count);
+        for _ in 0..vertex_count {
+            texcoords.push([
+                reader.read_f32::<LittleEndian>()?,
+                reader.read_f32::<LittleEndian>()?,
+            ]);
+        }
+
+        reader.seek(SeekFrom::Start(vertices_offset))?;
+        let mut vertices = Vec::with_capacity(frame_vertex_count);
+        for _ in 0..frame_vertex_count {
+            let mut position = [0.0; 3];
+            for component in &mut position {
+                *component = reader.read_i16::<LittleEndian>()? as f32 * XYZ_SCALE;
+            }
+            let normal = decode_normal(reader.read_u16::<LittleEndian>()?);
+            vertices.push(Md3Vertex {
+                position: position.into(),
+                normal,
+            });
+        }
+
+        surfaces.push(Md3Surface {
+            name,
+            skins: vec![None; shaders.len()],
+            shaders,
+            triangles,
+            texcoords,
+            vertices,
+            vertex_count,
+        });
+
+        surface_start += end_offset;
+    }
+
+    Ok(Md3Model {
+        frames,
+        tags,
+        tag_count,
+        surfaces,
+        flags,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use byteorder::WriteBytesExt;
+    use cgmath::InnerSpace as _;
+    use std::io::{Cursor, Write};
+
+    fn write_name(out: &mut Vec<u8>, name: &str, len: usize) {
+        let mut bytes = name.as_bytes().to_vec();
+        bytes.resize(len, 0);
+        out.write_all(&bytes).unwrap();
+    }
+
+    // one frame, one tag and a surface with a single triangle
+    fn test_md3() -> Vec<u8> {
+        let mut out = Vec::new();
+        let header_size = 108;
+        let frame_size = 56;
+        let tag_size = 112;
+
+        out.write_i32::<LittleEndian>(MAGIC).unwrap();
+        out.write_i32::<LittleEndian>(VERSION).unwrap();
+        write_name(&mut out, "test", MAX_NAME);
+        out.write_i32::<LittleEndian>(ModelFlags::ROCKET.bits() as i32)
+            .unwrap();
+        for count in [1, 1, 1, 0] {
+            out.write_i32::<LittleEndian>(count).unwrap();
+        }
+        out.write_i32::<LittleEndian>(header_size).unwrap();
+        out.write_i32::<LittleEndian>(header_size + frame_size)
+            .unwrap();
+        out.write_i32::<LittleEndian>(header_size + frame_size + tag_size)
+            .unwrap();
+        out.write_i32::<LittleEndian>(0).unwrap();
+
+        for v in [-1.0, -1.0, -1.0, 1.0, 1.0, 1.0, 0.0, 0.0, 0.0, 2.0] {
+            out.write_f32::<LittleEndian>(v).unwrap();
+        }
+        write_name(&mut out, "frame0", MAX_FRAME_NAME);
+
+        write_name(&mut out, "tag_weapon", MAX_NAME);
+        for v in [1.0, 2.0, 3.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0] {
+            out.write_f32::<LittleEndian>(v).unwrap();
+        }
+
+        let surface_header_size = 108;
+        let triangles_offset = surface_header_size;
+        let shaders_offset = triangles_offset + 12;
+        let texcoords_offset = shaders_offset + 68;
+        let vertices_offset = texcoords_offset + 24;
+        let end_offset = vertices_offset + 24;
+
+        out.write_i32::<LittleEndian>(MAGIC).unwrap();
+        write_name(&mut out, "body", MAX_NAME);
+        for v in [
+            0,
+            1,
+            1,
+            3,
+            1,
+            triangles_offset,
+            shaders_offset,
+            texcoords_offset,
+            vertices_offset,
+            end_offset,
+        ] {
+            out.write_i32::<LittleEndian>(v).unwrap();
+        }
+        for i in [0, 1, 2] {
+            out.write_i32::<LittleEndian>(i).unwrap();
+        }
+        write_name(&mut out, "progs/test_skin", MAX_NAME);
+        out.write_i32::<LittleEndian>(0).unwrap();
+        for v in [0.0, 0.0, 1.0, 0.0, 0.0, 1.0] {
+            out.write_f32::<LittleEndian>(v).unwrap();
+        }
+        for (x, y, z) in [(0, 0, 0), (64, 0, 0), (0, 128, 0)] {
+            out.write_i16::<LittleEndian>(x).unwrap();
+            out.write_i16::<LittleEndian>(y).unwrap();
+            out.write_i16::<LittleEndian>(z).unwrap();
+            out.write_u16::<LittleEndian>(0).unwrap();
+        }
+
+        out
+    }
+
+    #[test]
+    fn test_load() {
+        let model = load(Cursor::new(test_md3())).unwrap();
+
+        assert_eq!(model.flags(), ModelFlags::ROCKET);
+        assert_eq!(model.frames().len(), 1);
+        assert_eq!(model.frames()[0].name(), "frame0");
+        assert_eq!(model.radius(), 2.0);
+
+        let tag = model.tag(0, "tag_weapon").unwrap();
+        assert_eq!(tag.origin(), Vector3::new(1.0, 2.0, 3.0));
+        assert!(model.tag(0, "tag_head").is_none());
+        assert!(model.tag(1, "tag_weapon").is_none());
+
+        let surface = &model.surfaces()[0];
+        assert_eq!(surface.name(), "body");
+        assert_eq!(surface.shaders(), ["progs/test_skin"]);
+        assert_eq!(surface.triangles(), [[0, 1, 2]]);
+        assert_eq!(surface.texcoords()[1], [1.0, 0.0]);
+        let positions = surface
+            .frame_vertices(0)
+            .iter()
+            .map(|v| v.position())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            positions,
+            [
+                Vector3::new(0.0, 0.0, 0.0),
+                Vector3::new(1.0, 0.0, 0.0),
+                Vector3::new(0.0, 2.0, 0.0)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_load_rejects_bad_counts() {
+        // the surface's vertex count
+        let vertex_count_offset = 108 + 56 + 112 + 4 + MAX_NAME + 12;
+        let with_vertex_count = |count: i32| {
+            let mut data = test_md3();
+            data[vertex_count_offset..vertex_count_offset + 4]
+                .copy_from_slice(&count.to_le_bytes());
+            load(Cursor::new(data))
+        };
+
+        assert!(matches!(
+            with_vertex_count(i32::MAX),
+            Err(Md3FileError::InvalidCount("vertex", _))
+        ));
+        assert!(matches!(
+            with_vertex_count(MD3_MAX_VERTS as i32),
+            Err(Md3FileError::Truncated(_))
+        ));
+    }
+
+    #[test]
+    fn test_decode_normal() {
+        let up = decode_normal(0);
+        assert!((up - Vector3::unit_z()).magnitude() < 1e-6);
+
+        // a quarter turn of longitude lies in the horizontal plane
+        let forward = decode_normal(64);
+        assert!((forward - Vector3::unit_x()).magnitude() < 1e-6);
+
+        let left = decode_normal(64 << 8 | 64);
+        assert!((left - Vector3::unit_y()).magnitude() < 1e-6);
+    }
+
+    #[test]
+    fn test_skin_paths() {
+        assert_eq!(
+            skin_paths("progs/player.md3", "models/players/ranger/body.tga"),
+            [
+                "models/players/ranger/body.tga",
+                "models/players/ranger/body.png",
+                "progs/body.tga",
+                "progs/body.png",
+            ]
+        );
+        assert_eq!(
+            skin_paths("progs/player.md3", "progs/player_0"),
+            ["progs/player_0", "progs/player_0.tga", "progs/player_0.png"]
+        );
+    }
+}
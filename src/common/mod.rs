@@ -24,12 +24,15 @@ pub mod bsp;
 pub mod console;
 pub mod engine;
 pub mod host;
+pub mod iqm;
 pub mod math;
+pub mod md3;
 pub mod mdl;
 pub mod model;
 pub mod net;
 pub mod pak;
 pub mod parse;
+pub mod savegame;
 pub mod sprite;
 pub mod util;
 pub mod vfs;
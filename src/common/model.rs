@@ -17,6 +17,8 @@
 
 use crate::common::{
     bsp::{BspFileError, BspModel},
+    iqm::{self, IqmFileError, IqmModel},
+    md3::{self, Md3FileError, Md3Model},
     mdl::{self, AliasModel, MdlFileError},
     sprite::{self, SpriteModel},
     vfs::{Vfs, VfsError},
@@ -34,6 +36,10 @@ pub enum ModelError {
     BspFile(#[from] BspFileError),
     #[error("MDL file error: {0}")]
     MdlFile(#[from] MdlFileError),
+    #[error("MD3 file error: {0}")]
+    Md3File(#[from] Md3FileError),
+    #[error("IQM file error: {0}")]
+    IqmFile(#[from] IqmFileError),
     #[error("SPR file error")]
     SprFile,
     #[error("Virtual filesystem error: {0}")]
@@ -73,6 +79,8 @@ pub enum ModelKind {
     None,
     Brush(BspModel),
     Alias(AliasModel),
+    Md3(Md3Model),
+    Iqm(IqmModel),
     Sprite(SpriteModel),
 }
 
@@ -93,12 +101,21 @@ impl Model {
     where
         S: AsRef<str>,
     {
+        profile_span!("model_load");
         let name = name.as_ref();
         // TODO: original engine uses the magic numbers of each format instead of the extension.
         if name.ends_with(".bsp") {
             panic!("BSP files may contain multiple models, use bsp::load for this");
         } else if name.ends_with(".mdl") {
             Ok(Model::from_alias_model(name, mdl::load(vfs.open(name)?)?))
+        } else if name.ends_with(".md3") {
+            let mut md3_model = md3::load(vfs.open(name)?)?;
+            md3_model.load_skins(vfs, name);
+            Ok(Model::from_md3_model(name, md3_model))
+        } else if name.ends_with(".iqm") {
+            let mut iqm_model = iqm::load(vfs.open(name)?)?;
+            iqm_model.load_skins(vfs, name);
+            Ok(Model::from_iqm_model(name, iqm_model))
         } else if name.ends_with(".spr") {
             Ok(Model::from_sprite_model(
                 name,
@@ -135,6 +152,34 @@ impl Model {
         }
     }
 
+    /// Construct a new generic model from an MD3 model.
+    pub fn from_md3_model<S>(name: S, md3_model: Md3Model) -> Model
+    where
+        S: AsRef<str>,
+    {
+        let flags = md3_model.flags();
+
+        Model {
+            name: name.as_ref().into(),
+            kind: ModelKind::Md3(md3_model),
+            flags,
+        }
+    }
+
+    /// Construct a new generic model from an IQM model.
+    pub fn from_iqm_model<S>(name: S, iqm_model: IqmModel) -> Model
+    where
+        S: AsRef<str>,
+    {
+        let flags = iqm_model.flags();
+
+        Model {
+            name: name.as_ref().into(),
+            kind: ModelKind::Iqm(iqm_model),
+            flags,
+        }
+    }
+
     /// Construct a new generic model from a sprite model.
     pub fn from_sprite_model<S>(name: S, sprite_model: SpriteModel) -> Model
     where
@@ -163,6 +208,8 @@ impl Model {
             // TODO: maybe change this?
             // https://github.com/id-Software/Quake/blob/master/WinQuake/gl_model.c#L1625
             ModelKind::Alias(_) => Vector3::new(-16.0, -16.0, -16.0),
+            ModelKind::Md3(ref md3_model) => md3_model.min(),
+            ModelKind::Iqm(ref iqm_model) => iqm_model.min(),
         }
     }
 
@@ -177,6 +224,8 @@ impl Model {
             // TODO: maybe change this?
             // https://github.com/id-Software/Quake/blob/master/WinQuake/gl_model.c#L1625
             ModelKind::Alias(_) => Vector3::new(16.0, 16.0, 16.0),
+            ModelKind::Md3(ref md3_model) => md3_model.max(),
+            ModelKind::Iqm(ref iqm_model) => iqm_model.max(),
         }
     }
 
@@ -188,6 +237,7 @@ impl Model {
             ModelKind::Sprite(ref _smodel) => SyncType::Sync,
             // TODO: expose sync_type in Mdl and reflect it here
             ModelKind::Alias(ref _amodel) => SyncType::Sync,
+            ModelKind::Md3(_) | ModelKind::Iqm(_) => SyncType::Sync,
         }
     }
 
@@ -488,6 +488,108 @@ pub enum Response {
     RuleInfo(ResponseRuleInfo),
 }
 
+impl Response {
+    /// Parses a `Response` from a received packet.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Response, NetError> {
+        let mut reader = BufReader::new(bytes);
+
+        let control = reader.read_i32::<NetworkEndian>()?;
+
+        // TODO: figure out what a control value of -1 means
+        if control == -1 {
+            return Err(NetError::with_msg("Control value is -1"));
+        }
+
+        // high 4 bits must be 0x8000 (CONNECT_CONTROL)
+        if control & !CONNECT_LENGTH_MASK != CONNECT_CONTROL {
+            return Err(NetError::invalid_data(format!(
+                "control value {:X}",
+                control & !CONNECT_LENGTH_MASK
+            )));
+        }
+
+        // low 4 bits must be total length of packet
+        let control_len = (control & CONNECT_LENGTH_MASK) as usize;
+        if control_len != bytes.len() {
+            return Err(NetError::with_msg(format!(
+                "Actual packet length ({}) differs from header value ({})",
+                bytes.len(),
+                control_len,
+            )));
+        }
+
+        let response_byte = reader.read_u8()?;
+        let response_code = match ResponseCode::from_u8(response_byte) {
+            Some(r) => r,
+            None => {
+                return Err(NetError::invalid_data(format!(
+                    "response code {}",
+                    response_byte
+                )))
+            }
+        };
+
+        let response = match response_code {
+            ResponseCode::Accept => {
+                let port = reader.read_i32::<LittleEndian>()?;
+                Response::Accept(ResponseAccept { port })
+            }
+
+            ResponseCode::Reject => {
+                let message = util::read_cstring(&mut reader)?;
+                Response::Reject(ResponseReject { message })
+            }
+
+            ResponseCode::ServerInfo => {
+                let address = util::read_cstring(&mut reader)?.into_string();
+                let hostname = util::read_cstring(&mut reader)?.into_string();
+                let levelname = util::read_cstring(&mut reader)?.into_string();
+                let client_count = reader.read_u8()?;
+                let client_max = reader.read_u8()?;
+                let protocol_version = reader.read_u8()?;
+
+                Response::ServerInfo(ResponseServerInfo {
+                    address,
+                    hostname,
+                    levelname,
+                    client_count,
+                    client_max,
+                    protocol_version,
+                })
+            }
+
+            ResponseCode::PlayerInfo => {
+                let player_id = reader.read_u8()?;
+                let player_name = util::read_cstring(&mut reader)?.into_string();
+                let colors = reader.read_i32::<LittleEndian>()?;
+                let frags = reader.read_i32::<LittleEndian>()?;
+                let connect_duration = reader.read_i32::<LittleEndian>()?;
+                let address = util::read_cstring(&mut reader)?.into_string();
+
+                Response::PlayerInfo(ResponsePlayerInfo {
+                    player_id,
+                    player_name,
+                    colors,
+                    frags,
+                    connect_duration,
+                    address,
+                })
+            }
+
+            ResponseCode::RuleInfo => {
+                let cvar_name = util::read_cstring(&mut reader)?.into_string();
+                let cvar_val = util::read_cstring(&mut reader)?.into_string();
+                Response::RuleInfo(ResponseRuleInfo {
+                    cvar_name,
+                    cvar_val,
+                })
+            }
+        };
+
+        Ok(response)
+    }
+}
+
 impl ConnectPacket for Response {
     fn code(&self) -> u8 {
         use self::Response::*;
@@ -615,6 +717,21 @@ impl ConnectListener {
         Ok((request, remote))
     }
 
+    pub fn local_addr(&self) -> Result<SocketAddr, NetError> {
+        Ok(self.socket.local_addr()?)
+    }
+
+    /// Receives a request if one is waiting, without blocking.
+    pub fn try_recv_request(&self) -> Result<Option<(Request, SocketAddr)>, NetError> {
+        self.socket.set_nonblocking(true)?;
+
+        match self.recv_request() {
+            Ok(request) => Ok(Some(request)),
+            Err(NetError::Io { source, .. }) if source.kind() == ErrorKind::WouldBlock => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
     pub fn send_response(&self, response: Response, remote: SocketAddr) -> Result<(), NetError> {
         self.socket.send_to(&response.to_bytes()?, remote)?;
         Ok(())
@@ -667,75 +784,7 @@ impl ConnectSocket {
         };
         self.socket.set_read_timeout(None)?;
 
-        let mut reader = BufReader::new(&recv_buf[..len]);
-
-        let control = reader.read_i32::<NetworkEndian>()?;
-
-        // TODO: figure out what a control value of -1 means
-        if control == -1 {
-            return Err(NetError::with_msg("Control value is -1"));
-        }
-
-        // high 4 bits must be 0x8000 (CONNECT_CONTROL)
-        if control & !CONNECT_LENGTH_MASK != CONNECT_CONTROL {
-            return Err(NetError::invalid_data(format!(
-                "control value {:X}",
-                control & !CONNECT_LENGTH_MASK
-            )));
-        }
-
-        // low 4 bits must be total length of packet
-        let control_len = (control & CONNECT_LENGTH_MASK) as usize;
-        if control_len != len {
-            return Err(NetError::with_msg(format!(
-                "Actual packet length ({}) differs from header value ({})",
-                len, control_len,
-            )));
-        }
-
-        let response_byte = reader.read_u8()?;
-        let response_code = match ResponseCode::from_u8(response_byte) {
-            Some(r) => r,
-            None => {
-                return Err(NetError::invalid_data(format!(
-                    "response code {}",
-                    response_byte
-                )))
-            }
-        };
-
-        let response = match response_code {
-            ResponseCode::Accept => {
-                let port = reader.read_i32::<LittleEndian>()?;
-                Response::Accept(ResponseAccept { port })
-            }
-
-            ResponseCode::Reject => {
-                let message = util::read_cstring(&mut reader)?;
-                Response::Reject(ResponseReject { message })
-            }
-
-            ResponseCode::ServerInfo => {
-                let address = util::read_cstring(&mut reader)?.into_string();
-                let hostname = util::read_cstring(&mut reader)?.into_string();
-                let levelname = util::read_cstring(&mut reader)?.into_string();
-                let client_count = reader.read_u8()?;
-                let client_max = reader.read_u8()?;
-                let protocol_version = reader.read_u8()?;
-
-                Response::ServerInfo(ResponseServerInfo {
-                    address,
-                    hostname,
-                    levelname,
-                    client_count,
-                    client_max,
-                    protocol_version,
-                })
-            }
-
-            ResponseCode::PlayerInfo => unimplemented!(),
-            ResponseCode::RuleInfo => unimplemented!(),
-        };
+        let response = Response::from_bytes(&recv_buf[..len])?;
 
         Ok(Some((response, remote)))
     }
@@ -841,4 +890,81 @@ mod test {
     fn test_connect_listener_bind() {
         let _listener = ConnectListener::bind("127.0.0.1:26000").unwrap();
     }
+
+    #[test]
+    fn test_connect_listener_try_recv() {
+        let listener = ConnectListener::bind("127.0.0.1:0").unwrap();
+        assert!(listener.try_recv_request().unwrap().is_none());
+
+        let mut socket = ConnectSocket::bind("127.0.0.1:0").unwrap();
+        socket
+            .send_request(
+                Request::connect("QUAKE", CONNECT_PROTOCOL_VERSION),
+                listener.local_addr().unwrap(),
+            )
+            .unwrap();
+
+        // the datagram may take a moment to arrive
+        let mut received = None;
+        for _ in 0..100 {
+            received = listener.try_recv_request().unwrap();
+            if received.is_some() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+
+        match received {
+            Some((Request::Connect(connect), _)) => {
+                assert_eq!(connect.game_name, "QUAKE");
+                assert_eq!(connect.proto_ver, CONNECT_PROTOCOL_VERSION);
+            }
+            _ => panic!("expected a connection request"),
+        }
+    }
+
+    #[test]
+    fn test_response_player_info_from_bytes() {
+        let response_player_info = ResponsePlayerInfo {
+            player_id: 3,
+            player_name: String::from("player"),
+            colors: 0x4d,
+            frags: 12,
+            connect_duration: 120,
+            address: String::from("127.0.0.1"),
+        };
+        let packet = response_player_info.to_bytes().unwrap();
+
+        match Response::from_bytes(&packet).unwrap() {
+            Response::PlayerInfo(info) => {
+                assert_eq!(info.player_id, 3);
+                assert_eq!(info.player_name, "player");
+                assert_eq!(info.colors, 0x4d);
+                assert_eq!(info.frags, 12);
+                assert_eq!(info.connect_duration, 120);
+                assert_eq!(info.address, "127.0.0.1");
+            }
+            _ => panic!("expected player info"),
+        }
+
+        // a truncated reply is an error rather than a panic
+        assert!(Response::from_bytes(&packet[..packet.len() - 4]).is_err());
+    }
+
+    #[test]
+    fn test_response_rule_info_from_bytes() {
+        let response_rule_info = ResponseRuleInfo {
+            cvar_name: String::from("sv_gravity"),
+            cvar_val: String::from("800"),
+        };
+        let packet = response_rule_info.to_bytes().unwrap();
+
+        match Response::from_bytes(&packet).unwrap() {
+            Response::RuleInfo(rule) => {
+                assert_eq!(rule.cvar_name, "sv_gravity");
+                assert_eq!(rule.cvar_val, "800");
+            }
+            _ => panic!("expected rule info"),
+        }
+    }
 }
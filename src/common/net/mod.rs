@@ -45,7 +45,7 @@ use snafu::{prelude::*, Backtrace};
 use super::util::QString;
 
 pub const MAX_MESSAGE: usize = 8192;
-const MAX_DATAGRAM: usize = 1024;
+pub const MAX_DATAGRAM: usize = 1024;
 const HEADER_SIZE: usize = 8;
 const MAX_PACKET: usize = HEADER_SIZE + MAX_DATAGRAM;
 
@@ -72,6 +72,7 @@ pub const DEFAULT_VIEWHEIGHT: f32 = 22.0;
 pub struct ServerMessage {
     pub client_id: usize,
     pub packet: Vec<u8>,
+    pub kind: MessageKind,
 }
 
 #[derive(PartialEq, Eq, Copy, Clone, Hash, Default)]
@@ -255,7 +256,7 @@ bitflags! {
     }
 }
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
 pub struct PlayerColor {
     top: u8,
     bottom: u8,
@@ -284,6 +285,39 @@ impl PlayerColor {
     pub fn bits(&self) -> u8 {
         self.top << 4 | (self.bottom & 0x0F)
     }
+
+    pub fn top(&self) -> u8 {
+        self.top
+    }
+
+    pub fn bottom(&self) -> u8 {
+        self.bottom
+    }
+
+    /// Map a palette index in a player skin to the index it's drawn with in these colors.
+    ///
+    /// Skins are drawn in the palette's default shirt and pants ranges, which are replaced with
+    /// the ranges of the chosen colors.
+    pub fn translate(&self, index: u8) -> u8 {
+        const TOP_RANGE: u8 = 16;
+        const BOTTOM_RANGE: u8 = 96;
+
+        for (range, color) in [(TOP_RANGE, self.top), (BOTTOM_RANGE, self.bottom)] {
+            if (range..range + 16).contains(&index) {
+                let offset = index - range;
+                let start = (color & 0x0F) * 16;
+
+                // the ranges from 128 onwards run from light to dark rather than dark to light
+                return if start < 128 {
+                    start + offset
+                } else {
+                    start + 15 - offset
+                };
+            }
+        }
+
+        index
+    }
 }
 
 impl ::std::convert::From<u8> for PlayerColor {
@@ -2580,6 +2614,22 @@ where
 mod test {
     use super::*;
 
+    #[test]
+    fn test_player_color_translate() {
+        let color = PlayerColor::new(4, 13);
+
+        // shirt and pants ranges are replaced
+        assert_eq!(color.translate(16), 64);
+        assert_eq!(color.translate(31), 79);
+        assert_eq!(color.translate(96), 223);
+        assert_eq!(color.translate(111), 208);
+
+        // everything else is left alone
+        assert_eq!(color.translate(15), 15);
+        assert_eq!(color.translate(32), 32);
+        assert_eq!(color.translate(0xFF), 0xFF);
+    }
+
     #[test]
     fn test_server_cmd_update_stat_read_write_eq() {
         let src = ServerCmd::UpdateStat {
@@ -36,6 +36,7 @@ use bevy::{
 use byteorder::{LittleEndian, ReadBytesExt};
 use futures::AsyncReadExt as _;
 use hashbrown::HashMap;
+#[cfg(not(target_arch = "wasm32"))]
 use memmap2::{Mmap, MmapOptions};
 use thiserror::Error;
 
@@ -74,10 +75,12 @@ enum PakEntry {
 
 #[derive(Debug)]
 enum PakBacking {
+    #[cfg(not(target_arch = "wasm32"))]
     Mmap(Mmap),
     Memory(Box<[u8]>),
 }
 
+#[cfg(not(target_arch = "wasm32"))]
 impl From<Mmap> for PakBacking {
     fn from(value: Mmap) -> Self {
         Self::Mmap(value)
@@ -93,6 +96,7 @@ impl From<Box<[u8]>> for PakBacking {
 impl AsRef<[u8]> for PakBacking {
     fn as_ref(&self) -> &[u8] {
         match self {
+            #[cfg(not(target_arch = "wasm32"))]
             Self::Mmap(mmap) => mmap.as_ref(),
             Self::Memory(mem) => mem.as_ref(),
         }
@@ -102,12 +106,14 @@ impl AsRef<[u8]> for PakBacking {
 /// An open Pak archive.
 #[derive(Asset, TypePath, Debug)]
 pub struct Pak {
+    path: PathBuf,
     memory: PakBacking,
     entries: HashMap<PathBuf, PakEntry>,
 }
 
+/// Loads pakfiles through the asset server, which fetches them over HTTP on the web.
 #[derive(Default)]
-struct PakLoader;
+pub struct PakLoader;
 
 impl AssetLoader for PakLoader {
     type Asset = Pak;
@@ -118,14 +124,14 @@ impl AssetLoader for PakLoader {
         &'a self,
         reader: &'a mut Reader,
         _settings: &'a (),
-        _load_context: &'a mut LoadContext,
+        load_context: &'a mut LoadContext,
     ) -> BoxedFuture<'a, Result<Self::Asset, Self::Error>> {
         Box::pin(async move {
             let mut data = Vec::new();
 
             reader.read_to_end(&mut data).await?;
 
-            Pak::read(data.into_boxed_slice())
+            Pak::read(data.into_boxed_slice(), load_context.path().to_owned())
         })
     }
 
@@ -205,10 +211,16 @@ impl Pak {
     {
         debug!("Opening {}", path.as_ref().to_str().unwrap());
 
-        Self::read(unsafe { MmapOptions::new().map(&fs::File::open(path)?)? })
+        #[cfg(not(target_arch = "wasm32"))]
+        let bytes = unsafe { MmapOptions::new().map(&fs::File::open(&path)?)? };
+        // there's no memory mapping on the web, where paks are usually loaded by `PakLoader`
+        #[cfg(target_arch = "wasm32")]
+        let bytes = fs::read(&path)?.into_boxed_slice();
+
+        Self::read(bytes, path.as_ref().to_owned())
     }
 
-    fn read<B: Into<PakBacking>>(bytes: B) -> Result<Self, PakError> {
+    fn read<B: Into<PakBacking>>(bytes: B, path: PathBuf) -> Result<Self, PakError> {
         let bytes = bytes.into();
         let mut reader = io::Cursor::new(bytes.as_ref());
 
@@ -288,11 +300,22 @@ impl Pak {
         map.shrink_to_fit();
 
         Ok(Pak {
+            path,
             memory: bytes,
             entries: map,
         })
     }
 
+    /// The path the pakfile was loaded from.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The contents of the whole pakfile.
+    pub fn data(&self) -> &[u8] {
+        self.memory.as_ref()
+    }
+
     /// Opens a file in the file tree for reading.
     ///
     /// # Examples
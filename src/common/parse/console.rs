@@ -15,12 +15,11 @@
 // DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 
+use std::borrow::Cow;
+
 use crate::{
     client::input::game::{Binding, BindingValidState, Trigger},
-    common::{
-        console::{CmdName, RunCmd},
-        parse::quoted,
-    },
+    common::console::{CmdName, RunCmd},
 };
 
 use nom::{
@@ -104,9 +103,48 @@ pub fn basic_arg(input: &str) -> nom::IResult<&str, &str> {
     }
 }
 
+/// Match a quoted string of ASCII characters, which may be empty.
+///
+/// Inside the quotes, `\"` is a quote and `\\` is a backslash. Any other backslash is kept as it
+/// is, so that paths written by other engines still work.
+pub fn quoted_arg(input: &str) -> nom::IResult<&str, Cow<'_, str>> {
+    let error = || nom::Err::Error(nom::error::Error::new(input, nom::error::ErrorKind::Char));
+
+    let contents = input.strip_prefix('"').ok_or_else(error)?;
+
+    // only allocated if there are escapes
+    let mut unescaped: Option<String> = None;
+    let mut chars = contents.char_indices().peekable();
+    while let Some((i, chr)) = chars.next() {
+        match chr {
+            '"' => {
+                let arg = match unescaped {
+                    Some(arg) => Cow::Owned(arg),
+                    None => Cow::Borrowed(&contents[..i]),
+                };
+                return Ok((&contents[i + 1..], arg));
+            }
+            '\\' if matches!(chars.peek(), Some((_, '"' | '\\'))) => {
+                let (_, escaped) = chars.next().unwrap();
+                unescaped
+                    .get_or_insert_with(|| contents[..i].to_owned())
+                    .push(escaped);
+            }
+            chr if !chr.is_ascii() || chr.is_ascii_control() => break,
+            chr => {
+                if let Some(arg) = &mut unescaped {
+                    arg.push(chr);
+                }
+            }
+        }
+    }
+
+    Err(error())
+}
+
 /// Match a basic argument or a quoted string.
-pub fn arg(input: &str) -> nom::IResult<&str, &str> {
-    alt((quoted, basic_arg))(input)
+pub fn arg(input: &str) -> nom::IResult<&str, Cow<'_, str>> {
+    alt((quoted_arg, basic_arg.map(Cow::Borrowed)))(input)
 }
 
 /// Match a command terminator.
@@ -152,7 +190,7 @@ pub fn terminated_command(input: &str) -> nom::IResult<&str, RunCmd> {
 pub fn command(input: &str) -> nom::IResult<&str, RunCmd> {
     tuple((
         command_name,
-        many0(preceded(space0, arg.map(|arg| arg.to_owned()))),
+        many0(preceded(space0, arg.map(Cow::into_owned))),
     ))
     .map(|(cmd, rest)| RunCmd(cmd, rest.into()))
     .parse(input)
@@ -236,13 +274,25 @@ mod test {
     #[test]
     fn test_arg_basic() {
         let result = arg("basic_arg \t;");
-        assert_eq!(result, Ok((" \t;", "basic_arg")));
+        assert_eq!(result, Ok((" \t;", "basic_arg".into())));
     }
 
     #[test]
     fn test_quoted_arg() {
         let result = arg("\"quoted argument\";\n");
-        assert_eq!(result, Ok((";\n", "quoted argument")));
+        assert_eq!(result, Ok((";\n", "quoted argument".into())));
+    }
+
+    #[test]
+    fn test_quoted_arg_escapes() {
+        let result = arg(r#""say \"hi\" \\o/" rest"#);
+        assert_eq!(result, Ok((" rest", r#"say "hi" \o/"#.into())));
+
+        let result = arg(r#""maps\e1m1""#);
+        assert_eq!(result, Ok(("", r#"maps\e1m1"#.into())));
+
+        assert_eq!(arg(r#""""#), Ok(("", "".into())));
+        assert!(arg(r#""unterminated\""#).is_err());
     }
 
     #[test]
@@ -0,0 +1,195 @@
+//! Quake savegame (`.sav`) files.
+//!
+//! Savegames are plain text: a version number, a description of the save, the player's spawn
+//! parameters, skill, map name and server time, followed by the light styles and entity state.
+
+use std::{
+    fmt,
+    io::{self, BufRead},
+};
+
+use thiserror::Error;
+
+/// The only savegame version written by the original engine.
+pub const SAVEGAME_VERSION: i32 = 5;
+
+/// The number of save slots available in the menu.
+pub const MAX_SAVEGAMES: usize = 12;
+
+/// The number of spawn parameters stored for the player.
+pub const NUM_SPAWN_PARMS: usize = 16;
+
+/// The length of the level name part of the savegame comment.
+const COMMENT_LEVEL_NAME_LEN: usize = 22;
+
+#[derive(Error, Debug)]
+pub enum SaveError {
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Unexpected end of savegame")]
+    UnexpectedEof,
+    #[error("Savegame is version {0}, expected {}", SAVEGAME_VERSION)]
+    Version(i32),
+    #[error("Invalid savegame field \"{0}\"")]
+    InvalidField(String),
+}
+
+/// The file name of the savegame in `slot`.
+pub fn slot_filename(slot: usize) -> String {
+    format!("s{}.sav", slot)
+}
+
+/// Escapes newlines and backslashes in a value, which would otherwise end the line it's on.
+pub fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('\n', "\\n")
+}
+
+/// Undoes [`escape`] in the same way as the original engine, which turns `\n` into a newline and
+/// a backslash followed by anything else into a single backslash.
+pub fn unescape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n')) => {
+                chars.next();
+                out.push('\n');
+            }
+            ('\\', Some(_)) => {
+                chars.next();
+                out.push('\\');
+            }
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// The information at the start of a savegame, which is enough to describe it in the menu.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SaveHeader {
+    /// The level name followed by the kill count, e.g. `the Slipgate Complex   kills:  3/ 30`.
+    pub comment: String,
+    pub spawn_parms: [f32; NUM_SPAWN_PARMS],
+    pub skill: i32,
+    pub map_name: String,
+    /// The server time when the game was saved, in seconds.
+    pub time: f32,
+}
+
+impl SaveHeader {
+    pub fn read<R>(reader: &mut R) -> Result<SaveHeader, SaveError>
+    where
+        R: BufRead,
+    {
+        let mut next_line = || -> Result<String, SaveError> {
+            let mut line = String::new();
+            if reader.read_line(&mut line)? == 0 {
+                return Err(SaveError::UnexpectedEof);
+            }
+
+            Ok(line.trim_end_matches(['\r', '\n']).to_owned())
+        };
+
+        fn parse<T: std::str::FromStr>(s: String) -> Result<T, SaveError> {
+            s.trim().parse().map_err(|_| SaveError::InvalidField(s))
+        }
+
+        let version: i32 = parse(next_line()?)?;
+        if version != SAVEGAME_VERSION {
+            return Err(SaveError::Version(version));
+        }
+
+        // spaces are replaced with underscores so that the comment can be read with `fscanf`
+        let comment = unescape(&next_line()?).replace('_', " ");
+
+        let mut spawn_parms = [0.0; NUM_SPAWN_PARMS];
+        for parm in spawn_parms.iter_mut() {
+            *parm = parse(next_line()?)?;
+        }
+
+        // the skill is written as a float by some engines
+        let skill = parse::<f32>(next_line()?)? as i32;
+        let map_name = unescape(next_line()?.trim());
+        let time = parse(next_line()?)?;
+
+        Ok(SaveHeader {
+            comment,
+            spawn_parms,
+            skill,
+            map_name,
+            time,
+        })
+    }
+
+    /// The name of the level, without the kill count.
+    pub fn level_name(&self) -> &str {
+        let end = self
+            .comment
+            .char_indices()
+            .nth(COMMENT_LEVEL_NAME_LEN)
+            .map(|(i, _)| i)
+            .unwrap_or(self.comment.len());
+
+        self.comment[..end].trim()
+    }
+}
+
+impl fmt::Display for SaveHeader {
+    /// Writes the header as it's read by [`SaveHeader::read`], one field per line.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", SAVEGAME_VERSION)?;
+        writeln!(f, "{}", escape(&self.comment.replace(' ', "_")))?;
+        for parm in self.spawn_parms {
+            writeln!(f, "{:.6}", parm)?;
+        }
+        writeln!(f, "{}", self.skill)?;
+        writeln!(f, "{}", escape(&self.map_name))?;
+        writeln!(f, "{:.6}", self.time)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_read_header() {
+        let mut src = String::from("5\nthe_Slipgate_Complex___kills:__3/_30\n");
+        for i in 0..NUM_SPAWN_PARMS {
+            src.push_str(&format!("{}.000000\n", i));
+        }
+        src.push_str("1\ne1m1\n123.500000\nm\n");
+
+        let header = SaveHeader::read(&mut src.as_bytes()).unwrap();
+        assert_eq!(header.comment, "the Slipgate Complex   kills:  3/ 30");
+        assert_eq!(header.level_name(), "the Slipgate Complex");
+        assert_eq!(header.spawn_parms[3], 3.0);
+        assert_eq!(header.skill, 1);
+        assert_eq!(header.map_name, "e1m1");
+        assert_eq!(header.time, 123.5);
+    }
+
+    #[test]
+    fn test_header_round_trip() {
+        let header = SaveHeader {
+            comment: "a \\ level".to_owned(),
+            spawn_parms: [2.0; NUM_SPAWN_PARMS],
+            skill: 3,
+            map_name: "two\nlines".to_owned(),
+            time: 1.5,
+        };
+
+        let text = header.to_string();
+        assert!(text.starts_with("5\na_\\\\_level\n"));
+        assert_eq!(SaveHeader::read(&mut text.as_bytes()).unwrap(), header);
+    }
+
+    #[test]
+    fn test_read_header_bad_version() {
+        assert!(matches!(
+            SaveHeader::read(&mut "6\n".as_bytes()),
+            Err(SaveError::Version(6))
+        ));
+    }
+}
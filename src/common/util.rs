@@ -233,6 +233,13 @@ where
     })
 }
 
+/// The name of the file at `path`, without its directories or `extension`, e.g. `e1m1` for
+/// `maps/e1m1.bsp` and `.bsp`.
+pub fn file_stem<'a>(path: &'a str, extension: &str) -> &'a str {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    name.strip_suffix(extension).unwrap_or(name)
+}
+
 pub unsafe fn any_as_bytes<T>(t: &T) -> &[u8]
 where
     T: Pod,
@@ -14,19 +14,18 @@
 // NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM,
 // DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
-use bevy::{prelude::*, render::extract_resource::ExtractResource};
+use bevy::{asset::LoadState, prelude::*, render::extract_resource::ExtractResource};
 use std::{
-    fs::{File, OpenOptions},
-    io::{self, BufReader, BufWriter, Cursor, Read, Seek, SeekFrom},
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, BufWriter, Cursor, Read, Seek, SeekFrom, Write},
     iter,
-    path::{Path, PathBuf},
+    path::{Component, Path, PathBuf},
     sync::Arc,
 };
 
-use crate::{
-    client::SeismonGameSettings,
-    common::pak::{Pak, PakError},
-};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::client::SeismonGameSettings;
+use crate::common::pak::{Pak, PakError};
 
 use thiserror::Error;
 
@@ -36,12 +35,119 @@ pub enum VfsError {
     Pak(#[from] PakError),
     #[error("File does not exist: {0}")]
     NoSuchFile(String),
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+    #[error("`{0}/` directory does not exist")]
+    NoSuchGame(String),
+    #[error("{0} is outside the game directory")]
+    OutsideGameDir(String),
+}
+
+/// Check that a path to write to stays inside the game directory, so that commands like `condump`
+/// can't be used to overwrite arbitrary files.
+fn check_writable_path(virtual_path: &str) -> Result<(), VfsError> {
+    let inside = Path::new(virtual_path)
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+    if inside {
+        Ok(())
+    } else {
+        Err(VfsError::OutsideGameDir(virtual_path.to_owned()))
+    }
+}
+
+/// The game directory that is always loaded, with any other game on top of it.
+pub const BASE_GAME: &str = "id1";
+
+/// Whether `path` holds game data: pakfiles or a `progs.dat`.
+fn is_game_dir(path: &Path) -> bool {
+    let Ok(entries) = fs::read_dir(path) else {
+        return false;
+    };
+
+    entries.filter_map(Result::ok).any(|entry| {
+        let name = entry.file_name().to_string_lossy().to_ascii_lowercase();
+        name == "progs.dat" || (name.starts_with("pak") && name.ends_with(".pak"))
+    })
+}
+
+/// List the game directories in `base_dir`, including the base game, sorted by name.
+pub fn find_games(base_dir: &Path) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(base_dir) else {
+        return Vec::new();
+    };
+
+    let mut games = entries
+        .filter_map(Result::ok)
+        .filter(|entry| is_game_dir(&entry.path()))
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .collect::<Vec<_>>();
+    games.sort();
+
+    games
 }
 
 #[derive(Debug)]
 enum VfsComponent {
     Pak(Pak),
     Directory(PathBuf),
+    /// The browser's local storage, which holds the files the game writes on the web, like
+    /// `config.cfg`.
+    #[cfg(target_arch = "wasm32")]
+    Storage,
+}
+
+/// A pakfile or directory mounted in the VFS.
+pub enum Mount<'a> {
+    Pak(&'a Pak),
+    Directory(&'a Path),
+    #[cfg(target_arch = "wasm32")]
+    Storage,
+}
+
+/// The browser's local storage, where files are stored as text under their virtual path.
+#[cfg(target_arch = "wasm32")]
+mod storage {
+    use std::io;
+
+    /// Prefixed to the virtual paths, so that other pages on the same origin don't clash.
+    const KEY_PREFIX: &str = "seismon/";
+
+    fn local_storage() -> io::Result<web_sys::Storage> {
+        web_sys::window()
+            .and_then(|window| window.local_storage().ok().flatten())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::Unsupported, "no local storage"))
+    }
+
+    fn js_error(e: wasm_bindgen::JsValue) -> io::Error {
+        io::Error::new(io::ErrorKind::Other, format!("{:?}", e))
+    }
+
+    pub fn get(virtual_path: &str) -> Option<String> {
+        local_storage()
+            .ok()?
+            .get_item(&format!("{}{}", KEY_PREFIX, virtual_path))
+            .ok()
+            .flatten()
+    }
+
+    pub fn set(virtual_path: &str, contents: &str) -> io::Result<()> {
+        local_storage()?
+            .set_item(&format!("{}{}", KEY_PREFIX, virtual_path), contents)
+            .map_err(js_error)
+    }
+
+    /// Returns whether there was anything to remove.
+    pub fn remove(virtual_path: &str) -> io::Result<bool> {
+        if get(virtual_path).is_none() {
+            return Ok(false);
+        }
+
+        local_storage()?
+            .remove_item(&format!("{}{}", KEY_PREFIX, virtual_path))
+            .map_err(js_error)?;
+        Ok(true)
+    }
 }
 
 #[derive(Clone, Debug, Resource, ExtractResource)]
@@ -50,6 +156,16 @@ pub struct Vfs {
 }
 
 impl FromWorld for Vfs {
+    #[cfg(target_arch = "wasm32")]
+    fn from_world(_world: &mut World) -> Self {
+        // there's no filesystem on the web, the paks are mounted as `PendingPaks` downloads them
+        // and the game's files are written to the browser's storage
+        Vfs {
+            components: vec![VfsComponent::Storage.into()],
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
     fn from_world(world: &mut World) -> Self {
         if let Some(settings) = world.get_resource::<SeismonGameSettings>() {
             Self::with_base_dir(settings.base_dir.clone(), settings.game.as_deref())
@@ -67,7 +183,30 @@ impl Vfs {
     }
 
     /// Initializes the virtual filesystem using a base directory.
+    ///
+    /// Exits the process if the base game or `game` doesn't exist.
     pub fn with_base_dir(base_dir: PathBuf, game: Option<&str>) -> Vfs {
+        match Vfs::try_with_base_dir(base_dir, game) {
+            Ok(vfs) => vfs,
+            Err(VfsError::NoSuchGame(game)) => {
+                error!(
+                    "`{0}/` directory does not exist! Use the `--base-dir` option with the name of the directory which contains `{0}/`.",
+                    game
+                );
+
+                std::process::exit(1);
+            }
+            Err(e) => {
+                error!("{}", e);
+
+                std::process::exit(1);
+            }
+        }
+    }
+
+    /// Initializes the virtual filesystem using a base directory, returning an error if the base
+    /// game or `game` doesn't exist.
+    pub fn try_with_base_dir(base_dir: PathBuf, game: Option<&str>) -> Result<Vfs, VfsError> {
         let mut vfs = Vfs::new();
 
         let mut quake_dir = base_dir;
@@ -77,25 +216,15 @@ impl Vfs {
             game_dir
         });
 
-        quake_dir.push("id1");
+        quake_dir.push(BASE_GAME);
 
         if !quake_dir.is_dir() {
-            error!(concat!(
-                "`id1/` directory does not exist! Use the `--base-dir` option with the name of the",
-                " directory which contains `id1/`."
-            ));
-
-            std::process::exit(1);
+            return Err(VfsError::NoSuchGame(BASE_GAME.to_owned()));
         }
 
-        if let Some(game_dir) = &game_dir {
+        if let (Some(game), Some(game_dir)) = (game, &game_dir) {
             if !game_dir.is_dir() {
-                error!(
-                        "`{0}/` directory does not exist! Use the `--base-dir` option with the name of the directory which contains `{0}/`.",
-                        game.unwrap()
-                    );
-
-                std::process::exit(1);
+                return Err(VfsError::NoSuchGame(game.to_owned()));
             }
         }
 
@@ -117,7 +246,7 @@ impl Vfs {
                     }
                 }
 
-                vfs.add_pakfile(&pak_path).unwrap();
+                vfs.add_pakfile(&pak_path)?;
                 num_paks += 1;
 
                 // Remove the file name, leaving the game directory.
@@ -126,14 +255,14 @@ impl Vfs {
 
             // Allow files in id1 dir to overwrite files in paks (unsure if this is correct for Quake
             // but it's a nice feature)
-            vfs.add_directory(&pak_path).unwrap();
+            vfs.add_directory(&pak_path)?;
         }
 
         if num_paks == 0 {
             warn!("No PAK files found.");
         }
 
-        vfs
+        Ok(vfs)
     }
 
     pub fn add_pakfile<P>(&mut self, path: P) -> Result<(), VfsError>
@@ -141,11 +270,21 @@ impl Vfs {
         P: AsRef<Path>,
     {
         let path = path.as_ref();
-        self.components
-            .push(VfsComponent::Pak(Pak::new(path)?).into());
+        self.add_pak(Pak::new(path)?);
         Ok(())
     }
 
+    /// Mount an already-loaded pakfile above the existing components, except for the browser's
+    /// storage, whose files stay above every pak like a game directory's.
+    pub fn add_pak(&mut self, pak: Pak) {
+        let index = match self.components.last().map(|c| &**c) {
+            #[cfg(target_arch = "wasm32")]
+            Some(VfsComponent::Storage) => self.components.len() - 1,
+            _ => self.components.len(),
+        };
+        self.components.insert(index, VfsComponent::Pak(pak).into());
+    }
+
     pub fn add_directory<P>(&mut self, path: P) -> Result<(), VfsError>
     where
         P: AsRef<Path>,
@@ -155,6 +294,16 @@ impl Vfs {
         Ok(())
     }
 
+    /// The mounted paks and directories, with the ones whose files take precedence last.
+    pub fn mounts(&self) -> impl Iterator<Item = Mount<'_>> + '_ {
+        self.components.iter().map(|c| match &**c {
+            VfsComponent::Pak(pak) => Mount::Pak(pak),
+            VfsComponent::Directory(path) => Mount::Directory(path),
+            #[cfg(target_arch = "wasm32")]
+            VfsComponent::Storage => Mount::Storage,
+        })
+    }
+
     pub fn open<S>(&self, virtual_path: S) -> Result<VirtualFile, VfsError>
     where
         S: AsRef<str>,
@@ -178,19 +327,67 @@ impl Vfs {
                         return Ok(VirtualFile::FileBacked(BufReader::new(f)));
                     }
                 }
+
+                #[cfg(target_arch = "wasm32")]
+                VfsComponent::Storage => {
+                    if let Some(contents) = storage::get(vp) {
+                        return Ok(VirtualFile::StorageBacked(Cursor::new(
+                            contents.into_bytes(),
+                        )));
+                    }
+                }
             }
         }
 
         Err(VfsError::NoSuchFile(vp.to_owned()))
     }
 
-    pub fn write<S>(&self, virtual_path: S) -> Result<BufWriter<File>, VfsError>
+    pub fn write<S>(&self, virtual_path: S) -> Result<VfsWriter, VfsError>
     where
         S: AsRef<str>,
     {
         let vp = virtual_path.as_ref();
+        check_writable_path(vp)?;
 
         // iterate in reverse so later PAKs overwrite earlier ones
+        for c in self.components.iter().rev() {
+            match &**c {
+                VfsComponent::Pak(_) => {}
+
+                #[cfg(target_arch = "wasm32")]
+                VfsComponent::Storage => {
+                    return Ok(VfsWriter::Storage {
+                        virtual_path: vp.to_owned(),
+                        contents: Vec::new(),
+                    })
+                }
+
+                VfsComponent::Directory(path) => {
+                    let mut full_path = path.to_owned();
+                    full_path.push(vp);
+
+                    if let Ok(f) = OpenOptions::new()
+                        .write(true)
+                        .create(true)
+                        .truncate(true)
+                        .open(full_path)
+                    {
+                        return Ok(VfsWriter::File(BufWriter::new(f)));
+                    }
+                }
+            }
+        }
+
+        Err(VfsError::NoSuchFile(vp.to_owned()))
+    }
+
+    /// Delete a file from the filesystem. Files inside PAKs can't be removed.
+    pub fn remove<S>(&self, virtual_path: S) -> Result<(), VfsError>
+    where
+        S: AsRef<str>,
+    {
+        let vp = virtual_path.as_ref();
+
         for c in self.components.iter().rev() {
             match &**c {
                 VfsComponent::Pak(_) => {}
@@ -198,8 +395,16 @@ impl Vfs {
                     let mut full_path = path.to_owned();
                     full_path.push(vp);
 
-                    if let Ok(f) = OpenOptions::new().write(true).create(true).open(full_path) {
-                        return Ok(BufWriter::new(f));
+                    if full_path.is_file() {
+                        fs::remove_file(full_path)?;
+                        return Ok(());
+                    }
+                }
+
+                #[cfg(target_arch = "wasm32")]
+                VfsComponent::Storage => {
+                    if storage::remove(vp)? {
+                        return Ok(());
                     }
                 }
             }
@@ -215,17 +420,20 @@ impl Vfs {
         S: AsRef<str>,
     {
         let vp = virtual_path.as_ref();
+        check_writable_path(vp)?;
 
         // iterate in reverse so later PAKs overwrite earlier ones
         for c in self.components.iter().rev() {
             match &**c {
-                VfsComponent::Pak(_) => {}
                 VfsComponent::Directory(path) => {
                     let mut full_path = path.to_owned();
                     full_path.push(vp);
 
                     return Ok(full_path);
                 }
+
+                // anything else has no path to give
+                _ => {}
             }
         }
 
@@ -233,9 +441,133 @@ impl Vfs {
     }
 }
 
+/// Pakfiles being downloaded through the asset server, for platforms without a filesystem.
+///
+/// The paks are requested one at a time, `pak0.pak` upwards, first for the base game and then for
+/// the mod, stopping at the first one that doesn't exist. Each is mounted as soon as it arrives so
+/// that they're layered in the same order as on the desktop.
+#[derive(Resource)]
+pub struct PendingPaks {
+    games: Vec<String>,
+    game_id: usize,
+    pak_id: usize,
+    current: Handle<Pak>,
+}
+
+impl PendingPaks {
+    pub fn new(asset_server: &AssetServer, game: Option<&str>) -> PendingPaks {
+        let games: Vec<String> = iter::once(BASE_GAME)
+            .chain(game)
+            .map(ToOwned::to_owned)
+            .collect();
+        let current = asset_server.load(Self::pak_path(&games[0], 0));
+
+        PendingPaks {
+            games,
+            game_id: 0,
+            pak_id: 0,
+            current,
+        }
+    }
+
+    fn pak_path(game: &str, pak_id: usize) -> String {
+        format!("{}/pak{}.pak", game, pak_id)
+    }
+
+    /// Request the next pak, returning `false` once every game has been loaded.
+    fn advance(&mut self, asset_server: &AssetServer, found: bool) -> bool {
+        if found && self.pak_id + 1 < crate::common::MAX_PAKFILES {
+            self.pak_id += 1;
+        } else {
+            self.game_id += 1;
+            self.pak_id = 0;
+        }
+
+        match self.games.get(self.game_id) {
+            Some(game) => {
+                self.current = asset_server.load(Self::pak_path(game, self.pak_id));
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+pub mod systems {
+    use super::*;
+
+    pub fn mount_pending_paks(
+        mut commands: Commands,
+        mut pending: ResMut<PendingPaks>,
+        asset_server: Res<AssetServer>,
+        mut paks: ResMut<Assets<Pak>>,
+        mut vfs: ResMut<Vfs>,
+    ) {
+        let pak_id = pending.current.id();
+        let found = match asset_server.get_load_state(pak_id) {
+            Some(LoadState::Loaded) => match paks.remove(pak_id) {
+                Some(pak) => {
+                    vfs.add_pak(pak);
+                    true
+                }
+                None => false,
+            },
+            Some(LoadState::Failed) => false,
+            _ => return,
+        };
+
+        if !pending.advance(&asset_server, found) {
+            commands.remove_resource::<PendingPaks>();
+        }
+    }
+}
+
+/// A file being written through the VFS.
+pub enum VfsWriter {
+    File(BufWriter<File>),
+    /// Stored in the browser's local storage on `flush`, or when dropped. Local storage only holds
+    /// text, so anything that isn't UTF-8 is stored lossily.
+    #[cfg(target_arch = "wasm32")]
+    Storage {
+        virtual_path: String,
+        contents: Vec<u8>,
+    },
+}
+
+impl Write for VfsWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            VfsWriter::File(file) => file.write(buf),
+            #[cfg(target_arch = "wasm32")]
+            VfsWriter::Storage { contents, .. } => contents.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            VfsWriter::File(file) => file.flush(),
+            #[cfg(target_arch = "wasm32")]
+            VfsWriter::Storage {
+                virtual_path,
+                contents,
+            } => storage::set(virtual_path, &String::from_utf8_lossy(contents)),
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Drop for VfsWriter {
+    fn drop(&mut self) {
+        // like `BufWriter`, errors on drop are ignored
+        let _ = self.flush();
+    }
+}
+
 pub enum VirtualFile<'a> {
     PakBacked(Cursor<&'a [u8]>),
     FileBacked(BufReader<File>),
+    #[cfg(target_arch = "wasm32")]
+    StorageBacked(Cursor<Vec<u8>>),
 }
 
 impl<'a> Read for VirtualFile<'a> {
@@ -243,6 +575,8 @@ impl<'a> Read for VirtualFile<'a> {
         match self {
             VirtualFile::PakBacked(curs) => curs.read(buf),
             VirtualFile::FileBacked(file) => file.read(buf),
+            #[cfg(target_arch = "wasm32")]
+            VirtualFile::StorageBacked(curs) => curs.read(buf),
         }
     }
 }
@@ -252,6 +586,8 @@ impl<'a> Seek for VirtualFile<'a> {
         match self {
             VirtualFile::PakBacked(curs) => curs.seek(pos),
             VirtualFile::FileBacked(file) => file.seek(pos),
+            #[cfg(target_arch = "wasm32")]
+            VirtualFile::StorageBacked(curs) => curs.seek(pos),
         }
     }
 }
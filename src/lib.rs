@@ -26,6 +26,17 @@
 // TODO: Is this necessary?
 #![recursion_limit = "256"]
 
+/// Enter a profiling span which lasts until the end of the enclosing scope.
+///
+/// Spans are only recorded with the `profiling` feature, which sends Bevy's tracing output to
+/// Tracy. Without it this expands to nothing.
+macro_rules! profile_span {
+    ($name:literal) => {
+        #[cfg(feature = "profiling")]
+        let _span = ::bevy::utils::tracing::info_span!($name).entered();
+    };
+}
+
 pub mod client;
 pub mod common;
 pub mod server;
@@ -1,18 +1,19 @@
 use std::path::PathBuf;
 
-use bevy::prelude::*;
+use bevy::{prelude::*, tasks::ComputeTaskPool};
 use clap::Parser;
 use failure::Error;
 
 use crate::{
     client::{input::InputFocus, Connection, ConnectionState},
     common::{
-        console::{ExecResult, RegisterCmdExt},
-        net::{ClientMessage, ServerMessage, SignOnStage},
+        bsp::LevelCache,
+        console::{quote_if_needed, ExecResult, RegisterCmdExt},
+        net::{ClientMessage, ServerMessage, SignOnStage, MAX_CLIENTS},
     },
 };
 
-use super::*;
+use super::{save::SaveGame, *};
 
 pub fn register_commands(app: &mut App) {
     // TODO: Implement `changelevel` (move to new level without resetting persistant state
@@ -23,6 +24,167 @@ pub fn register_commands(app: &mut App) {
             default()
         }
     }));
+    app.command(cmd_save);
+    app.command(cmd_load.map(|res| -> ExecResult {
+        if let Err(e) = res {
+            format!("load: {}", e).into()
+        } else {
+            default()
+        }
+    }));
+    app.command(cmd_prefetch);
+    app.command(cmd_setpos);
+    app.command(cmd_checksums);
+    app.command(cmd_serverinfo);
+}
+
+/// Turn a map name as typed at the console into its path in the VFS.
+fn map_path(mut map_name: PathBuf) -> String {
+    if map_name.extension().is_none() {
+        map_name.set_extension("bsp");
+    }
+
+    let mut path = PathBuf::from("maps");
+    path.push(map_name);
+
+    format!("{}", path.display())
+}
+
+#[derive(Parser)]
+#[command(
+    name = "prefetch",
+    about = "Load a map in the background so that changing to it is quicker"
+)]
+struct Prefetch {
+    map_name: PathBuf,
+}
+
+fn cmd_prefetch(
+    In(Prefetch { map_name }): In<Prefetch>,
+    vfs: Res<Vfs>,
+    levels: Res<LevelCache>,
+) -> ExecResult {
+    levels.prefetch(&vfs, &map_path(map_name));
+    default()
+}
+
+#[derive(Parser)]
+#[command(
+    name = "setpos",
+    about = "Move the local player to a position, optionally setting the view angles (requires sv_cheats)",
+    allow_negative_numbers = true
+)]
+struct SetPos {
+    x: f32,
+    y: f32,
+    z: f32,
+    #[arg(requires = "yaw")]
+    pitch: Option<f32>,
+    yaw: Option<f32>,
+}
+
+fn cmd_setpos(
+    In(SetPos {
+        x,
+        y,
+        z,
+        pitch,
+        yaw,
+    }): In<SetPos>,
+    session: Option<ResMut<Session>>,
+    mut registry: ResMut<Registry>,
+    vfs: Res<Vfs>,
+) -> ExecResult {
+    if registry.read_cvar::<u8>("sv_cheats").unwrap_or(0) == 0 {
+        return "setpos is a cheat, set sv_cheats 1 to use it".into();
+    }
+
+    let Some(mut session) = session.filter(|s| !s.loading()) else {
+        return "setpos: not running a server".into();
+    };
+    let Session { persist, level, .. } = &mut *session;
+
+    // the local player always takes the first slot
+    let Some(ent_id) = persist
+        .client_slots
+        .active_clients()
+        .next()
+        .and_then(|id| persist.client(id))
+        .and_then(|c| c.entity())
+    else {
+        return "setpos: no local player".into();
+    };
+
+    let angles = pitch.zip(yaw).map(|(pitch, yaw)| [pitch, yaw, 0.0]);
+    if let Err(e) = level.teleport_entity(
+        ent_id,
+        Vector3::new(x, y, z),
+        angles,
+        registry.reborrow(),
+        &vfs,
+    ) {
+        return format!("setpos: {}", e).into();
+    }
+
+    default()
+}
+
+#[derive(Parser)]
+#[command(
+    name = "sv_checksums",
+    about = "Print the latest state checksum, or write every checksum for this level to a file (requires sv_deterministic)"
+)]
+struct Checksums {
+    file: Option<PathBuf>,
+}
+
+fn cmd_checksums(
+    In(Checksums { file }): In<Checksums>,
+    session: Option<Res<Session>>,
+) -> ExecResult {
+    let Some(session) = session else {
+        return "sv_checksums: not running a server".into();
+    };
+    let level = session.level();
+    let Some(checksums) = level.checksums() else {
+        return "sv_checksums: this level isn't deterministic, set sv_deterministic 1 and restart the map".into();
+    };
+
+    match file {
+        None => match checksums.last() {
+            Some(checksum) => format!("tick {}: {:016x}", level.tick(), checksum).into(),
+            None => "sv_checksums: no ticks have run yet".into(),
+        },
+        Some(path) => {
+            let mut out = String::new();
+            for (tick, checksum) in checksums.iter().enumerate() {
+                out.push_str(&format!("{} {:016x}\n", tick + 1, checksum));
+            }
+
+            match std::fs::write(&path, out) {
+                Ok(()) => {
+                    format!("Wrote {} checksums to {}", checksums.len(), path.display()).into()
+                }
+                Err(e) => format!("sv_checksums: {}", e).into(),
+            }
+        }
+    }
+}
+
+#[derive(Parser)]
+#[command(
+    name = "serverinfo",
+    about = "Print the cvars which describe the server"
+)]
+struct ServerInfo;
+
+fn cmd_serverinfo(In(ServerInfo): In<ServerInfo>, registry: Res<Registry>) -> ExecResult {
+    let mut out = String::new();
+    for (name, value) in registry.serverinfo() {
+        out.push_str(&format!("{:<16} {}\n", name, quote_if_needed(&value)));
+    }
+
+    out.into()
 }
 
 #[derive(Parser)]
@@ -32,40 +194,87 @@ struct Map {
 }
 
 fn cmd_map(
-    In(Map { mut map_name }): In<Map>,
+    In(Map { map_name }): In<Map>,
     mut commands: Commands,
     session: Option<ResMut<Session>>,
-    mut focus: ResMut<InputFocus>,
+    focus: Option<ResMut<InputFocus>>,
     vfs: Res<Vfs>,
     mut registry: ResMut<Registry>,
     mut client_events: ResMut<Events<ClientMessage>>,
     mut server_events: ResMut<Events<ServerMessage>>,
+    levels: Res<LevelCache>,
 ) -> Result<(), Error> {
-    if map_name.extension().is_none() {
-        map_name.set_extension("bsp");
-    }
+    let new_session = new_session(&map_path(map_name), registry.reborrow(), &vfs, &levels)?;
 
-    let mut path = PathBuf::from("maps");
-    path.push(map_name);
+    start_session(
+        new_session,
+        &mut commands,
+        session,
+        focus,
+        &mut client_events,
+        &mut server_events,
+    );
 
-    let bsp_name = format!("{}", path.display());
-    let bsp = vfs.open(&bsp_name)?;
-    let (models, entmap) = crate::common::bsp::load(bsp)?;
-    let progs = vfs.open("progs.dat")?;
-    let progs = crate::server::progs::load(progs)?;
+    Ok(())
+}
 
-    // TODO: Make `max_clients` a cvar
-    let new_session = Session::new(
-        bsp_name,
-        8,
-        registry.reborrow(),
-        &*vfs,
+/// Load a map and the progs and spawn the map's entities.
+fn new_session(
+    bsp_name: &str,
+    registry: Mut<Registry>,
+    vfs: &Vfs,
+    levels: &LevelCache,
+) -> Result<Session, Error> {
+    // the progs don't depend on the level, so load them on the task pool while the level is
+    // parsed
+    let mut level = None;
+    let mut progs = ComputeTaskPool::get().scope(|scope| {
+        scope.spawn(async {
+            let progs = vfs.open("progs.dat")?;
+            Ok::<_, Error>(crate::server::progs::load(progs)?)
+        });
+
+        // this is instant if the level was prefetched
+        level = Some(levels.load(vfs, bsp_name));
+    });
+    let (models, entmap) = level.unwrap()?;
+    let progs = progs.pop().unwrap()?;
+
+    let max_clients = registry
+        .read_cvar::<usize>("maxplayers")
+        .unwrap_or(1)
+        .clamp(1, MAX_CLIENTS);
+    Ok(Session::new(
+        bsp_name.to_owned(),
+        max_clients,
+        registry,
+        vfs,
         progs,
         models,
         entmap,
-    );
+    ))
+}
+
+/// Replace the running server, if there is one, and connect the local client to the new one. Remote
+/// clients of the old server are kept, and sign on again once the new level has loaded.
+///
+/// A dedicated server has no local client, which is known by there being no `InputFocus`.
+fn start_session(
+    mut new_session: Session,
+    commands: &mut Commands,
+    session: Option<ResMut<Session>>,
+    focus: Option<ResMut<InputFocus>>,
+    client_events: &mut Events<ClientMessage>,
+    server_events: &mut Events<ServerMessage>,
+) {
+    // the local player always takes the first slot, which is reserved before any remote clients
+    // can connect
+    if focus.is_some() {
+        new_session.new_client();
+    }
 
     if let Some(mut session) = session {
+        new_session.take_clients(&mut session);
         *session = new_session;
     } else {
         commands.insert_resource(new_session);
@@ -74,10 +283,129 @@ fn cmd_map(
     client_events.clear();
     server_events.clear();
 
+    let Some(mut focus) = focus else {
+        return;
+    };
+
     // TODO: This should not be handled here, server and client should be decoupled
     commands.insert_resource(Connection::new_server());
     commands.insert_resource(ConnectionState::SignOn(SignOnStage::Not));
     *focus = InputFocus::Game;
+}
+
+/// Turn a savegame name as typed at the console into its path in the game directory.
+fn save_path(vfs: &Vfs, mut name: PathBuf) -> Result<PathBuf, String> {
+    if name
+        .components()
+        .any(|c| !matches!(c, std::path::Component::Normal(_)))
+    {
+        return Err("relative pathnames are not allowed".into());
+    }
+
+    if name.extension().is_none() {
+        name.set_extension("sav");
+    }
+
+    vfs.find_writable_filename(format!("{}", name.display()))
+        .map_err(|e| format!("{}", e))
+}
+
+#[derive(Parser)]
+#[command(
+    name = "save",
+    about = "Save the game, so that it can be restored with `load`"
+)]
+struct Save {
+    name: PathBuf,
+}
+
+fn cmd_save(
+    In(Save { name }): In<Save>,
+    session: Option<Res<Session>>,
+    registry: Res<Registry>,
+    vfs: Res<Vfs>,
+) -> ExecResult {
+    let Some(session) = session.filter(|s| !s.loading()) else {
+        return "save: not playing a local game".into();
+    };
+
+    let mut players = session.persist.client_slots.active_clients();
+    let Some(player) = players
+        .next()
+        .and_then(|id| session.client(id))
+        .and_then(|c| c.entity())
+    else {
+        return "save: not playing a local game".into();
+    };
+    if players.next().is_some() {
+        return "save: can't save multiplayer games".into();
+    }
+
+    match session.level.is_dead(player) {
+        Ok(false) => (),
+        Ok(true) => return "save: can't save with a dead player".into(),
+        Err(e) => return format!("save: {}", e).into(),
+    }
+
+    let path = match save_path(&vfs, name) {
+        Ok(path) => path,
+        Err(e) => return format!("save: {}", e).into(),
+    };
+
+    let skill = registry.read_cvar::<f32>("skill").unwrap_or(1.0) as i32;
+    let save = match session.level.save_game(skill) {
+        Ok(save) => save,
+        Err(e) => return format!("save: {}", e).into(),
+    };
+
+    match std::fs::write(&path, save.to_string()) {
+        Ok(()) => format!("Saved game to {}", path.display()).into(),
+        Err(e) => format!("save: couldn't write {}: {}", path.display(), e).into(),
+    }
+}
+
+#[derive(Parser)]
+#[command(name = "load", about = "Restore a game saved with `save`")]
+struct Load {
+    name: PathBuf,
+}
+
+fn cmd_load(
+    In(Load { name }): In<Load>,
+    mut commands: Commands,
+    session: Option<ResMut<Session>>,
+    focus: Option<ResMut<InputFocus>>,
+    vfs: Res<Vfs>,
+    mut registry: ResMut<Registry>,
+    mut client_events: ResMut<Events<ClientMessage>>,
+    mut server_events: ResMut<Events<ServerMessage>>,
+    levels: Res<LevelCache>,
+) -> Result<(), Error> {
+    let path = save_path(&vfs, name).map_err(|e| failure::format_err!("{}", e))?;
+    let save = SaveGame::parse(&std::fs::read_to_string(&path)?)?;
+
+    // the progs read the skill when the map spawns
+    registry.set_cvar("skill", save.header.skill.to_string())?;
+
+    let mut new_session = new_session(
+        &map_path(save.header.map_name.clone().into()),
+        registry.reborrow(),
+        &vfs,
+        &levels,
+    )?;
+    new_session
+        .level
+        .restore(&save, registry.reborrow(), &vfs)?;
+    new_session.loadgame = true;
+
+    start_session(
+        new_session,
+        &mut commands,
+        session,
+        focus,
+        &mut client_events,
+        &mut server_events,
+    );
 
     Ok(())
 }
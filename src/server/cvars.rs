@@ -4,17 +4,47 @@ use bevy::{
     time::{Fixed, Time},
 };
 
-use crate::common::console::RegisterCmdExt;
+use crate::common::console::{Cvar, RegisterCmdExt};
 
 pub fn register_cvars(app: &mut App) {
     app.cvar("sv_paused", "0", "1 if the server is paused, 0 otherwise")
         .cvar(
             "teamplay",
-            "1",
+            Cvar::new("1").notify().serverinfo(),
             "0: deathmatch, 1: co-op (friendly fire disabled), 2: co-op (friendly fire enabled)",
         )
-        .cvar("skill", "1", "0: easy, 1: normal, 2: hard, 3: nightmare")
-        .cvar("sv_gravity", "800", "Gravity strength")
+        .cvar(
+            "skill",
+            Cvar::new("1").serverinfo(),
+            "0: easy, 1: normal, 2: hard, 3: nightmare",
+        )
+        .cvar(
+            "maxplayers",
+            Cvar::new("1").serverinfo(),
+            "The number of players the server allows, remote players can connect if this is more than 1 (takes effect on the next map)",
+        )
+        .cvar(
+            "hostname",
+            Cvar::new("UNNAMED").serverinfo(),
+            "The name of the server, as shown in server lists",
+        )
+        .cvar(
+            "hostport",
+            "26000",
+            "The UDP port that the server listens for remote players on",
+        )
+        .cvar(
+            "sv_cheats",
+            "0",
+            "1 to allow cheat commands such as `setpos`",
+        )
+        .cvar(
+            "sv_deterministic",
+            "0",
+            "1 to seed the server's RNG from sv_seed and record a state checksum every tick, so that the same inputs always give the same game (takes effect on the next map)",
+        )
+        .cvar("sv_seed", "0", "RNG seed used when sv_deterministic is set")
+        .cvar("sv_gravity", Cvar::new("800").notify(), "Gravity strength")
         .cvar("sv_maxvelocity", "2000", "Maximum velocity of entities")
         .cvar_on_set(
             "sys_tickrate",
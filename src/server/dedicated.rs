@@ -0,0 +1,115 @@
+//! Running a server without a client (`-dedicated`).
+//!
+//! A dedicated server has no window, renderer or sound. Console commands are read from stdin a
+//! line at a time, the console's output is written to stdout, and players connect over the network
+//! as described in [`super::remote`].
+
+use std::{
+    io::{self, Write as _},
+    path::PathBuf,
+    thread,
+};
+
+use bevy::prelude::*;
+use crossbeam_channel::Receiver;
+
+use crate::{
+    client::SeismonGameSettings,
+    common::{
+        self,
+        console::{ConsoleOutput, RunCmd, SeismonConsoleCorePlugin},
+        host::HostError,
+        vfs::Vfs,
+    },
+};
+
+use super::Session;
+
+pub struct SeismonDedicatedPlugin {
+    pub base_dir: Option<PathBuf>,
+    pub game: Option<String>,
+}
+
+/// Lines typed into the terminal, read on a thread of their own since reading stdin blocks.
+#[derive(Resource)]
+struct StdinLines(Receiver<String>);
+
+impl StdinLines {
+    fn spawn() -> StdinLines {
+        let (send, recv) = crossbeam_channel::unbounded();
+        thread::spawn(move || {
+            for line in io::stdin().lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                if send.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        StdinLines(recv)
+    }
+}
+
+impl Plugin for SeismonDedicatedPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(SeismonGameSettings {
+            base_dir: self
+                .base_dir
+                .clone()
+                .unwrap_or_else(|| common::default_base_dir()),
+            game: self.game.clone(),
+        })
+        .init_resource::<Vfs>()
+        .add_plugins(SeismonConsoleCorePlugin)
+        .insert_resource(StdinLines::spawn())
+        .add_systems(
+            Update,
+            (
+                systems::read_stdin,
+                systems::print_console_output,
+                systems::handle_host_errors.run_if(on_event::<HostError>()),
+            ),
+        );
+    }
+}
+
+pub mod systems {
+    use super::*;
+
+    pub fn read_stdin(stdin: Res<StdinLines>, mut console_cmds: EventWriter<RunCmd<'static>>) {
+        for line in stdin.0.try_iter() {
+            match RunCmd::parse_many(&line) {
+                Ok(cmds) => console_cmds.send_batch(cmds.into_iter().map(RunCmd::into_owned)),
+                Err(e) => warn!("Couldn't parse \"{}\": {}", line, e),
+            }
+        }
+    }
+
+    pub fn print_console_output(mut console: ResMut<ConsoleOutput>) {
+        let mut stdout = io::stdout().lock();
+        for (_, chunk) in console.drain_unwritten() {
+            let _ = write!(stdout, "{}", chunk.text);
+        }
+        let _ = stdout.flush();
+    }
+
+    /// Shut the server down after an error. It can be started again with `map`.
+    pub fn handle_host_errors(
+        mut commands: Commands,
+        mut host_errors: EventReader<HostError>,
+        session: Option<Res<Session>>,
+    ) {
+        // later errors in the same frame are usually caused by the first
+        let Some(HostError(message)) = host_errors.read().next().cloned() else {
+            return;
+        };
+        host_errors.clear();
+
+        error!("Host_Error: {}", message);
+        if session.is_some() {
+            commands.remove_resource::<Session>();
+        }
+    }
+}
@@ -17,19 +17,26 @@
 
 mod commands;
 mod cvars;
+// there's no stdin or threads to read it on the web
+#[cfg(not(target_arch = "wasm32"))]
+pub mod dedicated;
 pub mod precache;
 pub mod progs;
+pub mod remote;
+pub mod save;
 pub mod world;
 
-use std::{fmt, io::Write, ops::Bound};
+use std::{collections::BTreeSet, fmt, hash::Hasher, io::Write, ops::Bound};
 
 use crate::{
     common::{
-        console::{Registry, RunCmd},
+        bsp::LevelCache,
+        console::{quote, CvarNotify, Registry, RunCmd},
         engine::{self, duration_from_f32, duration_to_f32},
+        host::HostError,
         math::Hyperplane,
         model::Model,
-        net::{EntityState, ServerCmd},
+        net::{self, ClientMessage, EntityState, GameType, ServerCmd, ServerMessage, SignOnStage},
         parse,
         util::QString,
         vfs::Vfs,
@@ -52,7 +59,8 @@ use self::{
     },
     world::{
         phys::{self, CollideKind, CollisionFlags, Trace, TraceEndKind},
-        EntityFlags, EntitySolid, FieldAddrFloat, FieldAddrFunctionId, FieldAddrStringId, World,
+        Entity, EntityError, EntityFlags, EntitySolid, EntityTypeDef, FieldAddrFloat,
+        FieldAddrFunctionId, FieldAddrStringId, World,
     },
 };
 
@@ -63,8 +71,9 @@ use byteorder::{LittleEndian, WriteBytesExt as _};
 use cgmath::{Array, Deg, InnerSpace, Matrix3, Vector3, Zero};
 use chrono::Duration;
 use failure::bail;
-use hashbrown::{HashMap, HashSet};
+use hashbrown::HashMap;
 use num::FromPrimitive;
+use rand::{rngs::SmallRng, Rng as _, SeedableRng as _};
 use serde::Deserialize;
 use snafu::{Backtrace, Report};
 
@@ -84,19 +93,38 @@ impl Plugin for SeismonServerPlugin {
             (
                 systems::recv_client_messages,
                 systems::server_update,
-                systems::server_spawn.pipe(
-                    |In(res), mut commands: Commands, mut runcmd: EventWriter<RunCmd<'static>>| {
-                        if let Err(e) = res {
-                            error!("Failed spawning server: {}", Report::from_error(e));
-                            commands.remove_resource::<Session>();
-                            runcmd.send("startdemos".into());
-                        }
-                    },
-                ),
+                systems::server_spawn.pipe(|In(res), mut host_errors: EventWriter<HostError>| {
+                    if let Err(e) = res {
+                        host_errors.send(HostError(format!(
+                            "Failed spawning server: {}",
+                            Report::from_error(e)
+                        )));
+                    }
+                }),
             )
                 .run_if(resource_exists::<Session>),
+        )
+        .add_systems(
+            Update,
+            (
+                remote::systems::recv_remote_messages,
+                remote::systems::send_remote_messages,
+                remote::systems::accept_connections,
+            )
+                .chain(),
+        )
+        .add_systems(
+            Update,
+            systems::broadcast_notify_cvars.run_if(resource_exists::<Session>),
         );
 
+        app.add_event::<HostError>()
+            .add_event::<ClientMessage>()
+            .add_event::<ServerMessage>();
+
+        app.init_resource::<LevelCache>()
+            .init_resource::<remote::RemoteClients>();
+
         commands::register_commands(app);
         cvars::register_cvars(app);
     }
@@ -109,6 +137,10 @@ pub struct Client {
     state: ClientState,
     // TODO: Per-client send
     buffer: Vec<u8>,
+
+    /// Whether the client was connected when the map changed, and still has to be told to sign on
+    /// to the new one.
+    reconnect: bool,
 }
 
 impl Default for Client {
@@ -118,6 +150,7 @@ impl Default for Client {
             color: 0,
             state: ClientState::Connecting,
             buffer: default(),
+            reconnect: false,
         }
     }
 }
@@ -211,10 +244,24 @@ impl ClientSlots {
         self.slots.len()
     }
 
-    /// Finds an available connection slot for a new client.
-    pub fn find_available(&mut self) -> Option<&mut Client> {
-        let slot = self.slots.iter_mut().find(|s| s.is_none())?;
-        Some(slot.insert(Client::default()))
+    /// Finds an available connection slot for a new client and returns its ID.
+    pub fn find_available(&mut self) -> Option<usize> {
+        let id = self.slots.iter().position(Option::is_none)?;
+        self.slots[id] = Some(Client::default());
+        Some(id)
+    }
+
+    /// Puts a new client in the slot `id`, if it's free.
+    pub fn insert(&mut self, id: usize) -> Option<&mut Client> {
+        match self.slots.get_mut(id)? {
+            Some(_) => None,
+            slot => Some(slot.insert(Client::default())),
+        }
+    }
+
+    /// Frees the slot `id`.
+    pub fn remove(&mut self, id: usize) -> Option<Client> {
+        self.slots.get_mut(id)?.take()
     }
 }
 
@@ -259,6 +306,10 @@ pub struct Session {
     persist: SessionPersistent,
     state: SessionState,
     level: LevelState,
+
+    /// Whether the level was restored from a savegame, in which case the players' entities already
+    /// exist and aren't spawned again.
+    loadgame: bool,
 }
 
 impl Session {
@@ -275,6 +326,7 @@ impl Session {
             persist: SessionPersistent::new(max_clients),
             state: SessionState::Loading,
             level: LevelState::new(bsp_name, progs, models, entmap, registry, vfs),
+            loadgame: false,
         }
     }
 
@@ -293,12 +345,131 @@ impl Session {
         self.persist.client_mut(slot)
     }
 
-    pub fn new_client(&mut self) -> Option<&mut Client> {
+    /// Reserves a slot for a new client, returning its ID, or `None` if the server is full.
+    pub fn new_client(&mut self) -> Option<usize> {
         self.persist.client_slots.find_available()
     }
 
+    /// Moves the clients of `old`, the session this one replaces, into the same slots here. They
+    /// stay connected and sign on again once this level has loaded. Clients whose slots are already
+    /// taken, or are beyond `maxplayers`, are left behind.
+    pub fn take_clients(&mut self, old: &mut Session) {
+        for (id, slot) in old.persist.client_slots.slots.iter_mut().enumerate() {
+            let Some(old_client) = slot.take() else {
+                continue;
+            };
+
+            if let Some(client) = self.persist.client_slots.insert(id) {
+                client.name = old_client.name;
+                client.color = old_client.color;
+                client.reconnect = true;
+            }
+        }
+    }
+
+    /// Returns the number of clients which have a slot, including those still signing on.
+    pub fn client_count(&self) -> usize {
+        self.persist.client_slots.connected_clients().count()
+    }
+
+    /// The name of the map, e.g. `e1m1`.
+    pub fn map_name(&self) -> &str {
+        self.level.map_name()
+    }
+
+    /// The `ServerInfo` message that starts the sign-on of a client.
+    pub fn server_info(&self, registry: &Registry) -> Result<Vec<u8>, net::NetError> {
+        let teamplay = registry
+            .get_cvar("teamplay")
+            .and_then(|t| t.value().as_name());
+
+        // Match string with `starts_with` so we can handle `?GameName`
+        let game_type = match teamplay {
+            Some(t) if t.starts_with("0") => GameType::Deathmatch,
+            Some(t) if t.starts_with("1") || t.starts_with("2") => GameType::CoOp,
+            // Invalid game type, default to DM
+            _ => GameType::Deathmatch,
+        };
+
+        let mut packet = Vec::new();
+        ServerCmd::ServerInfo {
+            protocol_version: net::PROTOCOL_VERSION as _,
+            max_clients: self.max_clients() as _,
+            game_type,
+            message: "Seismon server".into(),
+            model_precache: self
+                .level
+                .model_precache
+                .iter()
+                .map(ToOwned::to_owned)
+                .collect(),
+            sound_precache: self
+                .level
+                .sound_precache
+                .iter()
+                .map(ToOwned::to_owned)
+                .collect(),
+        }
+        .serialize(&mut packet)?;
+
+        for (id, style) in self.level.lightstyles.iter().enumerate() {
+            let value = self.level.string_table.get(*style).unwrap_or_default();
+            if !value.is_empty() {
+                ServerCmd::LightStyle {
+                    id: id as _,
+                    value: value.into_owned(),
+                }
+                .serialize(&mut packet)?;
+            }
+        }
+
+        ServerCmd::SignOnStage {
+            stage: SignOnStage::Prespawn,
+        }
+        .serialize(&mut packet)?;
+
+        Ok(packet)
+    }
+
+    /// Removes a client from the game, running `ClientDisconnect` if it had spawned.
+    pub fn drop_client(
+        &mut self,
+        slot: usize,
+        mut registry: Mut<Registry>,
+        vfs: &Vfs,
+    ) -> Result<(), ProgsError> {
+        let Some(client) = self.persist.client_slots.remove(slot) else {
+            return Ok(());
+        };
+
+        if let Some(entity) = client.entity() {
+            self.level.globals.store(GlobalAddrEntity::Self_, entity)?;
+            self.level
+                .globals
+                .store(GlobalAddrFloat::Time, duration_to_f32(self.level.time))?;
+
+            let client_disconnect = self
+                .level
+                .globals
+                .function_id(GlobalAddrFunction::ClientDisconnect as i16)?;
+            self.level
+                .execute_program(client_disconnect, registry.reborrow(), vfs)?;
+        }
+
+        ServerCmd::UpdateName {
+            player_id: slot as _,
+            new_name: QString::default(),
+        }
+        .serialize(&mut self.level.broadcast)?;
+
+        Ok(())
+    }
+
     pub fn clientcmd_prespawn(&mut self, slot: usize) -> Result<(), failure::Error> {
-        self.new_client().unwrap();
+        // remote clients are given a slot when they connect, the local client takes its slot here
+        if self.client(slot).is_none() && self.persist.client_slots.insert(slot).is_none() {
+            bail!("No slot for client {}", slot);
+        }
 
         // TODO: Actually run prespawn routines
 
@@ -348,6 +519,21 @@ impl Session {
         mut registry: Mut<Registry>,
         vfs: &Vfs,
     ) -> Result<(), failure::Error> {
+        // a restored player's entity is already set up, just like everything else in the level
+        let restored = EntityId(slot + 1);
+        if self.loadgame && self.level.world.entities.exists(restored) {
+            let Some(client) = self.client_mut(slot) else {
+                bail!("No such client {}", slot);
+            };
+
+            client.state = ClientState::Active(ClientActive {
+                privileged: true,
+                entity_id: restored,
+            });
+
+            return Ok(());
+        }
+
         let client_entity = self.level.world.alloc_uninitialized_reserved()?;
 
         let Some(client) = self.client_mut(slot) else {
@@ -458,9 +644,34 @@ pub struct ServerVars {
     max_velocity: f32,
 }
 
+/// 64-bit FNV-1a. Unlike the standard library's hashers its output is fixed, so checksums can be
+/// compared between builds and machines.
+struct StateHasher(u64);
+
+impl Default for StateHasher {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for StateHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ *byte as u64).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
 /// Server-side level state.
 #[derive(Debug)]
 pub struct LevelState {
+    /// The path of the level's BSP file, e.g. `maps/e1m1.bsp`.
+    map_path: String,
+
     string_table: StringTable,
     sound_precache: Precache,
     model_precache: Precache,
@@ -482,7 +693,16 @@ pub struct LevelState {
     /// This contains the entities and world geometry.
     world: World,
 
-    new_entities: HashSet<EntityId>,
+    /// Source of QuakeC's `random()`.
+    rng: SmallRng,
+
+    /// The number of frames run on this level.
+    tick: u64,
+
+    /// The state checksum after each frame, if `sv_deterministic` was set when the level began.
+    checksums: Option<Vec<u64>>,
+
+    new_entities: BTreeSet<EntityId>,
 
     broadcast: Vec<u8>,
 }
@@ -520,7 +740,15 @@ impl LevelState {
         let world = World::new(models, entity_def, &mut string_table).unwrap();
         let entity_list = parse::entities(&entmap).unwrap();
 
+        let deterministic = registry.read_cvar::<u8>("sv_deterministic").unwrap_or(0) != 0;
+        let rng = if deterministic {
+            SmallRng::seed_from_u64(registry.read_cvar::<u64>("sv_seed").unwrap_or(0))
+        } else {
+            SmallRng::from_entropy()
+        };
+
         let mut level = LevelState {
+            map_path,
             string_table,
             sound_precache,
             model_precache,
@@ -530,6 +758,9 @@ impl LevelState {
             cx,
             globals,
             world,
+            rng,
+            tick: 0,
+            checksums: deterministic.then(Vec::new),
 
             broadcast: default(),
         };
@@ -592,6 +823,8 @@ impl LevelState {
     ) -> Result<(), ProgsError> {
         use Opcode::*;
 
+        profile_span!("progs_execute");
+
         let mut runaway = 10000;
 
         let exit_depth = self.cx.call_stack_depth();
@@ -669,7 +902,10 @@ impl LevelState {
                     macro_rules! todo_builtin {
                         ($id:ident) => {{
                             self.cx.print_backtrace(&self.string_table);
-                            panic!(concat!("TODO: ", stringify!($id)))
+                            return Err(ProgsError::with_msg(concat!(
+                                "builtin not implemented: ",
+                                stringify!($id)
+                            )));
                         }};
                     }
 
@@ -699,7 +935,7 @@ impl LevelState {
                             SetModel => self.builtin_set_model()?,
                             SetSize => self.builtin_set_size()?,
                             Break => todo_builtin!(Break),
-                            Random => self.globals.builtin_random()?,
+                            Random => self.builtin_random()?,
                             Sound => self.builtin_sound()?,
                             Normalize => self.builtin_normalize()?,
                             Error => self.builtin_err("Error")?,
@@ -804,7 +1040,7 @@ impl LevelState {
                 LoadV => self.op_load_v(a, b, c)?,
                 LoadS => self.op_load_s(a, b, c)?,
                 LoadEnt => self.op_load_ent(a, b, c)?,
-                LoadFld => panic!("load_fld not implemented"),
+                LoadFld => return Err(ProgsError::with_msg("load_fld not implemented")),
                 LoadFnc => self.op_load_fnc(a, b, c)?,
                 Address => self.op_address(a, b, c)?,
                 StoreF => self.globals.op_store_f(a, b, c)?,
@@ -817,7 +1053,7 @@ impl LevelState {
                 StorePV => self.op_storep_v(a, b, c)?,
                 StorePS => self.op_storep_s(a, b, c)?,
                 StorePEnt => self.op_storep_ent(a, b, c)?,
-                StorePFld => panic!("storep_fld not implemented"),
+                StorePFld => return Err(ProgsError::with_msg("storep_fld not implemented")),
                 StorePFnc => self.op_storep_fnc(a, b, c)?,
                 NotF => self.globals.op_not_f(a, b, c)?,
                 NotV => self.globals.op_not_v(a, b, c)?,
@@ -933,6 +1169,29 @@ impl LevelState {
         Ok(())
     }
 
+    /// Move an entity to `origin` and stop it, as a teleporter would. If `angles` are given, a
+    /// player entity will also have its view turned to face them.
+    pub fn teleport_entity(
+        &mut self,
+        ent_id: EntityId,
+        origin: Vector3<f32>,
+        angles: Option<[f32; 3]>,
+        registry: Mut<Registry>,
+        vfs: &Vfs,
+    ) -> Result<(), ProgsError> {
+        let type_def = &self.world.type_def;
+        let ent = self.world.entities.get_mut(ent_id)?;
+        ent.store(type_def, FieldAddrVector::Velocity, [0.0; 3])?;
+
+        if let Some(angles) = angles {
+            ent.store(type_def, FieldAddrVector::Angles, angles)?;
+            ent.store(type_def, FieldAddrVector::ViewAngle, angles)?;
+            ent.put_float(type_def, 1.0, FieldAddrFloat::FixAngle as i16)?;
+        }
+
+        self.set_entity_origin(ent_id, origin, registry, vfs)
+    }
+
     pub fn set_entity_model(
         &mut self,
         ent_id: EntityId,
@@ -1026,6 +1285,7 @@ impl LevelState {
         mut registry: Mut<Registry>,
         vfs: &Vfs,
     ) -> Result<(), ProgsError> {
+        profile_span!("server_physics");
         self.start_frame(registry.reborrow(), vfs)?;
 
         let server_vars = registry
@@ -1093,10 +1353,38 @@ impl LevelState {
         }
 
         self.time += frame_time;
+        self.tick += 1;
+
+        if self.checksums.is_some() {
+            let checksum = self.checksum();
+            debug!("Tick {} checksum {:016x}", self.tick, checksum);
+            self.checksums.as_mut().unwrap().push(checksum);
+        }
 
         Ok(())
     }
 
+    /// A hash of everything the simulation depends on. Two servers that were given the same
+    /// inputs in deterministic mode have the same checksum after every frame.
+    pub fn checksum(&self) -> u64 {
+        let mut state = StateHasher::default();
+        let time = self.time.num_microseconds().unwrap_or(i64::MAX);
+        state.write(&time.to_le_bytes());
+        self.globals.hash_into(&mut state);
+        self.world.entities.hash_into(&mut state);
+        state.finish()
+    }
+
+    /// The number of frames run on this level.
+    pub fn tick(&self) -> u64 {
+        self.tick
+    }
+
+    /// The checksum after each frame, starting with the first, if the level is deterministic.
+    pub fn checksums(&self) -> Option<&[u64]> {
+        self.checksums.as_deref()
+    }
+
     pub fn physics_player(
         &mut self,
         clients: &ClientSlots,
@@ -2060,11 +2348,9 @@ impl LevelState {
         Ok(())
     }
 
-    // TODO: move to Globals
-    // #[inline]
     pub fn builtin_random(&mut self) -> Result<(), ProgsError> {
         self.globals
-            .put_float(rand::random(), GLOBAL_ADDR_RETURN as i16)?;
+            .put_float(self.rng.gen(), GLOBAL_ADDR_RETURN as i16)?;
 
         Ok(())
     }
@@ -2566,28 +2852,96 @@ impl LevelState {
     }
 }
 
+/// Store a client's movement command on its player entity, like `SV_ReadClientMove`. The
+/// impulse is only overwritten when one was sent, so that QuakeC sees it on the next think even
+/// if another move arrives first.
+pub fn apply_client_move(
+    entity: &mut Entity,
+    type_def: &EntityTypeDef,
+    cmd: &net::ClientCmd,
+) -> Result<(), EntityError> {
+    let &net::ClientCmd::Move {
+        angles,
+        fwd_move,
+        side_move,
+        up_move,
+        button_flags,
+        impulse,
+        ..
+    } = cmd
+    else {
+        return Ok(());
+    };
+
+    entity.put_vector(
+        type_def,
+        [angles.x.0, angles.y.0, angles.z.0],
+        FieldAddrVector::ViewAngle as _,
+    )?;
+    entity.put_vector(
+        type_def,
+        [fwd_move as _, side_move as _, up_move as _],
+        FieldAddrVector::MoveDirection as _,
+    )?;
+
+    let pressed = |flag| button_flags.contains(flag) as u8 as f32;
+    entity.put_float(
+        type_def,
+        pressed(net::ButtonFlags::ATTACK),
+        FieldAddrFloat::Button0 as _,
+    )?;
+    entity.put_float(
+        type_def,
+        pressed(net::ButtonFlags::JUMP),
+        FieldAddrFloat::Button2 as _,
+    )?;
+
+    if impulse != 0 {
+        entity.put_float(type_def, impulse as f32, FieldAddrFloat::Impulse as _)?;
+    }
+
+    Ok(())
+}
+
 pub mod systems {
     use crate::common::{
         console::CmdName,
-        net::{self, ClientCmd, ClientMessage, GameType, ServerMessage, SignOnStage},
+        net::{ClientCmd, MessageKind},
     };
 
     use super::*;
 
+    /// Drop a client that sent something the server can't act on, rather than bringing the whole
+    /// session down with it.
+    fn drop_bad_client(
+        server: &mut Session,
+        client_id: usize,
+        reason: &str,
+        registry: Mut<Registry>,
+        vfs: &Vfs,
+        host_errors: &mut EventWriter<HostError>,
+    ) {
+        warn!("Dropping client {}: {}", client_id, reason);
+        if let Err(e) = server.drop_client(client_id, registry, vfs) {
+            host_errors.send(HostError(format!("dropping client {}: {}", client_id, e)));
+        }
+    }
+
     pub fn recv_client_messages(
         mut server: ResMut<Session>,
         mut client_msgs: EventReader<ClientMessage>,
         mut server_messages: EventWriter<ServerMessage>,
+        mut host_errors: EventWriter<HostError>,
         mut registry: ResMut<Registry>,
         vfs: Res<Vfs>,
     ) {
-        let mut out_packet = Vec::new();
-        for ClientMessage {
+        'messages: for ClientMessage {
             client_id,
             packet,
             kind: _,
         } in client_msgs.read()
         {
+            let mut out_packet = Vec::new();
             let mut packet = &packet[..];
             let client_id = *client_id;
             loop {
@@ -2604,12 +2958,37 @@ pub mod systems {
                                     continue;
                                 }
 
+                                let args_ok = match &*name {
+                                    "prespawn" | "spawn" | "begin" => args.is_empty(),
+                                    "name" => args.len() == 1,
+                                    "color" => matches!(args.len(), 1 | 2),
+                                    _ => true,
+                                };
+                                if !args_ok {
+                                    drop_bad_client(
+                                        &mut server,
+                                        client_id,
+                                        &format!("bad arguments to {}: {:?}", name, args),
+                                        registry.reborrow(),
+                                        &vfs,
+                                        &mut host_errors,
+                                    );
+                                    continue 'messages;
+                                }
+
                                 match &*name {
                                     "prespawn" => {
-                                        // TODO: Error handling
-                                        assert!(args.is_empty());
-
-                                        server.clientcmd_prespawn(client_id).unwrap();
+                                        if let Err(e) = server.clientcmd_prespawn(client_id) {
+                                            drop_bad_client(
+                                                &mut server,
+                                                client_id,
+                                                &format!("prespawn: {}", e),
+                                                registry.reborrow(),
+                                                &vfs,
+                                                &mut host_errors,
+                                            );
+                                            continue 'messages;
+                                        }
 
                                         ServerCmd::SignOnStage {
                                             stage: SignOnStage::ClientInfo,
@@ -2618,23 +2997,28 @@ pub mod systems {
                                         .unwrap();
                                     }
                                     "name" => {
-                                        // TODO: Error handling
-                                        assert!(args.len() == 1);
-
-                                        server
-                                            .clientcmd_name(
-                                                client_id,
-                                                args.into_iter().next().unwrap().to_owned().into(),
-                                            )
-                                            .unwrap();
+                                        if let Err(e) = server.clientcmd_name(
+                                            client_id,
+                                            args.into_iter().next().unwrap().to_owned().into(),
+                                        ) {
+                                            warn!("name: {}", e);
+                                        }
                                     }
                                     "color" => {
-                                        assert!(args.len() == 2);
-
                                         warn!("TODO: Set color");
                                     }
                                     "spawn" => {
-                                        server.clientcmd_spawn(client_id).unwrap();
+                                        if let Err(e) = server.clientcmd_spawn(client_id) {
+                                            drop_bad_client(
+                                                &mut server,
+                                                client_id,
+                                                &format!("spawn: {}", e),
+                                                registry.reborrow(),
+                                                &vfs,
+                                                &mut host_errors,
+                                            );
+                                            continue 'messages;
+                                        }
 
                                         ServerCmd::SignOnStage {
                                             stage: SignOnStage::Begin,
@@ -2643,15 +3027,35 @@ pub mod systems {
                                         .unwrap();
                                     }
                                     "begin" => {
-                                        // TODO: Error handling
-                                        assert!(args.is_empty());
-
-                                        server
-                                            .clientcmd_begin(client_id, registry.reborrow(), &*vfs)
-                                            .unwrap();
+                                        if let Err(e) = server.clientcmd_begin(
+                                            client_id,
+                                            registry.reborrow(),
+                                            &*vfs,
+                                        ) {
+                                            drop_bad_client(
+                                                &mut server,
+                                                client_id,
+                                                &format!("begin: {}", e),
+                                                registry.reborrow(),
+                                                &vfs,
+                                                &mut host_errors,
+                                            );
+                                            continue 'messages;
+                                        }
 
-                                        let client_ent =
-                                            server.client(client_id).unwrap().entity().unwrap();
+                                        let Some(client_ent) =
+                                            server.client(client_id).and_then(|c| c.entity())
+                                        else {
+                                            drop_bad_client(
+                                                &mut server,
+                                                client_id,
+                                                "begin: no entity",
+                                                registry.reborrow(),
+                                                &vfs,
+                                                &mut host_errors,
+                                            );
+                                            continue 'messages;
+                                        };
 
                                         // TODO: Error handling
                                         ServerCmd::SetView {
@@ -2675,15 +3079,7 @@ pub mod systems {
                                 }
                             }
                         }
-                        ClientCmd::Move {
-                            send_time,
-                            angles,
-                            fwd_move,
-                            side_move,
-                            up_move,
-                            button_flags,
-                            impulse,
-                        } => {
+                        cmd @ ClientCmd::Move { .. } => {
                             let Session { persist, level, .. } = &mut *server;
 
                             if let Some(entity) = persist
@@ -2691,13 +3087,7 @@ pub mod systems {
                                 .and_then(|c| c.entity())
                                 .and_then(|ent_id| level.world.entities.get_mut(ent_id).ok())
                             {
-                                entity
-                                    .put_vector(
-                                        &level.world.type_def,
-                                        [fwd_move as _, side_move as _, up_move as _],
-                                        FieldAddrVector::MoveDirection as _,
-                                    )
-                                    .unwrap();
+                                apply_client_move(entity, &level.world.type_def, &cmd).unwrap();
                             }
                         }
                         other => {
@@ -2711,14 +3101,30 @@ pub mod systems {
                     }
                 };
             }
+
+            if !out_packet.is_empty() {
+                server_messages.send(ServerMessage {
+                    client_id,
+                    packet: out_packet,
+                    kind: MessageKind::Reliable,
+                });
+            }
         }
+    }
 
-        if !out_packet.is_empty() {
-            // TODO: Should not hard-code client id 0
-            server_messages.send(ServerMessage {
-                client_id: 0,
-                packet: out_packet,
-            });
+    /// Tell every player when a cvar flagged with `notify` changes, the way Quake announces
+    /// changes to `teamplay` or `sv_gravity`.
+    pub fn broadcast_notify_cvars(
+        mut server: ResMut<Session>,
+        mut notified: EventReader<CvarNotify>,
+    ) {
+        for CvarNotify { name, value } in notified.read() {
+            let text = format!("\"{}\" changed to {}\n", name, quote(value));
+            if let Err(e) = (ServerCmd::Print { text: text.into() })
+                .serialize(&mut server.level_mut().broadcast)
+            {
+                error!("Couldn't broadcast cvar change: {}", e);
+            }
         }
     }
 
@@ -2734,8 +3140,9 @@ pub mod systems {
         }
 
         // In `sv_init.c` there is a comment saying to run physics twice before starting the server
-        // properly to "allow everything to settle".
-        for _ in 0..2 {
+        // properly to "allow everything to settle". A restored level has already settled.
+        let settle_frames = if server.loadgame { 0 } else { 2 };
+        for _ in 0..settle_frames {
             let server = &mut *server;
             server.level.physics(
                 &server.persist.client_slots,
@@ -2748,58 +3155,13 @@ pub mod systems {
 
         server.state = SessionState::Active;
 
-        let teamplay = registry
-            .get_cvar("teamplay")
-            .and_then(|t| t.value().as_name());
-
-        // Match string with `starts_with` so we can handle `?GameName`
-        let game_type = match teamplay {
-            Some(t) if t.starts_with("0") => GameType::Deathmatch,
-            Some(t) if t.starts_with("1") || t.starts_with("2") => GameType::CoOp,
-            // Invalid game type, default to DM
-            _ => GameType::Deathmatch,
-        };
-
-        let mut packet = Vec::new();
-        ServerCmd::ServerInfo {
-            protocol_version: net::PROTOCOL_VERSION as _,
-            max_clients: server.max_clients() as _,
-            game_type,
-            message: "Seismon server".into(),
-            model_precache: server
-                .level
-                .model_precache
-                .iter()
-                .map(ToOwned::to_owned)
-                .collect(),
-            sound_precache: server
-                .level
-                .sound_precache
-                .iter()
-                .map(ToOwned::to_owned)
-                .collect(),
-        }
-        .serialize(&mut packet)?;
-
-        for (id, style) in server.level.lightstyles.iter().enumerate() {
-            let value = server.level.string_table.get(*style).unwrap_or_default();
-            if !value.is_empty() {
-                ServerCmd::LightStyle {
-                    id: id as _,
-                    value: value.into_owned(),
-                }
-                .serialize(&mut packet)?;
-            }
-        }
-
-        ServerCmd::SignOnStage {
-            stage: SignOnStage::Prespawn,
-        }
-        .serialize(&mut packet)?;
-
+        // the local client, if there is one, takes the first slot. Remote clients are sent this
+        // when they connect.
+        let packet = server.server_info(&registry)?;
         server_messages.send(ServerMessage {
             client_id: 0,
             packet,
+            kind: MessageKind::Reliable,
         });
 
         Ok(())
@@ -2809,6 +3171,7 @@ pub mod systems {
         mut server: ResMut<Session>,
         time: Res<Time<Fixed>>,
         mut server_messages: EventWriter<ServerMessage>,
+        mut host_errors: EventWriter<HostError>,
         mut registry: ResMut<Registry>,
         vfs: Res<Vfs>,
     ) {
@@ -2827,11 +3190,14 @@ pub mod systems {
             } => {
                 if let Err(e) = level.physics(
                     &persist.client_slots,
-                    Duration::from_std(time.elapsed()).unwrap(),
+                    Duration::from_std(time.delta()).unwrap(),
                     registry.reborrow(),
                     &*vfs,
                 ) {
-                    error!("Failed running frame: {}", Report::from_error(e));
+                    host_errors.send(HostError(format!(
+                        "Failed running frame: {}",
+                        Report::from_error(e)
+                    )));
                     false
                 } else {
                     true
@@ -2843,12 +3209,7 @@ pub mod systems {
         if send_diff {
             let Session { persist, level, .. } = &mut *server;
 
-            // TODO: Stop hardcoding `8` for max players
-            for client_id in persist
-                .client_slots
-                .active_clients()
-                .collect::<ArrayVec<usize, 8>>()
-            {
+            for client_id in persist.client_slots.active_clients().collect::<Vec<_>>() {
                 let mut packet = Vec::new();
 
                 ServerCmd::Time {
@@ -2936,7 +3297,11 @@ pub mod systems {
                 // events related to those entities
                 packet.extend_from_slice(&level.broadcast);
 
-                server_messages.send(ServerMessage { client_id, packet });
+                server_messages.send(ServerMessage {
+                    client_id,
+                    packet,
+                    kind: MessageKind::Unreliable,
+                });
             }
 
             level.broadcast.clear();
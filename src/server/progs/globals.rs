@@ -15,7 +15,7 @@
 // DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 
-use std::{error::Error, fmt, rc::Rc};
+use std::{error::Error, fmt, hash::Hasher, rc::Rc};
 
 use crate::server::progs::{
     EntityId, FieldAddr, FunctionId, GlobalDef, StringId, StringTable, Type,
@@ -241,6 +241,18 @@ impl Globals {
         Globals { defs, addrs }
     }
 
+    /// Returns the definitions of every named global.
+    pub fn defs(&self) -> &[GlobalDef] {
+        &self.defs
+    }
+
+    /// Feed the raw value of every global into `state`.
+    pub fn hash_into<H: Hasher>(&self, state: &mut H) {
+        for addr in self.addrs.iter() {
+            state.write(addr);
+        }
+    }
+
     /// Performs a type check at `addr` with type `type_`.
     ///
     /// The type check allows checking `QFloat` against `QVector` and vice-versa, since vectors have
@@ -1087,11 +1099,6 @@ impl Globals {
 
     // QuakeC built-in functions ===============================================
 
-    #[inline]
-    pub fn builtin_random(&mut self) -> Result<(), GlobalsError> {
-        self.put_float(rand::random(), GLOBAL_ADDR_RETURN as i16)
-    }
-
     /// Calculate `v_forward`, `v_right` and `v_up` from `angles`.
     ///
     /// This requires some careful coordinate system transformations. Angle vectors are stored
@@ -224,7 +224,7 @@ impl StringId {
     }
 }
 
-#[derive(Copy, Clone, Debug, Default, Eq, Hash, PartialEq)]
+#[derive(Copy, Clone, Debug, Default, Eq, Hash, PartialEq, PartialOrd, Ord)]
 #[repr(C)]
 pub struct EntityId(pub usize);
 
@@ -290,10 +290,11 @@ struct Lump {
 
 #[derive(Debug)]
 pub struct GlobalDef {
-    save: bool,
-    type_: Type,
-    offset: u16,
-    name_id: StringId,
+    /// Whether this global is written to savegames.
+    pub save: bool,
+    pub type_: Type,
+    pub offset: u16,
+    pub name_id: StringId,
 }
 
 /// An entity field definition.
@@ -323,6 +324,7 @@ pub fn load<R>(mut src: R) -> Result<LoadProgs, ProgsError>
 where
     R: Read + Seek,
 {
+    profile_span!("progs_load");
     assert!(src.read_i32::<LittleEndian>()? == VERSION);
     assert!(src.read_i32::<LittleEndian>()? == CRC);
 
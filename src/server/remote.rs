@@ -0,0 +1,454 @@
+//! Clients connecting over the network.
+//!
+//! While the server is running a game for more than one player (see `maxplayers`), it listens on
+//! `hostport` for connection requests, whether it's a dedicated server or a listen server with a
+//! local player in the first slot. Each accepted client gets a socket of
+//! its own and a slot in the `Session`, and from then on its messages go through the same
+//! `ClientMessage` and `ServerMessage` events as the local client's. Signon messages are sent
+//! reliably, and split between commands if they're too long for one message. The per-frame updates
+//! are sent as datagrams, and dropped if they're too long for one, as in NetQuake.
+//!
+//! Changing the map moves the clients into the new `Session`, so remote clients stay connected.
+//! Once the level has loaded they're sent `reconnect` and the new server info, and sign on again.
+
+use std::{
+    collections::VecDeque,
+    io::Cursor,
+    mem,
+    net::{SocketAddr, UdpSocket},
+    time::Duration,
+};
+
+use bevy::{prelude::*, utils::Instant};
+
+use crate::common::{
+    console::Registry,
+    net::{
+        connect::{
+            ConnectListener, Request, Response, ResponseAccept, ResponseReject, ResponseServerInfo,
+            CONNECT_PROTOCOL_VERSION,
+        },
+        BlockingMode, ClientMessage, MessageKind, NetError, QSocket, ServerCmd, ServerMessage,
+        GAME_NAME, MAX_DATAGRAM, MAX_MESSAGE, PROTOCOL_VERSION,
+    },
+    vfs::Vfs,
+};
+
+use super::Session;
+
+/// How long to wait for the acknowledgement of a reliable message before sending it again.
+const RESEND_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long a client can go without sending anything before it's dropped.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+struct RemoteClient {
+    client_id: usize,
+    addr: SocketAddr,
+    port: u16,
+    socket: QSocket,
+
+    /// Messages waiting for the previous reliable message to be acknowledged.
+    reliable: VecDeque<Vec<u8>>,
+    last_send: Instant,
+    last_recv: Instant,
+}
+
+impl RemoteClient {
+    /// Queue a reliable message, splitting it if it's too long to send at once.
+    fn queue_reliable(&mut self, msg: &[u8]) {
+        if msg.len() <= MAX_MESSAGE {
+            self.reliable.push_back(msg.to_owned());
+        } else {
+            self.reliable.extend(split_message(msg));
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), NetError> {
+        if !self.socket.can_send() {
+            if self.last_send.elapsed() > RESEND_INTERVAL {
+                self.socket.resend_msg()?;
+                self.last_send = Instant::now();
+            }
+            return Ok(());
+        }
+
+        // everything that's queued up goes in one message, as far as it fits
+        let mut msg = Vec::new();
+        while let Some(next) = self.reliable.front() {
+            if !msg.is_empty() && msg.len() + next.len() > MAX_MESSAGE {
+                break;
+            }
+            msg.extend(self.reliable.pop_front().unwrap());
+        }
+
+        if !msg.is_empty() {
+            self.socket.begin_send_msg(&msg)?;
+            self.last_send = Instant::now();
+        }
+
+        Ok(())
+    }
+}
+
+/// Split a message into pieces of at most `MAX_MESSAGE` bytes, between its commands, so that the
+/// client can parse each piece on its own. A single command that's longer than that can't be sent,
+/// so it's dropped.
+fn split_message(msg: &[u8]) -> Vec<Vec<u8>> {
+    let mut pieces = Vec::new();
+    let mut piece = Vec::new();
+    let mut reader = Cursor::new(msg);
+
+    loop {
+        let start = reader.position() as usize;
+        match ServerCmd::deserialize(&mut reader) {
+            Ok(Some(_)) => (),
+            Ok(None) => break,
+            Err(e) => {
+                warn!("Dropping the rest of a message that can't be split: {}", e);
+                break;
+            }
+        }
+
+        let cmd = &msg[start..reader.position() as usize];
+        if cmd.len() > MAX_MESSAGE {
+            warn!("Dropping {} byte command", cmd.len());
+            continue;
+        }
+
+        if piece.len() + cmd.len() > MAX_MESSAGE {
+            pieces.push(mem::take(&mut piece));
+        }
+        piece.extend_from_slice(cmd);
+    }
+
+    if !piece.is_empty() {
+        pieces.push(piece);
+    }
+
+    pieces
+}
+
+/// The listening socket and the connected remote clients.
+#[derive(Resource, Default)]
+pub struct RemoteClients {
+    listener: Option<ConnectListener>,
+
+    /// The port that the listener was last bound to, even if binding it failed.
+    port: Option<u16>,
+    clients: Vec<RemoteClient>,
+}
+
+impl RemoteClients {
+    /// Close the listening socket. Clients that are already connected stay connected.
+    fn close(&mut self) {
+        if self.listener.take().is_some() {
+            info!("No longer listening for clients");
+        }
+        self.port = None;
+    }
+
+    /// Open the listening socket on `port`, unless it's already open.
+    fn listen(&mut self, port: u16) {
+        if self.port == Some(port) {
+            return;
+        }
+
+        self.port = Some(port);
+        self.listener = match ConnectListener::bind(("0.0.0.0", port)) {
+            Ok(listener) => {
+                info!("Listening for clients on UDP port {}", port);
+                Some(listener)
+            }
+            Err(e) => {
+                error!("Couldn't listen on UDP port {}: {}", port, e);
+                None
+            }
+        };
+    }
+
+    /// Accept a client, giving it a slot in `session` and its own socket.
+    fn accept(&mut self, session: &mut Session, registry: &Registry, addr: SocketAddr) -> Response {
+        // the accept was lost, so the client asked again
+        if let Some(client) = self.clients.iter().find(|c| c.addr == addr) {
+            return Response::Accept(ResponseAccept {
+                port: client.port as i32,
+            });
+        }
+
+        let reject = |message: &'static str| {
+            Response::Reject(ResponseReject {
+                message: message.into(),
+            })
+        };
+
+        if session.loading() {
+            return reject("Server is loading a map");
+        }
+
+        let socket = UdpSocket::bind(("0.0.0.0", 0));
+        let (socket, port) = match socket.and_then(|s| s.local_addr().map(|a| (s, a.port()))) {
+            Ok(socket) => socket,
+            Err(e) => {
+                error!("Couldn't open a socket for {}: {}", addr, e);
+                return reject("Server error");
+            }
+        };
+
+        let server_info = match session.server_info(registry) {
+            Ok(packet) => packet,
+            Err(e) => {
+                error!("Couldn't write server info: {}", e);
+                return reject("Server error");
+            }
+        };
+
+        let Some(client_id) = session.new_client() else {
+            return reject("Server is full");
+        };
+
+        info!("Client {} connected from {}", client_id, addr);
+        self.clients.push(RemoteClient {
+            client_id,
+            addr,
+            port,
+            socket: QSocket::new(socket, addr),
+            reliable: VecDeque::from([server_info]),
+            last_send: Instant::now(),
+            last_recv: Instant::now(),
+        });
+
+        Response::Accept(ResponseAccept { port: port as i32 })
+    }
+}
+
+pub mod systems {
+    use super::*;
+
+    /// Answer connection requests and server list queries.
+    pub fn accept_connections(
+        mut remote: ResMut<RemoteClients>,
+        mut session: Option<ResMut<Session>>,
+        registry: Res<Registry>,
+    ) {
+        // single player games aren't hosted
+        if !session.as_deref().is_some_and(|s| s.max_clients() > 1) {
+            remote.close();
+            return;
+        }
+
+        remote.listen(registry.read_cvar::<u16>("hostport").unwrap_or(26000));
+
+        loop {
+            let received = match &remote.listener {
+                Some(listener) => listener.try_recv_request(),
+                None => return,
+            };
+            let (request, addr) = match received {
+                Ok(Some(request)) => request,
+                Ok(None) => return,
+                Err(e) => {
+                    debug!("Bad connection request: {}", e);
+                    continue;
+                }
+            };
+
+            let response = match (request, session.as_deref_mut()) {
+                (Request::Connect(connect), _) if connect.game_name != GAME_NAME => continue,
+                (Request::Connect(connect), _) if connect.proto_ver != CONNECT_PROTOCOL_VERSION => {
+                    Response::Reject(ResponseReject {
+                        message: "Incompatible version".into(),
+                    })
+                }
+                (Request::Connect(_), Some(session)) => remote.accept(session, &registry, addr),
+                (Request::Connect(_), None) => Response::Reject(ResponseReject {
+                    message: "Server is not running a map".into(),
+                }),
+
+                (Request::ServerInfo(info), Some(session)) if info.game_name == GAME_NAME => {
+                    let hostname = registry.get_cvar("hostname").and_then(|c| {
+                        let value = c.value();
+                        value.as_name().or(value.as_str()).map(ToOwned::to_owned)
+                    });
+
+                    Response::ServerInfo(ResponseServerInfo {
+                        address: remote
+                            .listener
+                            .as_ref()
+                            .and_then(|l| l.local_addr().ok())
+                            .map(|a| a.to_string())
+                            .unwrap_or_default(),
+                        hostname: hostname.unwrap_or_default(),
+                        levelname: session.map_name().to_owned(),
+                        client_count: session.client_count() as u8,
+                        client_max: session.max_clients() as u8,
+                        protocol_version: PROTOCOL_VERSION,
+                    })
+                }
+
+                // TODO: Player and rule queries
+                _ => continue,
+            };
+
+            let Some(listener) = &remote.listener else {
+                return;
+            };
+            if let Err(e) = listener.send_response(response, addr) {
+                warn!("Couldn't respond to {}: {}", addr, e);
+            }
+        }
+    }
+
+    /// Read the messages from remote clients, and drop the clients that have left.
+    pub fn recv_remote_messages(
+        mut remote: ResMut<RemoteClients>,
+        mut session: Option<ResMut<Session>>,
+        mut registry: ResMut<Registry>,
+        vfs: Res<Vfs>,
+        mut client_events: EventWriter<ClientMessage>,
+    ) {
+        let mut dropped = Vec::new();
+        remote.clients.retain_mut(|client| {
+            // the server was shut down, or the client didn't fit in the new map's slots
+            let Some(session) = session
+                .as_deref_mut()
+                .filter(|s| s.client(client.client_id).is_some())
+            else {
+                let mut packet = Vec::new();
+                ServerCmd::Disconnect.serialize(&mut packet).unwrap();
+                let _ = client.socket.begin_send_msg(&packet);
+                return false;
+            };
+
+            // the map changed, so once it's loaded the client has to sign on again
+            if !session.loading()
+                && mem::take(&mut session.client_mut(client.client_id).unwrap().reconnect)
+            {
+                let mut packet = Vec::new();
+                ServerCmd::StuffText {
+                    text: "reconnect\n".into(),
+                }
+                .serialize(&mut packet)
+                .unwrap();
+                match session.server_info(&registry) {
+                    Ok(server_info) => packet.extend(server_info),
+                    Err(e) => error!("Couldn't write server info: {}", e),
+                }
+
+                // anything still queued was for the old map
+                client.reliable.clear();
+                client.queue_reliable(&packet);
+            }
+
+            loop {
+                match client.socket.recv_msg(BlockingMode::NonBlocking) {
+                    Ok(packet) if packet.is_empty() => break,
+                    Ok(packet) => {
+                        client.last_recv = Instant::now();
+                        client_events.send(ClientMessage {
+                            client_id: client.client_id,
+                            packet,
+                            kind: MessageKind::Reliable,
+                        });
+                    }
+                    Err(e) => {
+                        info!("Client {} disconnected: {}", client.client_id, e);
+                        dropped.push(client.client_id);
+                        return false;
+                    }
+                }
+            }
+
+            if client.last_recv.elapsed() > TIMEOUT {
+                info!("Client {} timed out", client.client_id);
+                dropped.push(client.client_id);
+                return false;
+            }
+
+            true
+        });
+
+        let Some(session) = session.as_deref_mut() else {
+            return;
+        };
+        for client_id in dropped {
+            if let Err(e) = session.drop_client(client_id, registry.reborrow(), &vfs) {
+                error!("Dropping client {}: {}", client_id, e);
+            }
+        }
+    }
+
+    /// Send the server's messages to the remote clients that they're for.
+    pub fn send_remote_messages(
+        mut remote: ResMut<RemoteClients>,
+        mut server_events: EventReader<ServerMessage>,
+    ) {
+        for ServerMessage {
+            client_id,
+            packet,
+            kind,
+        } in server_events.read()
+        {
+            let Some(client) = remote
+                .clients
+                .iter_mut()
+                .find(|c| c.client_id == *client_id)
+            else {
+                continue;
+            };
+
+            match kind {
+                MessageKind::Unreliable if packet.len() <= MAX_DATAGRAM => {
+                    if let Err(e) = client.socket.send_msg_unreliable(packet) {
+                        warn!("Sending to client {}: {}", client_id, e);
+                    }
+                }
+
+                // the next update replaces this one, so it's not worth holding up the reliable
+                // messages for
+                MessageKind::Unreliable => debug!(
+                    "Dropping {} byte update to client {}",
+                    packet.len(),
+                    client_id
+                ),
+
+                _ => client.queue_reliable(packet),
+            }
+        }
+
+        for client in &mut remote.clients {
+            if let Err(e) = client.flush() {
+                warn!("Sending to client {}: {}", client.client_id, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_split_message() {
+        let mut msg = Vec::new();
+        for i in 0..1000 {
+            ServerCmd::Print {
+                text: format!("line {}\n", i).into(),
+            }
+            .serialize(&mut msg)
+            .unwrap();
+        }
+        assert!(msg.len() > MAX_MESSAGE);
+
+        let pieces = split_message(&msg);
+        assert!(pieces.len() > 1);
+        assert!(pieces.iter().all(|piece| piece.len() <= MAX_MESSAGE));
+        assert_eq!(pieces.concat(), msg);
+
+        // every piece starts on a command
+        for piece in &pieces {
+            let mut reader = Cursor::new(&piece[..]);
+            while ServerCmd::deserialize(&mut reader).unwrap().is_some() {}
+        }
+    }
+}
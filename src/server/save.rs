@@ -0,0 +1,580 @@
+//! Savegames (`save` and `load`).
+//!
+//! Savegames are text files in the format used by the original engine, so a game saved by one
+//! engine can be loaded by another as long as both run the same progs. After a version number and
+//! a comment shown in the load menu, a save holds the spawn parameters, the skill, the map name,
+//! the level time and 64 lightstyles, one per line. This is followed by a block of `"key" "value"`
+//! pairs holding the globals which the progs mark for saving, and then a block for every entity
+//! slot. Only fields which aren't zero are written, and free slots are written as empty blocks.
+//!
+//! ```text
+//! 5
+//! the_Slipgate_Complex__kills:__3/_34____
+//! ...
+//! {
+//! "total_secrets" "6.000000"
+//! }
+//! {
+//! "classname" "worldspawn"
+//! ...
+//! }
+//! ```
+
+use std::fmt;
+
+use bevy::prelude::*;
+use failure::{bail, format_err, Error};
+
+use crate::common::{
+    console::Registry,
+    engine,
+    savegame::{escape, unescape, SaveHeader, NUM_SPAWN_PARMS},
+    vfs::Vfs,
+};
+
+use super::{
+    progs::{FunctionId, GlobalAddrFloat, ProgsError, StringId, Type},
+    world::{FieldAddrFloat, FieldAddrStringId, MAX_ENTITIES},
+    EntityId, LevelState, MAX_LIGHTSTYLES,
+};
+
+/// The number of lightstyles in a savegame, which is the original engine's limit.
+const SAVE_LIGHTSTYLES: usize = 64;
+
+/// The length of the comment, including padding.
+const COMMENT_LENGTH: usize = 39;
+
+/// `"key" "value"` pairs, in the order they're written.
+pub type Fields = Vec<(String, String)>;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SaveGame {
+    /// The comment, spawn parameters, skill, map name and level time, which are also read by the
+    /// load menu.
+    pub header: SaveHeader,
+    pub lightstyles: Vec<String>,
+    pub globals: Fields,
+
+    /// The fields of every entity slot. Free slots have no fields.
+    pub entities: Vec<Fields>,
+}
+
+/// The comment for a save of the level called `level_name`: the name, followed by the number of
+/// monsters killed. Spaces are written as underscores.
+pub fn comment(level_name: &str, killed: i32, total: i32) -> String {
+    let text = format!("{:<22.22}kills:{:3}/{:3}", level_name, killed, total);
+    format!("{:<1$.1$}", text, COMMENT_LENGTH).replace(char::is_whitespace, " ")
+}
+
+/// Splits a savegame into tokens in the same way as the original engine: quoted strings, braces,
+/// and anything else separated by whitespace.
+struct Tokens<'a>(&'a str);
+
+impl<'a> Iterator for Tokens<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        let text = self.0.trim_start();
+        if text.is_empty() {
+            self.0 = text;
+            return None;
+        }
+
+        if let Some(quoted) = text.strip_prefix('"') {
+            let end = quoted.find('"').unwrap_or(quoted.len());
+            self.0 = quoted.get(end + 1..).unwrap_or("");
+            return Some(&quoted[..end]);
+        }
+
+        let end = if text.starts_with(['{', '}']) {
+            1
+        } else {
+            text.find(|c: char| c.is_whitespace() || c == '"')
+                .unwrap_or(text.len())
+        };
+        self.0 = &text[end..];
+        Some(&text[..end])
+    }
+}
+
+fn token<'a>(tokens: &mut Tokens<'a>) -> Result<&'a str, Error> {
+    tokens
+        .next()
+        .ok_or_else(|| format_err!("unexpected end of savegame"))
+}
+
+/// Parses a `{ "key" "value" ... }` block, or returns `None` at the end of the savegame.
+fn block(tokens: &mut Tokens) -> Result<Option<Fields>, Error> {
+    match tokens.next() {
+        None => return Ok(None),
+        Some("{") => (),
+        Some(other) => bail!("expected {{, found \"{}\"", other),
+    }
+
+    let mut fields = Vec::new();
+    loop {
+        let key = token(tokens)?;
+        if key == "}" {
+            return Ok(Some(fields));
+        }
+
+        let value = token(tokens)?;
+        if value == "}" {
+            bail!("no value for \"{}\"", key);
+        }
+
+        fields.push((key.to_owned(), unescape(value)));
+    }
+}
+
+fn write_block(f: &mut fmt::Formatter, fields: &Fields) -> fmt::Result {
+    writeln!(f, "{{")?;
+    for (key, value) in fields {
+        writeln!(f, "\"{}\" \"{}\"", key, escape(value))?;
+    }
+    writeln!(f, "}}")
+}
+
+impl SaveGame {
+    pub fn parse(text: &str) -> Result<SaveGame, Error> {
+        // the header is line based, and the rest is split into tokens
+        let mut rest = text.as_bytes();
+        let header = SaveHeader::read(&mut rest)?;
+        let tokens = &mut Tokens(&text[text.len() - rest.len()..]);
+
+        let lightstyles = (0..SAVE_LIGHTSTYLES)
+            .map(|_| token(tokens).map(str::to_owned))
+            .collect::<Result<_, _>>()?;
+
+        let globals = block(tokens)?.ok_or_else(|| format_err!("savegame has no globals"))?;
+        let mut entities = Vec::new();
+        while let Some(fields) = block(tokens)? {
+            entities.push(fields);
+        }
+
+        Ok(SaveGame {
+            header,
+            lightstyles,
+            globals,
+            entities,
+        })
+    }
+}
+
+impl fmt::Display for SaveGame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.header)?;
+        for style in &self.lightstyles {
+            writeln!(f, "{}", if style.is_empty() { "m" } else { style })?;
+        }
+
+        write_block(f, &self.globals)?;
+        for fields in &self.entities {
+            write_block(f, fields)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The number of 4-byte words taken up by a value of type `type_`.
+fn type_size(type_: Type) -> usize {
+    match type_ {
+        Type::QVector => 3,
+        _ => 1,
+    }
+}
+
+/// Whether a field is a component of a vector field, like `origin_x`, which is saved as part of
+/// the vector.
+fn is_component(name: &str) -> bool {
+    name.len() > 2 && name.as_bytes()[name.len() - 2] == b'_'
+}
+
+impl LevelState {
+    /// Formats a value as it's written to a savegame, or returns `None` if it can't be saved.
+    fn save_value(&self, type_: Type, words: &[[u8; 4]]) -> Option<String> {
+        let int = |i: usize| i32::from_le_bytes(words[i]);
+        let float = |i: usize| f32::from_le_bytes(words[i]);
+
+        Some(match type_ {
+            Type::QVoid => "void".to_owned(),
+            Type::QString => self
+                .string_table
+                .get(StringId(int(0) as usize))?
+                .to_str()
+                .into_owned(),
+            Type::QFloat => format!("{:.6}", float(0)),
+            Type::QVector => format!("{:.6} {:.6} {:.6}", float(0), float(1), float(2)),
+            Type::QEntity => int(0).to_string(),
+            Type::QField => {
+                let def = self
+                    .world
+                    .type_def
+                    .field_defs()
+                    .iter()
+                    .find(|def| def.type_ != Type::QVoid && def.offset as i32 == int(0))?;
+                self.string_table.get(def.name_id)?.to_str().into_owned()
+            }
+            Type::QFunction => {
+                let def = self.cx.function_def(FunctionId(int(0) as usize)).ok()?;
+                self.string_table.get(def.name_id)?.to_str().into_owned()
+            }
+            Type::QPointer => return None,
+        })
+    }
+
+    /// Parses a value from a savegame into the words it's stored as.
+    fn load_value(&mut self, type_: Type, value: &str) -> Result<Vec<[u8; 4]>, ProgsError> {
+        let invalid = || ProgsError::with_msg(format!("invalid {} \"{}\"", type_, value));
+        let float = |text: &str| text.trim().parse::<f32>().map_err(|_| invalid());
+
+        let words = match type_ {
+            Type::QVoid | Type::QPointer => Vec::new(),
+            Type::QString => {
+                let id = if value.is_empty() {
+                    StringId(0)
+                } else {
+                    self.string_table.insert(value.replace("\\n", "\n"))
+                };
+                vec![(id.0 as i32).to_le_bytes()]
+            }
+            Type::QFloat => vec![float(value)?.to_le_bytes()],
+            Type::QVector => {
+                let components = value
+                    .split_whitespace()
+                    .map(float)
+                    .collect::<Result<Vec<_>, _>>()?;
+                if components.len() != 3 {
+                    return Err(invalid());
+                }
+
+                components.iter().map(|c| c.to_le_bytes()).collect()
+            }
+            Type::QEntity => {
+                let id = value.trim().parse::<i32>().map_err(|_| invalid())?;
+                if id < 0 || id as usize >= MAX_ENTITIES {
+                    return Err(invalid());
+                }
+
+                vec![id.to_le_bytes()]
+            }
+            Type::QField => {
+                let offset = self.find_field(value).ok_or_else(invalid)?.1;
+                vec![(offset as i32).to_le_bytes()]
+            }
+            Type::QFunction => {
+                let id = self
+                    .cx
+                    .find_function_by_name(&self.string_table, value)
+                    .map_err(|_| invalid())?;
+                vec![(id.0 as i32).to_le_bytes()]
+            }
+        };
+
+        Ok(words)
+    }
+
+    /// Returns the type and address of the entity field called `name`.
+    fn find_field(&self, name: &str) -> Option<(Type, i16)> {
+        self.world
+            .type_def
+            .field_defs()
+            .iter()
+            .find(|def| {
+                self.string_table
+                    .get(def.name_id)
+                    .is_some_and(|n| &*n.raw == name.as_bytes())
+            })
+            .map(|def| (def.type_, def.offset as i16))
+    }
+
+    /// Returns the type and address of the global called `name`.
+    fn find_global(&self, name: &str) -> Option<(Type, i16)> {
+        self.globals
+            .defs()
+            .iter()
+            .find(|def| {
+                self.string_table
+                    .get(def.name_id)
+                    .is_some_and(|n| &*n.raw == name.as_bytes())
+            })
+            .map(|def| (def.type_, def.offset as i16))
+    }
+
+    /// The path of this level's map in the form written to savegames, e.g. `e1m1`.
+    pub fn map_name(&self) -> &str {
+        let name = self
+            .map_path
+            .strip_prefix("maps/")
+            .unwrap_or(&self.map_path);
+        name.strip_suffix(".bsp").unwrap_or(name)
+    }
+
+    /// Captures the state of the level.
+    ///
+    /// The server doesn't keep spawn parameters for its clients yet, so the spawn parameter
+    /// globals are saved in their place.
+    pub fn save_game(&self, skill: i32) -> Result<SaveGame, ProgsError> {
+        let level_name = self
+            .world
+            .entities
+            .try_get(EntityId(0))?
+            .load(&self.world.type_def, FieldAddrStringId::Message)
+            .ok()
+            .and_then(|id| self.string_table.get(id))
+            .map(|name| name.to_str().into_owned())
+            .unwrap_or_default();
+        let comment = comment(
+            &level_name,
+            self.globals.load(GlobalAddrFloat::KilledMonsters)? as i32,
+            self.globals.load(GlobalAddrFloat::TotalMonsters)? as i32,
+        );
+
+        let mut spawn_parms = [0.0; NUM_SPAWN_PARMS];
+        for (i, parm) in spawn_parms.iter_mut().enumerate() {
+            *parm = self
+                .globals
+                .get_float(GlobalAddrFloat::Arg0 as i16 + i as i16)?;
+        }
+
+        let lightstyles = self.lightstyles[..SAVE_LIGHTSTYLES]
+            .iter()
+            .map(|id| {
+                self.string_table
+                    .get(*id)
+                    .map(|style| style.to_str().into_owned())
+                    .unwrap_or_default()
+            })
+            .collect();
+
+        let mut globals = Vec::new();
+        for def in self.globals.defs() {
+            if !def.save || !matches!(def.type_, Type::QString | Type::QFloat | Type::QEntity) {
+                continue;
+            }
+
+            let (Some(name), Ok(word)) = (
+                self.string_table.get(def.name_id),
+                self.globals.get_bytes(def.offset as i16),
+            ) else {
+                continue;
+            };
+
+            if let Some(value) = self.save_value(def.type_, &[word]) {
+                globals.push((name.to_str().into_owned(), value));
+            }
+        }
+
+        let slots = self.world.entities.iter().last().map_or(0, |id| id.0 + 1);
+        let mut entities = Vec::with_capacity(slots);
+        for id in 0..slots {
+            let mut fields = Vec::new();
+            let ent_id = EntityId(id);
+
+            if self.world.entities.exists(ent_id) {
+                let ent = self.world.entities.try_get(ent_id)?;
+
+                for def in self.world.type_def.field_defs() {
+                    let Some(name) = self.string_table.get(def.name_id) else {
+                        continue;
+                    };
+                    let name = name.to_str();
+                    if matches!(def.type_, Type::QVoid | Type::QPointer) || is_component(&name) {
+                        continue;
+                    }
+
+                    let words = (0..type_size(def.type_))
+                        .map(|i| ent.get_bytes(def.offset as i16 + i as i16))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    if words.iter().all(|word| *word == [0; 4]) {
+                        continue;
+                    }
+
+                    if let Some(value) = self.save_value(def.type_, &words) {
+                        fields.push((name.into_owned(), value));
+                    }
+                }
+            }
+
+            entities.push(fields);
+        }
+
+        Ok(SaveGame {
+            header: SaveHeader {
+                comment,
+                spawn_parms,
+                skill,
+                map_name: self.map_name().to_owned(),
+                time: engine::duration_to_f32(self.time),
+            },
+            lightstyles,
+            globals,
+            entities,
+        })
+    }
+
+    /// Replaces the state of the level with a savegame. The level must have just been spawned from
+    /// the savegame's map, so that everything the save refers to has been precached.
+    pub fn restore(
+        &mut self,
+        save: &SaveGame,
+        mut registry: Mut<Registry>,
+        vfs: &Vfs,
+    ) -> Result<(), ProgsError> {
+        if save.entities.len() > MAX_ENTITIES {
+            return Err(ProgsError::with_msg(format!(
+                "savegame has {} entities, the limit is {}",
+                save.entities.len(),
+                MAX_ENTITIES
+            )));
+        }
+
+        self.time = engine::duration_from_f32(save.header.time);
+
+        for (i, style) in save.lightstyles.iter().take(MAX_LIGHTSTYLES).enumerate() {
+            self.lightstyles[i] = self.string_table.insert(style);
+        }
+
+        for (i, parm) in save.header.spawn_parms.iter().enumerate() {
+            self.globals
+                .put_float(*parm, GlobalAddrFloat::Arg0 as i16 + i as i16)?;
+        }
+
+        for (name, value) in &save.globals {
+            let Some((type_, addr)) = self.find_global(name) else {
+                warn!("Ignoring saved global \"{}\", which doesn't exist", name);
+                continue;
+            };
+
+            for (i, word) in self.load_value(type_, value)?.into_iter().enumerate() {
+                self.globals.put_bytes(word, addr + i as i16)?;
+            }
+        }
+
+        // entities spawned from the map beyond the end of the save were removed before it was made
+        let slots = self.world.entities.iter().last().map_or(0, |id| id.0 + 1);
+        for id in 0..slots.max(save.entities.len()) {
+            let ent_id = EntityId(id);
+            if id != 0 && self.world.entities.exists(ent_id) {
+                self.world.remove_entity(ent_id)?;
+            }
+
+            let Some(fields) = save.entities.get(id).filter(|fields| !fields.is_empty()) else {
+                continue;
+            };
+
+            self.world.entities.insert(ent_id, &self.world.type_def);
+            for (name, value) in fields {
+                let Some((type_, addr)) = self.find_field(name) else {
+                    warn!("Ignoring saved field \"{}\", which doesn't exist", name);
+                    continue;
+                };
+
+                let words = self.load_value(type_, value)?;
+                let ent = self.world.entities.get_mut(ent_id)?;
+                for (i, word) in words.into_iter().enumerate() {
+                    ent.put_bytes(word, addr + i as i16)?;
+                }
+            }
+
+            self.link_entity(ent_id, false, registry.reborrow(), vfs)?;
+        }
+
+        let entities = &self.world.entities;
+        self.new_entities.retain(|id| entities.exists(*id));
+
+        Ok(())
+    }
+
+    /// Whether the player controlling `ent_id` is dead, in which case the game can't be saved.
+    pub fn is_dead(&self, ent_id: EntityId) -> Result<bool, ProgsError> {
+        let health = self
+            .world
+            .entities
+            .try_get(ent_id)?
+            .load(&self.world.type_def, FieldAddrFloat::Health)?;
+        Ok(health <= 0.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_comment() {
+        assert_eq!(
+            comment("the Slipgate Complex", 3, 34),
+            "the Slipgate Complex  kills:  3/ 34    "
+        );
+        assert_eq!(comment("", 0, 0).len(), COMMENT_LENGTH);
+    }
+
+    #[test]
+    fn test_save_game_round_trip() {
+        let save = SaveGame {
+            header: SaveHeader {
+                comment: comment("start", 0, 0),
+                spawn_parms: [1.0; NUM_SPAWN_PARMS],
+                skill: 2,
+                map_name: "e1m1".to_owned(),
+                time: 12.5,
+            },
+            lightstyles: (0..SAVE_LIGHTSTYLES)
+                .map(|i| {
+                    if i < 2 {
+                        "abc".to_owned()
+                    } else {
+                        "m".to_owned()
+                    }
+                })
+                .collect(),
+            globals: vec![
+                ("string_null".to_owned(), "".to_owned()),
+                ("total_monsters".to_owned(), "34.000000".to_owned()),
+            ],
+            entities: vec![
+                vec![("classname".to_owned(), "worldspawn".to_owned())],
+                vec![],
+                vec![
+                    (
+                        "origin".to_owned(),
+                        "-1.000000 2.000000 3.500000".to_owned(),
+                    ),
+                    ("message".to_owned(), "a {braced} message".to_owned()),
+                    ("netname".to_owned(), "two\nlines \\n".to_owned()),
+                ],
+            ],
+        };
+
+        let text = save.to_string();
+        assert!(text.starts_with("5\nstart"));
+        assert!(text.contains("\"netname\" \"two\\nlines \\\\n\"\n"));
+        assert_eq!(SaveGame::parse(&text).unwrap(), save);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(SaveGame::parse("").is_err());
+        assert!(SaveGame::parse("4\ncomment\n").is_err());
+
+        let mut text = SaveGame {
+            header: SaveHeader {
+                comment: "x".to_owned(),
+                spawn_parms: [0.0; NUM_SPAWN_PARMS],
+                skill: 1,
+                map_name: "start".to_owned(),
+                time: 0.0,
+            },
+            lightstyles: vec!["m".to_owned(); SAVE_LIGHTSTYLES],
+            globals: vec![],
+            entities: vec![],
+        }
+        .to_string();
+        assert!(SaveGame::parse(&text).is_ok());
+
+        text.push_str("{\n\"classname\"\n}\n");
+        assert!(SaveGame::parse(&text).is_err());
+    }
+}
@@ -15,7 +15,7 @@
 // DAMAGES OR OTHER LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
 // OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 
-use std::{error::Error, fmt, iter};
+use std::{error::Error, fmt, hash::Hasher, iter};
 
 use crate::{
     common::{
@@ -510,6 +510,13 @@ impl Entity {
         }
     }
 
+    /// Feed the raw value of every field into `state`.
+    pub fn hash_into<H: Hasher>(&self, state: &mut H) {
+        for addr in self.addrs.iter() {
+            state.write(addr);
+        }
+    }
+
     pub fn type_check(
         &self,
         type_def: &EntityTypeDef,
@@ -19,19 +19,18 @@ mod entity;
 pub mod phys;
 
 use std::{
-    collections::HashSet,
+    collections::BTreeSet,
+    hash::Hasher,
     iter,
     ops::{Bound, RangeBounds},
 };
 
-use self::{
-    entity::Entity,
-    phys::{Collide, CollideKind},
-};
+use self::phys::{Collide, CollideKind};
 pub use self::{
     entity::{
-        EntityError, EntityFlags, EntitySolid, EntityTypeDef, FieldAddrEntityId, FieldAddrFloat,
-        FieldAddrFunctionId, FieldAddrStringId, FieldAddrVector,
+        Entity, EntityError, EntityFlags, EntitySolid, EntityTypeDef, FieldAddrEntityId,
+        FieldAddrFloat, FieldAddrFunctionId, FieldAddrStringId, FieldAddrVector,
+        STATIC_ADDRESS_COUNT,
     },
     phys::{MoveKind, Trace, TraceEnd, TraceEndKind, TraceStart},
 };
@@ -40,7 +39,7 @@ use crate::{
     common::{
         bsp,
         bsp::{BspCollisionHull, BspLeafContents},
-        mdl,
+        iqm, md3, mdl,
         model::{Model, ModelKind},
         parse, sprite,
         vfs::Vfs,
@@ -58,7 +57,7 @@ use hashbrown::HashMap;
 
 const AREA_DEPTH: usize = 4;
 const NUM_AREA_NODES: usize = 2usize.pow(AREA_DEPTH as u32 + 1) - 1;
-const MAX_ENTITIES: usize = 600;
+pub const MAX_ENTITIES: usize = 600;
 
 #[derive(Debug)]
 enum AreaNodeKind {
@@ -69,8 +68,8 @@ enum AreaNodeKind {
 #[derive(Debug)]
 struct AreaNode {
     kind: AreaNodeKind,
-    triggers: HashSet<EntityId>,
-    solids: HashSet<EntityId>,
+    triggers: BTreeSet<EntityId>,
+    solids: BTreeSet<EntityId>,
 }
 
 // The areas form a quadtree-like BSP tree which alternates splitting on the X
@@ -122,8 +121,8 @@ impl AreaNode {
                         front: 2 * len + 1,
                         back: 2 * len + 2,
                     }),
-                    triggers: HashSet::new(),
-                    solids: HashSet::new(),
+                    triggers: BTreeSet::new(),
+                    solids: BTreeSet::new(),
                 });
             }
         }
@@ -132,8 +131,8 @@ impl AreaNode {
         for _ in 0..2usize.pow(AREA_DEPTH as u32) {
             nodes.push(AreaNode {
                 kind: AreaNodeKind::Leaf,
-                triggers: HashSet::new(),
-                solids: HashSet::new(),
+                triggers: BTreeSet::new(),
+                solids: BTreeSet::new(),
             });
         }
 
@@ -311,6 +310,16 @@ impl Entities {
             .into_iter()
     }
 
+    /// Feed the fields of every entity into `state`, in entity order.
+    pub fn hash_into<H: Hasher>(&self, state: &mut H) {
+        for id in self.iter() {
+            state.write(&(id.0 as u64).to_le_bytes());
+            if let Some(entity) = self.get(id) {
+                entity.hash_into(state);
+            }
+        }
+    }
+
     pub fn iter(&self) -> impl Iterator<Item = EntityId> + '_ {
         self.range(..)
     }
@@ -541,6 +550,19 @@ impl World {
             let alias_model = mdl::load(data).unwrap();
             self.models
                 .push(Model::from_alias_model(name.to_str(), alias_model));
+        } else if name.ends_with(b".md3") {
+            let data = vfs.open(name.to_str()).unwrap();
+            let md3_model = md3::load(data).unwrap();
+            self.models
+                .push(Model::from_md3_model(name.to_str(), md3_model));
+        } else if name.ends_with(b".iqm") {
+            let load_error = |e: &dyn std::fmt::Display| {
+                ProgsError::with_msg(format!("Couldn't load {}: {}", name, e))
+            };
+            let data = vfs.open(name.to_str()).map_err(|e| load_error(&e))?;
+            let iqm_model = iqm::load(data).map_err(|e| load_error(&e))?;
+            self.models
+                .push(Model::from_iqm_model(name.to_str(), iqm_model));
         } else if name.ends_with(b".spr") {
             let data = vfs.open(name.to_str()).unwrap();
             let sprite_model = sprite::load(data);